@@ -0,0 +1,74 @@
+//! Device-side endpoint throughput benchmark.
+//!
+//! Usage: `bench_device [TRANSFER_SIZE] [QUEUE_LEN] [DURATION_SECS]`
+//!
+//! The gadget provides a vendor-specific interface with a bulk IN and a bulk OUT endpoint.
+//! Once the function is enabled, data is first sent to the host and then received from it,
+//! thus the host must continuously read from and then write to the endpoints, e.g. using
+//! `dd` on a bulk endpoint or a small libusb program.
+
+use std::{env, time::Duration};
+
+use usb_gadget::{
+    default_udc,
+    function::custom::{
+        bench::{self, BenchConfig},
+        Custom, Endpoint, Event, Interface,
+    },
+    Class, Config, Gadget, Id, Strings,
+};
+
+fn main() {
+    env_logger::init();
+
+    let mut args = env::args().skip(1).map(|arg| arg.parse::<u64>().expect("invalid argument"));
+    let mut config = BenchConfig::new();
+    if let Some(transfer_size) = args.next() {
+        config = config.with_transfer_size(transfer_size as usize);
+    }
+    if let Some(queue_len) = args.next() {
+        config = config.with_queue_len(queue_len as u32);
+    }
+    if let Some(duration) = args.next() {
+        config = config.with_duration(Duration::from_secs(duration));
+    }
+    println!("{config:?}");
+
+    usb_gadget::remove_all().expect("cannot remove all gadgets");
+
+    let (mut ep_tx, ep_tx_dir) = config.device_to_host();
+    let (mut ep_rx, ep_rx_dir) = config.host_to_device();
+
+    let (mut custom, handle) = Custom::builder()
+        .with_interface(
+            Interface::new(Class::vendor_specific(1, 2), "benchmark")
+                .with_endpoint(Endpoint::bulk(ep_tx_dir))
+                .with_endpoint(Endpoint::bulk(ep_rx_dir)),
+        )
+        .build();
+
+    let udc = default_udc().expect("cannot get UDC");
+    let reg = Gadget::new(
+        Class::vendor_specific(255, 255),
+        Id::new(6, 0x11),
+        Strings::new("manufacturer", "benchmark", "serial_number"),
+    )
+    .with_config(Config::new("config").with_function(handle))
+    .bind(&udc)
+    .expect("cannot bind to UDC");
+
+    println!("Waiting for host to enable function");
+    loop {
+        if let Event::Enable = custom.event().expect("event failed") {
+            break;
+        }
+    }
+
+    let res = bench::send(&mut ep_tx, &config).expect("send benchmark failed");
+    println!("send: {res}");
+
+    let res = bench::recv(&mut ep_rx, &config).expect("receive benchmark failed");
+    println!("receive: {res}");
+
+    reg.remove().unwrap();
+}
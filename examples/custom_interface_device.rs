@@ -113,12 +113,12 @@ fn main() {
                                 println!("Stopping");
                                 stop.store(true, Ordering::Relaxed);
                             }
-                            ctrl_data = req.recv_all().unwrap();
+                            ctrl_data = req.recv_all_timeout(Duration::from_secs(1)).unwrap();
                             println!("Control data: {ctrl_data:x?}");
                         }
                         Event::SetupDeviceToHost(req) => {
                             println!("Replying with data");
-                            req.send(&ctrl_data).unwrap();
+                            req.send_timeout(&ctrl_data, Duration::from_secs(1)).unwrap();
                         }
                         _ => (),
                     }
@@ -111,12 +111,12 @@ async fn main() {
                     println!("Stopping");
                     stop.store(true, Ordering::Relaxed);
                 }
-                ctrl_data = req.recv_all().unwrap();
+                ctrl_data = req.recv_all_async().await.unwrap();
                 println!("Control data: {ctrl_data:x?}");
             }
             Event::SetupDeviceToHost(req) => {
                 println!("Replying with data");
-                req.send(&ctrl_data).unwrap();
+                req.send_async(&ctrl_data).await.unwrap();
             }
             _ => (),
         }
@@ -8,6 +8,7 @@ use std::{
     fs::{File, OpenOptions},
     io::{self, Read, Write},
     os::unix::io::AsRawFd,
+    time::Duration,
 };
 
 use usb_gadget::{
@@ -18,8 +19,6 @@ use usb_gadget::{
 
 // Printer read buffer size, best equal to EP wMaxPacketSize
 const BUF_SIZE: usize = 512;
-// Printer device path - 0 assumes we are the only printer gadget!
-const DEV_PATH: &str = "/dev/g_printer0";
 // Pages to 'print' before exiting
 const PRINT_EXIT_COUNT: u8 = 1;
 // Default printer status
@@ -30,14 +29,14 @@ const DEFAULT_STATUS: StatusFlags =
 ioctl_read!(ioctl_read_printer_status, GADGET_IOC_MAGIC, GADGET_GET_PRINTER_STATUS, u8);
 ioctl_readwrite!(ioctl_write_printer_status, GADGET_IOC_MAGIC, GADGET_SET_PRINTER_STATUS, u8);
 
-fn create_printer_gadget() -> io::Result<RegGadget> {
+fn create_printer_gadget() -> io::Result<(Printer, RegGadget)> {
     usb_gadget::remove_all().expect("cannot remove all gadgets");
 
     let udc = default_udc().expect("cannot get UDC");
     let mut builder = Printer::builder();
     builder.pnp_string = Some("Rust PNP".to_string());
 
-    let (_, func) = builder.build();
+    let (printer, func) = builder.build();
     let reg =
         // Linux Foundation VID Gadget PID
         Gadget::new(Class::interface_specific(), Id::new(0x1d6b, 0x0104), Strings::new("Clippy Manufacturer", "Rusty Printer", "RUST0123456"))
@@ -45,7 +44,7 @@ fn create_printer_gadget() -> io::Result<RegGadget> {
                 .with_function(func))
             .bind(&udc)?;
 
-    Ok(reg)
+    Ok((printer, reg))
 }
 
 fn read_printer_data(file: &mut File) -> io::Result<()> {
@@ -118,33 +117,28 @@ fn main() -> io::Result<()> {
     env_logger::init();
 
     // create printer gadget, will unbind on drop
-    let g_printer = create_printer_gadget().map_err(|e| {
+    let (printer, g_printer) = create_printer_gadget().map_err(|e| {
         eprintln!("Failed to create printer gadget: {e}");
         e
     })?;
     println!("Printer gadget created: {}", g_printer.path().display());
 
     // wait for device file creation
-    println!("Attempt open device path: {}", DEV_PATH);
-    let mut count = 0;
-    let mut file = loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
-
-        match OpenOptions::new().read(true).write(true).open(DEV_PATH) {
-            Ok(file) => break file,
-            Err(_) if count < 5 => count += 1,
-            Err(err) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    format!("Printer {DEV_PATH} not found or cannot open: {err}",),
-                ))
-            }
-        }
-    };
+    let dev_path = printer.device_timeout(Duration::from_secs(5))?;
+    println!("Attempt open device path: {}", dev_path.display());
+    let mut file = OpenOptions::new().read(true).write(true).open(&dev_path).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Printer {} not found or cannot open: {err}", dev_path.display()),
+        )
+    })?;
 
     print_status(set_printer_status(&file, DEFAULT_STATUS, false)?);
     if let Err(e) = read_printer_data(&mut file) {
-        return Err(io::Error::new(io::ErrorKind::Other, format!("Failed to read data from {DEV_PATH}: {e}")));
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Failed to read data from {}: {e}", dev_path.display()),
+        ));
     }
 
     Ok(())
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usb_gadget::{
+    function::custom::{RawDescs, OS_DESC_VERSION_1_00},
+    Speed,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(descs) = RawDescs::parse(data) else { return };
+    for speed in [Speed::FullSpeed, Speed::HighSpeed, Speed::SuperSpeed] {
+        let _ = descs.host_bytes(speed);
+    }
+
+    let Ok(encoded) = descs.encode(OS_DESC_VERSION_1_00) else { return };
+    let reparsed = RawDescs::parse(&encoded).expect("encoded descriptors must parse");
+    assert_eq!(reparsed.encode(OS_DESC_VERSION_1_00).unwrap(), encoded);
+});
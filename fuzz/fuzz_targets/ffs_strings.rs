@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usb_gadget::function::custom::RawStrings;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(strings) = RawStrings::parse(data) else { return };
+    let encoded = strings.to_bytes().expect("parsed strings must encode");
+    let reparsed = RawStrings::parse(&encoded).expect("encoded strings must parse");
+    assert_eq!(reparsed.0, strings.0);
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+//! Seed inputs with values reported by the kernel are in `seeds/sysfs_values`,
+//! run using `cargo fuzz run sysfs_values corpus/sysfs_values seeds/sysfs_values`.
+
+use libfuzzer_sys::fuzz_target;
+use usb_gadget::{Speed, UdcState};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(value) = std::str::from_utf8(data) else { return };
+
+    match Speed::from_sysfs(value) {
+        Ok(speed) => assert_eq!(Speed::from_sysfs(&speed.to_string()), Ok(speed)),
+        Err(raw) => assert!(value.contains(&raw)),
+    }
+
+    match UdcState::from_sysfs(value) {
+        Ok(state) => assert_eq!(UdcState::from_sysfs(&state.to_string()), Ok(state)),
+        Err(raw) => assert!(value.contains(&raw)),
+    }
+});
@@ -0,0 +1,338 @@
+//! Filesystem access and capabilities required by USB gadgets.
+//!
+//! This information is intended for setting up sandboxes, such as Landlock rulesets
+//! or seccomp filters, after a USB gadget has been registered.
+
+use nix::{
+    fcntl::AtFlags,
+    sys::statfs::{statfs, FsType, SYSFS_MAGIC},
+    unistd::{faccessat, AccessFlags},
+};
+use std::{
+    error::Error as StdError,
+    fmt, fs,
+    io::{Error, ErrorKind, Result},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// Kind of access to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum AccessKind {
+    /// Reading files and listing directories.
+    Read,
+    /// Reading and writing existing files.
+    ReadWrite,
+    /// Creating files, directories and symbolic links beneath the path.
+    Create,
+    /// Removing files, directories and symbolic links beneath the path.
+    Remove,
+    /// Mounting and unmounting a filesystem at the path.
+    Mount,
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::ReadWrite => write!(f, "read-write"),
+            Self::Create => write!(f, "create"),
+            Self::Remove => write!(f, "remove"),
+            Self::Mount => write!(f, "mount"),
+        }
+    }
+}
+
+/// Access to a path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct PathAccess {
+    /// Path.
+    ///
+    /// If it is a directory, access is required to everything beneath it.
+    pub path: PathBuf,
+    /// Kind of access.
+    pub kind: AccessKind,
+    /// Reason the access is required.
+    pub purpose: &'static str,
+}
+
+impl fmt::Display for PathAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}: {}", self.kind, self.path.display(), self.purpose)
+    }
+}
+
+/// Linux capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Capability {
+    /// `CAP_SYS_ADMIN`: mounting FunctionFS.
+    SysAdmin,
+    /// `CAP_SYS_MODULE`: loading kernel modules of function drivers.
+    ///
+    /// Not required if all modules are already loaded or built into the kernel.
+    SysModule,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SysAdmin => write!(f, "CAP_SYS_ADMIN"),
+            Self::SysModule => write!(f, "CAP_SYS_MODULE"),
+        }
+    }
+}
+
+/// Filesystem access and capabilities required by a USB gadget or function.
+///
+/// Writing to configfs additionally requires the file permissions of the
+/// configfs attributes, which are usually owned by root.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AccessRequirements {
+    /// Required access to paths.
+    pub paths: Vec<PathAccess>,
+    /// Required capabilities.
+    pub capabilities: Vec<Capability>,
+}
+
+impl AccessRequirements {
+    /// Creates empty access requirements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether nothing is required.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.capabilities.is_empty()
+    }
+
+    /// Adds required access to a path.
+    pub fn add_path(&mut self, path: impl AsRef<Path>, kind: AccessKind, purpose: &'static str) {
+        let access = PathAccess { path: path.as_ref().to_path_buf(), kind, purpose };
+        if !self.paths.contains(&access) {
+            self.paths.push(access);
+        }
+    }
+
+    /// Adds a required capability.
+    pub fn add_capability(&mut self, capability: Capability) {
+        if !self.capabilities.contains(&capability) {
+            self.capabilities.push(capability);
+        }
+    }
+
+    /// Adds all requirements of `other`.
+    pub fn extend(&mut self, other: AccessRequirements) {
+        for access in other.paths {
+            if !self.paths.contains(&access) {
+                self.paths.push(access);
+            }
+        }
+        for capability in other.capabilities {
+            self.add_capability(capability);
+        }
+    }
+
+    /// Paths that require the specified kind of access.
+    pub fn paths_with(&self, kind: AccessKind) -> impl Iterator<Item = &Path> {
+        self.paths.iter().filter(move |access| access.kind == kind).map(|access| access.path.as_path())
+    }
+
+    /// Checks that the process has the required access to all existing paths.
+    ///
+    /// Fails with an [`InsufficientPermissions`] error for the first path lacking access.
+    /// Capabilities are not checked, since they may not be needed on every system.
+    pub fn check(&self) -> Result<()> {
+        for access in &self.paths {
+            check_path(&access.path, access.kind)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AccessRequirements {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for access in &self.paths {
+            writeln!(f, "{access}")?;
+        }
+        for capability in &self.capabilities {
+            writeln!(f, "capability {capability}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The process lacks permissions to access a path.
+///
+/// Returned as inner error of an [`ErrorKind::PermissionDenied`] error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InsufficientPermissions {
+    /// Path that could not be accessed.
+    pub path: PathBuf,
+    /// Kind of access that was denied.
+    pub kind: AccessKind,
+    /// Effective user id of the process.
+    pub uid: u32,
+    /// Effective group id of the process.
+    pub gid: u32,
+    /// User and group id owning the path, if it exists.
+    pub owner: Option<(u32, u32)>,
+    fs: PathFs,
+}
+
+/// Filesystem a path resides on, as far as relevant for remediation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PathFs {
+    Configfs,
+    FunctionFs,
+    Sysfs,
+    Other,
+}
+
+const CONFIGFS_MAGIC: FsType = FsType(0x6265_6570 as _);
+const FUNCTIONFS_MAGIC: FsType = FsType(0x0a64_7361 as _);
+
+impl InsufficientPermissions {
+    /// Collects information about the process and path.
+    pub(crate) fn new(path: impl AsRef<Path>, kind: AccessKind) -> Self {
+        let path = path.as_ref();
+        let meta = fs::metadata(path).ok();
+        let fs = match statfs(path).map(|st| st.filesystem_type()) {
+            Ok(ty) if ty == CONFIGFS_MAGIC => PathFs::Configfs,
+            Ok(ty) if ty == FUNCTIONFS_MAGIC => PathFs::FunctionFs,
+            Ok(ty) if ty == SYSFS_MAGIC => PathFs::Sysfs,
+            _ => PathFs::Other,
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            kind,
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getegid() },
+            owner: meta.map(|meta| (meta.uid(), meta.gid())),
+            fs,
+        }
+    }
+
+    /// Suggestion for granting the required access.
+    pub fn remediation(&self) -> String {
+        let Self { uid, gid, .. } = self;
+        match (self.kind, self.fs) {
+            (AccessKind::Mount, _) => "run as root or grant the capability CAP_SYS_ADMIN".to_string(),
+            (_, PathFs::Configfs) => format!(
+                "run as root or change the owner of {} to {uid}:{gid}, since configfs has no uid and gid mount options",
+                self.path.display()
+            ),
+            (_, PathFs::FunctionFs) => format!(
+                "mount FunctionFS with uid={uid},gid={gid} by setting ffs_uid and ffs_gid of the custom function"
+            ),
+            (_, PathFs::Sysfs) => "run as root".to_string(),
+            (_, PathFs::Other) => format!(
+                "run as root or grant {} access to user {uid} or group {gid}, for example using a udev rule",
+                self.kind
+            ),
+        }
+    }
+}
+
+impl fmt::Display for InsufficientPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} access to {} denied for uid {} gid {}",
+            self.kind,
+            self.path.display(),
+            self.uid,
+            self.gid
+        )?;
+        if let Some((uid, gid)) = self.owner {
+            write!(f, " (owned by {uid}:{gid})")?;
+        }
+        write!(f, ": {}", self.remediation())
+    }
+}
+
+impl StdError for InsufficientPermissions {}
+
+/// Converts a permission error into an [`InsufficientPermissions`] error naming the path.
+pub(crate) fn permission_error(path: impl AsRef<Path>, kind: AccessKind, err: Error) -> Error {
+    if err.kind() != ErrorKind::PermissionDenied {
+        return err;
+    }
+    Error::new(ErrorKind::PermissionDenied, InsufficientPermissions::new(path, kind))
+}
+
+/// Checks that the process has the specified access to a path, if it exists.
+///
+/// For directories read and read-write access only require listing them,
+/// since the access applies to the files beneath.
+pub(crate) fn check_path(path: &Path, kind: AccessKind) -> Result<()> {
+    let Ok(meta) = fs::metadata(path) else { return Ok(()) };
+
+    let mode = match (kind, meta.is_dir()) {
+        (AccessKind::Mount, _) => return Ok(()),
+        (AccessKind::Read | AccessKind::ReadWrite, true) => AccessFlags::R_OK | AccessFlags::X_OK,
+        (AccessKind::Read, false) => AccessFlags::R_OK,
+        (AccessKind::ReadWrite, false) => AccessFlags::R_OK | AccessFlags::W_OK,
+        (AccessKind::Create | AccessKind::Remove, _) => AccessFlags::W_OK | AccessFlags::X_OK,
+    };
+
+    match faccessat(None, path, mode, AtFlags::AT_EACCESS) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(permission_error(path, kind, err.into())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{AccessKind, AccessRequirements, Capability, InsufficientPermissions, PathFs};
+
+    #[test]
+    fn extend_deduplicates() {
+        let mut a = AccessRequirements::new();
+        a.add_path("/dev", AccessKind::ReadWrite, "device node");
+        a.add_capability(Capability::SysAdmin);
+
+        let mut b = AccessRequirements::new();
+        b.add_path("/dev", AccessKind::ReadWrite, "device node");
+        b.add_path("/sys/class/udc", AccessKind::Read, "UDC state");
+        b.add_capability(Capability::SysAdmin);
+
+        a.extend(b);
+        assert_eq!(a.paths.len(), 2);
+        assert_eq!(a.capabilities, vec![Capability::SysAdmin]);
+        assert_eq!(a.paths_with(AccessKind::Read).collect::<Vec<_>>(), vec![Path::new("/sys/class/udc")]);
+        assert_eq!(
+            a.to_string(),
+            "read-write /dev: device node\nread /sys/class/udc: UDC state\ncapability CAP_SYS_ADMIN\n"
+        );
+    }
+
+    #[test]
+    fn insufficient_permissions() {
+        let mut access = AccessRequirements::new();
+        access.add_path(std::env::temp_dir(), AccessKind::Read, "temporary files");
+        access.add_path("/nonexistent/usb-gadget", AccessKind::Create, "missing");
+        access.check().unwrap();
+
+        let err = InsufficientPermissions {
+            path: PathBuf::from("/dev/ffs-serial"),
+            kind: AccessKind::ReadWrite,
+            uid: 1000,
+            gid: 100,
+            owner: Some((0, 0)),
+            fs: PathFs::FunctionFs,
+        };
+        assert_eq!(
+            err.to_string(),
+            "read-write access to /dev/ffs-serial denied for uid 1000 gid 100 (owned by 0:0): \
+             mount FunctionFS with uid=1000,gid=100 by setting ffs_uid and ffs_gid of the custom function"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Command line tool for administering USB gadgets.
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{Error, ErrorKind, Result},
+    process::ExitCode,
+    str::FromStr,
+};
+
+use usb_gadget::{
+    default_udc, diagnose,
+    function::other::{Other, OtherBuilder},
+    registered, udcs, Class, Config, Gadget, Id, Namespace, RegGadget, Speed, Strings, Udc,
+};
+
+const USAGE: &str = "\
+Usage: usb-gadget <COMMAND>
+
+Commands:
+  udcs                          List USB device controllers (UDCs)
+  gadgets                       List registered USB gadgets
+  create <FILE> [UDC]           Register the gadget defined in FILE and bind it to a UDC
+  remove <GADGET>               Unbind and remove a registered gadget
+  bind <GADGET> [UDC]           Bind a registered gadget to a UDC
+  unbind <GADGET>               Unbind a registered gadget
+  doctor [FILE]                 Diagnose the USB gadget setup of the system
+  dump <FILE> [SPEED]           Dump the descriptors of the gadget defined in FILE
+  attributes <DRIVER>           List the attributes of a kernel function driver
+
+If UDC is omitted, the default UDC is used.
+
+Gadget definition file:
+  vendor_id = 0x1d6b
+  product_id = 0x0104
+  class = 0xef 0x02 0x01
+  manufacturer = Manufacturer
+  product = Product
+  serial_number = 0001
+
+  [function net0]
+  driver = ecm
+  dev_addr = 66:f9:7d:f2:3e:2a
+
+  [config]
+  description = Ethernet
+  max_power = 500
+  functions = net0";
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[&str]) -> Result<()> {
+    match args {
+        ["udcs"] => list_udcs(),
+        ["gadgets"] => list_gadgets(),
+        ["create", file, udc @ ..] => {
+            let mut reg = parse_gadget(&fs::read_to_string(file)?)?.register()?;
+            reg.bind(Some(&find_udc(udc.first().copied())?))?;
+            println!("{}", reg.name().to_string_lossy());
+            reg.detach();
+            Ok(())
+        }
+        ["remove", gadget] => find_gadget(gadget)?.remove(),
+        ["bind", gadget, udc @ ..] => find_gadget(gadget)?.bind(Some(&find_udc(udc.first().copied())?)),
+        ["unbind", gadget] => find_gadget(gadget)?.bind(None),
+        ["doctor"] => doctor(None),
+        ["doctor", file] => doctor(Some(&parse_gadget(&fs::read_to_string(file)?)?)),
+        ["dump", file, speed @ ..] => {
+            let speed = match speed.first() {
+                Some(speed) => Speed::from_str(speed).map_err(|err| invalid(format!("invalid speed: {err}")))?,
+                None => Speed::HighSpeed,
+            };
+            print!("{}", parse_gadget(&fs::read_to_string(file)?)?.dump(speed)?);
+            Ok(())
+        }
+        ["attributes", driver] => {
+            for attr in Other::discover_attributes(driver, &Namespace::default())? {
+                let access = if attr.writable { "rw" } else { "ro" };
+                println!("{access} {} = {}", attr.name.display(), attr.value_str().unwrap_or("<binary>"));
+            }
+            Ok(())
+        }
+        ["help" | "--help" | "-h"] => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            Err(invalid("invalid command line"))
+        }
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg.into())
+}
+
+fn list_udcs() -> Result<()> {
+    for udc in udcs()? {
+        let state = udc.state().map(|s| s.to_string()).unwrap_or_else(|_| "unknown".to_string());
+        let max_speed = udc.max_speed().unwrap_or_default();
+        let function = udc.function()?.map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+        let device_path = udc.device_path().map(|p| p.display().to_string()).unwrap_or_default();
+        println!("{}\t{state}\t{max_speed}\t{device_path}\t{function}", udc.name().to_string_lossy());
+    }
+    Ok(())
+}
+
+fn list_gadgets() -> Result<()> {
+    for gadget in registered()? {
+        let udc = gadget.udc()?.map(|udc| udc.to_string_lossy().to_string()).unwrap_or_else(|| "-".to_string());
+        println!("{}\t{udc}", gadget.name().to_string_lossy());
+    }
+    Ok(())
+}
+
+fn doctor(gadget: Option<&Gadget>) -> Result<()> {
+    let report = diagnose(gadget);
+    print!("{report}");
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::Other, "problems found"))
+    }
+}
+
+fn find_gadget(name: &str) -> Result<RegGadget> {
+    registered()?
+        .into_iter()
+        .find(|gadget| gadget.name() == name)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("gadget {name} not found")))
+}
+
+fn find_udc(name: Option<&str>) -> Result<Udc> {
+    match name {
+        Some(name) => Udc::by_name(name),
+        None => default_udc(),
+    }
+}
+
+fn parse_num<T: TryFrom<u64>>(value: &str) -> Result<T> {
+    let num = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| invalid(format!("invalid number {value}")))?;
+    T::try_from(num).map_err(|_| invalid(format!("number {value} out of range")))
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" => Ok(false),
+        _ => Err(invalid(format!("invalid boolean {value}"))),
+    }
+}
+
+/// Section of a gadget definition file.
+enum Section {
+    Gadget,
+    Function,
+    Config(usize),
+}
+
+/// Function section of a gadget definition file.
+struct FunctionDef {
+    name: String,
+    builder: Option<OtherBuilder>,
+    attrs: Vec<(String, String)>,
+}
+
+/// Parses a gadget definition file.
+fn parse_gadget(text: &str) -> Result<Gadget> {
+    let mut id = Id::new(0x1d6b, 0x0104);
+    let mut class = Class::interface_specific();
+    let mut strings = Strings::new("", "", "");
+    let mut functions: Vec<FunctionDef> = Vec::new();
+    let mut configs: Vec<(Config, Vec<String>)> = Vec::new();
+
+    let mut section = Section::Gadget;
+    for (no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = |msg: &str| invalid(format!("line {}: {msg}", no + 1));
+
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = match header.split_whitespace().collect::<Vec<_>>()[..] {
+                ["function", name] => {
+                    functions.push(FunctionDef { name: name.to_string(), builder: None, attrs: Vec::new() });
+                    Section::Function
+                }
+                ["config"] => {
+                    configs.push((Config::new(""), Vec::new()));
+                    Section::Config(configs.len() - 1)
+                }
+                _ => return Err(err("invalid section")),
+            };
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| err("expected key = value"))?;
+        let (key, value) = (key.trim(), value.trim());
+        let value_err = |e: Error| err(&e.to_string());
+
+        match &section {
+            Section::Gadget => match key {
+                "vendor_id" => id.vendor = parse_num(value).map_err(value_err)?,
+                "product_id" => id.product = parse_num(value).map_err(value_err)?,
+                "class" => {
+                    let parts = value.split_whitespace().map(parse_num).collect::<Result<Vec<u8>>>();
+                    match parts.map_err(value_err)?[..] {
+                        [c, s, p] => class = Class::new(c, s, p),
+                        _ => return Err(err("class must consist of class, subclass and protocol")),
+                    }
+                }
+                "manufacturer" => strings.manufacturer = value.to_string(),
+                "product" => strings.product = value.to_string(),
+                "serial_number" => strings.serial_number = value.to_string(),
+                _ => return Err(err(&format!("unknown key {key}"))),
+            },
+            Section::Function => {
+                let func = functions.last_mut().unwrap();
+                if key == "driver" {
+                    func.builder = Some(Other::builder(value).map_err(value_err)?);
+                } else {
+                    func.attrs.push((key.to_string(), value.to_string()));
+                }
+            }
+            Section::Config(idx) => {
+                let (config, funcs) = &mut configs[*idx];
+                match key {
+                    "description" => config.description = [(Default::default(), value.to_string())].into(),
+                    "max_power" => config.max_power = parse_num(value).map_err(value_err)?,
+                    "self_powered" => config.self_powered = parse_bool(value).map_err(value_err)?,
+                    "remote_wakeup" => config.remote_wakeup = parse_bool(value).map_err(value_err)?,
+                    "functions" => funcs.extend(value.split_whitespace().map(String::from)),
+                    _ => return Err(err(&format!("unknown key {key}"))),
+                }
+            }
+        }
+    }
+
+    let mut handles = HashMap::new();
+    for FunctionDef { name, builder, attrs } in functions {
+        let mut builder = builder.ok_or_else(|| invalid(format!("function {name} has no driver")))?;
+        for (attr, value) in attrs {
+            builder.set(attr, value)?;
+        }
+        handles.insert(name, builder.build().1);
+    }
+
+    let mut gadget = Gadget::new(class, id, strings);
+    for (mut config, funcs) in configs {
+        for func in funcs {
+            let handle = handles.get(&func).ok_or_else(|| invalid(format!("unknown function {func}")))?;
+            config.add_function(handle.clone());
+        }
+        gadget.add_config(config);
+    }
+    Ok(gadget)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_gadget, USAGE};
+
+    #[test]
+    fn usage_example() {
+        let (_, example) = USAGE.split_once("Gadget definition file:\n").unwrap();
+        let gadget = parse_gadget(example).unwrap();
+        assert_eq!(gadget.id.vendor, 0x1d6b);
+        assert_eq!(gadget.configs.len(), 1);
+        assert_eq!(gadget.configs[0].functions.len(), 1);
+
+        assert!(parse_gadget("[config]\nfunctions = missing").is_err());
+        assert!(parse_gadget("[function f]\ndev_addr = 1").is_err());
+        assert!(parse_gadget("class = 1 2").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+//! Well-known USB base class codes.
+//!
+//! Common combinations of class, subclass and protocol codes are available as
+//! constants of [`Class`](crate::Class), for example [`Class::MISC_IAD`](crate::Class::MISC_IAD).
+
+/// Class information is determined from the interface descriptors.
+///
+/// Can only be used as device class.
+pub const INTERFACE_SPECIFIC: u8 = 0x00;
+
+/// Audio.
+pub const AUDIO: u8 = 0x01;
+
+/// Communications and CDC control.
+pub const CDC: u8 = 0x02;
+
+/// Human interface device (HID).
+pub const HID: u8 = 0x03;
+
+/// Physical interface device.
+pub const PHYSICAL: u8 = 0x05;
+
+/// Still imaging, such as PTP.
+pub const STILL_IMAGE: u8 = 0x06;
+
+/// Printer.
+pub const PRINTER: u8 = 0x07;
+
+/// Mass storage.
+pub const MSC: u8 = 0x08;
+
+/// Hub.
+///
+/// Can only be used as device class.
+pub const HUB: u8 = 0x09;
+
+/// CDC data.
+pub const CDC_DATA: u8 = 0x0a;
+
+/// Smart card.
+pub const SMART_CARD: u8 = 0x0b;
+
+/// Content security.
+pub const CONTENT_SECURITY: u8 = 0x0d;
+
+/// Video.
+pub const VIDEO: u8 = 0x0e;
+
+/// Personal healthcare.
+pub const PERSONAL_HEALTHCARE: u8 = 0x0f;
+
+/// Audio/video devices.
+pub const AUDIO_VIDEO: u8 = 0x10;
+
+/// Diagnostic device.
+pub const DIAGNOSTIC: u8 = 0xdc;
+
+/// Wireless controller.
+pub const WIRELESS: u8 = 0xe0;
+
+/// Miscellaneous.
+pub const MISC: u8 = 0xef;
+
+/// Application specific, such as DFU and USBTMC.
+pub const APPLICATION_SPECIFIC: u8 = 0xfe;
+
+/// Vendor specific.
+pub const VENDOR_SPECIFIC: u8 = 0xff;
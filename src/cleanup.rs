@@ -0,0 +1,143 @@
+//! Removal of USB gadgets on abnormal termination of the process.
+
+use std::{
+    panic,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, Once,
+    },
+};
+
+use crate::gadget::remove_at;
+
+/// Directories of attached gadgets registered by this process.
+static OWNED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Number of guards requesting removal on panic.
+static ON_PANIC: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of guards requesting removal on exit.
+static ON_EXIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Starts tracking an attached gadget.
+pub(crate) fn track(dir: &Path) {
+    OWNED.lock().unwrap().push(dir.to_path_buf());
+}
+
+/// Stops tracking a gadget because it has been removed or detached.
+pub(crate) fn untrack(dir: &Path) {
+    OWNED.lock().unwrap().retain(|owned| owned != dir);
+}
+
+/// Removes all tracked gadgets.
+///
+/// The lock is not waited for, since the hook may run on a thread that holds it.
+fn remove_owned(reason: &str) {
+    let Ok(mut owned) = OWNED.try_lock() else { return };
+    for dir in owned.drain(..) {
+        log::warn!("removing gadget at {} due to {reason}", dir.display());
+        if let Err(err) = remove_at(&dir, &[]) {
+            log::warn!("removing gadget at {} failed: {err}", dir.display());
+        }
+    }
+}
+
+extern "C" fn exit_hook() {
+    if ON_EXIT.load(Ordering::SeqCst) > 0 {
+        remove_owned("process exit");
+    }
+}
+
+/// Installs the panic and exit hooks once per process.
+fn install_hooks() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        // When unwinding, a panic may be caught and gadgets are removed when their
+        // handles are dropped or by the exit hook, if the panic ends the process.
+        if cfg!(panic = "abort") {
+            let prev = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if ON_PANIC.load(Ordering::SeqCst) > 0 {
+                    remove_owned("panic");
+                }
+                prev(info);
+            }));
+        }
+
+        if unsafe { libc::atexit(exit_hook) } != 0 {
+            log::warn!("registering exit hook for gadget removal failed");
+        }
+    });
+}
+
+/// Removes the USB gadgets registered by this process when it terminates abnormally.
+///
+/// While this guard exists, gadgets that are still attached are removed if the process exits
+/// without dropping them, for example by calling [`std::process::exit`] or when the main
+/// thread panics.
+/// In builds using `panic = "abort"`, where [`RegGadget`](crate::RegGadget)s are never dropped,
+/// they are also removed when any thread panics.
+///
+/// Gadgets that have been [detached](crate::RegGadget::detach) or obtained by
+/// calling [`registered`](crate::registered) are never removed.
+/// Functions are not notified, for example FunctionFS instances stay mounted.
+///
+/// The hooks are installed on first use and stay installed; dropping the guard disables them.
+#[derive(Debug)]
+#[must_use = "removal on abnormal termination is disabled when the guard is dropped"]
+pub struct RemovalGuard {
+    on_panic: bool,
+    on_exit: bool,
+}
+
+impl RemovalGuard {
+    /// Removes gadgets on exit and, if panics abort, on panic.
+    pub fn install() -> Self {
+        Self::with_triggers(true, true)
+    }
+
+    /// Removes gadgets only on the specified triggers.
+    pub fn with_triggers(on_panic: bool, on_exit: bool) -> Self {
+        install_hooks();
+        if on_panic {
+            ON_PANIC.fetch_add(1, Ordering::SeqCst);
+        }
+        if on_exit {
+            ON_EXIT.fetch_add(1, Ordering::SeqCst);
+        }
+        Self { on_panic, on_exit }
+    }
+
+    /// Directories of the gadgets that would currently be removed.
+    pub fn owned() -> Vec<PathBuf> {
+        OWNED.lock().unwrap().clone()
+    }
+}
+
+impl Drop for RemovalGuard {
+    fn drop(&mut self) {
+        if self.on_panic {
+            ON_PANIC.fetch_sub(1, Ordering::SeqCst);
+        }
+        if self.on_exit {
+            ON_EXIT.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{track, untrack, RemovalGuard};
+
+    #[test]
+    fn tracking() {
+        let dir = Path::new("/nonexistent/usb_gadget/tracking");
+        track(dir);
+        assert!(RemovalGuard::owned().iter().any(|owned| owned == dir));
+        untrack(dir);
+        assert!(!RemovalGuard::owned().iter().any(|owned| owned == dir));
+    }
+}
@@ -0,0 +1,273 @@
+//! Deferred binding of USB gadgets to USB device controllers (UDCs) that appear later.
+
+use std::{
+    ffi::OsString,
+    fmt,
+    io::Error,
+    panic,
+    path::PathBuf,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{udcs, RegGadget, Speed, Udc};
+
+/// Criteria a USB device controller (UDC) must meet for a deferred gadget to be bound to it.
+///
+/// Only UDCs not used by another gadget are considered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct UdcCriteria {
+    /// Name of the UDC.
+    ///
+    /// If unset, any UDC matches.
+    pub name: Option<OsString>,
+    /// Minimum speed the UDC must support.
+    ///
+    /// UDCs whose maximum speed is unknown match.
+    pub min_speed: Option<Speed>,
+    /// Path of the underlying device in sysfs.
+    ///
+    /// Unlike the name, it remains stable across reboots.
+    /// See [`Udc::device_path`] for details.
+    pub device_path: Option<PathBuf>,
+}
+
+impl UdcCriteria {
+    /// Matches any unused UDC.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the UDC.
+    #[must_use]
+    pub fn with_name(mut self, name: impl Into<OsString>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the path of the underlying device in sysfs.
+    #[must_use]
+    pub fn with_device_path(mut self, device_path: impl Into<PathBuf>) -> Self {
+        self.device_path = Some(device_path.into());
+        self
+    }
+
+    /// Sets the minimum speed the UDC must support.
+    #[must_use]
+    pub fn with_min_speed(mut self, min_speed: Speed) -> Self {
+        self.min_speed = Some(min_speed);
+        self
+    }
+
+    /// Whether the UDC meets the criteria.
+    pub fn matches(&self, udc: &Udc) -> bool {
+        if let Some(name) = &self.name {
+            if udc.name() != name {
+                return false;
+            }
+        }
+
+        if let Some(device_path) = &self.device_path {
+            if !udc.device_path().is_ok_and(|path| path == *device_path) {
+                return false;
+            }
+        }
+
+        if let Some(min_speed) = self.min_speed {
+            let max_speed = udc.max_speed().unwrap_or_default();
+            if max_speed != Speed::Unknown && max_speed > min_speed {
+                return false;
+            }
+        }
+
+        matches!(udc.function(), Ok(None))
+    }
+}
+
+/// Callback invoked when the deferred gadget has been bound to a UDC.
+type BoundCallback = Box<dyn FnMut(&RegGadget, &Udc) + Send>;
+
+/// Callback invoked when binding the deferred gadget to a UDC failed.
+type ErrorCallback = Box<dyn FnMut(&Udc, &Error) + Send>;
+
+/// Settings for binding a USB gadget to a USB device controller (UDC) once it appears.
+///
+/// Pass to [`Gadget::register_deferred`](crate::Gadget::register_deferred).
+pub struct DeferredBind {
+    /// Criteria the UDC must meet.
+    pub criteria: UdcCriteria,
+    /// Interval for checking for new UDCs.
+    ///
+    /// Defaults to 500 ms.
+    pub poll_interval: Duration,
+    on_bound: Option<BoundCallback>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl fmt::Debug for DeferredBind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeferredBind")
+            .field("criteria", &self.criteria)
+            .field("poll_interval", &self.poll_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DeferredBind {
+    /// Binds to the first UDC meeting the specified criteria.
+    pub fn new(criteria: UdcCriteria) -> Self {
+        Self { criteria, poll_interval: Duration::from_millis(500), on_bound: None, on_error: None }
+    }
+
+    /// Sets the interval for checking for new UDCs.
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets the callback invoked when the gadget has been bound to a UDC.
+    ///
+    /// It is called from the watcher thread.
+    #[must_use]
+    pub fn with_on_bound(mut self, on_bound: impl FnMut(&RegGadget, &Udc) + Send + 'static) -> Self {
+        self.on_bound = Some(Box::new(on_bound));
+        self
+    }
+
+    /// Sets the callback invoked when binding the gadget to a matching UDC failed.
+    ///
+    /// Binding to the UDC is not retried until it disappears and reappears.
+    /// It is called from the watcher thread.
+    #[must_use]
+    pub fn with_on_error(mut self, on_error: impl FnMut(&Udc, &Error) + Send + 'static) -> Self {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+}
+
+/// State shared between [`DeferredGadget`] and its watcher thread.
+#[derive(Debug, Default)]
+struct Shared {
+    udc: Mutex<Option<Udc>>,
+    bound: Condvar,
+}
+
+/// A registered USB gadget that is bound once a matching USB device controller (UDC) appears.
+///
+/// Obtained by calling [`Gadget::register_deferred`](crate::Gadget::register_deferred).
+/// A watcher thread checks for matching UDCs until the gadget has been bound.
+/// Dropping this stops the watcher and removes the gadget.
+#[derive(Debug)]
+pub struct DeferredGadget {
+    shared: Arc<Shared>,
+    stop_tx: Option<mpsc::Sender<()>>,
+    watcher: Option<JoinHandle<RegGadget>>,
+}
+
+impl DeferredGadget {
+    pub(crate) fn new(reg: RegGadget, mut deferred: DeferredBind) -> Self {
+        let shared = Arc::new(Shared::default());
+        let (stop_tx, stop_rx) = mpsc::channel();
+
+        let thread_shared = shared.clone();
+        let watcher = thread::spawn(move || {
+            let mut failed: Vec<OsString> = Vec::new();
+
+            loop {
+                let mut candidates = udcs().unwrap_or_default();
+                candidates.sort_by_key(|udc| udc.name().to_os_string());
+                failed.retain(|name| candidates.iter().any(|udc| udc.name() == name));
+
+                for udc in candidates {
+                    if failed.iter().any(|name| name == udc.name()) || !deferred.criteria.matches(&udc) {
+                        continue;
+                    }
+
+                    match reg.bind(Some(&udc)) {
+                        Ok(()) => {
+                            log::info!(
+                                "deferred gadget {} bound to {}",
+                                reg.name().to_string_lossy(),
+                                udc.name().to_string_lossy()
+                            );
+                            if let Some(on_bound) = &mut deferred.on_bound {
+                                on_bound(&reg, &udc);
+                            }
+                            *thread_shared.udc.lock().unwrap() = Some(udc);
+                            thread_shared.bound.notify_all();
+                            return reg;
+                        }
+                        Err(err) => {
+                            log::warn!(
+                                "binding deferred gadget to {} failed: {err}",
+                                udc.name().to_string_lossy()
+                            );
+                            if let Some(on_error) = &mut deferred.on_error {
+                                on_error(&udc, &err);
+                            }
+                            failed.push(udc.name().to_os_string());
+                        }
+                    }
+                }
+
+                match stop_rx.recv_timeout(deferred.poll_interval) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => (),
+                    _ => return reg,
+                }
+            }
+        });
+
+        Self { shared, stop_tx: Some(stop_tx), watcher: Some(watcher) }
+    }
+
+    /// The UDC the gadget has been bound to, if any.
+    pub fn udc(&self) -> Option<Udc> {
+        self.shared.udc.lock().unwrap().clone()
+    }
+
+    /// Waits until the gadget has been bound to a UDC.
+    ///
+    /// Returns `None` if the timeout has been reached.
+    pub fn wait_bound(&self, timeout: Option<Duration>) -> Option<Udc> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut udc = self.shared.udc.lock().unwrap();
+
+        while udc.is_none() {
+            udc = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.checked_duration_since(Instant::now())?;
+                    self.shared.bound.wait_timeout(udc, remaining).unwrap().0
+                }
+                None => self.shared.bound.wait(udc).unwrap(),
+            };
+        }
+
+        udc.clone()
+    }
+
+    /// Stops waiting for a UDC and returns the registered gadget.
+    ///
+    /// The gadget is bound if a matching UDC has appeared before.
+    pub fn into_reg(mut self) -> RegGadget {
+        match self.stop().unwrap() {
+            Ok(reg) => reg,
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+
+    /// Stops the watcher thread and returns the registered gadget it owned.
+    fn stop(&mut self) -> Option<thread::Result<RegGadget>> {
+        self.stop_tx.take();
+        Some(self.watcher.take()?.join())
+    }
+}
+
+impl Drop for DeferredGadget {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
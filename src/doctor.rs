@@ -0,0 +1,558 @@
+//! Diagnosis of the USB gadget setup of the system.
+
+use std::{
+    collections::BTreeSet,
+    fmt, fs,
+    io::{ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    configfs_dir,
+    function::custom::{ffs_mounts, FfsMount},
+    registered, udcs, usb_gadget_dir, Gadget, UdcState,
+};
+
+/// Directory containing the kernel modules of all kernel releases.
+const MODULES_DIR: &str = "/lib/modules";
+
+/// Aspect of the system examined by [`diagnose`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Check {
+    /// configfs is mounted.
+    Configfs,
+    /// The `libcomposite` kernel module is loaded.
+    Libcomposite,
+    /// A USB device controller (UDC) is present.
+    Udc,
+    /// Registered USB gadgets are bound to a UDC.
+    DanglingGadget,
+    /// FunctionFS instances are in use by a process.
+    OrphanedFfs,
+    /// Kernel function drivers required by the gadget are available.
+    FunctionDriver,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Configfs => "configfs",
+            Self::Libcomposite => "libcomposite",
+            Self::Udc => "UDC",
+            Self::DanglingGadget => "dangling gadget",
+            Self::OrphanedFfs => "orphaned FunctionFS",
+            Self::FunctionDriver => "function driver",
+        })
+    }
+}
+
+/// Severity of a [`Finding`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// No problem found.
+    #[default]
+    Ok,
+    /// Possible problem that does not prevent USB gadgets from working.
+    Warning,
+    /// Problem that prevents USB gadgets from working.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "ok",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// Result of a single check performed by [`diagnose`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Finding {
+    /// Examined aspect.
+    pub check: Check,
+    /// Severity.
+    pub severity: Severity,
+    /// Description of the finding.
+    pub message: String,
+    /// Hint how to fix the problem.
+    pub remediation: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: Check, message: impl Into<String>) -> Self {
+        Self { check, severity: Severity::Ok, message: message.into(), remediation: None }
+    }
+
+    fn problem(
+        check: Check, severity: Severity, message: impl Into<String>, remediation: impl Into<String>,
+    ) -> Self {
+        Self { check, severity, message: message.into(), remediation: Some(remediation.into()) }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.severity, self.check, self.message)?;
+        if let Some(remediation) = &self.remediation {
+            write!(f, " (hint: {remediation})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Diagnosis report produced by [`diagnose`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Report {
+    /// Findings in the order the checks were performed.
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Highest severity of all findings.
+    pub fn severity(&self) -> Severity {
+        self.findings.iter().map(|finding| finding.severity).max().unwrap_or_default()
+    }
+
+    /// Whether no problems have been found.
+    pub fn is_ok(&self) -> bool {
+        self.severity() == Severity::Ok
+    }
+
+    /// Findings that indicate a problem.
+    pub fn problems(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|finding| finding.severity != Severity::Ok)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{finding}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Examines the system for common problems preventing USB gadgets from working.
+///
+/// This checks that configfs is mounted, `libcomposite` is loaded and a UDC is present.
+/// It also reports registered USB gadgets not bound to any UDC and FunctionFS instances
+/// no process is using, which are usually left behind by crashed programs.
+/// If `gadget` is specified, the availability of the kernel function drivers it uses
+/// is checked as well.
+///
+/// Loading of `libcomposite` is attempted, otherwise the system is not modified.
+pub fn diagnose(gadget: Option<&Gadget>) -> Report {
+    let mut findings = Vec::new();
+
+    let configfs = check_configfs(&mut findings);
+    if configfs {
+        check_libcomposite(&mut findings);
+    }
+    check_udcs(&mut findings);
+    if configfs {
+        check_dangling_gadgets(&mut findings);
+    }
+    check_orphaned_ffs(ffs_mounts(), &mut findings);
+    if let Some(gadget) = gadget {
+        check_function_drivers(gadget, &mut findings);
+    }
+
+    Report { findings }
+}
+
+fn check_configfs(findings: &mut Vec<Finding>) -> bool {
+    match configfs_dir() {
+        Ok(dir) => {
+            findings.push(Finding::ok(Check::Configfs, format!("mounted at {}", dir.display())));
+            true
+        }
+        Err(err) => {
+            findings.push(Finding::problem(
+                Check::Configfs,
+                Severity::Error,
+                err.to_string(),
+                "enable CONFIG_CONFIGFS_FS and run `mount -t configfs none /sys/kernel/config`",
+            ));
+            false
+        }
+    }
+}
+
+fn check_libcomposite(findings: &mut Vec<Finding>) {
+    findings.push(match usb_gadget_dir() {
+        Ok(dir) => Finding::ok(Check::Libcomposite, format!("loaded, gadgets reside in {}", dir.display())),
+        Err(err) => Finding::problem(
+            Check::Libcomposite,
+            Severity::Error,
+            err.to_string(),
+            "enable CONFIG_USB_LIBCOMPOSITE and CONFIG_USB_CONFIGFS and run `modprobe libcomposite`",
+        ),
+    });
+}
+
+fn check_udcs(findings: &mut Vec<Finding>) {
+    let udcs = match udcs() {
+        Ok(udcs) => udcs,
+        Err(err) => {
+            findings.push(Finding::problem(
+                Check::Udc,
+                Severity::Error,
+                format!("enumerating UDCs failed: {err}"),
+                "enable CONFIG_USB_GADGET and make sure sysfs is mounted",
+            ));
+            return;
+        }
+    };
+
+    if udcs.is_empty() {
+        findings.push(Finding::problem(
+            Check::Udc,
+            Severity::Error,
+            "no USB device controller found",
+            "load the UDC driver of your hardware and make sure the USB port is configured for device \
+             or OTG mode, for example in the device tree",
+        ));
+        return;
+    }
+
+    for udc in udcs {
+        let name = udc.name().to_string_lossy();
+        let state = match udc.state() {
+            Ok(state) => state,
+            Err(err) => {
+                findings.push(Finding::problem(
+                    Check::Udc,
+                    Severity::Warning,
+                    format!("{name}: reading state failed: {err}"),
+                    "run as root",
+                ));
+                continue;
+            }
+        };
+        let function = match udc.function() {
+            Ok(Some(function)) => format!(", used by {}", function.to_string_lossy()),
+            _ => String::new(),
+        };
+        findings.push(Finding::ok(Check::Udc, format!("{name}: {state}{function}")));
+        if state == UdcState::NotAttached && !function.is_empty() {
+            findings.push(Finding::problem(
+                Check::Udc,
+                Severity::Warning,
+                format!("{name}: not connected to a USB host"),
+                "check the USB cable and that the port is not in host mode",
+            ));
+        }
+    }
+}
+
+fn check_dangling_gadgets(findings: &mut Vec<Finding>) {
+    let gadgets = match registered() {
+        Ok(gadgets) => gadgets,
+        Err(err) => {
+            findings.push(Finding::problem(
+                Check::DanglingGadget,
+                Severity::Warning,
+                format!("enumerating registered gadgets failed: {err}"),
+                "run as root",
+            ));
+            return;
+        }
+    };
+
+    let mut dangling = 0;
+    for gadget in gadgets {
+        if let Ok(None) = gadget.udc() {
+            dangling += 1;
+            findings.push(Finding::problem(
+                Check::DanglingGadget,
+                Severity::Warning,
+                format!("gadget {} is not bound to a UDC", gadget.name().to_string_lossy()),
+                format!(
+                    "remove it if it is left over using `usb-gadget remove {}`, which unlinks its functions \
+                     and removes its directories in the order configfs requires",
+                    gadget.name().to_string_lossy()
+                ),
+            ));
+        }
+    }
+
+    if dangling == 0 {
+        findings.push(Finding::ok(Check::DanglingGadget, "all registered gadgets are bound"));
+    }
+}
+
+fn check_orphaned_ffs(mounts: Result<Vec<FfsMount>>, findings: &mut Vec<Finding>) {
+    let mounts = match mounts {
+        Ok(mounts) => mounts,
+        Err(err) => {
+            findings.push(Finding::problem(
+                Check::OrphanedFfs,
+                Severity::Warning,
+                format!("enumerating FunctionFS instances failed: {err}"),
+                "make sure procfs is mounted",
+            ));
+            return;
+        }
+    };
+
+    let mut orphaned = 0;
+    for mount in mounts.iter().filter(|mount| mount.is_orphaned()) {
+        orphaned += 1;
+        findings.push(Finding::problem(
+            Check::OrphanedFfs,
+            Severity::Warning,
+            format!(
+                "FunctionFS instance {} at {} is not in use by any process",
+                mount.instance.to_string_lossy(),
+                mount.dir.display()
+            ),
+            "claim or clean it up using `usb_gadget::function::custom::ffs_mounts`",
+        ));
+    }
+
+    if mounts.iter().any(|mount| mount.ep0_holders.is_empty() && mount.holders_unknown) {
+        findings.push(Finding::problem(
+            Check::OrphanedFfs,
+            Severity::Warning,
+            "cannot determine whether FunctionFS instances are in use, since not all processes could be inspected",
+            "run as root",
+        ));
+    } else if orphaned == 0 {
+        findings.push(Finding::ok(Check::OrphanedFfs, format!("{} instances, all in use", mounts.len())));
+    }
+}
+
+fn check_function_drivers(gadget: &Gadget, findings: &mut Vec<Finding>) {
+    let drivers: BTreeSet<_> = gadget
+        .configs
+        .iter()
+        .flat_map(|config| &config.functions)
+        .map(|func| func.get().driver().to_string_lossy().into_owned())
+        .collect();
+    if drivers.is_empty() {
+        return;
+    }
+
+    check_function_modules(drivers, KernelModules::load(), findings);
+}
+
+fn check_function_modules(
+    drivers: BTreeSet<String>, modules: Result<KernelModules>, findings: &mut Vec<Finding>,
+) {
+    let modules = match modules {
+        Ok(modules) => modules,
+        Err(err) => {
+            findings.push(Finding::problem(
+                Check::FunctionDriver,
+                Severity::Warning,
+                format!("reading kernel module information failed: {err}"),
+                format!("install the kernel modules of the running kernel into {MODULES_DIR}"),
+            ));
+            return;
+        }
+    };
+
+    for driver in drivers {
+        findings.push(match modules.function_module(&driver) {
+            Some(FunctionModule { module, builtin: true }) => {
+                Finding::ok(Check::FunctionDriver, format!("{driver}: built into kernel as {module}"))
+            }
+            Some(FunctionModule { module, builtin: false }) => {
+                Finding::ok(Check::FunctionDriver, format!("{driver}: provided by module {module}"))
+            }
+            None => Finding::problem(
+                Check::FunctionDriver,
+                Severity::Error,
+                format!("{driver}: no kernel function driver available"),
+                format!("enable the kernel configuration option providing usb_f_{driver}"),
+            ),
+        });
+    }
+}
+
+/// Kernel module providing a USB function driver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FunctionModule {
+    module: String,
+    builtin: bool,
+}
+
+/// Module aliases of the running kernel.
+#[derive(Debug, Default)]
+struct KernelModules {
+    /// Contents of `modules.alias`.
+    alias: String,
+    /// Contents of `modules.builtin.modinfo`.
+    builtin_modinfo: Vec<u8>,
+}
+
+impl KernelModules {
+    fn load() -> Result<Self> {
+        let release = fs::read_to_string("/proc/sys/kernel/osrelease")?;
+        Self::from_dir(&Path::new(MODULES_DIR).join(release.trim()))
+    }
+
+    fn from_dir(dir: &Path) -> Result<Self> {
+        let read_opt = |path: PathBuf| match fs::read(path) {
+            Ok(data) => Ok(data),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        };
+
+        if !dir.is_dir() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, format!("{} not found", dir.display())));
+        }
+
+        Ok(Self {
+            alias: String::from_utf8_lossy(&read_opt(dir.join("modules.alias"))?).into_owned(),
+            builtin_modinfo: read_opt(dir.join("modules.builtin.modinfo"))?,
+        })
+    }
+
+    /// Finds the module providing the specified USB function driver.
+    ///
+    /// Function drivers register the module alias `usbfunc:<driver>`.
+    fn function_module(&self, driver: &str) -> Option<FunctionModule> {
+        let alias = format!("usbfunc:{driver}");
+
+        for entry in self.builtin_modinfo.split(|&b| b == 0) {
+            let entry = String::from_utf8_lossy(entry);
+            let Some((module, value)) = entry.split_once(".alias=") else { continue };
+            if value == alias {
+                return Some(FunctionModule { module: module.to_string(), builtin: true });
+            }
+        }
+
+        for line in self.alias.lines() {
+            let mut parts = line.split_whitespace();
+            if parts.next() == Some("alias") && parts.next() == Some(alias.as_str()) {
+                if let Some(module) = parts.next() {
+                    return Some(FunctionModule { module: module.to_string(), builtin: false });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeSet,
+        io::{Error, ErrorKind},
+    };
+
+    use super::{
+        check_function_modules, check_orphaned_ffs, Check, FunctionModule, KernelModules, Report, Severity,
+    };
+    use crate::function::custom::FfsMount;
+
+    fn modules() -> KernelModules {
+        KernelModules {
+            alias: "alias usbfunc:acm usb_f_acm\nalias usbfunc:ffs usb_f_fs\n".to_string(),
+            builtin_modinfo: b"usb_f_hid.alias=usbfunc:hid\0usb_f_hid.license=GPL\0".to_vec(),
+        }
+    }
+
+    fn ffs_mount(instance: &str, ep0_holders: Vec<u32>, holders_unknown: bool) -> FfsMount {
+        FfsMount {
+            instance: instance.into(),
+            dir: format!("/dev/ffs-{instance}").into(),
+            ep0_holders,
+            holders_unknown,
+        }
+    }
+
+    #[test]
+    fn function_module() {
+        let modules = modules();
+
+        assert_eq!(
+            modules.function_module("ffs"),
+            Some(FunctionModule { module: "usb_f_fs".to_string(), builtin: false })
+        );
+        assert_eq!(
+            modules.function_module("hid"),
+            Some(FunctionModule { module: "usb_f_hid".to_string(), builtin: true })
+        );
+        assert_eq!(modules.function_module("ac"), None);
+        assert_eq!(modules.function_module("uvc"), None);
+    }
+
+    #[test]
+    fn function_drivers() {
+        let drivers: BTreeSet<_> = ["acm", "hid", "uvc"].into_iter().map(String::from).collect();
+
+        let mut findings = Vec::new();
+        check_function_modules(drivers.clone(), Ok(modules()), &mut findings);
+        let report = Report { findings };
+        assert_eq!(report.severity(), Severity::Error);
+        assert!(report.findings.iter().all(|finding| finding.check == Check::FunctionDriver));
+        assert_eq!(
+            report.findings.iter().map(|finding| finding.message.as_str()).collect::<Vec<_>>(),
+            [
+                "acm: provided by module usb_f_acm",
+                "hid: built into kernel as usb_f_hid",
+                "uvc: no kernel function driver available"
+            ]
+        );
+        let problems: Vec<_> = report.problems().collect();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].remediation.as_deref(),
+            Some("enable the kernel configuration option providing usb_f_uvc")
+        );
+
+        let mut findings = Vec::new();
+        check_function_modules(drivers, Err(Error::new(ErrorKind::NotFound, "missing")), &mut findings);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(findings[0].message, "reading kernel module information failed: missing");
+    }
+
+    #[test]
+    fn orphaned_ffs() {
+        let mut findings = Vec::new();
+        check_orphaned_ffs(
+            Ok(vec![ffs_mount("a", vec![1], false), ffs_mount("b", vec![2, 3], false)]),
+            &mut findings,
+        );
+        let report = Report { findings };
+        assert!(report.is_ok());
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].message, "2 instances, all in use");
+
+        let mut findings = Vec::new();
+        check_orphaned_ffs(
+            Ok(vec![ffs_mount("a", vec![1], false), ffs_mount("b", Vec::new(), false)]),
+            &mut findings,
+        );
+        let report = Report { findings };
+        assert_eq!(report.severity(), Severity::Warning);
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].check, Check::OrphanedFfs);
+        assert_eq!(
+            report.findings[0].message,
+            "FunctionFS instance b at /dev/ffs-b is not in use by any process"
+        );
+
+        let mut findings = Vec::new();
+        check_orphaned_ffs(Ok(vec![ffs_mount("a", Vec::new(), true)]), &mut findings);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(findings[0].remediation.as_deref(), Some("run as root"));
+
+        let mut findings = Vec::new();
+        check_orphaned_ffs(Err(Error::new(ErrorKind::NotFound, "no procfs")), &mut findings);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].message, "enumerating FunctionFS instances failed: no procfs");
+    }
+}
@@ -0,0 +1,295 @@
+//! Recording of configfs operations without performing them.
+
+use std::{
+    cell::RefCell,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::{self, OpenOptions},
+    io::{Error, ErrorKind, Result, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{access::permission_error, system_ops, AccessKind};
+
+/// Filesystem operation performed while registering a USB gadget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConfigOp {
+    /// Load a kernel module.
+    LoadModule(OsString),
+    /// Create a directory.
+    CreateDir(PathBuf),
+    /// Create a directory and its parent directories.
+    CreateDirAll(PathBuf),
+    /// Remove an empty directory.
+    RemoveDir(PathBuf),
+    /// Write a value to an attribute.
+    Write {
+        /// Attribute path.
+        path: PathBuf,
+        /// Written value.
+        value: Vec<u8>,
+    },
+    /// Create a symbolic link.
+    Symlink {
+        /// Link target.
+        target: PathBuf,
+        /// Path of the link.
+        link: PathBuf,
+    },
+    /// Mount a filesystem.
+    Mount {
+        /// Source.
+        source: OsString,
+        /// Mount point.
+        target: PathBuf,
+        /// Filesystem type.
+        fs_type: String,
+        /// Mount options.
+        data: String,
+    },
+}
+
+impl fmt::Display for ConfigOp {
+    /// Formats the operation as a shell command.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::LoadModule(module) => write!(f, "modprobe -q {} || true", quote(module)),
+            Self::CreateDir(path) => write!(f, "mkdir {}", quote(path)),
+            Self::CreateDirAll(path) => write!(f, "mkdir -p {}", quote(path)),
+            Self::RemoveDir(path) => write!(f, "rmdir {}", quote(path)),
+            Self::Write { path, value } => match std::str::from_utf8(value) {
+                Ok(text) if !text.chars().any(|c| c.is_control() && c != '\n') => {
+                    write!(f, "printf '%s' {} > {}", quote(text), quote(path))
+                }
+                _ => {
+                    let escaped: String = value.iter().map(|b| format!("\\{b:03o}")).collect();
+                    write!(f, "printf '{escaped}' > {}", quote(path))
+                }
+            },
+            Self::Symlink { target, link } => write!(f, "ln -s {} {}", quote(target), quote(link)),
+            Self::Mount { source, target, fs_type, data } => {
+                write!(f, "mount -t {} -o {} {} {}", quote(fs_type), quote(data), quote(source), quote(target))
+            }
+        }
+    }
+}
+
+/// Quotes a value for use in a POSIX shell.
+fn quote(value: impl AsRef<OsStr>) -> String {
+    let value = value.as_ref().to_string_lossy();
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | ':' | ',' | '='))
+    {
+        value.into_owned()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+/// Operations a USB gadget registration would perform.
+///
+/// Obtained from [`Gadget::dry_run`](crate::Gadget::dry_run).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DryRun {
+    /// Directory the gadget would be registered at in configfs.
+    pub dir: PathBuf,
+    /// Operations in the order they would be performed.
+    pub ops: Vec<ConfigOp>,
+}
+
+impl DryRun {
+    /// Renders the operations as a POSIX shell script.
+    pub fn to_shell_script(&self) -> String {
+        let mut script = String::from("#!/bin/sh\nset -e\n\n");
+        for op in &self.ops {
+            script.push_str(&op.to_string());
+            script.push('\n');
+        }
+        script
+    }
+}
+
+thread_local! {
+    static RECORDING: RefCell<Option<Vec<ConfigOp>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` while recording operations instead of performing them on the current thread.
+pub(crate) fn record<R>(f: impl FnOnce() -> R) -> (R, Vec<ConfigOp>) {
+    let previous = RECORDING.with(|rec| rec.borrow_mut().replace(Vec::new()));
+    let res = f();
+    let ops = RECORDING.with(|rec| std::mem::replace(&mut *rec.borrow_mut(), previous)).unwrap_or_default();
+    (res, ops)
+}
+
+/// Whether operations are being recorded on the current thread.
+pub(crate) fn is_recording() -> bool {
+    RECORDING.with(|rec| rec.borrow().is_some())
+}
+
+/// Records the operation if recording and returns whether it was recorded.
+fn recorded(op: impl FnOnce() -> ConfigOp) -> bool {
+    RECORDING.with(|rec| match &mut *rec.borrow_mut() {
+        Some(ops) => {
+            ops.push(op());
+            true
+        }
+        None => false,
+    })
+}
+
+/// Whether a directory exists.
+///
+/// When recording, directories created by the kernel within configfs are assumed to exist.
+pub(crate) fn is_dir(path: &Path) -> bool {
+    is_recording() || path.is_dir()
+}
+
+/// Whether an attribute exists and is writable.
+///
+/// Some attributes are read-only in older kernels.
+/// When recording, attributes created by the kernel within configfs are assumed to be writable.
+pub(crate) fn is_writable(path: &Path) -> bool {
+    is_recording() || path.metadata().is_ok_and(|meta| meta.permissions().mode() & 0o222 != 0)
+}
+
+/// Loads a kernel module.
+pub(crate) fn load_module(module: &OsStr) -> Result<()> {
+    if recorded(|| ConfigOp::LoadModule(module.to_os_string())) {
+        return Ok(());
+    }
+    system_ops().modprobe(module)
+}
+
+/// Creates a directory.
+///
+/// When recording, this fails if the directory already exists.
+pub(crate) fn create_dir(path: &Path) -> Result<()> {
+    if is_recording() && path.exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, "directory exists"));
+    }
+    if recorded(|| ConfigOp::CreateDir(path.to_path_buf())) {
+        return Ok(());
+    }
+    fs::create_dir(path).map_err(|err| permission_error(parent(path), AccessKind::Create, err))
+}
+
+/// Creates a directory and its parent directories.
+pub(crate) fn create_dir_all(path: &Path) -> Result<()> {
+    if recorded(|| ConfigOp::CreateDirAll(path.to_path_buf())) {
+        return Ok(());
+    }
+    fs::create_dir_all(path).map_err(|err| permission_error(parent(path), AccessKind::Create, err))
+}
+
+/// Removes an empty directory.
+pub(crate) fn remove_dir(path: &Path) -> Result<()> {
+    if recorded(|| ConfigOp::RemoveDir(path.to_path_buf())) {
+        return Ok(());
+    }
+    fs::remove_dir(path).map_err(|err| permission_error(parent(path), AccessKind::Remove, err))
+}
+
+/// Lists the subdirectories of a directory.
+///
+/// When recording, a missing directory is treated as empty.
+pub(crate) fn sub_dirs(path: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if is_recording() && err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(permission_error(path, AccessKind::Read, err)),
+    };
+
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+/// Writes an attribute.
+pub(crate) fn write(path: &Path, value: &[u8]) -> Result<()> {
+    if recorded(|| ConfigOp::Write { path: path.to_path_buf(), value: value.to_vec() }) {
+        return Ok(());
+    }
+    fs::write(path, value).map_err(|err| permission_error(path, AccessKind::ReadWrite, err))
+}
+
+/// Writes an attribute using a single write call and returns the number of bytes accepted.
+///
+/// Unlike [`write`], the remainder is not written if the kernel accepts only part of
+/// the value, since each write replaces the value of a configfs attribute.
+pub(crate) fn write_once(path: &Path, value: &[u8]) -> Result<usize> {
+    if recorded(|| ConfigOp::Write { path: path.to_path_buf(), value: value.to_vec() }) {
+        return Ok(value.len());
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|err| permission_error(path, AccessKind::ReadWrite, err))?;
+    file.write(value)
+}
+
+/// Creates a symbolic link.
+pub(crate) fn symlink(target: &Path, link: &Path) -> Result<()> {
+    if recorded(|| ConfigOp::Symlink { target: target.to_path_buf(), link: link.to_path_buf() }) {
+        return Ok(());
+    }
+    system_ops().symlink(target, link).map_err(|err| permission_error(parent(link), AccessKind::Create, err))
+}
+
+/// Mounts a filesystem.
+pub(crate) fn mount(source: &OsStr, target: &Path, fs_type: &str, data: &str) -> Result<()> {
+    if recorded(|| ConfigOp::Mount {
+        source: source.to_os_string(),
+        target: target.to_path_buf(),
+        fs_type: fs_type.to_string(),
+        data: data.to_string(),
+    }) {
+        return Ok(());
+    }
+    system_ops()
+        .mount(source, target, fs_type, data)
+        .map_err(|err| permission_error(target, AccessKind::Mount, err))
+}
+
+/// Parent directory of a path, in which it is created.
+fn parent(path: &Path) -> &Path {
+    path.parent().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{create_dir, record, remove_dir, sub_dirs, write, ConfigOp, DryRun};
+
+    #[test]
+    fn record_ops() {
+        let ((), ops) = record(|| {
+            create_dir(Path::new("/nonexistent/g0")).unwrap();
+            write(Path::new("/nonexistent/g0/strings/0x409/product"), b"it's a gadget").unwrap();
+            write(Path::new("/nonexistent/g0/report_desc"), &[0x05, 0x01]).unwrap();
+            assert!(sub_dirs(Path::new("/nonexistent/g0/strings")).unwrap().is_empty());
+            remove_dir(Path::new("/nonexistent/g0/strings/0x407")).unwrap();
+        });
+        assert_eq!(ops.len(), 4);
+        assert_eq!(ops[0], ConfigOp::CreateDir("/nonexistent/g0".into()));
+
+        let dry_run = DryRun { dir: "/nonexistent/g0".into(), ops };
+        assert_eq!(
+            dry_run.to_shell_script(),
+            "#!/bin/sh\nset -e\n\nmkdir /nonexistent/g0\n\
+             printf '%s' 'it'\\''s a gadget' > /nonexistent/g0/strings/0x409/product\n\
+             printf '\\005\\001' > /nonexistent/g0/report_desc\n\
+             rmdir /nonexistent/g0/strings/0x407\n"
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Descriptor dumps in the style of `lsusb -v`.
+
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{Class, EndpointRequirement, Speed, UdcEndpointType};
+
+/// USB descriptors as seen by the USB host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HostDescriptors {
+    /// Raw descriptors, each prefixed by its length and type.
+    pub data: Vec<u8>,
+    /// Strings referenced by the descriptors by index.
+    pub strings: HashMap<u8, String>,
+}
+
+impl HostDescriptors {
+    /// Creates new host descriptors.
+    pub fn new(data: Vec<u8>, strings: HashMap<u8, String>) -> Self {
+        Self { data, strings }
+    }
+}
+
+/// Renders raw USB descriptors as text in the style of `lsusb -v`.
+///
+/// `data` is a sequence of descriptors, for example a device descriptor followed by
+/// a configuration descriptor including its interfaces and endpoints.
+/// `speed` is used to interpret speed-dependent fields and `strings` provides the
+/// text of string descriptors by index.
+///
+/// This can be used to diff the descriptors defined by a gadget against a dump
+/// obtained on the USB host.
+///
+/// See [`Gadget::dump`](crate::Gadget::dump) to render the descriptors of a USB gadget.
+pub fn dump_descriptors(data: &[u8], speed: Speed, strings: &HashMap<u8, String>) -> String {
+    let mut dump = DescriptorDump::new(speed);
+    dump.push(data, strings);
+    dump.finish()
+}
+
+/// Iterates over the descriptors within raw descriptor data.
+fn split_descriptors(mut data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        let len = usize::from(*data.first()?);
+        if len < 2 || len > data.len() {
+            return None;
+        }
+        let (desc, rest) = data.split_at(len);
+        data = rest;
+        Some(desc)
+    })
+}
+
+/// Counts the interfaces, i.e. interface descriptors of alternate setting 0, within raw descriptors.
+pub(crate) fn count_interfaces(data: &[u8]) -> u8 {
+    let count = split_descriptors(data)
+        .filter(|desc| desc[1] == DescriptorDump::INTERFACE && desc.len() >= 4 && desc[3] == 0)
+        .count();
+    count.try_into().unwrap_or(u8::MAX)
+}
+
+/// Classes of the interfaces, i.e. interface descriptors of alternate setting 0, within raw descriptors.
+pub(crate) fn interface_classes(data: &[u8]) -> Vec<Class> {
+    split_descriptors(data)
+        .filter(|desc| desc[1] == DescriptorDump::INTERFACE && desc.len() >= 9 && desc[3] == 0)
+        .map(|desc| Class::new(desc[5], desc[6], desc[7]))
+        .collect()
+}
+
+/// Endpoints described by the endpoint descriptors within raw descriptors.
+///
+/// Control endpoints are not included.
+pub(crate) fn endpoint_requirements(data: &[u8]) -> Vec<EndpointRequirement> {
+    split_descriptors(data)
+        .filter(|desc| desc[1] == DescriptorDump::ENDPOINT && desc.len() >= 4)
+        .filter_map(|desc| {
+            let transfer_type = match desc[3] & 0x03 {
+                0x01 => UdcEndpointType::Isochronous,
+                0x02 => UdcEndpointType::Bulk,
+                0x03 => UdcEndpointType::Interrupt,
+                _ => return None,
+            };
+            Some(EndpointRequirement { device_to_host: desc[2] & 0x80 != 0, transfer_type })
+        })
+        .collect()
+}
+
+/// Incremental renderer of USB descriptors.
+pub(crate) struct DescriptorDump {
+    out: String,
+    speed: Speed,
+    level: usize,
+}
+
+impl DescriptorDump {
+    const DEVICE: u8 = 0x01;
+    const CONFIG: u8 = 0x02;
+    const INTERFACE: u8 = 0x04;
+    const ENDPOINT: u8 = 0x05;
+    const INTERFACE_ASSOC: u8 = 0x0b;
+    const SS_ENDPOINT_COMP: u8 = 0x30;
+    const SSP_ISOC_ENDPOINT_COMP: u8 = 0x31;
+
+    pub(crate) fn new(speed: Speed) -> Self {
+        Self { out: String::new(), speed, level: 0 }
+    }
+
+    /// Renders a sequence of descriptors.
+    pub(crate) fn push(&mut self, mut data: &[u8], strings: &HashMap<u8, String>) {
+        while !data.is_empty() {
+            let len = usize::from(data[0]);
+            if len < 2 || len > data.len() {
+                self.unrecognized("truncated descriptor", data);
+                return;
+            }
+            let (desc, rest) = data.split_at(len);
+            data = rest;
+            self.desc(desc, strings);
+        }
+    }
+
+    /// Adds a note at the specified nesting level.
+    pub(crate) fn note(&mut self, level: usize, text: &str) {
+        let _ = writeln!(self.out, "{}{text}", self.indent(level));
+    }
+
+    pub(crate) fn finish(self) -> String {
+        self.out
+    }
+
+    fn indent(&self, level: usize) -> String {
+        " ".repeat(2 * level)
+    }
+
+    fn section(&mut self, level: usize, title: &str, desc: &[u8]) {
+        self.level = level;
+        let _ = writeln!(self.out, "{}{title}:", self.indent(level));
+        self.field("bLength", desc[0], "");
+        self.field("bDescriptorType", desc[1], "");
+    }
+
+    fn field(&mut self, name: &str, value: impl ToString, comment: &str) {
+        let indent = self.indent(self.level + 1);
+        let value = value.to_string();
+        let line = format!("{indent}{name:<19}{value:>6} {comment}");
+        let _ = writeln!(self.out, "{}", line.trim_end());
+    }
+
+    fn detail(&mut self, name: &str, value: &str) {
+        let line = format!("{}{name:<25}{value}", self.indent(self.level + 2));
+        let _ = writeln!(self.out, "{}", line.trim_end());
+    }
+
+    fn string(&mut self, name: &str, idx: u8, strings: &HashMap<u8, String>) {
+        let text = strings.get(&idx).map(|s| s.as_str()).unwrap_or_default();
+        self.field(name, idx, text);
+    }
+
+    fn unrecognized(&mut self, what: &str, data: &[u8]) {
+        let hex: Vec<_> = data.iter().map(|b| format!("{b:02x}")).collect();
+        let _ = writeln!(self.out, "{}** UNRECOGNIZED ({what}): {}", self.indent(self.level + 1), hex.join(" "));
+    }
+
+    fn desc(&mut self, desc: &[u8], strings: &HashMap<u8, String>) {
+        let u16_at = |pos: usize| u16::from_le_bytes([desc[pos], desc[pos + 1]]);
+
+        match (desc[1], desc.len()) {
+            (Self::DEVICE, 18) => {
+                self.section(0, "Device Descriptor", desc);
+                self.field("bcdUSB", bcd(u16_at(2)), "");
+                self.field("bDeviceClass", desc[4], device_class_name(desc[4]));
+                self.field("bDeviceSubClass", desc[5], "");
+                self.field("bDeviceProtocol", desc[6], "");
+                self.field("bMaxPacketSize0", desc[7], "");
+                self.field("idVendor", format!("0x{:04x}", u16_at(8)), "");
+                self.field("idProduct", format!("0x{:04x}", u16_at(10)), "");
+                self.field("bcdDevice", bcd(u16_at(12)), "");
+                self.string("iManufacturer", desc[14], strings);
+                self.string("iProduct", desc[15], strings);
+                self.string("iSerial", desc[16], strings);
+                self.field("bNumConfigurations", desc[17], "");
+            }
+            (Self::CONFIG, 9) => {
+                self.section(1, "Configuration Descriptor", desc);
+                self.field("wTotalLength", format!("0x{:04x}", u16_at(2)), "");
+                self.field("bNumInterfaces", desc[4], "");
+                self.field("bConfigurationValue", desc[5], "");
+                self.string("iConfiguration", desc[6], strings);
+                self.field("bmAttributes", format!("0x{:02x}", desc[7]), "");
+                if desc[7] & 0x40 != 0 {
+                    self.detail("Self Powered", "");
+                } else {
+                    self.detail("(Bus Powered)", "");
+                }
+                if desc[7] & 0x20 != 0 {
+                    self.detail("Remote Wakeup", "");
+                }
+                let unit = if matches!(self.speed, Speed::SuperSpeed | Speed::SuperSpeedPlus) { 8 } else { 2 };
+                let max_power = u32::from(desc[8]) * unit;
+                let _ = writeln!(self.out, "{}{:<19}{max_power:>6}mA", self.indent(self.level + 1), "MaxPower");
+            }
+            (Self::INTERFACE_ASSOC, 8) => {
+                self.section(2, "Interface Association", desc);
+                self.field("bFirstInterface", desc[2], "");
+                self.field("bInterfaceCount", desc[3], "");
+                self.field("bFunctionClass", desc[4], class_name(desc[4]));
+                self.field("bFunctionSubClass", desc[5], "");
+                self.field("bFunctionProtocol", desc[6], "");
+                self.string("iFunction", desc[7], strings);
+            }
+            (Self::INTERFACE, 9) => {
+                self.section(2, "Interface Descriptor", desc);
+                self.field("bInterfaceNumber", desc[2], "");
+                self.field("bAlternateSetting", desc[3], "");
+                self.field("bNumEndpoints", desc[4], "");
+                self.field("bInterfaceClass", desc[5], class_name(desc[5]));
+                self.field("bInterfaceSubClass", desc[6], "");
+                self.field("bInterfaceProtocol", desc[7], "");
+                self.string("iInterface", desc[8], strings);
+            }
+            (Self::ENDPOINT, 7 | 9) => {
+                self.section(3, "Endpoint Descriptor", desc);
+                let addr = desc[2];
+                let dir = if addr & 0x80 != 0 { "IN" } else { "OUT" };
+                self.field("bEndpointAddress", format!("0x{addr:02x}"), &format!(" EP {} {dir}", addr & 0x0f));
+                self.field("bmAttributes", desc[3], "");
+                let attrs = desc[3];
+                self.detail(
+                    "Transfer Type",
+                    ["Control", "Isochronous", "Bulk", "Interrupt"][usize::from(attrs & 0x03)],
+                );
+                self.detail(
+                    "Synch Type",
+                    ["None", "Asynchronous", "Adaptive", "Synchronous"][usize::from(attrs >> 2 & 0x03)],
+                );
+                self.detail(
+                    "Usage Type",
+                    ["Data", "Feedback", "Implicit feedback Data", "Reserved"][usize::from(attrs >> 4 & 0x03)],
+                );
+                let mps = u16_at(4);
+                self.field(
+                    "wMaxPacketSize",
+                    format!("0x{mps:04x}"),
+                    &format!(" {}x {} bytes", (mps >> 11 & 0x03) + 1, mps & 0x7ff),
+                );
+                self.field("bInterval", desc[6], "");
+                if desc.len() == 9 {
+                    self.field("bRefresh", desc[7], "");
+                    self.field("bSynchAddress", desc[8], "");
+                }
+            }
+            (Self::SS_ENDPOINT_COMP, 6) => {
+                self.section(4, "SuperSpeed Endpoint Companion Descriptor", desc);
+                self.field("bMaxBurst", desc[2], "");
+                self.field("bmAttributes", desc[3], "");
+                self.field("wBytesPerInterval", format!("0x{:04x}", u16_at(4)), "");
+            }
+            (Self::SSP_ISOC_ENDPOINT_COMP, 8) => {
+                self.section(4, "SuperSpeedPlus Isochronous Endpoint Companion Descriptor", desc);
+                let bytes_per_interval = u32::from_le_bytes([desc[4], desc[5], desc[6], desc[7]]);
+                self.field("dwBytesPerInterval", format!("0x{bytes_per_interval:08x}"), "");
+            }
+            (descriptor_type, _) => self.unrecognized(&format!("type 0x{descriptor_type:02x}"), desc),
+        }
+    }
+}
+
+/// Formats a BCD version number.
+fn bcd(value: u16) -> String {
+    format!("{:x}.{:02x}", value >> 8, value & 0xff)
+}
+
+fn device_class_name(class: u8) -> &'static str {
+    match class {
+        0x00 => "(Defined at Interface level)",
+        0xef => "Miscellaneous Device",
+        class => class_name(class),
+    }
+}
+
+fn class_name(class: u8) -> &'static str {
+    match class {
+        0x01 => "Audio",
+        0x02 => "Communications",
+        0x03 => "Human Interface Device",
+        0x05 => "Physical Interface Device",
+        0x06 => "Imaging",
+        0x07 => "Printer",
+        0x08 => "Mass Storage",
+        0x09 => "Hub",
+        0x0a => "CDC Data",
+        0x0b => "Chip/SmartCard",
+        0x0d => "Content Security",
+        0x0e => "Video",
+        0x0f => "Personal Healthcare",
+        0x10 => "Audio/Video",
+        0xdc => "Diagnostic",
+        0xe0 => "Wireless",
+        0xef => "Miscellaneous",
+        0xfe => "Application Specific Interface",
+        0xff => "Vendor Specific Class",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{count_interfaces, dump_descriptors};
+    use crate::Speed;
+
+    #[test]
+    fn dump() {
+        let data = [
+            9, 0x02, 0x20, 0x00, 1, 1, 4, 0xc0, 50, // configuration
+            9, 0x04, 0, 0, 2, 0xff, 1, 2, 5, // interface
+            7, 0x05, 0x81, 0x02, 0x00, 0x02, 0, // endpoint
+            7, 0x05, 0x02, 0x02, 0x00, 0x02, 0, // endpoint
+        ];
+        let strings = [(5, "custom".to_string())].into();
+
+        let text = dump_descriptors(&data, Speed::HighSpeed, &strings);
+        let expected = r"  Configuration Descriptor:
+    bLength                 9
+    bDescriptorType         2
+    wTotalLength       0x0020
+    bNumInterfaces          1
+    bConfigurationValue     1
+    iConfiguration          4
+    bmAttributes         0xc0
+      Self Powered
+    MaxPower              100mA
+    Interface Descriptor:
+      bLength                 9
+      bDescriptorType         4
+      bInterfaceNumber        0
+      bAlternateSetting       0
+      bNumEndpoints           2
+      bInterfaceClass       255 Vendor Specific Class
+      bInterfaceSubClass      1
+      bInterfaceProtocol      2
+      iInterface              5 custom
+      Endpoint Descriptor:
+        bLength                 7
+        bDescriptorType         5
+        bEndpointAddress     0x81  EP 1 IN
+        bmAttributes            2
+          Transfer Type            Bulk
+          Synch Type               None
+          Usage Type               Data
+        wMaxPacketSize     0x0200  1x 512 bytes
+        bInterval               0
+      Endpoint Descriptor:
+        bLength                 7
+        bDescriptorType         5
+        bEndpointAddress     0x02  EP 2 OUT
+        bmAttributes            2
+          Transfer Type            Bulk
+          Synch Type               None
+          Usage Type               Data
+        wMaxPacketSize     0x0200  1x 512 bytes
+        bInterval               0
+";
+        assert_eq!(text, expected);
+        assert_eq!(count_interfaces(&data), 1);
+
+        let text = dump_descriptors(&[3, 0x24, 0x01], Speed::HighSpeed, &HashMap::new());
+        assert_eq!(text, "  ** UNRECOGNIZED (type 0x24): 03 24 01\n");
+    }
+}
@@ -15,7 +15,7 @@
 //! let reg =
 //!     // USB device descriptor base class 0, 0, 0: use Interface Descriptors
 //!     // Linux Foundation VID Gadget PID
-//!     Gadget::new(Class::new(0, 0, 0), Id::new(0x1d6b, 0x0104), Strings::new("Clippy Manufacturer", "Rust UAC2", "RUST0123456"))
+//!     Gadget::new(Class::interface_specific(), Id::new(0x1d6b, 0x0104), Strings::new("Clippy Manufacturer", "Rust UAC2", "RUST0123456"))
 //!         .with_config(Config::new("Audio Config 1").with_function(func))
 //!         .bind(&udc)
 //!         .expect("cannot bind to UDC");
@@ -29,10 +29,10 @@
 //! );
 //! ```
 
-use std::{ffi::OsString, io::Result};
+use std::{any::Any, ffi::OsString, io::Result};
 
 use super::{
-    util::{FunctionDir, Status},
+    util::{FunctionDir, Status, Strictness},
     Function, Handle,
 };
 
@@ -63,8 +63,8 @@ impl Channel {
 /// Audio device configuration.
 ///
 /// Fields are optional and will be set to f_uac2 default values if not specified, see
-/// drivers/usb/gadget/function/u_uac2.h. Not all fields are supported by all kernels; permission
-/// denied errors may occur if unsupported fields are set.
+/// drivers/usb/gadget/function/u_uac2.h. Not all fields are supported by all kernels; see
+/// [`Uac2Builder::strictness`] for how unsupported fields are handled.
 #[derive(Debug, Clone, Default)]
 #[non_exhaustive]
 pub struct Uac2Config {
@@ -118,6 +118,8 @@ pub struct Uac2Builder {
     pub clock_source_in_name: Option<String>,
     /// The name of the output clock source
     pub clock_source_out_name: Option<String>,
+    /// Handling of attributes not supported by the running kernel.
+    pub strictness: Strictness,
 }
 
 impl Uac2Builder {
@@ -159,116 +161,123 @@ impl Function for Uac2Function {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
     fn register(&self) -> Result<()> {
+        let strictness = self.builder.strictness;
+
         // capture
         if let Some(channel_mask) = self.builder.capture.channel.channel_mask {
-            self.dir.write("c_chmask", channel_mask.to_string())?;
+            self.dir.write_optional("c_chmask", channel_mask.to_string(), strictness)?;
         }
         if let Some(sample_rate) = self.builder.capture.channel.sample_rate {
-            self.dir.write("c_srate", sample_rate.to_string())?;
+            self.dir.write_optional("c_srate", sample_rate.to_string(), strictness)?;
         }
         if let Some(sample_size) = self.builder.capture.channel.sample_size {
-            self.dir.write("c_ssize", sample_size.to_string())?;
+            self.dir.write_optional("c_ssize", sample_size.to_string(), strictness)?;
         }
         if let Some(sync_type) = self.builder.capture.sync_type {
-            self.dir.write("c_sync", sync_type.to_string())?;
+            self.dir.write_optional("c_sync", sync_type.to_string(), strictness)?;
         }
         if let Some(hs_interval) = self.builder.capture.hs_interval {
-            self.dir.write("c_hs_bint", hs_interval.to_string())?;
+            self.dir.write_optional("c_hs_bint", hs_interval.to_string(), strictness)?;
         }
         if let Some(mute_present) = self.builder.capture.mute_present {
-            self.dir.write("c_mute_present", (mute_present as u8).to_string())?;
+            self.dir.write_optional("c_mute_present", (mute_present as u8).to_string(), strictness)?;
         }
         if let Some(volume_present) = self.builder.capture.volume_present {
-            self.dir.write("c_volume_present", (volume_present as u8).to_string())?;
+            self.dir.write_optional("c_volume_present", (volume_present as u8).to_string(), strictness)?;
         }
         if let Some(volume_min) = self.builder.capture.volume_min {
-            self.dir.write("c_volume_min", volume_min.to_string())?;
+            self.dir.write_optional("c_volume_min", volume_min.to_string(), strictness)?;
         }
         if let Some(volume_max) = self.builder.capture.volume_max {
-            self.dir.write("c_volume_max", volume_max.to_string())?;
+            self.dir.write_optional("c_volume_max", volume_max.to_string(), strictness)?;
         }
         if let Some(volume_resolution) = self.builder.capture.volume_resolution {
-            self.dir.write("c_volume_res", volume_resolution.to_string())?;
+            self.dir.write_optional("c_volume_res", volume_resolution.to_string(), strictness)?;
         }
         if let Some(volume_name) = &self.builder.capture.volume_name {
-            self.dir.write("c_fu_vol_name", volume_name)?;
+            self.dir.write_optional("c_fu_vol_name", volume_name, strictness)?;
         }
         if let Some(terminal_type) = self.builder.capture.terminal_type {
-            self.dir.write("c_terminal_type", terminal_type.to_string())?;
+            self.dir.write_optional("c_terminal_type", terminal_type.to_string(), strictness)?;
         }
         if let Some(input_terminal_name) = &self.builder.capture.input_terminal_name {
-            self.dir.write("c_it_name", input_terminal_name)?;
+            self.dir.write_optional("c_it_name", input_terminal_name, strictness)?;
         }
         if let Some(input_terminal_channel_name) = &self.builder.capture.input_terminal_channel_name {
-            self.dir.write("c_it_ch_name", input_terminal_channel_name)?;
+            self.dir.write_optional("c_it_ch_name", input_terminal_channel_name, strictness)?;
         }
         if let Some(output_terminal_name) = &self.builder.capture.output_terminal_name {
-            self.dir.write("c_ot_name", output_terminal_name)?;
+            self.dir.write_optional("c_ot_name", output_terminal_name, strictness)?;
         }
 
         // playback
         if let Some(channel_mask) = self.builder.playback.channel.channel_mask {
-            self.dir.write("p_chmask", channel_mask.to_string())?;
+            self.dir.write_optional("p_chmask", channel_mask.to_string(), strictness)?;
         }
         if let Some(sample_rate) = self.builder.playback.channel.sample_rate {
-            self.dir.write("p_srate", sample_rate.to_string())?;
+            self.dir.write_optional("p_srate", sample_rate.to_string(), strictness)?;
         }
         if let Some(sample_size) = self.builder.playback.channel.sample_size {
-            self.dir.write("p_ssize", sample_size.to_string())?;
+            self.dir.write_optional("p_ssize", sample_size.to_string(), strictness)?;
         }
         if let Some(hs_interval) = self.builder.playback.hs_interval {
-            self.dir.write("p_hs_bint", hs_interval.to_string())?;
+            self.dir.write_optional("p_hs_bint", hs_interval.to_string(), strictness)?;
         }
         if let Some(mute_present) = self.builder.playback.mute_present {
-            self.dir.write("p_mute_present", (mute_present as u8).to_string())?;
+            self.dir.write_optional("p_mute_present", (mute_present as u8).to_string(), strictness)?;
         }
         if let Some(volume_present) = self.builder.playback.volume_present {
-            self.dir.write("p_volume_present", (volume_present as u8).to_string())?;
+            self.dir.write_optional("p_volume_present", (volume_present as u8).to_string(), strictness)?;
         }
         if let Some(volume_min) = self.builder.playback.volume_min {
-            self.dir.write("p_volume_min", volume_min.to_string())?;
+            self.dir.write_optional("p_volume_min", volume_min.to_string(), strictness)?;
         }
         if let Some(volume_max) = self.builder.playback.volume_max {
-            self.dir.write("p_volume_max", volume_max.to_string())?;
+            self.dir.write_optional("p_volume_max", volume_max.to_string(), strictness)?;
         }
         if let Some(volume_resolution) = self.builder.playback.volume_resolution {
-            self.dir.write("p_volume_res", volume_resolution.to_string())?;
+            self.dir.write_optional("p_volume_res", volume_resolution.to_string(), strictness)?;
         }
         if let Some(volume_name) = &self.builder.playback.volume_name {
-            self.dir.write("p_fu_vol_name", volume_name)?;
+            self.dir.write_optional("p_fu_vol_name", volume_name, strictness)?;
         }
         if let Some(terminal_type) = self.builder.playback.terminal_type {
-            self.dir.write("p_terminal_type", terminal_type.to_string())?;
+            self.dir.write_optional("p_terminal_type", terminal_type.to_string(), strictness)?;
         }
         if let Some(input_terminal_name) = &self.builder.playback.input_terminal_name {
-            self.dir.write("p_it_name", input_terminal_name)?;
+            self.dir.write_optional("p_it_name", input_terminal_name, strictness)?;
         }
         if let Some(input_terminal_channel_name) = &self.builder.playback.input_terminal_channel_name {
-            self.dir.write("p_it_ch_name", input_terminal_channel_name)?;
+            self.dir.write_optional("p_it_ch_name", input_terminal_channel_name, strictness)?;
         }
         if let Some(output_terminal_name) = &self.builder.playback.output_terminal_name {
-            self.dir.write("p_ot_name", output_terminal_name)?;
+            self.dir.write_optional("p_ot_name", output_terminal_name, strictness)?;
         }
 
         // general
         if let Some(fb_max) = self.builder.fb_max {
-            self.dir.write("fb_max", fb_max.to_string())?;
+            self.dir.write_optional("fb_max", fb_max.to_string(), strictness)?;
         }
         if let Some(request_number) = self.builder.request_number {
-            self.dir.write("req_number", request_number.to_string())?;
+            self.dir.write_optional("req_number", request_number.to_string(), strictness)?;
         }
         if let Some(function_name) = &self.builder.function_name {
-            self.dir.write("function_name", function_name)?;
+            self.dir.write_optional("function_name", function_name, strictness)?;
         }
         if let Some(control_name) = &self.builder.control_name {
-            self.dir.write("if_ctrl_name", control_name)?;
+            self.dir.write_optional("if_ctrl_name", control_name, strictness)?;
         }
         if let Some(clock_source_in_name) = &self.builder.clock_source_in_name {
-            self.dir.write("clksrc_in_name", clock_source_in_name)?;
+            self.dir.write_optional("clksrc_in_name", clock_source_in_name, strictness)?;
         }
         if let Some(clock_source_out_name) = &self.builder.clock_source_out_name {
-            self.dir.write("clksrc_out_name", clock_source_out_name)?;
+            self.dir.write_optional("clksrc_out_name", clock_source_out_name, strictness)?;
         }
 
         Ok(())
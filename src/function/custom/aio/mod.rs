@@ -11,11 +11,18 @@ use std::{
     os::fd::{AsRawFd, RawFd},
     pin::Pin,
     ptr,
-    sync::{mpsc, mpsc::TryRecvError, Arc},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+        mpsc::TryRecvError,
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use super::latency::LatencyStats;
+
 mod sys;
 
 pub use sys::opcode;
@@ -26,15 +33,17 @@ struct EventFd(Arc<eventfd::EventFd>);
 
 impl EventFd {
     /// Create new eventfd with initial value and semaphore characteristics, if requested.
-    pub fn new(initval: u32, semaphore: bool) -> Result<Self> {
-        let flags = if semaphore { EfdFlags::EFD_SEMAPHORE } else { EfdFlags::empty() };
+    pub fn new(initval: u32, semaphore: bool, nonblocking: bool) -> Result<Self> {
+        let mut flags = EfdFlags::EFD_CLOEXEC;
+        flags.set(EfdFlags::EFD_SEMAPHORE, semaphore);
+        flags.set(EfdFlags::EFD_NONBLOCK, nonblocking);
         let fd = eventfd::EventFd::from_value_and_flags(initval, flags)?;
         Ok(Self(Arc::new(fd)))
     }
 
-    /// Decrease value by one or set to zero if using semaphore characteristics.
+    /// Decrease value by one if using semaphore characteristics or set to zero otherwise.
     ///
-    /// Blocks while value is zero.
+    /// Blocks while value is zero, unless non-blocking.
     pub fn read(&self) -> Result<u64> {
         let mut buf = [0; 8];
         let ret = unsafe { libc::read(self.0.as_raw_fd(), buf.as_mut_ptr() as *mut _, buf.len()) };
@@ -63,21 +72,38 @@ impl AsRawFd for EventFd {
 }
 
 /// AIO context wrapper.
-#[derive(Debug)]
-struct Context(sys::ContextId);
+///
+/// Owns the submitted operations, so that their buffers are only freed
+/// after the context has been destroyed and the kernel no longer accesses them.
+struct Context {
+    id: sys::ContextId,
+    active: Mutex<HashMap<u64, Op>>,
+}
 
 impl Context {
     /// create an asynchronous I/O context
     pub fn new(nr_events: u32) -> Result<Self> {
         let mut id = 0;
         unsafe { sys::setup(nr_events, &mut id) }?;
-        Ok(Self(id))
+        Ok(Self { id, active: Mutex::new(HashMap::new()) })
+    }
+
+    /// Submitted operations.
+    fn active(&self) -> MutexGuard<'_, HashMap<u64, Op>> {
+        self.active.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Context").field(&self.id).finish()
     }
 }
 
 impl Drop for Context {
     fn drop(&mut self) {
-        unsafe { sys::destroy(self.0) }.expect("cannot destory AIO context");
+        // Waits for all outstanding operations, which are freed afterwards.
+        unsafe { sys::destroy(self.id) }.expect("cannot destory AIO context");
     }
 }
 
@@ -85,7 +111,7 @@ impl Deref for Context {
     type Target = sys::ContextId;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.id
     }
 }
 
@@ -174,11 +200,15 @@ struct Op {
     pub iocb: Pin<Box<sys::IoCb>>,
     /// Buffer referenced by [`Self::iocb`].
     pub buf: Buffer,
+    /// Id of driver that submitted the operation.
+    pub client: u64,
+    /// Time of submission.
+    pub submitted: Instant,
 }
 
 impl Default for Op {
     fn default() -> Self {
-        Self { iocb: Box::pin(Default::default()), buf: Default::default() }
+        Self { iocb: Box::pin(Default::default()), buf: Default::default(), client: 0, submitted: Instant::now() }
     }
 }
 
@@ -199,7 +229,46 @@ impl Op {
             Err(Error::from_raw_os_error(-i32::try_from(event.res).unwrap()))
         };
 
-        CompletedOp { id: event.data, res: event.res, res2: event.res2, result }
+        CompletedOp {
+            id: event.data,
+            res: event.res,
+            res2: event.res2,
+            result,
+            latency: self.submitted.elapsed(),
+        }
+    }
+}
+
+/// Options for submitting an AIO operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct SubmitOptions {
+    /// I/O priority of the operation, as encoded by `ioprio_set(2)`.
+    ///
+    /// If `None`, the priority of the submitting thread is used.
+    pub priority: Option<i16>,
+    /// `RWF_*` flags of the operation, see `preadv2(2)`.
+    pub rw_flags: i32,
+}
+
+impl SubmitOptions {
+    /// Creates default submit options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the I/O priority of the operation.
+    #[must_use]
+    pub fn with_priority(mut self, priority: i16) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Sets the `RWF_*` flags of the operation.
+    #[must_use]
+    pub fn with_rw_flags(mut self, rw_flags: i32) -> Self {
+        self.rw_flags = rw_flags;
+        self
     }
 }
 
@@ -221,6 +290,7 @@ pub struct CompletedOp {
     res: i64,
     res2: i64,
     result: Result<Buffer>,
+    latency: Duration,
 }
 
 impl CompletedOp {
@@ -230,13 +300,18 @@ impl CompletedOp {
         self.id
     }
 
+    /// Time between submission and completion.
+    #[allow(dead_code)]
+    pub const fn latency(&self) -> Duration {
+        self.latency
+    }
+
     /// Retrieve result.
     pub fn result(self) -> Result<Buffer> {
         self.result
     }
 
     /// Result code.
-    #[allow(dead_code)]
     pub const fn res(&self) -> i64 {
         self.res
     }
@@ -249,11 +324,15 @@ impl CompletedOp {
 }
 
 enum Cmd {
-    Insert(Op),
-    Remove(u64),
+    Register {
+        client: u64,
+        done_tx: mpsc::Sender<CompletedOp>,
+        notify: TNotify,
+    },
+    Unregister(u64),
     #[allow(dead_code)]
     Cancel(u64),
-    CancelAll,
+    CancelAll(u64),
 }
 
 #[cfg(feature = "tokio")]
@@ -261,108 +340,159 @@ type TNotify = Arc<tokio::sync::Notify>;
 #[cfg(not(feature = "tokio"))]
 type TNotify = Arc<()>;
 
-/// AIO driver.
+/// AIO context and completion thread that can be shared by multiple endpoints.
 ///
-/// All outstanding operations are cancelled when this is dropped.
-pub struct Driver {
+/// Sharing reduces the number of threads and wakeups when many endpoints are used.
+/// The queue length must be large enough to hold the outstanding operations of
+/// all endpoints using it, otherwise submissions fail with [`ErrorKind::WouldBlock`].
+///
+/// The thread exits once all clones and all endpoints using it have been dropped.
+#[derive(Clone)]
+pub struct SharedAio {
     aio: Arc<Context>,
-    cmd_tx: mpsc::Sender<Cmd>,
-    done_rx: mpsc::Receiver<CompletedOp>,
-    next_id: u64,
     eventfd: EventFd,
-    space: u32,
+    cmd_tx: mpsc::Sender<Cmd>,
+    next_id: Arc<AtomicU64>,
+    next_client: Arc<AtomicU64>,
     queue_length: u32,
-    #[cfg(feature = "tokio")]
-    notify: Arc<tokio::sync::Notify>,
 }
 
-impl fmt::Debug for Driver {
+impl fmt::Debug for SharedAio {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Driver")
-            .field("aio", &*self.aio)
-            .field("next_id", &self.next_id)
-            .field("space", &self.space)
-            .field("queue_length", &self.queue_length)
-            .finish()
+        f.debug_struct("SharedAio").field("aio", &*self.aio).field("queue_length", &self.queue_length).finish()
     }
 }
 
-impl Driver {
-    /// Create new AIO driver.
-    pub fn new(queue_length: u32, thread_name: Option<String>) -> Result<Self> {
+impl SharedAio {
+    /// Creates a new shared AIO context with the specified queue length.
+    pub fn new(queue_length: u32) -> Result<Self> {
+        Self::with_thread_name(queue_length, Some("usb-gadget-aio".to_string()))
+    }
+
+    fn with_thread_name(queue_length: u32, thread_name: Option<String>) -> Result<Self> {
         let (cmd_tx, cmd_rx) = mpsc::channel();
-        let (done_tx, done_rx) = mpsc::channel();
 
         let aio = Arc::new(Context::new(queue_length)?);
-        let eventfd = EventFd::new(0, true)?;
-
-        #[cfg(feature = "tokio")]
-        let notify = Arc::new(tokio::sync::Notify::new());
-        #[cfg(not(feature = "tokio"))]
-        let notify = Arc::new(());
+        let eventfd = EventFd::new(0, true, false)?;
 
-        let aio_thread = aio.clone();
+        let reactor = Reactor::new(aio.clone(), cmd_rx);
         let eventfd_thread = eventfd.clone();
-        let notify_thread = notify.clone();
 
         let mut builder = thread::Builder::new();
         if let Some(thread_name) = thread_name {
             builder = builder.name(thread_name);
         }
-        builder.spawn(|| Self::thread(aio_thread, eventfd_thread, cmd_rx, done_tx, notify_thread))?;
+        builder.spawn(|| reactor.thread(eventfd_thread))?;
+
+        Ok(Self::from_parts(aio, eventfd, cmd_tx, queue_length))
+    }
+
+    /// Creates a new shared AIO context with the specified queue length, whose
+    /// completions are processed by a task on the current tokio runtime.
+    ///
+    /// No thread is spawned; instead, the AIO eventfd is polled using the runtime's
+    /// reactor and completions are dispatched to the waiting endpoints from the task.
+    /// This makes it feasible to run many endpoints on a `current_thread` runtime.
+    ///
+    /// Endpoints using this context must only be awaited asynchronously from within
+    /// the runtime, since blocking waits prevent the task from processing completions
+    /// on a `current_thread` runtime.
+    ///
+    /// Outstanding operations are cancelled when the runtime shuts down.
+    ///
+    /// # Panics
+    /// Panics if called outside of a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn new_async(queue_length: u32) -> Result<Self> {
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+
+        let aio = Arc::new(Context::new(queue_length)?);
+        let eventfd = EventFd::new(0, false, true)?;
+
+        let reactor = Reactor::new(aio.clone(), cmd_rx);
+        let async_eventfd =
+            tokio::io::unix::AsyncFd::with_interest(eventfd.clone(), tokio::io::Interest::READABLE)?;
+        tokio::spawn(reactor.task(async_eventfd));
 
-        Ok(Self {
+        Ok(Self::from_parts(aio, eventfd, cmd_tx, queue_length))
+    }
+
+    fn from_parts(aio: Arc<Context>, eventfd: EventFd, cmd_tx: mpsc::Sender<Cmd>, queue_length: u32) -> Self {
+        Self {
             aio,
-            cmd_tx,
-            done_rx,
-            next_id: 0,
             eventfd,
-            space: queue_length,
+            cmd_tx,
+            next_id: Arc::new(AtomicU64::new(0)),
+            next_client: Arc::new(AtomicU64::new(0)),
             queue_length,
-            #[cfg(feature = "tokio")]
-            notify,
-        })
+        }
     }
 
-    /// Returns whether the queue of AIO operations is full.
-    pub fn is_full(&self) -> bool {
-        self.space == 0
+    /// Queue length.
+    pub fn queue_length(&self) -> u32 {
+        self.queue_length
     }
 
-    /// Returns whether the queue of AIO operations is empty.
-    pub fn is_empty(&self) -> bool {
-        self.space == self.queue_length
+    /// Sends a command to the thread.
+    ///
+    /// The command is discarded if the task has been dropped together with its runtime.
+    fn send(&self, cmd: Cmd) {
+        let _ = self.cmd_tx.send(cmd);
+        self.eventfd.write(1).unwrap();
     }
+}
 
-    /// Submits an AIO operation.
-    pub fn submit(&mut self, opcode: u16, file: impl AsRawFd, buf: impl Into<Buffer>) -> Result<OpHandle> {
-        if self.is_full() {
-            return Err(Error::new(ErrorKind::WouldBlock, "no AIO queue space available"));
-        }
+/// Registration of a driver with the AIO thread.
+///
+/// All outstanding operations are cancelled when this is dropped.
+struct Client {
+    shared: SharedAio,
+    id: u64,
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    notify: TNotify,
+}
+
+impl Client {
+    /// Registers a new client receiving completed operations through `done_tx`.
+    fn new(shared: &SharedAio, done_tx: mpsc::Sender<CompletedOp>) -> Self {
+        #[cfg(feature = "tokio")]
+        let notify = Arc::new(tokio::sync::Notify::new());
+        #[cfg(not(feature = "tokio"))]
+        let notify = Arc::new(());
+
+        let id = shared.next_client.fetch_add(1, Ordering::Relaxed);
+        shared.send(Cmd::Register { client: id, done_tx, notify: notify.clone() });
 
-        let id = self.next_id;
-        self.next_id = self.next_id.wrapping_add(1);
+        Self { shared: shared.clone(), id, notify }
+    }
 
-        let mut buf = buf.into();
-        let iocb =
-            sys::IoCb::new(opcode, file.as_raw_fd(), unsafe { buf.as_mut_ptr() }, buf.size().try_into().unwrap())
-                .with_resfd(self.eventfd.as_raw_fd())
-                .with_data(id);
+    /// Submits an AIO operation without accounting for queue space.
+    fn submit(&self, opcode: u16, fd: RawFd, buf: Buffer, options: &SubmitOptions) -> Result<OpHandle> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut buf = buf;
+        let mut iocb = sys::IoCb::new(opcode, fd, unsafe { buf.as_mut_ptr() }, buf.size().try_into().unwrap())
+            .with_resfd(self.shared.eventfd.as_raw_fd())
+            .with_data(id)
+            .with_rw_flags(options.rw_flags);
+        if let Some(priority) = options.priority {
+            iocb = iocb.with_reqprio(priority);
+        }
 
-        let mut op = Op { iocb: Box::pin(iocb), buf };
+        let mut op = Op { iocb: Box::pin(iocb), buf, client: self.id, submitted: Instant::now() };
         let iocb_ptr = op.iocb_ptr();
-        self.cmd_tx.send(Cmd::Insert(op)).unwrap();
+        if self.shared.aio.active().insert(id, op).is_some() {
+            panic!("submitted aio request with duplicate id");
+        }
 
         let mut iocbs = [iocb_ptr];
-        match unsafe { sys::submit(**self.aio, 1, iocbs.as_mut_ptr()) } {
+        match unsafe { sys::submit(**self.shared.aio, 1, iocbs.as_mut_ptr()) } {
             Ok(1) => {
-                self.space -= 1;
-                self.eventfd.write(1).unwrap();
+                self.shared.eventfd.write(1).unwrap();
                 Ok(OpHandle(id))
             }
             res => {
-                self.cmd_tx.send(Cmd::Remove(id)).unwrap();
-                self.eventfd.write(1).unwrap();
+                self.shared.aio.active().remove(&id);
 
                 match res {
                     Ok(_) => Err(Error::new(ErrorKind::WouldBlock, "AIO request not accepted")),
@@ -372,6 +502,89 @@ impl Driver {
         }
     }
 
+    /// Requests cancellation of all operations.
+    fn cancel_all(&self) {
+        self.shared.send(Cmd::CancelAll(self.id));
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.cancel_all();
+        self.shared.send(Cmd::Unregister(self.id));
+    }
+}
+
+/// AIO driver.
+///
+/// All outstanding operations are cancelled when this is dropped.
+pub struct Driver {
+    client: Arc<Client>,
+    done_rx: mpsc::Receiver<CompletedOp>,
+    space: u32,
+    queue_length: u32,
+    latency: Option<LatencyStats>,
+}
+
+impl fmt::Debug for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Driver")
+            .field("aio", &*self.client.shared.aio)
+            .field("client", &self.client.id)
+            .field("space", &self.space)
+            .field("queue_length", &self.queue_length)
+            .finish()
+    }
+}
+
+impl Driver {
+    /// Create new AIO driver using its own AIO context and thread.
+    pub fn new(queue_length: u32, thread_name: Option<String>) -> Result<Self> {
+        let shared = SharedAio::with_thread_name(queue_length, thread_name)?;
+        Ok(Self::shared(&shared, queue_length))
+    }
+
+    /// Create new AIO driver using a shared AIO context and thread.
+    pub fn shared(shared: &SharedAio, queue_length: u32) -> Self {
+        let (done_tx, done_rx) = mpsc::channel();
+        Self {
+            client: Arc::new(Client::new(shared, done_tx)),
+            done_rx,
+            space: queue_length,
+            queue_length,
+            latency: None,
+        }
+    }
+
+    /// Returns whether the queue of AIO operations is full.
+    pub fn is_full(&self) -> bool {
+        self.space == 0
+    }
+
+    /// Returns whether the queue of AIO operations is empty.
+    pub fn is_empty(&self) -> bool {
+        self.space == self.queue_length
+    }
+
+    /// Submits an AIO operation.
+    #[allow(dead_code)]
+    pub fn submit(&mut self, opcode: u16, file: impl AsRawFd, buf: impl Into<Buffer>) -> Result<OpHandle> {
+        self.submit_with(opcode, file, buf, &SubmitOptions::default())
+    }
+
+    /// Submits an AIO operation using the specified options.
+    pub fn submit_with(
+        &mut self, opcode: u16, file: impl AsRawFd, buf: impl Into<Buffer>, options: &SubmitOptions,
+    ) -> Result<OpHandle> {
+        if self.is_full() {
+            return Err(Error::new(ErrorKind::WouldBlock, "no AIO queue space available"));
+        }
+
+        let handle = self.client.submit(opcode, file.as_raw_fd(), buf.into(), options)?;
+        self.space -= 1;
+        Ok(handle)
+    }
+
     /// Retrieves the next operation from the completion queue.
     ///
     /// Blocks until a completed operation becomes available.
@@ -381,25 +594,36 @@ impl Driver {
         }
 
         let res = self.done_rx.recv().unwrap();
-        self.space += 1;
+        self.retrieved(&res);
         Some(res)
     }
 
     /// Asynchronously retrieves the next operation from the completion queue.
     ///
     /// Waits until a completed operation becomes available.
+    ///
+    /// # Cancel safety
+    /// This method is cancel safe.
+    /// Completed operations are kept in the completion queue until they are retrieved,
+    /// thus dropping the future before it resolves does not lose any operation.
     #[cfg(feature = "tokio")]
     pub async fn wait_completed(&mut self) -> Option<CompletedOp> {
         if self.is_empty() {
             return None;
         }
 
+        let notify = self.client.notify.clone();
         loop {
+            // Register for notification before checking the queue to avoid missing a wakeup.
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
             if let Some(op) = self.try_completed() {
                 return Some(op);
             }
 
-            self.notify.notified().await;
+            notified.await;
         }
     }
 
@@ -412,8 +636,8 @@ impl Driver {
         }
 
         let res = self.done_rx.recv_timeout(timeout).ok();
-        if res.is_some() {
-            self.space += 1;
+        if let Some(op) = &res {
+            self.retrieved(op);
         }
         res
     }
@@ -423,121 +647,616 @@ impl Driver {
     /// Returns immediately if no completed operation is available.
     pub fn try_completed(&mut self) -> Option<CompletedOp> {
         let res = self.done_rx.try_recv().ok();
-        if res.is_some() {
-            self.space += 1;
+        if let Some(op) = &res {
+            self.retrieved(op);
         }
         res
     }
 
+    /// Accounts for an operation retrieved from the completion queue.
+    fn retrieved(&mut self, op: &CompletedOp) {
+        self.space += 1;
+        if let (Some(stats), true) = (&mut self.latency, op.res >= 0) {
+            stats.record(op.latency);
+        }
+    }
+
+    /// Sets the latency statistics that completed operations are recorded into.
+    pub fn set_latency_stats(&mut self, stats: Option<LatencyStats>) {
+        self.latency = stats;
+    }
+
+    /// Latency statistics, if enabled.
+    pub fn latency_stats(&self) -> Option<&LatencyStats> {
+        self.latency.as_ref()
+    }
+
     /// Requests cancellation of the specified operation.
     #[allow(dead_code)]
     pub fn cancel(&mut self, handle: OpHandle) {
-        self.cmd_tx.send(Cmd::Cancel(handle.0)).unwrap();
-        self.eventfd.write(1).unwrap();
+        self.client.shared.send(Cmd::Cancel(handle.0));
     }
 
     /// Requests cancellation of all operations.
     pub fn cancel_all(&mut self) {
-        self.cmd_tx.send(Cmd::CancelAll).unwrap();
-        self.eventfd.write(1).unwrap();
+        self.client.cancel_all();
     }
 
-    /// Thread managing submitted AIO operations.
-    fn thread(
-        aio: Arc<Context>, eventfd: EventFd, cmd_rx: mpsc::Receiver<Cmd>, done_tx: mpsc::Sender<CompletedOp>,
-        notify: TNotify,
-    ) {
-        #[cfg(not(feature = "tokio"))]
-        let _ = notify;
+    /// Splits the driver into a submitter and a completer that can be used from different threads.
+    ///
+    /// Outstanding operations are kept.
+    pub fn split(self) -> (Submitter, Completer) {
+        let space = Arc::new(Space::new(self.queue_length, self.queue_length - self.space));
+        let submitter = Submitter { client: self.client.clone(), space: space.clone() };
+        let completer = Completer { client: self.client, done_rx: self.done_rx, space, latency: self.latency };
+        (submitter, completer)
+    }
+}
+
+/// Queue space shared by a [`Submitter`] and a [`Completer`].
+struct Space {
+    state: Mutex<SpaceState>,
+    changed: Condvar,
+    #[cfg(feature = "tokio")]
+    notify: tokio::sync::Notify,
+    queue_length: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SpaceState {
+    /// Operations being submitted or outstanding.
+    reserved: u32,
+    /// Operations outstanding.
+    submitted: u32,
+}
+
+impl Space {
+    fn new(queue_length: u32, submitted: u32) -> Self {
+        Self {
+            state: Mutex::new(SpaceState { reserved: submitted, submitted }),
+            changed: Condvar::new(),
+            #[cfg(feature = "tokio")]
+            notify: tokio::sync::Notify::new(),
+            queue_length,
+        }
+    }
+
+    fn state(&self) -> SpaceState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Updates the state and wakes all waiters.
+    fn update(&self, f: impl FnOnce(&mut SpaceState)) {
+        f(&mut self.state.lock().unwrap());
+        self.changed.notify_all();
+        #[cfg(feature = "tokio")]
+        self.notify.notify_waiters();
+    }
+
+    /// Reserves space for an operation, if available.
+    fn reserve(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.reserved == self.queue_length {
+            return false;
+        }
+        state.reserved += 1;
+        true
+    }
+
+    /// Waits until `cond` holds or the timeout is reached and returns the state.
+    fn wait(&self, timeout: Option<Duration>, cond: impl Fn(&SpaceState) -> bool) -> SpaceState {
+        let state = self.state.lock().unwrap();
+        let state = match timeout {
+            Some(timeout) => self.changed.wait_timeout_while(state, timeout, |s| !cond(s)).unwrap().0,
+            None => self.changed.wait_while(state, |s| !cond(s)).unwrap(),
+        };
+        *state
+    }
+}
+
+/// Submitting half of a split AIO driver.
+pub struct Submitter {
+    client: Arc<Client>,
+    space: Arc<Space>,
+}
+
+impl fmt::Debug for Submitter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Submitter")
+            .field("client", &self.client.id)
+            .field("state", &self.space.state())
+            .field("queue_length", &self.space.queue_length)
+            .finish()
+    }
+}
+
+impl Submitter {
+    /// Returns whether the queue of AIO operations is full.
+    pub fn is_full(&self) -> bool {
+        self.space.state().reserved == self.space.queue_length
+    }
+
+    /// Returns whether the queue of AIO operations is empty.
+    pub fn is_empty(&self) -> bool {
+        self.space.state().reserved == 0
+    }
+
+    /// Submits an AIO operation using the specified options.
+    pub fn submit_with(
+        &mut self, opcode: u16, file: impl AsRawFd, buf: impl Into<Buffer>, options: &SubmitOptions,
+    ) -> Result<OpHandle> {
+        if !self.space.reserve() {
+            return Err(Error::new(ErrorKind::WouldBlock, "no AIO queue space available"));
+        }
+
+        match self.client.submit(opcode, file.as_raw_fd(), buf.into(), options) {
+            Ok(handle) => {
+                self.space.update(|state| state.submitted += 1);
+                Ok(handle)
+            }
+            Err(err) => {
+                self.space.update(|state| state.reserved -= 1);
+                // Wake a completer waiting asynchronously for the operation.
+                #[cfg(feature = "tokio")]
+                self.client.notify.notify_one();
+                Err(err)
+            }
+        }
+    }
+
+    /// Waits until queue space is available or the timeout is reached.
+    ///
+    /// Returns whether queue space is available.
+    pub fn wait_space(&self, timeout: Option<Duration>) -> bool {
+        self.space.wait(timeout, |state| state.reserved < self.space.queue_length).reserved
+            < self.space.queue_length
+    }
+
+    /// Asynchronously waits until queue space is available.
+    ///
+    /// # Cancel safety
+    /// This method is cancel safe.
+    #[cfg(feature = "tokio")]
+    pub async fn wait_space_async(&self) {
+        loop {
+            let notified = self.space.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if !self.is_full() {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Requests cancellation of all operations.
+    pub fn cancel_all(&self) {
+        self.client.cancel_all();
+    }
+}
+
+/// Completing half of a split AIO driver.
+pub struct Completer {
+    client: Arc<Client>,
+    done_rx: mpsc::Receiver<CompletedOp>,
+    space: Arc<Space>,
+    latency: Option<LatencyStats>,
+}
+
+impl fmt::Debug for Completer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Completer")
+            .field("client", &self.client.id)
+            .field("state", &self.space.state())
+            .field("queue_length", &self.space.queue_length)
+            .finish()
+    }
+}
 
-        let mut active: HashMap<u64, Op> = HashMap::new();
-        let mut event_queue = VecDeque::new();
+impl Completer {
+    /// Returns whether no operations are outstanding.
+    pub fn is_empty(&self) -> bool {
+        self.space.state().reserved == 0
+    }
+
+    /// Retrieves the next operation from the completion queue.
+    ///
+    /// Blocks until a completed operation becomes available.
+    /// Returns `None` if no operations are outstanding.
+    pub fn completed(&mut self) -> Option<CompletedOp> {
+        if self.space.wait(None, |state| state.submitted > 0 || state.reserved == 0).submitted == 0 {
+            return None;
+        }
+
+        let res = self.done_rx.recv().unwrap();
+        self.retrieved(&res);
+        Some(res)
+    }
 
-        'outer: loop {
+    /// Asynchronously retrieves the next operation from the completion queue.
+    ///
+    /// Waits until a completed operation becomes available.
+    /// Returns `None` if no operations are outstanding.
+    ///
+    /// # Cancel safety
+    /// This method is cancel safe.
+    #[cfg(feature = "tokio")]
+    pub async fn wait_completed(&mut self) -> Option<CompletedOp> {
+        let notify = self.client.notify.clone();
+        loop {
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if let Some(op) = self.try_completed() {
+                return Some(op);
+            }
+            if self.is_empty() {
+                return None;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Retrieves the next operation from the completion queue with a timeout.
+    ///
+    /// Blocks until a completed operation becomes available or the timeout is reached.
+    pub fn completed_timeout(&mut self, timeout: Duration) -> Option<CompletedOp> {
+        let start = Instant::now();
+        if self.space.wait(Some(timeout), |state| state.submitted > 0 || state.reserved == 0).submitted == 0 {
+            return None;
+        }
+
+        let res = self.done_rx.recv_timeout(timeout.saturating_sub(start.elapsed())).ok();
+        if let Some(op) = &res {
+            self.retrieved(op);
+        }
+        res
+    }
+
+    /// Retrieves the next operation from the completion queue without blocking.
+    pub fn try_completed(&mut self) -> Option<CompletedOp> {
+        let res = self.done_rx.try_recv().ok();
+        if let Some(op) = &res {
+            self.retrieved(op);
+        }
+        res
+    }
+
+    /// Accounts for an operation retrieved from the completion queue.
+    fn retrieved(&mut self, op: &CompletedOp) {
+        self.space.update(|state| {
+            state.reserved -= 1;
+            state.submitted -= 1;
+        });
+        if let (Some(stats), true) = (&mut self.latency, op.res >= 0) {
+            stats.record(op.latency);
+        }
+    }
+
+    /// Sets the latency statistics that completed operations are recorded into.
+    pub fn set_latency_stats(&mut self, stats: Option<LatencyStats>) {
+        self.latency = stats;
+    }
+
+    /// Latency statistics, if enabled.
+    pub fn latency_stats(&self) -> Option<&LatencyStats> {
+        self.latency.as_ref()
+    }
+}
+
+type Clients = HashMap<u64, (mpsc::Sender<CompletedOp>, TNotify)>;
+
+/// Manages submitted AIO operations and dispatches their completions.
+///
+/// Outstanding operations are cancelled when this is dropped.
+struct Reactor {
+    aio: Arc<Context>,
+    cmd_rx: mpsc::Receiver<Cmd>,
+    clients: Clients,
+    event_queue: VecDeque<sys::IoEvent>,
+}
+
+impl Reactor {
+    fn new(aio: Arc<Context>, cmd_rx: mpsc::Receiver<Cmd>) -> Self {
+        Self { aio, cmd_rx, clients: HashMap::new(), event_queue: VecDeque::new() }
+    }
+
+    /// Thread processing events signalled through the semaphore eventfd.
+    fn thread(mut self, eventfd: EventFd) {
+        loop {
             // Wait for event.
             eventfd.read().unwrap();
 
-            // Process commands.
-            loop {
-                match cmd_rx.try_recv() {
-                    Ok(Cmd::Insert(op)) => {
-                        if active.insert(op.iocb.data, op).is_some() {
-                            panic!("submitted aio request with duplicate id");
-                        }
-                    }
-                    Ok(Cmd::Remove(id)) => {
-                        active.remove(&id).expect("received remove request for unknown id");
-                    }
-                    Ok(Cmd::Cancel(id)) => {
-                        if let Entry::Occupied(mut op) = active.entry(id) {
-                            let mut event = MaybeUninit::<sys::IoEvent>::uninit();
-                            if unsafe {
-                                sys::cancel(**aio, op.get_mut().iocb_ptr(), &mut event as *mut _ as *mut _)
-                            }
+            if !self.process() {
+                break;
+            }
+        }
+    }
+
+    /// Task processing events signalled through the non-blocking eventfd.
+    #[cfg(feature = "tokio")]
+    async fn task(mut self, eventfd: tokio::io::unix::AsyncFd<EventFd>) {
+        loop {
+            let mut guard = match eventfd.readable().await {
+                Ok(guard) => guard,
+                Err(err) => {
+                    log::error!("polling AIO eventfd failed: {err}");
+                    return;
+                }
+            };
+
+            match eventfd.get_ref().read() {
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    guard.clear_ready();
+                    continue;
+                }
+                Err(err) => panic!("reading AIO eventfd failed: {err}"),
+            }
+
+            if !self.process() {
+                break;
+            }
+        }
+    }
+
+    /// Delivers a completed operation to the driver that submitted it, if it still exists.
+    fn deliver(clients: &Clients, client: u64, op: CompletedOp) {
+        if let Some((done_tx, notify)) = clients.get(&client) {
+            let _ = done_tx.send(op);
+            #[cfg(feature = "tokio")]
+            notify.notify_one();
+            #[cfg(not(feature = "tokio"))]
+            let _ = notify;
+        }
+    }
+
+    /// Processes pending commands and completed operations.
+    ///
+    /// Returns `false` once the shared context has been dropped and no operations are active.
+    fn process(&mut self) -> bool {
+        let Self { aio, cmd_rx, clients, event_queue } = self;
+        let mut active = aio.active();
+
+        // Process commands.
+        loop {
+            match cmd_rx.try_recv() {
+                Ok(Cmd::Register { client, done_tx, notify }) => {
+                    clients.insert(client, (done_tx, notify));
+                }
+                Ok(Cmd::Unregister(client)) => {
+                    clients.remove(&client);
+                }
+                Ok(Cmd::Cancel(id)) => {
+                    if let Entry::Occupied(mut op) = active.entry(id) {
+                        let mut event = MaybeUninit::<sys::IoEvent>::uninit();
+                        if unsafe { sys::cancel(***aio, op.get_mut().iocb_ptr(), &mut event as *mut _ as *mut _) }
                             .is_ok()
-                            {
-                                let _ = done_tx.send(op.remove().complete(unsafe { event.assume_init() }));
-                                #[cfg(feature = "tokio")]
-                                notify.notify_one();
-                            }
+                        {
+                            let op = op.remove();
+                            let client = op.client;
+                            Self::deliver(clients, client, op.complete(unsafe { event.assume_init() }));
                         }
                     }
-                    Ok(Cmd::CancelAll) => {
-                        active.retain(|_id, op| {
-                            let mut event = MaybeUninit::<sys::IoEvent>::uninit();
-                            if unsafe { sys::cancel(**aio, op.iocb_ptr(), &mut event as *mut _ as *mut _) }
-                                .is_ok()
-                            {
-                                let _ = done_tx.send(mem::take(op).complete(unsafe { event.assume_init() }));
-                                #[cfg(feature = "tokio")]
-                                notify.notify_one();
-                                false
-                            } else {
-                                true
-                            }
-                        });
-                    }
-                    Err(TryRecvError::Disconnected) if active.is_empty() => break 'outer,
-                    Err(_) => break,
                 }
+                Ok(Cmd::CancelAll(client)) => {
+                    active.retain(|_id, op| {
+                        if op.client != client {
+                            return true;
+                        }
+
+                        let mut event = MaybeUninit::<sys::IoEvent>::uninit();
+                        if unsafe { sys::cancel(***aio, op.iocb_ptr(), &mut event as *mut _ as *mut _) }.is_ok() {
+                            Self::deliver(
+                                clients,
+                                client,
+                                mem::take(op).complete(unsafe { event.assume_init() }),
+                            );
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                }
+                Err(TryRecvError::Disconnected) if active.is_empty() => return false,
+                Err(_) => break,
             }
+        }
 
-            // Fetch AIO events.
-            loop {
-                let mut events = [MaybeUninit::<sys::IoEvent>::uninit(); 16];
+        // Fetch AIO events.
+        loop {
+            let mut events = [MaybeUninit::<sys::IoEvent>::uninit(); 16];
 
-                let n = unsafe {
-                    sys::getevents(**aio, 0, events.len() as _, events.as_mut_ptr() as *mut _, ptr::null())
-                }
-                .expect("io_getevents failed");
+            let n = unsafe {
+                sys::getevents(***aio, 0, events.len() as _, events.as_mut_ptr() as *mut _, ptr::null())
+            }
+            .expect("io_getevents failed");
 
-                if n == 0 {
-                    break;
-                }
+            if n == 0 {
+                break;
+            }
 
-                for event in events.into_iter().take(n.try_into().unwrap()) {
-                    let event = unsafe { event.assume_init() };
-                    event_queue.push_back(event);
-                }
+            for event in events.into_iter().take(n.try_into().unwrap()) {
+                let event = unsafe { event.assume_init() };
+                event_queue.push_back(event);
             }
+        }
 
-            // Process AIO events.
-            while let Some(event) = event_queue.front() {
-                match active.remove(&event.data) {
-                    Some(op) => {
-                        let _ = done_tx.send(op.complete(event_queue.pop_front().unwrap()));
-                        #[cfg(feature = "tokio")]
-                        notify.notify_one();
-                    }
-                    None => break,
+        // Process AIO events.
+        while let Some(event) = event_queue.front() {
+            match active.remove(&event.data) {
+                Some(op) => {
+                    let client = op.client;
+                    Self::deliver(clients, client, op.complete(event_queue.pop_front().unwrap()));
                 }
+                None => break,
             }
         }
+
+        true
     }
 }
 
-impl Drop for Driver {
+impl Drop for Reactor {
     fn drop(&mut self) {
-        self.cancel_all();
+        // The task is dropped with operations in flight when the tokio runtime shuts down.
+        // Operations that cannot be cancelled stay owned by the context until it is destroyed.
+        let aio = &*self.aio;
+        aio.active().retain(|_id, op| {
+            let mut event = MaybeUninit::<sys::IoEvent>::uninit();
+            unsafe { sys::cancel(**aio, op.iocb_ptr(), &mut event as *mut _ as *mut _) }.is_err()
+        });
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod test {
+    use bytes::{Bytes, BytesMut};
+    use std::{
+        io::Write,
+        os::fd::AsRawFd,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{opcode, Driver, SharedAio};
+
+    #[test]
+    fn split_driver() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"usb-gadget").unwrap();
+
+        let (mut submitter, mut completer) = Driver::new(2, None).unwrap().split();
+        let submit = std::thread::spawn(move || {
+            for _ in 0..16 {
+                assert!(submitter.wait_space(Some(Duration::from_secs(10))));
+                submitter
+                    .submit_with(
+                        opcode::PREAD,
+                        file.as_raw_fd(),
+                        BytesMut::with_capacity(64),
+                        &Default::default(),
+                    )
+                    .unwrap();
+            }
+            submitter
+        });
+
+        let mut n = 0;
+        while n < 16 {
+            if let Some(op) = completer.completed_timeout(Duration::from_millis(10)) {
+                let buf: BytesMut = op.result().unwrap().try_into().unwrap();
+                assert_eq!(&buf[..], b"usb-gadget");
+                n += 1;
+            }
+        }
+
+        let submitter = submit.join().unwrap();
+        assert!(submitter.is_empty());
+        assert!(completer.completed().is_none());
+    }
+
+    #[tokio::test]
+    async fn wait_completed_cancel_safe() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"usb-gadget").unwrap();
+
+        let mut driver = Driver::new(4, None).unwrap();
+        driver.submit(opcode::PREAD, file.as_raw_fd(), BytesMut::with_capacity(64)).unwrap();
+
+        // Drop the future immediately, possibly after the operation has completed.
+        let mut completed = tokio::time::timeout(Duration::ZERO, driver.wait_completed()).await.ok().flatten();
+
+        if completed.is_none() {
+            completed = driver.wait_completed().await;
+        }
+
+        let buf: BytesMut = completed.unwrap().result().unwrap().try_into().unwrap();
+        assert_eq!(&buf[..], b"usb-gadget");
+        assert!(driver.is_empty());
+        assert!(driver.wait_completed().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn shared_drivers() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"usb-gadget").unwrap();
+
+        let shared = SharedAio::new(8).unwrap();
+        let mut drivers = [Driver::shared(&shared, 4), Driver::shared(&shared, 4)];
+        for driver in &mut drivers {
+            driver.submit(opcode::PREAD, file.as_raw_fd(), BytesMut::with_capacity(64)).unwrap();
+        }
+
+        for driver in &mut drivers {
+            let buf: BytesMut = driver.wait_completed().await.unwrap().result().unwrap().try_into().unwrap();
+            assert_eq!(&buf[..], b"usb-gadget");
+            assert!(driver.is_empty());
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn async_shared_drivers() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"usb-gadget").unwrap();
+
+        let shared = SharedAio::new_async(64).unwrap();
+        let mut drivers: Vec<_> = (0..32).map(|_| Driver::shared(&shared, 2)).collect();
+        for _ in 0..2 {
+            for driver in &mut drivers {
+                driver.submit(opcode::PREAD, file.as_raw_fd(), BytesMut::with_capacity(64)).unwrap();
+            }
+        }
+
+        for driver in &mut drivers {
+            for _ in 0..2 {
+                let buf: BytesMut = driver.wait_completed().await.unwrap().result().unwrap().try_into().unwrap();
+                assert_eq!(&buf[..], b"usb-gadget");
+            }
+            assert!(driver.is_empty());
+        }
+    }
+
+    #[test]
+    fn async_runtime_dropped() {
+        struct Owner(Arc<AtomicBool>);
+
+        impl AsRef<[u8]> for Owner {
+            fn as_ref(&self) -> &[u8] {
+                b"usb-gadget"
+            }
+        }
+
+        impl Drop for Owner {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let file = tempfile::tempfile().unwrap();
+        let freed = Arc::new(AtomicBool::new(false));
+
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let (shared, mut driver) = rt.block_on(async {
+            let shared = SharedAio::new_async(4).unwrap();
+            let driver = Driver::shared(&shared, 4);
+            (shared, driver)
+        });
+        driver.submit(opcode::PWRITE, file.as_raw_fd(), Bytes::from_owner(Owner(freed.clone()))).unwrap();
+
+        // The buffer must outlive the task and is freed once the AIO context is destroyed.
+        drop(rt);
+        assert!(!freed.load(Ordering::SeqCst));
+
+        drop(driver);
+        drop(shared);
+        assert!(freed.load(Ordering::SeqCst));
     }
 }
@@ -112,6 +112,17 @@ impl IoCb {
         self.data = data;
         self
     }
+
+    pub fn with_reqprio(mut self, reqprio: i16) -> Self {
+        self.reqprio = reqprio;
+        self.flags |= flags::IOPRIO;
+        self
+    }
+
+    pub fn with_rw_flags(mut self, rw_flags: c_int) -> Self {
+        self.rw_flags = rw_flags;
+        self
+    }
 }
 
 /// create an asynchronous I/O context
@@ -0,0 +1,264 @@
+//! Throughput benchmarks of endpoints.
+//!
+//! The functions of this module drive an [`EndpointSender`] or [`EndpointReceiver`]
+//! for a configurable duration and report the achieved throughput and CPU usage.
+//! This allows quantifying the effect of the queue length, packet size and AIO
+//! configuration on reproducible numbers.
+//!
+//! The USB host must concurrently read from or write to the endpoint being benchmarked.
+
+use bytes::{Bytes, BytesMut};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    mem::MaybeUninit,
+    time::{Duration, Instant},
+};
+
+use super::{aio::CompletedOp, EndpointDirection, EndpointReceiver, EndpointSender};
+
+/// Benchmark configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BenchConfig {
+    /// Size of each transfer in bytes.
+    pub transfer_size: usize,
+    /// Queue length of endpoints created by [`device_to_host`](Self::device_to_host)
+    /// and [`host_to_device`](Self::host_to_device).
+    pub queue_len: u32,
+    /// Duration of the benchmark.
+    pub duration: Duration,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            transfer_size: 16_384,
+            queue_len: EndpointDirection::DEFAULT_QUEUE_LEN,
+            duration: Duration::from_secs(10),
+        }
+    }
+}
+
+impl BenchConfig {
+    /// Creates the default benchmark configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of each transfer in bytes.
+    #[must_use]
+    pub fn with_transfer_size(mut self, transfer_size: usize) -> Self {
+        self.transfer_size = transfer_size;
+        self
+    }
+
+    /// Sets the queue length of endpoints created from this configuration.
+    #[must_use]
+    pub fn with_queue_len(mut self, queue_len: u32) -> Self {
+        self.queue_len = queue_len;
+        self
+    }
+
+    /// Sets the duration of the benchmark.
+    #[must_use]
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Creates a device to host endpoint using the configured queue length.
+    pub fn device_to_host(&self) -> (EndpointSender, EndpointDirection) {
+        let (tx, dir) = EndpointDirection::device_to_host();
+        (tx, dir.with_queue_len(self.queue_len))
+    }
+
+    /// Creates a host to device endpoint using the configured queue length.
+    pub fn host_to_device(&self) -> (EndpointReceiver, EndpointDirection) {
+        let (rx, dir) = EndpointDirection::host_to_device();
+        (rx, dir.with_queue_len(self.queue_len))
+    }
+}
+
+/// Benchmark result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BenchResult {
+    /// Number of transferred bytes.
+    pub bytes: u64,
+    /// Number of completed transfers.
+    pub transfers: u64,
+    /// Elapsed wall-clock time.
+    pub elapsed: Duration,
+    /// CPU time consumed by the process, including all of its threads.
+    pub cpu_time: Duration,
+}
+
+impl BenchResult {
+    /// Throughput in megabytes (10⁶ bytes) per second.
+    pub fn mb_per_sec(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.bytes as f64 / self.elapsed.as_secs_f64() / 1e6
+    }
+
+    /// CPU usage as fraction of the elapsed time.
+    ///
+    /// This may exceed one if multiple threads were busy.
+    pub fn cpu_usage(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.cpu_time.as_secs_f64() / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes in {} transfers within {:.2} s: {:.2} MB/s, {:.1} % CPU",
+            self.bytes,
+            self.transfers,
+            self.elapsed.as_secs_f64(),
+            self.mb_per_sec(),
+            self.cpu_usage() * 100.0
+        )
+    }
+}
+
+/// CPU time consumed by the process.
+fn cpu_time() -> Result<Duration> {
+    let mut usage = MaybeUninit::<libc::rusage>::uninit();
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    let usage = unsafe { usage.assume_init() };
+
+    let time = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1_000);
+    Ok(time(usage.ru_utime) + time(usage.ru_stime))
+}
+
+/// Measures elapsed and CPU time of a benchmark.
+struct Measurement {
+    start: Instant,
+    cpu_start: Duration,
+    bytes: u64,
+    transfers: u64,
+}
+
+impl Measurement {
+    fn start() -> Result<Self> {
+        Ok(Self { start: Instant::now(), cpu_start: cpu_time()?, bytes: 0, transfers: 0 })
+    }
+
+    fn remaining(&self, duration: Duration) -> Option<Duration> {
+        duration.checked_sub(self.start.elapsed()).filter(|remaining| !remaining.is_zero())
+    }
+
+    fn record(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        self.transfers += 1;
+    }
+
+    fn record_sent(&mut self, comp: CompletedOp) -> Result<()> {
+        let bytes = usize::try_from(comp.res()).unwrap_or_default();
+        comp.result()?;
+        self.record(bytes);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<BenchResult> {
+        Ok(BenchResult {
+            bytes: self.bytes,
+            transfers: self.transfers,
+            elapsed: self.start.elapsed(),
+            cpu_time: cpu_time()?.saturating_sub(self.cpu_start),
+        })
+    }
+}
+
+fn check_config(config: &BenchConfig) -> Result<()> {
+    if config.transfer_size == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "transfer size must not be zero"));
+    }
+    Ok(())
+}
+
+/// Benchmarks sending data to the host.
+///
+/// Transfers are enqueued until the configured duration has elapsed, keeping the
+/// queue of the endpoint full.
+/// The benchmark ends early if the host stops reading.
+pub fn send(ep: &mut EndpointSender, config: &BenchConfig) -> Result<BenchResult> {
+    check_config(config)?;
+    let data = Bytes::from(vec![0x55; config.transfer_size]);
+
+    // Transfers are counted when completed, since queued transfers may be cancelled.
+    let mut m = Measurement::start()?;
+    while let Some(remaining) = m.remaining(config.duration) {
+        let io = ep.0.get()?;
+        while let Some(comp) = io.aio.try_completed() {
+            m.record_sent(comp)?;
+        }
+        if io.aio.is_full() {
+            match io.aio.completed_timeout(remaining) {
+                Some(comp) => m.record_sent(comp)?,
+                None => break,
+            }
+        }
+        ep.try_send(data.clone())?;
+    }
+
+    let io = ep.0.get()?;
+    while let Some(comp) = io.aio.completed_timeout(config.duration) {
+        m.record_sent(comp)?;
+    }
+    if !io.aio.is_empty() {
+        ep.cancel()?;
+    }
+
+    m.finish()
+}
+
+/// Benchmarks receiving data from the host.
+///
+/// Receive buffers are enqueued until the configured duration has elapsed, keeping
+/// the queue of the endpoint full.
+pub fn recv(ep: &mut EndpointReceiver, config: &BenchConfig) -> Result<BenchResult> {
+    check_config(config)?;
+
+    let mut m = Measurement::start()?;
+    while let Some(remaining) = m.remaining(config.duration) {
+        if let Some(data) = ep.recv_timeout(BytesMut::with_capacity(config.transfer_size), remaining)? {
+            m.record(data.len());
+        }
+    }
+    let res = m.finish();
+
+    ep.cancel()?;
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{cpu_time, BenchResult};
+
+    #[test]
+    fn result() {
+        let res = BenchResult {
+            bytes: 50_000_000,
+            transfers: 1000,
+            elapsed: Duration::from_secs(2),
+            cpu_time: Duration::from_millis(500),
+        };
+        assert_eq!(res.mb_per_sec(), 25.0);
+        assert_eq!(res.cpu_usage(), 0.25);
+        assert_eq!(res.to_string(), "50000000 bytes in 1000 transfers within 2.00 s: 25.00 MB/s, 25.0 % CPU");
+
+        assert!(cpu_time().is_ok());
+    }
+}
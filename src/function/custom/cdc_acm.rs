@@ -0,0 +1,493 @@
+//! Communications device class (CDC) abstract control model (ACM) implemented in user space.
+//!
+//! This provides a serial port on top of a [custom function](super::Custom), giving the
+//! application full control over the line coding, control line state and break signaling.
+//! Use the kernel's [serial function](crate::function::serial) when this is not required.
+//!
+//! Class-specific requests from the host are handled by [`Acm::event`] and reported as
+//! [`AcmEvent`]s.
+//! Data is transferred using the endpoints returned by [`Acm::data`].
+//!
+//! The functional descriptors of the communication interface refer to interface numbers
+//! relative to the function, since FunctionFS does not renumber them.
+//! Thus the function must provide the first interfaces of its configuration.
+
+use bytes::Bytes;
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    time::Duration,
+};
+
+use super::{
+    Association, CtrlRecipient, CtrlType, Custom, CustomBuilder, CustomDesc, Endpoint, EndpointDirection,
+    EndpointReceiver, EndpointSender, Event, Interface,
+};
+use crate::{function::Handle, Class};
+
+/// Interface class of the communication interface.
+pub const COMM_CLASS: Class = Class::CDC_ACM;
+
+/// Interface class of the data interface.
+pub const DATA_CLASS: Class = Class::CDC_DATA;
+
+/// Class-specific request: set line coding.
+pub const REQUEST_SET_LINE_CODING: u8 = 0x20;
+
+/// Class-specific request: get line coding.
+pub const REQUEST_GET_LINE_CODING: u8 = 0x21;
+
+/// Class-specific request: set control line state.
+pub const REQUEST_SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+/// Class-specific request: send break.
+pub const REQUEST_SEND_BREAK: u8 = 0x23;
+
+/// Notification: serial state.
+pub const NOTIFICATION_SERIAL_STATE: u8 = 0x20;
+
+/// Descriptor type of class-specific interface descriptors.
+const CS_INTERFACE: u8 = 0x24;
+
+/// Number of stop bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StopBits {
+    /// 1 stop bit.
+    #[default]
+    One,
+    /// 1.5 stop bits.
+    OnePointFive,
+    /// 2 stop bits.
+    Two,
+}
+
+/// Parity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Parity {
+    /// No parity.
+    #[default]
+    None,
+    /// Odd parity.
+    Odd,
+    /// Even parity.
+    Even,
+    /// Mark parity.
+    Mark,
+    /// Space parity.
+    Space,
+}
+
+/// Line coding of a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineCoding {
+    /// Data terminal rate in bits per second.
+    pub baud_rate: u32,
+    /// Stop bits.
+    pub stop_bits: StopBits,
+    /// Parity.
+    pub parity: Parity,
+    /// Data bits: 5, 6, 7, 8 or 16.
+    pub data_bits: u8,
+}
+
+impl Default for LineCoding {
+    /// 115200 baud, 8 data bits, no parity, 1 stop bit.
+    fn default() -> Self {
+        Self { baud_rate: 115_200, stop_bits: StopBits::One, parity: Parity::None, data_bits: 8 }
+    }
+}
+
+impl LineCoding {
+    /// Size of the line coding structure.
+    pub const SIZE: usize = 7;
+
+    /// Parses and validates a line coding structure.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() != Self::SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid line coding length"));
+        }
+
+        let stop_bits = match data[4] {
+            0 => StopBits::One,
+            1 => StopBits::OnePointFive,
+            2 => StopBits::Two,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "invalid number of stop bits")),
+        };
+        let parity = match data[5] {
+            0 => Parity::None,
+            1 => Parity::Odd,
+            2 => Parity::Even,
+            3 => Parity::Mark,
+            4 => Parity::Space,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "invalid parity")),
+        };
+        let data_bits = data[6];
+        if !matches!(data_bits, 5..=8 | 16) {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid number of data bits"));
+        }
+
+        Ok(Self { baud_rate: u32::from_le_bytes(data[0..4].try_into().unwrap()), stop_bits, parity, data_bits })
+    }
+
+    /// Serializes the line coding structure.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[0..4].copy_from_slice(&self.baud_rate.to_le_bytes());
+        data[4] = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::OnePointFive => 1,
+            StopBits::Two => 2,
+        };
+        data[5] = match self.parity {
+            Parity::None => 0,
+            Parity::Odd => 1,
+            Parity::Even => 2,
+            Parity::Mark => 3,
+            Parity::Space => 4,
+        };
+        data[6] = self.data_bits;
+        data
+    }
+}
+
+/// Control line state set by the host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ControlLineState {
+    /// Data terminal ready (DTR).
+    pub dtr: bool,
+    /// Request to send (RTS).
+    pub rts: bool,
+}
+
+impl ControlLineState {
+    /// Control line state from the value of a `SET_CONTROL_LINE_STATE` request.
+    pub fn from_value(value: u16) -> Self {
+        Self { dtr: value & 0x01 != 0, rts: value & 0x02 != 0 }
+    }
+}
+
+/// Serial state reported to the host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SerialState {
+    /// Data carrier detect (DCD).
+    pub dcd: bool,
+    /// Data set ready (DSR).
+    pub dsr: bool,
+    /// Break detected.
+    pub brk: bool,
+    /// Ring signal.
+    pub ring: bool,
+    /// Framing error occurred.
+    pub framing: bool,
+    /// Parity error occurred.
+    pub parity: bool,
+    /// Received data has been discarded due to overrun.
+    pub overrun: bool,
+}
+
+impl SerialState {
+    /// State bitmap.
+    pub fn to_bits(&self) -> u16 {
+        [self.dcd, self.dsr, self.brk, self.ring, self.framing, self.parity, self.overrun]
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &set)| bits | (u16::from(set) << i))
+    }
+
+    /// `SERIAL_STATE` notification for the communication interface with the specified number.
+    pub fn to_notification(&self, interface: u16) -> [u8; 10] {
+        let mut data = [0xa1, NOTIFICATION_SERIAL_STATE, 0, 0, 0, 0, 2, 0, 0, 0];
+        data[4..6].copy_from_slice(&interface.to_le_bytes());
+        data[8..10].copy_from_slice(&self.to_bits().to_le_bytes());
+        data
+    }
+}
+
+/// Event of a CDC ACM function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AcmEvent {
+    /// The function was enabled by the host.
+    Enabled,
+    /// The function was disabled by the host.
+    Disabled,
+    /// The function was unbound from the USB device controller.
+    Unbound,
+    /// The host set the line coding.
+    SetLineCoding(LineCoding),
+    /// The host set the control line state.
+    SetControlLineState(ControlLineState),
+    /// The host requested to send a break.
+    SendBreak {
+        /// Duration of the break in milliseconds.
+        ///
+        /// `0xffff` requests a break until a break with duration zero is sent.
+        duration_ms: u16,
+    },
+}
+
+/// Builder for a CDC ACM function.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AcmBuilder {
+    /// Function name.
+    pub name: String,
+    /// Initial line coding.
+    pub line_coding: LineCoding,
+    /// Whether `SEND_BREAK` requests are supported.
+    pub send_break: bool,
+    /// Polling interval of the notification endpoint in milliseconds.
+    pub notify_interval_ms: u16,
+}
+
+impl AcmBuilder {
+    /// Sets the initial line coding.
+    #[must_use]
+    pub fn with_line_coding(mut self, line_coding: LineCoding) -> Self {
+        self.line_coding = line_coding;
+        self
+    }
+
+    /// Builds the custom function builder and the CDC ACM function.
+    ///
+    /// Further interfaces may be added to the returned custom function builder.
+    pub fn build_custom(self) -> (CustomBuilder, AcmPending) {
+        let (ep_notify, ep_notify_dir) = EndpointDirection::device_to_host();
+        let (ep_in, ep_in_dir) = EndpointDirection::device_to_host();
+        let (ep_out, ep_out_dir) = EndpointDirection::host_to_device();
+
+        let capabilities = if self.send_break { 0x06 } else { 0x02 };
+        let assoc = Association::new(COMM_CLASS, &self.name);
+        let builder = Custom::builder()
+            .with_interface(
+                Interface::new(COMM_CLASS, &self.name)
+                    .with_association(&assoc)
+                    .with_custom_desc(CustomDesc::new(CS_INTERFACE, vec![0x00, 0x10, 0x01]))
+                    .with_custom_desc(CustomDesc::new(CS_INTERFACE, vec![0x01, 0x00, 0x01]))
+                    .with_custom_desc(CustomDesc::new(CS_INTERFACE, vec![0x02, capabilities]))
+                    .with_custom_desc(CustomDesc::new(CS_INTERFACE, vec![0x06, 0x00, 0x01]))
+                    .with_endpoint(Endpoint::interrupt_notification(ep_notify_dir, 16, self.notify_interval_ms)),
+            )
+            .with_interface(
+                Interface::new(DATA_CLASS, &self.name)
+                    .with_association(&assoc)
+                    .with_endpoint(Endpoint::bulk(ep_in_dir))
+                    .with_endpoint(Endpoint::bulk(ep_out_dir)),
+            );
+
+        (builder, AcmPending { ep_notify, ep_in, ep_out, line_coding: self.line_coding })
+    }
+
+    /// Builds the CDC ACM function.
+    ///
+    /// The returned handle must be added to a USB gadget configuration.
+    pub fn build(self) -> (Acm, Handle) {
+        let (builder, pending) = self.build_custom();
+        let (custom, handle) = builder.build();
+        (pending.with_custom(custom), handle)
+    }
+}
+
+/// CDC ACM function whose custom function has not been built yet.
+///
+/// Obtained from [`AcmBuilder::build_custom`].
+pub struct AcmPending {
+    ep_notify: EndpointSender,
+    ep_in: EndpointSender,
+    ep_out: EndpointReceiver,
+    line_coding: LineCoding,
+}
+
+impl fmt::Debug for AcmPending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AcmPending").field("line_coding", &self.line_coding).finish()
+    }
+}
+
+impl AcmPending {
+    /// Completes the CDC ACM function using the custom function built from the
+    /// builder returned by [`AcmBuilder::build_custom`].
+    pub fn with_custom(self, custom: Custom) -> Acm {
+        let Self { ep_notify, ep_in, ep_out, line_coding } = self;
+        Acm {
+            custom,
+            ep_notify,
+            ep_in,
+            ep_out,
+            line_coding,
+            control_line_state: ControlLineState::default(),
+            enabled: false,
+        }
+    }
+}
+
+/// CDC ACM function implemented in user space.
+///
+/// Call [`event`](Self::event) regularly to handle requests from the host.
+pub struct Acm {
+    custom: Custom,
+    ep_notify: EndpointSender,
+    ep_in: EndpointSender,
+    ep_out: EndpointReceiver,
+    line_coding: LineCoding,
+    control_line_state: ControlLineState,
+    enabled: bool,
+}
+
+impl fmt::Debug for Acm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Acm")
+            .field("line_coding", &self.line_coding)
+            .field("control_line_state", &self.control_line_state)
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+impl Acm {
+    /// Creates a new CDC ACM function builder.
+    pub fn builder() -> AcmBuilder {
+        AcmBuilder {
+            name: "CDC ACM".to_string(),
+            line_coding: LineCoding::default(),
+            send_break: true,
+            notify_interval_ms: 32,
+        }
+    }
+
+    /// The underlying custom function.
+    pub fn custom(&mut self) -> &mut Custom {
+        &mut self.custom
+    }
+
+    /// Data endpoints for sending data to and receiving data from the host.
+    pub fn data(&mut self) -> (&mut EndpointSender, &mut EndpointReceiver) {
+        (&mut self.ep_in, &mut self.ep_out)
+    }
+
+    /// Current line coding.
+    pub fn line_coding(&self) -> LineCoding {
+        self.line_coding
+    }
+
+    /// Sets the line coding reported to the host.
+    pub fn set_line_coding(&mut self, line_coding: LineCoding) {
+        self.line_coding = line_coding;
+    }
+
+    /// Control line state last set by the host.
+    pub fn control_line_state(&self) -> ControlLineState {
+        self.control_line_state
+    }
+
+    /// Whether the function is enabled by the host.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Sends a `SERIAL_STATE` notification to the host.
+    pub fn notify_serial_state(&mut self, state: SerialState) -> Result<()> {
+        let interface = self.custom.interface_numbers()?.first().copied().unwrap_or_default();
+        self.ep_notify.send_and_flush(Bytes::copy_from_slice(&state.to_notification(interface.into())))
+    }
+
+    /// Waits for the next event with the specified timeout.
+    ///
+    /// Class-specific requests are answered and unsupported requests are stalled.
+    pub fn event(&mut self, timeout: Duration) -> Result<Option<AcmEvent>> {
+        let Some(event) = self.custom.event_timeout(timeout)? else { return Ok(None) };
+
+        let acm_event = match event {
+            Event::Enable => {
+                self.enabled = true;
+                Some(AcmEvent::Enabled)
+            }
+            Event::Disable => {
+                self.enabled = false;
+                Some(AcmEvent::Disabled)
+            }
+            Event::Unbind => {
+                self.enabled = false;
+                Some(AcmEvent::Unbound)
+            }
+            Event::SetupHostToDevice(req) => {
+                let ctrl_req = req.ctrl_req().clone();
+                if ctrl_req.ctrl_type() != CtrlType::Class || ctrl_req.recipient() != CtrlRecipient::Interface {
+                    req.halt()?;
+                    return Ok(None);
+                }
+
+                match ctrl_req.request {
+                    REQUEST_SET_LINE_CODING if req.len() == LineCoding::SIZE => {
+                        // The status stage completes with the data stage, thus invalid
+                        // line codings cannot be stalled and are ignored.
+                        match LineCoding::parse(&req.recv_all()?) {
+                            Ok(line_coding) => {
+                                self.line_coding = line_coding;
+                                Some(AcmEvent::SetLineCoding(line_coding))
+                            }
+                            Err(err) => {
+                                log::warn!("ignoring invalid line coding: {err}");
+                                None
+                            }
+                        }
+                    }
+                    REQUEST_SET_CONTROL_LINE_STATE if req.is_empty() => {
+                        req.recv_all()?;
+                        self.control_line_state = ControlLineState::from_value(ctrl_req.value);
+                        Some(AcmEvent::SetControlLineState(self.control_line_state))
+                    }
+                    REQUEST_SEND_BREAK if req.is_empty() => {
+                        req.recv_all()?;
+                        Some(AcmEvent::SendBreak { duration_ms: ctrl_req.value })
+                    }
+                    _ => {
+                        req.halt()?;
+                        None
+                    }
+                }
+            }
+            Event::SetupDeviceToHost(req) => {
+                let ctrl_req = req.ctrl_req();
+                if ctrl_req.ctrl_type() == CtrlType::Class
+                    && ctrl_req.recipient() == CtrlRecipient::Interface
+                    && ctrl_req.request == REQUEST_GET_LINE_CODING
+                {
+                    let len = req.len().min(LineCoding::SIZE);
+                    req.send(&self.line_coding.to_bytes()[..len])?;
+                } else {
+                    req.halt()?;
+                }
+                None
+            }
+            _ => None,
+        };
+
+        Ok(acm_event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ControlLineState, LineCoding, Parity, SerialState, StopBits};
+
+    #[test]
+    fn line_coding() {
+        let coding = LineCoding { baud_rate: 9600, stop_bits: StopBits::Two, parity: Parity::Even, data_bits: 7 };
+        let data = coding.to_bytes();
+        assert_eq!(data, [0x80, 0x25, 0, 0, 2, 2, 7]);
+        assert_eq!(LineCoding::parse(&data).unwrap(), coding);
+
+        assert!(LineCoding::parse(&[0x80, 0x25, 0, 0, 3, 0, 8]).is_err());
+        assert!(LineCoding::parse(&[0x80, 0x25, 0, 0, 0, 0, 9]).is_err());
+        assert!(LineCoding::parse(&data[..6]).is_err());
+    }
+
+    #[test]
+    fn control_and_serial_state() {
+        assert_eq!(ControlLineState::from_value(3), ControlLineState { dtr: true, rts: true });
+
+        let state = SerialState { dcd: true, dsr: true, overrun: true, ..Default::default() };
+        assert_eq!(state.to_notification(2), [0xa1, 0x20, 0, 0, 2, 0, 2, 0, 0x43, 0]);
+    }
+}
@@ -0,0 +1,838 @@
+//! Device firmware upgrade (DFU) implemented in user space.
+//!
+//! This implements the DFU 1.1 protocol in DFU mode on top of a [custom function](super::Custom),
+//! allowing standard tools such as `dfu-util` to download firmware to the device and,
+//! optionally, upload it from the device.
+//! The kernel does not provide a DFU function.
+//!
+//! Firmware storage is provided by implementing [`DfuHandler`].
+//! Errors returned by the handler are reported to the host as DFU status.
+//! Return an I/O error containing a [`DfuStatus`] to report a specific status.
+//!
+//! Requests from the host are handled by [`Dfu::event`], which reports the progress
+//! of the transfer as [`DfuEvent`]s.
+//!
+//! FunctionFS accepts the DFU functional descriptor since Linux 6.11;
+//! registration fails on earlier kernels.
+
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    time::Duration,
+};
+
+use super::{CtrlRecipient, CtrlType, Custom, CustomBuilder, CustomDesc, Event, Interface};
+use crate::{function::Handle, Class};
+
+/// Interface class of a DFU function in DFU mode.
+pub const CLASS: Class = Class::DFU;
+
+/// Class-specific request: detach.
+pub const REQUEST_DETACH: u8 = 0x00;
+
+/// Class-specific request: download a block.
+pub const REQUEST_DNLOAD: u8 = 0x01;
+
+/// Class-specific request: upload a block.
+pub const REQUEST_UPLOAD: u8 = 0x02;
+
+/// Class-specific request: get status.
+pub const REQUEST_GETSTATUS: u8 = 0x03;
+
+/// Class-specific request: clear error status.
+pub const REQUEST_CLRSTATUS: u8 = 0x04;
+
+/// Class-specific request: get state.
+pub const REQUEST_GETSTATE: u8 = 0x05;
+
+/// Class-specific request: abort the current transfer.
+pub const REQUEST_ABORT: u8 = 0x06;
+
+/// Descriptor type of the DFU functional descriptor.
+const DFU_FUNCTIONAL: u8 = 0x21;
+
+/// DFU specification release 1.1.
+const DFU_VERSION: u16 = 0x0110;
+
+/// State of the DFU function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum DfuState {
+    /// Running the application, waiting for detach.
+    AppIdle = 0,
+    /// Detach requested, waiting for USB reset.
+    AppDetach = 1,
+    /// Waiting for requests.
+    DfuIdle = 2,
+    /// Block received, waiting for status request.
+    DnloadSync = 3,
+    /// Programming a block.
+    DnBusy = 4,
+    /// Waiting for the next block.
+    DnloadIdle = 5,
+    /// End of download received, waiting for status request.
+    ManifestSync = 6,
+    /// Applying the downloaded firmware.
+    Manifest = 7,
+    /// Firmware applied, waiting for USB reset.
+    ManifestWaitReset = 8,
+    /// Upload in progress.
+    UploadIdle = 9,
+    /// An error occurred, waiting for status to be cleared.
+    Error = 10,
+}
+
+/// Status of the DFU function as reported to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum DfuStatus {
+    /// No error.
+    Ok = 0x00,
+    /// File is not targeted for this device.
+    Target = 0x01,
+    /// File fails a vendor-specific verification test.
+    File = 0x02,
+    /// Device is unable to write memory.
+    Write = 0x03,
+    /// Memory erase failed.
+    Erase = 0x04,
+    /// Memory erase check failed.
+    CheckErased = 0x05,
+    /// Program memory function failed.
+    Prog = 0x06,
+    /// Programmed memory failed verification.
+    Verify = 0x07,
+    /// Address out of range.
+    Address = 0x08,
+    /// End of download received, but the firmware is incomplete.
+    NotDone = 0x09,
+    /// Firmware is corrupt.
+    Firmware = 0x0a,
+    /// Vendor-specific error.
+    Vendor = 0x0b,
+    /// Unexpected USB reset.
+    UsbReset = 0x0c,
+    /// Unexpected power-on reset.
+    PowerOnReset = 0x0d,
+    /// Unknown error.
+    Unknown = 0x0e,
+    /// Unexpected request received.
+    StalledPacket = 0x0f,
+}
+
+impl fmt::Display for DfuStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DFU status {self:?} (0x{:02x})", *self as u8)
+    }
+}
+
+impl std::error::Error for DfuStatus {}
+
+impl DfuStatus {
+    /// Status contained in the I/O error or the specified default status.
+    fn from_error(err: &Error, default: Self) -> Self {
+        err.get_ref().and_then(|err| err.downcast_ref::<Self>()).copied().unwrap_or(default)
+    }
+}
+
+/// Response to a `DFU_GETSTATUS` request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DfuStatusResponse {
+    /// Status.
+    pub status: DfuStatus,
+    /// Minimum time the host waits before the next `DFU_GETSTATUS` request in milliseconds.
+    ///
+    /// Only the lower 24 bits are used.
+    pub poll_timeout_ms: u32,
+    /// State the function enters after this response.
+    pub state: DfuState,
+}
+
+impl DfuStatusResponse {
+    /// Size of the status response.
+    pub const SIZE: usize = 6;
+
+    /// Serializes the status response.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let timeout = self.poll_timeout_ms.min(0xff_ffff).to_le_bytes();
+        [self.status as u8, timeout[0], timeout[1], timeout[2], self.state as u8, 0]
+    }
+}
+
+/// Firmware storage of a DFU function.
+pub trait DfuHandler: Send {
+    /// Prepares the storage for a new download, for example by erasing it.
+    ///
+    /// This is called before the first block of a download is written.
+    /// Errors are reported as [`DfuStatus::Erase`] by default.
+    fn begin(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes a block of the firmware at the specified offset.
+    ///
+    /// Errors are reported as [`DfuStatus::Write`] by default.
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()>;
+
+    /// Applies the downloaded firmware after the host has signaled the end of the download.
+    ///
+    /// Errors are reported as [`DfuStatus::Firmware`] by default.
+    fn manifest(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Discards a partially downloaded firmware after the host aborted the download.
+    fn abort(&mut self) {}
+
+    /// Reads firmware from the specified offset for upload to the host.
+    ///
+    /// Returns the number of bytes read; a value smaller than the length of `buf`
+    /// ends the upload.
+    /// Only called if [upload](DfuBuilder::can_upload) is enabled.
+    fn read(&mut self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Err(Error::new(ErrorKind::Unsupported, "upload is not supported"))
+    }
+}
+
+/// Event of a DFU function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DfuEvent {
+    /// The function was enabled by the host.
+    Enabled,
+    /// The function was disabled by the host.
+    Disabled,
+    /// The function was unbound from the USB device controller.
+    Unbound,
+    /// The host requested a detach.
+    ///
+    /// This is only valid in run-time mode; in DFU mode the request is stalled.
+    Detach {
+        /// Timeout in milliseconds within which the host will issue a USB reset.
+        timeout_ms: u16,
+    },
+    /// A download has started.
+    DownloadStarted,
+    /// A block has been downloaded and written.
+    Downloaded {
+        /// Offset of the block within the firmware.
+        offset: u64,
+        /// Length of the block.
+        len: usize,
+    },
+    /// The download has completed and the firmware has been manifested.
+    Manifested {
+        /// Total size of the downloaded firmware.
+        size: u64,
+    },
+    /// A block has been uploaded.
+    Uploaded {
+        /// Offset of the block within the firmware.
+        offset: u64,
+        /// Length of the block.
+        len: usize,
+    },
+    /// The host aborted the current transfer.
+    Aborted,
+    /// An error occurred.
+    Error(DfuStatus),
+}
+
+/// Builder for a DFU function.
+#[non_exhaustive]
+pub struct DfuBuilder {
+    /// Interface name.
+    pub name: String,
+    /// Maximum number of bytes the host transfers per control request.
+    pub transfer_size: u16,
+    /// Whether the host may upload the firmware from the device.
+    pub can_upload: bool,
+    /// Whether the function remains usable after manifestation without a USB reset.
+    pub manifestation_tolerant: bool,
+    /// Time the host waits for a USB reset after a detach request in milliseconds.
+    pub detach_timeout_ms: u16,
+    /// Poll timeout reported to the host in status responses in milliseconds.
+    pub poll_timeout_ms: u32,
+    /// Firmware storage.
+    pub handler: Box<dyn DfuHandler>,
+}
+
+impl fmt::Debug for DfuBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DfuBuilder")
+            .field("name", &self.name)
+            .field("transfer_size", &self.transfer_size)
+            .field("can_upload", &self.can_upload)
+            .field("manifestation_tolerant", &self.manifestation_tolerant)
+            .field("detach_timeout_ms", &self.detach_timeout_ms)
+            .field("poll_timeout_ms", &self.poll_timeout_ms)
+            .finish()
+    }
+}
+
+impl DfuBuilder {
+    /// Sets the maximum number of bytes the host transfers per control request.
+    #[must_use]
+    pub fn with_transfer_size(mut self, transfer_size: u16) -> Self {
+        self.transfer_size = transfer_size;
+        self
+    }
+
+    /// Sets whether the host may upload the firmware from the device.
+    #[must_use]
+    pub fn with_upload(mut self, can_upload: bool) -> Self {
+        self.can_upload = can_upload;
+        self
+    }
+
+    /// Sets whether the function remains usable after manifestation without a USB reset.
+    #[must_use]
+    pub fn with_manifestation_tolerant(mut self, manifestation_tolerant: bool) -> Self {
+        self.manifestation_tolerant = manifestation_tolerant;
+        self
+    }
+
+    /// DFU functional descriptor data, excluding length and descriptor type.
+    fn functional_desc(&self) -> Vec<u8> {
+        let mut attributes = 0x01;
+        if self.can_upload {
+            attributes |= 0x02;
+        }
+        if self.manifestation_tolerant {
+            attributes |= 0x04;
+        }
+
+        let mut data = vec![attributes];
+        data.extend(self.detach_timeout_ms.to_le_bytes());
+        data.extend(self.transfer_size.to_le_bytes());
+        data.extend(DFU_VERSION.to_le_bytes());
+        data
+    }
+
+    /// Builds the custom function builder and the DFU function.
+    ///
+    /// Further interfaces may be added to the returned custom function builder.
+    pub fn build_custom(self) -> Result<(CustomBuilder, DfuPending)> {
+        if self.transfer_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "transfer size must not be zero"));
+        }
+
+        let builder = Custom::builder().with_interface(
+            Interface::new(CLASS, &self.name)
+                .with_custom_desc(CustomDesc::new(DFU_FUNCTIONAL, self.functional_desc())),
+        );
+
+        let machine = Machine {
+            handler: self.handler,
+            transfer_size: self.transfer_size,
+            can_upload: self.can_upload,
+            manifestation_tolerant: self.manifestation_tolerant,
+            poll_timeout_ms: self.poll_timeout_ms,
+            state: DfuState::DfuIdle,
+            status: DfuStatus::Ok,
+            offset: 0,
+        };
+        Ok((builder, DfuPending { machine }))
+    }
+
+    /// Builds the DFU function.
+    ///
+    /// The returned handle must be added to a USB gadget configuration.
+    pub fn build(self) -> Result<(Dfu, Handle)> {
+        let (builder, pending) = self.build_custom()?;
+        let (custom, handle) = builder.build();
+        Ok((pending.with_custom(custom), handle))
+    }
+}
+
+/// DFU function whose custom function has not been built yet.
+///
+/// Obtained from [`DfuBuilder::build_custom`].
+pub struct DfuPending {
+    machine: Machine,
+}
+
+impl fmt::Debug for DfuPending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DfuPending").finish()
+    }
+}
+
+impl DfuPending {
+    /// Completes the DFU function using the custom function built from the
+    /// builder returned by [`DfuBuilder::build_custom`].
+    pub fn with_custom(self, custom: Custom) -> Dfu {
+        Dfu { custom, machine: self.machine, enabled: false }
+    }
+}
+
+/// DFU protocol state machine.
+struct Machine {
+    handler: Box<dyn DfuHandler>,
+    transfer_size: u16,
+    can_upload: bool,
+    manifestation_tolerant: bool,
+    poll_timeout_ms: u32,
+    state: DfuState,
+    status: DfuStatus,
+    /// Offset of the next block to download or upload.
+    offset: u64,
+}
+
+impl Machine {
+    /// Enters the error state with the specified status.
+    fn fail(&mut self, status: DfuStatus) -> DfuEvent {
+        log::warn!("DFU error: {status}");
+        self.state = DfuState::Error;
+        self.status = status;
+        DfuEvent::Error(status)
+    }
+
+    /// Handles a USB reset or disabling of the function.
+    fn reset(&mut self) {
+        match self.state {
+            DfuState::DfuIdle | DfuState::ManifestWaitReset => (),
+            DfuState::Error => return,
+            _ => {
+                self.handler.abort();
+                self.status = DfuStatus::UsbReset;
+                self.state = DfuState::Error;
+                return;
+            }
+        }
+        self.state = DfuState::DfuIdle;
+        self.offset = 0;
+    }
+
+    /// Handles a `DFU_DNLOAD` request with the received data.
+    fn dnload(&mut self, data: &[u8]) -> Vec<DfuEvent> {
+        let mut events = Vec::new();
+
+        match self.state {
+            DfuState::DfuIdle if !data.is_empty() => {
+                self.offset = 0;
+                if let Err(err) = self.handler.begin() {
+                    log::warn!("beginning DFU download failed: {err}");
+                    events.push(self.fail(DfuStatus::from_error(&err, DfuStatus::Erase)));
+                    return events;
+                }
+                events.push(DfuEvent::DownloadStarted);
+            }
+            DfuState::DnloadIdle => (),
+            _ => {
+                events.push(self.fail(DfuStatus::StalledPacket));
+                return events;
+            }
+        }
+
+        if data.is_empty() {
+            self.state = DfuState::ManifestSync;
+            return events;
+        }
+
+        match self.handler.write(self.offset, data) {
+            Ok(()) => {
+                events.push(DfuEvent::Downloaded { offset: self.offset, len: data.len() });
+                self.offset += data.len() as u64;
+                self.state = DfuState::DnloadSync;
+            }
+            Err(err) => {
+                log::warn!("writing DFU block at offset {} failed: {err}", self.offset);
+                events.push(self.fail(DfuStatus::from_error(&err, DfuStatus::Write)));
+            }
+        }
+
+        events
+    }
+
+    /// Handles a `DFU_GETSTATUS` request.
+    fn get_status(&mut self) -> (DfuStatusResponse, Option<DfuEvent>) {
+        let mut event = None;
+        let state = match self.state {
+            DfuState::DnloadSync => {
+                self.state = DfuState::DnloadIdle;
+                DfuState::DnloadIdle
+            }
+            DfuState::ManifestSync => match self.handler.manifest() {
+                Ok(()) => {
+                    event = Some(DfuEvent::Manifested { size: self.offset });
+                    if self.manifestation_tolerant {
+                        self.state = DfuState::DfuIdle;
+                        self.offset = 0;
+                        DfuState::DfuIdle
+                    } else {
+                        self.state = DfuState::ManifestWaitReset;
+                        DfuState::Manifest
+                    }
+                }
+                Err(err) => {
+                    log::warn!("manifesting DFU firmware failed: {err}");
+                    event = Some(self.fail(DfuStatus::from_error(&err, DfuStatus::Firmware)));
+                    DfuState::Error
+                }
+            },
+            state => state,
+        };
+
+        (DfuStatusResponse { status: self.status, poll_timeout_ms: self.poll_timeout_ms, state }, event)
+    }
+
+    /// Handles a `DFU_UPLOAD` request for at most `len` bytes.
+    ///
+    /// Returns `None` if the request must be stalled.
+    fn upload(&mut self, len: usize) -> (Option<Vec<u8>>, Option<DfuEvent>) {
+        match self.state {
+            DfuState::DfuIdle if self.can_upload => self.offset = 0,
+            DfuState::UploadIdle => (),
+            _ => return (None, Some(self.fail(DfuStatus::StalledPacket))),
+        }
+
+        // A transfer shorter than the clamped request length ends the upload.
+        let len = len.min(self.transfer_size.into());
+        let mut buf = vec![0; len];
+        match self.handler.read(self.offset, &mut buf) {
+            Ok(n) => {
+                let n = n.min(len);
+                buf.truncate(n);
+                let event = DfuEvent::Uploaded { offset: self.offset, len: n };
+                if n < len {
+                    self.state = DfuState::DfuIdle;
+                    self.offset = 0;
+                } else {
+                    self.state = DfuState::UploadIdle;
+                    self.offset += n as u64;
+                }
+                (Some(buf), Some(event))
+            }
+            Err(err) => {
+                log::warn!("reading DFU firmware at offset {} failed: {err}", self.offset);
+                (None, Some(self.fail(DfuStatus::from_error(&err, DfuStatus::Unknown))))
+            }
+        }
+    }
+
+    /// Handles a `DFU_DETACH` request.
+    ///
+    /// Returns `None` if the request must be stalled, which is the case in all DFU mode states.
+    fn detach(&mut self, timeout_ms: u16) -> Option<DfuEvent> {
+        match self.state {
+            DfuState::AppIdle => {
+                self.state = DfuState::AppDetach;
+                Some(DfuEvent::Detach { timeout_ms })
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles a `DFU_CLRSTATUS` request.
+    fn clear_status(&mut self) -> bool {
+        if self.state != DfuState::Error {
+            self.fail(DfuStatus::StalledPacket);
+            return false;
+        }
+        self.state = DfuState::DfuIdle;
+        self.status = DfuStatus::Ok;
+        self.offset = 0;
+        true
+    }
+
+    /// Handles a `DFU_ABORT` request.
+    fn abort(&mut self) -> Option<DfuEvent> {
+        match self.state {
+            DfuState::DfuIdle => None,
+            DfuState::DnloadSync | DfuState::DnloadIdle | DfuState::ManifestSync | DfuState::UploadIdle => {
+                if self.state != DfuState::UploadIdle {
+                    self.handler.abort();
+                }
+                self.state = DfuState::DfuIdle;
+                self.offset = 0;
+                Some(DfuEvent::Aborted)
+            }
+            _ => Some(self.fail(DfuStatus::StalledPacket)),
+        }
+    }
+}
+
+/// DFU function implemented in user space.
+///
+/// Call [`event`](Self::event) regularly or [`run`](Self::run) to handle requests from the host.
+pub struct Dfu {
+    custom: Custom,
+    machine: Machine,
+    enabled: bool,
+}
+
+impl fmt::Debug for Dfu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dfu")
+            .field("state", &self.machine.state)
+            .field("status", &self.machine.status)
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+impl Dfu {
+    /// Creates a new DFU function builder using the specified firmware storage.
+    pub fn builder(handler: impl DfuHandler + 'static) -> DfuBuilder {
+        DfuBuilder {
+            name: "DFU".to_string(),
+            transfer_size: 4096,
+            can_upload: false,
+            manifestation_tolerant: true,
+            detach_timeout_ms: 1000,
+            poll_timeout_ms: 0,
+            handler: Box::new(handler),
+        }
+    }
+
+    /// The underlying custom function.
+    pub fn custom(&mut self) -> &mut Custom {
+        &mut self.custom
+    }
+
+    /// Firmware storage.
+    pub fn handler(&mut self) -> &mut dyn DfuHandler {
+        self.machine.handler.as_mut()
+    }
+
+    /// Current DFU state.
+    pub fn state(&self) -> DfuState {
+        self.machine.state
+    }
+
+    /// Current DFU status.
+    pub fn status(&self) -> DfuStatus {
+        self.machine.status
+    }
+
+    /// Whether the function is enabled by the host.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Waits for the next event with the specified timeout.
+    ///
+    /// DFU requests are answered and unsupported requests are stalled.
+    /// Since a request may produce multiple events, the returned vector may contain
+    /// more than one entry.
+    pub fn event(&mut self, timeout: Duration) -> Result<Vec<DfuEvent>> {
+        let Some(event) = self.custom.event_timeout(timeout)? else { return Ok(Vec::new()) };
+        let machine = &mut self.machine;
+
+        let events = match event {
+            Event::Enable => {
+                self.enabled = true;
+                machine.reset();
+                vec![DfuEvent::Enabled]
+            }
+            Event::Disable => {
+                self.enabled = false;
+                machine.reset();
+                vec![DfuEvent::Disabled]
+            }
+            Event::Unbind => {
+                self.enabled = false;
+                machine.reset();
+                vec![DfuEvent::Unbound]
+            }
+            Event::SetupHostToDevice(req) => {
+                let ctrl_req = req.ctrl_req().clone();
+                if ctrl_req.ctrl_type() != CtrlType::Class || ctrl_req.recipient() != CtrlRecipient::Interface {
+                    req.halt()?;
+                    return Ok(Vec::new());
+                }
+
+                match ctrl_req.request {
+                    REQUEST_DNLOAD if req.len() <= machine.transfer_size.into() => {
+                        let data = req.recv_all()?;
+                        machine.dnload(&data)
+                    }
+                    REQUEST_CLRSTATUS if req.is_empty() => {
+                        if machine.clear_status() {
+                            req.recv_all()?;
+                        } else {
+                            req.halt()?;
+                        }
+                        Vec::new()
+                    }
+                    REQUEST_ABORT if req.is_empty() => {
+                        let event = machine.abort();
+                        if machine.state == DfuState::Error {
+                            req.halt()?;
+                        } else {
+                            req.recv_all()?;
+                        }
+                        event.into_iter().collect()
+                    }
+                    REQUEST_DETACH if req.is_empty() => match machine.detach(ctrl_req.value) {
+                        Some(event) => {
+                            req.recv_all()?;
+                            vec![event]
+                        }
+                        None => {
+                            req.halt()?;
+                            vec![machine.fail(DfuStatus::StalledPacket)]
+                        }
+                    },
+                    _ => {
+                        req.halt()?;
+                        vec![machine.fail(DfuStatus::StalledPacket)]
+                    }
+                }
+            }
+            Event::SetupDeviceToHost(req) => {
+                let ctrl_req = req.ctrl_req().clone();
+                if ctrl_req.ctrl_type() != CtrlType::Class || ctrl_req.recipient() != CtrlRecipient::Interface {
+                    req.halt()?;
+                    return Ok(Vec::new());
+                }
+
+                match ctrl_req.request {
+                    REQUEST_GETSTATUS => {
+                        let (response, event) = machine.get_status();
+                        let len = req.len().min(DfuStatusResponse::SIZE);
+                        req.send(&response.to_bytes()[..len])?;
+                        event.into_iter().collect()
+                    }
+                    REQUEST_GETSTATE => {
+                        let len = req.len().min(1);
+                        req.send(&[machine.state as u8][..len])?;
+                        Vec::new()
+                    }
+                    REQUEST_UPLOAD => {
+                        let (data, event) = machine.upload(req.len());
+                        match data {
+                            Some(data) => {
+                                req.send(&data)?;
+                            }
+                            None => req.halt()?,
+                        }
+                        event.into_iter().collect()
+                    }
+                    _ => {
+                        req.halt()?;
+                        vec![machine.fail(DfuStatus::StalledPacket)]
+                    }
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(events)
+    }
+
+    /// Handles requests until the function is unbound from the USB device controller.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            for event in self.event(Duration::from_millis(100))? {
+                log::debug!("DFU event: {event:?}");
+                if event == DfuEvent::Unbound {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Error, ErrorKind, Result};
+
+    use super::{Dfu, DfuEvent, DfuHandler, DfuState, DfuStatus, DfuStatusResponse};
+
+    #[derive(Default)]
+    struct Memory {
+        data: Vec<u8>,
+        manifested: bool,
+    }
+
+    impl DfuHandler for Memory {
+        fn begin(&mut self) -> Result<()> {
+            self.data.clear();
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+            if data == b"bad" {
+                return Err(Error::new(ErrorKind::Other, DfuStatus::Verify));
+            }
+            assert_eq!(offset, self.data.len() as u64);
+            self.data.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn manifest(&mut self) -> Result<()> {
+            self.manifested = true;
+            Ok(())
+        }
+
+        fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+            let data = self.data.get(offset as usize..).unwrap_or_default();
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn status_response() {
+        let response = DfuStatusResponse {
+            status: DfuStatus::Ok,
+            poll_timeout_ms: 0x0102_0304,
+            state: DfuState::DnloadIdle,
+        };
+        assert_eq!(response.to_bytes(), [0, 0xff, 0xff, 0xff, 5, 0]);
+    }
+
+    #[test]
+    fn download_and_upload() {
+        let (_builder, pending) =
+            Dfu::builder(Memory::default()).with_transfer_size(4).with_upload(true).build_custom().unwrap();
+        let mut machine = pending.machine;
+
+        assert_eq!(
+            machine.dnload(b"abcd"),
+            [DfuEvent::DownloadStarted, DfuEvent::Downloaded { offset: 0, len: 4 }]
+        );
+        assert_eq!(machine.get_status().0.state, DfuState::DnloadIdle);
+        assert_eq!(machine.dnload(b"ef"), [DfuEvent::Downloaded { offset: 4, len: 2 }]);
+        assert_eq!(machine.get_status().0.state, DfuState::DnloadIdle);
+        assert!(machine.dnload(b"").is_empty());
+        assert_eq!(machine.state, DfuState::ManifestSync);
+        let (response, event) = machine.get_status();
+        assert_eq!(response.state, DfuState::DfuIdle);
+        assert_eq!(event, Some(DfuEvent::Manifested { size: 6 }));
+
+        // Requests longer than the transfer size are clamped and do not end the upload.
+        assert_eq!(machine.upload(8).0.unwrap(), b"abcd");
+        assert_eq!(machine.state, DfuState::UploadIdle);
+        assert_eq!(machine.upload(8).0.unwrap(), b"ef");
+        assert_eq!(machine.state, DfuState::DfuIdle);
+    }
+
+    #[test]
+    fn errors() {
+        let (_builder, pending) = Dfu::builder(Memory::default()).build_custom().unwrap();
+        let mut machine = pending.machine;
+
+        assert_eq!(machine.dnload(b""), [DfuEvent::Error(DfuStatus::StalledPacket)]);
+        assert!(machine.clear_status());
+
+        assert_eq!(machine.dnload(b"bad")[1], DfuEvent::Error(DfuStatus::Verify));
+        let (response, _) = machine.get_status();
+        assert_eq!((response.status, response.state), (DfuStatus::Verify, DfuState::Error));
+        assert!(machine.clear_status());
+        assert_eq!(machine.state, DfuState::DfuIdle);
+
+        assert_eq!(machine.upload(4).1, Some(DfuEvent::Error(DfuStatus::StalledPacket)));
+        assert!(machine.clear_status());
+
+        assert_eq!(machine.detach(1000), None);
+
+        machine.dnload(b"ab");
+        machine.get_status();
+        assert_eq!(machine.abort(), Some(DfuEvent::Aborted));
+        assert_eq!(machine.state, DfuState::DfuIdle);
+    }
+}
@@ -0,0 +1,130 @@
+//! FunctionFS bindings.
+
+use nix::{ioctl_none, ioctl_read, ioctl_write_int_bad, request_code_none};
+use std::{
+    ffi::OsStr,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::{linux_version, system_ops, Speed};
+
+mod wire;
+pub use wire::*;
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err {
+            Error::StringsDifferAcrossLanguages | Error::Overflow => ErrorKind::InvalidInput,
+            Error::Truncated | Error::Invalid(_) => ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl Descs {
+    /// Parses FunctionFS descriptors in the version 2 format.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        Ok(Self::read(data)?)
+    }
+
+    /// Serializes the descriptors used at the specified speed as sent to the USB host.
+    pub fn host_bytes(&self, speed: Speed) -> io::Result<Vec<u8>> {
+        Ok(self.write_host(speed)?)
+    }
+
+    /// Serializes the descriptors in the version 2 format.
+    ///
+    /// Microsoft OS descriptors are tagged with the specified BCD version, which must be
+    /// [`OS_DESC_VERSION_1_00`] for Linux 6.4 and later and [`OS_DESC_VERSION_LEGACY`] for earlier versions.
+    pub fn encode(&self, os_desc_version: u16) -> io::Result<Vec<u8>> {
+        Ok(self.write(os_desc_version)?)
+    }
+
+    /// Serializes the descriptors in the version 2 format for the running kernel.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let os_desc_version = if linux_version().unwrap_or_default() >= (6, 4) {
+            OS_DESC_VERSION_1_00
+        } else {
+            OS_DESC_VERSION_LEGACY
+        };
+        self.encode(os_desc_version)
+    }
+}
+
+impl EndpointDesc {
+    /// Parse from raw descriptor data.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        Ok(Self::read(data)?)
+    }
+}
+
+impl Strings {
+    /// Parses FunctionFS strings.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        Ok(Self::read(data)?)
+    }
+
+    /// Serializes the strings.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        Ok(self.write()?)
+    }
+}
+
+pub const FS_TYPE: &str = "functionfs";
+
+/// FunctionFS mount options.
+#[derive(Debug, Clone, Default)]
+pub struct MountOptions {
+    pub no_disconnect: bool,
+    pub rmode: Option<u32>,
+    pub fmode: Option<u32>,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl MountOptions {
+    fn to_mount_data(&self) -> String {
+        let mut opts = Vec::new();
+
+        if self.no_disconnect {
+            opts.push("no_disconnect=1".to_string());
+        }
+        if let Some(v) = self.rmode {
+            opts.push(format!("rmode={v}"));
+        }
+        if let Some(v) = self.fmode {
+            opts.push(format!("fmode={v}"));
+        }
+        if let Some(v) = self.mode {
+            opts.push(format!("mode={v}"));
+        }
+        if let Some(v) = self.uid {
+            opts.push(format!("uid={v}"));
+        }
+        if let Some(v) = self.gid {
+            opts.push(format!("gid={v}"));
+        }
+
+        opts.join(",")
+    }
+}
+
+pub fn mount(instance: &OsStr, target: &Path, opts: &MountOptions) -> std::io::Result<()> {
+    crate::dry_run::mount(instance, target, FS_TYPE, &opts.to_mount_data())
+}
+
+pub fn umount(target: &Path, lazy: bool) -> std::io::Result<()> {
+    system_ops().umount(target, lazy)
+}
+
+ioctl_none!(fifo_status, 'g', 1);
+ioctl_none!(fifo_flush, 'g', 2);
+ioctl_none!(clear_halt, 'g', 3);
+
+ioctl_write_int_bad!(interface_revmap, request_code_none!('g', 128));
+ioctl_none!(endpoint_revmap, 'g', 129);
+ioctl_read!(endpoint_desc, 'g', 130, [u8; EndpointDesc::AUDIO_SIZE]);
@@ -0,0 +1,977 @@
+//! FunctionFS wire format.
+//!
+//! Encoding and decoding of the descriptors, strings and events exchanged with
+//! FunctionFS through `ep0`.
+//! This is pure data transformation without any system calls, thus it can be
+//! validated independently and used to inspect FunctionFS blobs on other systems.
+//! Only `core` and `alloc` are used, so that it can also be built for `no_std` targets;
+//! conversions to [`std::io::Error`] live in the parent module.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use bitflags::bitflags;
+use core::{fmt, num::TryFromIntError};
+
+use crate::{Language, Speed};
+
+/// FunctionFS wire format error.
+#[derive(Debug, Clone)]
+pub enum Error {
+    StringsDifferAcrossLanguages,
+    Overflow,
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::StringsDifferAcrossLanguages => write!(f, "string count differs across languages"),
+            Error::Overflow => write!(f, "too many descriptor entries"),
+            Error::Truncated => write!(f, "data is truncated"),
+            Error::Invalid(msg) => write!(f, "invalid data: {msg}"),
+        }
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(_: TryFromIntError) -> Self {
+        Self::Overflow
+    }
+}
+
+pub(crate) type Result<T> = core::result::Result<T, Error>;
+
+/// Splits `len` bytes from the front of `data`.
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(Error::Truncated);
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+/// Reading of little-endian values from the front of a byte slice.
+trait ReadLe {
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]>;
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+}
+
+impl ReadLe for &[u8] {
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(take(self, N)?.try_into().unwrap())
+    }
+}
+
+/// Appending of little-endian values to a byte vector.
+trait WriteLe {
+    fn write_u8(&mut self, value: u8);
+    fn write_u16(&mut self, value: u16);
+    fn write_u32(&mut self, value: u32);
+    fn write_i32(&mut self, value: i32);
+}
+
+impl WriteLe for Vec<u8> {
+    fn write_u8(&mut self, value: u8) {
+        self.push(value);
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// USB direction to device.
+pub const DIR_OUT: u8 = 0x00;
+/// USB direction to host.
+pub const DIR_IN: u8 = 0x80;
+
+/// BCD version of Microsoft OS descriptors expected by Linux 6.4 and later.
+pub const OS_DESC_VERSION_1_00: u16 = 0x0100;
+
+/// BCD version of Microsoft OS descriptors expected before Linux 6.4.
+pub const OS_DESC_VERSION_LEGACY: u16 = 0x0001;
+
+bitflags! {
+    /// FunctionFS descriptor flags.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Flags: u32 {
+        /// Full speed descriptors are present.
+        const HAS_FS_DESC = 1;
+        /// High speed descriptors are present.
+        const HAS_HS_DESC = 2;
+        /// Super speed descriptors are present.
+        const HAS_SS_DESC = 4;
+        /// Microsoft OS descriptors are present.
+        const HAS_MS_OS_DESC = 8;
+        /// Endpoint numbers are virtual.
+        const VIRTUAL_ADDR = 16;
+        /// Eventfd is present.
+        const EVENTFD = 32;
+        /// All control requests are forwarded.
+        const ALL_CTRL_RECIP = 64;
+        /// Control requests in configuration 0 are forwarded.
+        const CONFIG0_SETUP = 128;
+    }
+}
+
+/// FunctionFS descriptors, as written to `ep0`.
+#[derive(Clone, Debug)]
+pub struct Descs {
+    /// Flags.
+    pub flags: Flags,
+    /// Eventfd for notifications.
+    pub eventfd: Option<i32>,
+    /// Full speed descriptors.
+    pub fs_descrs: Vec<Desc>,
+    /// High speed descriptors.
+    pub hs_descrs: Vec<Desc>,
+    /// Super speed descriptors.
+    pub ss_descrs: Vec<Desc>,
+    /// Microsoft OS descriptors.
+    pub os_descrs: Vec<OsDesc>,
+}
+
+impl Descs {
+    const MAGIC_V2: u32 = 3;
+
+    /// Decodes FunctionFS descriptors in the version 2 format.
+    pub(crate) fn read(mut data: &[u8]) -> Result<Self> {
+        if data.read_u32()? != Self::MAGIC_V2 {
+            return Err(Error::Invalid("unsupported descriptors magic"));
+        }
+
+        let len: usize = data.read_u32()?.try_into()?;
+        data = take(&mut data, len.checked_sub(8).ok_or(Error::Invalid("descriptors length"))?)?;
+
+        let flags = Flags::from_bits(data.read_u32()?).ok_or(Error::Invalid("unknown descriptors flags"))?;
+        let eventfd = if flags.contains(Flags::EVENTFD) { Some(data.read_i32()?) } else { None };
+
+        let mut counts = [0; 4];
+        for (count, flag) in counts.iter_mut().zip([
+            Flags::HAS_FS_DESC,
+            Flags::HAS_HS_DESC,
+            Flags::HAS_SS_DESC,
+            Flags::HAS_MS_OS_DESC,
+        ]) {
+            if flags.contains(flag) {
+                *count = data.read_u32()?;
+            }
+        }
+        let [fs_count, hs_count, ss_count, os_count] = counts;
+
+        let fs_descrs = (0..fs_count).map(|_| Desc::parse(&mut data)).collect::<Result<_>>()?;
+        let hs_descrs = (0..hs_count).map(|_| Desc::parse(&mut data)).collect::<Result<_>>()?;
+        let ss_descrs = (0..ss_count).map(|_| Desc::parse(&mut data)).collect::<Result<_>>()?;
+        let os_descrs = (0..os_count).map(|_| OsDesc::parse(&mut data)).collect::<Result<_>>()?;
+
+        if !data.is_empty() {
+            return Err(Error::Invalid("trailing data after descriptors"));
+        }
+
+        Ok(Self { flags, eventfd, fs_descrs, hs_descrs, ss_descrs, os_descrs })
+    }
+
+    /// Descriptors used at the specified speed.
+    pub fn for_speed(&self, speed: Speed) -> &[Desc] {
+        match speed {
+            Speed::SuperSpeedPlus | Speed::SuperSpeed => &self.ss_descrs,
+            Speed::HighSpeed => &self.hs_descrs,
+            _ => &self.fs_descrs,
+        }
+    }
+
+    /// Encodes the descriptors used at the specified speed as sent to the USB host.
+    pub(crate) fn write_host(&self, speed: Speed) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for desc in self.for_speed(speed) {
+            data.extend(desc.to_bytes()?);
+        }
+        Ok(data)
+    }
+
+    /// Encodes the descriptors in the version 2 format.
+    pub(crate) fn write(&self, os_desc_version: u16) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        data.write_u32(Self::MAGIC_V2);
+        data.write_u32(0); // length
+
+        let mut flags = self.flags;
+        flags.insert(Flags::HAS_FS_DESC | Flags::HAS_HS_DESC | Flags::HAS_SS_DESC | Flags::HAS_MS_OS_DESC);
+        flags.set(Flags::EVENTFD, self.eventfd.is_some());
+        data.write_u32(flags.bits());
+
+        if let Some(fd) = self.eventfd {
+            data.write_i32(fd);
+        }
+
+        data.write_u32(self.fs_descrs.len().try_into()?);
+        data.write_u32(self.hs_descrs.len().try_into()?);
+        data.write_u32(self.ss_descrs.len().try_into()?);
+        data.write_u32(self.os_descrs.len().try_into()?);
+
+        for fs_descr in &self.fs_descrs {
+            data.extend(fs_descr.to_bytes()?);
+        }
+        for hs_descr in &self.hs_descrs {
+            data.extend(hs_descr.to_bytes()?);
+        }
+        for ss_descr in &self.ss_descrs {
+            data.extend(ss_descr.to_bytes()?);
+        }
+        for os_descr in &self.os_descrs {
+            data.extend(os_descr.to_bytes(os_desc_version)?);
+        }
+
+        let len: u32 = data.len().try_into()?;
+        data[4..8].copy_from_slice(&len.to_le_bytes());
+
+        Ok(data)
+    }
+}
+
+/// USB descriptor.
+#[derive(Clone, Debug)]
+pub enum Desc {
+    /// Interface descriptor.
+    Interface(InterfaceDesc),
+    /// Endpoint descriptor.
+    Endpoint(EndpointDesc),
+    /// SuperSpeed endpoint companion descriptor.
+    SsEndpointComp(SsEndpointComp),
+    /// Interface association descriptor.
+    InterfaceAssoc(InterfaceAssocDesc),
+    /// Other descriptor.
+    Custom(CustomDesc),
+}
+
+impl From<InterfaceDesc> for Desc {
+    fn from(value: InterfaceDesc) -> Self {
+        Self::Interface(value)
+    }
+}
+
+impl From<EndpointDesc> for Desc {
+    fn from(value: EndpointDesc) -> Self {
+        Self::Endpoint(value)
+    }
+}
+
+impl From<SsEndpointComp> for Desc {
+    fn from(value: SsEndpointComp) -> Self {
+        Self::SsEndpointComp(value)
+    }
+}
+
+impl From<InterfaceAssocDesc> for Desc {
+    fn from(value: InterfaceAssocDesc) -> Self {
+        Self::InterfaceAssoc(value)
+    }
+}
+
+impl From<CustomDesc> for Desc {
+    fn from(value: CustomDesc) -> Self {
+        Self::Custom(value)
+    }
+}
+
+impl Desc {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        data.write_u8(0);
+
+        match self {
+            Self::Interface(d) => d.write(&mut data)?,
+            Self::Endpoint(d) => d.write(&mut data)?,
+            Self::SsEndpointComp(d) => d.write(&mut data)?,
+            Self::InterfaceAssoc(d) => d.write(&mut data)?,
+            Self::Custom(d) => d.write(&mut data)?,
+        }
+
+        data[0] = data.len().try_into()?;
+        Ok(data)
+    }
+
+    fn parse(data: &mut &[u8]) -> Result<Self> {
+        let len = data.first().copied().ok_or(Error::Truncated)?;
+        if len < 2 {
+            return Err(Error::Invalid("descriptor length"));
+        }
+        let raw = take(data, len.into())?;
+        let mut body = &raw[2..];
+
+        let desc = match raw[1] {
+            InterfaceDesc::TYPE if raw.len() == InterfaceDesc::SIZE => Self::Interface(InterfaceDesc {
+                interface_number: body.read_u8()?,
+                alternate_setting: body.read_u8()?,
+                num_endpoints: body.read_u8()?,
+                interface_class: body.read_u8()?,
+                interface_sub_class: body.read_u8()?,
+                interface_protocol: body.read_u8()?,
+                name_idx: body.read_u8()?,
+            }),
+            EndpointDesc::TYPE => Self::Endpoint(EndpointDesc::read(raw)?),
+            SsEndpointComp::TYPE if raw.len() == SsEndpointComp::SIZE => Self::SsEndpointComp(SsEndpointComp {
+                max_burst: body.read_u8()?,
+                attributes: body.read_u8()?,
+                bytes_per_interval: body.read_u16()?,
+            }),
+            InterfaceAssocDesc::TYPE if raw.len() == InterfaceAssocDesc::SIZE => {
+                Self::InterfaceAssoc(InterfaceAssocDesc {
+                    first_interface: body.read_u8()?,
+                    interface_count: body.read_u8()?,
+                    function_class: body.read_u8()?,
+                    function_sub_class: body.read_u8()?,
+                    function_protocol: body.read_u8()?,
+                    name_idx: body.read_u8()?,
+                })
+            }
+            InterfaceDesc::TYPE | SsEndpointComp::TYPE | InterfaceAssocDesc::TYPE => {
+                return Err(Error::Invalid("descriptor length"))
+            }
+            descriptor_type => Self::Custom(CustomDesc::new(descriptor_type, body.to_vec())),
+        };
+
+        Ok(desc)
+    }
+}
+
+/// USB interface descriptor.
+#[derive(Clone, Debug)]
+pub struct InterfaceDesc {
+    /// Interface number.
+    pub interface_number: u8,
+    /// Alternate setting.
+    pub alternate_setting: u8,
+    /// Number of endpoints.
+    pub num_endpoints: u8,
+    /// Interface class.
+    pub interface_class: u8,
+    /// Interface sub class.
+    pub interface_sub_class: u8,
+    /// Interface protocol.
+    pub interface_protocol: u8,
+    /// Index of interface name string.
+    pub name_idx: u8,
+}
+
+impl InterfaceDesc {
+    /// Interface descriptor type.
+    pub const TYPE: u8 = 0x04;
+
+    /// Size.
+    pub const SIZE: usize = 9;
+
+    fn write(&self, data: &mut Vec<u8>) -> Result<()> {
+        data.write_u8(Self::TYPE);
+        data.write_u8(self.interface_number);
+        data.write_u8(self.alternate_setting);
+        data.write_u8(self.num_endpoints);
+        data.write_u8(self.interface_class);
+        data.write_u8(self.interface_sub_class);
+        data.write_u8(self.interface_protocol);
+        data.write_u8(self.name_idx);
+        Ok(())
+    }
+}
+
+/// USB endpoint descriptor.
+#[derive(Clone, Debug)]
+pub struct EndpointDesc {
+    /// Endpoint address.
+    pub endpoint_address: u8,
+    /// Attributes.
+    pub attributes: u8,
+    /// Maximum packet size.
+    pub max_packet_size: u16,
+    /// Interval.
+    pub interval: u8,
+    /// Audio-endpoint specific data.
+    pub audio: Option<AudioEndpointDesc>,
+}
+
+/// Audio-part of USB endpoint descriptor.
+#[derive(Clone, Debug)]
+pub struct AudioEndpointDesc {
+    /// Refresh.
+    pub refresh: u8,
+    /// Sync address.
+    pub synch_address: u8,
+}
+
+impl EndpointDesc {
+    /// Endpoint descriptor type.
+    pub const TYPE: u8 = 0x05;
+
+    /// Size without audio.
+    pub const SIZE: usize = 7;
+    /// Size with audio.
+    pub const AUDIO_SIZE: usize = 9;
+
+    fn write(&self, data: &mut Vec<u8>) -> Result<()> {
+        data.write_u8(Self::TYPE);
+        data.write_u8(self.endpoint_address);
+        data.write_u8(self.attributes);
+        data.write_u16(self.max_packet_size);
+        data.write_u8(self.interval);
+
+        if let Some(audio) = &self.audio {
+            data.write_u8(audio.refresh);
+            data.write_u8(audio.synch_address);
+        }
+
+        Ok(())
+    }
+
+    /// Decodes raw descriptor data.
+    pub(crate) fn read(mut data: &[u8]) -> Result<Self> {
+        let size = data.read_u8()?;
+        if usize::from(size) != Self::SIZE && usize::from(size) != Self::AUDIO_SIZE {
+            return Err(Error::Invalid("endpoint descriptor size mismatch"));
+        }
+
+        if data.read_u8()? != Self::TYPE {
+            return Err(Error::Invalid("endpoint descriptor type mismatch"));
+        }
+
+        let endpoint_address = data.read_u8()?;
+        let attributes = data.read_u8()?;
+        let max_packet_size = data.read_u16()?;
+        let interval = data.read_u8()?;
+
+        let audio = if usize::from(size) == Self::AUDIO_SIZE {
+            let refresh = data.read_u8()?;
+            let synch_address = data.read_u8()?;
+            Some(AudioEndpointDesc { refresh, synch_address })
+        } else {
+            None
+        };
+
+        Ok(Self { endpoint_address, attributes, max_packet_size, interval, audio })
+    }
+}
+
+/// USB SuperSpeed endpoint companion descriptor.
+#[derive(Clone, Debug)]
+pub struct SsEndpointComp {
+    /// Maximum burst.
+    pub max_burst: u8,
+    /// Attributes.
+    pub attributes: u8,
+    /// Bytes per interval.
+    pub bytes_per_interval: u16,
+}
+
+impl SsEndpointComp {
+    /// SuperSpeed endpoint companion descriptor type.
+    pub const TYPE: u8 = 0x30;
+
+    /// Size.
+    pub const SIZE: usize = 6;
+
+    fn write(&self, data: &mut Vec<u8>) -> Result<()> {
+        data.write_u8(Self::TYPE);
+        data.write_u8(self.max_burst);
+        data.write_u8(self.attributes);
+        data.write_u16(self.bytes_per_interval);
+        Ok(())
+    }
+}
+
+/// USB interface association descriptor.
+#[derive(Clone, Debug)]
+pub struct InterfaceAssocDesc {
+    /// First interface.
+    pub first_interface: u8,
+    /// Interface count.
+    pub interface_count: u8,
+    /// Function class.
+    pub function_class: u8,
+    /// Function sub class.
+    pub function_sub_class: u8,
+    /// Function protocol.
+    pub function_protocol: u8,
+    /// Index of function name string.
+    pub name_idx: u8,
+}
+
+impl InterfaceAssocDesc {
+    /// Interface association descriptor type.
+    pub const TYPE: u8 = 0x0b;
+
+    /// Size.
+    pub const SIZE: usize = 8;
+
+    fn write(&self, data: &mut Vec<u8>) -> Result<()> {
+        data.write_u8(Self::TYPE);
+        data.write_u8(self.first_interface);
+        data.write_u8(self.interface_count);
+        data.write_u8(self.function_class);
+        data.write_u8(self.function_sub_class);
+        data.write_u8(self.function_protocol);
+        data.write_u8(self.name_idx);
+        Ok(())
+    }
+}
+
+/// Microsoft OS descriptor.
+#[derive(Clone, Debug)]
+pub struct OsDesc {
+    /// Interface.
+    pub interface: u8,
+    /// Extended descriptor.
+    pub ext: OsDescExt,
+}
+
+impl OsDesc {
+    fn to_bytes(&self, os_desc_version: u16) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.write_u8(self.interface);
+        data.write_u32(0); // length
+
+        self.ext.write(&mut data, os_desc_version)?;
+
+        let len: u32 = data.len().try_into()?;
+        data[1..5].copy_from_slice(&len.to_le_bytes());
+        Ok(data)
+    }
+
+    fn parse(data: &mut &[u8]) -> Result<Self> {
+        let interface = data.read_u8()?;
+        let len: usize = data.read_u32()?.try_into()?;
+        let mut body = take(data, len.checked_sub(5).ok_or(Error::Invalid("OS descriptor length"))?)?;
+
+        let _bcd_version = body.read_u16()?;
+        let ext = match body.read_u16()? {
+            4 => {
+                let count = body.read_u8()?;
+                let _reserved = body.read_u8()?;
+                let compats = (0..count).map(|_| OsExtCompat::parse(&mut body)).collect::<Result<_>>()?;
+                OsDescExt::ExtCompat(compats)
+            }
+            5 => {
+                let count = body.read_u16()?;
+                let props = (0..count).map(|_| OsExtProp::parse(&mut body)).collect::<Result<_>>()?;
+                OsDescExt::ExtProp(props)
+            }
+            _ => return Err(Error::Invalid("unknown OS descriptor index")),
+        };
+
+        if !body.is_empty() {
+            return Err(Error::Invalid("trailing data in OS descriptor"));
+        }
+
+        Ok(Self { interface, ext })
+    }
+}
+
+/// Microsoft OS extended descriptor.
+#[derive(Clone, Debug)]
+pub enum OsDescExt {
+    /// Extended compatibility descriptors.
+    ExtCompat(Vec<OsExtCompat>),
+    /// Extended properties.
+    ExtProp(Vec<OsExtProp>),
+}
+
+impl OsDescExt {
+    fn write(&self, data: &mut Vec<u8>, os_desc_version: u16) -> Result<()> {
+        match self {
+            Self::ExtCompat(compats) => {
+                data.write_u16(os_desc_version); // bcdVersion
+                data.write_u16(4); // wIndex
+                data.write_u8(compats.len().try_into()?);
+                data.write_u8(0);
+
+                for compat in compats {
+                    compat.write(data)?;
+                }
+            }
+            Self::ExtProp(props) => {
+                data.write_u16(os_desc_version); // bcdVersion
+                data.write_u16(5); // wIndex
+                data.write_u16(props.len().try_into()?);
+
+                for prop in props {
+                    data.extend(prop.to_bytes()?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Microsoft extended compatibility descriptor.
+#[derive(Clone, Debug)]
+pub struct OsExtCompat {
+    /// First interface number.
+    pub first_interface_number: u8,
+    /// Compatible id.
+    pub compatible_id: [u8; 8],
+    /// Sub compatible id.
+    pub sub_compatible_id: [u8; 8],
+}
+
+impl OsExtCompat {
+    fn write(&self, data: &mut Vec<u8>) -> Result<()> {
+        data.write_u8(self.first_interface_number);
+        data.write_u8(1);
+        data.extend_from_slice(&self.compatible_id);
+        data.extend_from_slice(&self.sub_compatible_id);
+        data.extend_from_slice(&[0; 6]);
+        Ok(())
+    }
+
+    fn parse(data: &mut &[u8]) -> Result<Self> {
+        let first_interface_number = data.read_u8()?;
+        let _reserved = data.read_u8()?;
+        let compatible_id = data.read_array()?;
+        let sub_compatible_id = data.read_array()?;
+        take(data, 6)?;
+        Ok(Self { first_interface_number, compatible_id, sub_compatible_id })
+    }
+}
+
+/// Microsoft extended property descriptor.
+#[derive(Clone, Debug)]
+pub struct OsExtProp {
+    /// Data type.
+    pub data_type: u32,
+    /// Property name.
+    pub name: String,
+    /// Property data.
+    pub data: Vec<u8>,
+}
+
+impl OsExtProp {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        data.write_u32(0); // length
+        data.write_u32(self.data_type);
+        data.write_u16(self.name.len().try_into()?);
+        data.extend_from_slice(self.name.as_bytes());
+        data.write_u32(self.data.len().try_into()?);
+        data.extend_from_slice(&self.data);
+
+        let len: u32 = data.len().try_into()?;
+        assert_eq!(len as usize, 14 + self.name.len() + self.data.len(), "invalid OS descriptor length");
+        data[0..4].copy_from_slice(&len.to_le_bytes());
+        Ok(data)
+    }
+
+    fn parse(data: &mut &[u8]) -> Result<Self> {
+        let len: usize = data.read_u32()?.try_into()?;
+        let mut body = take(data, len.checked_sub(4).ok_or(Error::Invalid("OS property length"))?)?;
+
+        let data_type = body.read_u32()?;
+        let name_len = body.read_u16()?;
+        let name = String::from_utf8(take(&mut body, name_len.into())?.to_vec())
+            .map_err(|_| Error::Invalid("OS property name"))?;
+        let data_len: usize = body.read_u32()?.try_into()?;
+        let data = take(&mut body, data_len)?.to_vec();
+
+        if !body.is_empty() {
+            return Err(Error::Invalid("trailing data in OS property"));
+        }
+
+        Ok(Self { data_type, name, data })
+    }
+}
+
+/// Custom descriptor.
+///
+/// The raw descriptor published will be of the form:
+/// `[length, descriptor_type, data...]`
+///
+/// The data is passed to the host unchanged, thus it cannot refer to strings:
+/// FunctionFS assigns the host-visible string indices only within the standard
+/// descriptors it parses, such as the interface name.
+#[derive(Clone, Debug)]
+pub struct CustomDesc {
+    /// Descriptor type.
+    pub descriptor_type: u8,
+    /// Custom data.
+    pub data: Vec<u8>,
+}
+
+impl CustomDesc {
+    /// Creates a new custom descriptor.
+    ///
+    /// The data must not include the length and descriptor type.
+    pub fn new(descriptor_type: u8, data: Vec<u8>) -> Self {
+        Self { descriptor_type, data }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) -> Result<()> {
+        data.write_u8(self.descriptor_type);
+        data.extend_from_slice(&self.data);
+        Ok(())
+    }
+}
+
+/// FunctionFS strings, as written to `ep0`.
+#[derive(Clone, Debug)]
+pub struct Strings(pub BTreeMap<Language, Vec<String>>);
+
+impl Strings {
+    const MAGIC: u32 = 2;
+
+    /// Decodes FunctionFS strings.
+    pub(crate) fn read(mut data: &[u8]) -> Result<Self> {
+        if data.read_u32()? != Self::MAGIC {
+            return Err(Error::Invalid("unsupported strings magic"));
+        }
+
+        let len: usize = data.read_u32()?.try_into()?;
+        data = take(&mut data, len.checked_sub(8).ok_or(Error::Invalid("strings length"))?)?;
+
+        let str_count = data.read_u32()?;
+        let lang_count = data.read_u32()?;
+
+        let mut strings = BTreeMap::new();
+        for _ in 0..lang_count {
+            let lang = Language::from(data.read_u16()?);
+
+            let mut lang_strings = Vec::new();
+            for _ in 0..str_count {
+                let end = data.iter().position(|&b| b == 0).ok_or(Error::Truncated)?;
+                let str = String::from_utf8(take(&mut data, end)?.to_vec())
+                    .map_err(|_| Error::Invalid("string is not UTF-8"))?;
+                take(&mut data, 1)?;
+                lang_strings.push(str);
+            }
+
+            if strings.insert(lang, lang_strings).is_some() {
+                return Err(Error::Invalid("duplicate language"));
+            }
+        }
+
+        if !data.is_empty() {
+            return Err(Error::Invalid("trailing data after strings"));
+        }
+
+        Ok(Self(strings))
+    }
+
+    /// Encodes the strings.
+    pub(crate) fn write(&self) -> Result<Vec<u8>> {
+        let str_count = self.0.values().next().map(|v| v.len()).unwrap_or_default();
+        if !self.0.values().all(|v| v.len() == str_count) {
+            return Err(Error::StringsDifferAcrossLanguages);
+        }
+
+        let mut data = Vec::new();
+
+        data.write_u32(Self::MAGIC);
+        data.write_u32(0); // length
+        data.write_u32(str_count.try_into()?);
+        data.write_u32(self.0.len().try_into()?);
+
+        // Languages are ordered by their code to make the encoding deterministic.
+        let mut langs: Vec<_> = self.0.iter().map(|(lang, strings)| (u16::from(*lang), strings)).collect();
+        langs.sort_unstable_by_key(|(code, _)| *code);
+
+        for (code, strings) in langs {
+            data.write_u16(code);
+            for str in strings {
+                data.extend_from_slice(str.as_bytes());
+                data.write_u8(0);
+            }
+        }
+
+        let len: u32 = data.len().try_into()?;
+        data[4..8].copy_from_slice(&len.to_le_bytes());
+        Ok(data)
+    }
+}
+
+/// USB control request.
+#[derive(Clone, Debug)]
+pub struct CtrlReq {
+    /// Request type.
+    pub request_type: u8,
+    /// Request.
+    pub request: u8,
+    /// Value.
+    pub value: u16,
+    /// Index.
+    pub index: u16,
+    /// Length.
+    pub length: u16,
+}
+
+impl CtrlReq {
+    /// Parse from buffer.
+    pub fn parse(mut buf: &[u8]) -> Result<Self> {
+        let request_type = buf.read_u8()?;
+        let request = buf.read_u8()?;
+        let value = buf.read_u16()?;
+        let index = buf.read_u16()?;
+        let length = buf.read_u16()?;
+        Ok(Self { request_type, request, value, index, length })
+    }
+}
+
+/// FunctionFS event type.
+pub mod event {
+    pub const BIND: u8 = 0;
+    pub const UNBIND: u8 = 1;
+    pub const ENABLE: u8 = 2;
+    pub const DISABLE: u8 = 3;
+    pub const SETUP: u8 = 4;
+    pub const SUSPEND: u8 = 5;
+    pub const RESUME: u8 = 6;
+}
+
+/// FunctionFS event.
+pub struct Event {
+    pub data: [u8; 8],
+    pub event_type: u8,
+}
+
+impl Event {
+    /// Size of raw event data.
+    pub const SIZE: usize = 12;
+
+    /// Parse FunctionFS event data.
+    pub fn parse(mut buf: &[u8]) -> Result<Self> {
+        let data = buf.read_array()?;
+        let event_type = buf.read_u8()?;
+        let _pad: [u8; 3] = buf.read_array()?;
+        Ok(Self { data, event_type })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeMap;
+
+    use super::{
+        Desc, Descs, EndpointDesc, Flags, InterfaceDesc, OsDesc, OsDescExt, OsExtProp, SsEndpointComp, Strings,
+        OS_DESC_VERSION_1_00,
+    };
+    use crate::Language;
+
+    /// Checks that decoding does not panic and that decoded data re-encodes stably.
+    fn check_descs(data: &[u8]) {
+        let Ok(parsed) = Descs::read(data) else { return };
+        let Ok(encoded) = parsed.write(OS_DESC_VERSION_1_00) else { return };
+        let reparsed = Descs::read(&encoded).unwrap();
+        assert_eq!(reparsed.write(OS_DESC_VERSION_1_00).unwrap(), encoded);
+    }
+
+    /// Checks that decoding does not panic and that decoded data re-encodes stably.
+    fn check_strings(data: &[u8]) {
+        let Ok(parsed) = Strings::read(data) else { return };
+        let encoded = parsed.write().unwrap();
+        assert_eq!(Strings::read(&encoded).unwrap().0, parsed.0);
+    }
+
+    /// Derives inputs from valid data by truncating it and flipping bits.
+    fn mutations(data: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let truncated = (0..data.len()).map(|len| data[..len].to_vec());
+        let flipped = (0..data.len() * 8).map(|bit| {
+            let mut data = data.to_vec();
+            data[bit / 8] ^= 1 << (bit % 8);
+            data
+        });
+        truncated.chain(flipped)
+    }
+
+    #[test]
+    fn descs_roundtrip() {
+        let intf = InterfaceDesc {
+            interface_number: 0,
+            alternate_setting: 0,
+            num_endpoints: 1,
+            interface_class: 0xff,
+            interface_sub_class: 1,
+            interface_protocol: 2,
+            name_idx: 1,
+        };
+        let ep = EndpointDesc {
+            endpoint_address: 0x81,
+            attributes: 2,
+            max_packet_size: 512,
+            interval: 0,
+            audio: None,
+        };
+        let descs = Descs {
+            flags: Flags::ALL_CTRL_RECIP,
+            eventfd: None,
+            fs_descrs: vec![intf.clone().into(), ep.clone().into()],
+            hs_descrs: vec![intf.clone().into(), ep.clone().into()],
+            ss_descrs: vec![
+                intf.into(),
+                ep.into(),
+                SsEndpointComp { max_burst: 2, attributes: 0, bytes_per_interval: 0 }.into(),
+            ],
+            os_descrs: vec![OsDesc {
+                interface: 0,
+                ext: OsDescExt::ExtProp(vec![OsExtProp {
+                    data_type: 1,
+                    name: "DeviceInterfaceGUID".to_string(),
+                    data: vec![0; 4],
+                }]),
+            }],
+        };
+
+        let data = descs.write(OS_DESC_VERSION_1_00).unwrap();
+        let parsed = Descs::read(&data).unwrap();
+        assert!(parsed.flags.contains(Flags::ALL_CTRL_RECIP));
+        assert_eq!(parsed.fs_descrs.len(), 2);
+        assert_eq!(parsed.hs_descrs.len(), 2);
+        assert!(matches!(parsed.ss_descrs[2], Desc::SsEndpointComp(SsEndpointComp { max_burst: 2, .. })));
+        assert_eq!(parsed.os_descrs.len(), 1);
+        assert_eq!(parsed.write(OS_DESC_VERSION_1_00).unwrap(), data);
+
+        assert!(Descs::read(&data[..data.len() - 1]).is_err());
+        for input in mutations(&data) {
+            check_descs(&input);
+        }
+    }
+
+    #[test]
+    fn strings_roundtrip() {
+        let strings = Strings(BTreeMap::from([
+            (Language::EnglishUnitedStates, vec!["a".to_string(), "bc".to_string()]),
+            (Language::GermanStandard, vec!["d".to_string(), "ef".to_string()]),
+        ]));
+
+        let data = strings.write().unwrap();
+        let parsed = Strings::read(&data).unwrap();
+        assert_eq!(parsed.0, strings.0);
+        assert_eq!(parsed.write().unwrap(), data);
+
+        assert!(Strings::read(&data[..data.len() - 1]).is_err());
+        for input in mutations(&data) {
+            check_strings(&input);
+        }
+    }
+}
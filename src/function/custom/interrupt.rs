@@ -0,0 +1,96 @@
+//! Blocking operations that are interrupted when a timeout expires.
+//!
+//! FunctionFS performs the data stage of a control request within the read or write call
+//! on `ep0` and only a signal can interrupt it; non-blocking mode and AIO are not supported.
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::{
+    io::{Error, ErrorKind, Result},
+    sync::{mpsc, Once},
+    thread,
+    time::Duration,
+};
+
+/// Signal used to interrupt the operation.
+///
+/// It is ignored by default, thus handling it does not change the behavior of the process.
+const SIGNAL: Signal = Signal::SIGURG;
+
+/// Interval in which the signal is repeated until the operation has been interrupted.
+const REPEAT: Duration = Duration::from_millis(10);
+
+extern "C" fn handle_signal(_: libc::c_int) {}
+
+/// Installs a handler for [`SIGNAL`] that does nothing, unless the process handles it already.
+fn install_handler() -> Result<()> {
+    static INSTALL: Once = Once::new();
+    let mut res = Ok(());
+    INSTALL.call_once(|| {
+        let action = SigAction::new(SigHandler::Handler(handle_signal), SaFlags::empty(), SigSet::empty());
+        res = match unsafe { sigaction(SIGNAL, &action) } {
+            Ok(prev) if matches!(prev.handler(), SigHandler::SigDfl | SigHandler::SigIgn) => Ok(()),
+            Ok(prev) => unsafe { sigaction(SIGNAL, &prev) }.map(|_| ()),
+            Err(err) => Err(err),
+        }
+        .map_err(Error::from);
+    });
+    res
+}
+
+/// Performs the blocking operation `op` on a helper thread and interrupts it if it does not
+/// complete within the timeout.
+///
+/// Returns `None` if the operation has been interrupted.
+pub(crate) fn run<T: Send>(timeout: Duration, op: impl FnOnce() -> Result<T> + Send) -> Result<Option<T>> {
+    install_handler()?;
+
+    thread::scope(|s| {
+        let (id_tx, id_rx) = mpsc::channel();
+        let (res_tx, res_rx) = mpsc::channel();
+        s.spawn(move || {
+            let _ = id_tx.send(unsafe { libc::pthread_self() });
+            let mut set = SigSet::empty();
+            set.add(SIGNAL);
+            let _ = res_tx.send(set.thread_unblock().map_err(Error::from).and_then(|()| op()));
+        });
+        let thread = id_rx.recv().unwrap();
+
+        let mut interrupting = false;
+        loop {
+            match res_rx.recv_timeout(if interrupting { REPEAT } else { timeout }) {
+                Ok(Ok(value)) => return Ok(Some(value)),
+                Ok(Err(err)) if interrupting && err.kind() == ErrorKind::Interrupted => return Ok(None),
+                Ok(Err(err)) => return Err(err),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    // The thread is joined at the end of the scope, thus its id remains valid.
+                    unsafe { libc::pthread_kill(thread, SIGNAL as libc::c_int) };
+                    interrupting = true;
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(Error::new(ErrorKind::Other, "operation thread panicked"))
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, io::Read, io::Write, time::Duration};
+
+    use super::run;
+
+    #[test]
+    fn never_completes() {
+        let (rx, tx) = nix::unistd::pipe().unwrap();
+        let mut rx = File::from(rx);
+        let mut tx = File::from(tx);
+
+        let mut buf = [0; 4];
+        assert!(run(Duration::from_millis(100), || rx.read(&mut buf)).unwrap().is_none());
+
+        tx.write_all(b"data").unwrap();
+        assert_eq!(run(Duration::from_secs(10), || rx.read(&mut buf)).unwrap(), Some(4));
+        assert_eq!(&buf, b"data");
+    }
+}
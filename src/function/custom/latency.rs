@@ -0,0 +1,188 @@
+//! Endpoint latency measurement.
+
+use std::time::Duration;
+
+/// Histogram of durations with buckets of equal width.
+///
+/// The last bucket also counts all durations exceeding the range of the histogram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    bucket_width: Duration,
+    counts: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    /// Creates an empty histogram with the specified bucket width and number of buckets.
+    ///
+    /// At least one bucket is always present.
+    pub fn new(bucket_width: Duration, buckets: usize) -> Self {
+        Self { bucket_width, counts: vec![0; buckets.max(1)] }
+    }
+
+    /// Width of each bucket.
+    pub fn bucket_width(&self) -> Duration {
+        self.bucket_width
+    }
+
+    /// Number of samples in each bucket.
+    ///
+    /// Bucket `i` counts durations in the range `[i * bucket_width, (i + 1) * bucket_width)`.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Total number of samples.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Number of samples that are at least as long as the specified deadline.
+    ///
+    /// Since samples are grouped into buckets, samples in the bucket containing
+    /// the deadline are counted as well.
+    pub fn exceeding(&self, deadline: Duration) -> u64 {
+        self.counts.iter().skip(self.bucket(deadline)).sum()
+    }
+
+    fn bucket(&self, value: Duration) -> usize {
+        let idx = match self.bucket_width.as_nanos() {
+            0 => 0,
+            width => value.as_nanos() / width,
+        };
+        idx.min(self.counts.len() as u128 - 1) as usize
+    }
+
+    fn record(&mut self, value: Duration) {
+        let idx = self.bucket(value);
+        self.counts[idx] += 1;
+    }
+
+    fn reset(&mut self) {
+        self.counts.iter_mut().for_each(|c| *c = 0);
+    }
+}
+
+/// Latency statistics of endpoint operations.
+///
+/// The latency of an operation is the time between its submission to the kernel and
+/// its completion, as measured by the monotonic clock.
+/// Jitter is the absolute difference between the latencies of consecutive operations.
+/// Failed and cancelled operations are not recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyStats {
+    count: u64,
+    min: Option<Duration>,
+    max: Option<Duration>,
+    total: Duration,
+    last: Option<Duration>,
+    latency: LatencyHistogram,
+    jitter: LatencyHistogram,
+}
+
+impl LatencyStats {
+    /// Creates empty latency statistics using histograms with the specified bucket width
+    /// and number of buckets.
+    pub fn new(bucket_width: Duration, buckets: usize) -> Self {
+        Self {
+            count: 0,
+            min: None,
+            max: None,
+            total: Duration::ZERO,
+            last: None,
+            latency: LatencyHistogram::new(bucket_width, buckets),
+            jitter: LatencyHistogram::new(bucket_width, buckets),
+        }
+    }
+
+    /// Number of recorded operations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Minimum latency.
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// Maximum latency.
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// Mean latency.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            (self.total.as_nanos() / u128::from(self.count)).try_into().unwrap_or(u64::MAX),
+        ))
+    }
+
+    /// Latency of the most recently recorded operation.
+    pub fn last(&self) -> Option<Duration> {
+        self.last
+    }
+
+    /// Histogram of latencies.
+    pub fn latency_histogram(&self) -> &LatencyHistogram {
+        &self.latency
+    }
+
+    /// Histogram of jitter.
+    pub fn jitter_histogram(&self) -> &LatencyHistogram {
+        &self.jitter
+    }
+
+    /// Clears all recorded data.
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.min = None;
+        self.max = None;
+        self.total = Duration::ZERO;
+        self.last = None;
+        self.latency.reset();
+        self.jitter.reset();
+    }
+
+    /// Records the latency of an operation.
+    pub(crate) fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+        self.total = self.total.saturating_add(latency);
+        self.latency.record(latency);
+
+        if let Some(last) = self.last {
+            self.jitter.record(if latency > last { latency - last } else { last - latency });
+        }
+        self.last = Some(latency);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::LatencyStats;
+
+    #[test]
+    fn record() {
+        let mut stats = LatencyStats::new(Duration::from_millis(1), 4);
+        for ms in [1, 3, 2, 10] {
+            stats.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.min(), Some(Duration::from_millis(1)));
+        assert_eq!(stats.max(), Some(Duration::from_millis(10)));
+        assert_eq!(stats.mean(), Some(Duration::from_millis(4)));
+        assert_eq!(stats.latency_histogram().counts(), &[0, 1, 1, 2]);
+        assert_eq!(stats.jitter_histogram().counts(), &[0, 1, 1, 1]);
+        assert_eq!(stats.latency_histogram().exceeding(Duration::from_millis(3)), 2);
+
+        stats.reset();
+        assert_eq!(stats.count(), 0);
+        assert_eq!(stats.latency_histogram().total(), 0);
+    }
+}
@@ -3,39 +3,74 @@
 //! The Linux kernel configuration option `CONFIG_USB_CONFIGFS_F_FS` must be enabled.
 
 use bytes::{Bytes, BytesMut};
-use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::{
+    fcntl::{openat, OFlag},
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::stat::Mode,
+};
 use proc_mounts::MountIter;
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     ffi::{OsStr, OsString},
     fmt, fs,
     fs::File,
     hash::Hash,
     io::{Error, ErrorKind, Read, Result, Write},
-    os::fd::{AsFd, AsRawFd, RawFd},
+    mem,
+    os::{
+        fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    },
     path::{Path, PathBuf},
+    process,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex, Weak,
     },
-    time::Duration,
+    thread,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
 use super::{
-    util::{split_function_dir, value, FunctionDir, Status},
+    util::{split_function_dir, value, FunctionDir, State, Status},
     Function, Handle,
 };
-use crate::{Class, Language};
+use crate::{
+    dry_run, dump_descriptors, AccessKind, AccessRequirements, Capability, Class, GadgetState, HostDescriptors,
+    Language, Speed,
+};
 
 mod aio;
+pub mod bench;
+pub mod cdc_acm;
+pub mod dfu;
 mod ffs;
+mod interrupt;
+mod latency;
+pub mod msc_bot;
+mod pool;
+pub mod ptp;
+mod router;
+mod split;
+mod webusb;
 
 pub(crate) fn driver() -> &'static OsStr {
     OsStr::new("ffs")
 }
 
-pub use ffs::CustomDesc;
+pub use aio::{SharedAio, SubmitOptions};
+pub use ffs::{
+    CustomDesc, Desc as RawDesc, Descs as RawDescs, Flags as RawDescsFlags,
+    InterfaceAssocDesc as RawInterfaceAssocDesc, InterfaceDesc as RawInterfaceDesc, OsDesc as RawOsDesc,
+    OsDescExt as RawOsDescExt, OsExtCompat as RawOsExtCompat, OsExtProp as RawOsExtProp,
+    SsEndpointComp as RawSsEndpointComp, Strings as RawStrings, OS_DESC_VERSION_1_00, OS_DESC_VERSION_LEGACY,
+};
+pub use latency::{LatencyHistogram, LatencyStats};
+pub use pool::{BufferPool, PooledBuffer};
+pub use router::{CtrlFilter, CtrlRecipient, CtrlType, SetupRouter};
+pub use split::{RecvCompleteHalf, RecvSubmitHalf, SendCompleteHalf, SendSubmitHalf};
+pub use webusb::WebUsbRequests;
 
 /// An USB interface.
 #[derive(Debug)]
@@ -110,6 +145,16 @@ impl Interface {
 }
 
 /// Interface association.
+///
+/// An interface association can only group interfaces of the same custom function and
+/// these interfaces must be adjacent.
+/// Interface numbers are local to the custom function and remapped by the kernel when
+/// the USB gadget is bound, thus an association cannot span interfaces provided by other
+/// functions.
+/// Kernel functions that require an interface association, such as NCM, provide their own.
+///
+/// The interface numbers assigned to the custom function depend on its position
+/// within the [configuration](crate::Config::add_function).
 #[derive(Debug, Clone)]
 pub struct Association {
     addr: Arc<()>,
@@ -160,6 +205,10 @@ pub struct EndpointDirection {
     direction: Direction,
     /// Queue length.
     pub queue_len: u32,
+    /// Open the endpoint file for direct I/O (`O_DIRECT`).
+    ///
+    /// Registration fails if this is not supported by the kernel.
+    pub direct_io: bool,
     tx: value::Sender<EndpointIo>,
 }
 
@@ -168,26 +217,30 @@ impl fmt::Debug for EndpointDirection {
         f.debug_struct("EndpointDirection")
             .field("direction", &self.direction)
             .field("queue_len", &self.queue_len)
+            .field("direct_io", &self.direct_io)
             .finish()
     }
 }
 
 impl EndpointDirection {
-    const DEFAULT_QUEUE_LEN: u32 = 16;
+    /// Default queue length.
+    pub const DEFAULT_QUEUE_LEN: u32 = 16;
 
     /// From device to host.
     pub fn device_to_host() -> (EndpointSender, EndpointDirection) {
         let (tx, rx) = value::channel();
         let writer = EndpointSender(rx);
-        let this = Self { direction: Direction::DeviceToHost, tx, queue_len: Self::DEFAULT_QUEUE_LEN };
+        let this =
+            Self { direction: Direction::DeviceToHost, tx, queue_len: Self::DEFAULT_QUEUE_LEN, direct_io: false };
         (writer, this)
     }
 
     /// From host to device.
     pub fn host_to_device() -> (EndpointReceiver, EndpointDirection) {
         let (tx, rx) = value::channel();
-        let reader = EndpointReceiver(rx);
-        let this = Self { direction: Direction::HostToDevice, tx, queue_len: Self::DEFAULT_QUEUE_LEN };
+        let reader = EndpointReceiver { io: rx, pool: None };
+        let this =
+            Self { direction: Direction::HostToDevice, tx, queue_len: Self::DEFAULT_QUEUE_LEN, direct_io: false };
         (reader, this)
     }
 
@@ -197,6 +250,13 @@ impl EndpointDirection {
         self.queue_len = queue_len;
         self
     }
+
+    /// Sets whether the endpoint file is opened for direct I/O (`O_DIRECT`).
+    #[must_use]
+    pub fn with_direct_io(mut self, direct_io: bool) -> Self {
+        self.direct_io = direct_io;
+        self
+    }
 }
 
 /// Endpoint synchronization type.
@@ -289,11 +349,31 @@ pub struct Endpoint {
     /// for super speed.
     pub max_burst_ss: u8,
     /// Number of bytes per interval for super speed.
+    ///
+    /// FunctionFS does not accept SuperSpeedPlus isochronous endpoint companion descriptors,
+    /// thus larger values required at Gen 2 speeds are not supported.
     pub bytes_per_interval_ss: u16,
     /// Interval for polling endpoint for data transfers.
+    ///
+    /// Used for all speeds, unless overridden by [`interval_hs`](Self::interval_hs)
+    /// or [`interval_ss`](Self::interval_ss).
     pub interval: u8,
+    /// Interval for polling endpoint for data transfers for high speed.
+    pub interval_hs: Option<u8>,
+    /// Interval for polling endpoint for data transfers for super speed.
+    pub interval_ss: Option<u8>,
     /// Data for audio endpoints.
     pub audio: Option<EndpointAudio>,
+    /// Class-specific descriptor of audio data endpoints, following the endpoint descriptor.
+    pub audio_general: Option<AudioEndpointGeneral>,
+    /// Requested endpoint address (`bEndpointAddress`), including the direction bit.
+    ///
+    /// If unspecified, endpoint numbers are assigned sequentially, skipping the numbers
+    /// of endpoints with a requested address.
+    /// The USB device controller may still assign a different address when binding, since
+    /// its endpoints are allocated by the kernel; use [`Custom::endpoint_addresses`] to
+    /// obtain the final mapping.
+    pub address: Option<u8>,
 }
 
 /// Extension of USB endpoint for audio.
@@ -305,6 +385,92 @@ pub struct EndpointAudio {
     pub synch_address: u8,
 }
 
+/// Unit of the lock delay of an audio data endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LockDelayUnits {
+    /// Undefined.
+    #[default]
+    Undefined,
+    /// Milliseconds.
+    Milliseconds,
+    /// Decoded PCM samples.
+    Samples,
+}
+
+/// Class-specific audio data endpoint descriptor (`CS_ENDPOINT` of subtype `EP_GENERAL`)
+/// as defined by the USB Audio Class 1.0 specification.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct AudioEndpointGeneral {
+    /// Whether the sampling frequency control is supported.
+    pub sampling_freq_control: bool,
+    /// Whether the pitch control is supported.
+    pub pitch_control: bool,
+    /// Whether the endpoint requires packets of maximum size.
+    pub max_packets_only: bool,
+    /// Unit of [`lock_delay`](Self::lock_delay).
+    pub lock_delay_units: LockDelayUnits,
+    /// Time the endpoint needs to reliably lock its clock recovery circuitry.
+    pub lock_delay: u16,
+}
+
+impl AudioEndpointGeneral {
+    /// Class-specific endpoint descriptor type.
+    const CS_ENDPOINT: u8 = 0x25;
+    /// General endpoint descriptor subtype.
+    const EP_GENERAL: u8 = 0x01;
+
+    /// Creates a descriptor without controls and lock delay.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the sampling frequency control is supported.
+    #[must_use]
+    pub fn with_sampling_freq_control(mut self, sampling_freq_control: bool) -> Self {
+        self.sampling_freq_control = sampling_freq_control;
+        self
+    }
+
+    /// Sets whether the pitch control is supported.
+    #[must_use]
+    pub fn with_pitch_control(mut self, pitch_control: bool) -> Self {
+        self.pitch_control = pitch_control;
+        self
+    }
+
+    /// Sets whether the endpoint requires packets of maximum size.
+    #[must_use]
+    pub fn with_max_packets_only(mut self, max_packets_only: bool) -> Self {
+        self.max_packets_only = max_packets_only;
+        self
+    }
+
+    /// Sets the lock delay.
+    #[must_use]
+    pub fn with_lock_delay(mut self, lock_delay_units: LockDelayUnits, lock_delay: u16) -> Self {
+        self.lock_delay_units = lock_delay_units;
+        self.lock_delay = lock_delay;
+        self
+    }
+
+    fn to_custom_desc(self) -> CustomDesc {
+        let attributes = u8::from(self.sampling_freq_control)
+            | u8::from(self.pitch_control) << 1
+            | u8::from(self.max_packets_only) << 7;
+        let lock_delay_units = match self.lock_delay_units {
+            LockDelayUnits::Undefined => 0,
+            LockDelayUnits::Milliseconds => 1,
+            LockDelayUnits::Samples => 2,
+        };
+        let [delay_lo, delay_hi] = self.lock_delay.to_le_bytes();
+        CustomDesc::new(
+            Self::CS_ENDPOINT,
+            vec![Self::EP_GENERAL, attributes, lock_delay_units, delay_lo, delay_hi],
+        )
+    }
+}
+
 impl Endpoint {
     /// Creates a new bulk endpoint.
     pub fn bulk(direction: EndpointDirection) -> Self {
@@ -325,7 +491,47 @@ impl Endpoint {
                 Direction::DeviceToHost => 0,
                 Direction::HostToDevice => 1,
             },
+            interval_hs: None,
+            interval_ss: None,
             audio: None,
+            audio_general: None,
+            address: None,
+        }
+    }
+
+    /// Requests the specified endpoint address (`bEndpointAddress`).
+    ///
+    /// The address consists of the endpoint number (1 to 15) and the direction bit
+    /// (`0x80` for device to host), which must match the direction of the endpoint.
+    #[must_use]
+    pub fn with_address(mut self, address: u8) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    /// Sets the class-specific descriptor of an audio data endpoint.
+    #[must_use]
+    pub fn with_audio_general(mut self, audio_general: AudioEndpointGeneral) -> Self {
+        self.audio_general = Some(audio_general);
+        self
+    }
+
+    /// Creates a new interrupt endpoint for notifications, such as those of CDC functions.
+    ///
+    /// The polling interval is specified in milliseconds and encoded for each speed:
+    /// as milliseconds for full speed and as exponent of 125 µs microframes for
+    /// high and super speed, rounded down to the next supported interval.
+    pub fn interrupt_notification(direction: EndpointDirection, max_packet_size: u16, interval_ms: u16) -> Self {
+        let interval_ms = interval_ms.max(1);
+        let exponent = (u32::from(interval_ms) * 8).ilog2() + 1;
+        let interval_hs = exponent.min(16) as u8;
+        Self {
+            max_packet_size_hs: max_packet_size,
+            max_packet_size_ss: max_packet_size,
+            interval: interval_ms.min(255) as u8,
+            interval_hs: Some(interval_hs),
+            interval_ss: Some(interval_hs),
+            ..Self::custom(direction, TransferType::Interrupt)
         }
     }
 }
@@ -350,6 +556,16 @@ impl OsExtCompat {
     pub const fn winusb() -> Self {
         Self::new(*b"WINUSB\0\0", [0; 8])
     }
+
+    /// Use Microsoft RNDIS driver.
+    pub const fn rndis() -> Self {
+        Self::new(*b"RNDIS\0\0\0", *b"5162001\0")
+    }
+
+    /// Use Microsoft NCM driver.
+    pub const fn winncm() -> Self {
+        Self::new(*b"WINNCM\0\0", [0; 8])
+    }
 }
 
 /// Microsoft extended property descriptor.
@@ -524,6 +740,25 @@ pub struct CustomBuilder {
     ///
     /// Implies [`ffs_no_init`](Self::ffs_no_init).
     pub ffs_no_mount: bool,
+    /// Maximum data length of control requests.
+    ///
+    /// Control requests with more data are stalled automatically and reported as
+    /// [`Event::SetupTooLarge`].
+    /// If unspecified, control requests of all lengths are accepted.
+    pub max_ctrl_len: Option<u16>,
+    /// Shared AIO context used for all endpoints.
+    ///
+    /// The same context may be used by multiple custom functions.
+    /// If unspecified, each endpoint uses its own AIO context and thread.
+    /// Use [`SharedAio::new_async`] to process completions on the tokio runtime instead.
+    pub aio: Option<SharedAio>,
+    /// BCD version of Microsoft OS descriptors written to FunctionFS.
+    ///
+    /// Linux 6.4 changed the version expected by FunctionFS from [`OS_DESC_VERSION_LEGACY`]
+    /// to [`OS_DESC_VERSION_1_00`].
+    /// If unspecified, the version is chosen based on the version of the running kernel.
+    /// Specify it for kernels that have backported the change.
+    pub os_desc_version: Option<u16>,
 }
 
 impl CustomBuilder {
@@ -543,6 +778,11 @@ impl CustomBuilder {
                 ep_files: ep_files.clone(),
                 existing_ffs: false,
                 ffs_dir: ffs_dir_rx,
+                interface_count: self.interfaces.len(),
+                max_ctrl_len: self.max_ctrl_len,
+                owner: process::id(),
+                activation: Activation::default(),
+                _ffs_dir_fd: None,
             },
             Handle::new(CustomFunction {
                 builder: self,
@@ -551,6 +791,7 @@ impl CustomBuilder {
                 ep_files,
                 ffs_dir_created: AtomicBool::new(false),
                 ffs_dir_tx,
+                ffs_dir_fd: None,
             }),
         )
     }
@@ -563,11 +804,30 @@ impl CustomBuilder {
     /// been registered externally.
     pub fn existing(mut self, ffs_dir: impl AsRef<Path>) -> Result<Custom> {
         self.ffs_dir = Some(ffs_dir.as_ref().to_path_buf());
+        self.do_existing(None)
+    }
+
+    /// Use the pre-mounted FunctionFS directory referred to by the specified directory file descriptor.
+    ///
+    /// All device files are opened relative to the file descriptor, thus the FunctionFS
+    /// directory does not need to be reachable by path.
+    /// This is useful in sandboxed environments where the directory file descriptor is inherited.
+    ///
+    /// [`Custom::ffs_dir`] reports the directory as `/proc/self/fd/<fd>`.
+    ///
+    /// See [`existing`](Self::existing) for details.
+    pub fn existing_fd(mut self, ffs_dir: OwnedFd) -> Result<Custom> {
+        self.ffs_dir = Some(PathBuf::from(format!("/proc/self/fd/{}", ffs_dir.as_raw_fd())));
+        self.do_existing(Some(ffs_dir))
+    }
 
+    fn do_existing(self, ffs_dir_fd: Option<OwnedFd>) -> Result<Custom> {
         let dir = FunctionDir::new();
         let (ep0_tx, ep0_rx) = value::channel();
         let (ffs_dir_tx, ffs_dir_rx) = value::channel();
         let ep_files = Arc::new(Mutex::new(Vec::new()));
+        let interface_count = self.interfaces.len();
+        let max_ctrl_len = self.max_ctrl_len;
 
         let func = CustomFunction {
             builder: self,
@@ -576,10 +836,23 @@ impl CustomBuilder {
             ep_files: ep_files.clone(),
             ffs_dir_created: AtomicBool::new(false),
             ffs_dir_tx,
+            ffs_dir_fd,
         };
         func.init()?;
 
-        Ok(Custom { dir, ep0: ep0_rx, setup_event: None, ep_files, existing_ffs: true, ffs_dir: ffs_dir_rx })
+        Ok(Custom {
+            dir,
+            ep0: ep0_rx,
+            setup_event: None,
+            ep_files,
+            existing_ffs: true,
+            ffs_dir: ffs_dir_rx,
+            interface_count,
+            max_ctrl_len,
+            owner: process::id(),
+            activation: Activation::default(),
+            _ffs_dir_fd: func.ffs_dir_fd,
+        })
     }
 
     /// Add an USB interface.
@@ -589,9 +862,36 @@ impl CustomBuilder {
         self
     }
 
-    /// Build functionfs descriptors and strings.
-    fn ffs_descs(&self) -> Result<(ffs::Descs, ffs::Strings)> {
-        let mut strings = ffs::Strings(HashMap::new());
+    /// Sets the BCD version of Microsoft OS descriptors written to FunctionFS.
+    ///
+    /// See [`os_desc_version`](Self::os_desc_version) for details.
+    #[must_use]
+    pub fn with_os_desc_version(mut self, os_desc_version: u16) -> Self {
+        self.os_desc_version = Some(os_desc_version);
+        self
+    }
+
+    /// Normalizes the names of all interfaces and interface associations to the specified languages.
+    ///
+    /// Use the same language set as for the [gadget](crate::Gadget::normalize_languages).
+    /// See [`LanguageSet`](crate::LanguageSet) for details.
+    pub fn normalize_languages(&mut self, languages: &crate::LanguageSet) -> Result<()> {
+        for intf in &mut self.interfaces {
+            intf.name = languages.normalize(&intf.name)?;
+            if let Some(assoc) = &mut intf.association {
+                assoc.name = languages.normalize(&assoc.name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates the FunctionFS descriptors and strings for this function.
+    ///
+    /// These are written to `ep0` during initialization.
+    /// Use [`RawDescs::parse`] and [`RawStrings::parse`] to decode descriptors
+    /// written by other implementations for comparison.
+    pub fn ffs_descs(&self) -> Result<(RawDescs, RawStrings)> {
+        let mut strings = ffs::Strings(BTreeMap::new());
         let mut add_strings = |strs: &HashMap<Language, String>| {
             let all_langs: HashSet<_> = strings.0.keys().chain(strs.keys()).cloned().collect();
             let str_cnt = strings.0.values().next().map(|s| s.len()).unwrap_or_default();
@@ -607,7 +907,7 @@ impl CustomBuilder {
         let mut ss_descrs = Vec::new();
         let mut os_descrs = Vec::new();
 
-        let mut endpoint_num: u8 = 0;
+        let mut endpoint_addresses = self.endpoint_addresses()?.into_iter();
 
         let mut assocs: HashMap<Association, ffs::InterfaceAssocDesc> = HashMap::new();
 
@@ -641,16 +941,8 @@ impl CustomBuilder {
             }
 
             for ep in &intf.endpoints {
-                endpoint_num += 1;
-                if endpoint_num >= ffs::DIR_IN {
-                    return Err(Error::new(ErrorKind::InvalidInput, "too many endpoints"));
-                }
-
                 let ep_desc = ffs::EndpointDesc {
-                    endpoint_address: match ep.direction.direction {
-                        Direction::DeviceToHost => endpoint_num | ffs::DIR_IN,
-                        Direction::HostToDevice => endpoint_num | ffs::DIR_OUT,
-                    },
+                    endpoint_address: endpoint_addresses.next().unwrap(),
                     attributes: ep.transfer.to_attributes(),
                     max_packet_size: 0,
                     interval: ep.interval,
@@ -665,12 +957,33 @@ impl CustomBuilder {
                     bytes_per_interval: ep.bytes_per_interval_ss,
                 };
 
+                let audio_general = ep.audio_general.map(AudioEndpointGeneral::to_custom_desc);
+
                 fs_descrs.push(ep_desc.clone().into());
-                hs_descrs
-                    .push(ffs::EndpointDesc { max_packet_size: ep.max_packet_size_hs, ..ep_desc.clone() }.into());
-                ss_descrs
-                    .push(ffs::EndpointDesc { max_packet_size: ep.max_packet_size_ss, ..ep_desc.clone() }.into());
+                hs_descrs.push(
+                    ffs::EndpointDesc {
+                        max_packet_size: ep.max_packet_size_hs,
+                        interval: ep.interval_hs.unwrap_or(ep.interval),
+                        ..ep_desc.clone()
+                    }
+                    .into(),
+                );
+                ss_descrs.push(
+                    ffs::EndpointDesc {
+                        max_packet_size: ep.max_packet_size_ss,
+                        interval: ep.interval_ss.unwrap_or(ep.interval),
+                        ..ep_desc.clone()
+                    }
+                    .into(),
+                );
                 ss_descrs.push(ss_comp_desc.into());
+
+                // Class-specific endpoint descriptors follow the companion descriptors.
+                if let Some(audio_general) = audio_general {
+                    fs_descrs.push(audio_general.clone().into());
+                    hs_descrs.push(audio_general.clone().into());
+                    ss_descrs.push(audio_general.into());
+                }
             }
 
             if let Some(assoc) = &intf.association {
@@ -686,7 +999,7 @@ impl CustomBuilder {
                     }),
                 };
 
-                if iad.first_interface + interface_number != interface_number {
+                if iad.first_interface + iad.interface_count != interface_number {
                     return Err(Error::new(ErrorKind::InvalidInput, "associated interfaces must be adjacent"));
                 }
 
@@ -735,6 +1048,29 @@ impl CustomBuilder {
         Ok((descs, strings))
     }
 
+    /// USB descriptors of the interfaces as seen by the USB host at the specified speed.
+    ///
+    /// Interface numbers, endpoint addresses and string indices are relative to this function.
+    /// The kernel renumbers them when the function is added to a USB gadget.
+    pub fn host_descriptors(&self, speed: Speed) -> Result<HostDescriptors> {
+        let (descs, strings) = self.ffs_descs()?;
+        let strings = strings
+            .0
+            .get(&Language::default())
+            .map(|strs| (1..).zip(strs.iter().cloned()).collect())
+            .unwrap_or_default();
+        Ok(HostDescriptors::new(descs.host_bytes(speed)?, strings))
+    }
+
+    /// Renders the USB descriptors of the interfaces at the specified speed as text
+    /// in the style of `lsusb -v`.
+    ///
+    /// See [`host_descriptors`](Self::host_descriptors) for numbering.
+    pub fn dump(&self, speed: Speed) -> Result<String> {
+        let descs = self.host_descriptors(speed)?;
+        Ok(dump_descriptors(&descs.data, speed, &descs.strings))
+    }
+
     /// Gets the descriptor and string data for writing into `ep0` of FunctionFS.
     ///
     /// Normally, this is done automatically when the custom function is registered.
@@ -742,7 +1078,83 @@ impl CustomBuilder {
     /// to `ep0` by other means.
     pub fn ffs_descriptors_and_strings(&self) -> Result<(Vec<u8>, Vec<u8>)> {
         let (descs, strs) = self.ffs_descs()?;
-        Ok((descs.to_bytes()?, strs.to_bytes()?))
+        Ok((self.encode_ffs_descs(&descs)?, strs.to_bytes()?))
+    }
+
+    /// Endpoint addresses (`bEndpointAddress`) used in the descriptors of all endpoints
+    /// in the order they were added.
+    ///
+    /// Requested [endpoint addresses](Endpoint::address) are validated and the remaining
+    /// endpoints are numbered sequentially.
+    pub fn endpoint_addresses(&self) -> Result<Vec<u8>> {
+        let endpoints = || self.interfaces.iter().flat_map(|intf| &intf.endpoints);
+
+        let mut reserved = HashSet::new();
+        for ep in endpoints() {
+            let Some(address) = ep.address else { continue };
+            let num = address & !ffs::DIR_IN;
+            if !(1..=15).contains(&num) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid endpoint address {address:#04x}, endpoint number must be 1 to 15"),
+                ));
+            }
+            let is_in = address & ffs::DIR_IN != 0;
+            if is_in != (ep.direction.direction == Direction::DeviceToHost) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("direction bit of endpoint address {address:#04x} does not match endpoint direction"),
+                ));
+            }
+            if !reserved.insert(address) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("endpoint address {address:#04x} is requested more than once"),
+                ));
+            }
+        }
+        let reserved_nums: HashSet<_> = reserved.iter().map(|address| address & !ffs::DIR_IN).collect();
+
+        let mut endpoint_num: u8 = 0;
+        let mut addresses = Vec::new();
+        for ep in endpoints() {
+            let address = match ep.address {
+                Some(address) => address,
+                None => {
+                    endpoint_num += 1;
+                    while reserved_nums.contains(&endpoint_num) {
+                        endpoint_num += 1;
+                    }
+                    if endpoint_num >= ffs::DIR_IN {
+                        return Err(Error::new(ErrorKind::InvalidInput, "too many endpoints"));
+                    }
+                    match ep.direction.direction {
+                        Direction::DeviceToHost => endpoint_num | ffs::DIR_IN,
+                        Direction::HostToDevice => endpoint_num | ffs::DIR_OUT,
+                    }
+                }
+            };
+            addresses.push(address);
+        }
+
+        Ok(addresses)
+    }
+
+    /// Serializes the FunctionFS descriptors using the configured version of Microsoft OS descriptors.
+    fn encode_ffs_descs(&self, descs: &RawDescs) -> Result<Vec<u8>> {
+        match self.os_desc_version {
+            Some(version @ (OS_DESC_VERSION_1_00 | OS_DESC_VERSION_LEGACY)) => {
+                if !descs.os_descrs.is_empty() {
+                    log::debug!("using Microsoft OS descriptor version {version:#06x}");
+                }
+                descs.encode(version)
+            }
+            Some(version) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported Microsoft OS descriptor version {version:#06x}"),
+            )),
+            None => descs.to_bytes(),
+        }
     }
 }
 
@@ -760,6 +1172,7 @@ struct CustomFunction {
     ep_files: Arc<Mutex<Vec<Arc<File>>>>,
     ffs_dir_created: AtomicBool,
     ffs_dir_tx: value::Sender<PathBuf>,
+    ffs_dir_fd: Option<OwnedFd>,
 }
 
 impl CustomFunction {
@@ -771,6 +1184,25 @@ impl CustomFunction {
         }
     }
 
+    /// Opens a file within the FunctionFS directory for reading and writing.
+    fn open_ffs_file(&self, name: &str, direct_io: bool) -> Result<File> {
+        let direct = if direct_io { libc::O_DIRECT } else { 0 };
+
+        match &self.ffs_dir_fd {
+            Some(dir_fd) => {
+                log::debug!("opening functionfs file {name} relative to directory fd {}", dir_fd.as_raw_fd());
+                let flags = OFlag::O_RDWR | OFlag::O_CLOEXEC | OFlag::from_bits_retain(direct);
+                let fd = openat(Some(dir_fd.as_raw_fd()), name, flags, Mode::empty())?;
+                Ok(File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+            }
+            None => {
+                let path = self.ffs_dir()?.join(name);
+                log::debug!("opening functionfs file {}", path.display());
+                File::options().read(true).write(true).custom_flags(direct).open(path)
+            }
+        }
+    }
+
     /// Initialize FunctionFS.
     ///
     /// It must already be mounted.
@@ -783,10 +1215,10 @@ impl CustomFunction {
             log::trace!("functionfs strings: {strs:?}");
 
             let ep0_path = ffs_dir.join("ep0");
-            let mut ep0 = File::options().read(true).write(true).open(&ep0_path)?;
+            let mut ep0 = self.open_ffs_file("ep0", false)?;
 
             log::debug!("writing functionfs descriptors to {}", ep0_path.display());
-            let descs_data = descs.to_bytes()?;
+            let descs_data = self.builder.encode_ffs_descs(&descs)?;
             log::trace!("functionfs descriptor data: {descs_data:x?}");
             if ep0.write(&descs_data)? != descs_data.len() {
                 return Err(Error::new(ErrorKind::UnexpectedEof, "short descriptor write"));
@@ -808,8 +1240,16 @@ impl CustomFunction {
                 for ep in &intf.endpoints {
                     endpoint_num += 1;
 
-                    let ep_path = ffs_dir.join(format!("ep{endpoint_num}"));
-                    let (ep_io, ep_file) = EndpointIo::new(ep_path, ep.direction.queue_len)?;
+                    let ep_name = format!("ep{endpoint_num}");
+                    let ep_file = self.open_ffs_file(&ep_name, ep.direction.direct_io)?;
+                    let (ep_io, ep_file) = EndpointIo::new(
+                        ffs_dir.join(ep_name),
+                        ep_file,
+                        ep.direction.queue_len,
+                        self.builder.aio.as_ref(),
+                        self.dir.status(),
+                        SpeedEndpointDesc::for_endpoint(ep),
+                    )?;
                     ep.direction.tx.send(ep_io).unwrap();
                     ep_files.push(ep_file);
                 }
@@ -850,7 +1290,8 @@ impl Function for CustomFunction {
 
         let ffs_dir = self.ffs_dir()?;
         log::debug!("creating functionfs directory {}", ffs_dir.display());
-        match fs::create_dir(&ffs_dir) {
+        match dry_run::create_dir(&ffs_dir) {
+            Ok(()) if dry_run::is_recording() => (),
             Ok(()) => self.ffs_dir_created.store(true, Ordering::SeqCst),
             Err(err) if err.kind() == ErrorKind::AlreadyExists => (),
             Err(err) => return Err(err),
@@ -867,9 +1308,61 @@ impl Function for CustomFunction {
         log::debug!("mounting functionfs into {} using options {mount_opts:?}", ffs_dir.display());
         ffs::mount(&self.dir.instance()?, &ffs_dir, &mount_opts)?;
 
+        if dry_run::is_recording() {
+            return Ok(());
+        }
         self.init()
     }
 
+    fn descriptors(&self, speed: Speed) -> Result<Option<HostDescriptors>> {
+        Ok(Some(self.builder.host_descriptors(speed)?))
+    }
+
+    fn interface_guids(&self) -> Vec<Uuid> {
+        let mut guids = Vec::new();
+        for prop in self.builder.interfaces.iter().flat_map(|intf| &intf.os_ext_props) {
+            let values = match (prop.name.as_str(), &prop.value) {
+                ("DeviceInterfaceGUID", OsRegValue::Sz(value)) => vec![value],
+                ("DeviceInterfaceGUIDs", OsRegValue::MultiSz(values)) => values.iter().collect(),
+                _ => continue,
+            };
+            for value in values {
+                match Uuid::parse_str(value.trim_start_matches('{').trim_end_matches('}')) {
+                    Ok(guid) if !guids.contains(&guid) => guids.push(guid),
+                    Ok(_) => (),
+                    Err(err) => log::warn!("invalid device interface GUID {value}: {err}"),
+                }
+            }
+        }
+        guids
+    }
+
+    fn required_access(&self) -> AccessRequirements {
+        let mut access = AccessRequirements::new();
+        if self.ffs_dir_fd.is_some() {
+            return access;
+        }
+
+        // The default directory name is only known once the function has been registered.
+        let ffs_dir = match (&self.builder.ffs_dir, self.dir.instance()) {
+            (Some(ffs_dir), _) => ffs_dir.clone(),
+            (None, Ok(instance)) => default_ffs_dir(&instance),
+            (None, Err(_)) => PathBuf::from("/dev"),
+        };
+        access.add_path(&ffs_dir, AccessKind::ReadWrite, "opening FunctionFS endpoint files");
+
+        if !self.builder.ffs_no_mount {
+            let parent =
+                if ffs_dir == Path::new("/dev") { &ffs_dir } else { ffs_dir.parent().unwrap_or(&ffs_dir) };
+            access.add_path(parent, AccessKind::Create, "creating FunctionFS mount point");
+            access.add_path(parent, AccessKind::Remove, "removing FunctionFS mount point");
+            access.add_path(&ffs_dir, AccessKind::Mount, "mounting FunctionFS");
+            access.add_capability(Capability::SysAdmin);
+        }
+
+        access
+    }
+
     fn pre_removal(&self) -> Result<()> {
         self.close();
         Ok(())
@@ -893,25 +1386,159 @@ pub(crate) fn remove_handler(dir: PathBuf) -> Result<()> {
         let Ok(mount) = mount else { continue };
 
         if mount.fstype == ffs::FS_TYPE && mount.source == instance {
-            log::debug!("unmounting functionfs {} from {}", instance.to_string_lossy(), mount.dest.display());
-            if let Err(err) = ffs::umount(&mount.dest, false) {
-                log::debug!("unmount failed, trying lazy unmount: {err}");
-                ffs::umount(&mount.dest, true)?;
+            unmount_ffs(instance, &mount.dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmounts a FunctionFS instance and removes its mount point, if it was created by this library.
+fn unmount_ffs(instance: &OsStr, dir: &Path) -> Result<()> {
+    log::debug!("unmounting functionfs {} from {}", instance.to_string_lossy(), dir.display());
+    if let Err(err) = ffs::umount(dir, false) {
+        log::debug!("unmount failed, trying lazy unmount: {err}");
+        ffs::umount(dir, true)?;
+    }
+
+    if dir == default_ffs_dir(instance) {
+        let _ = fs::remove_dir(dir);
+    }
+
+    Ok(())
+}
+
+/// A mounted FunctionFS instance.
+///
+/// Use [`ffs_mounts`] to discover FunctionFS instances, for example ones left behind
+/// by a crashed process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FfsMount {
+    /// FunctionFS instance name.
+    pub instance: OsString,
+    /// Mount point.
+    pub dir: PathBuf,
+    /// Process ids of processes that have the `ep0` file open.
+    ///
+    /// Processes whose file descriptors cannot be inspected due to missing permissions
+    /// are not included.
+    pub ep0_holders: Vec<u32>,
+    /// Whether the file descriptors of some processes could not be inspected,
+    /// usually due to missing permissions.
+    ///
+    /// If set, the instance may be in use by a process not listed in [`ep0_holders`](Self::ep0_holders).
+    pub holders_unknown: bool,
+}
+
+impl FfsMount {
+    /// Whether no process is known or may have the `ep0` file open.
+    pub fn is_orphaned(&self) -> bool {
+        self.ep0_holders.is_empty() && !self.holders_unknown
+    }
+
+    /// Adopts the FunctionFS instance by using it for the custom interface specified by `builder`.
+    ///
+    /// Fails if the instance is not orphaned.
+    pub fn claim(&self, builder: CustomBuilder) -> Result<Custom> {
+        self.check_orphaned()?;
+        builder.existing(&self.dir)
+    }
+
+    /// Unmounts the FunctionFS instance.
+    ///
+    /// The mount point is removed if it was created by this library.
+    /// Fails if the instance is not orphaned.
+    pub fn cleanup(self) -> Result<()> {
+        self.check_orphaned()?;
+        unmount_ffs(&self.instance, &self.dir)
+    }
+
+    fn check_orphaned(&self) -> Result<()> {
+        let instance = self.instance.to_string_lossy();
+        match self.ep0_holders.first() {
+            Some(pid) => {
+                Err(Error::new(ErrorKind::Other, format!("FunctionFS instance {instance} is in use by process {pid}")))
             }
+            None if self.holders_unknown => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("cannot determine whether FunctionFS instance {instance} is in use, since not all processes could be inspected"),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lists all mounted FunctionFS instances.
+///
+/// For each instance the processes having its `ep0` file open are determined by
+/// inspecting `/proc`.
+/// Without sufficient permissions to inspect all processes, instances are not reported
+/// as orphaned and cannot be claimed or cleaned up.
+pub fn ffs_mounts() -> Result<Vec<FfsMount>> {
+    let mut mounts = Vec::new();
+    for mount in MountIter::new()? {
+        let Ok(mount) = mount else { continue };
+        if mount.fstype == ffs::FS_TYPE {
+            mounts.push(FfsMount {
+                instance: mount.source.into(),
+                dir: mount.dest,
+                ep0_holders: Vec::new(),
+                holders_unknown: false,
+            });
+        }
+    }
 
-            if mount.dest == default_ffs_dir(instance) {
-                let _ = fs::remove_dir(&mount.dest);
+    if mounts.is_empty() {
+        return Ok(mounts);
+    }
+
+    let ep0_paths: Vec<_> = mounts.iter().map(|mount| mount.dir.join("ep0")).collect();
+    let mut holders_unknown = false;
+    for proc in fs::read_dir("/proc")? {
+        let Ok(proc) = proc else { continue };
+        let Some(pid) = proc.file_name().to_str().and_then(|pid| pid.parse::<u32>().ok()) else { continue };
+        let fds = match fs::read_dir(proc.path().join("fd")) {
+            Ok(fds) => fds,
+            // The process has exited meanwhile.
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                log::debug!("cannot inspect file descriptors of process {pid}: {err}");
+                holders_unknown = true;
+                continue;
+            }
+        };
+
+        for fd in fds {
+            let Ok(fd) = fd else { continue };
+            let Ok(target) = fs::read_link(fd.path()) else { continue };
+            for (mount, ep0_path) in mounts.iter_mut().zip(&ep0_paths) {
+                if target == *ep0_path && !mount.ep0_holders.contains(&pid) {
+                    mount.ep0_holders.push(pid);
+                }
             }
         }
     }
 
-    Ok(())
+    for mount in &mut mounts {
+        mount.holders_unknown = holders_unknown;
+    }
+
+    Ok(mounts)
 }
 
 /// Custom USB interface, implemented in user code.
 ///
 /// Dropping this causes all endpoint files to be closed.
 /// However, the FunctionFS instance stays mounted until the USB gadget is unregistered.
+///
+/// # Process ownership
+/// Endpoint files are opened with the close-on-exec flag and are owned by the process
+/// that opened them.
+/// After a fork, the endpoints cannot be used from the child process and accessing them
+/// fails with [`ErrorKind::PermissionDenied`].
+/// Use [`dup_fd`](Self::dup_fd) and [`EndpointControl::dup_fd`] to deliberately
+/// share endpoint files with other processes.
 #[derive(Debug)]
 pub struct Custom {
     dir: FunctionDir,
@@ -920,6 +1547,36 @@ pub struct Custom {
     ep_files: Arc<Mutex<Vec<Arc<File>>>>,
     existing_ffs: bool,
     ffs_dir: value::Receiver<PathBuf>,
+    interface_count: usize,
+    max_ctrl_len: Option<u16>,
+    owner: u32,
+    activation: Activation,
+    _ffs_dir_fd: Option<OwnedFd>,
+}
+
+/// Activation state tracked from FunctionFS events.
+#[derive(Debug, Default, Clone, Copy)]
+struct Activation {
+    bound: bool,
+    enabled: bool,
+    suspended: bool,
+}
+
+impl Activation {
+    fn update(&mut self, event_type: u8) {
+        match event_type {
+            ffs::event::BIND => self.bound = true,
+            ffs::event::UNBIND => *self = Self::default(),
+            ffs::event::ENABLE => {
+                self.enabled = true;
+                self.suspended = false;
+            }
+            ffs::event::DISABLE => self.enabled = false,
+            ffs::event::SUSPEND => self.suspended = true,
+            ffs::event::RESUME => self.suspended = false,
+            _ => (),
+        }
+    }
 }
 
 impl Custom {
@@ -937,6 +1594,9 @@ impl Custom {
             ffs_no_disconnect: false,
             ffs_no_init: false,
             ffs_no_mount: false,
+            max_ctrl_len: None,
+            aio: None,
+            os_desc_version: None,
         }
     }
 
@@ -952,11 +1612,48 @@ impl Custom {
         }
     }
 
+    /// Speed negotiated with the USB host, which selects the endpoint descriptor set in use.
+    ///
+    /// See [`EndpointControl::negotiated_speed`] for the mapping of speeds to descriptor sets.
+    /// Use [`EndpointControl::negotiated_speed`] when [`CustomBuilder::existing`] has been
+    /// used to create this object.
+    pub fn negotiated_speed(&self) -> Result<Speed> {
+        match self.status() {
+            Some(status) => status.speed(),
+            None => Err(Error::new(ErrorKind::Unsupported, "registration status not available")),
+        }
+    }
+
     fn ep0(&mut self) -> Result<Arc<File>> {
+        check_owner(self.owner)?;
         let ep0 = self.ep0.get()?;
         ep0.upgrade().ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "USB gadget was removed"))
     }
 
+    /// Activation state of the USB gadget, taking FunctionFS events into account.
+    ///
+    /// The function is considered configured once [`Event::Enable`] has been received,
+    /// which happens when the host selects a configuration containing this function.
+    /// Events are only taken into account once they have been read using
+    /// [`event`](Self::event) or a related method.
+    ///
+    /// If an existing FunctionFS directory is used, the state is determined from events only.
+    pub fn gadget_state(&self) -> Result<GadgetState> {
+        let activation = self.activation;
+        if activation.suspended {
+            return Ok(GadgetState::Suspended);
+        }
+        if activation.enabled {
+            return Ok(GadgetState::Configured);
+        }
+
+        match self.status() {
+            Some(status) => status.gadget_state(),
+            None if activation.bound => Ok(GadgetState::Bound),
+            None => Ok(GadgetState::Registered),
+        }
+    }
+
     /// Returns real address of an interface.
     pub fn real_address(&mut self, intf: u8) -> Result<u8> {
         let ep0 = self.ep0()?;
@@ -964,6 +1661,41 @@ impl Custom {
         Ok(address as u8)
     }
 
+    /// Endpoint addresses (`bEndpointAddress`) assigned by the kernel to the endpoints
+    /// of this function.
+    ///
+    /// The returned addresses are in the order the endpoints were added to the builder
+    /// and correspond to the addresses returned by [`CustomBuilder::endpoint_addresses`].
+    /// They are only available while the function is bound to a UDC.
+    pub fn endpoint_addresses(&mut self) -> Result<Vec<u8>> {
+        let ep_files = self.ep_files.lock().unwrap();
+        let Some((_ep0, ep_files)) = ep_files.split_last() else {
+            return Err(Error::new(ErrorKind::NotConnected, "endpoint files are not open"));
+        };
+        ep_files
+            .iter()
+            .map(|file| {
+                let address = unsafe { ffs::endpoint_revmap(file.as_raw_fd()) }?;
+                Ok(address as u8)
+            })
+            .collect()
+    }
+
+    /// Interface numbers assigned by the kernel to the interfaces of this function.
+    ///
+    /// The returned interface numbers are in the order the interfaces were added to the builder.
+    /// They are only available while the function is active, i.e. the USB gadget is bound
+    /// and the host has selected the configuration containing this function.
+    pub fn interface_numbers(&mut self) -> Result<Vec<u8>> {
+        (0..self.interface_count)
+            .map(|intf| {
+                let intf =
+                    u8::try_from(intf).map_err(|_| Error::new(ErrorKind::InvalidInput, "too many interfaces"))?;
+                self.real_address(intf)
+            })
+            .collect()
+    }
+
     /// Clear previous event if it was forgotten.
     fn clear_prev_event(&mut self) -> Result<()> {
         let mut ep0 = self.ep0()?;
@@ -983,7 +1715,7 @@ impl Custom {
     }
 
     /// Blocking read event.
-    fn read_event(&mut self) -> Result<Event> {
+    fn read_event(&mut self) -> Result<Event<'_>> {
         let mut ep0 = self.ep0()?;
 
         let mut buf = [0; ffs::Event::SIZE];
@@ -992,29 +1724,58 @@ impl Custom {
             return Err(Error::new(ErrorKind::InvalidData, "invalid event size"));
         }
         let raw_event = ffs::Event::parse(&buf)?;
+        self.activation.update(raw_event.event_type);
+
+        if raw_event.event_type == ffs::event::SETUP {
+            let ctrl_req = ffs::CtrlReq::parse(&raw_event.data)?;
+            if self.max_ctrl_len.is_some_and(|max| ctrl_req.length > max) {
+                log::debug!("stalling control request with length {} exceeding maximum", ctrl_req.length);
+                let mut buf = [0; 1];
+                let _ =
+                    if (ctrl_req.request_type & ffs::DIR_IN) != 0 { ep0.read(&mut buf) } else { ep0.write(&buf) };
+                return Ok(Event::SetupTooLarge(ctrl_req));
+            }
+        }
+
         Ok(Event::from_ffs(raw_event, self))
     }
 
     /// Wait for an event for the specified duration.
     fn wait_event_sync(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        self.poll_ep0(PollFlags::POLLIN, timeout)
+    }
+
+    /// Wait for `ep0` to become ready for the specified operations.
+    fn poll_ep0(&mut self, flags: PollFlags, timeout: Option<Duration>) -> Result<bool> {
         let ep0 = self.ep0()?;
 
-        let mut fds = [PollFd::new(ep0.as_fd(), PollFlags::POLLIN)];
-        poll(&mut fds, timeout.map(|d| d.as_millis().try_into().unwrap()).unwrap_or(PollTimeout::NONE))?;
-        Ok(fds[0].revents().map(|e| e.contains(PollFlags::POLLIN)).unwrap_or_default())
+        let mut fds = [PollFd::new(ep0.as_fd(), flags)];
+        poll(
+            &mut fds,
+            timeout.map(|d| d.as_millis().try_into().unwrap_or(PollTimeout::MAX)).unwrap_or(PollTimeout::NONE),
+        )?;
+        Ok(fds[0].revents().map(|e| e.intersects(flags)).unwrap_or_default())
     }
 
-    /// Asynchronously wait for an event to be available.
+    /// Asynchronously wait for `ep0` to become ready for the specified operations.
     #[cfg(feature = "tokio")]
-    pub async fn wait_event(&mut self) -> Result<()> {
-        use tokio::io::{unix::AsyncFd, Interest};
+    async fn ready_ep0(&mut self, interest: tokio::io::Interest) -> Result<Arc<File>> {
+        use tokio::io::unix::AsyncFd;
 
         let ep0 = self.ep0()?;
+        {
+            let async_fd = AsyncFd::with_interest(ep0.as_fd(), interest)?;
+            let mut guard = async_fd.ready(interest).await?;
+            guard.clear_ready();
+        }
 
-        let async_fd = AsyncFd::with_interest(ep0.as_fd(), Interest::READABLE)?;
-        let mut guard = async_fd.readable().await?;
-        guard.clear_ready();
+        Ok(ep0)
+    }
 
+    /// Asynchronously wait for an event to be available.
+    #[cfg(feature = "tokio")]
+    pub async fn wait_event(&mut self) -> Result<()> {
+        self.ready_ep0(tokio::io::Interest::READABLE).await?;
         Ok(())
     }
 
@@ -1026,7 +1787,7 @@ impl Custom {
     /// Wait for an event and returns it.
     ///
     /// Blocks until an event becomes available.
-    pub fn event(&mut self) -> Result<Event> {
+    pub fn event(&mut self) -> Result<Event<'_>> {
         self.clear_prev_event()?;
         self.read_event()
     }
@@ -1034,7 +1795,7 @@ impl Custom {
     /// Wait for an event with a timeout and returns it.
     ///
     /// Blocks until an event becomes available.
-    pub fn event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>> {
+    pub fn event_timeout(&mut self, timeout: Duration) -> Result<Option<Event<'_>>> {
         if self.wait_event_sync(Some(timeout))? {
             Ok(Some(self.read_event()?))
         } else {
@@ -1045,7 +1806,7 @@ impl Custom {
     /// Gets the next event, if available.
     ///
     /// Does not wait for an event to become available.
-    pub fn try_event(&mut self) -> Result<Option<Event>> {
+    pub fn try_event(&mut self) -> Result<Option<Event<'_>>> {
         self.clear_prev_event()?;
 
         if self.has_event() {
@@ -1055,16 +1816,170 @@ impl Custom {
         }
     }
 
+    /// Blocking iterator over events.
+    ///
+    /// Iteration ends after the function has been unbound or removed.
+    pub fn events(&mut self) -> Events<'_> {
+        Events { custom: self, poll_interval: Events::DEFAULT_POLL_INTERVAL, done: false }
+    }
+
     /// File descriptor of endpoint 0.
+    ///
+    /// The file descriptor remains owned by this object and must not be closed.
     pub fn fd(&mut self) -> Result<RawFd> {
         let ep0 = self.ep0()?;
         Ok(ep0.as_raw_fd())
     }
 
+    /// Duplicates the file descriptor of endpoint 0.
+    ///
+    /// The duplicate has the close-on-exec flag set and keeps the endpoint file open
+    /// until it is closed, even if this object is dropped.
+    /// Clear the flag to pass it to another program.
+    pub fn dup_fd(&mut self) -> Result<OwnedFd> {
+        let ep0 = self.ep0()?;
+        ep0.as_fd().try_clone_to_owned()
+    }
+
     /// FunctionFS directory.
     pub fn ffs_dir(&mut self) -> Result<PathBuf> {
         Ok(self.ffs_dir.get()?.clone())
     }
+
+    /// Reinitializes the FunctionFS instance with the interfaces and descriptors
+    /// specified by `builder`, while the USB gadget stays registered.
+    ///
+    /// All endpoint files, including endpoint 0, are closed and the new descriptors
+    /// and strings are written into a newly opened `ep0` file.
+    /// Endpoints of this function become unusable and the endpoints specified
+    /// in `builder` must be used instead.
+    /// Duplicated file descriptors of endpoint 0 must be closed beforehand.
+    ///
+    /// Unless [`CustomBuilder::ffs_no_disconnect`] is set, the kernel unbinds the USB gadget
+    /// when endpoint 0 is closed, in which case the gadget is bound to its UDC again.
+    /// Either way the host must re-enumerate the device to observe the new descriptors.
+    ///
+    /// The FunctionFS settings of `builder`, such as the mount options, are ignored.
+    /// Descriptors reported by [`Gadget`](crate::Gadget) methods remain those of the
+    /// original builder.
+    pub fn reinit(mut self, builder: CustomBuilder) -> Result<Custom> {
+        /// Maximum time for the kernel to reset the FunctionFS instance.
+        const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
+        check_owner(self.owner)?;
+        let ffs_dir = self.ffs_dir()?;
+        let udc = self.status().and_then(|status| status.udc());
+
+        log::debug!("reinitializing functionfs at {}", ffs_dir.display());
+        self.ep_files.lock().unwrap().clear();
+        let ep_files = mem::take(&mut self.ep_files);
+
+        let (ep0_tx, ep0_rx) = value::channel();
+        let (ffs_dir_tx, ffs_dir_rx) = value::channel();
+        let interface_count = builder.interfaces.len();
+        let max_ctrl_len = builder.max_ctrl_len;
+        let func = CustomFunction {
+            builder: CustomBuilder { ffs_dir: Some(ffs_dir), ffs_no_init: false, ..builder },
+            dir: self.dir.clone(),
+            ep0_tx,
+            ep_files: ep_files.clone(),
+            ffs_dir_created: AtomicBool::new(false),
+            ffs_dir_tx,
+            ffs_dir_fd: self._ffs_dir_fd.take(),
+        };
+
+        let start = Instant::now();
+        loop {
+            match func.init() {
+                Ok(()) => break,
+                Err(err) if err.raw_os_error() == Some(libc::EBUSY) && start.elapsed() < RESET_TIMEOUT => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if let Some(udc) = udc {
+            let udc_path = self.dir.gadget_dir()?.join("UDC");
+            if fs::read_to_string(&udc_path)?.trim().is_empty() {
+                log::debug!("rebinding USB gadget to {}", udc.name().to_string_lossy());
+                dry_run::write(&udc_path, udc.name().as_bytes())?;
+            }
+        }
+
+        Ok(Custom {
+            dir: self.dir.clone(),
+            ep0: ep0_rx,
+            setup_event: None,
+            ep_files,
+            existing_ffs: self.existing_ffs,
+            ffs_dir: ffs_dir_rx,
+            interface_count,
+            max_ctrl_len,
+            owner: self.owner,
+            activation: Activation::default(),
+            _ffs_dir_fd: func.ffs_dir_fd,
+        })
+    }
+}
+
+/// Blocking iterator over the events of a custom USB interface.
+///
+/// Obtained by calling [`Custom::events`].
+/// Since an event borrows the interface for answering control requests, this does not
+/// implement [`Iterator`]; instead use `while let Some(event) = events.next_event()`.
+///
+/// Iteration ends after an [`Event::Unbind`] or an error has been returned, or when
+/// the function has been removed.
+#[derive(Debug)]
+pub struct Events<'a> {
+    custom: &'a mut Custom,
+    poll_interval: Duration,
+    done: bool,
+}
+
+impl Events<'_> {
+    /// Default interval for checking whether the function has been removed.
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Sets the interval for checking whether the function has been removed while
+    /// waiting for an event.
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Waits for the next event.
+    ///
+    /// Returns `None` when iteration has ended.
+    pub fn next_event(&mut self) -> Option<Result<Event<'_>>> {
+        if self.done {
+            return None;
+        }
+
+        let res = loop {
+            if let Err(err) = self.custom.clear_prev_event() {
+                break Err(err);
+            }
+
+            match self.custom.wait_event_sync(Some(self.poll_interval)) {
+                Ok(true) => break self.custom.read_event(),
+                Ok(false) => {
+                    if self.custom.status().is_some_and(|status| status.state() == State::Removed) {
+                        self.done = true;
+                        return None;
+                    }
+                }
+                Err(err) => break Err(err),
+            }
+        };
+
+        if matches!(res, Ok(Event::Unbind) | Err(_)) {
+            self.done = true;
+        }
+        Some(res)
+    }
 }
 
 impl Drop for Custom {
@@ -1093,6 +2008,10 @@ pub enum Event<'a> {
     SetupHostToDevice(CtrlReceiver<'a>),
     /// Control request with data from device to host.
     SetupDeviceToHost(CtrlSender<'a>),
+    /// Control request with more data than [`CustomBuilder::max_ctrl_len`].
+    ///
+    /// The request has already been stalled.
+    SetupTooLarge(CtrlReq),
     /// Unknown event.
     Unknown(u8),
 }
@@ -1165,6 +2084,74 @@ impl CtrlSender<'_> {
         Ok(n)
     }
 
+    /// Send the response to the USB host, failing if the data stage does not complete within the timeout.
+    ///
+    /// If the timeout expires, the data stage is aborted, an [`ErrorKind::TimedOut`] error
+    /// containing [`CtrlTimeout`] is returned and the control request is stalled.
+    /// The data stage is aborted by interrupting it using the `SIGURG` signal, which is
+    /// ignored by default; a handler doing nothing is installed unless the process handles it.
+    ///
+    /// Returns the number of bytes sent.
+    pub fn send_timeout(self, data: &[u8], timeout: Duration) -> Result<usize> {
+        let ep0 = self.custom.ep0()?;
+        let Some(n) = interrupt::run(timeout, || (&*ep0).write(data))? else {
+            return Err(CtrlTimeout::error(&self.ctrl_req));
+        };
+
+        self.custom.setup_event = None;
+        Ok(n)
+    }
+
+    /// Asynchronously send the response to the USB host.
+    ///
+    /// Dropping the returned future before `ep0` becomes ready stalls the control request.
+    /// Use [`tokio::time::timeout`] to limit the waiting time.
+    ///
+    /// Returns the number of bytes sent.
+    #[cfg(feature = "tokio")]
+    pub async fn send_async(self, data: &[u8]) -> Result<usize> {
+        let ep0 = self.custom.ready_ep0(tokio::io::Interest::WRITABLE).await?;
+
+        let data = data.to_vec();
+        let n = tokio::task::spawn_blocking(move || (&*ep0).write(&data)).await??;
+
+        self.custom.setup_event = None;
+        Ok(n)
+    }
+
+    /// Send the response to the USB host, reading it from the provided reader.
+    ///
+    /// Up to [`len`](Self::len) bytes are read in chunks of `chunk_size` bytes until the reader
+    /// is exhausted and `progress` is called with the number of bytes read so far and the
+    /// total length after each chunk.
+    /// FunctionFS transfers the data stage of a control request in a single operation,
+    /// thus the response is sent once it has been read completely.
+    ///
+    /// Returns the number of bytes sent.
+    pub fn send_from(
+        self, mut reader: impl Read, chunk_size: usize, mut progress: impl FnMut(usize, usize),
+    ) -> Result<usize> {
+        if chunk_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "chunk size must not be zero"));
+        }
+
+        let total = self.len();
+        let mut data = vec![0; total];
+        let mut pos = 0;
+        while pos < total {
+            let end = (pos + chunk_size).min(total);
+            let n = reader.read(&mut data[pos..end])?;
+            if n == 0 {
+                break;
+            }
+            pos += n;
+            progress(pos, total);
+        }
+        data.truncate(pos);
+
+        self.send(&data)
+    }
+
     /// Stall the endpoint.
     pub fn halt(mut self) -> Result<()> {
         self.do_halt()
@@ -1189,6 +2176,32 @@ impl Drop for CtrlSender<'_> {
     }
 }
 
+/// Error indicating that the data stage of a control request did not complete in time.
+///
+/// This is returned as the inner error of an [`ErrorKind::TimedOut`] I/O error by
+/// [`CtrlSender::send_timeout`], [`CtrlReceiver::recv_timeout`] and
+/// [`CtrlReceiver::recv_all_timeout`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CtrlTimeout {
+    /// The control request that timed out.
+    pub ctrl_req: CtrlReq,
+}
+
+impl CtrlTimeout {
+    fn error(ctrl_req: &CtrlReq) -> Error {
+        Error::new(ErrorKind::TimedOut, Self { ctrl_req: ctrl_req.clone() })
+    }
+}
+
+impl fmt::Display for CtrlTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timeout during data stage of control request {:?}", self.ctrl_req)
+    }
+}
+
+impl std::error::Error for CtrlTimeout {}
+
 /// Receiver for data belonging to USB control request.
 ///
 /// Dropping this stalls the endpoint.
@@ -1238,6 +2251,78 @@ impl CtrlReceiver<'_> {
         Ok(n)
     }
 
+    /// Receive all data from the USB host, failing if the data stage does not complete within the timeout.
+    ///
+    /// See [`recv_timeout`](Self::recv_timeout) for details.
+    pub fn recv_all_timeout(self, timeout: Duration) -> Result<Vec<u8>> {
+        let mut buf = vec![0; self.len()];
+        let n = self.recv_timeout(&mut buf, timeout)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Receive the data from the USB host into the provided buffer, failing if the data stage
+    /// does not complete within the timeout.
+    ///
+    /// If the timeout expires, the data stage is aborted, an [`ErrorKind::TimedOut`] error
+    /// containing [`CtrlTimeout`] is returned and the control request is stalled.
+    /// See [`CtrlSender::send_timeout`] for how the data stage is aborted.
+    ///
+    /// Returns the amount of data received.
+    pub fn recv_timeout(self, data: &mut [u8], timeout: Duration) -> Result<usize> {
+        let ep0 = self.custom.ep0()?;
+        let Some(n) = interrupt::run(timeout, || (&*ep0).read(data))? else {
+            return Err(CtrlTimeout::error(&self.ctrl_req));
+        };
+
+        self.custom.setup_event = None;
+        Ok(n)
+    }
+
+    /// Asynchronously receive all data from the USB host.
+    ///
+    /// Dropping the returned future before `ep0` becomes ready stalls the control request.
+    /// Use [`tokio::time::timeout`] to limit the waiting time.
+    #[cfg(feature = "tokio")]
+    pub async fn recv_all_async(self) -> Result<Vec<u8>> {
+        let ep0 = self.custom.ready_ep0(tokio::io::Interest::READABLE).await?;
+
+        let mut buf = vec![0; self.len()];
+        let buf = tokio::task::spawn_blocking(move || {
+            let n = (&*ep0).read(&mut buf)?;
+            buf.truncate(n);
+            Ok::<_, Error>(buf)
+        })
+        .await??;
+
+        self.custom.setup_event = None;
+        Ok(buf)
+    }
+
+    /// Receive the data from the USB host and write it to the provided writer.
+    ///
+    /// FunctionFS transfers the data stage of a control request in a single operation,
+    /// thus all data is received first.
+    /// It is then written in chunks of `chunk_size` bytes and `progress` is called with the
+    /// number of bytes written so far and the total length after each chunk.
+    ///
+    /// Returns the amount of data received.
+    pub fn recv_to(
+        self, mut writer: impl Write, chunk_size: usize, mut progress: impl FnMut(usize, usize),
+    ) -> Result<usize> {
+        if chunk_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "chunk size must not be zero"));
+        }
+
+        let data = self.recv_all()?;
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            writer.write_all(chunk)?;
+            progress((i * chunk_size + chunk.len()).min(data.len()), data.len());
+        }
+
+        Ok(data.len())
+    }
+
     /// Stall the endpoint.
     pub fn halt(mut self) -> Result<()> {
         self.do_halt()
@@ -1264,31 +2349,91 @@ impl Drop for CtrlReceiver<'_> {
 
 /// Endpoint IO access.
 struct EndpointIo {
+    ep: EndpointFile,
+    aio: aio::Driver,
+}
+
+/// Endpoint file and descriptor information.
+struct EndpointFile {
     path: PathBuf,
     file: Weak<File>,
-    aio: aio::Driver,
+    owner: u32,
+    status: Status,
+    speed_descs: [SpeedEndpointDesc; 3],
+}
+
+/// Values of the endpoint descriptor registered for one speed.
+#[derive(Debug, Clone, Copy)]
+struct SpeedEndpointDesc {
+    speed: Speed,
+    max_packet_size: u16,
+    interval: u8,
+}
+
+impl SpeedEndpointDesc {
+    /// Descriptor values for full, high and super speed.
+    fn for_endpoint(ep: &Endpoint) -> [Self; 3] {
+        [
+            Self { speed: Speed::FullSpeed, max_packet_size: 0, interval: ep.interval },
+            Self {
+                speed: Speed::HighSpeed,
+                max_packet_size: ep.max_packet_size_hs,
+                interval: ep.interval_hs.unwrap_or(ep.interval),
+            },
+            Self {
+                speed: Speed::SuperSpeed,
+                max_packet_size: ep.max_packet_size_ss,
+                interval: ep.interval_ss.unwrap_or(ep.interval),
+            },
+        ]
+    }
 }
 
 impl EndpointIo {
-    fn new(path: PathBuf, queue_len: u32) -> Result<(Self, Arc<File>)> {
-        log::debug!("opening endpoint file {} with queue length {queue_len}", path.display());
-        let file = Arc::new(File::options().read(true).write(true).open(&path)?);
-        let aio = aio::Driver::new(queue_len, Some(path.to_string_lossy().to_string()))?;
-        Ok((Self { path, file: Arc::downgrade(&file), aio }, file))
+    fn new(
+        path: PathBuf, file: File, queue_len: u32, shared: Option<&aio::SharedAio>, status: Status,
+        speed_descs: [SpeedEndpointDesc; 3],
+    ) -> Result<(Self, Arc<File>)> {
+        log::debug!("using endpoint file {} with queue length {queue_len}", path.display());
+        let file = Arc::new(file);
+        let aio = match shared {
+            Some(shared) => aio::Driver::shared(shared, queue_len),
+            None => aio::Driver::new(queue_len, Some(path.to_string_lossy().to_string()))?,
+        };
+        let ep = EndpointFile { path, file: Arc::downgrade(&file), owner: process::id(), status, speed_descs };
+        Ok((Self { ep, aio }, file))
     }
+}
 
+impl EndpointFile {
     fn file(&self) -> Result<Arc<File>> {
+        check_owner(self.owner)?;
         self.file.upgrade().ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "USB gadget was removed"))
     }
 }
 
+/// Ensures that endpoint files are only used by the process that opened them.
+fn check_owner(owner: u32) -> Result<()> {
+    if process::id() == owner {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::PermissionDenied, "endpoint file is owned by another process"))
+    }
+}
+
 impl fmt::Debug for EndpointIo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.ep.fmt(f)
+    }
+}
+
+impl fmt::Debug for EndpointFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.path.display())
     }
 }
 
-impl Drop for EndpointIo {
+impl Drop for EndpointFile {
     fn drop(&mut self) {
         log::debug!("releasing endpoint file {}", self.path.display());
     }
@@ -1299,14 +2444,14 @@ impl Drop for EndpointIo {
 /// All control requests are executed immediately, bypassing the send or receive queue.
 #[derive(Debug)]
 pub struct EndpointControl<'a> {
-    io: &'a EndpointIo,
+    io: &'a EndpointFile,
     direction: Direction,
 }
 
 pub use ffs::{AudioEndpointDesc as RawAudioEndpointDesc, EndpointDesc as RawEndpointDesc};
 
 impl<'a> EndpointControl<'a> {
-    fn new(io: &'a EndpointIo, direction: Direction) -> Self {
+    fn new(io: &'a EndpointFile, direction: Direction) -> Self {
         Self { io, direction }
     }
 
@@ -1372,6 +2517,9 @@ impl<'a> EndpointControl<'a> {
     }
 
     /// Returns the endpoint descriptor in-use.
+    ///
+    /// The kernel selects the descriptor from the set registered for the negotiated speed,
+    /// see [`negotiated_speed`](Self::negotiated_speed).
     pub fn descriptor(&self) -> Result<RawEndpointDesc> {
         let file = self.io.file()?;
         let mut data = [0; ffs::EndpointDesc::AUDIO_SIZE];
@@ -1379,11 +2527,79 @@ impl<'a> EndpointControl<'a> {
         ffs::EndpointDesc::parse(&data)
     }
 
+    /// Speed of the descriptor set the in-use [`descriptor`](Self::descriptor) was taken from.
+    ///
+    /// The descriptor sets map to speeds as follows:
+    ///
+    ///   * full speed descriptors: [`Speed::FullSpeed`] and [`Speed::LowSpeed`],
+    ///   * high speed descriptors: [`Speed::HighSpeed`],
+    ///   * super speed descriptors: [`Speed::SuperSpeed`] and [`Speed::SuperSpeedPlus`].
+    ///
+    /// The speed is read from the UDC the function is bound to.
+    /// If it is not available, for example when the FunctionFS instance was not registered
+    /// by this library, it is derived from the contents of the in-use descriptor.
+    /// Returns [`Speed::Unknown`] if the descriptor matches more than one set.
+    pub fn negotiated_speed(&self) -> Result<Speed> {
+        if let Ok(speed) = self.io.status.speed() {
+            if speed != Speed::Unknown {
+                return Ok(speed);
+            }
+        }
+
+        let desc = self.descriptor()?;
+        let mut matching = self
+            .io
+            .speed_descs
+            .iter()
+            .filter(|sd| sd.max_packet_size == desc.max_packet_size && sd.interval == desc.interval);
+        match (matching.next(), matching.next()) {
+            (Some(sd), None) => Ok(sd.speed),
+            _ => Ok(Speed::Unknown),
+        }
+    }
+
     /// File descriptor of this endpoint.
+    ///
+    /// The file descriptor remains owned by the endpoint and must not be closed.
     pub fn fd(&mut self) -> Result<RawFd> {
         let file = self.io.file()?;
         Ok(file.as_raw_fd())
     }
+
+    /// Duplicates the file descriptor of this endpoint.
+    ///
+    /// The duplicate has the close-on-exec flag set and keeps the endpoint file open
+    /// until it is closed, even if the USB gadget is removed.
+    /// Clear the flag to pass it to another program.
+    pub fn dup_fd(&self) -> Result<OwnedFd> {
+        let file = self.io.file()?;
+        file.as_fd().try_clone_to_owned()
+    }
+}
+
+/// Borrowed data buffer that invokes a callback when dropped.
+struct BorrowedBuf {
+    ptr: *const u8,
+    len: usize,
+    on_complete: Option<Box<dyn FnOnce() + Send>>,
+}
+
+// The caller of `EndpointSender::send_borrowed` guarantees that the buffer stays valid
+// until the completion callback is invoked.
+unsafe impl Send for BorrowedBuf {}
+
+impl AsRef<[u8]> for BorrowedBuf {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for BorrowedBuf {
+    fn drop(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete();
+        }
+    }
 }
 
 /// USB endpoint from device to host sender.
@@ -1392,9 +2608,9 @@ pub struct EndpointSender(value::Receiver<EndpointIo>);
 
 impl EndpointSender {
     /// Gets the endpoint control interface.
-    pub fn control(&mut self) -> Result<EndpointControl> {
+    pub fn control(&mut self) -> Result<EndpointControl<'_>> {
         let io = self.0.get()?;
-        Ok(EndpointControl::new(io, Direction::DeviceToHost))
+        Ok(EndpointControl::new(&io.ep, Direction::DeviceToHost))
     }
 
     /// Maximum packet size.
@@ -1436,6 +2652,9 @@ impl EndpointSender {
     ///
     /// Waits until send space is available.
     /// Also returns errors of previously enqueued send operations.
+    ///
+    /// This method is cancel safe in the sense that no completed send operation is lost,
+    /// but `data` is dropped without being enqueued if the future is dropped before it resolves.
     #[cfg(feature = "tokio")]
     pub async fn send_async(&mut self, data: Bytes) -> Result<()> {
         self.wait_ready().await?;
@@ -1456,14 +2675,65 @@ impl EndpointSender {
     /// Fails if no send space is available.
     /// Also returns errors of previously enqueued send operations.
     pub fn try_send(&mut self, data: Bytes) -> Result<()> {
+        self.try_send_with(data, &SubmitOptions::default())
+    }
+
+    /// Enqueue data held by `owner` for sending without copying it.
+    ///
+    /// Blocks until send space is available.
+    /// `owner` is dropped once the send operation has completed or has been cancelled.
+    /// This allows sending from externally allocated memory, such as memory-mapped frames.
+    pub fn send_owned<T>(&mut self, owner: T) -> Result<()>
+    where
+        T: AsRef<[u8]> + Send + 'static,
+    {
+        self.send(Bytes::from_owner(owner))
+    }
+
+    /// Enqueue borrowed data for sending without copying it.
+    ///
+    /// Blocks until send space is available.
+    /// `on_complete` is called from an arbitrary thread once the send operation has completed
+    /// or has been cancelled, or immediately if enqueueing fails.
+    /// Prefer [`send_owned`](Self::send_owned) if the buffer can be owned.
+    ///
+    /// # Safety
+    /// `data` must remain valid and must not be modified until `on_complete` has been called.
+    pub unsafe fn send_borrowed(
+        &mut self, data: &[u8], on_complete: impl FnOnce() + Send + 'static,
+    ) -> Result<()> {
+        let buf = BorrowedBuf { ptr: data.as_ptr(), len: data.len(), on_complete: Some(Box::new(on_complete)) };
+        self.send(Bytes::from_owner(buf))
+    }
+
+    /// Enqueue data for sending using the specified submit options without waiting for send space.
+    ///
+    /// Fails if no send space is available.
+    /// Also returns errors of previously enqueued send operations.
+    pub fn try_send_with(&mut self, data: Bytes, options: &SubmitOptions) -> Result<()> {
         self.try_ready()?;
 
         let io = self.0.get()?;
-        let file = io.file()?;
-        io.aio.submit(aio::opcode::PWRITE, file.as_raw_fd(), data)?;
+        let file = io.ep.file()?;
+        io.aio.submit_with(aio::opcode::PWRITE, file.as_raw_fd(), data, options)?;
         Ok(())
     }
 
+    /// Enables or disables latency measurement of send operations.
+    ///
+    /// Send operations are recorded into the provided statistics when they are
+    /// retrieved from the completion queue, i.e. when waiting for send space or flushing.
+    /// Pass `None` to disable latency measurement.
+    pub fn set_latency_stats(&mut self, stats: Option<LatencyStats>) -> Result<()> {
+        self.0.get()?.aio.set_latency_stats(stats);
+        Ok(())
+    }
+
+    /// Latency statistics of send operations, if enabled.
+    pub fn latency_stats(&mut self) -> Result<Option<LatencyStats>> {
+        Ok(self.0.get()?.aio.latency_stats().cloned())
+    }
+
     /// Whether send space is available.
     ///
     /// Send space will only become available when [`ready`](Self::ready),
@@ -1485,6 +2755,8 @@ impl EndpointSender {
     /// Asynchronously wait for send space to be available.
     ///
     /// Also returns errors of previously enqueued send operations.
+    ///
+    /// This method is cancel safe.
     #[cfg(feature = "tokio")]
     pub async fn wait_ready(&mut self) -> Result<()> {
         let io = self.0.get()?;
@@ -1557,6 +2829,9 @@ impl EndpointSender {
     /// Waits for all enqueued data to be sent.
     ///
     /// Returns an error if any enqueued send operation has failed.
+    ///
+    /// This method is cancel safe. If the future is dropped before it resolves, data
+    /// that has not been sent yet remains enqueued.
     #[cfg(feature = "tokio")]
     pub async fn flush_async(&mut self) -> Result<()> {
         let io = self.0.get()?;
@@ -1598,13 +2873,16 @@ impl EndpointSender {
 
 /// USB endpoint from host to device receiver.
 #[derive(Debug)]
-pub struct EndpointReceiver(value::Receiver<EndpointIo>);
+pub struct EndpointReceiver {
+    io: value::Receiver<EndpointIo>,
+    pool: Option<BufferPool>,
+}
 
 impl EndpointReceiver {
     /// Gets the endpoint control interface.
-    pub fn control(&mut self) -> Result<EndpointControl> {
-        let io = self.0.get()?;
-        Ok(EndpointControl::new(io, Direction::HostToDevice))
+    pub fn control(&mut self) -> Result<EndpointControl<'_>> {
+        let io = self.io.get()?;
+        Ok(EndpointControl::new(&io.ep, Direction::HostToDevice))
     }
 
     /// Maximum packet size.
@@ -1662,6 +2940,10 @@ impl EndpointReceiver {
     ///
     /// Waits for space in the receive queue and enqueues the buffer for receiving data.
     /// Returns received data, if a buffer in the receive queue was filled.
+    ///
+    /// This method is cancel safe in the sense that no received data is lost, but `buf` is
+    /// dropped without being enqueued if the future is dropped before it resolves.
+    /// Received data remains available for subsequent fetch calls.
     #[cfg(feature = "tokio")]
     pub async fn recv_async(&mut self, buf: BytesMut) -> Result<Option<BytesMut>> {
         let data = if self.is_ready() { self.try_fetch()? } else { self.fetch_async().await? };
@@ -1691,18 +2973,44 @@ impl EndpointReceiver {
     ///
     /// Fails if no receive queue space is available.
     pub fn try_recv(&mut self, buf: BytesMut) -> Result<()> {
-        let io = self.0.get()?;
-        let file = io.file()?;
-        io.aio.submit(aio::opcode::PREAD, file.as_raw_fd(), buf)?;
+        self.try_recv_with(buf, &SubmitOptions::default())
+    }
+
+    /// Enqueue the buffer for receiving using the specified submit options without waiting for
+    /// receive queue space.
+    ///
+    /// The buffer should have been allocated with the desired capacity using
+    /// [`BytesMut::with_capacity`].
+    ///
+    /// Fails if no receive queue space is available.
+    pub fn try_recv_with(&mut self, buf: BytesMut, options: &SubmitOptions) -> Result<()> {
+        let io = self.io.get()?;
+        let file = io.ep.file()?;
+        io.aio.submit_with(aio::opcode::PREAD, file.as_raw_fd(), buf, options)?;
+        Ok(())
+    }
+
+    /// Enables or disables latency measurement of receive operations.
+    ///
+    /// Receive operations are recorded into the provided statistics when they are
+    /// fetched from the completion queue.
+    /// Pass `None` to disable latency measurement.
+    pub fn set_latency_stats(&mut self, stats: Option<LatencyStats>) -> Result<()> {
+        self.io.get()?.aio.set_latency_stats(stats);
         Ok(())
     }
 
+    /// Latency statistics of receive operations, if enabled.
+    pub fn latency_stats(&mut self) -> Result<Option<LatencyStats>> {
+        Ok(self.io.get()?.aio.latency_stats().cloned())
+    }
+
     /// Whether receive queue space is available.
     ///
     /// Receive space will only become available when [`fetch`](Self::fetch),
     /// [`fetch_timeout`](Self::fetch_timeout) or [`try_fetch`](Self::try_fetch) are called.
     pub fn is_ready(&mut self) -> bool {
-        let Ok(io) = self.0.get() else { return false };
+        let Ok(io) = self.io.get() else { return false };
         !io.aio.is_full()
     }
 
@@ -1711,7 +3019,7 @@ impl EndpointReceiver {
     /// The receive queue will only be drained when [`fetch`](Self::fetch),
     /// [`fetch_timeout`](Self::fetch_timeout) or [`try_fetch`](Self::try_fetch) are called.
     pub fn is_empty(&mut self) -> bool {
-        let Ok(io) = self.0.get() else { return true };
+        let Ok(io) = self.io.get() else { return true };
         io.aio.is_empty()
     }
 
@@ -1719,7 +3027,7 @@ impl EndpointReceiver {
     ///
     /// `Ok(None)` is returned if no receive buffers are enqueued.
     pub fn fetch(&mut self) -> Result<Option<BytesMut>> {
-        let io = self.0.get()?;
+        let io = self.io.get()?;
 
         let Some(comp) = io.aio.completed() else {
             return Ok(None);
@@ -1732,9 +3040,12 @@ impl EndpointReceiver {
     /// returns it.
     ///
     /// `Ok(None)` is returned if no receive buffers are enqueued.
+    ///
+    /// This method is cancel safe. If the future is dropped before it resolves, received
+    /// data remains available for subsequent fetch calls.
     #[cfg(feature = "tokio")]
     pub async fn fetch_async(&mut self) -> Result<Option<BytesMut>> {
-        let io = self.0.get()?;
+        let io = self.io.get()?;
 
         let Some(comp) = io.aio.wait_completed().await else {
             return Ok(None);
@@ -1748,7 +3059,7 @@ impl EndpointReceiver {
     ///
     /// `Ok(None)` is returned if no receive buffers are enqueued.
     pub fn fetch_timeout(&mut self, timeout: Duration) -> Result<Option<BytesMut>> {
-        let io = self.0.get()?;
+        let io = self.io.get()?;
 
         let Some(comp) = io.aio.completed_timeout(timeout) else {
             return Ok(None);
@@ -1761,7 +3072,7 @@ impl EndpointReceiver {
     ///
     /// Does not wait for data to be received.
     pub fn try_fetch(&mut self) -> Result<Option<BytesMut>> {
-        let io = self.0.get()?;
+        let io = self.io.get()?;
 
         let Some(comp) = io.aio.try_completed() else { return Ok(None) };
         let data = comp.result()?;
@@ -1770,12 +3081,232 @@ impl EndpointReceiver {
     }
 
     /// Removes all buffers from the receive queue and clears all errors.
+    ///
+    /// If a buffer pool is set, the removed buffers are returned to it.
     pub fn cancel(&mut self) -> Result<()> {
-        let io = self.0.get()?;
+        let io = self.io.get()?;
 
         io.aio.cancel_all();
-        while io.aio.completed().is_some() {}
+        while let Some(comp) = io.aio.completed() {
+            if let (Some(pool), Ok(buf)) = (&self.pool, comp.result()) {
+                if let Ok(buf) = buf.try_into() {
+                    pool.recycle(buf);
+                }
+            }
+        }
 
         Ok(())
     }
+
+    /// Sets the buffer pool used in auto-requeue mode.
+    ///
+    /// When a buffer pool is set, the `*_pooled` methods keep the receive queue filled with
+    /// buffers taken from the pool and return received data as [`PooledBuffer`]s.
+    /// Dropping a pooled buffer returns it to the pool for reuse.
+    ///
+    /// Buffers enqueued by other means are returned as pooled buffers by the `*_pooled` methods
+    /// and buffers from the pool are returned as plain buffers by the other fetch methods.
+    /// Thus, mixing both should be avoided.
+    ///
+    /// Pass `None` to disable auto-requeue mode.
+    pub fn set_buffer_pool(&mut self, pool: Option<BufferPool>) {
+        self.pool = pool;
+    }
+
+    /// The buffer pool used in auto-requeue mode, if any.
+    pub fn buffer_pool(&self) -> Option<&BufferPool> {
+        self.pool.as_ref()
+    }
+
+    /// Fills all available receive queue space with buffers from the pool.
+    fn requeue(&mut self) -> Result<BufferPool> {
+        let Some(pool) = self.pool.clone() else {
+            return Err(Error::new(ErrorKind::InvalidInput, "no buffer pool set"));
+        };
+
+        while self.is_ready() {
+            self.try_recv(pool.take())?;
+        }
+
+        Ok(pool)
+    }
+
+    /// Waits for data to be received into a buffer from the pool, then returns it.
+    ///
+    /// Requires a buffer pool to be set using [`set_buffer_pool`](Self::set_buffer_pool).
+    /// The receive queue is filled with buffers from the pool before waiting.
+    pub fn recv_pooled(&mut self) -> Result<PooledBuffer> {
+        let pool = self.requeue()?;
+        let data = self.fetch()?.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "receive queue is empty"))?;
+        Ok(pool.wrap(data))
+    }
+
+    /// Asynchronously waits for data to be received into a buffer from the pool, then returns it.
+    ///
+    /// Requires a buffer pool to be set using [`set_buffer_pool`](Self::set_buffer_pool).
+    /// The receive queue is filled with buffers from the pool before waiting.
+    ///
+    /// This method is cancel safe. If the future is dropped before it resolves, received
+    /// data remains available for subsequent calls.
+    #[cfg(feature = "tokio")]
+    pub async fn recv_pooled_async(&mut self) -> Result<PooledBuffer> {
+        let pool = self.requeue()?;
+        let data = self
+            .fetch_async()
+            .await?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "receive queue is empty"))?;
+        Ok(pool.wrap(data))
+    }
+
+    /// Waits for data to be received into a buffer from the pool with a timeout, then returns it.
+    ///
+    /// Requires a buffer pool to be set using [`set_buffer_pool`](Self::set_buffer_pool).
+    /// The receive queue is filled with buffers from the pool before waiting.
+    /// `Ok(None)` is returned if the timeout is reached.
+    pub fn recv_pooled_timeout(&mut self, timeout: Duration) -> Result<Option<PooledBuffer>> {
+        let pool = self.requeue()?;
+        Ok(self.fetch_timeout(timeout)?.map(|data| pool.wrap(data)))
+    }
+
+    /// If data has been received into a buffer from the pool, returns it.
+    ///
+    /// Requires a buffer pool to be set using [`set_buffer_pool`](Self::set_buffer_pool).
+    /// The receive queue is filled with buffers from the pool, but no waiting for data occurs.
+    pub fn try_recv_pooled(&mut self) -> Result<Option<PooledBuffer>> {
+        let pool = self.requeue()?;
+        Ok(self.try_fetch()?.map(|data| pool.wrap(data)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::{
+        Association, AudioEndpointGeneral, BorrowedBuf, Custom, Endpoint, EndpointAudio, EndpointDirection,
+        Interface, LockDelayUnits, OsExtCompat, SyncType, TransferType, UsageType, OS_DESC_VERSION_1_00,
+        OS_DESC_VERSION_LEGACY,
+    };
+    use crate::{Class, Speed};
+
+    #[test]
+    fn borrowed_buf() {
+        let data = [1u8, 2, 3];
+        let completed = Arc::new(AtomicBool::new(false));
+        let completed_cb = completed.clone();
+        let buf = BorrowedBuf {
+            ptr: data.as_ptr(),
+            len: data.len(),
+            on_complete: Some(Box::new(move || completed_cb.store(true, Ordering::SeqCst))),
+        };
+
+        let bytes = Bytes::from_owner(buf);
+        let clone = bytes.clone();
+        assert_eq!(&*bytes, &data[..]);
+        drop(bytes);
+        assert!(!completed.load(Ordering::SeqCst));
+        drop(clone);
+        assert!(completed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn interrupt_notification_interval() {
+        for (ms, fs, hs) in
+            [(1, 1, 4), (10, 10, 7), (16, 16, 8), (32, 32, 9), (1000, 255, 13), (u16::MAX, 255, 16)]
+        {
+            let (_, dir) = EndpointDirection::device_to_host();
+            let ep = Endpoint::interrupt_notification(dir, 16, ms);
+            assert_eq!(ep.interval, fs);
+            assert_eq!(ep.interval_hs, Some(hs));
+            assert_eq!(ep.interval_ss, Some(hs));
+            assert_eq!(ep.max_packet_size_hs, 16);
+        }
+    }
+
+    #[test]
+    fn association_adjacency() {
+        let assoc = Association::new(Class::vendor_specific(1, 2), "assoc");
+
+        let adjacent = Custom::builder()
+            .with_interface(Interface::new(Class::vendor_specific(1, 2), "a").with_association(&assoc))
+            .with_interface(Interface::new(Class::vendor_specific(1, 2), "b").with_association(&assoc))
+            .with_interface(Interface::new(Class::vendor_specific(1, 2), "c").with_association(&assoc));
+        assert!(adjacent.ffs_descriptors_and_strings().is_ok());
+
+        let separated = Custom::builder()
+            .with_interface(Interface::new(Class::vendor_specific(1, 2), "a").with_association(&assoc))
+            .with_interface(Interface::new(Class::vendor_specific(1, 2), "b"))
+            .with_interface(Interface::new(Class::vendor_specific(1, 2), "c").with_association(&assoc));
+        assert!(separated.ffs_descriptors_and_strings().is_err());
+    }
+
+    #[test]
+    fn os_desc_version() {
+        let builder = || {
+            Custom::builder().with_interface(
+                Interface::new(Class::vendor_specific(1, 2), "intf").with_os_ext_compat(OsExtCompat::winusb()),
+            )
+        };
+        let (descs, _strings) = builder().ffs_descs().unwrap();
+
+        for version in [OS_DESC_VERSION_1_00, OS_DESC_VERSION_LEGACY] {
+            let (data, _) = builder().with_os_desc_version(version).ffs_descriptors_and_strings().unwrap();
+            assert_eq!(data, descs.encode(version).unwrap());
+        }
+
+        assert!(builder().with_os_desc_version(0x0200).ffs_descriptors_and_strings().is_err());
+    }
+
+    #[test]
+    fn endpoint_addresses() {
+        let intf = || Interface::new(Class::vendor_specific(1, 2), "intf");
+        let ep_in = || Endpoint::bulk(EndpointDirection::device_to_host().1);
+        let ep_out = || Endpoint::bulk(EndpointDirection::host_to_device().1);
+
+        let builder = Custom::builder()
+            .with_interface(intf().with_endpoint(ep_in()).with_endpoint(ep_out().with_address(0x01)))
+            .with_interface(intf().with_endpoint(ep_in().with_address(0x82)).with_endpoint(ep_out()));
+        assert_eq!(builder.endpoint_addresses().unwrap(), [0x83, 0x01, 0x82, 0x04]);
+        assert!(builder.ffs_descriptors_and_strings().is_ok());
+
+        let wrong_direction = Custom::builder().with_interface(intf().with_endpoint(ep_in().with_address(0x01)));
+        assert!(wrong_direction.endpoint_addresses().is_err());
+
+        let invalid_number = Custom::builder().with_interface(intf().with_endpoint(ep_out().with_address(0x10)));
+        assert!(invalid_number.endpoint_addresses().is_err());
+
+        let duplicate = Custom::builder().with_interface(
+            intf().with_endpoint(ep_out().with_address(0x02)).with_endpoint(ep_out().with_address(0x02)),
+        );
+        assert!(duplicate.endpoint_addresses().is_err());
+    }
+
+    #[test]
+    fn audio_endpoint() {
+        let transfer = TransferType::Isochronous { sync: SyncType::Adaptive, usage: UsageType::Data };
+        let mut ep = Endpoint::custom(EndpointDirection::host_to_device().1, transfer).with_audio_general(
+            AudioEndpointGeneral::new()
+                .with_sampling_freq_control(true)
+                .with_max_packets_only(true)
+                .with_lock_delay(LockDelayUnits::Milliseconds, 0x0102),
+        );
+        ep.audio = Some(EndpointAudio { refresh: 0, synch_address: 0 });
+        let builder =
+            Custom::builder().with_interface(Interface::new(Class::AUDIO_STREAMING, "audio").with_endpoint(ep));
+
+        for speed in [Speed::HighSpeed, Speed::SuperSpeed] {
+            let descs = builder.host_descriptors(speed).unwrap();
+            let data = &descs.data[9..];
+            assert_eq!(&data[..2], &[9, 0x05]);
+            let cs = match speed {
+                Speed::SuperSpeed => &data[15..],
+                _ => &data[9..],
+            };
+            assert_eq!(cs, &[7, 0x25, 0x01, 0x81, 0x01, 0x02, 0x01]);
+        }
+    }
 }
@@ -0,0 +1,987 @@
+//! Mass storage class (MSC) bulk-only transport (BOT) implemented in user space.
+//!
+//! This implements the USB mass storage bulk-only transport and a basic set of SCSI
+//! commands on top of a [custom function](super::Custom).
+//! It is useful when the kernel's [mass storage function](crate::function::msd) is not
+//! suitable, for example for virtual or encrypted media.
+//!
+//! Storage is provided by implementing [`BlockDevice`] for each logical unit (LUN).
+//! Errors returned by the block device are reported to the host as SCSI sense data.
+//! Return an I/O error containing [`SenseData`] to report specific sense data.
+
+use bytes::{Bytes, BytesMut};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    time::Duration,
+};
+
+use super::{
+    Custom, CustomBuilder, Direction, Endpoint, EndpointControl, EndpointDirection, EndpointReceiver,
+    EndpointSender, Event, Interface,
+};
+use crate::{function::Handle, Class};
+
+/// Interface class of a SCSI mass storage device using bulk-only transport.
+pub const CLASS: Class = Class::MSC_BOT;
+
+/// Class-specific request: bulk-only mass storage reset.
+pub const REQUEST_RESET: u8 = 0xff;
+
+/// Class-specific request: get maximum LUN.
+pub const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+
+/// Command block wrapper (CBW).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cbw {
+    /// Tag to be returned in the command status wrapper.
+    pub tag: u32,
+    /// Number of bytes the host expects to transfer in the data stage.
+    pub data_transfer_length: u32,
+    /// Direction of the data stage.
+    pub direction: Direction,
+    /// Logical unit number.
+    pub lun: u8,
+    /// Command block.
+    pub cb: Vec<u8>,
+}
+
+impl Cbw {
+    /// Size of a command block wrapper.
+    pub const SIZE: usize = 31;
+
+    /// Signature of a command block wrapper.
+    pub const SIGNATURE: u32 = 0x4342_5355;
+
+    /// Parses and validates a command block wrapper.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() != Self::SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid CBW length"));
+        }
+        if u32::from_le_bytes(data[0..4].try_into().unwrap()) != Self::SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid CBW signature"));
+        }
+
+        let cb_len = usize::from(data[14] & 0x1f);
+        if !(1..=16).contains(&cb_len) || data[13] & 0xf0 != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid CBW command block"));
+        }
+
+        Ok(Self {
+            tag: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            data_transfer_length: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            direction: if data[12] & 0x80 != 0 { Direction::DeviceToHost } else { Direction::HostToDevice },
+            lun: data[13] & 0x0f,
+            cb: data[15..15 + cb_len].to_vec(),
+        })
+    }
+
+    /// Whether the host expects to receive data.
+    pub fn is_data_in(&self) -> bool {
+        self.data_transfer_length > 0 && self.direction == Direction::DeviceToHost
+    }
+
+    /// Whether the host expects to send data.
+    pub fn is_data_out(&self) -> bool {
+        self.data_transfer_length > 0 && self.direction == Direction::HostToDevice
+    }
+}
+
+/// Command status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CswStatus {
+    /// Command passed.
+    Passed = 0,
+    /// Command failed.
+    Failed = 1,
+    /// Phase error.
+    ///
+    /// The host must perform a reset recovery.
+    PhaseError = 2,
+}
+
+/// Command status wrapper (CSW).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Csw {
+    /// Tag of the associated command block wrapper.
+    pub tag: u32,
+    /// Difference between the expected and actually transferred amount of data.
+    pub residue: u32,
+    /// Command status.
+    pub status: CswStatus,
+}
+
+impl Csw {
+    /// Size of a command status wrapper.
+    pub const SIZE: usize = 13;
+
+    /// Signature of a command status wrapper.
+    pub const SIGNATURE: u32 = 0x5342_5355;
+
+    /// Encodes the command status wrapper.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[0..4].copy_from_slice(&Self::SIGNATURE.to_le_bytes());
+        data[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        data[8..12].copy_from_slice(&self.residue.to_le_bytes());
+        data[12] = self.status as u8;
+        data
+    }
+}
+
+/// SCSI sense data.
+///
+/// Return this as the inner error of an I/O error from a [`BlockDevice`] method to
+/// report it to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SenseData {
+    /// Sense key.
+    pub key: u8,
+    /// Additional sense code.
+    pub asc: u8,
+    /// Additional sense code qualifier.
+    pub ascq: u8,
+}
+
+impl SenseData {
+    /// No sense.
+    pub const NO_SENSE: Self = Self::new(0x00, 0x00, 0x00);
+    /// Medium not present.
+    pub const MEDIUM_NOT_PRESENT: Self = Self::new(0x02, 0x3a, 0x00);
+    /// Unrecovered read error.
+    pub const READ_ERROR: Self = Self::new(0x03, 0x11, 0x00);
+    /// Write error.
+    pub const WRITE_ERROR: Self = Self::new(0x03, 0x0c, 0x00);
+    /// Invalid command operation code.
+    pub const INVALID_COMMAND: Self = Self::new(0x05, 0x20, 0x00);
+    /// Logical block address out of range.
+    pub const LBA_OUT_OF_RANGE: Self = Self::new(0x05, 0x21, 0x00);
+    /// Invalid field in command block.
+    pub const INVALID_FIELD_IN_CDB: Self = Self::new(0x05, 0x24, 0x00);
+    /// Logical unit not supported.
+    pub const LUN_NOT_SUPPORTED: Self = Self::new(0x05, 0x25, 0x00);
+    /// Medium removal prevented.
+    pub const MEDIUM_REMOVAL_PREVENTED: Self = Self::new(0x05, 0x53, 0x02);
+    /// Write protected.
+    pub const WRITE_PROTECTED: Self = Self::new(0x07, 0x27, 0x00);
+
+    /// Creates sense data.
+    pub const fn new(key: u8, asc: u8, ascq: u8) -> Self {
+        Self { key, asc, ascq }
+    }
+
+    /// Sense data for an I/O error returned by a block device.
+    fn from_error(err: &Error, write: bool) -> Self {
+        if let Some(sense) = err.get_ref().and_then(|err| err.downcast_ref::<Self>()) {
+            return *sense;
+        }
+
+        match err.kind() {
+            ErrorKind::NotFound => Self::MEDIUM_NOT_PRESENT,
+            ErrorKind::PermissionDenied => Self::WRITE_PROTECTED,
+            ErrorKind::InvalidInput => Self::LBA_OUT_OF_RANGE,
+            ErrorKind::Unsupported => Self::INVALID_FIELD_IN_CDB,
+            _ if write => Self::WRITE_ERROR,
+            _ => Self::READ_ERROR,
+        }
+    }
+
+    /// Encodes the sense data in fixed format.
+    fn to_fixed(self) -> Vec<u8> {
+        vec![0x70, 0, self.key, 0, 0, 0, 0, 10, 0, 0, 0, 0, self.asc, self.ascq, 0, 0, 0, 0]
+    }
+}
+
+impl fmt::Display for SenseData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SCSI sense key 0x{:02x}, ASC 0x{:02x}, ASCQ 0x{:02x}", self.key, self.asc, self.ascq)
+    }
+}
+
+impl std::error::Error for SenseData {}
+
+/// SCSI command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ScsiCommand {
+    /// TEST UNIT READY.
+    TestUnitReady,
+    /// REQUEST SENSE.
+    RequestSense {
+        /// Allocation length.
+        allocation_length: u8,
+    },
+    /// INQUIRY.
+    Inquiry {
+        /// Enable vital product data.
+        evpd: bool,
+        /// Page code.
+        page_code: u8,
+        /// Allocation length.
+        allocation_length: u16,
+    },
+    /// MODE SENSE (6) or MODE SENSE (10).
+    ModeSense {
+        /// Whether the 10-byte variant was used.
+        long: bool,
+        /// Page code.
+        page_code: u8,
+        /// Allocation length.
+        allocation_length: u16,
+    },
+    /// START STOP UNIT.
+    StartStopUnit {
+        /// Start.
+        start: bool,
+        /// Load or eject medium.
+        load_eject: bool,
+    },
+    /// PREVENT ALLOW MEDIUM REMOVAL.
+    PreventAllowMediumRemoval {
+        /// Whether removal is prevented.
+        prevent: bool,
+    },
+    /// READ FORMAT CAPACITIES.
+    ReadFormatCapacities {
+        /// Allocation length.
+        allocation_length: u16,
+    },
+    /// READ CAPACITY (10).
+    ReadCapacity10,
+    /// READ CAPACITY (16).
+    ReadCapacity16 {
+        /// Allocation length.
+        allocation_length: u32,
+    },
+    /// READ (6, 10, 12 or 16).
+    Read {
+        /// Logical block address.
+        lba: u64,
+        /// Number of blocks.
+        blocks: u32,
+    },
+    /// WRITE (6, 10, 12 or 16).
+    Write {
+        /// Logical block address.
+        lba: u64,
+        /// Number of blocks.
+        blocks: u32,
+    },
+    /// VERIFY (10).
+    Verify,
+    /// SYNCHRONIZE CACHE (10 or 16).
+    SynchronizeCache,
+    /// Unsupported command.
+    Unknown {
+        /// Operation code.
+        opcode: u8,
+    },
+}
+
+impl ScsiCommand {
+    /// Parses a SCSI command block.
+    pub fn parse(cb: &[u8]) -> Result<Self> {
+        fn be(cb: &[u8], range: std::ops::Range<usize>) -> Result<u64> {
+            let bytes =
+                cb.get(range).ok_or_else(|| Error::new(ErrorKind::InvalidData, "command block too short"))?;
+            Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+        }
+
+        let Some(&opcode) = cb.first() else {
+            return Err(Error::new(ErrorKind::InvalidData, "empty command block"));
+        };
+
+        Ok(match opcode {
+            0x00 => Self::TestUnitReady,
+            0x03 => Self::RequestSense { allocation_length: be(cb, 4..5)? as u8 },
+            0x12 => Self::Inquiry {
+                evpd: be(cb, 1..2)? & 1 != 0,
+                page_code: be(cb, 2..3)? as u8,
+                allocation_length: be(cb, 3..5)? as u16,
+            },
+            0x1a => Self::ModeSense {
+                long: false,
+                page_code: be(cb, 2..3)? as u8 & 0x3f,
+                allocation_length: be(cb, 4..5)? as u16,
+            },
+            0x5a => Self::ModeSense {
+                long: true,
+                page_code: be(cb, 2..3)? as u8 & 0x3f,
+                allocation_length: be(cb, 7..9)? as u16,
+            },
+            0x1b => {
+                let flags = be(cb, 4..5)?;
+                Self::StartStopUnit { start: flags & 1 != 0, load_eject: flags & 2 != 0 }
+            }
+            0x1e => Self::PreventAllowMediumRemoval { prevent: be(cb, 4..5)? & 1 != 0 },
+            0x23 => Self::ReadFormatCapacities { allocation_length: be(cb, 7..9)? as u16 },
+            0x25 => Self::ReadCapacity10,
+            0x9e if be(cb, 1..2)? & 0x1f == 0x10 => {
+                Self::ReadCapacity16 { allocation_length: be(cb, 10..14)? as u32 }
+            }
+            0x08 | 0x0a => {
+                let lba = be(cb, 1..4)? & 0x1f_ffff;
+                let blocks = match be(cb, 4..5)? {
+                    0 => 256,
+                    n => n as u32,
+                };
+                if opcode == 0x08 {
+                    Self::Read { lba, blocks }
+                } else {
+                    Self::Write { lba, blocks }
+                }
+            }
+            0x28 => Self::Read { lba: be(cb, 2..6)?, blocks: be(cb, 7..9)? as u32 },
+            0x2a => Self::Write { lba: be(cb, 2..6)?, blocks: be(cb, 7..9)? as u32 },
+            0xa8 => Self::Read { lba: be(cb, 2..6)?, blocks: be(cb, 6..10)? as u32 },
+            0xaa => Self::Write { lba: be(cb, 2..6)?, blocks: be(cb, 6..10)? as u32 },
+            0x88 => Self::Read { lba: be(cb, 2..10)?, blocks: be(cb, 10..14)? as u32 },
+            0x8a => Self::Write { lba: be(cb, 2..10)?, blocks: be(cb, 10..14)? as u32 },
+            0x2f => Self::Verify,
+            0x35 | 0x91 => Self::SynchronizeCache,
+            opcode => Self::Unknown { opcode },
+        })
+    }
+}
+
+/// Block storage backing a logical unit (LUN).
+pub trait BlockDevice: Send {
+    /// Size of a block in bytes.
+    fn block_size(&self) -> u32;
+
+    /// Number of blocks.
+    fn block_count(&self) -> u64;
+
+    /// Reads blocks starting at the specified logical block address.
+    ///
+    /// The length of `buf` is a multiple of the block size.
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Writes blocks starting at the specified logical block address.
+    ///
+    /// The length of `data` is a multiple of the block size.
+    fn write(&mut self, lba: u64, data: &[u8]) -> Result<()>;
+
+    /// Flushes written data to the storage medium.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether a medium is present.
+    fn is_present(&self) -> bool {
+        true
+    }
+
+    /// Whether the medium is read-only.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Whether the medium is removable.
+    fn is_removable(&self) -> bool {
+        false
+    }
+
+    /// Ejects the medium on request of the host.
+    fn eject(&mut self) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "eject is not supported"))
+    }
+
+    /// Notifies that the host prevents or allows removal of the medium.
+    fn set_removal_prevented(&mut self, _prevented: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Builder for a mass storage device using bulk-only transport.
+#[non_exhaustive]
+pub struct MscBotBuilder {
+    /// Interface name.
+    pub interface_name: String,
+    /// Vendor identification reported by INQUIRY (up to 8 characters).
+    pub vendor: String,
+    /// Product identification reported by INQUIRY (up to 16 characters).
+    pub product: String,
+    /// Product revision reported by INQUIRY (up to 4 characters).
+    pub revision: String,
+    /// Maximum amount of data transferred per endpoint operation.
+    pub max_transfer: usize,
+    /// Logical units.
+    pub luns: Vec<Box<dyn BlockDevice>>,
+}
+
+impl fmt::Debug for MscBotBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MscBotBuilder")
+            .field("interface_name", &self.interface_name)
+            .field("vendor", &self.vendor)
+            .field("product", &self.product)
+            .field("revision", &self.revision)
+            .field("max_transfer", &self.max_transfer)
+            .field("luns", &self.luns.len())
+            .finish()
+    }
+}
+
+impl MscBotBuilder {
+    /// Adds a logical unit.
+    #[must_use]
+    pub fn with_lun(mut self, device: impl BlockDevice + 'static) -> Self {
+        self.luns.push(Box::new(device));
+        self
+    }
+
+    /// Sets the identification reported by INQUIRY.
+    #[must_use]
+    pub fn with_inquiry(
+        mut self, vendor: impl AsRef<str>, product: impl AsRef<str>, revision: impl AsRef<str>,
+    ) -> Self {
+        self.vendor = vendor.as_ref().to_string();
+        self.product = product.as_ref().to_string();
+        self.revision = revision.as_ref().to_string();
+        self
+    }
+
+    /// Builds the custom function builder and the mass storage device.
+    ///
+    /// Further interfaces may be added to the returned custom function builder.
+    pub fn build_custom(self) -> Result<(CustomBuilder, MscBotPending)> {
+        if self.luns.is_empty() || self.luns.len() > 16 {
+            return Err(Error::new(ErrorKind::InvalidInput, "between 1 and 16 LUNs are required"));
+        }
+        if self.luns.iter().any(|lun| lun.block_size() == 0) {
+            return Err(Error::new(ErrorKind::InvalidInput, "block size must not be zero"));
+        }
+
+        let (ep_in, ep_in_dir) = EndpointDirection::device_to_host();
+        let (ep_out, ep_out_dir) = EndpointDirection::host_to_device();
+        let builder = Custom::builder().with_interface(
+            Interface::new(CLASS, &self.interface_name)
+                .with_endpoint(Endpoint::bulk(ep_in_dir))
+                .with_endpoint(Endpoint::bulk(ep_out_dir)),
+        );
+
+        let luns = self.luns.into_iter().map(|device| Lun { device, sense: SenseData::NO_SENSE }).collect();
+        let inquiry = Inquiry { vendor: self.vendor, product: self.product, revision: self.revision };
+        Ok((builder, MscBotPending { ep_in, ep_out, luns, inquiry, max_transfer: self.max_transfer.max(1) }))
+    }
+
+    /// Builds the mass storage device.
+    ///
+    /// The returned handle must be added to a USB gadget configuration.
+    pub fn build(self) -> Result<(MscBot, Handle)> {
+        let (builder, pending) = self.build_custom()?;
+        let (custom, handle) = builder.build();
+        Ok((pending.with_custom(custom), handle))
+    }
+}
+
+/// Mass storage device whose custom function has not been built yet.
+///
+/// Obtained from [`MscBotBuilder::build_custom`].
+pub struct MscBotPending {
+    ep_in: EndpointSender,
+    ep_out: EndpointReceiver,
+    luns: Vec<Lun>,
+    inquiry: Inquiry,
+    max_transfer: usize,
+}
+
+impl fmt::Debug for MscBotPending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MscBotPending").field("luns", &self.luns.len()).finish()
+    }
+}
+
+impl MscBotPending {
+    /// Completes the mass storage device using the custom function built from the
+    /// builder returned by [`MscBotBuilder::build_custom`].
+    pub fn with_custom(self, custom: Custom) -> MscBot {
+        let Self { ep_in, ep_out, luns, inquiry, max_transfer } = self;
+        MscBot { custom, ep_in, ep_out, luns, inquiry, max_transfer, enabled: false, needs_reset: false }
+    }
+}
+
+#[derive(Debug)]
+struct Inquiry {
+    vendor: String,
+    product: String,
+    revision: String,
+}
+
+struct Lun {
+    device: Box<dyn BlockDevice>,
+    sense: SenseData,
+}
+
+/// Mass storage device using bulk-only transport.
+///
+/// Call [`process`](Self::process) or [`run`](Self::run) to handle requests from the host.
+pub struct MscBot {
+    custom: Custom,
+    ep_in: EndpointSender,
+    ep_out: EndpointReceiver,
+    luns: Vec<Lun>,
+    inquiry: Inquiry,
+    max_transfer: usize,
+    enabled: bool,
+    needs_reset: bool,
+}
+
+impl fmt::Debug for MscBot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MscBot")
+            .field("luns", &self.luns.len())
+            .field("enabled", &self.enabled)
+            .field("needs_reset", &self.needs_reset)
+            .finish()
+    }
+}
+
+impl MscBot {
+    /// Creates a new mass storage device builder.
+    pub fn builder() -> MscBotBuilder {
+        MscBotBuilder {
+            interface_name: "mass storage".to_string(),
+            vendor: "Linux".to_string(),
+            product: "USB gadget".to_string(),
+            revision: "1.0".to_string(),
+            max_transfer: 65_536,
+            luns: Vec::new(),
+        }
+    }
+
+    /// The underlying custom function.
+    pub fn custom(&mut self) -> &mut Custom {
+        &mut self.custom
+    }
+
+    /// Block device of the specified logical unit.
+    pub fn lun(&mut self, lun: u8) -> Option<&mut dyn BlockDevice> {
+        Some(self.luns.get_mut(usize::from(lun))?.device.as_mut())
+    }
+
+    /// Whether the function is enabled by the host.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Handles pending control events and processes at most one command from the host,
+    /// waiting for it with the specified timeout.
+    ///
+    /// Returns whether a command block wrapper was received.
+    pub fn process(&mut self, timeout: Duration) -> Result<bool> {
+        self.handle_events()?;
+        if !self.enabled {
+            std::thread::sleep(timeout.min(Duration::from_millis(100)));
+            return Ok(false);
+        }
+
+        if self.ep_out.is_empty() {
+            let size = self.ep_out.max_packet_size()?.max(Cbw::SIZE);
+            self.ep_out.try_recv(BytesMut::with_capacity(size))?;
+        }
+        let Some(data) = self.ep_out.fetch_timeout(timeout)? else { return Ok(false) };
+
+        match Cbw::parse(&data) {
+            Ok(cbw) if !self.needs_reset => {
+                log::trace!("received {cbw:?}");
+                let csw = self.execute(&cbw)?;
+                log::trace!("sending {csw:?}");
+                self.ep_in.send_and_flush(Bytes::copy_from_slice(&csw.to_bytes()))?;
+            }
+            res => {
+                if let Err(err) = res {
+                    log::debug!("received invalid CBW: {err}");
+                }
+                // Invalid CBW: stall both endpoints until reset recovery.
+                self.needs_reset = true;
+                halt(&self.ep_in.control()?)?;
+                halt(&self.ep_out.control()?)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Processes commands until the function is unbound from the USB device controller.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            if self.handle_events()? {
+                return Ok(());
+            }
+            self.process(Duration::from_millis(100))?;
+        }
+    }
+
+    /// Handles pending control events.
+    ///
+    /// Returns whether the function has been unbound.
+    fn handle_events(&mut self) -> Result<bool> {
+        let mut unbound = false;
+
+        while let Some(event) = self.custom.try_event()? {
+            match event {
+                Event::Enable => {
+                    log::debug!("mass storage function enabled");
+                    self.enabled = true;
+                    self.needs_reset = false;
+                }
+                Event::Disable | Event::Unbind => {
+                    unbound |= matches!(event, Event::Unbind);
+                    self.enabled = false;
+                    self.ep_out.cancel()?;
+                    self.ep_in.cancel()?;
+                }
+                Event::SetupHostToDevice(req) => {
+                    let ctrl_req = req.ctrl_req();
+                    if ctrl_req.request_type == 0x21 && ctrl_req.request == REQUEST_RESET && ctrl_req.length == 0
+                    {
+                        log::debug!("bulk-only mass storage reset");
+                        self.needs_reset = false;
+                        req.recv_all()?;
+                    } else {
+                        req.halt()?;
+                    }
+                }
+                Event::SetupDeviceToHost(req) => {
+                    let ctrl_req = req.ctrl_req();
+                    if ctrl_req.request_type == 0xa1
+                        && ctrl_req.request == REQUEST_GET_MAX_LUN
+                        && ctrl_req.length >= 1
+                    {
+                        req.send(&[(self.luns.len() - 1) as u8])?;
+                    } else {
+                        req.halt()?;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(unbound)
+    }
+
+    /// Executes a command and returns its status.
+    fn execute(&mut self, cbw: &Cbw) -> Result<Csw> {
+        let Some(lun) = self.luns.get_mut(usize::from(cbw.lun)) else {
+            let res = match ScsiCommand::parse(&cbw.cb) {
+                Ok(ScsiCommand::RequestSense { allocation_length }) => {
+                    Ok(truncate(SenseData::LUN_NOT_SUPPORTED.to_fixed(), allocation_length.into()))
+                }
+                _ => Err(SenseData::LUN_NOT_SUPPORTED),
+            };
+            return self.data_in(cbw, res);
+        };
+
+        match ScsiCommand::parse(&cbw.cb) {
+            Ok(ScsiCommand::Read { lba, blocks }) => self.read(cbw, lba, blocks),
+            Ok(ScsiCommand::Write { lba, blocks }) => self.write(cbw, lba, blocks),
+            Ok(cmd) => {
+                let res = command(lun, &self.inquiry, &cmd);
+                self.data_in(cbw, res)
+            }
+            Err(_) => {
+                lun.sense = SenseData::INVALID_COMMAND;
+                self.data_in(cbw, Err(SenseData::INVALID_COMMAND))
+            }
+        }
+    }
+
+    /// Completes a command without data or with data sent to the host.
+    fn data_in(&mut self, cbw: &Cbw, res: std::result::Result<Vec<u8>, SenseData>) -> Result<Csw> {
+        let (data, status) = match res {
+            Ok(data) => (data, CswStatus::Passed),
+            Err(sense) => {
+                if let Some(lun) = self.luns.get_mut(usize::from(cbw.lun)) {
+                    lun.sense = sense;
+                }
+                (Vec::new(), CswStatus::Failed)
+            }
+        };
+
+        let expected = cbw.data_transfer_length as usize;
+        if cbw.is_data_out() {
+            // Host wants to send data that the command does not accept.
+            halt(&self.ep_out.control()?)?;
+            let status = if data.is_empty() { status } else { CswStatus::PhaseError };
+            return Ok(Csw { tag: cbw.tag, residue: cbw.data_transfer_length, status });
+        }
+        if data.len() > expected {
+            // Command has more data than the host expects.
+            if expected > 0 {
+                halt(&self.ep_in.control()?)?;
+            }
+            return Ok(Csw { tag: cbw.tag, residue: cbw.data_transfer_length, status: CswStatus::PhaseError });
+        }
+
+        let len = data.len();
+        if len > 0 {
+            self.ep_in.send_and_flush(data.into())?;
+        }
+        if len < expected && len % self.ep_in.max_packet_size()? == 0 {
+            // No short packet terminates the data stage.
+            halt(&self.ep_in.control()?)?;
+        }
+
+        Ok(Csw { tag: cbw.tag, residue: (expected - len) as u32, status })
+    }
+
+    /// Checks the block range of a READ or WRITE command.
+    fn check_range(
+        &mut self, cbw: &Cbw, lba: u64, blocks: u32, write: bool,
+    ) -> std::result::Result<usize, SenseData> {
+        let device = &self.luns[usize::from(cbw.lun)].device;
+        if !device.is_present() {
+            return Err(SenseData::MEDIUM_NOT_PRESENT);
+        }
+        if write && device.is_read_only() {
+            return Err(SenseData::WRITE_PROTECTED);
+        }
+        if lba.checked_add(blocks.into()).map_or(true, |end| end > device.block_count()) {
+            return Err(SenseData::LBA_OUT_OF_RANGE);
+        }
+        Ok(device.block_size() as usize)
+    }
+
+    /// Executes a READ command.
+    fn read(&mut self, cbw: &Cbw, lba: u64, blocks: u32) -> Result<Csw> {
+        let block_size = match self.check_range(cbw, lba, blocks, false) {
+            Ok(block_size) => block_size,
+            Err(sense) => return self.data_in(cbw, Err(sense)),
+        };
+
+        let expected = cbw.data_transfer_length as usize;
+        let len = blocks as usize * block_size;
+        if len > 0 && (!cbw.is_data_in() || len > expected) {
+            return self.data_in(cbw, Ok(vec![0; len]));
+        }
+
+        let lun = &mut self.luns[usize::from(cbw.lun)];
+        let chunk_blocks = (self.max_transfer / block_size).max(1) as u64;
+        let mut status = CswStatus::Passed;
+        let mut done = 0;
+        while done < u64::from(blocks) {
+            let n = (u64::from(blocks) - done).min(chunk_blocks);
+            let mut buf = vec![0; n as usize * block_size];
+            if let Err(err) = lun.device.read(lba + done, &mut buf) {
+                log::debug!("reading {n} blocks at {} failed: {err}", lba + done);
+                lun.sense = SenseData::from_error(&err, false);
+                status = CswStatus::Failed;
+                break;
+            }
+            self.ep_in.send(buf.into())?;
+            done += n;
+        }
+        self.ep_in.flush()?;
+        if status == CswStatus::Passed {
+            lun.sense = SenseData::NO_SENSE;
+        }
+
+        let sent = done as usize * block_size;
+        if sent < expected && sent % self.ep_in.max_packet_size()? == 0 {
+            halt(&self.ep_in.control()?)?;
+        }
+
+        Ok(Csw { tag: cbw.tag, residue: (expected - sent) as u32, status })
+    }
+
+    /// Executes a WRITE command.
+    fn write(&mut self, cbw: &Cbw, lba: u64, blocks: u32) -> Result<Csw> {
+        let block_size = match self.check_range(cbw, lba, blocks, true) {
+            Ok(block_size) => block_size,
+            Err(sense) => return self.data_in(cbw, Err(sense)),
+        };
+
+        let expected = cbw.data_transfer_length as usize;
+        let len = blocks as usize * block_size;
+        if len > 0 && (!cbw.is_data_out() || len > expected) {
+            // Host does not send the data the command requires.
+            match cbw.direction {
+                Direction::DeviceToHost if expected > 0 => halt(&self.ep_in.control()?)?,
+                Direction::HostToDevice if expected > 0 => halt(&self.ep_out.control()?)?,
+                _ => (),
+            }
+            return Ok(Csw { tag: cbw.tag, residue: cbw.data_transfer_length, status: CswStatus::PhaseError });
+        }
+
+        let lun = &mut self.luns[usize::from(cbw.lun)];
+        let chunk_blocks = (self.max_transfer / block_size).max(1) as u64;
+        let mut status = CswStatus::Passed;
+        let mut received = 0;
+        let mut done = 0;
+        while done < u64::from(blocks) {
+            let n = (u64::from(blocks) - done).min(chunk_blocks);
+            let chunk_len = n as usize * block_size;
+
+            let mut buf = BytesMut::with_capacity(chunk_len);
+            while buf.len() < chunk_len {
+                let data = self.ep_out.recv_and_fetch(BytesMut::with_capacity(chunk_len - buf.len()))?;
+                if data.is_empty() {
+                    break;
+                }
+                received += data.len();
+                buf.extend_from_slice(&data);
+            }
+            if buf.len() < chunk_len {
+                status = CswStatus::PhaseError;
+                break;
+            }
+
+            if let Err(err) = lun.device.write(lba + done, &buf) {
+                log::debug!("writing {n} blocks at {} failed: {err}", lba + done);
+                lun.sense = SenseData::from_error(&err, true);
+                status = CswStatus::Failed;
+                break;
+            }
+            done += n;
+        }
+        if status == CswStatus::Passed {
+            lun.sense = SenseData::NO_SENSE;
+        }
+
+        if received < expected {
+            // Remaining data is not accepted.
+            halt(&self.ep_out.control()?)?;
+        }
+
+        Ok(Csw { tag: cbw.tag, residue: (expected - received) as u32, status })
+    }
+}
+
+/// Executes a command without data from the host and returns the data for the host.
+fn command(lun: &mut Lun, inquiry: &Inquiry, cmd: &ScsiCommand) -> std::result::Result<Vec<u8>, SenseData> {
+    let device = &mut lun.device;
+    let present = || if device.is_present() { Ok(()) } else { Err(SenseData::MEDIUM_NOT_PRESENT) };
+
+    let res = match *cmd {
+        ScsiCommand::TestUnitReady | ScsiCommand::Verify => present().map(|()| Vec::new()),
+        ScsiCommand::RequestSense { allocation_length } => {
+            let sense = std::mem::replace(&mut lun.sense, SenseData::NO_SENSE);
+            return Ok(truncate(sense.to_fixed(), allocation_length.into()));
+        }
+        ScsiCommand::Inquiry { evpd: true, .. } => Err(SenseData::INVALID_FIELD_IN_CDB),
+        ScsiCommand::Inquiry { allocation_length, .. } => {
+            let mut data = vec![0x00, if device.is_removable() { 0x80 } else { 0x00 }, 0x04, 0x02, 31, 0, 0, 0];
+            data.extend(padded(&inquiry.vendor, 8));
+            data.extend(padded(&inquiry.product, 16));
+            data.extend(padded(&inquiry.revision, 4));
+            Ok(truncate(data, allocation_length.into()))
+        }
+        ScsiCommand::ModeSense { long, allocation_length, .. } => {
+            let wp = if device.is_read_only() { 0x80 } else { 0x00 };
+            let data = if long { vec![0, 6, 0, wp, 0, 0, 0, 0] } else { vec![3, 0, wp, 0] };
+            Ok(truncate(data, allocation_length.into()))
+        }
+        ScsiCommand::StartStopUnit { start: false, load_eject: true } => {
+            device.eject().map(|()| Vec::new()).map_err(|err| SenseData::from_error(&err, false))
+        }
+        ScsiCommand::StartStopUnit { .. } => Ok(Vec::new()),
+        ScsiCommand::PreventAllowMediumRemoval { prevent } => device
+            .set_removal_prevented(prevent)
+            .map(|()| Vec::new())
+            .map_err(|err| SenseData::from_error(&err, false)),
+        ScsiCommand::ReadFormatCapacities { allocation_length } => present().map(|()| {
+            let blocks = u32::try_from(device.block_count()).unwrap_or(u32::MAX);
+            let mut data = vec![0, 0, 0, 8];
+            data.extend(blocks.to_be_bytes());
+            data.push(0x02);
+            data.extend(&device.block_size().to_be_bytes()[1..]);
+            truncate(data, allocation_length.into())
+        }),
+        ScsiCommand::ReadCapacity10 => present().map(|()| {
+            let last = u32::try_from(device.block_count().saturating_sub(1)).unwrap_or(u32::MAX);
+            let mut data = last.to_be_bytes().to_vec();
+            data.extend(device.block_size().to_be_bytes());
+            data
+        }),
+        ScsiCommand::ReadCapacity16 { allocation_length } => present().map(|()| {
+            let mut data = device.block_count().saturating_sub(1).to_be_bytes().to_vec();
+            data.extend(device.block_size().to_be_bytes());
+            data.resize(32, 0);
+            truncate(data, allocation_length as usize)
+        }),
+        ScsiCommand::SynchronizeCache => present()
+            .and_then(|()| device.flush().map(|()| Vec::new()).map_err(|err| SenseData::from_error(&err, true))),
+        ScsiCommand::Read { .. } | ScsiCommand::Write { .. } | ScsiCommand::Unknown { .. } => {
+            Err(SenseData::INVALID_COMMAND)
+        }
+    };
+
+    lun.sense = match &res {
+        Ok(_) => SenseData::NO_SENSE,
+        Err(sense) => *sense,
+    };
+    res
+}
+
+/// Truncates data to the allocation length.
+/// Halts an endpoint.
+///
+/// FunctionFS reports a successfully set halt as EBADMSG.
+fn halt(control: &EndpointControl) -> Result<()> {
+    match control.halt() {
+        Err(err) if err.raw_os_error() == Some(libc::EBADMSG) => Ok(()),
+        res => res,
+    }
+}
+
+fn truncate(mut data: Vec<u8>, allocation_length: usize) -> Vec<u8> {
+    data.truncate(allocation_length);
+    data
+}
+
+/// Pads an ASCII string with spaces to the specified length.
+fn padded(s: &str, len: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = s.bytes().filter(|b| b.is_ascii_graphic() || *b == b' ').take(len).collect();
+    data.resize(len, b' ');
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cbw, Csw, CswStatus, Direction, ScsiCommand};
+
+    #[test]
+    fn cbw_csw() {
+        let mut data = vec![0x55, 0x53, 0x42, 0x43, 1, 2, 3, 4, 0, 2, 0, 0, 0x80, 1, 10];
+        data.extend([0x28, 0, 0, 0, 0, 0x10, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]);
+        let cbw = Cbw::parse(&data).unwrap();
+        assert_eq!(cbw.tag, 0x0403_0201);
+        assert_eq!(cbw.data_transfer_length, 512);
+        assert_eq!(cbw.direction, Direction::DeviceToHost);
+        assert_eq!(cbw.lun, 1);
+        assert!(cbw.is_data_in());
+        assert_eq!(ScsiCommand::parse(&cbw.cb).unwrap(), ScsiCommand::Read { lba: 0x10, blocks: 4 });
+
+        assert!(Cbw::parse(&data[..30]).is_err());
+        data[0] = 0;
+        assert!(Cbw::parse(&data).is_err());
+
+        let csw = Csw { tag: cbw.tag, residue: 12, status: CswStatus::Failed };
+        assert_eq!(csw.to_bytes(), [0x55, 0x53, 0x42, 0x53, 1, 2, 3, 4, 12, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn scsi_commands() {
+        assert_eq!(
+            ScsiCommand::parse(&[0x08, 0x01, 0x02, 0x03, 0]).unwrap(),
+            ScsiCommand::Read { lba: 0x010203, blocks: 256 }
+        );
+        assert_eq!(
+            ScsiCommand::parse(&[0x8a, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0]).unwrap(),
+            ScsiCommand::Write { lba: 1 << 32, blocks: 8 }
+        );
+        assert_eq!(
+            ScsiCommand::parse(&[0x9e, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0]).unwrap(),
+            ScsiCommand::ReadCapacity16 { allocation_length: 32 }
+        );
+        assert_eq!(
+            ScsiCommand::parse(&[0x12, 0, 0, 0, 36, 0]).unwrap(),
+            ScsiCommand::Inquiry { evpd: false, page_code: 0, allocation_length: 36 }
+        );
+        assert_eq!(ScsiCommand::parse(&[0xff]).unwrap(), ScsiCommand::Unknown { opcode: 0xff });
+        assert!(ScsiCommand::parse(&[0x28, 0]).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Receive buffer pool.
+
+use bytes::BytesMut;
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// Pool of receive buffers that are recycled after use.
+///
+/// Set it on an [`EndpointReceiver`](super::EndpointReceiver) using
+/// [`set_buffer_pool`](super::EndpointReceiver::set_buffer_pool) to enable auto-requeue mode.
+/// In this mode the receive queue is automatically kept filled with buffers taken from
+/// the pool and received data is returned as [`PooledBuffer`]s, which go back into the pool
+/// when dropped.
+///
+/// Cloning a buffer pool yields a handle to the same pool.
+#[derive(Clone)]
+pub struct BufferPool(Arc<PoolInner>);
+
+struct PoolInner {
+    size: usize,
+    count: usize,
+    alignment: usize,
+    free: Mutex<Vec<BytesMut>>,
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("size", &self.0.size)
+            .field("count", &self.0.count)
+            .field("alignment", &self.0.alignment)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+impl BufferPool {
+    /// Creates a new buffer pool keeping up to `count` buffers of `size` bytes each.
+    pub fn new(size: usize, count: usize) -> Self {
+        Self::aligned(size, count, 1).unwrap()
+    }
+
+    /// Creates a new buffer pool keeping up to `count` buffers of `size` bytes each,
+    /// with the start of each buffer aligned to `alignment` bytes.
+    ///
+    /// The alignment must be a power of two.
+    pub fn aligned(size: usize, count: usize, alignment: usize) -> Result<Self> {
+        if !alignment.is_power_of_two() {
+            return Err(Error::new(ErrorKind::InvalidInput, "buffer alignment must be a power of two"));
+        }
+
+        Ok(Self(Arc::new(PoolInner { size, count, alignment, free: Mutex::new(Vec::with_capacity(count)) })))
+    }
+
+    /// Size of each buffer in bytes.
+    pub fn size(&self) -> usize {
+        self.0.size
+    }
+
+    /// Maximum number of unused buffers kept for reuse.
+    pub fn count(&self) -> usize {
+        self.0.count
+    }
+
+    /// Alignment of the start of each buffer in bytes.
+    pub fn alignment(&self) -> usize {
+        self.0.alignment
+    }
+
+    /// Number of unused buffers currently held by the pool.
+    pub fn available(&self) -> usize {
+        self.0.free.lock().unwrap().len()
+    }
+
+    /// Takes an empty buffer from the pool, allocating a new one if none is available.
+    pub(crate) fn take(&self) -> BytesMut {
+        match self.0.free.lock().unwrap().pop() {
+            Some(buf) => buf,
+            None => self.alloc(),
+        }
+    }
+
+    /// Returns a buffer to the pool.
+    ///
+    /// The buffer is discarded if the pool is full or it does not match the pool's parameters.
+    pub(crate) fn recycle(&self, mut buf: BytesMut) {
+        if buf.capacity() != self.0.size || buf.as_ptr().align_offset(self.0.alignment) != 0 {
+            return;
+        }
+
+        buf.clear();
+
+        let mut free = self.0.free.lock().unwrap();
+        if free.len() < self.0.count {
+            free.push(buf);
+        }
+    }
+
+    /// Wraps a filled buffer taken from this pool.
+    pub(crate) fn wrap(&self, buf: BytesMut) -> PooledBuffer {
+        PooledBuffer { buf: Some(buf), pool: self.clone() }
+    }
+
+    /// Allocates a new aligned buffer.
+    fn alloc(&self) -> BytesMut {
+        let PoolInner { size, alignment, .. } = *self.0;
+
+        let mut buf = BytesMut::with_capacity(size + alignment - 1);
+        let offset = buf.as_ptr().align_offset(alignment);
+        buf.resize(offset, 0);
+        let _ = buf.split_to(offset);
+        let _ = buf.split_off(size);
+        buf
+    }
+}
+
+/// Buffer filled with received data that is returned to its [`BufferPool`] when dropped.
+pub struct PooledBuffer {
+    buf: Option<BytesMut>,
+    pool: BufferPool,
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PooledBuffer").field(&**self).finish()
+    }
+}
+
+impl PooledBuffer {
+    /// Takes the buffer out of the pool.
+    ///
+    /// The pool will allocate a replacement buffer when needed.
+    pub fn into_inner(mut self) -> BytesMut {
+        self.buf.take().unwrap()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &Self::Target {
+        self.buf.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.buf.as_mut().unwrap()
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.recycle(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BufferPool;
+
+    #[test]
+    fn aligned_recycle() {
+        let pool = BufferPool::aligned(512, 2, 64).unwrap();
+
+        let buf = pool.take();
+        assert_eq!(buf.capacity(), 512);
+        assert_eq!(buf.as_ptr().align_offset(64), 0);
+        let ptr = buf.as_ptr();
+
+        let mut filled = pool.wrap(buf);
+        filled.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(&filled[..], &[1, 2, 3]);
+        drop(filled);
+        assert_eq!(pool.available(), 1);
+
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+
+        pool.recycle(buf);
+        pool.recycle(pool.alloc());
+        pool.recycle(pool.alloc());
+        assert_eq!(pool.available(), 2);
+
+        assert!(BufferPool::aligned(512, 2, 3).is_err());
+    }
+}
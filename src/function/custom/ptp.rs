@@ -0,0 +1,619 @@
+//! Still image capture device using the Picture Transfer Protocol (PTP) implemented in user space.
+//!
+//! This provides the interface layout of the USB still image capture device class
+//! (PIMA 15740) on top of a [custom function](super::Custom): a bulk endpoint pair for
+//! operations and an interrupt endpoint for events.
+//!
+//! Class-specific requests from the host are handled by [`Ptp::event`] and reported as
+//! [`PtpEvent`]s.
+//! The application implements the PTP operations by receiving command containers and
+//! sending data and response containers using [`Ptp::recv_container`] and
+//! [`Ptp::send_container`].
+//! [`Container`] and [`ContainerHeader`] provide the framing of PTP containers.
+
+use bytes::{Bytes, BytesMut};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    time::{Duration, Instant},
+};
+
+use super::{
+    CtrlRecipient, CtrlType, Custom, CustomBuilder, Endpoint, EndpointDirection, EndpointReceiver,
+    EndpointSender, Event, Interface,
+};
+use crate::{function::Handle, Class};
+
+/// Interface class of a still image capture device.
+pub const CLASS: Class = Class::STILL_IMAGE_PTP;
+
+/// Class-specific request: cancel the current transaction.
+pub const REQUEST_CANCEL: u8 = 0x64;
+
+/// Class-specific request: get extended event data.
+pub const REQUEST_GET_EXTENDED_EVENT_DATA: u8 = 0x65;
+
+/// Class-specific request: device reset.
+pub const REQUEST_DEVICE_RESET: u8 = 0x66;
+
+/// Class-specific request: get device status.
+pub const REQUEST_GET_DEVICE_STATUS: u8 = 0x67;
+
+/// Cancellation code sent with a cancel request.
+const CANCELLATION_CODE: u16 = 0x4001;
+
+/// Response code: operation completed successfully.
+pub const RESPONSE_OK: u16 = 0x2001;
+
+/// Response code: general error.
+pub const RESPONSE_GENERAL_ERROR: u16 = 0x2002;
+
+/// Response code: operation not supported.
+pub const RESPONSE_OPERATION_NOT_SUPPORTED: u16 = 0x2005;
+
+/// Response code: device busy.
+pub const RESPONSE_DEVICE_BUSY: u16 = 0x2019;
+
+/// Response code: transaction cancelled.
+pub const RESPONSE_TRANSACTION_CANCELLED: u16 = 0x201f;
+
+/// Type of a PTP container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum ContainerType {
+    /// Command block sent by the host.
+    Command = 1,
+    /// Data block.
+    Data = 2,
+    /// Response block sent by the device.
+    Response = 3,
+    /// Event block sent by the device.
+    Event = 4,
+}
+
+impl TryFrom<u16> for ContainerType {
+    type Error = Error;
+
+    fn try_from(value: u16) -> Result<Self> {
+        match value {
+            1 => Ok(Self::Command),
+            2 => Ok(Self::Data),
+            3 => Ok(Self::Response),
+            4 => Ok(Self::Event),
+            _ => Err(Error::new(ErrorKind::InvalidData, "invalid PTP container type")),
+        }
+    }
+}
+
+/// Header of a PTP container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContainerHeader {
+    /// Length of the container in bytes, including the header.
+    pub length: u32,
+    /// Container type.
+    pub container_type: ContainerType,
+    /// Operation, response or event code.
+    pub code: u16,
+    /// Transaction id.
+    pub transaction_id: u32,
+}
+
+impl ContainerHeader {
+    /// Size of a container header.
+    pub const SIZE: usize = 12;
+
+    /// Parses a container header from the start of the specified data.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "PTP container header too short"));
+        }
+
+        let length = u32::from_le_bytes(data[0..4].try_into().unwrap());
+        if (length as usize) < Self::SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid PTP container length"));
+        }
+
+        Ok(Self {
+            length,
+            container_type: u16::from_le_bytes(data[4..6].try_into().unwrap()).try_into()?,
+            code: u16::from_le_bytes(data[6..8].try_into().unwrap()),
+            transaction_id: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+        })
+    }
+
+    /// Length of the payload following the header.
+    pub fn payload_len(&self) -> usize {
+        self.length as usize - Self::SIZE
+    }
+
+    /// Serializes the container header.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[0..4].copy_from_slice(&self.length.to_le_bytes());
+        data[4..6].copy_from_slice(&(self.container_type as u16).to_le_bytes());
+        data[6..8].copy_from_slice(&self.code.to_le_bytes());
+        data[8..12].copy_from_slice(&self.transaction_id.to_le_bytes());
+        data
+    }
+}
+
+/// PTP container consisting of a header and payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    /// Container type.
+    pub container_type: ContainerType,
+    /// Operation, response or event code.
+    pub code: u16,
+    /// Transaction id.
+    pub transaction_id: u32,
+    /// Payload, i.e. parameters or data.
+    pub payload: Bytes,
+}
+
+impl Container {
+    /// Maximum number of parameters of a command or response container.
+    pub const MAX_PARAMS: usize = 5;
+
+    /// Maximum number of parameters of an event container.
+    pub const MAX_EVENT_PARAMS: usize = 3;
+
+    /// Creates a container with parameters as payload.
+    fn with_params(container_type: ContainerType, code: u16, transaction_id: u32, params: &[u32]) -> Self {
+        let payload: Vec<u8> = params.iter().flat_map(|param| param.to_le_bytes()).collect();
+        Self { container_type, code, transaction_id, payload: payload.into() }
+    }
+
+    /// Creates a command container.
+    pub fn command(code: u16, transaction_id: u32, params: &[u32]) -> Self {
+        Self::with_params(ContainerType::Command, code, transaction_id, params)
+    }
+
+    /// Creates a data container.
+    pub fn data(code: u16, transaction_id: u32, data: Bytes) -> Self {
+        Self { container_type: ContainerType::Data, code, transaction_id, payload: data }
+    }
+
+    /// Creates a response container.
+    pub fn response(code: u16, transaction_id: u32, params: &[u32]) -> Self {
+        Self::with_params(ContainerType::Response, code, transaction_id, params)
+    }
+
+    /// Creates an event container.
+    pub fn event(code: u16, transaction_id: u32, params: &[u32]) -> Self {
+        Self::with_params(ContainerType::Event, code, transaction_id, params)
+    }
+
+    /// Parses a complete container.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let header = ContainerHeader::parse(data)?;
+        if header.length as usize != data.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "PTP container length mismatch"));
+        }
+
+        Ok(Self {
+            container_type: header.container_type,
+            code: header.code,
+            transaction_id: header.transaction_id,
+            payload: Bytes::copy_from_slice(&data[ContainerHeader::SIZE..]),
+        })
+    }
+
+    /// Header of the container.
+    pub fn header(&self) -> ContainerHeader {
+        ContainerHeader {
+            length: (ContainerHeader::SIZE + self.payload.len()) as u32,
+            container_type: self.container_type,
+            code: self.code,
+            transaction_id: self.transaction_id,
+        }
+    }
+
+    /// Parameters contained in the payload of a command, response or event container.
+    pub fn params(&self) -> Result<Vec<u32>> {
+        if self.payload.len() % 4 != 0 || self.payload.len() / 4 > Self::MAX_PARAMS {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid PTP container parameters"));
+        }
+        Ok(self.payload.chunks_exact(4).map(|param| u32::from_le_bytes(param.try_into().unwrap())).collect())
+    }
+
+    /// Serializes the container.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut data = BytesMut::with_capacity(ContainerHeader::SIZE + self.payload.len());
+        data.extend_from_slice(&self.header().to_bytes());
+        data.extend_from_slice(&self.payload);
+        data.freeze()
+    }
+}
+
+/// Device status reported in response to a get device status request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceStatus {
+    /// Response code.
+    pub code: u16,
+    /// Parameters, e.g. the endpoint addresses that are halted.
+    pub params: Vec<u32>,
+}
+
+impl Default for DeviceStatus {
+    fn default() -> Self {
+        Self { code: RESPONSE_OK, params: Vec::new() }
+    }
+}
+
+impl DeviceStatus {
+    /// Serializes the device status.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = 4 + 4 * self.params.len();
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(&(len as u16).to_le_bytes());
+        data.extend_from_slice(&self.code.to_le_bytes());
+        for param in &self.params {
+            data.extend_from_slice(&param.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// Event of a PTP function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PtpEvent {
+    /// The function was enabled by the host.
+    Enabled,
+    /// The function was disabled by the host.
+    Disabled,
+    /// The function was unbound from the USB device controller.
+    Unbound,
+    /// The host cancelled a transaction.
+    ///
+    /// The device status is set to busy until it is reset using
+    /// [`Ptp::set_device_status`].
+    Cancel {
+        /// Id of the cancelled transaction.
+        transaction_id: u32,
+    },
+    /// The host requested a device reset.
+    ///
+    /// Pending transfers have been cancelled and the session should be closed.
+    DeviceReset,
+}
+
+/// Builder for a PTP function.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PtpBuilder {
+    /// Interface name.
+    pub interface_name: String,
+    /// Maximum packet size of the event endpoint.
+    pub event_max_packet_size: u16,
+    /// Polling interval of the event endpoint in milliseconds.
+    pub event_interval_ms: u16,
+    /// Size of the buffer used for receiving a transfer from the host.
+    pub recv_buffer_size: usize,
+    /// Maximum length of a container received from the host in bytes.
+    ///
+    /// Longer containers are rejected by [`Ptp::recv_container`].
+    pub max_container_size: u32,
+}
+
+impl PtpBuilder {
+    /// Sets the polling interval of the event endpoint in milliseconds.
+    #[must_use]
+    pub fn with_event_interval_ms(mut self, event_interval_ms: u16) -> Self {
+        self.event_interval_ms = event_interval_ms;
+        self
+    }
+
+    /// Sets the maximum length of a container received from the host in bytes.
+    #[must_use]
+    pub fn with_max_container_size(mut self, max_container_size: u32) -> Self {
+        self.max_container_size = max_container_size;
+        self
+    }
+
+    /// Builds the custom function builder and the PTP function.
+    ///
+    /// Further interfaces may be added to the returned custom function builder.
+    pub fn build_custom(self) -> (CustomBuilder, PtpPending) {
+        let (ep_in, ep_in_dir) = EndpointDirection::device_to_host();
+        let (ep_out, ep_out_dir) = EndpointDirection::host_to_device();
+        let (ep_event, ep_event_dir) = EndpointDirection::device_to_host();
+
+        let builder = Custom::builder().with_interface(
+            Interface::new(CLASS, &self.interface_name)
+                .with_endpoint(Endpoint::bulk(ep_in_dir))
+                .with_endpoint(Endpoint::bulk(ep_out_dir))
+                .with_endpoint(Endpoint::interrupt_notification(
+                    ep_event_dir,
+                    self.event_max_packet_size,
+                    self.event_interval_ms,
+                )),
+        );
+
+        let pending = PtpPending {
+            ep_in,
+            ep_out,
+            ep_event,
+            recv_buffer_size: self.recv_buffer_size.max(512),
+            max_container_size: self.max_container_size,
+        };
+        (builder, pending)
+    }
+
+    /// Builds the PTP function.
+    ///
+    /// The returned handle must be added to a USB gadget configuration.
+    pub fn build(self) -> (Ptp, Handle) {
+        let (builder, pending) = self.build_custom();
+        let (custom, handle) = builder.build();
+        (pending.with_custom(custom), handle)
+    }
+}
+
+/// PTP function whose custom function has not been built yet.
+///
+/// Obtained from [`PtpBuilder::build_custom`].
+pub struct PtpPending {
+    ep_in: EndpointSender,
+    ep_out: EndpointReceiver,
+    ep_event: EndpointSender,
+    recv_buffer_size: usize,
+    max_container_size: u32,
+}
+
+impl fmt::Debug for PtpPending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PtpPending").field("recv_buffer_size", &self.recv_buffer_size).finish()
+    }
+}
+
+impl PtpPending {
+    /// Completes the PTP function using the custom function built from the
+    /// builder returned by [`PtpBuilder::build_custom`].
+    pub fn with_custom(self, custom: Custom) -> Ptp {
+        let Self { ep_in, ep_out, ep_event, recv_buffer_size, max_container_size } = self;
+        Ptp {
+            custom,
+            ep_in,
+            ep_out,
+            ep_event,
+            recv_buffer_size,
+            max_container_size,
+            device_status: DeviceStatus::default(),
+            enabled: false,
+        }
+    }
+}
+
+/// Still image capture device using the Picture Transfer Protocol implemented in user space.
+///
+/// Call [`event`](Self::event) regularly to handle requests from the host.
+pub struct Ptp {
+    custom: Custom,
+    ep_in: EndpointSender,
+    ep_out: EndpointReceiver,
+    ep_event: EndpointSender,
+    recv_buffer_size: usize,
+    max_container_size: u32,
+    device_status: DeviceStatus,
+    enabled: bool,
+}
+
+impl fmt::Debug for Ptp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ptp").field("device_status", &self.device_status).field("enabled", &self.enabled).finish()
+    }
+}
+
+impl Ptp {
+    /// Creates a new PTP function builder.
+    pub fn builder() -> PtpBuilder {
+        PtpBuilder {
+            interface_name: "PTP".to_string(),
+            event_max_packet_size: 64,
+            event_interval_ms: 8,
+            recv_buffer_size: 65_536,
+            max_container_size: 64 * 1024 * 1024,
+        }
+    }
+
+    /// The underlying custom function.
+    pub fn custom(&mut self) -> &mut Custom {
+        &mut self.custom
+    }
+
+    /// Bulk endpoints for sending data to and receiving data from the host.
+    pub fn data(&mut self) -> (&mut EndpointSender, &mut EndpointReceiver) {
+        (&mut self.ep_in, &mut self.ep_out)
+    }
+
+    /// Whether the function is enabled by the host.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Device status reported to the host.
+    pub fn device_status(&self) -> &DeviceStatus {
+        &self.device_status
+    }
+
+    /// Sets the device status reported to the host.
+    pub fn set_device_status(&mut self, device_status: DeviceStatus) {
+        self.device_status = device_status;
+    }
+
+    /// Sends a container to the host using the bulk IN endpoint.
+    ///
+    /// A zero-length packet is appended if the container ends on a packet boundary.
+    pub fn send_container(&mut self, container: &Container) -> Result<()> {
+        let data = container.to_bytes();
+        let max_packet_size = self.ep_in.max_packet_size()?;
+        let zlp = max_packet_size != 0 && data.len() % max_packet_size == 0;
+
+        self.ep_in.send(data)?;
+        if zlp {
+            self.ep_in.send(Bytes::new())?;
+        }
+        self.ep_in.flush()
+    }
+
+    /// Sends a response container to the host.
+    pub fn send_response(&mut self, code: u16, transaction_id: u32, params: &[u32]) -> Result<()> {
+        if params.len() > Container::MAX_PARAMS {
+            return Err(Error::new(ErrorKind::InvalidInput, "too many PTP response parameters"));
+        }
+        self.send_container(&Container::response(code, transaction_id, params))
+    }
+
+    /// Sends an event container to the host using the interrupt endpoint.
+    pub fn send_event(&mut self, code: u16, transaction_id: u32, params: &[u32]) -> Result<()> {
+        if params.len() > Container::MAX_EVENT_PARAMS {
+            return Err(Error::new(ErrorKind::InvalidInput, "too many PTP event parameters"));
+        }
+        self.ep_event.send_and_flush(Container::event(code, transaction_id, params).to_bytes())
+    }
+
+    /// Receives a container from the host using the bulk OUT endpoint with the specified timeout.
+    ///
+    /// Data containers spanning multiple transfers are reassembled within the timeout.
+    /// Containers longer than [`PtpBuilder::max_container_size`] are rejected.
+    pub fn recv_container(&mut self, timeout: Duration) -> Result<Option<Container>> {
+        let deadline = Instant::now() + timeout;
+
+        if self.ep_out.is_empty() {
+            self.ep_out.try_recv(BytesMut::with_capacity(self.recv_buffer_size))?;
+        }
+        let Some(mut data) = self.ep_out.fetch_timeout(timeout)? else { return Ok(None) };
+
+        let header = ContainerHeader::parse(&data)?;
+        if header.length > self.max_container_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("PTP container of {} bytes exceeds maximum size", header.length),
+            ));
+        }
+
+        while data.len() < header.length as usize {
+            self.ep_out.try_recv(BytesMut::with_capacity(self.recv_buffer_size))?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Some(buf) = self.ep_out.fetch_timeout(remaining)? else {
+                self.ep_out.cancel()?;
+                return Err(Error::new(ErrorKind::TimedOut, "timeout receiving PTP container"));
+            };
+            if buf.is_empty() {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "PTP container truncated"));
+            }
+            data.extend_from_slice(&buf);
+        }
+
+        Container::parse(&data).map(Some)
+    }
+
+    /// Waits for the next event with the specified timeout.
+    ///
+    /// Class-specific requests are answered and unsupported requests are stalled.
+    pub fn event(&mut self, timeout: Duration) -> Result<Option<PtpEvent>> {
+        let Some(event) = self.custom.event_timeout(timeout)? else { return Ok(None) };
+
+        let ptp_event = match event {
+            Event::Enable => {
+                self.enabled = true;
+                Some(PtpEvent::Enabled)
+            }
+            Event::Disable => {
+                self.enabled = false;
+                Some(PtpEvent::Disabled)
+            }
+            Event::Unbind => {
+                self.enabled = false;
+                Some(PtpEvent::Unbound)
+            }
+            Event::SetupHostToDevice(req) => {
+                let ctrl_req = req.ctrl_req().clone();
+                if ctrl_req.ctrl_type() != CtrlType::Class || ctrl_req.recipient() != CtrlRecipient::Interface {
+                    req.halt()?;
+                    return Ok(None);
+                }
+
+                match ctrl_req.request {
+                    REQUEST_CANCEL if req.len() == 6 => {
+                        let data = req.recv_all()?;
+                        if u16::from_le_bytes([data[0], data[1]]) == CANCELLATION_CODE {
+                            let transaction_id = u32::from_le_bytes(data[2..6].try_into().unwrap());
+                            self.device_status = DeviceStatus { code: RESPONSE_DEVICE_BUSY, params: Vec::new() };
+                            self.ep_in.cancel()?;
+                            self.ep_out.cancel()?;
+                            Some(PtpEvent::Cancel { transaction_id })
+                        } else {
+                            log::warn!("ignoring cancel request with invalid cancellation code");
+                            None
+                        }
+                    }
+                    REQUEST_DEVICE_RESET if req.is_empty() => {
+                        req.recv_all()?;
+                        self.device_status = DeviceStatus::default();
+                        self.ep_in.cancel()?;
+                        self.ep_out.cancel()?;
+                        Some(PtpEvent::DeviceReset)
+                    }
+                    _ => {
+                        req.halt()?;
+                        None
+                    }
+                }
+            }
+            Event::SetupDeviceToHost(req) => {
+                let ctrl_req = req.ctrl_req();
+                if ctrl_req.ctrl_type() == CtrlType::Class
+                    && ctrl_req.recipient() == CtrlRecipient::Interface
+                    && ctrl_req.request == REQUEST_GET_DEVICE_STATUS
+                {
+                    let data = self.device_status.to_bytes();
+                    let len = req.len().min(data.len());
+                    req.send(&data[..len])?;
+                } else {
+                    req.halt()?;
+                }
+                None
+            }
+            _ => None,
+        };
+
+        Ok(ptp_event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{Container, ContainerHeader, ContainerType, DeviceStatus, RESPONSE_DEVICE_BUSY};
+
+    #[test]
+    fn containers() {
+        let open_session = Container::command(0x1002, 0, &[1]);
+        let data = open_session.to_bytes();
+        assert_eq!(&data[..], [16, 0, 0, 0, 1, 0, 0x02, 0x10, 0, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(Container::parse(&data).unwrap(), open_session);
+        assert_eq!(open_session.params().unwrap(), [1]);
+
+        let header = ContainerHeader::parse(&data[..ContainerHeader::SIZE]).unwrap();
+        assert_eq!(header.container_type, ContainerType::Command);
+        assert_eq!(header.payload_len(), 4);
+
+        let data_container = Container::data(0x1009, 7, Bytes::from_static(b"image"));
+        assert_eq!(Container::parse(&data_container.to_bytes()).unwrap(), data_container);
+
+        assert!(Container::parse(&data[..15]).is_err());
+        assert!(ContainerHeader::parse(&[11, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(ContainerHeader::parse(&[12, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(Container::data(0x1009, 7, Bytes::from_static(b"odd")).params().is_err());
+    }
+
+    #[test]
+    fn device_status() {
+        assert_eq!(DeviceStatus::default().to_bytes(), [4, 0, 0x01, 0x20]);
+        let busy = DeviceStatus { code: RESPONSE_DEVICE_BUSY, params: vec![0x81] };
+        assert_eq!(busy.to_bytes(), [8, 0, 0x19, 0x20, 0x81, 0, 0, 0]);
+    }
+}
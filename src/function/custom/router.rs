@@ -0,0 +1,299 @@
+//! Routing of control requests to handlers.
+
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+};
+
+use super::{CtrlReq, Event};
+
+/// Recipient of a control request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CtrlRecipient {
+    /// Device.
+    Device,
+    /// Interface.
+    Interface,
+    /// Endpoint.
+    Endpoint,
+    /// Other.
+    Other,
+    /// Reserved recipient value.
+    Reserved(u8),
+}
+
+/// Type of a control request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CtrlType {
+    /// Standard request defined by the USB specification.
+    Standard,
+    /// Class-specific request.
+    Class,
+    /// Vendor-specific request.
+    Vendor,
+    /// Reserved type.
+    Reserved,
+}
+
+impl CtrlReq {
+    /// Recipient of the request.
+    pub fn recipient(&self) -> CtrlRecipient {
+        match self.request_type & 0x1f {
+            0 => CtrlRecipient::Device,
+            1 => CtrlRecipient::Interface,
+            2 => CtrlRecipient::Endpoint,
+            3 => CtrlRecipient::Other,
+            other => CtrlRecipient::Reserved(other),
+        }
+    }
+
+    /// Type of the request.
+    pub fn ctrl_type(&self) -> CtrlType {
+        match (self.request_type >> 5) & 0b11 {
+            0 => CtrlType::Standard,
+            1 => CtrlType::Class,
+            2 => CtrlType::Vendor,
+            _ => CtrlType::Reserved,
+        }
+    }
+}
+
+/// Filter matching control requests.
+///
+/// Unspecified fields match any value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct CtrlFilter {
+    /// Recipient.
+    pub recipient: Option<CtrlRecipient>,
+    /// Request type.
+    pub ctrl_type: Option<CtrlType>,
+    /// Request code.
+    pub request: Option<u8>,
+    /// Index, i.e. interface number or endpoint address.
+    pub index: Option<u16>,
+}
+
+impl CtrlFilter {
+    /// Filter matching all requests.
+    pub const fn any() -> Self {
+        Self { recipient: None, ctrl_type: None, request: None, index: None }
+    }
+
+    /// Filter matching the specified recipient, type and request code.
+    pub const fn new(recipient: CtrlRecipient, ctrl_type: CtrlType, request: u8) -> Self {
+        Self { recipient: Some(recipient), ctrl_type: Some(ctrl_type), request: Some(request), index: None }
+    }
+
+    /// Additionally match the index.
+    #[must_use]
+    pub const fn with_index(mut self, index: u16) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Whether the control request matches this filter.
+    pub fn matches(&self, ctrl_req: &CtrlReq) -> bool {
+        self.recipient.map_or(true, |r| r == ctrl_req.recipient())
+            && self.ctrl_type.map_or(true, |t| t == ctrl_req.ctrl_type())
+            && self.request.map_or(true, |r| r == ctrl_req.request)
+            && self.index.map_or(true, |i| i == ctrl_req.index)
+    }
+}
+
+type InHandler = Box<dyn FnMut(&CtrlReq) -> Result<Vec<u8>> + Send>;
+type OutHandler = Box<dyn FnMut(&CtrlReq, &[u8]) -> Result<()> + Send>;
+
+enum Handler {
+    DeviceToHost(InHandler),
+    HostToDevice(OutHandler),
+}
+
+struct Route {
+    filter: CtrlFilter,
+    handler: Handler,
+}
+
+/// Standard request `GET_STATUS`.
+const GET_STATUS: u8 = 0x00;
+/// Standard request `CLEAR_FEATURE`.
+const CLEAR_FEATURE: u8 = 0x01;
+/// Standard request `SET_FEATURE`.
+const SET_FEATURE: u8 = 0x03;
+
+/// Router dispatching control requests of a [`Custom`](super::Custom) function to handlers.
+///
+/// Handlers are tried in the order they were added and the first one with a
+/// matching filter and data direction handles the request.
+/// If a handler returns an error, the request is stalled.
+/// Requests not handled by any handler are stalled, except for standard requests
+/// to interfaces that the kernel forwards to the function, which are acknowledged
+/// unless [disabled](Self::with_standard_defaults).
+///
+/// For requests with data from the host, the data stage and thus the status stage
+/// complete before the handler is invoked.
+/// Such requests can only be stalled by their handler if they carry no data.
+pub struct SetupRouter {
+    routes: Vec<Route>,
+    standard_defaults: bool,
+}
+
+impl fmt::Debug for SetupRouter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SetupRouter")
+            .field("filters", &self.routes.iter().map(|route| route.filter).collect::<Vec<_>>())
+            .field("standard_defaults", &self.standard_defaults)
+            .finish()
+    }
+}
+
+impl Default for SetupRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SetupRouter {
+    /// Creates a new router without handlers.
+    pub fn new() -> Self {
+        Self { routes: Vec::new(), standard_defaults: true }
+    }
+
+    /// Adds a handler for requests with data from device to host.
+    ///
+    /// The handler returns the response, which is truncated to the length requested by the host.
+    #[must_use]
+    pub fn with_device_to_host(
+        mut self, filter: CtrlFilter, handler: impl FnMut(&CtrlReq) -> Result<Vec<u8>> + Send + 'static,
+    ) -> Self {
+        self.routes.push(Route { filter, handler: Handler::DeviceToHost(Box::new(handler)) });
+        self
+    }
+
+    /// Adds a handler for requests with data from host to device.
+    ///
+    /// The handler receives the data sent by the host.
+    #[must_use]
+    pub fn with_host_to_device(
+        mut self, filter: CtrlFilter, handler: impl FnMut(&CtrlReq, &[u8]) -> Result<()> + Send + 'static,
+    ) -> Self {
+        self.routes.push(Route { filter, handler: Handler::HostToDevice(Box::new(handler)) });
+        self
+    }
+
+    /// Sets whether unhandled standard `GET_STATUS`, `SET_FEATURE` and `CLEAR_FEATURE`
+    /// requests to interfaces are acknowledged.
+    ///
+    /// Enabled by default.
+    #[must_use]
+    pub fn with_standard_defaults(mut self, standard_defaults: bool) -> Self {
+        self.standard_defaults = standard_defaults;
+        self
+    }
+
+    /// Handles the event if it is a control request.
+    ///
+    /// Returns all other events for processing by the caller.
+    /// An error is returned if communication with the host fails or a handler fails
+    /// after the status stage of its request has completed.
+    pub fn handle<'a>(&mut self, event: Event<'a>) -> Result<Option<Event<'a>>> {
+        match event {
+            Event::SetupDeviceToHost(sender) => {
+                let ctrl_req = sender.ctrl_req().clone();
+                let res = match self.find_device_to_host(&ctrl_req) {
+                    Some(handler) => handler(&ctrl_req),
+                    None => self.default_device_to_host(&ctrl_req),
+                };
+                match res {
+                    Ok(mut data) => {
+                        data.truncate(sender.len());
+                        sender.send(&data)?;
+                    }
+                    Err(err) => {
+                        log::debug!("stalling control request {ctrl_req:?}: {err}");
+                        sender.halt()?;
+                    }
+                }
+                Ok(None)
+            }
+            Event::SetupHostToDevice(receiver) => {
+                let ctrl_req = receiver.ctrl_req().clone();
+                let standard_defaults = self.standard_defaults;
+                match self.find_host_to_device(&ctrl_req) {
+                    Some(handler) if receiver.is_empty() => match handler(&ctrl_req, &[]) {
+                        Ok(()) => {
+                            receiver.recv_all()?;
+                        }
+                        Err(err) => {
+                            log::debug!("stalling control request {ctrl_req:?}: {err}");
+                            receiver.halt()?;
+                        }
+                    },
+                    Some(handler) => {
+                        let data = receiver.recv_all()?;
+                        handler(&ctrl_req, &data)?;
+                    }
+                    None if standard_defaults && is_standard_interface_feature(&ctrl_req) => {
+                        receiver.recv_all()?;
+                    }
+                    None => {
+                        log::debug!("stalling unhandled control request {ctrl_req:?}");
+                        receiver.halt()?;
+                    }
+                }
+                Ok(None)
+            }
+            other => Ok(Some(other)),
+        }
+    }
+
+    fn find_device_to_host(&mut self, ctrl_req: &CtrlReq) -> Option<&mut InHandler> {
+        self.routes.iter_mut().find_map(|route| match &mut route.handler {
+            Handler::DeviceToHost(handler) if route.filter.matches(ctrl_req) => Some(handler),
+            _ => None,
+        })
+    }
+
+    fn find_host_to_device(&mut self, ctrl_req: &CtrlReq) -> Option<&mut OutHandler> {
+        self.routes.iter_mut().find_map(|route| match &mut route.handler {
+            Handler::HostToDevice(handler) if route.filter.matches(ctrl_req) => Some(handler),
+            _ => None,
+        })
+    }
+
+    fn default_device_to_host(&self, ctrl_req: &CtrlReq) -> Result<Vec<u8>> {
+        if self.standard_defaults
+            && ctrl_req.ctrl_type() == CtrlType::Standard
+            && ctrl_req.recipient() == CtrlRecipient::Interface
+            && ctrl_req.request == GET_STATUS
+        {
+            return Ok(vec![0, 0]);
+        }
+        Err(Error::new(ErrorKind::Unsupported, "unhandled control request"))
+    }
+}
+
+/// Whether the request is a standard `SET_FEATURE` or `CLEAR_FEATURE` request to an interface.
+fn is_standard_interface_feature(ctrl_req: &CtrlReq) -> bool {
+    ctrl_req.ctrl_type() == CtrlType::Standard
+        && ctrl_req.recipient() == CtrlRecipient::Interface
+        && matches!(ctrl_req.request, SET_FEATURE | CLEAR_FEATURE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CtrlFilter, CtrlRecipient, CtrlReq, CtrlType};
+
+    #[test]
+    fn filter() {
+        let req = CtrlReq { request_type: 0xa1, request: 0xfe, value: 0, index: 2, length: 1 };
+        assert_eq!(req.recipient(), CtrlRecipient::Interface);
+        assert_eq!(req.ctrl_type(), CtrlType::Class);
+
+        assert!(CtrlFilter::any().matches(&req));
+        assert!(CtrlFilter::new(CtrlRecipient::Interface, CtrlType::Class, 0xfe).matches(&req));
+        assert!(CtrlFilter::new(CtrlRecipient::Interface, CtrlType::Class, 0xfe).with_index(2).matches(&req));
+        assert!(!CtrlFilter::new(CtrlRecipient::Interface, CtrlType::Class, 0xfe).with_index(1).matches(&req));
+        assert!(!CtrlFilter::new(CtrlRecipient::Device, CtrlType::Vendor, 0xfe).matches(&req));
+    }
+}
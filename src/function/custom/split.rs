@@ -0,0 +1,343 @@
+//! Endpoint senders and receivers split into submitting and completing halves.
+
+use bytes::{Bytes, BytesMut};
+use std::{
+    io::{Error, ErrorKind, Result},
+    os::fd::AsRawFd,
+    time::Duration,
+};
+
+use super::{
+    aio, Direction, EndpointControl, EndpointFile, EndpointIo, EndpointReceiver, EndpointSender, LatencyStats,
+    SubmitOptions,
+};
+
+impl EndpointIo {
+    /// Splits into the endpoint file with a submitter and a completer.
+    fn split(self) -> (EndpointFile, aio::Submitter, aio::Completer) {
+        let EndpointIo { ep, aio } = self;
+        let (submitter, completer) = aio.split();
+        (ep, submitter, completer)
+    }
+}
+
+impl EndpointSender {
+    /// Splits the sender into a half that enqueues data for sending and
+    /// a half that retrieves completed send operations.
+    ///
+    /// Each half can be moved to a different thread, so that a producer can
+    /// enqueue data while another thread reaps completions without locking.
+    /// Send space is released when the completing half retrieves a completed operation.
+    /// Data already enqueued remains enqueued.
+    ///
+    /// Fails if the function has not been registered yet.
+    pub fn split(mut self) -> Result<(SendSubmitHalf, SendCompleteHalf)> {
+        let (ep, submitter, completer) = self.0.take()?.split();
+        Ok((SendSubmitHalf { ep, submitter }, SendCompleteHalf { completer }))
+    }
+}
+
+/// Enqueuing half of a split [`EndpointSender`].
+///
+/// Obtained from [`EndpointSender::split`].
+#[derive(Debug)]
+pub struct SendSubmitHalf {
+    ep: EndpointFile,
+    submitter: aio::Submitter,
+}
+
+impl SendSubmitHalf {
+    /// Gets the endpoint control interface.
+    pub fn control(&self) -> EndpointControl<'_> {
+        EndpointControl::new(&self.ep, Direction::DeviceToHost)
+    }
+
+    /// Enqueue data for sending.
+    ///
+    /// Blocks until send space is released by the [completing half](SendCompleteHalf).
+    pub fn send(&mut self, data: Bytes) -> Result<()> {
+        self.submitter.wait_space(None);
+        self.try_send(data)
+    }
+
+    /// Asynchronously enqueue data for sending.
+    ///
+    /// Waits until send space is released by the [completing half](SendCompleteHalf).
+    ///
+    /// This method is cancel safe in the sense that `data` is dropped without being enqueued
+    /// if the future is dropped before it resolves.
+    #[cfg(feature = "tokio")]
+    pub async fn send_async(&mut self, data: Bytes) -> Result<()> {
+        self.submitter.wait_space_async().await;
+        self.try_send(data)
+    }
+
+    /// Enqueue data for sending with a timeout.
+    ///
+    /// Blocks until send space is released by the [completing half](SendCompleteHalf)
+    /// with the specified timeout.
+    pub fn send_timeout(&mut self, data: Bytes, timeout: Duration) -> Result<()> {
+        if !self.submitter.wait_space(Some(timeout)) {
+            return Err(Error::new(ErrorKind::TimedOut, "timeout waiting for send space"));
+        }
+        self.try_send(data)
+    }
+
+    /// Enqueue data for sending without waiting for send space.
+    ///
+    /// Fails if no send space is available.
+    pub fn try_send(&mut self, data: Bytes) -> Result<()> {
+        self.try_send_with(data, &SubmitOptions::default())
+    }
+
+    /// Enqueue data for sending using the specified submit options without waiting for send space.
+    ///
+    /// Fails if no send space is available.
+    pub fn try_send_with(&mut self, data: Bytes, options: &SubmitOptions) -> Result<()> {
+        let file = self.ep.file()?;
+        self.submitter.submit_with(aio::opcode::PWRITE, file.as_raw_fd(), data, options)?;
+        Ok(())
+    }
+
+    /// Whether send space is available.
+    pub fn is_ready(&self) -> bool {
+        !self.submitter.is_full()
+    }
+
+    /// Whether no send operations are outstanding or awaiting retrieval.
+    pub fn is_empty(&self) -> bool {
+        self.submitter.is_empty()
+    }
+
+    /// Requests cancellation of all enqueued send operations.
+    ///
+    /// Cancelled operations are retrieved by the [completing half](SendCompleteHalf).
+    pub fn cancel(&self) {
+        self.submitter.cancel_all();
+    }
+}
+
+/// Completion half of a split [`EndpointSender`].
+///
+/// Obtained from [`EndpointSender::split`].
+/// Completed send operations return the sent data, allowing its buffer to be reused.
+#[derive(Debug)]
+pub struct SendCompleteHalf {
+    completer: aio::Completer,
+}
+
+impl SendCompleteHalf {
+    /// Waits for the next send operation to complete and returns the sent data.
+    ///
+    /// `Ok(None)` is returned if no send operations are outstanding.
+    pub fn completed(&mut self) -> Result<Option<Bytes>> {
+        self.completer.completed().map(|comp| Ok(comp.result()?.into())).transpose()
+    }
+
+    /// Asynchronously waits for the next send operation to complete and returns the sent data.
+    ///
+    /// `Ok(None)` is returned if no send operations are outstanding.
+    ///
+    /// This method is cancel safe.
+    #[cfg(feature = "tokio")]
+    pub async fn completed_async(&mut self) -> Result<Option<Bytes>> {
+        self.completer.wait_completed().await.map(|comp| Ok(comp.result()?.into())).transpose()
+    }
+
+    /// Waits for the next send operation to complete with a timeout and returns the sent data.
+    ///
+    /// `Ok(None)` is returned if no send operations are outstanding or the timeout is reached.
+    pub fn completed_timeout(&mut self, timeout: Duration) -> Result<Option<Bytes>> {
+        self.completer.completed_timeout(timeout).map(|comp| Ok(comp.result()?.into())).transpose()
+    }
+
+    /// Returns the sent data of a completed send operation, if any.
+    ///
+    /// Does not wait for send operations to complete.
+    pub fn try_completed(&mut self) -> Result<Option<Bytes>> {
+        self.completer.try_completed().map(|comp| Ok(comp.result()?.into())).transpose()
+    }
+
+    /// Waits for all enqueued data to be sent.
+    ///
+    /// Returns an error if any enqueued send operation has failed.
+    pub fn flush(&mut self) -> Result<()> {
+        while self.completed()?.is_some() {}
+        Ok(())
+    }
+
+    /// Whether no send operations are outstanding or awaiting retrieval.
+    pub fn is_empty(&self) -> bool {
+        self.completer.is_empty()
+    }
+
+    /// Enables or disables latency measurement of send operations.
+    pub fn set_latency_stats(&mut self, stats: Option<LatencyStats>) {
+        self.completer.set_latency_stats(stats);
+    }
+
+    /// Latency statistics of send operations, if enabled.
+    pub fn latency_stats(&self) -> Option<&LatencyStats> {
+        self.completer.latency_stats()
+    }
+}
+
+impl EndpointReceiver {
+    /// Splits the receiver into a half that enqueues receive buffers and
+    /// a half that fetches received data.
+    ///
+    /// Each half can be moved to a different thread, so that a thread can
+    /// keep the receive queue filled while another thread processes received data
+    /// without locking.
+    /// Receive queue space is released when the fetching half retrieves a buffer.
+    /// Buffers already enqueued remain enqueued.
+    /// The [buffer pool](Self::set_buffer_pool), if any, is not retained.
+    ///
+    /// Fails if the function has not been registered yet.
+    pub fn split(mut self) -> Result<(RecvSubmitHalf, RecvCompleteHalf)> {
+        let (ep, submitter, completer) = self.io.take()?.split();
+        Ok((RecvSubmitHalf { ep, submitter }, RecvCompleteHalf { completer }))
+    }
+}
+
+/// Enqueuing half of a split [`EndpointReceiver`].
+///
+/// Obtained from [`EndpointReceiver::split`].
+#[derive(Debug)]
+pub struct RecvSubmitHalf {
+    ep: EndpointFile,
+    submitter: aio::Submitter,
+}
+
+impl RecvSubmitHalf {
+    /// Gets the endpoint control interface.
+    pub fn control(&self) -> EndpointControl<'_> {
+        EndpointControl::new(&self.ep, Direction::HostToDevice)
+    }
+
+    /// Enqueue the buffer for receiving.
+    ///
+    /// The buffer should have been allocated with the desired capacity using
+    /// [`BytesMut::with_capacity`].
+    ///
+    /// Blocks until receive queue space is released by the [completing half](RecvCompleteHalf).
+    pub fn recv(&mut self, buf: BytesMut) -> Result<()> {
+        self.submitter.wait_space(None);
+        self.try_recv(buf)
+    }
+
+    /// Asynchronously enqueue the buffer for receiving.
+    ///
+    /// Waits until receive queue space is released by the [completing half](RecvCompleteHalf).
+    ///
+    /// This method is cancel safe in the sense that `buf` is dropped without being enqueued
+    /// if the future is dropped before it resolves.
+    #[cfg(feature = "tokio")]
+    pub async fn recv_async(&mut self, buf: BytesMut) -> Result<()> {
+        self.submitter.wait_space_async().await;
+        self.try_recv(buf)
+    }
+
+    /// Enqueue the buffer for receiving with a timeout.
+    ///
+    /// Blocks until receive queue space is released by the [completing half](RecvCompleteHalf)
+    /// with the specified timeout.
+    pub fn recv_timeout(&mut self, buf: BytesMut, timeout: Duration) -> Result<()> {
+        if !self.submitter.wait_space(Some(timeout)) {
+            return Err(Error::new(ErrorKind::TimedOut, "timeout waiting for receive queue space"));
+        }
+        self.try_recv(buf)
+    }
+
+    /// Enqueue the buffer for receiving without waiting for receive queue space.
+    ///
+    /// Fails if no receive queue space is available.
+    pub fn try_recv(&mut self, buf: BytesMut) -> Result<()> {
+        self.try_recv_with(buf, &SubmitOptions::default())
+    }
+
+    /// Enqueue the buffer for receiving using the specified submit options without waiting for
+    /// receive queue space.
+    ///
+    /// Fails if no receive queue space is available.
+    pub fn try_recv_with(&mut self, buf: BytesMut, options: &SubmitOptions) -> Result<()> {
+        let file = self.ep.file()?;
+        self.submitter.submit_with(aio::opcode::PREAD, file.as_raw_fd(), buf, options)?;
+        Ok(())
+    }
+
+    /// Whether receive queue space is available.
+    pub fn is_ready(&self) -> bool {
+        !self.submitter.is_full()
+    }
+
+    /// Whether no receive buffers are enqueued or awaiting retrieval.
+    pub fn is_empty(&self) -> bool {
+        self.submitter.is_empty()
+    }
+
+    /// Requests cancellation of all enqueued receive buffers.
+    ///
+    /// Cancelled buffers are retrieved by the [completing half](RecvCompleteHalf).
+    pub fn cancel(&self) {
+        self.submitter.cancel_all();
+    }
+}
+
+/// Completion half of a split [`EndpointReceiver`].
+///
+/// Obtained from [`EndpointReceiver::split`].
+#[derive(Debug)]
+pub struct RecvCompleteHalf {
+    completer: aio::Completer,
+}
+
+impl RecvCompleteHalf {
+    /// Waits for data to be received into a previously enqueued receive buffer, then returns it.
+    ///
+    /// `Ok(None)` is returned if no receive buffers are enqueued.
+    pub fn fetch(&mut self) -> Result<Option<BytesMut>> {
+        self.completer.completed().map(|comp| Ok(comp.result()?.try_into().unwrap())).transpose()
+    }
+
+    /// Asynchronously waits for data to be received into a previously enqueued receive buffer,
+    /// then returns it.
+    ///
+    /// `Ok(None)` is returned if no receive buffers are enqueued.
+    ///
+    /// This method is cancel safe.
+    #[cfg(feature = "tokio")]
+    pub async fn fetch_async(&mut self) -> Result<Option<BytesMut>> {
+        self.completer.wait_completed().await.map(|comp| Ok(comp.result()?.try_into().unwrap())).transpose()
+    }
+
+    /// Waits for data to be received into a previously enqueued receive buffer with a timeout,
+    /// then returns it.
+    ///
+    /// `Ok(None)` is returned if no receive buffers are enqueued or the timeout is reached.
+    pub fn fetch_timeout(&mut self, timeout: Duration) -> Result<Option<BytesMut>> {
+        self.completer.completed_timeout(timeout).map(|comp| Ok(comp.result()?.try_into().unwrap())).transpose()
+    }
+
+    /// If data has been received into a previously enqueued receive buffer, returns it.
+    ///
+    /// Does not wait for data to be received.
+    pub fn try_fetch(&mut self) -> Result<Option<BytesMut>> {
+        self.completer.try_completed().map(|comp| Ok(comp.result()?.try_into().unwrap())).transpose()
+    }
+
+    /// Whether no receive buffers are enqueued or awaiting retrieval.
+    pub fn is_empty(&self) -> bool {
+        self.completer.is_empty()
+    }
+
+    /// Enables or disables latency measurement of receive operations.
+    pub fn set_latency_stats(&mut self, stats: Option<LatencyStats>) {
+        self.completer.set_latency_stats(stats);
+    }
+
+    /// Latency statistics of receive operations, if enabled.
+    pub fn latency_stats(&self) -> Option<&LatencyStats> {
+        self.completer.latency_stats()
+    }
+}
@@ -0,0 +1,213 @@
+//! WebUSB control requests handled in user space.
+
+use std::io::{Error, ErrorKind, Result};
+
+use super::{CtrlFilter, CtrlRecipient, CtrlReq, CtrlType, SetupRouter};
+use crate::WebUsb;
+
+/// WebUSB request `GET_ALLOWED_ORIGINS`.
+const GET_ALLOWED_ORIGINS: u16 = 1;
+/// WebUSB request `GET_URL`.
+const GET_URL: u16 = 2;
+
+/// Descriptor type of the allowed origins header.
+const ALLOWED_ORIGINS_HEADER: u8 = 0x00;
+/// Descriptor type of a configuration subset header.
+const CONFIGURATION_SUBSET_HEADER: u8 = 0x01;
+/// Descriptor type of a function subset header.
+const FUNCTION_SUBSET_HEADER: u8 = 0x02;
+/// Descriptor type of a URL.
+const URL_DESCRIPTOR: u8 = 0x03;
+
+/// Allowed origins of a function.
+#[derive(Debug, Clone)]
+struct FunctionOrigins {
+    config: u8,
+    first_interface: u8,
+    origins: Vec<u8>,
+}
+
+/// WebUSB `GET_URL` and `GET_ALLOWED_ORIGINS` requests answered by a [`Custom`](super::Custom)
+/// function in user space.
+///
+/// The kernel only answers `GET_URL` for the landing page configured by [`WebUsb`].
+/// This provides additional URLs, for example multiple landing pages, and
+/// allowed origins per device and per interface as specified by earlier revisions
+/// of the WebUSB specification.
+///
+/// The landing page of the [`WebUsb`] descriptor is URL index 1.
+/// Requests are served by adding them to a [`SetupRouter`] using [`route`](Self::route).
+/// Since WebUSB requests are directed to the device,
+/// [`CustomBuilder::all_ctrl_recipient`](super::CustomBuilder::all_ctrl_recipient) must be enabled.
+#[derive(Debug, Clone)]
+pub struct WebUsbRequests {
+    vendor_code: u8,
+    urls: Vec<String>,
+    device_origins: Vec<u8>,
+    function_origins: Vec<FunctionOrigins>,
+}
+
+impl WebUsbRequests {
+    /// Creates WebUSB request handling for the specified WebUSB descriptor.
+    pub fn new(web_usb: &WebUsb) -> Self {
+        Self {
+            vendor_code: web_usb.vendor_code,
+            urls: vec![web_usb.landing_page.clone()],
+            device_origins: Vec::new(),
+            function_origins: Vec::new(),
+        }
+    }
+
+    /// Adds a URL and returns its index for use in `GET_URL` requests.
+    ///
+    /// URLs starting with `https://` or `http://` are encoded with the corresponding scheme.
+    pub fn add_url(&mut self, url: impl AsRef<str>) -> Result<u8> {
+        let url = url.as_ref();
+        if let Some(index) = self.urls.iter().position(|u| u == url) {
+            return Ok(index as u8 + 1);
+        }
+
+        if url_descriptor(url).len() > usize::from(u8::MAX) {
+            return Err(Error::new(ErrorKind::InvalidInput, "URL too long"));
+        }
+        if self.urls.len() >= usize::from(u8::MAX) {
+            return Err(Error::new(ErrorKind::InvalidInput, "too many URLs"));
+        }
+
+        self.urls.push(url.to_string());
+        Ok(self.urls.len() as u8)
+    }
+
+    /// Allows the origin to access the whole device.
+    pub fn add_allowed_origin(&mut self, origin: impl AsRef<str>) -> Result<()> {
+        let index = self.add_url(origin)?;
+        if !self.device_origins.contains(&index) {
+            self.device_origins.push(index);
+        }
+        Ok(())
+    }
+
+    /// Allows the origin to access the function starting at the specified interface
+    /// in the specified configuration.
+    ///
+    /// Configuration values start at 1.
+    pub fn add_function_allowed_origin(
+        &mut self, config: u8, first_interface: u8, origin: impl AsRef<str>,
+    ) -> Result<()> {
+        let index = self.add_url(origin)?;
+        match self
+            .function_origins
+            .iter_mut()
+            .find(|f| f.config == config && f.first_interface == first_interface)
+        {
+            Some(func) if func.origins.contains(&index) => (),
+            Some(func) => func.origins.push(index),
+            None => self.function_origins.push(FunctionOrigins { config, first_interface, origins: vec![index] }),
+        }
+        Ok(())
+    }
+
+    /// URL descriptor with the specified index.
+    pub fn url_descriptor(&self, index: u8) -> Option<Vec<u8>> {
+        let url = self.urls.get(usize::from(index).checked_sub(1)?)?;
+        Some(url_descriptor(url))
+    }
+
+    /// Allowed origins descriptor including all configuration and function subset headers.
+    pub fn allowed_origins_descriptor(&self) -> Vec<u8> {
+        let mut configs: Vec<u8> = self.function_origins.iter().map(|f| f.config).collect();
+        configs.sort_unstable();
+        configs.dedup();
+
+        let mut data =
+            vec![5 + self.device_origins.len() as u8, ALLOWED_ORIGINS_HEADER, 0, 0, configs.len() as u8];
+        data.extend_from_slice(&self.device_origins);
+
+        for config in configs {
+            let funcs: Vec<_> = self.function_origins.iter().filter(|f| f.config == config).collect();
+            data.extend_from_slice(&[4, CONFIGURATION_SUBSET_HEADER, config, funcs.len() as u8]);
+            for func in funcs {
+                data.extend_from_slice(&[
+                    3 + func.origins.len() as u8,
+                    FUNCTION_SUBSET_HEADER,
+                    func.first_interface,
+                ]);
+                data.extend_from_slice(&func.origins);
+            }
+        }
+
+        let total = (data.len() as u16).to_le_bytes();
+        data[2..4].copy_from_slice(&total);
+        data
+    }
+
+    /// Filter matching WebUSB requests.
+    pub fn filter(&self) -> CtrlFilter {
+        CtrlFilter::new(CtrlRecipient::Device, CtrlType::Vendor, self.vendor_code)
+    }
+
+    /// Response to a WebUSB request.
+    ///
+    /// Fails if the request is not a valid WebUSB request.
+    pub fn respond(&self, ctrl_req: &CtrlReq) -> Result<Vec<u8>> {
+        if !self.filter().matches(ctrl_req) {
+            return Err(Error::new(ErrorKind::InvalidInput, "not a WebUSB request"));
+        }
+
+        match ctrl_req.index {
+            GET_URL => {
+                let index = u8::try_from(ctrl_req.value).unwrap_or_default();
+                self.url_descriptor(index).ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown URL index"))
+            }
+            GET_ALLOWED_ORIGINS => Ok(self.allowed_origins_descriptor()),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "unknown WebUSB request")),
+        }
+    }
+
+    /// Adds a handler for WebUSB requests to the router.
+    pub fn route(self, router: SetupRouter) -> SetupRouter {
+        router.with_device_to_host(self.filter(), move |ctrl_req| self.respond(ctrl_req))
+    }
+}
+
+/// Encodes a URL descriptor.
+fn url_descriptor(url: &str) -> Vec<u8> {
+    let (scheme, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (1, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (0, rest)
+    } else {
+        (0xff, url)
+    };
+
+    let mut data = vec![(3 + rest.len()).min(usize::from(u8::MAX)) as u8, URL_DESCRIPTOR, scheme];
+    data.extend_from_slice(rest.as_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::WebUsbRequests;
+    use crate::{function::custom::CtrlReq, WebUsb};
+
+    #[test]
+    fn urls_and_origins() {
+        let mut requests = WebUsbRequests::new(&WebUsb::new(0x42, "https://example.com"));
+        assert_eq!(requests.add_url("http://example.org/help").unwrap(), 2);
+        assert_eq!(requests.add_url("https://example.com").unwrap(), 1);
+        requests.add_allowed_origin("https://example.com").unwrap();
+        requests.add_function_allowed_origin(1, 0, "https://app.example.com").unwrap();
+
+        let get_url = CtrlReq { request_type: 0xc0, request: 0x42, value: 2, index: 2, length: 255 };
+        assert_eq!(requests.respond(&get_url).unwrap(), b"\x13\x03\x00example.org/help");
+
+        let get_origins = CtrlReq { request_type: 0xc0, request: 0x42, value: 0, index: 1, length: 255 };
+        assert_eq!(
+            requests.respond(&get_origins).unwrap(),
+            vec![6, 0x00, 14, 0, 1, 1, 4, 0x01, 1, 1, 4, 0x02, 0, 3]
+        );
+
+        let unknown = CtrlReq { request_type: 0xc0, request: 0x42, value: 9, index: 2, length: 255 };
+        assert!(requests.respond(&unknown).is_err());
+    }
+}
@@ -2,8 +2,12 @@
 //!
 //! The Linux kernel configuration option `CONFIG_USB_CONFIGFS_F_HID` must be enabled.
 
+use bitflags::bitflags;
 use std::{
+    any::Any,
+    collections::BTreeMap,
     ffi::OsString,
+    fmt,
     io::{Error, ErrorKind, Result},
 };
 
@@ -11,9 +15,179 @@ use super::{
     util::{FunctionDir, Status},
     Function, Handle,
 };
+use crate::{dry_run, linux_version, AccessKind, AccessRequirements, EndpointRequirement, UdcEndpointType};
+
+/// HID subclass of devices supporting the boot interface.
+pub const SUB_CLASS_BOOT: u8 = 1;
+
+/// HID boot protocol of keyboards.
+pub const PROTOCOL_KEYBOARD: u8 = 1;
+
+/// HID boot protocol of mice.
+pub const PROTOCOL_MOUSE: u8 = 2;
+
+/// Maximum length of a report descriptor accepted by the kernel.
+///
+/// configfs limits the size of a single attribute write to one page minus one byte.
+pub const MAX_REPORT_DESC_LEN: usize = 4095;
+
+/// Error indicating that the kernel did not accept the complete HID report descriptor.
+///
+/// This is returned as the inner error of an [`ErrorKind::InvalidInput`] I/O error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReportDescTruncated {
+    /// Length of the report descriptor.
+    pub len: usize,
+    /// Number of bytes accepted by the kernel.
+    pub accepted: usize,
+}
+
+impl fmt::Display for ReportDescTruncated {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "kernel accepted only {} of {} bytes of HID report descriptor (at most {MAX_REPORT_DESC_LEN} bytes are supported)",
+            self.accepted, self.len
+        )
+    }
+}
+
+impl std::error::Error for ReportDescTruncated {}
+
+/// Report descriptor of a boot keyboard.
+///
+/// Input reports are [`KeyboardReport`]s, output reports are [`KeyboardLeds`].
+pub const KEYBOARD_REPORT_DESC: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0xe0, //   Usage Minimum (Left Control)
+    0x29, 0xe7, //   Usage Maximum (Right GUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifiers
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x03, //   Input (Constant): reserved
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (Num Lock)
+    0x29, 0x05, //   Usage Maximum (Kana)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LEDs
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x03, //   Output (Constant): padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): key codes
+    0xc0, // End Collection
+];
+
+/// Report descriptor of a boot mouse with wheel.
+///
+/// Input reports are [`MouseReport`]s.
+pub const MOUSE_REPORT_DESC: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x05, //     Usage Maximum (5)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x05, //     Report Count (5)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute): buttons
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x03, //     Report Size (3)
+    0x81, 0x03, //     Input (Constant): padding
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x09, 0x38, //     Usage (Wheel)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x03, //     Report Count (3)
+    0x81, 0x06, //     Input (Data, Variable, Relative): X, Y, wheel
+    0xc0, //   End Collection
+    0xc0, // End Collection
+];
+
+/// Report descriptor of a gamepad with 16 buttons and two analog sticks.
+///
+/// Input reports are [`GamepadReport`]s.
+pub const GAMEPAD_REPORT_DESC: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x05, // Usage (Gamepad)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x09, //   Usage Page (Button)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x10, //   Usage Maximum (16)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x10, //   Report Count (16)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): buttons
+    0x05, 0x01, //   Usage Page (Generic Desktop)
+    0x09, 0x30, //   Usage (X)
+    0x09, 0x31, //   Usage (Y)
+    0x09, 0x33, //   Usage (Rx)
+    0x09, 0x34, //   Usage (Ry)
+    0x15, 0x81, //   Logical Minimum (-127)
+    0x25, 0x7f, //   Logical Maximum (127)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x04, //   Report Count (4)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): sticks
+    0xc0, // End Collection
+];
+
+/// Report descriptor of an absolute pointing device, such as a touch screen or tablet.
+///
+/// Input reports are [`AbsolutePointerReport`]s.
+pub const ABSOLUTE_POINTER_REPORT_DESC: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute): buttons
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x03, //     Input (Constant): padding
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x16, 0x00, 0x00, // Logical Minimum (0)
+    0x26, 0xff, 0x7f, // Logical Maximum (32767)
+    0x75, 0x10, //     Report Size (16)
+    0x95, 0x02, //     Report Count (2)
+    0x81, 0x02, //     Input (Data, Variable, Absolute): X, Y
+    0xc0, //   End Collection
+    0xc0, // End Collection
+];
 
 /// Builder for USB human interface device (HID) function.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[non_exhaustive]
 pub struct HidBuilder {
     /// HID subclass to use.
@@ -25,10 +199,94 @@ pub struct HidBuilder {
     /// HID report length.
     pub report_len: u8,
     /// No out endpoint?
+    ///
+    /// Output reports are then received via SET_REPORT requests on the control endpoint.
+    /// Requires Linux 5.18 or later.
     pub no_out_endpoint: bool,
+    /// Wake up the host when writing an input report while the USB bus is suspended?
+    ///
+    /// This only has an effect if [`Config::remote_wakeup`](crate::Config::remote_wakeup)
+    /// is set for the configuration containing this function and the host
+    /// has enabled remote wakeup.
+    /// Requires Linux 6.13 or later.
+    pub wakeup_on_write: bool,
 }
 
 impl HidBuilder {
+    /// Boot keyboard sending [`KeyboardReport`]s and receiving [`KeyboardLeds`].
+    pub fn keyboard() -> Self {
+        Self {
+            sub_class: SUB_CLASS_BOOT,
+            protocol: PROTOCOL_KEYBOARD,
+            report_desc: KEYBOARD_REPORT_DESC.to_vec(),
+            report_len: KeyboardReport::LEN as u8,
+            no_out_endpoint: false,
+            wakeup_on_write: false,
+        }
+    }
+
+    /// Boot mouse with wheel sending [`MouseReport`]s.
+    ///
+    /// Hosts using the boot protocol ignore the wheel.
+    pub fn mouse() -> Self {
+        Self {
+            sub_class: SUB_CLASS_BOOT,
+            protocol: PROTOCOL_MOUSE,
+            report_desc: MOUSE_REPORT_DESC.to_vec(),
+            report_len: MouseReport::LEN as u8,
+            no_out_endpoint: true,
+            wakeup_on_write: false,
+        }
+    }
+
+    /// Gamepad sending [`GamepadReport`]s.
+    pub fn gamepad() -> Self {
+        Self {
+            sub_class: 0,
+            protocol: 0,
+            report_desc: GAMEPAD_REPORT_DESC.to_vec(),
+            report_len: GamepadReport::LEN as u8,
+            no_out_endpoint: true,
+            wakeup_on_write: false,
+        }
+    }
+
+    /// Absolute pointing device sending [`AbsolutePointerReport`]s.
+    ///
+    /// This is not supported by the boot protocol.
+    pub fn absolute_pointer() -> Self {
+        Self {
+            sub_class: 0,
+            protocol: 0,
+            report_desc: ABSOLUTE_POINTER_REPORT_DESC.to_vec(),
+            report_len: AbsolutePointerReport::LEN as u8,
+            no_out_endpoint: true,
+            wakeup_on_write: false,
+        }
+    }
+
+    /// Linux version introducing the `no_out_endpoint` attribute.
+    const NO_OUT_ENDPOINT_VERSION: (u16, u16) = (5, 18);
+
+    /// Linux version introducing the `wakeup_on_write` attribute.
+    const WAKEUP_ON_WRITE_VERSION: (u16, u16) = (6, 13);
+
+    /// Checks that the report descriptor is well-formed and its reports fit into the report length.
+    ///
+    /// Registration fails if the reports do not fit into the report length.
+    /// Malformed descriptors only produce a warning, since they may still be accepted by the host.
+    pub fn validate(&self) -> Result<ReportDescInfo> {
+        let info = ReportDescInfo::parse(&self.report_desc)?;
+        let max_len = info.input_report_len.max(info.output_report_len);
+        if max_len > self.report_len.into() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("report length {} is less than {max_len} bytes required by descriptor", self.report_len),
+            ));
+        }
+        Ok(info)
+    }
+
     /// Build the USB function.
     ///
     /// The returned handle must be added to a USB gadget configuration.
@@ -36,6 +294,48 @@ impl HidBuilder {
         let dir = FunctionDir::new();
         (Hid { dir: dir.clone() }, Handle::new(HidFunction { builder: self, dir }))
     }
+
+    /// Sets the HID subclass.
+    #[must_use]
+    pub fn with_sub_class(mut self, sub_class: u8) -> Self {
+        self.sub_class = sub_class;
+        self
+    }
+
+    /// Sets the HID protocol.
+    #[must_use]
+    pub fn with_protocol(mut self, protocol: u8) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Sets the HID report descriptor.
+    #[must_use]
+    pub fn with_report_desc(mut self, report_desc: impl Into<Vec<u8>>) -> Self {
+        self.report_desc = report_desc.into();
+        self
+    }
+
+    /// Sets the HID report length.
+    #[must_use]
+    pub fn with_report_len(mut self, report_len: u8) -> Self {
+        self.report_len = report_len;
+        self
+    }
+
+    /// Sets whether the out endpoint is omitted.
+    #[must_use]
+    pub fn with_no_out_endpoint(mut self, no_out_endpoint: bool) -> Self {
+        self.no_out_endpoint = no_out_endpoint;
+        self
+    }
+
+    /// Sets whether writing an input report wakes up a suspended host.
+    #[must_use]
+    pub fn with_wakeup_on_write(mut self, wakeup_on_write: bool) -> Self {
+        self.wakeup_on_write = wakeup_on_write;
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -53,17 +353,80 @@ impl Function for HidFunction {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
+    fn required_endpoints(&self) -> Option<Vec<EndpointRequirement>> {
+        let mut eps = vec![EndpointRequirement::device_to_host(UdcEndpointType::Interrupt)];
+        if !self.builder.no_out_endpoint {
+            eps.push(EndpointRequirement::host_to_device(UdcEndpointType::Interrupt));
+        }
+        Some(eps)
+    }
+
+    fn required_access(&self) -> AccessRequirements {
+        let mut access = AccessRequirements::new();
+        access.add_path("/dev", AccessKind::ReadWrite, "opening HID device node /dev/hidgN");
+        access
+    }
+
     fn register(&self) -> Result<()> {
         self.dir.write("subclass", self.builder.sub_class.to_string())?;
         self.dir.write("protocol", self.builder.protocol.to_string())?;
-        self.dir.write("report_desc", &self.builder.report_desc)?;
+        match self.builder.validate() {
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::InvalidInput => return Err(err),
+            Err(err) => log::warn!("cannot check HID report descriptor: {err}"),
+        }
+        self.write_report_desc()?;
         self.dir.write("report_length", self.builder.report_len.to_string())?;
-        self.dir.write("no_out_endpoint", if self.builder.no_out_endpoint { "1" } else { "0" })?;
+        self.write_since("no_out_endpoint", self.builder.no_out_endpoint, HidBuilder::NO_OUT_ENDPOINT_VERSION)?;
+        self.write_since("wakeup_on_write", self.builder.wakeup_on_write, HidBuilder::WAKEUP_ON_WRITE_VERSION)?;
 
         Ok(())
     }
 }
 
+impl HidFunction {
+    /// Writes the report descriptor and verifies that the kernel accepted it completely.
+    fn write_report_desc(&self) -> Result<()> {
+        let desc = &self.builder.report_desc;
+        let truncated =
+            |accepted| Error::new(ErrorKind::InvalidInput, ReportDescTruncated { len: desc.len(), accepted });
+
+        let accepted = dry_run::write_once(&self.dir.property_path("report_desc")?, desc)?;
+        if accepted < desc.len() {
+            return Err(truncated(accepted));
+        }
+
+        if !dry_run::is_recording() {
+            let read = self.dir.read("report_desc")?;
+            if read != *desc {
+                return Err(truncated(read.len().min(desc.len())));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a boolean attribute that is only present since the specified Linux version.
+    ///
+    /// If the kernel is older, the attribute is skipped when disabled.
+    fn write_since(&self, name: &str, value: bool, since: (u16, u16)) -> Result<()> {
+        let supported = linux_version().map_or(true, |version| version >= since);
+        match (value, supported) {
+            (_, true) => self.dir.write(name, if value { "1" } else { "0" }),
+            (false, false) => Ok(()),
+            (true, false) => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("HID option {name} requires Linux {}.{} or later", since.0, since.1),
+            )),
+        }
+    }
+}
+
 /// USB human interface device (HID) function.
 #[derive(Debug)]
 pub struct Hid {
@@ -73,7 +436,7 @@ pub struct Hid {
 impl Hid {
     /// Creates a new USB human interface device (HID) builder.
     pub fn builder() -> HidBuilder {
-        HidBuilder { sub_class: 0, protocol: 0, report_desc: Vec::new(), report_len: 0, no_out_endpoint: false }
+        HidBuilder::default()
     }
 
     /// Access to registration status.
@@ -93,3 +456,408 @@ impl Hid {
         Ok((major, minor))
     }
 }
+
+/// Summary of a HID report descriptor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReportDescInfo {
+    /// Length of the longest input report in bytes, including the report ID.
+    pub input_report_len: usize,
+    /// Length of the longest output report in bytes, including the report ID.
+    pub output_report_len: usize,
+    /// Whether reports are prefixed by a report ID.
+    pub report_ids: bool,
+}
+
+impl ReportDescInfo {
+    /// Parses a HID report descriptor.
+    ///
+    /// Fails if the descriptor is truncated or its collections are unbalanced.
+    pub fn parse(desc: &[u8]) -> Result<Self> {
+        #[derive(Clone, Copy, Default)]
+        struct Globals {
+            size: u64,
+            count: u64,
+            id: u8,
+        }
+
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+
+        if desc.is_empty() {
+            return Err(invalid("report descriptor is empty".to_string()));
+        }
+
+        let mut globals = Globals::default();
+        let mut stack = Vec::new();
+        let mut depth = 0usize;
+        let mut report_ids = false;
+        let mut input_bits: BTreeMap<u8, u64> = BTreeMap::new();
+        let mut output_bits: BTreeMap<u8, u64> = BTreeMap::new();
+
+        let mut pos = 0;
+        while pos < desc.len() {
+            let prefix = desc[pos];
+
+            // Long items carry no information relevant here.
+            if prefix == 0xfe {
+                let Some(&len) = desc.get(pos + 1) else {
+                    return Err(invalid(format!("truncated long item at offset {pos}")));
+                };
+                pos += 3 + usize::from(len);
+                if pos > desc.len() {
+                    return Err(invalid(format!("truncated long item at offset {pos}")));
+                }
+                continue;
+            }
+
+            let len = match prefix & 0x03 {
+                3 => 4,
+                n => usize::from(n),
+            };
+            let Some(data) = desc.get(pos + 1..pos + 1 + len) else {
+                return Err(invalid(format!("truncated item at offset {pos}")));
+            };
+            let value = data.iter().rev().fold(0u64, |v, &b| v << 8 | u64::from(b));
+
+            match prefix & 0xfc {
+                // Input
+                0x80 => *input_bits.entry(globals.id).or_default() += globals.size * globals.count,
+                // Output
+                0x90 => *output_bits.entry(globals.id).or_default() += globals.size * globals.count,
+                // Collection
+                0xa0 => depth += 1,
+                // End Collection
+                0xc0 => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or_else(|| invalid(format!("end of collection without collection at offset {pos}")))?
+                }
+                // Report Size
+                0x74 => globals.size = value,
+                // Report ID
+                0x84 => {
+                    globals.id = match u8::try_from(value) {
+                        Ok(id) if id != 0 => id,
+                        _ => return Err(invalid(format!("invalid report id {value} at offset {pos}"))),
+                    };
+                    report_ids = true;
+                }
+                // Report Count
+                0x94 => globals.count = value,
+                // Push
+                0xa4 => stack.push(globals),
+                // Pop
+                0xb4 => {
+                    globals = stack.pop().ok_or_else(|| invalid(format!("pop without push at offset {pos}")))?
+                }
+                _ => (),
+            }
+
+            pos += 1 + len;
+        }
+
+        if depth != 0 {
+            return Err(invalid(format!("{depth} collection(s) not ended")));
+        }
+
+        let report_len = |bits: &BTreeMap<u8, u64>| {
+            bits.iter()
+                .map(|(&id, &bits)| bits.div_ceil(8) as usize + usize::from(id != 0))
+                .max()
+                .unwrap_or_default()
+        };
+
+        Ok(Self {
+            input_report_len: report_len(&input_bits),
+            output_report_len: report_len(&output_bits),
+            report_ids,
+        })
+    }
+}
+
+bitflags! {
+    /// Modifier keys of a [`KeyboardReport`].
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct KeyboardModifiers: u8 {
+        /// Left control.
+        const LEFT_CTRL = 1 << 0;
+        /// Left shift.
+        const LEFT_SHIFT = 1 << 1;
+        /// Left alt.
+        const LEFT_ALT = 1 << 2;
+        /// Left GUI (Windows or command key).
+        const LEFT_GUI = 1 << 3;
+        /// Right control.
+        const RIGHT_CTRL = 1 << 4;
+        /// Right shift.
+        const RIGHT_SHIFT = 1 << 5;
+        /// Right alt.
+        const RIGHT_ALT = 1 << 6;
+        /// Right GUI (Windows or command key).
+        const RIGHT_GUI = 1 << 7;
+    }
+}
+
+bitflags! {
+    /// Keyboard LEDs set by the host in the output report of the [keyboard preset](HidBuilder::keyboard).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct KeyboardLeds: u8 {
+        /// Num lock.
+        const NUM_LOCK = 1 << 0;
+        /// Caps lock.
+        const CAPS_LOCK = 1 << 1;
+        /// Scroll lock.
+        const SCROLL_LOCK = 1 << 2;
+        /// Compose.
+        const COMPOSE = 1 << 3;
+        /// Kana.
+        const KANA = 1 << 4;
+    }
+}
+
+bitflags! {
+    /// Buttons of a [`MouseReport`] or [`AbsolutePointerReport`].
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct MouseButtons: u8 {
+        /// Left button.
+        const LEFT = 1 << 0;
+        /// Right button.
+        const RIGHT = 1 << 1;
+        /// Middle button.
+        const MIDDLE = 1 << 2;
+        /// Back button.
+        ///
+        /// Not supported by [`AbsolutePointerReport`].
+        const BACK = 1 << 3;
+        /// Forward button.
+        ///
+        /// Not supported by [`AbsolutePointerReport`].
+        const FORWARD = 1 << 4;
+    }
+}
+
+/// Input report of the [keyboard preset](HidBuilder::keyboard).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct KeyboardReport {
+    /// Pressed modifier keys.
+    pub modifiers: KeyboardModifiers,
+    /// Usage IDs of pressed keys; unused slots are zero.
+    pub keys: [u8; 6],
+}
+
+impl KeyboardReport {
+    /// Report length in bytes.
+    pub const LEN: usize = 8;
+
+    /// Usage ID reported in all slots when more than six keys are pressed.
+    pub const ERROR_ROLL_OVER: u8 = 0x01;
+
+    /// Creates a report of the specified pressed modifiers and keys.
+    ///
+    /// If more than six keys are pressed, a roll over error is reported.
+    pub fn new(modifiers: KeyboardModifiers, keys: &[u8]) -> Self {
+        let mut report = Self { modifiers, keys: [0; 6] };
+        if keys.len() > report.keys.len() {
+            report.keys = [Self::ERROR_ROLL_OVER; 6];
+        } else {
+            report.keys[..keys.len()].copy_from_slice(keys);
+        }
+        report
+    }
+
+    /// Report data to write to the HID device.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let [k0, k1, k2, k3, k4, k5] = self.keys;
+        [self.modifiers.bits(), 0, k0, k1, k2, k3, k4, k5]
+    }
+}
+
+/// Input report of the [mouse preset](HidBuilder::mouse).
+///
+/// Movements are relative and limited to -127 to 127.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct MouseReport {
+    /// Pressed buttons.
+    pub buttons: MouseButtons,
+    /// Horizontal movement.
+    pub x: i8,
+    /// Vertical movement.
+    pub y: i8,
+    /// Wheel movement.
+    pub wheel: i8,
+}
+
+impl MouseReport {
+    /// Report length in bytes.
+    pub const LEN: usize = 4;
+
+    /// Creates a report of the specified pressed buttons and movement.
+    pub fn new(buttons: MouseButtons, x: i8, y: i8) -> Self {
+        Self { buttons, x, y, wheel: 0 }
+    }
+
+    /// Sets the wheel movement.
+    #[must_use]
+    pub fn with_wheel(mut self, wheel: i8) -> Self {
+        self.wheel = wheel;
+        self
+    }
+
+    /// Report data to write to the HID device.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let rel = |v: i8| v.max(-127) as u8;
+        [self.buttons.bits() & 0x1f, rel(self.x), rel(self.y), rel(self.wheel)]
+    }
+}
+
+/// Input report of the [gamepad preset](HidBuilder::gamepad).
+///
+/// Stick positions are limited to -127 to 127.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct GamepadReport {
+    /// Pressed buttons; bit `n` is button `n + 1`.
+    pub buttons: u16,
+    /// Horizontal position of the left stick.
+    pub x: i8,
+    /// Vertical position of the left stick.
+    pub y: i8,
+    /// Horizontal position of the right stick.
+    pub rx: i8,
+    /// Vertical position of the right stick.
+    pub ry: i8,
+}
+
+impl GamepadReport {
+    /// Report length in bytes.
+    pub const LEN: usize = 6;
+
+    /// Creates a report of the specified pressed buttons with centered sticks.
+    pub fn new(buttons: u16) -> Self {
+        Self { buttons, ..Default::default() }
+    }
+
+    /// Sets the position of the left stick.
+    #[must_use]
+    pub fn with_left_stick(mut self, x: i8, y: i8) -> Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Sets the position of the right stick.
+    #[must_use]
+    pub fn with_right_stick(mut self, rx: i8, ry: i8) -> Self {
+        self.rx = rx;
+        self.ry = ry;
+        self
+    }
+
+    /// Report data to write to the HID device.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let axis = |v: i8| v.max(-127) as u8;
+        let [b0, b1] = self.buttons.to_le_bytes();
+        [b0, b1, axis(self.x), axis(self.y), axis(self.rx), axis(self.ry)]
+    }
+}
+
+/// Input report of the [absolute pointer preset](HidBuilder::absolute_pointer).
+///
+/// Positions range from zero to [`MAX`](Self::MAX) across the screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct AbsolutePointerReport {
+    /// Pressed buttons.
+    pub buttons: MouseButtons,
+    /// Horizontal position.
+    pub x: u16,
+    /// Vertical position.
+    pub y: u16,
+}
+
+impl AbsolutePointerReport {
+    /// Report length in bytes.
+    pub const LEN: usize = 5;
+
+    /// Maximum position.
+    pub const MAX: u16 = 0x7fff;
+
+    /// Creates a report of the specified pressed buttons and position.
+    pub fn new(buttons: MouseButtons, x: u16, y: u16) -> Self {
+        Self { buttons, x, y }
+    }
+
+    /// Report data to write to the HID device.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let [x0, x1] = self.x.min(Self::MAX).to_le_bytes();
+        let [y0, y1] = self.y.min(Self::MAX).to_le_bytes();
+        [self.buttons.bits() & 0x07, x0, x1, y0, y1]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn presets() {
+        for (builder, input_len, output_len) in [
+            (HidBuilder::keyboard(), KeyboardReport::LEN, 1),
+            (HidBuilder::mouse(), MouseReport::LEN, 0),
+            (HidBuilder::gamepad(), GamepadReport::LEN, 0),
+            (HidBuilder::absolute_pointer(), AbsolutePointerReport::LEN, 0),
+        ] {
+            let info = builder.validate().unwrap();
+            assert_eq!(info.input_report_len, input_len);
+            assert_eq!(info.output_report_len, output_len);
+            assert_eq!(usize::from(builder.report_len), input_len);
+            assert!(!info.report_ids);
+        }
+    }
+
+    #[test]
+    fn invalid_report_desc() {
+        assert!(ReportDescInfo::parse(&[]).is_err());
+        assert!(ReportDescInfo::parse(&KEYBOARD_REPORT_DESC[..KEYBOARD_REPORT_DESC.len() - 1]).is_err());
+        assert!(ReportDescInfo::parse(&[0x05]).is_err());
+        assert!(ReportDescInfo::parse(&[0xc0]).is_err());
+        assert!(ReportDescInfo::parse(&[0x85, 0x00]).is_err());
+        assert_eq!(ReportDescInfo::parse(&[0xc0]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            HidBuilder::keyboard().with_report_len(4).validate().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        let info =
+            ReportDescInfo::parse(&[0xa1, 0x01, 0x85, 0x02, 0x75, 0x08, 0x95, 0x03, 0x81, 0x02, 0xc0]).unwrap();
+        assert_eq!(info.input_report_len, 4);
+        assert!(info.report_ids);
+    }
+
+    #[test]
+    fn report_desc_truncated() {
+        let err = ReportDescTruncated { len: 5000, accepted: MAX_REPORT_DESC_LEN };
+        assert_eq!(
+            err.to_string(),
+            "kernel accepted only 4095 of 5000 bytes of HID report descriptor (at most 4095 bytes are supported)"
+        );
+    }
+
+    #[test]
+    fn reports() {
+        let report = KeyboardReport::new(KeyboardModifiers::LEFT_SHIFT, &[0x04, 0x05]);
+        assert_eq!(report.to_bytes(), [0x02, 0, 0x04, 0x05, 0, 0, 0, 0]);
+        assert_eq!(KeyboardReport::new(KeyboardModifiers::empty(), &[4; 7]).keys, [0x01; 6]);
+
+        let report = MouseReport::new(MouseButtons::LEFT | MouseButtons::MIDDLE, -128, 5).with_wheel(-1);
+        assert_eq!(report.to_bytes(), [0x05, 0x81, 0x05, 0xff]);
+
+        let report = GamepadReport::new(0x8001).with_left_stick(127, -1).with_right_stick(0, 1);
+        assert_eq!(report.to_bytes(), [0x01, 0x80, 0x7f, 0xff, 0x00, 0x01]);
+
+        let report = AbsolutePointerReport::new(MouseButtons::RIGHT, 0x1234, 0xffff);
+        assert_eq!(report.to_bytes(), [0x02, 0x34, 0x12, 0xff, 0x7f]);
+    }
+}
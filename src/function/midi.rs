@@ -6,6 +6,7 @@
 //! # Example
 //!
 //! ```no_run
+//! use std::time::Duration;
 //! use usb_gadget::function::midi::Midi;
 //! use usb_gadget::{default_udc, Class, Config, Gadget, Id, Strings};
 //!
@@ -19,7 +20,7 @@
 //! let reg =
 //!     // USB device descriptor base class 0, 0, 0: use Interface Descriptors
 //!     // Linux Foundation VID Gadget PID
-//!     Gadget::new(Class::new(0, 0, 0), Id::new(0x1d6b, 0x0104), Strings::new("Clippy Manufacturer", "Rust MIDI", "RUST0123456"))
+//!     Gadget::new(Class::interface_specific(), Id::new(0x1d6b, 0x0104), Strings::new("Clippy Manufacturer", "Rust MIDI", "RUST0123456"))
 //!         .with_config(Config::new("MIDI Config 1").with_function(func))
 //!         .bind(&udc)
 //!         .expect("cannot bind to UDC");
@@ -31,14 +32,26 @@
 //!     udc.name().to_string_lossy(),
 //!     midi.status()
 //! );
+//!
+//! let device = midi.card_device(Some(Duration::from_secs(1))).expect("cannot find ALSA device");
+//! println!("rawmidi device at {}", device.path().display());
 //! ```
 
-use std::{ffi::OsString, io::Result};
+use std::{
+    any::Any,
+    ffi::OsString,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
 
 use super::{
     util::{FunctionDir, Status},
     Function, Handle,
 };
+use crate::{AccessKind, AccessRequirements, EndpointRequirement, UdcEndpointType};
 
 /// Builder for USB musical instrument digital interface (MIDI) function.
 ///
@@ -76,7 +89,10 @@ impl MidiBuilder {
     /// The returned handle must be added to a USB gadget configuration.
     pub fn build(self) -> (Midi, Handle) {
         let dir = FunctionDir::new();
-        (Midi { dir: dir.clone() }, Handle::new(MidiFunction { builder: self, dir }))
+        (
+            Midi { dir: dir.clone(), id: self.id.clone(), index: self.index },
+            Handle::new(MidiFunction { builder: self, dir }),
+        )
     }
 }
 
@@ -95,6 +111,25 @@ impl Function for MidiFunction {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
+    fn required_endpoints(&self) -> Option<Vec<EndpointRequirement>> {
+        Some(vec![
+            EndpointRequirement::device_to_host(UdcEndpointType::Bulk),
+            EndpointRequirement::host_to_device(UdcEndpointType::Bulk),
+        ])
+    }
+
+    fn required_access(&self) -> AccessRequirements {
+        let mut access = AccessRequirements::new();
+        access.add_path(SOUND_CLASS, AccessKind::Read, "locating ALSA sound card");
+        access.add_path("/dev/snd", AccessKind::ReadWrite, "opening ALSA MIDI device node");
+        access
+    }
+
     fn register(&self) -> Result<()> {
         if let Some(buflen) = self.builder.buflen {
             self.dir.write("buflen", buflen.to_string())?;
@@ -128,6 +163,8 @@ impl Function for MidiFunction {
 #[derive(Debug)]
 pub struct Midi {
     dir: FunctionDir,
+    id: Option<String>,
+    index: Option<u8>,
 }
 
 impl Midi {
@@ -140,4 +177,106 @@ impl Midi {
     pub fn status(&self) -> Status {
         self.dir.status()
     }
+
+    /// Resolves the ALSA sound card and rawmidi device created for this function.
+    ///
+    /// The sound card is created by the kernel once the USB gadget is bound to a UDC.
+    /// If `wait` is specified, resolution is retried until the sound card appears or
+    /// the timeout expires.
+    ///
+    /// Sound cards are matched by the UDC they belong to and, if specified, by
+    /// [`MidiBuilder::id`] and [`MidiBuilder::index`].
+    /// If multiple MIDI functions are bound to the same UDC, specify a unique id for each.
+    pub fn card_device(&self, wait: Option<Duration>) -> Result<MidiDevice> {
+        let deadline = wait.map(|wait| Instant::now() + wait);
+
+        loop {
+            match self.find_card_device() {
+                Err(err) if err.kind() == ErrorKind::NotFound => match deadline {
+                    Some(deadline) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+                    _ => return Err(err),
+                },
+                res => return res,
+            }
+        }
+    }
+
+    fn find_card_device(&self) -> Result<MidiDevice> {
+        let udc =
+            self.status().udc().ok_or_else(|| Error::new(ErrorKind::NotConnected, "USB function not bound"))?;
+        let udc_dev = fs::canonicalize(udc.dir().join("device"))?;
+        let id = self.id.as_deref().map(alsa_card_id);
+
+        let mut found = Vec::new();
+        for entry in fs::read_dir(SOUND_CLASS)? {
+            let Ok(entry) = entry else { continue };
+            let Some(card) = entry.file_name().to_str().and_then(|n| n.strip_prefix("card")?.parse().ok()) else {
+                continue;
+            };
+            let card_dir = entry.path();
+
+            // The sound card belongs to the gadget device, which is a child of the UDC device.
+            let Ok(card_dev) = fs::canonicalize(card_dir.join("device")) else { continue };
+            if card_dev.parent() != Some(udc_dev.as_path()) {
+                continue;
+            }
+
+            if self.index.is_some_and(|index| u32::from(index) != card) {
+                continue;
+            }
+
+            if let Some(id) = &id {
+                let Ok(card_id) = fs::read_to_string(card_dir.join("id")) else { continue };
+                if !card_id.trim().starts_with(id.as_str()) {
+                    continue;
+                }
+            }
+
+            let prefix = format!("midiC{card}D");
+            let Ok(devs) = fs::read_dir(&card_dir) else { continue };
+            for dev in devs.flatten() {
+                if let Some(device) = dev.file_name().to_str().and_then(|n| n.strip_prefix(&prefix)?.parse().ok())
+                {
+                    found.push(MidiDevice { card, device });
+                }
+            }
+        }
+
+        match found.len() {
+            0 => Err(Error::new(ErrorKind::NotFound, "MIDI sound card not found")),
+            1 => Ok(found.remove(0)),
+            _ => Err(Error::new(ErrorKind::Other, "multiple matching MIDI sound cards found")),
+        }
+    }
+}
+
+const SOUND_CLASS: &str = "/sys/class/sound";
+
+/// Card id as sanitized by ALSA.
+///
+/// ALSA skips leading non-alphabetic characters, drops all non-alphanumeric characters
+/// and limits the id to 15 characters.
+fn alsa_card_id(id: &str) -> String {
+    id.chars().skip_while(|c| !c.is_ascii_alphabetic()).filter(|c| c.is_ascii_alphanumeric()).take(15).collect()
+}
+
+/// ALSA rawmidi device of a MIDI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MidiDevice {
+    /// Sound card number.
+    pub card: u32,
+    /// Device number on the sound card.
+    pub device: u32,
+}
+
+impl MidiDevice {
+    /// Path of the rawmidi device file.
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(format!("/dev/snd/midiC{}D{}", self.card, self.device))
+    }
+
+    /// ALSA hardware name, as used by `amidi -p`.
+    pub fn hw_name(&self) -> String {
+        format!("hw:{},{}", self.card, self.device)
+    }
 }
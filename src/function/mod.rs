@@ -9,6 +9,7 @@ pub mod net;
 pub mod other;
 pub mod printer;
 pub mod serial;
+pub mod usbtmc;
 pub mod util;
 pub mod video;
 
@@ -2,18 +2,35 @@
 //!
 //! The Linux kernel configuration option `CONFIG_USB_CONFIGFS_MASS_STORAGE` must be enabled.
 
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::{
+        inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+        stat::{major, minor},
+    },
+    unistd::{access, AccessFlags},
+};
 use std::{
+    any::Any,
+    collections::BTreeSet,
     ffi::{OsStr, OsString},
-    fs,
+    fmt, fs,
     io::{Error, ErrorKind, Result},
-    os::unix::prelude::OsStrExt,
+    os::{
+        fd::AsFd,
+        unix::{fs::FileTypeExt, fs::MetadataExt, prelude::OsStrExt},
+    },
     path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant},
 };
 
 use super::{
     util::{FunctionDir, Status},
     Function, Handle,
 };
+use crate::{AccessKind, AccessRequirements, EndpointRequirement, UdcEndpointType};
 
 pub(crate) fn driver() -> &'static OsStr {
     OsStr::new("mass_storage")
@@ -39,6 +56,19 @@ pub struct Lun {
     /// Required if LUN is not marked as removable.
     file: Option<PathBuf>,
     /// Inquiry string.
+    ///
+    /// It consists of the vendor (8 characters), product (16 characters) and
+    /// revision (4 characters) identification reported to the host by the SCSI INQUIRY
+    /// command and is limited to [`INQUIRY_STRING_LEN`](Self::INQUIRY_STRING_LEN)
+    /// printable ASCII characters.
+    /// If empty, the kernel provides a default.
+    /// Use [`set_inquiry`](Self::set_inquiry) to set the fields individually.
+    ///
+    /// The kernel function driver does not report a SCSI unit serial number
+    /// (vital product data page 0x80).
+    /// Hosts identify the device by the USB serial number string of the gadget instead.
+    /// Use the [user-space mass storage implementation](crate::function::custom::msc_bot)
+    /// if full control over the reported SCSI data is required.
     pub inquiry_string: String,
 }
 
@@ -71,6 +101,59 @@ impl Lun {
         Ok(())
     }
 
+    /// Sets whether access to the LUN is read-only.
+    #[must_use]
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets whether the LUN is reported as CD-ROM.
+    #[must_use]
+    pub fn with_cdrom(mut self, cdrom: bool) -> Self {
+        self.cdrom = cdrom;
+        self
+    }
+
+    /// Sets whether the FUA flag in SCSI WRITE(10,12) is ignored.
+    #[must_use]
+    pub fn with_no_fua(mut self, no_fua: bool) -> Self {
+        self.no_fua = no_fua;
+        self
+    }
+
+    /// Sets whether the LUN is reported as removable.
+    #[must_use]
+    pub fn with_removable(mut self, removable: bool) -> Self {
+        self.removable = removable;
+        self
+    }
+
+    /// Maximum length of the [inquiry string](Self::inquiry_string).
+    pub const INQUIRY_STRING_LEN: usize = 28;
+
+    /// Sets the inquiry string from the vendor (up to 8 characters), product (up to 16 characters)
+    /// and revision (up to 4 characters) identification.
+    ///
+    /// Fields are padded with spaces.
+    pub fn set_inquiry(&mut self, vendor: &str, product: &str, revision: &str) -> Result<()> {
+        for (field, value, max_len) in
+            [("vendor", vendor, 8), ("product", product, 16), ("revision", revision, 4)]
+        {
+            if value.len() > max_len {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    InquiryStringError::FieldTooLong { field, max_len },
+                ));
+            }
+        }
+
+        let inquiry_string = format!("{vendor:<8}{product:<16}{revision:<4}");
+        validate_inquiry_string(&inquiry_string)?;
+        self.inquiry_string = inquiry_string;
+        Ok(())
+    }
+
     fn dir_name(idx: usize) -> String {
         format!("lun.{idx}")
     }
@@ -90,7 +173,7 @@ impl Default for Lun {
 }
 
 /// Builder for USB Mass Storage Device (MSD) function.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[non_exhaustive]
 pub struct MsdBuilder {
     /// Set to permit function to halt bulk endpoints.
@@ -102,6 +185,57 @@ pub struct MsdBuilder {
 }
 
 impl MsdBuilder {
+    /// Checks the LUNs for errors that would prevent registration.
+    ///
+    /// This verifies the number of LUNs against [`Msd::MAX_LUNS`], the inquiry strings and
+    /// that the backing files exist and are accessible by the current process.
+    /// Errors concerning LUNs contain a [`LunError`].
+    ///
+    /// This is called automatically when the function is registered.
+    pub fn validate(&self) -> Result<()> {
+        if self.luns.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "at least one LUN must exist"));
+        }
+        if self.luns.len() > Msd::MAX_LUNS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                LunError::TooMany { count: self.luns.len(), max: Msd::MAX_LUNS },
+            ));
+        }
+
+        for (lun, l) in self.luns.iter().enumerate() {
+            validate_inquiry_string(&l.inquiry_string)?;
+
+            let Some(path) = &l.file else {
+                if !l.removable {
+                    return Err(Error::new(ErrorKind::InvalidInput, LunError::MissingFile { lun }));
+                }
+                continue;
+            };
+
+            let flags =
+                if l.read_only || l.cdrom { AccessFlags::R_OK } else { AccessFlags::R_OK | AccessFlags::W_OK };
+            match access(path, flags) {
+                Ok(()) => (),
+                Err(Errno::ENOENT) => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        LunError::FileNotFound { lun, path: path.clone() },
+                    ))
+                }
+                Err(Errno::EACCES | Errno::EROFS | Errno::EPERM) => {
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        LunError::FileAccessDenied { lun, path: path.clone() },
+                    ))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+
     /// Build the USB function.
     ///
     /// The returned handle must be added to a USB gadget configuration.
@@ -121,6 +255,13 @@ impl MsdBuilder {
         self.add_lun(lun);
         self
     }
+
+    /// Sets whether the function is permitted to halt bulk endpoints.
+    #[must_use]
+    pub fn with_stall(mut self, stall: bool) -> Self {
+        self.stall = Some(stall);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -138,10 +279,37 @@ impl Function for MsdFunction {
         self.dir.clone()
     }
 
-    fn register(&self) -> Result<()> {
-        if self.builder.luns.is_empty() {
-            return Err(Error::new(ErrorKind::InvalidInput, "at least one LUN must exist"));
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
+    fn required_endpoints(&self) -> Option<Vec<EndpointRequirement>> {
+        Some(vec![
+            EndpointRequirement::device_to_host(UdcEndpointType::Bulk),
+            EndpointRequirement::host_to_device(UdcEndpointType::Bulk),
+        ])
+    }
+
+    fn required_access(&self) -> AccessRequirements {
+        let mut access = AccessRequirements::new();
+        for lun in &self.builder.luns {
+            let Some(file) = &lun.file else { continue };
+            let kind = if lun.read_only || lun.cdrom { AccessKind::Read } else { AccessKind::ReadWrite };
+            access.add_path(file, kind, "opening LUN backing file");
+        }
+        if let Ok(dir) = self.dir.dir() {
+            for idx in 0..self.builder.luns.len() {
+                let lun_dir = dir.join(Lun::dir_name(idx));
+                access.add_path(lun_dir.join("file"), AccessKind::ReadWrite, "changing LUN medium");
+                access.add_path(lun_dir.join("forced_eject"), AccessKind::ReadWrite, "ejecting LUN medium");
+            }
         }
+        access
+    }
+
+    fn register(&self) -> Result<()> {
+        self.builder.validate()?;
 
         if let Some(stall) = self.builder.stall {
             self.dir.write("stall", if stall { "1" } else { "0" })?;
@@ -175,6 +343,13 @@ pub struct Msd {
 }
 
 impl Msd {
+    /// Maximum number of LUNs per function supported by the Linux kernel.
+    ///
+    /// This is the compile-time limit `FSG_MAX_LUNS` of the kernel function driver,
+    /// which cannot be read at runtime.
+    /// Linux kernels before version 3.15 support at most 8 LUNs.
+    pub const MAX_LUNS: usize = 16;
+
     /// Creates a new USB Mass Storage Device (MSD) with the specified backing file.
     pub fn new(file: impl AsRef<Path>) -> Result<(Msd, Handle)> {
         let mut builder = Self::builder();
@@ -184,7 +359,7 @@ impl Msd {
 
     /// Creates a new USB Mass Storage Device (MSD) builder.
     pub fn builder() -> MsdBuilder {
-        MsdBuilder { stall: None, luns: Vec::new() }
+        MsdBuilder::default()
     }
 
     /// Access to registration status.
@@ -216,6 +391,418 @@ impl Msd {
     }
 }
 
+/// Policy for swapping the medium of a LUN using [`Msd::swap_media`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SwapPolicy {
+    /// Time to wait for the host to allow medium removal.
+    pub timeout: Duration,
+    /// Interval between ejection attempts.
+    pub retry_interval: Duration,
+    /// Forcibly eject the medium if the host still prevents removal after the timeout.
+    pub force: bool,
+}
+
+impl Default for SwapPolicy {
+    fn default() -> Self {
+        Self { timeout: Duration::from_secs(5), retry_interval: Duration::from_millis(100), force: false }
+    }
+}
+
+impl SwapPolicy {
+    /// Creates the default swap policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time to wait for the host to allow medium removal.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the interval between ejection attempts.
+    #[must_use]
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Sets whether the medium is forcibly ejected after the timeout.
+    #[must_use]
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+}
+
+/// Invalid [inquiry string](Lun::inquiry_string) of a LUN.
+///
+/// This is returned as the inner error of an [`ErrorKind::InvalidInput`] I/O error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InquiryStringError {
+    /// The inquiry string is longer than [`Lun::INQUIRY_STRING_LEN`] characters.
+    TooLong {
+        /// Length of the inquiry string.
+        len: usize,
+    },
+    /// The inquiry string contains a character that is not printable ASCII.
+    InvalidChar {
+        /// The invalid character.
+        ch: char,
+        /// Character position within the inquiry string.
+        pos: usize,
+    },
+    /// A field of the inquiry string is too long.
+    FieldTooLong {
+        /// Name of the field.
+        field: &'static str,
+        /// Maximum length of the field.
+        max_len: usize,
+    },
+}
+
+impl fmt::Display for InquiryStringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong { len } => {
+                write!(
+                    f,
+                    "inquiry string has {len} characters, but at most {} are allowed",
+                    Lun::INQUIRY_STRING_LEN
+                )
+            }
+            Self::InvalidChar { ch, pos } => {
+                write!(f, "inquiry string contains invalid character {ch:?} at position {pos}")
+            }
+            Self::FieldTooLong { field, max_len } => {
+                write!(f, "inquiry {field} must have at most {max_len} characters")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InquiryStringError {}
+
+/// Validates an inquiry string.
+fn validate_inquiry_string(inquiry_string: &str) -> Result<()> {
+    if let Some((pos, ch)) = inquiry_string.chars().enumerate().find(|(_, ch)| !matches!(ch, ' '..='~')) {
+        return Err(Error::new(ErrorKind::InvalidInput, InquiryStringError::InvalidChar { ch, pos }));
+    }
+    if inquiry_string.len() > Lun::INQUIRY_STRING_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            InquiryStringError::TooLong { len: inquiry_string.len() },
+        ));
+    }
+    Ok(())
+}
+
+/// Invalid logical unit (LUN) configuration.
+///
+/// This is returned as the inner error of the I/O error returned by [`MsdBuilder::validate`]
+/// and when registering the function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LunError {
+    /// More LUNs than supported by the kernel were specified.
+    TooMany {
+        /// Number of specified LUNs.
+        count: usize,
+        /// Maximum number of LUNs.
+        max: usize,
+    },
+    /// A non-removable LUN has no backing file.
+    MissingFile {
+        /// LUN index.
+        lun: usize,
+    },
+    /// The backing file of a LUN does not exist.
+    FileNotFound {
+        /// LUN index.
+        lun: usize,
+        /// Path of the backing file.
+        path: PathBuf,
+    },
+    /// The backing file of a LUN cannot be accessed with the required permissions.
+    FileAccessDenied {
+        /// LUN index.
+        lun: usize,
+        /// Path of the backing file.
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for LunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooMany { count, max } => {
+                write!(f, "{count} LUNs specified, but the kernel supports at most {max}")
+            }
+            Self::MissingFile { lun } => write!(f, "LUN {lun} is not removable but has no backing file"),
+            Self::FileNotFound { lun, path } => {
+                write!(f, "backing file {} of LUN {lun} does not exist", path.display())
+            }
+            Self::FileAccessDenied { lun, path } => {
+                write!(f, "backing file {} of LUN {lun} is not accessible", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LunError {}
+
+/// Error indicating that the host prevents removal of the medium of a LUN.
+///
+/// This is returned as the inner error of an [`ErrorKind::TimedOut`] I/O error by
+/// [`Msd::swap_media`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MediumLocked {
+    /// LUN whose medium is locked.
+    pub lun: usize,
+}
+
+impl fmt::Display for MediumLocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "host prevents removal of medium of LUN {}", self.lun)
+    }
+}
+
+impl std::error::Error for MediumLocked {}
+
+impl Msd {
+    /// Replaces the backing file of the LUN.
+    ///
+    /// The current medium is ejected first.
+    /// If the host prevents medium removal, ejection is retried until the host allows
+    /// it or the timeout of the policy expires.
+    /// Then the medium is ejected forcibly, if the policy allows it, or an
+    /// [`ErrorKind::TimedOut`] error containing [`MediumLocked`] is returned.
+    /// Finally the new backing file is attached.
+    pub fn swap_media(&self, lun: usize, new_path: impl AsRef<Path>, policy: &SwapPolicy) -> Result<()> {
+        let new_path = new_path.as_ref();
+        if !new_path.is_absolute() {
+            return Err(Error::new(ErrorKind::InvalidInput, "the LUN file path must be absolute"));
+        }
+
+        let deadline = Instant::now() + policy.timeout;
+        loop {
+            match self.set_file::<&Path>(lun, None) {
+                Ok(()) => break,
+                Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {
+                    if Instant::now() >= deadline {
+                        if policy.force {
+                            log::warn!("forcibly ejecting medium of LUN {lun}");
+                            self.force_eject(lun)?;
+                            break;
+                        }
+                        return Err(Error::new(ErrorKind::TimedOut, MediumLocked { lun }));
+                    }
+                    sleep(policy.retry_interval);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.set_file(lun, Some(new_path))
+    }
+
+    /// Asynchronously replaces the backing file of the LUN.
+    ///
+    /// See [`swap_media`](Self::swap_media) for details.
+    #[cfg(feature = "tokio")]
+    pub async fn swap_media_async(
+        &self, lun: usize, new_path: impl AsRef<Path>, policy: &SwapPolicy,
+    ) -> Result<()> {
+        let msd = Msd { dir: self.dir.clone() };
+        let new_path = new_path.as_ref().to_path_buf();
+        let policy = *policy;
+        tokio::task::spawn_blocking(move || msd.swap_media(lun, new_path, &policy))
+            .await
+            .map_err(|err| Error::new(ErrorKind::Other, err))?
+    }
+}
+
+impl Msd {
+    /// Creates a monitor reporting read and write activity of the host on the backing files
+    /// of all LUNs, for example to drive an activity LED.
+    ///
+    /// The monitor watches the backing files attached at the time of its creation.
+    /// Create a new monitor after changing the backing file of a LUN.
+    pub fn monitor(&self) -> Result<MsdMonitor> {
+        let mut files = Vec::new();
+        for lun in 0..Self::MAX_LUNS {
+            match self.dir.read_os_string(format!("{}/file", Lun::dir_name(lun))) {
+                Ok(file) if !file.is_empty() => files.push((lun, PathBuf::from(file))),
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::NotFound => break,
+                Err(err) => return Err(err),
+            }
+        }
+        MsdMonitor::new(files)
+    }
+}
+
+/// Kind of activity on the medium of a LUN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ActivityKind {
+    /// The host read from the medium.
+    Read,
+    /// The host wrote to the medium.
+    Write,
+}
+
+/// Activity on the medium of a LUN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MsdActivity {
+    /// LUN index.
+    pub lun: usize,
+    /// Kind of activity.
+    pub kind: ActivityKind,
+    /// Number of bytes transferred since the last report.
+    ///
+    /// Only known if the backing file is a block device, such as a loop device,
+    /// since the kernel keeps I/O statistics only for block devices.
+    pub bytes: Option<u64>,
+}
+
+/// Monitored backing file of a LUN.
+#[derive(Debug)]
+struct MonitoredLun {
+    lun: usize,
+    wd: WatchDescriptor,
+    /// Path of the statistics of the block device and the sectors read and written.
+    stat: Option<(PathBuf, [u64; 2])>,
+}
+
+impl MonitoredLun {
+    /// Reads the sectors read and written from the block device statistics.
+    fn sectors(stat: &Path) -> Result<[u64; 2]> {
+        let stat = fs::read_to_string(stat)?;
+        let fields: Vec<u64> = stat.split_whitespace().map(|v| v.parse().unwrap_or_default()).collect();
+        match (fields.get(2), fields.get(6)) {
+            (Some(&read), Some(&written)) => Ok([read, written]),
+            _ => Err(Error::new(ErrorKind::InvalidData, "invalid block device statistics")),
+        }
+    }
+
+    /// Bytes transferred since the last call, if known.
+    fn bytes(&mut self, kind: ActivityKind) -> Option<u64> {
+        const SECTOR_SIZE: u64 = 512;
+
+        let (path, last) = self.stat.as_mut()?;
+        let current = Self::sectors(path).ok()?;
+        let idx = kind as usize;
+        let bytes = current[idx].saturating_sub(last[idx]) * SECTOR_SIZE;
+        last[idx] = current[idx];
+        Some(bytes)
+    }
+}
+
+/// Monitors read and write activity on the backing files of a mass storage device.
+///
+/// Obtained from [`Msd::monitor`].
+/// Activity is detected using inotify, thus it is reported when the kernel accesses
+/// the backing file, which may be delayed by caching.
+#[derive(Debug)]
+pub struct MsdMonitor {
+    inotify: Inotify,
+    luns: Vec<MonitoredLun>,
+}
+
+impl MsdMonitor {
+    fn new(files: Vec<(usize, PathBuf)>) -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+
+        let mut luns = Vec::new();
+        for (lun, file) in files {
+            let wd = inotify.add_watch(&file, AddWatchFlags::IN_ACCESS | AddWatchFlags::IN_MODIFY)?;
+
+            let metadata = fs::metadata(&file)?;
+            let stat = if metadata.file_type().is_block_device() {
+                let rdev = metadata.rdev();
+                let path = PathBuf::from(format!("/sys/dev/block/{}:{}/stat", major(rdev), minor(rdev)));
+                let sectors = MonitoredLun::sectors(&path)?;
+                Some((path, sectors))
+            } else {
+                None
+            };
+
+            log::debug!("monitoring activity of LUN {lun} backed by {}", file.display());
+            luns.push(MonitoredLun { lun, wd, stat });
+        }
+
+        Ok(Self { inotify, luns })
+    }
+
+    /// Waits for activity with the specified timeout.
+    ///
+    /// Returns activity aggregated per LUN and kind since the last call.
+    /// An empty list is returned if the timeout expires.
+    pub fn activity(&mut self, timeout: Option<Duration>) -> Result<Vec<MsdActivity>> {
+        let mut fds = [PollFd::new(self.inotify.as_fd(), PollFlags::POLLIN)];
+        poll(
+            &mut fds,
+            timeout.map(|d| d.as_millis().try_into().unwrap_or(PollTimeout::MAX)).unwrap_or(PollTimeout::NONE),
+        )?;
+        self.try_activity()
+    }
+
+    /// Asynchronously waits for activity.
+    ///
+    /// Returns activity aggregated per LUN and kind since the last call.
+    #[cfg(feature = "tokio")]
+    pub async fn activity_async(&mut self) -> Result<Vec<MsdActivity>> {
+        use std::os::fd::AsRawFd;
+        use tokio::io::{unix::AsyncFd, Interest};
+
+        let async_fd = AsyncFd::with_interest(self.inotify.as_fd().as_raw_fd(), Interest::READABLE)?;
+        loop {
+            let mut guard = async_fd.readable().await?;
+            let activity = self.try_activity()?;
+            guard.clear_ready();
+
+            if !activity.is_empty() {
+                return Ok(activity);
+            }
+        }
+    }
+
+    /// Returns activity since the last call without waiting.
+    pub fn try_activity(&mut self) -> Result<Vec<MsdActivity>> {
+        let mut kinds = BTreeSet::new();
+        loop {
+            let events = match self.inotify.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            for event in events {
+                let Some(idx) = self.luns.iter().position(|lun| lun.wd == event.wd) else { continue };
+                if event.mask.contains(AddWatchFlags::IN_ACCESS) {
+                    kinds.insert((idx, ActivityKind::Read));
+                }
+                if event.mask.contains(AddWatchFlags::IN_MODIFY) {
+                    kinds.insert((idx, ActivityKind::Write));
+                }
+            }
+        }
+
+        Ok(kinds
+            .into_iter()
+            .map(|(idx, kind)| {
+                let lun = &mut self.luns[idx];
+                MsdActivity { lun: lun.lun, kind, bytes: lun.bytes(kind) }
+            })
+            .collect())
+    }
+}
+
 pub(crate) fn remove_handler(dir: PathBuf) -> Result<()> {
     for entry in fs::read_dir(dir)? {
         let Ok(entry) = entry else { continue };
@@ -229,3 +816,29 @@ pub(crate) fn remove_handler(dir: PathBuf) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Write, time::Duration};
+
+    use super::{ActivityKind, MsdActivity, MsdMonitor};
+
+    #[test]
+    fn monitor_file_activity() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut monitor = MsdMonitor::new(vec![(3, file.path().to_path_buf())]).unwrap();
+        assert!(monitor.try_activity().unwrap().is_empty());
+
+        file.write_all(b"data").unwrap();
+        fs::read(file.path()).unwrap();
+
+        let activity = monitor.activity(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(
+            activity,
+            [
+                MsdActivity { lun: 3, kind: ActivityKind::Read, bytes: None },
+                MsdActivity { lun: 3, kind: ActivityKind::Write, bytes: None }
+            ]
+        );
+    }
+}
@@ -1,16 +1,24 @@
 //! Net functions.
 
 use macaddr::MacAddr6;
+#[cfg(feature = "netlink")]
+use std::os::unix::ffi::OsStrExt;
 use std::{
+    any::Any,
     ffi::{OsStr, OsString},
+    fmt,
     io::{Error, ErrorKind, Result},
+    sync::Mutex,
 };
 
 use super::{
-    util::{FunctionDir, Status},
+    custom::OsExtCompat,
+    util::{FunctionDir, Status, Strictness},
     Function, Handle,
 };
-use crate::{gadget::Class, hex_u8};
+#[cfg(feature = "netlink")]
+use crate::netlink;
+use crate::{gadget::Class, hex_u8, EndpointRequirement, IdentityKey, UdcEndpointType};
 
 /// Class of USB network device.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -63,6 +71,44 @@ pub struct NetBuilder {
     pub qmult: Option<u32>,
     /// For RNDIS only: interface class.
     pub interface_class: Option<Class>,
+    /// For NCM only: maximum NTB input size in bytes.
+    ///
+    /// This attribute is not provided by mainline Linux, only by some vendor kernels.
+    /// If it is absent, it is handled according to [`strictness`](Self::strictness).
+    pub ntb_input_size: Option<u32>,
+    /// For NCM only: maximum NTB output size in bytes.
+    ///
+    /// See [`ntb_input_size`](Self::ntb_input_size) for kernel support.
+    pub ntb_output_size: Option<u32>,
+    /// For NCM only: maximum segment size in bytes.
+    ///
+    /// Requires Linux 6.6 or later.
+    pub max_segment_size: Option<u16>,
+    /// Name of the network interface created on the device.
+    ///
+    /// This may be a pattern containing `%d`, such as `usb%d`, that the kernel replaces by
+    /// the next free number.
+    /// Setting the interface name requires Linux 6.1 or later; on older kernels
+    /// it is handled according to the [strictness](Self::strictness).
+    pub ifname: Option<String>,
+    /// Interface string shown by the USB host, for example in its network settings.
+    ///
+    /// The Linux kernel network functions use fixed interface strings and do not allow
+    /// customizing them through configfs.
+    /// Thus, if this is set, registration fails with an [`ErrorKind::Unsupported`] error
+    /// containing [`InterfaceStringUnsupported`], unless [`strictness`](Self::strictness)
+    /// allows skipping unsupported attributes.
+    /// Use a [custom function](crate::function::custom) to provide a network interface
+    /// with arbitrary strings.
+    pub interface_string: Option<String>,
+    /// For RNDIS and NCM only: Microsoft extended compatibility descriptor of the interface.
+    ///
+    /// This is written to the per-interface OS descriptor directory of the kernel function
+    /// and reported to Windows hosts if the gadget has an [OS descriptor](crate::OsDescriptor).
+    /// The kernel defaults to [`OsExtCompat::rndis`] for RNDIS and [`OsExtCompat::winncm`] for NCM.
+    pub os_ext_compat: Option<OsExtCompat>,
+    /// Handling of attributes not supported by the running kernel.
+    pub strictness: Strictness,
 }
 
 impl NetBuilder {
@@ -71,7 +117,102 @@ impl NetBuilder {
     /// The returned handle must be added to a USB gadget configuration.
     pub fn build(self) -> (Net, Handle) {
         let dir = FunctionDir::new();
-        (Net { dir: dir.clone() }, Handle::new(NetFunction { builder: self, dir }))
+        (Net { dir: dir.clone() }, Handle::new(NetFunction { builder: self, dir, derived: Mutex::default() }))
+    }
+
+    /// Derives the device and host MAC addresses from the specified key.
+    ///
+    /// The context distinguishes multiple network functions of the same gadget, for
+    /// example the network interface name.
+    #[must_use]
+    pub fn with_identity(mut self, key: &IdentityKey, context: &str) -> Self {
+        self.dev_addr = Some(key.mac_address(format!("dev:{context}").as_bytes()));
+        self.host_addr = Some(key.mac_address(format!("host:{context}").as_bytes()));
+        self
+    }
+
+    /// Sets the MAC address of the device's end of the link.
+    #[must_use]
+    pub fn with_dev_addr(mut self, dev_addr: MacAddr6) -> Self {
+        self.dev_addr = Some(dev_addr);
+        self
+    }
+
+    /// Sets the MAC address of the host's end of the link.
+    #[must_use]
+    pub fn with_host_addr(mut self, host_addr: MacAddr6) -> Self {
+        self.host_addr = Some(host_addr);
+        self
+    }
+
+    /// Sets the queue length multiplier for high and super speed.
+    #[must_use]
+    pub fn with_qmult(mut self, qmult: u32) -> Self {
+        self.qmult = Some(qmult);
+        self
+    }
+
+    /// For RNDIS only: sets the interface class.
+    #[must_use]
+    pub fn with_interface_class(mut self, interface_class: Class) -> Self {
+        self.interface_class = Some(interface_class);
+        self
+    }
+
+    /// For NCM only: sets the maximum NTB input size in bytes.
+    #[must_use]
+    pub fn with_ntb_input_size(mut self, ntb_input_size: u32) -> Self {
+        self.ntb_input_size = Some(ntb_input_size);
+        self
+    }
+
+    /// For NCM only: sets the maximum NTB output size in bytes.
+    #[must_use]
+    pub fn with_ntb_output_size(mut self, ntb_output_size: u32) -> Self {
+        self.ntb_output_size = Some(ntb_output_size);
+        self
+    }
+
+    /// For NCM only: sets the maximum segment size in bytes.
+    #[must_use]
+    pub fn with_max_segment_size(mut self, max_segment_size: u16) -> Self {
+        self.max_segment_size = Some(max_segment_size);
+        self
+    }
+
+    /// Sets the name of the network interface created on the device.
+    #[must_use]
+    pub fn with_ifname(mut self, ifname: impl Into<String>) -> Self {
+        self.ifname = Some(ifname.into());
+        self
+    }
+
+    /// Sets the interface string shown by the USB host.
+    #[must_use]
+    pub fn with_interface_string(mut self, interface_string: impl Into<String>) -> Self {
+        self.interface_string = Some(interface_string.into());
+        self
+    }
+
+    /// For RNDIS and NCM only: sets the Microsoft extended compatibility descriptor.
+    #[must_use]
+    pub fn with_os_ext_compat(mut self, os_ext_compat: OsExtCompat) -> Self {
+        self.os_ext_compat = Some(os_ext_compat);
+        self
+    }
+
+    /// Sets the handling of attributes not supported by the running kernel.
+    #[must_use]
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+}
+
+impl Default for NetBuilder {
+    /// Builder for a CDC ECM network function.
+    fn default() -> Self {
+        Net::builder(NetClass::Ecm)
     }
 }
 
@@ -79,6 +220,16 @@ impl NetBuilder {
 struct NetFunction {
     builder: NetBuilder,
     dir: FunctionDir,
+    /// Derived device and host MAC addresses.
+    derived: Mutex<(Option<MacAddr6>, Option<MacAddr6>)>,
+}
+
+impl NetFunction {
+    /// Device and host MAC addresses, either specified or derived.
+    fn addrs(&self) -> (Option<MacAddr6>, Option<MacAddr6>) {
+        let (dev_addr, host_addr) = *self.derived.lock().unwrap();
+        (self.builder.dev_addr.or(dev_addr), self.builder.host_addr.or(host_addr))
+    }
 }
 
 impl Function for NetFunction {
@@ -90,12 +241,44 @@ impl Function for NetFunction {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
+    fn required_endpoints(&self) -> Option<Vec<EndpointRequirement>> {
+        let mut eps = vec![
+            EndpointRequirement::device_to_host(UdcEndpointType::Bulk),
+            EndpointRequirement::host_to_device(UdcEndpointType::Bulk),
+        ];
+        match self.builder.net_class {
+            NetClass::Ecm | NetClass::Ncm | NetClass::Rndis => {
+                eps.push(EndpointRequirement::device_to_host(UdcEndpointType::Interrupt))
+            }
+            NetClass::EcmSubset | NetClass::Eem => (),
+        }
+        Some(eps)
+    }
+
+    fn mac_addresses(&self) -> Vec<MacAddr6> {
+        let (dev_addr, host_addr) = self.addrs();
+        dev_addr.into_iter().chain(host_addr).collect()
+    }
+
+    fn derive_mac_addresses(&self, generate: &mut dyn FnMut() -> MacAddr6) {
+        let mut derived = self.derived.lock().unwrap();
+        derived.0 = self.builder.dev_addr.is_none().then(&mut *generate);
+        derived.1 = self.builder.host_addr.is_none().then(generate);
+    }
+
     fn register(&self) -> Result<()> {
-        if let Some(dev_addr) = self.builder.dev_addr {
+        let (dev_addr, host_addr) = self.addrs();
+
+        if let Some(dev_addr) = dev_addr {
             self.dir.write("dev_addr", dev_addr.to_string())?;
         }
 
-        if let Some(host_addr) = self.builder.host_addr {
+        if let Some(host_addr) = host_addr {
             self.dir.write("host_addr", host_addr.to_string())?;
         }
 
@@ -109,11 +292,72 @@ impl Function for NetFunction {
             self.dir.write("protocol", hex_u8(class.protocol))?;
         }
 
+        if let Some(ifname) = &self.builder.ifname {
+            self.dir.write_optional("ifname", ifname, self.builder.strictness)?;
+        }
+
+        if self.builder.interface_string.is_some() {
+            let err = InterfaceStringUnsupported { net_class: self.builder.net_class };
+            match self.builder.strictness {
+                Strictness::Ignore => (),
+                Strictness::Warn => log::warn!("{err}"),
+                Strictness::Error => return Err(Error::new(ErrorKind::Unsupported, err)),
+            }
+        }
+
+        if let Some(compat) = &self.builder.os_ext_compat {
+            let dir = format!("os_desc/interface.{}", self.builder.net_class.driver().to_string_lossy());
+            let strictness = self.builder.strictness;
+            self.dir.write_optional(format!("{dir}/compatible_id"), compat.compatible_id, strictness)?;
+            self.dir.write_optional(format!("{dir}/sub_compatible_id"), compat.sub_compatible_id, strictness)?;
+        }
+
+        if self.builder.net_class == NetClass::Ncm {
+            let strictness = self.builder.strictness;
+            if let Some(size) = self.builder.ntb_input_size {
+                self.dir.write_optional("ntb_input_size", size.to_string(), strictness)?;
+            }
+            if let Some(size) = self.builder.ntb_output_size {
+                self.dir.write_optional("ntb_output_size", size.to_string(), strictness)?;
+            }
+            if let Some(size) = self.builder.max_segment_size {
+                self.dir.write_optional("max_segment_size", size.to_string(), strictness)?;
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Error indicating that the interface string of a network function cannot be customized.
+///
+/// This is returned as the inner error of an [`ErrorKind::Unsupported`] I/O error
+/// when registering a network function with [`NetBuilder::interface_string`] set.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct InterfaceStringUnsupported {
+    /// Class of the network function.
+    pub net_class: NetClass,
+}
+
+impl fmt::Display for InterfaceStringUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the kernel {:?} network function does not allow customizing its interface string",
+            self.net_class
+        )
+    }
+}
+
+impl std::error::Error for InterfaceStringUnsupported {}
+
 /// Communication Device Class (CDC) network function.
+///
+/// The attributes of the function cannot be changed after registration, since the kernel
+/// refuses changes once the function is linked into a configuration, even while the gadget
+/// is not bound to a UDC.
+/// To change them, remove the gadget and register it again using an updated [`NetBuilder`].
 #[derive(Debug)]
 pub struct Net {
     dir: FunctionDir,
@@ -127,7 +371,20 @@ impl Net {
 
     /// Creates a new USB network function builder.
     pub fn builder(net_class: NetClass) -> NetBuilder {
-        NetBuilder { net_class, dev_addr: None, host_addr: None, qmult: None, interface_class: None }
+        NetBuilder {
+            net_class,
+            dev_addr: None,
+            host_addr: None,
+            qmult: None,
+            interface_class: None,
+            ntb_input_size: None,
+            ntb_output_size: None,
+            max_segment_size: None,
+            ifname: None,
+            interface_string: None,
+            os_ext_compat: None,
+            strictness: Strictness::default(),
+        }
     }
 
     /// Access to registration status.
@@ -145,8 +402,125 @@ impl Net {
         self.dir.read_string("host_addr")?.parse().map_err(|err| Error::new(ErrorKind::InvalidData, err))
     }
 
+    /// For NCM only: maximum NTB input size in bytes.
+    ///
+    /// `None` if the kernel does not provide this attribute, which is the case for mainline Linux.
+    pub fn ntb_input_size(&self) -> Result<Option<u32>> {
+        self.read_vendor_size("ntb_input_size")
+    }
+
+    /// For NCM only: maximum NTB output size in bytes.
+    ///
+    /// `None` if the kernel does not provide this attribute, which is the case for mainline Linux.
+    pub fn ntb_output_size(&self) -> Result<Option<u32>> {
+        self.read_vendor_size("ntb_output_size")
+    }
+
+    /// Reads a size attribute only provided by vendor kernels.
+    fn read_vendor_size(&self, name: &str) -> Result<Option<u32>> {
+        match self.dir.read_string(name) {
+            Ok(value) => value.parse().map(Some).map_err(|err| Error::new(ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// For NCM only: maximum segment size in bytes.
+    pub fn max_segment_size(&self) -> Result<u16> {
+        self.dir.read_string("max_segment_size")?.parse().map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    }
+
     /// Network device interface name associated with this function instance.
     pub fn ifname(&self) -> Result<OsString> {
         self.dir.read_os_string("ifname")
     }
+
+    /// Queue length multiplier for high and super speed.
+    pub fn qmult(&self) -> Result<u32> {
+        self.dir.read_string("qmult")?.parse().map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    }
+
+    /// Network device created for this function instance.
+    ///
+    /// This allows tuning the queue and offload settings of the network interface.
+    #[cfg(feature = "netlink")]
+    pub fn net_device(&self) -> Result<NetDevice> {
+        NetDevice::new(self.ifname()?)
+    }
+}
+
+/// Name of the ethtool feature for generic segmentation offload.
+#[cfg(feature = "netlink")]
+const GSO_FEATURE: &str = "tx-generic-segmentation";
+
+/// Name of the ethtool feature for generic receive offload.
+#[cfg(feature = "netlink")]
+const GRO_FEATURE: &str = "rx-gro";
+
+/// Network interface on the device side of a USB network function.
+///
+/// Provides queue and offload settings, similar to the `ethtool` and `ip link` utilities.
+/// These are accessed through netlink.
+/// Changing settings requires the `CAP_NET_ADMIN` capability.
+#[cfg(feature = "netlink")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetDevice {
+    ifname: OsString,
+}
+
+#[cfg(feature = "netlink")]
+impl NetDevice {
+    /// Network interface with the specified name.
+    pub fn new(ifname: impl AsRef<OsStr>) -> Result<Self> {
+        let ifname = ifname.as_ref();
+        if ifname.is_empty() || ifname.len() >= libc::IFNAMSIZ || ifname.as_bytes().contains(&b'/') {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid network interface name"));
+        }
+        Ok(Self { ifname: ifname.to_os_string() })
+    }
+
+    /// Network interface name.
+    pub fn ifname(&self) -> &OsStr {
+        &self.ifname
+    }
+
+    /// Length of the transmit queue in packets.
+    pub fn tx_queue_len(&self) -> Result<u32> {
+        netlink::link_u32(&self.ifname, netlink::IFLA_TXQLEN)
+    }
+
+    /// Sets the length of the transmit queue in packets.
+    pub fn set_tx_queue_len(&self, len: u32) -> Result<()> {
+        netlink::set_link_u32(&self.ifname, netlink::IFLA_TXQLEN, len)
+    }
+
+    /// Number of receive and transmit queues.
+    ///
+    /// The USB Ethernet driver of the kernel uses a single queue per direction.
+    pub fn queues(&self) -> Result<(u32, u32)> {
+        Ok((
+            netlink::link_u32(&self.ifname, netlink::IFLA_NUM_RX_QUEUES)?,
+            netlink::link_u32(&self.ifname, netlink::IFLA_NUM_TX_QUEUES)?,
+        ))
+    }
+
+    /// Whether generic segmentation offload (GSO) is enabled.
+    pub fn gso(&self) -> Result<bool> {
+        netlink::ethtool_feature(&self.ifname, GSO_FEATURE)
+    }
+
+    /// Enables or disables generic segmentation offload (GSO).
+    pub fn set_gso(&self, enabled: bool) -> Result<()> {
+        netlink::set_ethtool_feature(&self.ifname, GSO_FEATURE, enabled)
+    }
+
+    /// Whether generic receive offload (GRO) is enabled.
+    pub fn gro(&self) -> Result<bool> {
+        netlink::ethtool_feature(&self.ifname, GRO_FEATURE)
+    }
+
+    /// Enables or disables generic receive offload (GRO).
+    pub fn set_gro(&self, enabled: bool) -> Result<()> {
+        netlink::set_ethtool_feature(&self.ifname, GRO_FEATURE, enabled)
+    }
 }
@@ -1,6 +1,7 @@
 //! Other USB function.
 
 use std::{
+    any::Any,
     collections::HashMap,
     ffi::{OsStr, OsString},
     io::{Error, ErrorKind, Result},
@@ -9,9 +10,14 @@ use std::{
 };
 
 use super::{
-    util::{FunctionDir, Status},
+    util::{list_attributes, FunctionAttribute, FunctionDir, PropertyBatch, Status},
     Function, Handle,
 };
+use crate::{
+    dry_run,
+    gadget::{dir_name, remove_at, usb_gadget_dir},
+    Namespace,
+};
 
 /// Builder for other USB function implemented by a kernel function driver.
 #[derive(Debug, Clone)]
@@ -20,6 +26,10 @@ pub struct OtherBuilder {
     driver: OsString,
     /// Properties to set.
     properties: HashMap<PathBuf, Vec<u8>>,
+    /// Operations performed before properties are set.
+    plan: PropertyBatch,
+    /// Operations performed after the USB gadget has been registered.
+    post_registration: PropertyBatch,
 }
 
 impl OtherBuilder {
@@ -41,6 +51,41 @@ impl OtherBuilder {
         self.properties.insert(path, value.as_ref().to_vec());
         Ok(())
     }
+
+    /// Create a subdirectory.
+    ///
+    /// Subdirectories and symbolic links are created in the order they were added,
+    /// before properties are set.
+    /// They are removed in reverse order when the USB gadget is removed.
+    pub fn create_dir(&mut self, name: impl AsRef<Path>) -> Result<()> {
+        self.plan.create_dir(name)?;
+        Ok(())
+    }
+
+    /// Create a symbolic link within the function directory.
+    ///
+    /// See [`create_dir`](Self::create_dir) for ordering.
+    pub fn symlink(&mut self, target: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<()> {
+        self.plan.symlink(target, link)?;
+        Ok(())
+    }
+
+    /// Add a batch of operations performed before properties are set.
+    ///
+    /// See [`create_dir`](Self::create_dir) for ordering.
+    pub fn add_plan(&mut self, mut batch: PropertyBatch) {
+        self.plan.append(&mut batch);
+    }
+
+    /// Set a property value after the USB gadget has been registered.
+    ///
+    /// This is useful for properties that can only be written once the function
+    /// has been linked into a configuration.
+    /// Properties are set in the order they were added.
+    pub fn set_post_registration(&mut self, name: impl AsRef<Path>, value: impl AsRef<[u8]>) -> Result<()> {
+        self.post_registration.write(name, value)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -58,13 +103,29 @@ impl Function for OtherFunction {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
     fn register(&self) -> Result<()> {
+        self.dir.apply(self.builder.plan.clone())?;
+
         for (prop, val) in &self.builder.properties {
             self.dir.write(prop, val)?;
         }
 
         Ok(())
     }
+
+    fn post_registration(&self) -> Result<()> {
+        self.dir.apply(self.builder.post_registration.clone())
+    }
+
+    fn pre_removal(&self) -> Result<()> {
+        self.builder.plan.undo(&self.dir);
+        Ok(())
+    }
 }
 
 /// Other USB function implemented by a kernel function driver.
@@ -84,11 +145,14 @@ impl Other {
     /// Build a new other function implemented by the specified kernel function driver.
     pub fn builder(driver: impl AsRef<OsStr>) -> Result<OtherBuilder> {
         let driver = driver.as_ref();
-        if driver.as_bytes().contains(&b'.') || driver.as_bytes().contains(&b'/') || !driver.is_ascii() {
-            return Err(Error::new(ErrorKind::InvalidInput, "invalid driver name"));
-        }
-
-        Ok(OtherBuilder { driver: driver.to_os_string(), properties: HashMap::new() })
+        check_driver(driver)?;
+
+        Ok(OtherBuilder {
+            driver: driver.to_os_string(),
+            properties: HashMap::new(),
+            plan: PropertyBatch::new(),
+            post_registration: PropertyBatch::new(),
+        })
     }
 
     /// Access to registration status.
@@ -100,4 +164,46 @@ impl Other {
     pub fn get(&self, name: impl AsRef<Path>) -> Result<Vec<u8>> {
         self.dir.read(name)
     }
+
+    /// Lists the properties of the registered function and their current values.
+    pub fn attributes(&self) -> Result<Vec<FunctionAttribute>> {
+        self.dir.attributes()
+    }
+
+    /// Discovers the properties the running kernel provides for the specified function driver.
+    ///
+    /// A function instance is created within a temporary, unregistered USB gadget in
+    /// the specified namespace, its properties and their default values are read and
+    /// the gadget is removed again.
+    /// This loads the kernel module of the driver if necessary.
+    pub fn discover_attributes(
+        driver: impl AsRef<OsStr>, namespace: &Namespace,
+    ) -> Result<Vec<FunctionAttribute>> {
+        let driver = driver.as_ref();
+        check_driver(driver)?;
+
+        let gadget_dir = namespace.create_gadget_dir(&usb_gadget_dir()?)?;
+
+        // Instance names must be unique per driver across all gadgets, thus derive it
+        // from the gadget name like a registered function.
+        let func_dir = gadget_dir.join("functions").join(format!(
+            "{}.{}-0",
+            driver.to_string_lossy(),
+            dir_name(&gadget_dir)
+        ));
+        let res = dry_run::create_dir(&func_dir).and_then(|()| list_attributes(&func_dir));
+
+        if let Err(err) = remove_at(&gadget_dir, &[]) {
+            log::warn!("removing temporary gadget at {} failed: {err}", gadget_dir.display());
+        }
+        res
+    }
+}
+
+/// Checks that the function driver name is valid.
+fn check_driver(driver: &OsStr) -> Result<()> {
+    if driver.as_bytes().contains(&b'.') || driver.as_bytes().contains(&b'/') || !driver.is_ascii() {
+        return Err(Error::new(ErrorKind::InvalidInput, "invalid driver name"));
+    }
+    Ok(())
 }
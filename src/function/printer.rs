@@ -3,15 +3,28 @@
 //! The Linux kernel configuration option `CONFIG_USB_CONFIGFS_F_PRINTER` must be enabled.
 //!
 //! A device file at `/dev/g_printerN` will be created for each instance of the function, where N
-//! instance number. See `examples/printer.rs` for an example.
+//! instance number. Use [`Printer::device`] to obtain the device file of a function instance.
+//! See `examples/printer.rs` for an example.
 
 use bitflags::bitflags;
-use std::{ffi::OsString, io::Result};
+use std::{
+    any::Any,
+    collections::BTreeSet,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{Error, ErrorKind, Result},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 use super::{
-    util::{FunctionDir, Status},
+    util::{FunctionDir, Status, Strictness},
     Function, Handle,
 };
+use crate::{dry_run, AccessKind, AccessRequirements, EndpointRequirement, UdcEndpointType};
 
 /// Get printer status ioctrl ID
 pub const GADGET_GET_PRINTER_STATUS: u8 = 0x21;
@@ -33,13 +46,15 @@ bitflags! {
 }
 
 /// Builder for USB printer function.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[non_exhaustive]
 pub struct PrinterBuilder {
     /// The PNP ID string used for this printer.
     pub pnp_string: Option<String>,
     /// The number of 8k buffers to use per endpoint. The default is 10.
     pub qlen: Option<u8>,
+    /// Handling of attributes not supported by the running kernel.
+    pub strictness: Strictness,
 }
 
 impl PrinterBuilder {
@@ -48,7 +63,32 @@ impl PrinterBuilder {
     /// The returned handle must be added to a USB gadget configuration.
     pub fn build(self) -> (Printer, Handle) {
         let dir = FunctionDir::new();
-        (Printer { dir: dir.clone() }, Handle::new(PrinterFunction { builder: self, dir }))
+        let snapshot = Arc::new(Mutex::new(None));
+        (
+            Printer { dir: dir.clone(), snapshot: snapshot.clone() },
+            Handle::new(PrinterFunction { builder: self, dir, snapshot }),
+        )
+    }
+
+    /// Sets the PNP ID string used for this printer.
+    #[must_use]
+    pub fn with_pnp_string(mut self, pnp_string: impl Into<String>) -> Self {
+        self.pnp_string = Some(pnp_string.into());
+        self
+    }
+
+    /// Sets the number of 8k buffers to use per endpoint.
+    #[must_use]
+    pub fn with_qlen(mut self, qlen: u8) -> Self {
+        self.qlen = Some(qlen);
+        self
+    }
+
+    /// Sets the handling of attributes not supported by the running kernel.
+    #[must_use]
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
     }
 }
 
@@ -56,6 +96,48 @@ impl PrinterBuilder {
 struct PrinterFunction {
     builder: PrinterBuilder,
     dir: FunctionDir,
+    snapshot: Arc<Mutex<Option<Snapshot>>>,
+}
+
+/// Printer devices present when a printer function was registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    /// Minor numbers of printer devices present at registration.
+    minors: BTreeSet<u32>,
+    /// Number of printer functions of the gadget.
+    count: usize,
+    /// Position of the function among the printer functions of the gadget in creation order.
+    rank: usize,
+}
+
+/// sysfs class of printer gadget devices.
+const CLASS_DIR: &str = "/sys/class/usb_printer_gadget";
+
+/// Index of a function directory named `<driver>.<gadget>-<index>` by the gadget.
+fn creation_index(name: &OsStr) -> Option<usize> {
+    name.to_str()?.rsplit_once('-')?.1.parse().ok()
+}
+
+/// Minor numbers of currently present printer devices.
+fn present_minors() -> Result<BTreeSet<u32>> {
+    let entries = match fs::read_dir(CLASS_DIR) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut minors = BTreeSet::new();
+    for entry in entries {
+        let Ok(entry) = entry else { continue };
+        let name = entry.file_name();
+        if let Some(minor) = name.to_str().and_then(|name| name.strip_prefix("g_printer")) {
+            if let Ok(minor) = minor.parse() {
+                minors.insert(minor);
+            }
+        }
+    }
+
+    Ok(minors)
 }
 
 impl Function for PrinterFunction {
@@ -67,14 +149,62 @@ impl Function for PrinterFunction {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
+    fn required_endpoints(&self) -> Option<Vec<EndpointRequirement>> {
+        Some(vec![
+            EndpointRequirement::device_to_host(UdcEndpointType::Bulk),
+            EndpointRequirement::host_to_device(UdcEndpointType::Bulk),
+        ])
+    }
+
+    fn required_access(&self) -> AccessRequirements {
+        let mut access = AccessRequirements::new();
+        access.add_path(CLASS_DIR, AccessKind::Read, "locating printer device");
+        access.add_path("/dev", AccessKind::ReadWrite, "opening printer device node /dev/g_printerN");
+        access
+    }
+
     fn register(&self) -> Result<()> {
         if let Some(pnp_string) = &self.builder.pnp_string {
-            self.dir.write("pnp_string", pnp_string)?;
+            self.dir.write_optional("pnp_string", pnp_string, self.builder.strictness)?;
         }
         if let Some(qlen) = self.builder.qlen {
-            self.dir.write("q_len", qlen.to_string())?;
+            self.dir.write_optional("q_len", qlen.to_string(), self.builder.strictness)?;
+        }
+
+        if dry_run::is_recording() {
+            return Ok(());
+        }
+
+        // The kernel assigns the lowest free minor number when the function directory is created,
+        // thus the minor numbers of the printer functions of a gadget increase in creation order.
+        // Devices appear when bound, thus remember which devices were present beforehand.
+        let dir = self.dir.dir()?;
+        let mut indices = Vec::new();
+        for entry in fs::read_dir(dir.parent().unwrap())? {
+            let name = entry?.file_name();
+            if name.as_bytes().starts_with(b"printer.") {
+                indices.extend(creation_index(&name));
+            }
         }
+        indices.sort_unstable();
+        let own = dir.file_name().and_then(creation_index);
+        let rank = indices
+            .iter()
+            .position(|&idx| Some(idx) == own)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unexpected printer function directory name"))?;
+
+        *self.snapshot.lock().unwrap() = Some(Snapshot { minors: present_minors()?, count: indices.len(), rank });
+
+        Ok(())
+    }
 
+    fn post_removal(&self, _dir: &Path) -> Result<()> {
+        self.snapshot.lock().unwrap().take();
         Ok(())
     }
 }
@@ -83,12 +213,13 @@ impl Function for PrinterFunction {
 #[derive(Debug)]
 pub struct Printer {
     dir: FunctionDir,
+    snapshot: Arc<Mutex<Option<Snapshot>>>,
 }
 
 impl Printer {
     /// Creates a new USB printer builder.
     pub fn builder() -> PrinterBuilder {
-        PrinterBuilder { pnp_string: None, qlen: None }
+        PrinterBuilder::default()
     }
 
     /// Creates a new USB printer function and handle with f_printer defaults
@@ -100,4 +231,67 @@ impl Printer {
     pub fn status(&self) -> Status {
         self.dir.status()
     }
+
+    /// Path to the printer device file `/dev/g_printerN` of this function instance.
+    ///
+    /// The device file is created by the kernel once the USB gadget is bound to a UDC.
+    /// Since the kernel does not report the minor number of a printer function, the device is
+    /// identified among the printer devices that appeared since registration by the order in
+    /// which the printer functions of the USB gadget were created.
+    ///
+    /// A not found error is returned if the device is not present.
+    /// If printer devices of other USB gadgets appeared meanwhile, the device cannot be
+    /// identified and an error is returned.
+    pub fn device(&self) -> Result<PathBuf> {
+        let Some(snapshot) = self.snapshot.lock().unwrap().clone() else {
+            return Err(Error::new(ErrorKind::NotFound, "printer function not registered"));
+        };
+
+        let appeared: Vec<u32> = present_minors()?.difference(&snapshot.minors).copied().collect();
+        if appeared.len() < snapshot.count {
+            return Err(Error::new(ErrorKind::NotFound, "printer device not present"));
+        }
+        if appeared.len() > snapshot.count {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "printer devices of other gadgets appeared since registration, device cannot be identified",
+            ));
+        }
+
+        Ok(format!("/dev/g_printer{}", appeared[snapshot.rank]).into())
+    }
+
+    /// Path to the printer device file `/dev/g_printerN` of this function instance,
+    /// waiting for it to be created with a timeout.
+    ///
+    /// See [`device`](Self::device) for details.
+    pub fn device_timeout(&self, timeout: Duration) -> Result<PathBuf> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.device() {
+                Err(err) if err.kind() == ErrorKind::NotFound && Instant::now() < deadline => {
+                    sleep(Duration::from_millis(50))
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    return Err(Error::new(ErrorKind::TimedOut, "timeout waiting for printer device"))
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use super::creation_index;
+
+    #[test]
+    fn function_creation_index() {
+        assert_eq!(creation_index(OsStr::new("printer.usb-gadget0-3")), Some(3));
+        assert_eq!(creation_index(OsStr::new("printer.my-gadget-12")), Some(12));
+        assert_eq!(creation_index(OsStr::new("printer.usb0")), None);
+    }
 }
@@ -1,6 +1,7 @@
 //! Serial functions.
 
 use std::{
+    any::Any,
     ffi::{OsStr, OsString},
     io::{Error, ErrorKind, Result},
     path::PathBuf,
@@ -10,6 +11,7 @@ use super::{
     util::{FunctionDir, Status},
     Function, Handle,
 };
+use crate::{AccessKind, AccessRequirements, EndpointRequirement, UdcEndpointType};
 
 /// Class of USB serial function.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -51,6 +53,20 @@ impl SerialBuilder {
         let dir = FunctionDir::new();
         (Serial { dir: dir.clone() }, Handle::new(SerialFunction { builder: self, dir }))
     }
+
+    /// Sets whether the serial port is usable as console.
+    #[must_use]
+    pub fn with_console(mut self, console: bool) -> Self {
+        self.console = Some(console);
+        self
+    }
+}
+
+impl Default for SerialBuilder {
+    /// Builder for a CDC ACM serial function.
+    fn default() -> Self {
+        Serial::builder(SerialClass::Acm)
+    }
 }
 
 #[derive(Debug)]
@@ -68,6 +84,28 @@ impl Function for SerialFunction {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
+    fn required_endpoints(&self) -> Option<Vec<EndpointRequirement>> {
+        let mut eps = vec![
+            EndpointRequirement::device_to_host(UdcEndpointType::Bulk),
+            EndpointRequirement::host_to_device(UdcEndpointType::Bulk),
+        ];
+        if self.builder.serial_class == SerialClass::Acm {
+            eps.push(EndpointRequirement::device_to_host(UdcEndpointType::Interrupt));
+        }
+        Some(eps)
+    }
+
+    fn required_access(&self) -> AccessRequirements {
+        let mut access = AccessRequirements::new();
+        access.add_path("/dev", AccessKind::ReadWrite, "opening TTY device node /dev/ttyGSN");
+        access
+    }
+
     fn register(&self) -> Result<()> {
         if let Some(console) = self.builder.console {
             // Console support is optional.
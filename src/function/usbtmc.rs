@@ -0,0 +1,459 @@
+//! USB Test and Measurement Class (USBTMC) interface, implemented in user code.
+//!
+//! This provides the interface and endpoint layout, control request helpers and
+//! bulk message framing of USBTMC and its USB488 subclass on top of a
+//! [custom interface](super::custom).
+//! Instrument firmware thus only needs to handle the device-dependent messages,
+//! for example SCPI commands.
+//!
+//! The Linux kernel configuration option `CONFIG_USB_CONFIGFS_F_FS` must be enabled.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use usb_gadget::function::{custom::Custom, usbtmc::UsbTmc};
+//! use usb_gadget::{default_udc, Class, Config, Gadget, Id, Strings};
+//!
+//! let (tmc, intf) = UsbTmc::usb488("USBTMC instrument");
+//! let (custom, func) = Custom::builder().with_interface(intf).build();
+//!
+//! let udc = default_udc().expect("cannot get UDC");
+//! let reg = Gadget::new(
+//!     Class::interface_specific(),
+//!     Id::new(0x1d6b, 0x0104),
+//!     Strings::new("Clippy Manufacturer", "Rust Instrument", "RUST0123456"),
+//! )
+//! .with_config(Config::new("USBTMC Config 1").with_function(func))
+//! .bind(&udc)
+//! .expect("cannot bind to UDC");
+//! ```
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use std::io::{Error, ErrorKind, Result};
+
+use super::custom::{Endpoint, EndpointDirection, EndpointReceiver, EndpointSender, Interface, TransferType};
+use crate::Class;
+
+/// USBTMC interface class code.
+pub const CLASS: u8 = crate::classes::APPLICATION_SPECIFIC;
+/// USBTMC interface subclass code.
+pub const SUB_CLASS: u8 = 0x03;
+/// USBTMC interface protocol code.
+pub const PROTOCOL_USBTMC: u8 = 0x00;
+/// USB488 interface protocol code.
+pub const PROTOCOL_USB488: u8 = 0x01;
+
+/// USBTMC specification version 1.00 in BCD format.
+pub const BCD_USBTMC: u16 = 0x0100;
+/// USB488 specification version 1.00 in BCD format.
+pub const BCD_USB488: u16 = 0x0100;
+
+/// USBTMC class-specific control requests (`bRequest`).
+pub mod request {
+    /// Aborts a Bulk-OUT transfer.
+    pub const INITIATE_ABORT_BULK_OUT: u8 = 1;
+    /// Returns the status of an aborted Bulk-OUT transfer.
+    pub const CHECK_ABORT_BULK_OUT_STATUS: u8 = 2;
+    /// Aborts a Bulk-IN transfer.
+    pub const INITIATE_ABORT_BULK_IN: u8 = 3;
+    /// Returns the status of an aborted Bulk-IN transfer.
+    pub const CHECK_ABORT_BULK_IN_STATUS: u8 = 4;
+    /// Clears all input and output buffers.
+    pub const INITIATE_CLEAR: u8 = 5;
+    /// Returns the status of a clear operation.
+    pub const CHECK_CLEAR_STATUS: u8 = 6;
+    /// Returns the device capabilities.
+    pub const GET_CAPABILITIES: u8 = 7;
+    /// Activates a device-specific indicator for a short time.
+    pub const INDICATOR_PULSE: u8 = 64;
+    /// USB488: returns the status byte.
+    pub const READ_STATUS_BYTE: u8 = 128;
+    /// USB488: sets or clears the remote enable state.
+    pub const REN_CONTROL: u8 = 160;
+    /// USB488: places the device in local state.
+    pub const GO_TO_LOCAL: u8 = 161;
+    /// USB488: places the device in local lockout state.
+    pub const LOCAL_LOCKOUT: u8 = 162;
+}
+
+/// USBTMC status value returned in control request responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum StatusCode {
+    /// Success.
+    Success,
+    /// Operation is still pending.
+    Pending,
+    /// Failure.
+    Failed,
+    /// No transfer is in progress.
+    TransferNotInProgress,
+    /// No split transaction is in progress.
+    SplitNotInProgress,
+    /// A split transaction is in progress.
+    SplitInProgress,
+    /// Other status value.
+    Other(u8),
+}
+
+impl From<StatusCode> for u8 {
+    fn from(value: StatusCode) -> Self {
+        match value {
+            StatusCode::Success => 0x01,
+            StatusCode::Pending => 0x02,
+            StatusCode::Failed => 0x80,
+            StatusCode::TransferNotInProgress => 0x81,
+            StatusCode::SplitNotInProgress => 0x82,
+            StatusCode::SplitInProgress => 0x83,
+            StatusCode::Other(v) => v,
+        }
+    }
+}
+
+/// USB488 capabilities.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Usb488Capabilities {
+    /// USB488 specification version in BCD format.
+    pub bcd_usb488: u16,
+    /// Interface is a 488.2 USB488 interface.
+    pub is_488_2: bool,
+    /// Interface accepts `REN_CONTROL`, `GO_TO_LOCAL` and `LOCAL_LOCKOUT` requests.
+    pub ren_control: bool,
+    /// Interface accepts the `TRIGGER` Bulk-OUT message.
+    pub trigger: bool,
+    /// Device understands all mandatory SCPI commands.
+    pub scpi: bool,
+    /// Device is SR1 capable (service request).
+    pub sr1: bool,
+    /// Device is RL1 capable (remote/local).
+    pub rl1: bool,
+    /// Device is DT1 capable (device trigger).
+    pub dt1: bool,
+}
+
+impl Usb488Capabilities {
+    /// Creates USB488 capabilities for a 488.2 interface understanding SCPI.
+    pub fn new() -> Self {
+        Self { bcd_usb488: BCD_USB488, is_488_2: true, scpi: true, ..Default::default() }
+    }
+}
+
+/// USBTMC capabilities returned in response to the `GET_CAPABILITIES` request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// USBTMC specification version in BCD format.
+    pub bcd_usbtmc: u16,
+    /// Interface accepts the `INDICATOR_PULSE` request.
+    pub indicator_pulse: bool,
+    /// Interface is talk-only.
+    pub talk_only: bool,
+    /// Interface is listen-only.
+    pub listen_only: bool,
+    /// Device supports ending a Bulk-IN transfer when a byte matches the termination character.
+    pub term_char: bool,
+    /// USB488 subclass capabilities.
+    pub usb488: Option<Usb488Capabilities>,
+}
+
+impl Capabilities {
+    /// Size of the `GET_CAPABILITIES` response.
+    pub const SIZE: usize = 0x18;
+
+    /// Creates USBTMC capabilities without USB488 support.
+    pub fn new() -> Self {
+        Self { bcd_usbtmc: BCD_USBTMC, ..Default::default() }
+    }
+
+    /// Creates USBTMC capabilities with USB488 support.
+    pub fn usb488() -> Self {
+        Self { usb488: Some(Usb488Capabilities::new()), ..Self::new() }
+    }
+
+    /// Builds the response to the `GET_CAPABILITIES` request.
+    pub fn response(&self, status: StatusCode) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+
+        data[0] = status.into();
+        data[2..4].copy_from_slice(&self.bcd_usbtmc.to_le_bytes());
+        data[4] =
+            u8::from(self.listen_only) | u8::from(self.talk_only) << 1 | u8::from(self.indicator_pulse) << 2;
+        data[5] = u8::from(self.term_char);
+
+        if let Some(usb488) = &self.usb488 {
+            data[12..14].copy_from_slice(&usb488.bcd_usb488.to_le_bytes());
+            data[14] =
+                u8::from(usb488.trigger) | u8::from(usb488.ren_control) << 1 | u8::from(usb488.is_488_2) << 2;
+            data[15] = u8::from(usb488.dt1)
+                | u8::from(usb488.rl1) << 1
+                | u8::from(usb488.sr1) << 2
+                | u8::from(usb488.scpi) << 3;
+        }
+
+        data
+    }
+}
+
+/// Bulk message id (`MsgID`).
+pub mod msg_id {
+    /// Device-dependent command message from host.
+    pub const DEV_DEP_MSG_OUT: u8 = 1;
+    /// Request for device-dependent response message, or the response itself.
+    pub const DEV_DEP_MSG_IN: u8 = 2;
+    /// Vendor-specific command message from host.
+    pub const VENDOR_SPECIFIC_OUT: u8 = 126;
+    /// Request for vendor-specific response message, or the response itself.
+    pub const VENDOR_SPECIFIC_IN: u8 = 127;
+    /// USB488: trigger message.
+    pub const TRIGGER: u8 = 128;
+}
+
+/// Size of a bulk message header.
+pub const HEADER_SIZE: usize = 12;
+
+/// Header of a message received on the Bulk-OUT endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BulkOutHeader {
+    /// Device-dependent command message.
+    DevDepMsgOut {
+        /// Transfer identifier.
+        tag: u8,
+        /// Number of message data bytes, excluding header and alignment bytes.
+        transfer_size: u32,
+        /// Last byte of the transfer is the end of the message.
+        eom: bool,
+    },
+    /// Request for a device-dependent response message.
+    RequestDevDepMsgIn {
+        /// Transfer identifier.
+        tag: u8,
+        /// Maximum number of message data bytes to send.
+        transfer_size: u32,
+        /// Termination character, if the transfer should end when it is sent.
+        term_char: Option<u8>,
+    },
+    /// Vendor-specific command message.
+    VendorSpecificOut {
+        /// Transfer identifier.
+        tag: u8,
+        /// Number of message data bytes, excluding header and alignment bytes.
+        transfer_size: u32,
+    },
+    /// Request for a vendor-specific response message.
+    RequestVendorSpecificIn {
+        /// Transfer identifier.
+        tag: u8,
+        /// Maximum number of message data bytes to send.
+        transfer_size: u32,
+    },
+    /// USB488 trigger message.
+    Trigger {
+        /// Transfer identifier.
+        tag: u8,
+    },
+}
+
+impl BulkOutHeader {
+    /// Parses the header at the start of a Bulk-OUT transfer.
+    ///
+    /// Returns the header and the message data following it, without alignment bytes.
+    /// The message data may be shorter than the transfer size, if the message spans
+    /// multiple transfers.
+    pub fn parse(data: &[u8]) -> Result<(Self, &[u8])> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "USBTMC header too short"));
+        }
+
+        let (mut hdr, payload) = data.split_at(HEADER_SIZE);
+        let id = hdr.read_u8()?;
+        let tag = hdr.read_u8()?;
+        let tag_inverse = hdr.read_u8()?;
+        let _reserved = hdr.read_u8()?;
+        if tag == 0 || tag != !tag_inverse {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid USBTMC bTag"));
+        }
+
+        let transfer_size = hdr.read_u32::<LE>()?;
+        let attributes = hdr.read_u8()?;
+        let term_char = hdr.read_u8()?;
+
+        let this = match id {
+            msg_id::DEV_DEP_MSG_OUT => Self::DevDepMsgOut { tag, transfer_size, eom: attributes & 0x01 != 0 },
+            msg_id::DEV_DEP_MSG_IN => Self::RequestDevDepMsgIn {
+                tag,
+                transfer_size,
+                term_char: if attributes & 0x02 != 0 { Some(term_char) } else { None },
+            },
+            msg_id::VENDOR_SPECIFIC_OUT => Self::VendorSpecificOut { tag, transfer_size },
+            msg_id::VENDOR_SPECIFIC_IN => Self::RequestVendorSpecificIn { tag, transfer_size },
+            msg_id::TRIGGER => Self::Trigger { tag },
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unknown USBTMC MsgID")),
+        };
+
+        let payload = match this {
+            Self::DevDepMsgOut { transfer_size, .. } | Self::VendorSpecificOut { transfer_size, .. } => {
+                &payload[..payload.len().min(transfer_size.try_into().unwrap_or(usize::MAX))]
+            }
+            _ => &[],
+        };
+
+        Ok((this, payload))
+    }
+
+    /// Transfer identifier.
+    pub fn tag(&self) -> u8 {
+        match *self {
+            Self::DevDepMsgOut { tag, .. }
+            | Self::RequestDevDepMsgIn { tag, .. }
+            | Self::VendorSpecificOut { tag, .. }
+            | Self::RequestVendorSpecificIn { tag, .. }
+            | Self::Trigger { tag } => tag,
+        }
+    }
+}
+
+/// Header of a message sent on the Bulk-IN endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BulkInHeader {
+    /// Message id, either [`msg_id::DEV_DEP_MSG_IN`] or [`msg_id::VENDOR_SPECIFIC_IN`].
+    pub msg_id: u8,
+    /// Transfer identifier of the request this responds to.
+    pub tag: u8,
+    /// Number of message data bytes, excluding header and alignment bytes.
+    pub transfer_size: u32,
+    /// Last byte of the transfer is the end of the message.
+    pub eom: bool,
+    /// Transfer ended because the termination character was sent.
+    pub term_char: bool,
+}
+
+impl BulkInHeader {
+    /// Creates a header for a device-dependent response message.
+    pub fn dev_dep_msg_in(tag: u8, transfer_size: u32, eom: bool) -> Self {
+        Self { msg_id: msg_id::DEV_DEP_MSG_IN, tag, transfer_size, eom, term_char: false }
+    }
+
+    /// Creates a header for a vendor-specific response message.
+    pub fn vendor_specific_in(tag: u8, transfer_size: u32) -> Self {
+        Self { msg_id: msg_id::VENDOR_SPECIFIC_IN, tag, transfer_size, eom: false, term_char: false }
+    }
+
+    /// Encodes the header.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut data = [0; HEADER_SIZE];
+        let mut buf = &mut data[..];
+        buf.write_u8(self.msg_id).unwrap();
+        buf.write_u8(self.tag).unwrap();
+        buf.write_u8(!self.tag).unwrap();
+        buf.write_u8(0).unwrap();
+        buf.write_u32::<LE>(self.transfer_size).unwrap();
+        if self.msg_id == msg_id::DEV_DEP_MSG_IN {
+            buf.write_u8(u8::from(self.eom) | u8::from(self.term_char) << 1).unwrap();
+        }
+        data
+    }
+
+    /// Builds a complete Bulk-IN transfer consisting of this header, the message data and
+    /// alignment bytes.
+    ///
+    /// The transfer size of the header is set to the length of the message data.
+    pub fn frame(mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.transfer_size =
+            data.len().try_into().map_err(|_| Error::new(ErrorKind::InvalidInput, "USBTMC message too long"))?;
+
+        let mut frame = Vec::with_capacity(HEADER_SIZE + data.len() + 3);
+        frame.extend_from_slice(&self.to_bytes());
+        frame.extend_from_slice(data);
+        frame.resize(frame.len().next_multiple_of(4), 0);
+        Ok(frame)
+    }
+}
+
+/// Endpoints of an USBTMC interface.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct UsbTmc {
+    /// Bulk-OUT endpoint receiving messages from the host.
+    pub bulk_out: EndpointReceiver,
+    /// Bulk-IN endpoint sending responses to the host.
+    pub bulk_in: EndpointSender,
+    /// Interrupt-IN endpoint for USB488 service requests, if present.
+    pub interrupt_in: Option<EndpointSender>,
+}
+
+impl UsbTmc {
+    /// Creates an USBTMC interface with Bulk-OUT and Bulk-IN endpoints.
+    ///
+    /// The returned interface must be added to a [custom function](super::custom::Custom).
+    pub fn new(name: impl AsRef<str>) -> (Self, Interface) {
+        Self::with_protocol(name, PROTOCOL_USBTMC, false)
+    }
+
+    /// Creates an USB488 interface with Bulk-OUT, Bulk-IN and Interrupt-IN endpoints.
+    ///
+    /// The returned interface must be added to a [custom function](super::custom::Custom).
+    pub fn usb488(name: impl AsRef<str>) -> (Self, Interface) {
+        Self::with_protocol(name, PROTOCOL_USB488, true)
+    }
+
+    fn with_protocol(name: impl AsRef<str>, protocol: u8, interrupt: bool) -> (Self, Interface) {
+        let (bulk_out, bulk_out_dir) = EndpointDirection::host_to_device();
+        let (bulk_in, bulk_in_dir) = EndpointDirection::device_to_host();
+
+        let mut intf = Interface::new(Class::new(CLASS, SUB_CLASS, protocol), name)
+            .with_endpoint(Endpoint::bulk(bulk_out_dir))
+            .with_endpoint(Endpoint::bulk(bulk_in_dir));
+
+        let interrupt_in = if interrupt {
+            let (interrupt_in, interrupt_in_dir) = EndpointDirection::device_to_host();
+            let mut ep = Endpoint::custom(interrupt_in_dir, TransferType::Interrupt);
+            ep.max_packet_size_hs = 2;
+            ep.max_packet_size_ss = 2;
+            ep.interval = 4;
+            intf = intf.with_endpoint(ep);
+            Some(interrupt_in)
+        } else {
+            None
+        };
+
+        (Self { bulk_out, bulk_in, interrupt_in }, intf)
+    }
+
+    /// Builds the USB488 Interrupt-IN notification for a service request with the specified
+    /// status byte.
+    pub fn srq_notification(status_byte: u8) -> [u8; 2] {
+        [0x81, status_byte]
+    }
+
+    /// Builds the USB488 Interrupt-IN notification responding to a `READ_STATUS_BYTE` request
+    /// with the specified `bTag` value.
+    pub fn read_status_byte_notification(tag: u8, status_byte: u8) -> [u8; 2] {
+        [0x80 | (tag & 0x7f), status_byte]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bulk_framing() {
+        let data = [1, 5, !5, 0, 4, 0, 0, 0, 1, 0, 0, 0, b'*', b'I', b'D', b'N', 0xaa];
+        let (hdr, payload) = BulkOutHeader::parse(&data).unwrap();
+        assert_eq!(hdr, BulkOutHeader::DevDepMsgOut { tag: 5, transfer_size: 4, eom: true });
+        assert_eq!(payload, b"*IDN");
+
+        let data = [2, 6, !6, 0, 0, 1, 0, 0, 2, b'\n', 0, 0];
+        let (hdr, _) = BulkOutHeader::parse(&data).unwrap();
+        assert_eq!(hdr, BulkOutHeader::RequestDevDepMsgIn { tag: 6, transfer_size: 256, term_char: Some(b'\n') });
+
+        assert!(BulkOutHeader::parse(&[1, 5, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+
+        let frame = BulkInHeader::dev_dep_msg_in(6, 0, true).frame(b"ACME").unwrap();
+        assert_eq!(frame, [2, 6, !6, 0, 4, 0, 0, 0, 1, 0, 0, 0, b'A', b'C', b'M', b'E']);
+        let frame = BulkInHeader::dev_dep_msg_in(6, 0, true).frame(b"A").unwrap();
+        assert_eq!(frame.len(), 16);
+    }
+}
@@ -1,16 +1,26 @@
 //! Utils for implementing USB gadget functions.
 
+use macaddr::MacAddr6;
 use std::{
+    any::Any,
     collections::HashMap,
     ffi::{OsStr, OsString},
     fmt, fs,
     io::{Error, ErrorKind, Result},
-    os::unix::prelude::{OsStrExt, OsStringExt},
+    os::unix::{
+        fs::PermissionsExt,
+        prelude::{OsStrExt, OsStringExt},
+    },
     path::{Component, Path, PathBuf},
     sync::{Arc, Mutex, MutexGuard, Once, OnceLock},
 };
+use uuid::Uuid;
 
-use crate::{function::register_remove_handlers, trim_os_str};
+use super::Handle;
+use crate::{
+    dry_run, function::register_remove_handlers, trim_os_str, AccessRequirements, EndpointRequirement,
+    GadgetState, GadgetStateWatcher, HostDescriptors, Speed, Udc, UdcSpeedWatcher,
+};
 
 /// USB gadget function.
 pub trait Function: fmt::Debug + Send + Sync + 'static {
@@ -23,6 +33,83 @@ pub trait Function: fmt::Debug + Send + Sync + 'static {
     /// Register the function in configfs at the specified path.
     fn register(&self) -> Result<()>;
 
+    /// USB descriptors of the function as seen by the USB host at the specified speed.
+    ///
+    /// Returns `None` if the descriptors are provided by the kernel function driver.
+    fn descriptors(&self, _speed: Speed) -> Result<Option<HostDescriptors>> {
+        Ok(None)
+    }
+
+    /// Endpoints, excluding endpoint 0, the function requires from the UDC.
+    ///
+    /// Returns `None` if unknown.
+    /// By default this is determined from the [descriptors](Self::descriptors).
+    fn required_endpoints(&self) -> Option<Vec<EndpointRequirement>> {
+        let descs = self.descriptors(Speed::HighSpeed).ok()??;
+        Some(crate::dump::endpoint_requirements(&descs.data))
+    }
+
+    /// Number of endpoints, excluding endpoint 0, the function requires from the UDC.
+    ///
+    /// Returns `None` if unknown.
+    /// By default this is the number of [required endpoints](Self::required_endpoints).
+    fn endpoint_count(&self) -> Option<usize> {
+        self.required_endpoints().map(|eps| eps.len())
+    }
+
+    /// MAC addresses of the network interfaces provided by the function.
+    ///
+    /// Addresses assigned randomly by the kernel are not included.
+    /// This is used to detect duplicate addresses within a USB gadget.
+    fn mac_addresses(&self) -> Vec<MacAddr6> {
+        Vec::new()
+    }
+
+    /// Assigns MAC addresses obtained from `generate` to network interfaces whose
+    /// address has not been specified.
+    ///
+    /// This is called before registration if [`Gadget::mac_seed`](crate::Gadget::mac_seed) is set.
+    fn derive_mac_addresses(&self, _generate: &mut dyn FnMut() -> MacAddr6) {}
+
+    /// Creates an unregistered copy of the function with the same settings.
+    ///
+    /// Returns the user handle of the copy, such as [`Net`](crate::function::net::Net),
+    /// together with its function handle.
+    /// Returns `None` if the function cannot be duplicated, for example because it
+    /// is implemented in user space.
+    /// This is used by [`Gadget::clone_with`](crate::Gadget::clone_with).
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        None
+    }
+
+    /// Device interface GUIDs the function announces to Microsoft Windows hosts.
+    fn interface_guids(&self) -> Vec<Uuid> {
+        Vec::new()
+    }
+
+    /// Filesystem access and capabilities the function requires during its lifecycle,
+    /// beyond access to its configfs directory.
+    fn required_access(&self) -> AccessRequirements {
+        AccessRequirements::default()
+    }
+
+    /// Notifies the function that the USB gadget has been registered.
+    ///
+    /// This is called after all functions have been registered and linked into configurations.
+    fn post_registration(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Priority for removing the function from the USB gadget.
+    ///
+    /// Functions with a higher priority are notified and removed before functions with a
+    /// lower priority.
+    /// Functions of equal priority are removed in an unspecified order.
+    /// Can be overridden by [`RegGadget::set_removal_priority`](crate::RegGadget::set_removal_priority).
+    fn removal_priority(&self) -> i32 {
+        0
+    }
+
     /// Notifies the function that the USB gadget is about to be removed.
     fn pre_removal(&self) -> Result<()> {
         Ok(())
@@ -47,6 +134,18 @@ pub enum State {
     Removed,
 }
 
+/// Handling of optional attributes that are not supported by the running kernel.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Strictness {
+    /// Skip unsupported attributes silently.
+    Ignore,
+    /// Skip unsupported attributes and log a warning.
+    Warn,
+    /// Fail registration with a not found error.
+    #[default]
+    Error,
+}
+
 /// Provides access to the status of a USB function.
 #[derive(Clone, Debug)]
 pub struct Status(FunctionDir);
@@ -95,6 +194,45 @@ impl Status {
     pub fn path(&self) -> Option<PathBuf> {
         self.0.inner.lock().unwrap().dir.clone()
     }
+
+    /// The USB device controller (UDC) the function is bound to.
+    pub fn udc(&self) -> Option<Udc> {
+        self.0.inner.lock().unwrap().udc.clone()
+    }
+
+    /// Speed negotiated with the USB host by the UDC the function is bound to.
+    ///
+    /// Returns [`Speed::Unknown`] if the host has not enumerated the device yet.
+    pub fn speed(&self) -> Result<Speed> {
+        self.udc().ok_or_else(|| Error::new(ErrorKind::NotConnected, "USB function not bound"))?.current_speed()
+    }
+
+    /// Watch for changes of the speed negotiated with the USB host.
+    ///
+    /// Use this to adapt transfer sizes when the link comes up or changes speed.
+    pub fn watch_speed(&self) -> Result<UdcSpeedWatcher> {
+        self.udc().ok_or_else(|| Error::new(ErrorKind::NotConnected, "USB function not bound"))?.watch_speed()
+    }
+
+    /// Activation state of the USB gadget the function belongs to.
+    pub fn gadget_state(&self) -> Result<GadgetState> {
+        match self.udc() {
+            Some(udc) => Ok(GadgetState::from_udc_state(udc.state()?)),
+            None => Ok(GadgetState::Registered),
+        }
+    }
+
+    /// Watch for changes of the activation state of the USB gadget the function belongs to.
+    pub fn watch_gadget_state(&self) -> Result<GadgetStateWatcher> {
+        GadgetStateWatcher::new(
+            &self.udc().ok_or_else(|| Error::new(ErrorKind::NotConnected, "USB function not bound"))?,
+        )
+    }
+
+    /// Requested attributes that were not supported by the running kernel during registration.
+    pub fn unsupported_attributes(&self) -> Vec<PathBuf> {
+        self.0.inner.lock().unwrap().unsupported.clone()
+    }
 }
 
 /// USB gadget function directory container.
@@ -112,6 +250,8 @@ struct FunctionDirInner {
     dir: Option<PathBuf>,
     dir_was_set: bool,
     bound: bool,
+    udc: Option<Udc>,
+    unsupported: Vec<PathBuf>,
 }
 
 impl fmt::Debug for FunctionDir {
@@ -139,7 +279,9 @@ impl FunctionDir {
     pub(crate) fn set_dir(&self, function_dir: &Path) {
         let mut inner = self.inner.lock().unwrap();
         inner.dir = Some(function_dir.to_path_buf());
-        inner.dir_was_set = true;
+        // A dry run must leave the function unregistered once its directory is reset.
+        inner.dir_was_set |= !dry_run::is_recording();
+        inner.unsupported.clear();
 
         #[cfg(feature = "tokio")]
         self.notify.notify_waiters();
@@ -152,8 +294,11 @@ impl FunctionDir {
         self.notify.notify_waiters();
     }
 
-    pub(crate) fn set_bound(&self, bound: bool) {
-        self.inner.lock().unwrap().bound = bound;
+    pub(crate) fn set_bound(&self, udc: Option<&Udc>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.bound = udc.is_some();
+        inner.udc = udc.cloned();
+        drop(inner);
 
         #[cfg(feature = "tokio")]
         self.notify.notify_waiters();
@@ -174,6 +319,18 @@ impl FunctionDir {
             .ok_or_else(|| Error::new(ErrorKind::NotFound, "USB function not registered"))
     }
 
+    /// The configfs directory of the USB gadget the function belongs to.
+    pub fn gadget_dir(&self) -> Result<PathBuf> {
+        let dir = self.dir()?;
+        match dir.parent() {
+            Some(functions) if functions.file_name() == Some(OsStr::new("functions")) => {
+                functions.parent().map(Path::to_path_buf)
+            }
+            _ => None,
+        }
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("{} is not a gadget function", dir.display())))
+    }
+
     /// Driver name.
     pub fn driver(&self) -> Result<OsString> {
         let dir = self.dir()?;
@@ -202,14 +359,14 @@ impl FunctionDir {
     pub fn create_dir(&self, name: impl AsRef<Path>) -> Result<()> {
         let path = self.property_path(name)?;
         log::debug!("creating directory {}", path.display());
-        fs::create_dir(path)
+        dry_run::create_dir(&path)
     }
 
     /// Create a subdirectory and its parent directories.
     pub fn create_dir_all(&self, name: impl AsRef<Path>) -> Result<()> {
         let path = self.property_path(name)?;
         log::debug!("creating directories {}", path.display());
-        fs::create_dir_all(path)
+        dry_run::create_dir_all(&path)
     }
 
     /// Remove a subdirectory.
@@ -254,7 +411,38 @@ impl FunctionDir {
         let path = self.property_path(name)?;
         let value = value.as_ref();
         log::debug!("setting property {} to {}", path.display(), String::from_utf8_lossy(value));
-        fs::write(path, value)
+        dry_run::write(&path, value)
+    }
+
+    /// Write an optional property that may not be supported by the running kernel.
+    ///
+    /// If the property does not exist or is read-only, it is handled according to `strictness`
+    /// and recorded as an [unsupported attribute](Status::unsupported_attributes).
+    pub fn write_optional(
+        &self, name: impl AsRef<Path>, value: impl AsRef<[u8]>, strictness: Strictness,
+    ) -> Result<()> {
+        let path = self.property_path(&name)?;
+        if dry_run::is_writable(&path) {
+            return self.write(name, value);
+        }
+
+        self.inner.lock().unwrap().unsupported.push(name.as_ref().to_path_buf());
+        match strictness {
+            Strictness::Ignore => Ok(()),
+            Strictness::Warn => {
+                log::warn!("property {} is unsupported by kernel", path.display());
+                Ok(())
+            }
+            Strictness::Error => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("property {} is unsupported by kernel", name.as_ref().display()),
+            )),
+        }
+    }
+
+    /// Lists the attributes of the function directory and their current values.
+    pub fn attributes(&self) -> Result<Vec<FunctionAttribute>> {
+        list_attributes(&self.dir()?)
     }
 
     /// Create a symbolic link.
@@ -262,8 +450,203 @@ impl FunctionDir {
         let target = self.property_path(target)?;
         let link = self.property_path(link)?;
         log::debug!("creating symlink {} -> {}", link.display(), target.display());
-        std::os::unix::fs::symlink(target, link)
+        dry_run::symlink(&target, &link)
+    }
+
+    /// Apply a batch of property operations.
+    ///
+    /// The function directory is resolved once and the operations are performed in the
+    /// order they were added to the batch.
+    /// Processing stops at the first failing operation.
+    pub fn apply(&self, batch: PropertyBatch) -> Result<()> {
+        let dir = self.dir()?;
+        log::debug!("applying {} property operations to {}", batch.ops.len(), dir.display());
+
+        for op in batch.ops {
+            let res = match &op {
+                PropertyOp::Write(name, value) => dry_run::write(&dir.join(name), value),
+                PropertyOp::CreateDir(name) => dry_run::create_dir(&dir.join(name)),
+                PropertyOp::CreateDirAll(name) => dry_run::create_dir_all(&dir.join(name)),
+                PropertyOp::Symlink(target, link) => dry_run::symlink(&dir.join(target), &dir.join(link)),
+            };
+
+            log::trace!("{op:?} in {}", dir.display());
+
+            if let Err(err) = res {
+                log::debug!("{op:?} in {} failed: {err}", dir.display());
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Batch of property operations on a USB gadget function directory.
+///
+/// Operations are collected and then performed in order using [`FunctionDir::apply`],
+/// which resolves the function directory once for the whole batch.
+/// Each configfs attribute is a separate file, thus every operation is still performed
+/// by its own system call.
+#[derive(Debug, Clone, Default)]
+pub struct PropertyBatch {
+    ops: Vec<PropertyOp>,
+}
+
+#[derive(Clone)]
+enum PropertyOp {
+    Write(PathBuf, Vec<u8>),
+    CreateDir(PathBuf),
+    CreateDirAll(PathBuf),
+    Symlink(PathBuf, PathBuf),
+}
+
+impl fmt::Debug for PropertyOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Write(name, value) => {
+                write!(f, "setting property {} to {}", name.display(), String::from_utf8_lossy(value))
+            }
+            Self::CreateDir(name) => write!(f, "creating directory {}", name.display()),
+            Self::CreateDirAll(name) => write!(f, "creating directories {}", name.display()),
+            Self::Symlink(target, link) => {
+                write!(f, "creating symlink {} -> {}", link.display(), target.display())
+            }
+        }
+    }
+}
+
+impl PropertyBatch {
+    /// Creates an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of collected operations.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Whether no operations have been collected.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Appends all operations of another batch.
+    pub fn append(&mut self, other: &mut PropertyBatch) -> &mut Self {
+        self.ops.append(&mut other.ops);
+        self
+    }
+
+    /// Removes the directories and symbolic links created by this batch in reverse order.
+    ///
+    /// Errors are logged and ignored, since some paths may have been created by the kernel.
+    pub(crate) fn undo(&self, dir: &FunctionDir) {
+        let Ok(dir) = dir.dir() else { return };
+
+        for op in self.ops.iter().rev() {
+            let paths: Vec<PathBuf> = match op {
+                PropertyOp::Write(_, _) => continue,
+                PropertyOp::CreateDir(name) => vec![name.clone()],
+                PropertyOp::CreateDirAll(name) => {
+                    name.ancestors().filter(|p| !p.as_os_str().is_empty()).map(Path::to_path_buf).collect()
+                }
+                PropertyOp::Symlink(_, link) => vec![link.clone()],
+            };
+
+            for path in paths {
+                let path = dir.join(path);
+                let res = if path.is_symlink() { fs::remove_file(&path) } else { fs::remove_dir(&path) };
+                if let Err(err) = res {
+                    log::debug!("cannot remove {}: {err}", path.display());
+                }
+            }
+        }
     }
+
+    fn relative(name: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = name.as_ref();
+        if path.components().all(|c| matches!(c, Component::Normal(_))) {
+            Ok(path.to_path_buf())
+        } else {
+            Err(Error::new(ErrorKind::InvalidInput, "property path must be relative"))
+        }
+    }
+
+    /// Write a property.
+    pub fn write(&mut self, name: impl AsRef<Path>, value: impl AsRef<[u8]>) -> Result<&mut Self> {
+        self.ops.push(PropertyOp::Write(Self::relative(name)?, value.as_ref().to_vec()));
+        Ok(self)
+    }
+
+    /// Create a subdirectory.
+    pub fn create_dir(&mut self, name: impl AsRef<Path>) -> Result<&mut Self> {
+        self.ops.push(PropertyOp::CreateDir(Self::relative(name)?));
+        Ok(self)
+    }
+
+    /// Create a subdirectory and its parent directories.
+    pub fn create_dir_all(&mut self, name: impl AsRef<Path>) -> Result<&mut Self> {
+        self.ops.push(PropertyOp::CreateDirAll(Self::relative(name)?));
+        Ok(self)
+    }
+
+    /// Create a symbolic link.
+    pub fn symlink(&mut self, target: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<&mut Self> {
+        self.ops.push(PropertyOp::Symlink(Self::relative(target)?, Self::relative(link)?));
+        Ok(self)
+    }
+}
+
+/// Attribute of a USB function directory in configfs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FunctionAttribute {
+    /// Path relative to the function directory.
+    pub name: PathBuf,
+    /// Current value.
+    ///
+    /// `None` if the attribute is write-only or reading it failed.
+    pub value: Option<Vec<u8>>,
+    /// Whether the attribute can be written.
+    pub writable: bool,
+}
+
+impl FunctionAttribute {
+    /// Current value as trimmed string, if it is valid UTF-8.
+    pub fn value_str(&self) -> Option<&str> {
+        let value = std::str::from_utf8(self.value.as_ref()?).ok()?;
+        Some(value.trim_end_matches(['\0', '\n']).trim())
+    }
+}
+
+/// Lists the attributes within a function directory and its subdirectories, sorted by name.
+///
+/// Symbolic links are not followed.
+pub(crate) fn list_attributes(dir: &Path) -> Result<Vec<FunctionAttribute>> {
+    fn visit(base: &Path, dir: &Path, attrs: &mut Vec<FunctionAttribute>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                visit(base, &path, attrs)?;
+            } else if file_type.is_file() {
+                let mode = entry.metadata()?.permissions().mode();
+                attrs.push(FunctionAttribute {
+                    name: path.strip_prefix(base).unwrap().to_path_buf(),
+                    value: if mode & 0o444 != 0 { fs::read(&path).ok() } else { None },
+                    writable: mode & 0o222 != 0,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    let mut attrs = Vec::new();
+    visit(dir, dir, &mut attrs)?;
+    attrs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(attrs)
 }
 
 /// Split configfs function directory path into driver name and instance name.
@@ -458,7 +841,6 @@ pub(crate) mod value {
         }
 
         /// Take the value, if it has been sent.
-        #[allow(dead_code)]
         pub fn take(&mut self) -> Result<T, RecvError> {
             self.get()?;
 
@@ -8,6 +8,7 @@
 //! # Example
 //!
 //! ```no_run
+//! use std::time::Duration;
 //! use usb_gadget::function::video::{Uvc, Frame, Format};
 //! use usb_gadget::{default_udc, Class, Config, Gadget, Id, Strings};
 //!
@@ -25,9 +26,8 @@
 //!
 //! let udc = default_udc().expect("cannot get UDC");
 //! let reg =
-//!     // USB device descriptor base class 0xEF, 0x02, 0x01: Misc, Interface Association Descriptor
 //!     // Linux Foundation VID Gadget PID
-//!     Gadget::new(Class::new(0xEF, 0x02, 0x01), Id::new(0x1d6b, 0x0104), Strings::new("Clippy Manufacturer", "Rust Video Device", "RUST0123456"))
+//!     Gadget::new(Class::MISC_IAD, Id::new(0x1d6b, 0x0104), Strings::new("Clippy Manufacturer", "Rust Video Device", "RUST0123456"))
 //!         .with_config(Config::new("UVC Config 1").with_function(func))
 //!         .bind(&udc)
 //!         .expect("cannot bind to UDC");
@@ -39,20 +39,32 @@
 //!     udc.name().to_string_lossy(),
 //!     video.status()
 //! );
+//!
+//! let device = video.video_device(Some(Duration::from_secs(1))).expect("cannot find video device");
+//! println!("V4L2 video device at {}", device.display());
+//! for format in video.v4l2_formats() {
+//!     println!("{}x{} {:?}", format.width, format.height, format.format);
+//! }
 //! ```
 //! The gadget will bind won't enumaterate with host unless a userspace program (such as uvc-gadget)
 //! is running and responding to UVC control requests.
 
+use bitflags::bitflags;
 use std::{
+    any::Any,
     collections::HashSet,
     ffi::{OsStr, OsString},
-    fs,
+    fmt, fs,
     io::{Error, ErrorKind, Result},
     path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
 };
 
+use crate::{dry_run, hex_u16, AccessKind, AccessRequirements, Language, Speed};
+
 use super::{
-    util::{FunctionDir, Status},
+    util::{FunctionDir, PropertyBatch, Status, Strictness},
     Function, Handle,
 };
 
@@ -76,6 +88,20 @@ impl Format {
         &[Format::Yuyv, Format::Mjpeg]
     }
 
+    /// V4L2 pixel format code (`V4L2_PIX_FMT_*`) of the format on the video device.
+    pub fn fourcc(&self) -> u32 {
+        let code = match self {
+            Format::Yuyv => b"YUYV",
+            Format::Mjpeg => b"MJPG",
+        };
+        u32::from_le_bytes(*code)
+    }
+
+    /// Format from a V4L2 pixel format code.
+    pub fn from_fourcc(fourcc: u32) -> Option<Self> {
+        Self::all().iter().copied().find(|format| format.fourcc() == fourcc)
+    }
+
     fn dir_name(&self) -> &'static OsStr {
         match self {
             Format::Yuyv => OsStr::new("yuyv"),
@@ -157,7 +183,7 @@ impl From<Frame> for UvcFrame {
         UvcFrame {
             width: frame.width,
             height: frame.height,
-            intervals: frame.fps.iter().filter(|i| **i != 0).map(|i| (1_000_000_000 / *i as u32)).collect(),
+            intervals: frame.fps.iter().filter(|i| **i != 0).map(|i| 1_000_000_000 / *i as u32).collect(),
             color_matching: None,
             format: frame.format,
         }
@@ -189,31 +215,290 @@ impl UvcFrame {
         self.format.group_path().join(self.dir_name())
     }
 
+    /// Maximum size of a frame in bytes.
+    ///
+    /// This is the size of an uncompressed YUYV frame and is also used as buffer size
+    /// for compressed formats.
+    pub fn max_frame_size(&self) -> u32 {
+        self.width * self.height * 2
+    }
+
+    /// Format expected on the video device when streaming this frame.
+    pub fn v4l2_format(&self) -> V4l2Format {
+        V4l2Format {
+            format: self.format,
+            pixel_format: self.format.fourcc(),
+            width: self.width,
+            height: self.height,
+            bytes_per_line: match self.format {
+                Format::Yuyv => self.width * 2,
+                Format::Mjpeg => 0,
+            },
+            size_image: self.max_frame_size(),
+            intervals: self.intervals.iter().map(|&interval| V4l2Fract::from_interval(interval)).collect(),
+        }
+    }
+
+    /// Estimated size of a frame in bytes, as used for [bandwidth estimation](UvcBuilder::frame_rates).
+    ///
+    /// For MJPEG a compression ratio of 10:1 compared to YUYV is assumed.
+    pub fn estimated_frame_size(&self) -> u32 {
+        match self.format {
+            Format::Yuyv => self.max_frame_size(),
+            Format::Mjpeg => self.max_frame_size() / MJPEG_COMPRESSION_RATIO,
+        }
+    }
+
+    /// Highest frame rate of the available frame intervals.
+    pub fn max_fps(&self) -> f64 {
+        match self.intervals.iter().filter(|&&interval| interval != 0).min() {
+            Some(&interval) => 10_000_000.0 / interval as f64,
+            None => 0.0,
+        }
+    }
+
     /// Create a new UVC frame with the specified properties.
     pub fn new(width: u32, height: u32, format: Format, intervals: impl IntoIterator<Item = u32>) -> Self {
         Self { width, height, intervals: intervals.into_iter().collect(), color_matching: None, format }
     }
 }
 
+/// Assumed compression ratio of MJPEG frames compared to YUYV frames.
+const MJPEG_COMPRESSION_RATIO: u32 = 10;
+
+/// Size of the UVC payload header the kernel adds to each isochronous request.
+const PAYLOAD_HEADER_LEN: u64 = 12;
+
+/// Estimated frame rate of a [`UvcFrame`] given the bandwidth settings of the streaming endpoint.
+///
+/// Obtained by calling [`UvcBuilder::frame_rates`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct FrameRateEstimate {
+    /// Frame.
+    pub frame: UvcFrame,
+    /// Highest frame rate offered for the frame.
+    pub requested_fps: f64,
+    /// Highest frame rate achievable with the available bandwidth.
+    pub achievable_fps: f64,
+}
+
+impl FrameRateEstimate {
+    /// Whether the highest offered frame rate is achievable.
+    pub fn is_achievable(&self) -> bool {
+        self.requested_fps <= self.achievable_fps
+    }
+}
+
+impl fmt::Display for FrameRateEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} {:?} at {:.1} fps (achievable: {:.1} fps)",
+            self.frame.width, self.frame.height, self.frame.format, self.requested_fps, self.achievable_fps
+        )
+    }
+}
+
+/// Fraction of seconds as used by V4L2 for frame intervals (`struct v4l2_fract`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct V4l2Fract {
+    /// Numerator.
+    pub numerator: u32,
+    /// Denominator.
+    pub denominator: u32,
+}
+
+impl V4l2Fract {
+    /// Converts a UVC frame interval in 100 ns units.
+    pub fn from_interval(interval: u32) -> Self {
+        Self { numerator: interval, denominator: 10_000_000 }
+    }
+
+    /// Converts to a UVC frame interval in 100 ns units.
+    ///
+    /// Returns `None` if the denominator is zero or the result does not fit.
+    pub fn to_interval(&self) -> Option<u32> {
+        if self.denominator == 0 {
+            return None;
+        }
+        (u64::from(self.numerator) * 10_000_000 / u64::from(self.denominator)).try_into().ok()
+    }
+}
+
+/// Format of a frame as enumerated and negotiated on the V4L2 video device.
+///
+/// This corresponds to the values reported by the `VIDIOC_ENUM_FMT`, `VIDIOC_ENUM_FRAMESIZES`,
+/// `VIDIOC_ENUM_FRAMEINTERVALS` and `VIDIOC_G_FMT` ioctls of the video device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct V4l2Format {
+    /// Frame format.
+    pub format: Format,
+    /// V4L2 pixel format code.
+    pub pixel_format: u32,
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Bytes per line, zero for compressed formats.
+    pub bytes_per_line: u32,
+    /// Maximum size of a frame buffer in bytes.
+    pub size_image: u32,
+    /// Available frame intervals.
+    pub intervals: Vec<V4l2Fract>,
+}
+
+bitflags! {
+    /// Controls supported by the Processing Unit (`bmControls` field).
+    ///
+    /// The kernel describes the Processing Unit with a control bitmap of two bytes.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct ProcessingControls: u16 {
+        /// Brightness.
+        const BRIGHTNESS = 1 << 0;
+        /// Contrast.
+        const CONTRAST = 1 << 1;
+        /// Hue.
+        const HUE = 1 << 2;
+        /// Saturation.
+        const SATURATION = 1 << 3;
+        /// Sharpness.
+        const SHARPNESS = 1 << 4;
+        /// Gamma.
+        const GAMMA = 1 << 5;
+        /// White balance temperature.
+        const WHITE_BALANCE_TEMPERATURE = 1 << 6;
+        /// White balance component.
+        const WHITE_BALANCE_COMPONENT = 1 << 7;
+        /// Backlight compensation.
+        const BACKLIGHT_COMPENSATION = 1 << 8;
+        /// Gain.
+        const GAIN = 1 << 9;
+        /// Power line frequency.
+        const POWER_LINE_FREQUENCY = 1 << 10;
+        /// Automatic hue.
+        const HUE_AUTO = 1 << 11;
+        /// Automatic white balance temperature.
+        const WHITE_BALANCE_TEMPERATURE_AUTO = 1 << 12;
+        /// Automatic white balance component.
+        const WHITE_BALANCE_COMPONENT_AUTO = 1 << 13;
+        /// Digital multiplier.
+        const DIGITAL_MULTIPLIER = 1 << 14;
+        /// Digital multiplier limit.
+        const DIGITAL_MULTIPLIER_LIMIT = 1 << 15;
+    }
+}
+
+impl ProcessingControls {
+    /// Size of the control bitmap in bytes.
+    pub const SIZE: usize = 2;
+}
+
+bitflags! {
+    /// Controls supported by the Camera Terminal (`bmControls` field).
+    ///
+    /// The kernel describes the Camera Terminal with a control bitmap of three bytes.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct CameraControls: u32 {
+        /// Scanning mode.
+        const SCANNING_MODE = 1 << 0;
+        /// Auto-exposure mode.
+        const AE_MODE = 1 << 1;
+        /// Auto-exposure priority.
+        const AE_PRIORITY = 1 << 2;
+        /// Absolute exposure time.
+        const EXPOSURE_TIME_ABSOLUTE = 1 << 3;
+        /// Relative exposure time.
+        const EXPOSURE_TIME_RELATIVE = 1 << 4;
+        /// Absolute focus.
+        const FOCUS_ABSOLUTE = 1 << 5;
+        /// Relative focus.
+        const FOCUS_RELATIVE = 1 << 6;
+        /// Absolute iris.
+        const IRIS_ABSOLUTE = 1 << 7;
+        /// Relative iris.
+        const IRIS_RELATIVE = 1 << 8;
+        /// Absolute zoom.
+        const ZOOM_ABSOLUTE = 1 << 9;
+        /// Relative zoom.
+        const ZOOM_RELATIVE = 1 << 10;
+        /// Absolute pan and tilt.
+        const PAN_TILT_ABSOLUTE = 1 << 11;
+        /// Relative pan and tilt.
+        const PAN_TILT_RELATIVE = 1 << 12;
+        /// Absolute roll.
+        const ROLL_ABSOLUTE = 1 << 13;
+        /// Relative roll.
+        const ROLL_RELATIVE = 1 << 14;
+        /// Automatic focus.
+        const FOCUS_AUTO = 1 << 17;
+        /// Privacy.
+        const PRIVACY = 1 << 18;
+        /// Simple focus.
+        const FOCUS_SIMPLE = 1 << 19;
+        /// Window.
+        const WINDOW = 1 << 20;
+        /// Region of interest.
+        const REGION_OF_INTEREST = 1 << 21;
+    }
+}
+
+impl CameraControls {
+    /// Size of the control bitmap in bytes.
+    pub const SIZE: usize = 3;
+}
+
+/// Formats a control bitmap of the specified size for configfs.
+///
+/// Each byte is written as a decimal number on its own line, starting with the least significant.
+fn control_bitmap(bits: u32, size: usize) -> String {
+    bits.to_le_bytes()[..size].iter().map(|b| format!("{b}\n")).collect()
+}
+
 /// Builder for USB Video Class (UVC) function. None value uses the f_uvc default/generated value.
 #[derive(Debug, Clone, Default)]
 #[non_exhaustive]
 pub struct UvcBuilder {
     /// Interval for polling endpoint for data transfers
     pub streaming_interval: Option<u8>,
-    /// bMaxBurst for super speed companion descriptor. Valid values are 1-15.
+    /// bMaxBurst for super speed companion descriptor. Valid values are 0-15.
     pub streaming_max_burst: Option<u8>,
     /// Maximum packet size this endpoint is capable of sending or receiving when this configuration
-    /// is selected. Valid values are 1024/2048/3072.
+    /// is selected. Valid values are 1 to 3072.
+    ///
+    /// Values above 1024 use multiple transactions per interval at high speed and above.
+    /// At full speed the packet size is limited to 1023.
+    /// Use [`bandwidth`](Self::bandwidth) to validate the settings for a speed.
     pub streaming_max_packet: Option<u32>,
-    /// Video device interface name
+    /// Speed the bandwidth settings are validated against when building.
+    ///
+    /// This should be the maximum speed of the USB gadget.
+    /// If set, a warning is logged when building if the bandwidth settings are invalid
+    /// at this speed or a frame rate is not achievable with the available bandwidth.
+    pub expected_speed: Option<Speed>,
+    /// Video device interface name.
+    ///
+    /// This is shown by hosts in camera pickers and used for the interface association
+    /// and the VideoControl interface.
     pub function_name: Option<String>,
+    /// VideoStreaming interface name.
+    ///
+    /// This is provided as a string descriptor of the USB gadget and linked to the
+    /// VideoStreaming interface descriptors.
+    /// Requires Linux 6.3 or later.
+    ///
+    /// Formats and frames cannot be named, since their descriptors have no string fields.
+    pub streaming_name: Option<String>,
+    /// Handling of attributes not supported by the running kernel.
+    pub strictness: Strictness,
     /// Video frames available
     pub frames: Vec<UvcFrame>,
-    /// Processing Unit's bmControls field
-    pub processing_controls: Option<u8>,
-    /// Camera Terminal's bmControls field
-    pub camera_controls: Option<u8>,
+    /// Controls supported by the Processing Unit.
+    pub processing_controls: Option<ProcessingControls>,
+    /// Controls supported by the Camera Terminal.
+    pub camera_controls: Option<CameraControls>,
 }
 
 impl UvcBuilder {
@@ -221,8 +506,12 @@ impl UvcBuilder {
     ///
     /// The returned handle must be added to a USB gadget configuration.
     pub fn build(self) -> (Uvc, Handle) {
+        if let Some(speed) = self.expected_speed {
+            self.warn_bandwidth(speed);
+        }
+
         let dir = FunctionDir::new();
-        (Uvc { dir: dir.clone() }, Handle::new(UvcFunction { builder: self, dir }))
+        (Uvc { dir: dir.clone(), frames: self.frames.clone() }, Handle::new(UvcFunction { builder: self, dir }))
     }
 
     /// Add a frame to builder
@@ -242,6 +531,159 @@ impl UvcBuilder {
         self.frames = frames.into_iter().map(UvcFrame::from).collect();
         self
     }
+
+    /// Sets the interval for polling the streaming endpoint.
+    #[must_use]
+    pub fn with_streaming_interval(mut self, streaming_interval: u8) -> Self {
+        self.streaming_interval = Some(streaming_interval);
+        self
+    }
+
+    /// Sets bMaxBurst for the super speed companion descriptor.
+    #[must_use]
+    pub fn with_streaming_max_burst(mut self, streaming_max_burst: u8) -> Self {
+        self.streaming_max_burst = Some(streaming_max_burst);
+        self
+    }
+
+    /// Sets the maximum packet size of the streaming endpoint.
+    #[must_use]
+    pub fn with_streaming_max_packet(mut self, streaming_max_packet: u32) -> Self {
+        self.streaming_max_packet = Some(streaming_max_packet);
+        self
+    }
+
+    /// Sets the speed the bandwidth settings are validated against when building.
+    #[must_use]
+    pub fn with_expected_speed(mut self, expected_speed: Speed) -> Self {
+        self.expected_speed = Some(expected_speed);
+        self
+    }
+
+    /// Sets the video device interface name.
+    #[must_use]
+    pub fn with_function_name(mut self, function_name: impl Into<String>) -> Self {
+        self.function_name = Some(function_name.into());
+        self
+    }
+
+    /// Sets the VideoStreaming interface name.
+    #[must_use]
+    pub fn with_streaming_name(mut self, streaming_name: impl Into<String>) -> Self {
+        self.streaming_name = Some(streaming_name.into());
+        self
+    }
+
+    /// Sets the handling of attributes not supported by the running kernel.
+    #[must_use]
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Sets the controls supported by the Processing Unit.
+    #[must_use]
+    pub fn with_processing_controls(mut self, processing_controls: ProcessingControls) -> Self {
+        self.processing_controls = Some(processing_controls);
+        self
+    }
+
+    /// Sets the controls supported by the Camera Terminal.
+    #[must_use]
+    pub fn with_camera_controls(mut self, camera_controls: CameraControls) -> Self {
+        self.camera_controls = Some(camera_controls);
+        self
+    }
+
+    /// Isochronous bandwidth of the streaming endpoint in bytes per second at the specified speed.
+    ///
+    /// This validates the bandwidth settings for the speed and uses the kernel defaults for unset
+    /// settings.
+    /// An [`ErrorKind::InvalidInput`] error is returned if the settings are invalid at the speed.
+    pub fn bandwidth(&self, speed: Speed) -> Result<u64> {
+        let interval = self.streaming_interval.unwrap_or(1);
+        let max_packet = self.streaming_max_packet.unwrap_or(1024);
+        let max_burst = self.streaming_max_burst.unwrap_or(0);
+
+        if !(1..=16).contains(&interval) {
+            return Err(Error::new(ErrorKind::InvalidInput, "streaming interval must be between 1 and 16"));
+        }
+        if !(1..=3072).contains(&max_packet) {
+            return Err(Error::new(ErrorKind::InvalidInput, "streaming maximum packet size must be 1 to 3072"));
+        }
+        if max_burst > 15 {
+            return Err(Error::new(ErrorKind::InvalidInput, "streaming maximum burst must not exceed 15"));
+        }
+
+        // split into transactions per interval as done by the kernel
+        let mult = max_packet.div_ceil(1024);
+        let packet_size = u64::from(max_packet / mult);
+        let mult = u64::from(mult);
+
+        let (bytes_per_interval, interval_us) = match speed {
+            // the kernel clamps the maximum packet size to 1023 at full speed
+            Speed::FullSpeed => (u64::from(max_packet.min(1023)), 1000 << (interval - 1)),
+            Speed::HighSpeed => {
+                // high-bandwidth endpoints are forced to an interval of 1 by the kernel
+                let interval = if mult > 1 { 1 } else { interval };
+                (packet_size * mult, 125 << (interval - 1))
+            }
+            Speed::SuperSpeed | Speed::SuperSpeedPlus => {
+                if max_burst > 0 && packet_size != 1024 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "streaming maximum packet size must be a multiple of 1024 when bursting",
+                    ));
+                }
+                (packet_size * mult * (u64::from(max_burst) + 1), 125 << (interval - 1))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("isochronous video unsupported at {speed}"),
+                ))
+            }
+        };
+
+        Ok(bytes_per_interval * 1_000_000 / interval_us)
+    }
+
+    /// Estimates the achievable frame rates of the configured frames at the specified speed.
+    ///
+    /// The estimate accounts for the UVC payload headers and uses
+    /// [estimated frame sizes](UvcFrame::estimated_frame_size).
+    /// An [`ErrorKind::InvalidInput`] error is returned if the bandwidth settings are invalid at the speed.
+    pub fn frame_rates(&self, speed: Speed) -> Result<Vec<FrameRateEstimate>> {
+        let bandwidth = self.bandwidth(speed)?;
+        let interval = self.streaming_interval.unwrap_or(1);
+        let intervals_per_sec = match speed {
+            Speed::FullSpeed => 1000 >> (interval - 1).min(9),
+            _ => 8000 >> (interval - 1).min(12),
+        };
+        let payload = bandwidth.saturating_sub(PAYLOAD_HEADER_LEN * intervals_per_sec.max(1));
+
+        Ok(self
+            .frames
+            .iter()
+            .map(|frame| FrameRateEstimate {
+                frame: frame.clone(),
+                requested_fps: frame.max_fps(),
+                achievable_fps: payload as f64 / frame.estimated_frame_size().max(1) as f64,
+            })
+            .collect())
+    }
+
+    /// Logs warnings for invalid bandwidth settings and unachievable frame rates.
+    fn warn_bandwidth(&self, speed: Speed) {
+        match self.frame_rates(speed) {
+            Ok(estimates) => {
+                for estimate in estimates.iter().filter(|estimate| !estimate.is_achievable()) {
+                    log::warn!("UVC frame rate not achievable at {speed}: {estimate}");
+                }
+            }
+            Err(err) => log::warn!("invalid UVC bandwidth settings at {speed}: {err}"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -250,6 +692,39 @@ struct UvcFunction {
     dir: FunctionDir,
 }
 
+/// Links of the function directory to string descriptors of the VideoStreaming interface.
+const STREAMING_STRING_LINKS: &[&str] = &["vs0_desc", "vs1_desc"];
+
+impl UvcFunction {
+    /// Creates a string descriptor in the USB gadget and links it into the function directory.
+    fn link_string(&self, links: &[&str], value: &str) -> Result<()> {
+        let dir = self.dir.dir()?;
+        let instance = self.dir.instance()?;
+        let gadget_dir = dir.parent().and_then(|d| d.parent()).unwrap();
+
+        let lang_dir = gadget_dir.join("strings").join(hex_u16(Language::default().into()));
+        let string_dir = lang_dir.join(format!("{}-{}", instance.to_string_lossy(), links[0]));
+        log::debug!("creating string descriptor {}", string_dir.display());
+        if let Err(err) = dry_run::create_dir_all(&lang_dir).and_then(|()| dry_run::create_dir(&string_dir)) {
+            return match self.builder.strictness {
+                Strictness::Ignore => Ok(()),
+                Strictness::Warn => {
+                    log::warn!("custom string descriptors are unsupported by kernel: {err}");
+                    Ok(())
+                }
+                Strictness::Error => Err(err),
+            };
+        }
+        dry_run::write(&string_dir.join("s"), value.as_bytes())?;
+
+        for link in links {
+            dry_run::symlink(&string_dir, &dir.join(link))?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Function for UvcFunction {
     fn driver(&self) -> OsString {
         driver().into()
@@ -259,6 +734,23 @@ impl Function for UvcFunction {
         self.dir.clone()
     }
 
+    fn duplicate(&self) -> Option<(Box<dyn Any + Send>, Handle)> {
+        let (func, handle) = self.builder.clone().build();
+        Some((Box::new(func), handle))
+    }
+
+    fn removal_priority(&self) -> i32 {
+        // Some kernels require the video function to be removed before audio functions.
+        1
+    }
+
+    fn required_access(&self) -> AccessRequirements {
+        let mut access = AccessRequirements::new();
+        access.add_path(VIDEO_CLASS, AccessKind::Read, "locating V4L2 video device");
+        access.add_path("/dev", AccessKind::ReadWrite, "opening V4L2 video device node");
+        access
+    }
+
     fn register(&self) -> Result<()> {
         if self.builder.frames.is_empty() {
             return Err(Error::new(ErrorKind::InvalidInput, "at least one frame must exist"));
@@ -268,19 +760,21 @@ impl Function for UvcFunction {
             return Err(Error::new(ErrorKind::InvalidInput, "at least one interval must exist for every frame"));
         }
 
+        let mut batch = PropertyBatch::new();
+
         // format groups to link to header
         let mut formats_to_link: HashSet<Format> = HashSet::new();
 
+        // formats with color matching information
+        let mut formats_with_color_matching: HashSet<Format> = HashSet::new();
+
         // create frame descriptors
         for frame in &self.builder.frames {
-            self.dir.create_dir_all(frame.path())?;
-            self.dir.write(frame.path().join("wWidth"), frame.width.to_string())?;
-            self.dir.write(frame.path().join("wHeight"), frame.height.to_string())?;
-            self.dir.write(
-                frame.path().join("dwMaxVideoFrameBufferSize"),
-                (frame.width * frame.height * 2).to_string(),
-            )?;
-            self.dir.write(
+            batch.create_dir_all(frame.path())?;
+            batch.write(frame.path().join("wWidth"), frame.width.to_string())?;
+            batch.write(frame.path().join("wHeight"), frame.height.to_string())?;
+            batch.write(frame.path().join("dwMaxVideoFrameBufferSize"), frame.max_frame_size().to_string())?;
+            batch.write(
                 frame.path().join("dwFrameInterval"),
                 frame.intervals.iter().map(|i| i.to_string()).collect::<Vec<String>>().join("\n"),
             )?;
@@ -289,21 +783,21 @@ impl Function for UvcFunction {
             if let Some(color_matching) = frame.color_matching.as_ref() {
                 let color_matching_path = frame.format.color_matching_path();
                 // can only have one color matching information per format
-                if !color_matching_path.is_dir() {
-                    self.dir.create_dir_all(&color_matching_path)?;
-                    self.dir.write(
-                        frame.format.color_matching_path().join("bColorPrimaries"),
+                if formats_with_color_matching.insert(frame.format) {
+                    batch.create_dir_all(&color_matching_path)?;
+                    batch.write(
+                        color_matching_path.join("bColorPrimaries"),
                         color_matching.color_primaries.to_string(),
                     )?;
-                    self.dir.write(
-                        frame.format.color_matching_path().join("bTransferCharacteristics"),
+                    batch.write(
+                        color_matching_path.join("bTransferCharacteristics"),
                         color_matching.transfer_characteristics.to_string(),
                     )?;
-                    self.dir.write(
-                        frame.format.color_matching_path().join("bMatrixCoefficients"),
+                    batch.write(
+                        color_matching_path.join("bMatrixCoefficients"),
                         color_matching.matrix_coefficients.to_string(),
                     )?;
-                    self.dir.symlink(&color_matching_path, frame.format.color_matching_link_path())?;
+                    batch.symlink(&color_matching_path, frame.format.color_matching_link_path())?;
                 } else {
                     log::warn!("Color matching information already exists for format {:?}", frame.format);
                 }
@@ -312,39 +806,54 @@ impl Function for UvcFunction {
 
         // header linking format descriptors and associated frames to header after creating
         // otherwise cannot add new frames
-        self.dir.create_dir_all("streaming/header/h")?;
-        self.dir.create_dir_all("control/header/h")?;
+        batch.create_dir_all("streaming/header/h")?;
+        batch.create_dir_all("control/header/h")?;
 
         for format in formats_to_link {
-            self.dir.symlink(format.group_path(), format.header_link_path())?;
+            batch.symlink(format.group_path(), format.header_link_path())?;
         }
 
         // supported speeds: all linked but selected based on gadget speed: https://github.com/torvalds/linux/blob/master/drivers/usb/gadget/function/f_uvc.c#L732
-        self.dir.symlink("streaming/header/h", "streaming/class/fs/h")?;
-        self.dir.symlink("streaming/header/h", "streaming/class/hs/h")?;
-        self.dir.symlink("streaming/header/h", "streaming/class/ss/h")?;
-        self.dir.symlink("control/header/h", "control/class/fs/h")?;
-        self.dir.symlink("control/header/h", "control/class/ss/h")?;
+        batch.symlink("streaming/header/h", "streaming/class/fs/h")?;
+        batch.symlink("streaming/header/h", "streaming/class/hs/h")?;
+        batch.symlink("streaming/header/h", "streaming/class/ss/h")?;
+        batch.symlink("control/header/h", "control/class/fs/h")?;
+        batch.symlink("control/header/h", "control/class/ss/h")?;
 
         // controls
         if let Some(processing_controls) = self.builder.processing_controls {
-            self.dir.write("control/processing/default/bmControls", processing_controls.to_string())?;
+            batch.write(
+                "control/processing/default/bmControls",
+                control_bitmap(processing_controls.bits().into(), ProcessingControls::SIZE),
+            )?;
         }
 
         // terminal
         if let Some(camera_controls) = self.builder.camera_controls {
-            self.dir.write("control/terminal/camera/default/bmControls", camera_controls.to_string())?;
+            batch.write(
+                "control/terminal/camera/default/bmControls",
+                control_bitmap(camera_controls.bits(), CameraControls::SIZE),
+            )?;
         }
 
         // bandwidth configuration
         if let Some(interval) = self.builder.streaming_interval {
-            self.dir.write("streaming_interval", interval.to_string())?;
+            batch.write("streaming_interval", interval.to_string())?;
         }
         if let Some(max_burst) = self.builder.streaming_max_burst {
-            self.dir.write("streaming_maxburst", max_burst.to_string())?;
+            batch.write("streaming_maxburst", max_burst.to_string())?;
         }
         if let Some(max_packet) = self.builder.streaming_max_packet {
-            self.dir.write("streaming_maxpacket", max_packet.to_string())?;
+            batch.write("streaming_maxpacket", max_packet.to_string())?;
+        }
+
+        self.dir.apply(batch)?;
+
+        if let Some(function_name) = &self.builder.function_name {
+            self.dir.write_optional("function_name", function_name, self.builder.strictness)?;
+        }
+        if let Some(streaming_name) = &self.builder.streaming_name {
+            self.link_string(STREAMING_STRING_LINKS, streaming_name)?;
         }
 
         Ok(())
@@ -355,6 +864,7 @@ impl Function for UvcFunction {
 #[derive(Debug)]
 pub struct Uvc {
     dir: FunctionDir,
+    frames: Vec<UvcFrame>,
 }
 
 impl Uvc {
@@ -377,8 +887,84 @@ impl Uvc {
     pub fn status(&self) -> Status {
         self.dir.status()
     }
+
+    /// Configured frames.
+    pub fn frames(&self) -> &[UvcFrame] {
+        &self.frames
+    }
+
+    /// Formats the video device is expected to enumerate, in configuration order.
+    pub fn v4l2_formats(&self) -> Vec<V4l2Format> {
+        self.frames.iter().map(UvcFrame::v4l2_format).collect()
+    }
+
+    /// Selects the configured format closest to the requested one, as `VIDIOC_TRY_FMT` does.
+    ///
+    /// Only frames matching the requested pixel format are considered.
+    /// Among these, the frame with the smallest difference in size is chosen.
+    /// Returns `None` if the pixel format is not configured.
+    pub fn negotiate_format(&self, pixel_format: u32, width: u32, height: u32) -> Option<V4l2Format> {
+        self.frames
+            .iter()
+            .filter(|frame| frame.format.fourcc() == pixel_format)
+            .min_by_key(|frame| frame.width.abs_diff(width) as u64 + frame.height.abs_diff(height) as u64)
+            .map(UvcFrame::v4l2_format)
+    }
+
+    /// Resolves the V4L2 video device node `/dev/videoN` created for this function.
+    ///
+    /// The video device is created by the kernel once the USB gadget is bound to a UDC.
+    /// If `wait` is specified, resolution is retried until the video device appears or
+    /// the timeout expires.
+    ///
+    /// Video devices are matched by the UDC they belong to.
+    /// If multiple UVC functions are bound to the same UDC, this fails.
+    pub fn video_device(&self, wait: Option<Duration>) -> Result<PathBuf> {
+        let deadline = wait.map(|wait| Instant::now() + wait);
+
+        loop {
+            match self.find_video_device() {
+                Err(err) if err.kind() == ErrorKind::NotFound => match deadline {
+                    Some(deadline) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+                    _ => return Err(err),
+                },
+                res => return res,
+            }
+        }
+    }
+
+    fn find_video_device(&self) -> Result<PathBuf> {
+        let udc =
+            self.status().udc().ok_or_else(|| Error::new(ErrorKind::NotConnected, "USB function not bound"))?;
+        let udc_dev = fs::canonicalize(udc.dir().join("device"))?;
+
+        let mut found = Vec::new();
+        for entry in fs::read_dir(VIDEO_CLASS)? {
+            let Ok(entry) = entry else { continue };
+            let name = entry.file_name();
+            if !name.to_string_lossy().starts_with("video") {
+                continue;
+            }
+
+            // The video device belongs to the gadget device, which is a child of the UDC device.
+            let Ok(video_dev) = fs::canonicalize(entry.path().join("device")) else { continue };
+            if video_dev.parent() != Some(udc_dev.as_path()) {
+                continue;
+            }
+
+            found.push(Path::new("/dev").join(name));
+        }
+
+        match found.len() {
+            0 => Err(Error::new(ErrorKind::NotFound, "V4L2 video device not found")),
+            1 => Ok(found.remove(0)),
+            _ => Err(Error::new(ErrorKind::Other, "multiple matching V4L2 video devices found")),
+        }
+    }
 }
 
+const VIDEO_CLASS: &str = "/sys/class/video4linux";
+
 fn remove_class_headers<P: AsRef<Path>>(path: P) -> Result<()> {
     for entry in fs::read_dir(path)? {
         let Ok(entry) = entry else { continue };
@@ -394,6 +980,14 @@ fn remove_class_headers<P: AsRef<Path>>(path: P) -> Result<()> {
 }
 
 pub(crate) fn remove_handler(dir: PathBuf) -> Result<()> {
+    // remove links to string descriptors
+    for link in STREAMING_STRING_LINKS {
+        let path = dir.join(link);
+        if path.is_symlink() {
+            fs::remove_file(path)?;
+        }
+    }
+
     // remove header links for control and streaming
     let ctrl_class = dir.join("control/class");
     if ctrl_class.is_dir() {
@@ -450,3 +1044,37 @@ pub(crate) fn remove_handler(dir: PathBuf) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Format, Uvc, UvcFrame};
+    use crate::Speed;
+
+    #[test]
+    fn bandwidth() {
+        let mut builder = Uvc::builder().with_frames([
+            UvcFrame::new(640, 360, Format::Yuyv, [333_333]),
+            UvcFrame::new(1920, 1080, Format::Mjpeg, [166_666]),
+        ]);
+
+        assert_eq!(builder.bandwidth(Speed::HighSpeed).unwrap(), 8_192_000);
+        assert_eq!(builder.bandwidth(Speed::FullSpeed).unwrap(), 1_023_000);
+        assert!(builder.bandwidth(Speed::LowSpeed).is_err());
+
+        builder.streaming_max_packet = Some(3072);
+        assert_eq!(builder.bandwidth(Speed::HighSpeed).unwrap(), 24_576_000);
+        assert_eq!(builder.bandwidth(Speed::FullSpeed).unwrap(), 1_023_000);
+
+        let estimates = builder.frame_rates(Speed::HighSpeed).unwrap();
+        assert!(estimates[0].is_achievable());
+        assert!(!estimates[1].is_achievable());
+
+        builder.streaming_max_packet = Some(1024);
+        builder.streaming_max_burst = Some(15);
+        assert_eq!(builder.bandwidth(Speed::SuperSpeed).unwrap(), 131_072_000);
+        assert!(builder.frame_rates(Speed::SuperSpeed).unwrap().iter().all(|estimate| estimate.is_achievable()));
+
+        builder.streaming_max_packet = Some(1500);
+        assert!(builder.bandwidth(Speed::SuperSpeed).is_err());
+    }
+}
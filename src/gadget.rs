@@ -1,29 +1,39 @@
 //! USB gadget.
 
+use macaddr::MacAddr6;
 use nix::errno::Errno;
 use std::{
+    any::Any,
+    cmp,
     collections::{HashMap, HashSet},
     ffi::{OsStr, OsString},
-    fmt, fs,
+    fmt,
+    fs::{self, File},
     io::{Error, ErrorKind, Result},
     os::unix::{
-        fs::symlink,
+        fs::FileExt,
         prelude::{OsStrExt, OsStringExt},
     },
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::Duration,
 };
 
 use crate::{
-    configfs_dir, function,
+    access::{check_path, permission_error},
+    classes, cleanup, configfs_dir, dry_run, function,
     function::{
-        util::{call_remove_handler, init_remove_handlers},
+        util::{call_remove_handler, init_remove_handlers, Strictness},
         Handle,
     },
     hex_u16, hex_u8,
-    lang::Language,
+    lang::{Language, LanguageSet},
     request_module, trim_os_str,
-    udc::Udc,
-    Speed,
+    udc::{udcs, GadgetState, GadgetStateWatcher, Udc, UdcState},
+    AccessKind, AccessRequirements, Capability, DeferredBind, DeferredGadget, DescriptorDump, DryRun,
+    EndpointShortage, HostDescriptors, HostMatch, IdentityKey, InterfaceMatch, PowerHooks, PowerWatcher, Speed,
+    UdcCapabilities,
 };
 
 /// USB gadget ioctl magic byte.
@@ -42,7 +52,61 @@ pub struct Class {
 
 impl Class {
     /// Vendor specific class code.
-    pub const VENDOR_SPECIFIC: u8 = 0xff;
+    pub const VENDOR_SPECIFIC: u8 = classes::VENDOR_SPECIFIC;
+
+    /// Device class of composite devices using interface association descriptors.
+    pub const MISC_IAD: Self = Self::new(classes::MISC, 0x02, 0x01);
+
+    /// Device class of CDC devices without interface association descriptors.
+    pub const CDC_DEVICE: Self = Self::new(classes::CDC, 0x00, 0x00);
+
+    /// CDC abstract control model (ACM) interface with AT commands (V.250).
+    pub const CDC_ACM: Self = Self::new(classes::CDC, 0x02, 0x01);
+
+    /// CDC Ethernet control model (ECM) interface.
+    pub const CDC_ECM: Self = Self::new(classes::CDC, 0x06, 0x00);
+
+    /// CDC network control model (NCM) interface.
+    pub const CDC_NCM: Self = Self::new(classes::CDC, 0x0d, 0x00);
+
+    /// CDC data interface.
+    pub const CDC_DATA: Self = Self::new(classes::CDC_DATA, 0x00, 0x00);
+
+    /// HID interface without boot protocol.
+    pub const HID: Self = Self::new(classes::HID, 0x00, 0x00);
+
+    /// HID boot keyboard interface.
+    pub const HID_BOOT_KEYBOARD: Self = Self::new(classes::HID, 0x01, 0x01);
+
+    /// HID boot mouse interface.
+    pub const HID_BOOT_MOUSE: Self = Self::new(classes::HID, 0x01, 0x02);
+
+    /// Mass storage interface using SCSI commands and bulk-only transport.
+    pub const MSC_BOT: Self = Self::new(classes::MSC, 0x06, 0x50);
+
+    /// Bidirectional printer interface.
+    pub const PRINTER: Self = Self::new(classes::PRINTER, 0x01, 0x02);
+
+    /// Still image capture interface using PTP.
+    pub const STILL_IMAGE_PTP: Self = Self::new(classes::STILL_IMAGE, 0x01, 0x01);
+
+    /// Audio control interface.
+    pub const AUDIO_CONTROL: Self = Self::new(classes::AUDIO, 0x01, 0x00);
+
+    /// Audio streaming interface.
+    pub const AUDIO_STREAMING: Self = Self::new(classes::AUDIO, 0x02, 0x00);
+
+    /// MIDI streaming interface.
+    pub const MIDI_STREAMING: Self = Self::new(classes::AUDIO, 0x03, 0x00);
+
+    /// Video control interface.
+    pub const VIDEO_CONTROL: Self = Self::new(classes::VIDEO, 0x01, 0x00);
+
+    /// Video streaming interface.
+    pub const VIDEO_STREAMING: Self = Self::new(classes::VIDEO, 0x02, 0x00);
+
+    /// Device firmware upgrade (DFU) interface in DFU mode.
+    pub const DFU: Self = Self::new(classes::APPLICATION_SPECIFIC, 0x01, 0x02);
 
     /// Creates a new USB device or interface class.
     pub const fn new(class: u8, sub_class: u8, protocol: u8) -> Self {
@@ -59,7 +123,7 @@ impl Class {
     ///
     /// Can only be used as device class.
     pub const fn interface_specific() -> Self {
-        Self::new(0, 0, 0)
+        Self::new(classes::INTERFACE_SPECIFIC, 0, 0)
     }
 }
 
@@ -80,7 +144,7 @@ impl Id {
 }
 
 /// USB gadget description strings.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Strings {
     /// Manufacturer name.
     pub manufacturer: String,
@@ -102,7 +166,12 @@ impl Strings {
 }
 
 /// USB gadget operating system descriptor.
+///
+/// Requires support for OS descriptors in the kernel's configfs gadget interface.
+/// It cannot be served from user space by a [custom function](crate::function::custom::Custom)
+/// instead, since the kernel answers the host's request for the OS string descriptor itself.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub struct OsDescriptor {
     /// Vendor code for requests.
     pub vendor_code: u8,
@@ -112,20 +181,134 @@ pub struct OsDescriptor {
     ///
     /// Hosts which expect the "OS Descriptors" ask only for configurations at index 0,
     /// but Linux-based USB devices can provide more than one configuration.
+    /// Ignored if [`config_description`](Self::config_description) is set.
+    #[deprecated(
+        since = "0.8.0",
+        note = "use OsDescriptor::with_config instead, since indices break when reordering configurations"
+    )]
     pub config: usize,
+    /// Description of the configuration in [`Gadget::configs`] to be reported at index 0.
+    ///
+    /// It is resolved when the gadget is registered and must match the description
+    /// in the default language of exactly one configuration.
+    /// A missing description counts as empty.
+    pub config_description: Option<String>,
 }
 
 impl OsDescriptor {
+    /// Signature of the Microsoft OS descriptor.
+    pub const MICROSOFT_SIGN: &'static str = "MSFT100";
+
+    /// Maximum length of the signature in UTF-16 code units.
+    pub const QW_SIGN_LEN: usize = 7;
+
     /// Creates a new instance.
+    #[allow(deprecated)]
     pub const fn new(vendor_code: u8, qw_sign: String) -> Self {
-        Self { vendor_code, qw_sign, config: 0 }
+        Self { vendor_code, qw_sign, config: 0, config_description: None }
+    }
+
+    /// Creates a new instance using a signature encoded in UTF-16LE.
+    ///
+    /// Trailing zero code units are ignored.
+    pub fn from_utf16le(vendor_code: u8, qw_sign: &[u8]) -> Result<Self> {
+        if qw_sign.len() % 2 != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "UTF-16LE signature must have an even length"));
+        }
+
+        let mut units: Vec<u16> = qw_sign.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        while units.last() == Some(&0) {
+            units.pop();
+        }
+        let qw_sign = String::from_utf16(&units)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "signature is not valid UTF-16LE"))?;
+
+        let this = Self::new(vendor_code, qw_sign);
+        this.validate()?;
+        Ok(this)
     }
 
     /// The Microsoft OS descriptor.
     ///
     /// Uses vendor code 0xf0 for requests.
     pub fn microsoft() -> Self {
-        Self { vendor_code: 0xf0, qw_sign: "MSFT100".to_string(), config: 0 }
+        Self::new(0xf0, Self::MICROSOFT_SIGN.to_string())
+    }
+
+    /// Reports the specified configuration at index 0.
+    ///
+    /// The configuration is identified by its description in the default language,
+    /// which must be unique within the gadget.
+    #[must_use]
+    pub fn with_config(mut self, config: &Config) -> Self {
+        self.config_description = Some(Self::description(config).to_string());
+        self
+    }
+
+    /// Description of the configuration in the default language.
+    fn description(config: &Config) -> &str {
+        config.description.get(&Language::default()).map(String::as_str).unwrap_or_default()
+    }
+
+    /// Index of the configuration in `configs` to be reported at index 0.
+    #[allow(deprecated)]
+    fn config_index(&self, configs: &[Config]) -> Result<usize> {
+        let Some(desc) = &self.config_description else {
+            return if self.config < configs.len() {
+                Ok(self.config)
+            } else {
+                Err(Error::new(ErrorKind::InvalidInput, "invalid configuration index in OS descriptor"))
+            };
+        };
+
+        let mut matching = configs.iter().enumerate().filter(|(_, config)| Self::description(config) == desc);
+        match (matching.next(), matching.next()) {
+            (Some((idx, _)), None) => Ok(idx),
+            (None, _) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("no configuration with description {desc:?} for OS descriptor"),
+            )),
+            (Some(_), Some(_)) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("multiple configurations with description {desc:?} for OS descriptor"),
+            )),
+        }
+    }
+
+    /// Whether the signature is the one expected by Microsoft Windows.
+    pub fn is_microsoft(&self) -> bool {
+        self.qw_sign == Self::MICROSOFT_SIGN
+    }
+
+    /// The signature encoded in UTF-16LE and padded to its full length, as sent to the USB host.
+    pub fn qw_sign_utf16le(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = self.qw_sign.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        data.resize(2 * Self::QW_SIGN_LEN, 0);
+        data
+    }
+
+    /// Checks that the signature can be represented by the kernel.
+    ///
+    /// The signature must be non-empty, fit into [`QW_SIGN_LEN`](Self::QW_SIGN_LEN)
+    /// UTF-16 code units and must not contain control characters, since the kernel
+    /// silently truncates longer signatures.
+    pub fn validate(&self) -> Result<()> {
+        if self.qw_sign.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "OS descriptor signature must not be empty"));
+        }
+        if self.qw_sign.encode_utf16().count() > Self::QW_SIGN_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("OS descriptor signature must not exceed {} UTF-16 code units", Self::QW_SIGN_LEN),
+            ));
+        }
+        if self.qw_sign.chars().any(|c| c.is_control()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "OS descriptor signature must not contain control characters",
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -149,6 +332,11 @@ impl From<WebUsbVersion> for u16 {
 }
 
 /// USB gadget WebUSB descriptor.
+///
+/// Requires Linux 6.3 or later.
+/// Since the kernel generates the BOS descriptor announcing WebUSB support, it cannot be
+/// served from user space on older kernels.
+/// [`WebUsbRequests`](crate::function::custom::WebUsbRequests) only extends the requests answered.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct WebUsb {
     /// WebUSB specification version number.
@@ -180,6 +368,10 @@ pub struct Config {
     pub description: HashMap<Language, String>,
     /// Functions, i.e. USB interfaces, present in this configuration.
     pub functions: HashSet<function::Handle>,
+    /// Order in which functions are linked.
+    order: Vec<function::Handle>,
+    /// Names of function links.
+    link_names: HashMap<function::Handle, String>,
 }
 
 impl Config {
@@ -191,6 +383,8 @@ impl Config {
             remote_wakeup: false,
             description: [(Language::default(), description.as_ref().to_string())].into(),
             functions: Default::default(),
+            order: Vec::new(),
+            link_names: HashMap::new(),
         }
     }
 
@@ -202,24 +396,117 @@ impl Config {
     }
 
     /// Adds a USB function (interface) to this configuration.
+    ///
+    /// Functions are linked into the configuration in the order they were added.
+    /// The kernel assigns interface numbers in this order, thus each function
+    /// receives the interface numbers following those of the previously added functions.
+    /// For example, adding a CDC ACM function (two interfaces) followed by a custom function
+    /// with one interface results in interfaces 0 and 1 for CDC ACM and interface 2 for the
+    /// custom function.
+    /// The resulting layout is stable between runs, allowing hosts to cache driver bindings.
+    ///
+    /// Use [`set_function_order`](Self::set_function_order) to change the order and
+    /// [`Custom::interface_numbers`](function::custom::Custom::interface_numbers) to query the
+    /// interface numbers of a custom function after binding.
     pub fn add_function(&mut self, function_handle: function::Handle) {
-        self.functions.insert(function_handle);
+        if self.functions.insert(function_handle.clone()) {
+            self.order.push(function_handle);
+        }
     }
 
     /// Adds a USB function (interface) to this configuration.
+    ///
+    /// See [`add_function`](Self::add_function) for how interface numbers are assigned.
     #[must_use]
     pub fn with_function(mut self, function_handle: function::Handle) -> Self {
         self.add_function(function_handle);
         self
     }
 
+    /// Adds a USB function (interface) to this configuration using the specified link name.
+    ///
+    /// See [`set_link_name`](Self::set_link_name) for details.
+    #[must_use]
+    pub fn with_function_as(mut self, function_handle: function::Handle, link_name: impl AsRef<str>) -> Self {
+        self.set_link_name(&function_handle, link_name);
+        self.add_function(function_handle);
+        self
+    }
+
+    /// Sets the name of the link to the function in the configuration directory.
+    ///
+    /// By default, the link is named after the function directory.
+    /// The name must be unique within the configuration and must not contain a slash.
+    pub fn set_link_name(&mut self, function_handle: &function::Handle, link_name: impl AsRef<str>) {
+        self.link_names.insert(function_handle.clone(), link_name.as_ref().to_string());
+    }
+
+    /// Sets the order in which functions are linked into the configuration.
+    ///
+    /// This determines the interface numbers assigned by the kernel,
+    /// see [`add_function`](Self::add_function).
+    /// Functions not yet present in this configuration are added.
+    /// Functions present in this configuration but not specified follow in the order
+    /// they were added.
+    pub fn set_function_order(&mut self, order: impl IntoIterator<Item = function::Handle>) {
+        let mut new_order = Vec::new();
+        for func in order {
+            if !new_order.contains(&func) {
+                self.functions.insert(func.clone());
+                new_order.push(func);
+            }
+        }
+
+        for func in self.order.drain(..) {
+            if !new_order.contains(&func) {
+                new_order.push(func);
+            }
+        }
+
+        self.order = new_order;
+    }
+
+    /// The order in which functions are linked into the configuration.
+    pub fn function_order(&self) -> Vec<function::Handle> {
+        self.ordered_functions().cloned().collect()
+    }
+
     fn register(
         &self, gadget_dir: &Path, idx: usize, func_dirs: &HashMap<function::Handle, PathBuf>,
+        written: &mut WrittenAttrs,
     ) -> Result<PathBuf> {
         let dir = gadget_dir.join("configs").join(format!("c.{idx}"));
         log::debug!("creating config at {}", dir.display());
-        fs::create_dir(&dir)?;
+        dry_run::create_dir(&dir)?;
 
+        self.write_attributes(&dir, written)?;
+        self.write_description(&dir, written)?;
+
+        let mut link_names = HashSet::new();
+        for func in self.ordered_functions() {
+            let func_dir = &func_dirs[func];
+            let link_name = match self.link_names.get(func) {
+                Some(name) => OsStr::new(name),
+                None => func_dir.file_name().unwrap(),
+            };
+            if link_name.is_empty()
+                || link_name == "."
+                || link_name == ".."
+                || link_name.as_bytes().contains(&b'/')
+                || !link_names.insert(link_name)
+            {
+                return Err(Error::new(ErrorKind::InvalidInput, "invalid or duplicate function link name"));
+            }
+
+            log::debug!("adding function {} as {}", func_dir.display(), link_name.to_string_lossy());
+            dry_run::symlink(func_dir, &dir.join(link_name))?;
+        }
+
+        Ok(dir)
+    }
+
+    /// Writes the attributes to the configuration directory.
+    fn write_attributes(&self, dir: &Path, written: &mut WrittenAttrs) -> Result<()> {
         let mut attributes = 1 << 7;
         if self.self_powered {
             attributes |= 1 << 6;
@@ -228,22 +515,40 @@ impl Config {
             attributes |= 1 << 5;
         }
 
-        fs::write(dir.join("bmAttributes"), hex_u8(attributes))?;
-        fs::write(dir.join("MaxPower"), self.max_power.to_string())?;
+        written.write(dir.join("bmAttributes"), hex_u8(attributes))?;
+        written.write(dir.join("MaxPower"), self.max_power.to_string())
+    }
 
+    /// Writes the description strings to the configuration directory.
+    fn write_description(&self, dir: &Path, written: &mut WrittenAttrs) -> Result<()> {
         for (&lang, desc) in &self.description {
             let lang_dir = dir.join("strings").join(hex_u16(lang.into()));
-            fs::create_dir(&lang_dir)?;
-            fs::write(lang_dir.join("configuration"), desc)?;
+            if !lang_dir.is_dir() {
+                dry_run::create_dir(&lang_dir)?;
+            }
+            written.write(lang_dir.join("configuration"), desc)?;
         }
+        Ok(())
+    }
 
-        for func in &self.functions {
-            let func_dir = &func_dirs[func];
-            log::debug!("adding function {}", func_dir.display());
-            symlink(func_dir, dir.join(func_dir.file_name().unwrap()))?;
+    /// Copy of the configuration with functions replaced according to `map`.
+    fn map_functions(&self, map: &HashMap<function::Handle, function::Handle>) -> Self {
+        let get = |func: &function::Handle| map.get(func).unwrap_or(func).clone();
+        Self {
+            functions: self.functions.iter().map(get).collect(),
+            order: self.order.iter().map(get).collect(),
+            link_names: self.link_names.iter().map(|(func, name)| (get(func), name.clone())).collect(),
+            ..self.clone()
         }
+    }
 
-        Ok(dir)
+    /// Functions in link order.
+    ///
+    /// Functions inserted directly into [`functions`](Self::functions) follow in unspecified order.
+    fn ordered_functions(&self) -> impl Iterator<Item = &function::Handle> {
+        let mut rest: Vec<_> = self.functions.iter().filter(|func| !self.order.contains(func)).collect();
+        rest.sort();
+        self.order.iter().filter(|func| self.functions.contains(func)).chain(rest)
     }
 }
 
@@ -275,6 +580,88 @@ impl From<UsbVersion> for u16 {
     }
 }
 
+/// Maximum packet size of endpoint 0 (`bMaxPacketSize0`).
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MaxPacketSize0 {
+    /// Chosen based on the [maximum speed](Gadget::max_speed) and [USB version](Gadget::usb_version).
+    ///
+    /// This is 9, i.e. 512 bytes, for SuperSpeed capable gadgets, 8 for low speed gadgets
+    /// and 64 otherwise.
+    #[default]
+    Auto,
+    /// Specified value.
+    ///
+    /// This must be 8, 16, 32 or 64 bytes at full speed, 8 at low speed and 64 at high speed.
+    /// SuperSpeed requires the exponent 9, i.e. 512 bytes.
+    Value(u8),
+}
+
+impl From<u8> for MaxPacketSize0 {
+    fn from(value: u8) -> Self {
+        Self::Value(value)
+    }
+}
+
+/// Invalid maximum packet size of endpoint 0 for the speed of a USB gadget.
+///
+/// This is returned as the inner error of an [`ErrorKind::InvalidInput`] I/O error
+/// when registering a gadget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InvalidMaxPacketSize0 {
+    /// Specified value.
+    pub value: u8,
+    /// Speed the value is invalid for.
+    pub speed: Speed,
+    /// Values allowed at this speed.
+    pub allowed: Vec<u8>,
+}
+
+impl fmt::Display for InvalidMaxPacketSize0 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bMaxPacketSize0 {} is invalid at {}, allowed are {:?}", self.value, self.speed, self.allowed)
+    }
+}
+
+impl std::error::Error for InvalidMaxPacketSize0 {}
+
+/// Functions duplicated by [`Gadget::clone_with`].
+///
+/// Maps each function of the original gadget to its duplicate.
+#[derive(Default)]
+pub struct Duplicates {
+    funcs: HashMap<Handle, (Handle, Option<Box<dyn Any + Send>>)>,
+}
+
+impl fmt::Debug for Duplicates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.funcs.iter().map(|(orig, (dup, _))| (orig, dup))).finish()
+    }
+}
+
+impl Duplicates {
+    /// Handle of the duplicate of the specified function of the original gadget.
+    pub fn handle(&self, original: &Handle) -> Option<&Handle> {
+        self.funcs.get(original).map(|(dup, _)| dup)
+    }
+
+    /// Takes the user handle of the duplicate of the specified function of the original gadget,
+    /// for example a [`Net`](function::net::Net).
+    ///
+    /// Returns `None` if the function has not been duplicated, its user handle has already
+    /// been taken or is not of type `T`.
+    pub fn take<T: Any>(&mut self, original: &Handle) -> Option<T> {
+        let (_, user) = self.funcs.get_mut(original)?;
+        match user.take()?.downcast::<T>() {
+            Ok(value) => Some(*value),
+            Err(value) => {
+                *user = Some(value);
+                None
+            }
+        }
+    }
+}
+
 /// USB gadget definition.
 ///
 /// Fields set to `None` are left at their kernel-provided default values.
@@ -288,7 +675,7 @@ pub struct Gadget {
     /// USB device strings.
     pub strings: HashMap<Language, Strings>,
     /// Maximum endpoint 0 packet size.
-    pub max_packet_size0: u8,
+    pub max_packet_size0: MaxPacketSize0,
     /// Device release number in BCD format.
     ///
     /// No hexadecimal digit must exceed 9.
@@ -296,6 +683,10 @@ pub struct Gadget {
     /// USB specification version.
     pub usb_version: UsbVersion,
     /// Maximum speed supported by driver.
+    ///
+    /// When binding, it is validated against the maximum speed of the UDC according to
+    /// the [strictness](Self::strictness).
+    /// Use [`RegGadget::negotiated_speed`] to query the speed after enumeration.
     pub max_speed: Option<Speed>,
     /// OS descriptor extension.
     pub os_descriptor: Option<OsDescriptor>,
@@ -303,6 +694,36 @@ pub struct Gadget {
     pub web_usb: Option<WebUsb>,
     /// USB device configurations.
     pub configs: Vec<Config>,
+    /// Handling of extensions not supported by the running kernel, of a
+    /// [maximum speed](Self::max_speed) exceeding the speed of the UDC and of
+    /// the UDC lacking endpoints required by the functions.
+    ///
+    /// Defaults to [`Strictness::Warn`].
+    pub strictness: Strictness,
+    /// Namespace the gadget is registered in.
+    pub namespace: Namespace,
+    /// Maximum number of threads used to register functions concurrently.
+    ///
+    /// Function directories are independent of each other, thus registering them
+    /// concurrently speeds up registration of gadgets with many functions,
+    /// especially on slow storage.
+    /// Defaults to 1, i.e. functions are registered sequentially.
+    pub register_threads: usize,
+    /// Seed for deriving MAC addresses of network functions.
+    ///
+    /// If set, network interfaces of functions without an explicitly specified
+    /// MAC address are assigned locally administered addresses derived from this seed.
+    /// The same seed and gadget definition always yields the same addresses.
+    /// If unset, the kernel assigns random addresses.
+    pub mac_seed: Option<u64>,
+    /// Allows registering the gadget without configurations or with configurations
+    /// containing no functions.
+    ///
+    /// This is intended for testing USB host stacks with unusual device topologies.
+    /// Linux refuses to bind such gadgets; binding fails with
+    /// [`BindErrorCause::NoConfigurations`] or [`BindErrorCause::EmptyConfiguration`].
+    /// Disabled by default, causing registration to fail with an [`ErrorKind::InvalidInput`] error.
+    pub allow_empty_configs: bool,
 }
 
 impl Gadget {
@@ -312,13 +733,18 @@ impl Gadget {
             device_class,
             id,
             strings: [(Language::default(), strings)].into(),
-            max_packet_size0: 64,
+            max_packet_size0: MaxPacketSize0::Auto,
             device_release: 0x0000,
             usb_version: UsbVersion::default(),
             max_speed: None,
             os_descriptor: None,
             web_usb: None,
             configs: Vec::new(),
+            strictness: Strictness::Warn,
+            namespace: Namespace::default(),
+            register_threads: 1,
+            mac_seed: None,
+            allow_empty_configs: false,
         }
     }
 
@@ -334,6 +760,13 @@ impl Gadget {
         self
     }
 
+    /// Sets whether configurations without functions and gadgets without configurations are allowed.
+    #[must_use]
+    pub fn with_allow_empty_configs(mut self, allow_empty_configs: bool) -> Self {
+        self.allow_empty_configs = allow_empty_configs;
+        self
+    }
+
     /// Sets the OS descriptor.
     #[must_use]
     pub fn with_os_descriptor(mut self, os_descriptor: OsDescriptor) -> Self {
@@ -348,135 +781,915 @@ impl Gadget {
         self
     }
 
-    /// Register the USB gadget.
+    /// Sets the namespace the gadget is registered in.
+    #[must_use]
+    pub fn with_namespace(mut self, namespace: &Namespace) -> Self {
+        self.namespace = namespace.clone();
+        self
+    }
+
+    /// Sets the maximum number of threads used to register functions concurrently.
+    #[must_use]
+    pub fn with_register_threads(mut self, register_threads: usize) -> Self {
+        self.register_threads = register_threads;
+        self
+    }
+
+    /// Normalizes the strings of the gadget and its configurations to the specified languages.
     ///
-    /// At least one [configuration](Config) must be added before the gadget
-    /// can be registered.
-    pub fn register(self) -> Result<RegGadget> {
-        if self.configs.is_empty() {
-            return Err(Error::new(ErrorKind::InvalidInput, "USB gadget must have at least one configuration"));
+    /// See [`LanguageSet`] for details.
+    pub fn normalize_languages(&mut self, languages: &LanguageSet) -> Result<()> {
+        self.strings = languages.normalize(&self.strings)?;
+        for config in &mut self.configs {
+            config.description = languages.normalize(&config.description)?;
         }
+        Ok(())
+    }
 
-        let usb_gadget_dir = usb_gadget_dir()?;
+    /// Creates a copy of the gadget with duplicated functions and applies `modify` to it.
+    ///
+    /// Since a function can only be registered once, each function is replaced by an
+    /// unregistered copy with the same settings, allowing both gadgets to be registered
+    /// at the same time, for example on two UDCs.
+    /// Functions implemented in user space, such as [custom functions](function::custom),
+    /// cannot be duplicated and cause an [`ErrorKind::Unsupported`] error.
+    ///
+    /// The duplicated functions are returned together with the copy.
+    ///
+    /// If a [MAC address seed](Self::mac_seed) is set and not changed by `modify`, the copy
+    /// uses a seed derived from it, its namespace and its id; thus copies differing in neither
+    /// obtain the same MAC addresses.
+    /// Explicitly specified MAC addresses are copied and thus should be changed in `modify`.
+    pub fn clone_with(&self, modify: impl FnOnce(&mut Gadget)) -> Result<(Gadget, Duplicates)> {
+        let mut duplicates = Duplicates::default();
+        for func in self.configs.iter().flat_map(|c| &c.functions) {
+            if !duplicates.funcs.contains_key(func) {
+                let (user, dup) = func.get().duplicate().ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::Unsupported,
+                        format!("function {} cannot be duplicated", func.get().driver().to_string_lossy()),
+                    )
+                })?;
+                duplicates.funcs.insert(func.clone(), (dup, Some(user)));
+            }
+        }
 
-        let mut gadget_idx: u16 = 0;
-        let dir = loop {
-            let dir = usb_gadget_dir.join(format!("usb-gadget{gadget_idx}"));
-            match fs::create_dir(&dir) {
-                Ok(()) => break dir,
-                Err(err) if err.kind() == ErrorKind::AlreadyExists => (),
-                Err(err) => return Err(err),
+        let map = duplicates.funcs.iter().map(|(orig, (dup, _))| (orig.clone(), dup.clone())).collect();
+        let mut gadget = Gadget {
+            configs: self.configs.iter().map(|config| config.map_functions(&map)).collect(),
+            ..self.clone()
+        };
+        modify(&mut gadget);
+
+        if let Some(seed) = self.mac_seed.filter(|&seed| gadget.mac_seed == Some(seed)) {
+            let mut key = [0; IdentityKey::SIZE];
+            key[..8].copy_from_slice(&seed.to_le_bytes());
+            key[8..].copy_from_slice(&seed.to_le_bytes());
+            let context =
+                format!("clone:{}:{:04x}:{:04x}", gadget.namespace.prefix(), gadget.id.vendor, gadget.id.product);
+            gadget.mac_seed = Some(IdentityKey::new(key).mac_seed(context.as_bytes()));
+        }
+
+        Ok((gadget, duplicates))
+    }
+
+    /// Whether the gadget may operate at SuperSpeed.
+    fn is_super_speed(&self) -> bool {
+        u16::from(self.usb_version) >= 0x0300
+            && self.max_speed.map_or(true, |speed| matches!(speed, Speed::SuperSpeed | Speed::SuperSpeedPlus))
+    }
+
+    /// Validated `bMaxPacketSize0` value written to configfs.
+    ///
+    /// Fails with an [`ErrorKind::InvalidInput`] error containing [`InvalidMaxPacketSize0`]
+    /// if the specified value is invalid for the speed of the gadget.
+    pub fn max_packet_size0_value(&self) -> Result<u8> {
+        let (speed, allowed) = if self.is_super_speed() {
+            (self.max_speed.unwrap_or(Speed::SuperSpeed), vec![9])
+        } else {
+            match self.max_speed {
+                Some(Speed::LowSpeed) => (Speed::LowSpeed, vec![8]),
+                Some(Speed::HighSpeed) => (Speed::HighSpeed, vec![64]),
+                _ => (Speed::FullSpeed, vec![8, 16, 32, 64]),
             }
-            gadget_idx = gadget_idx
-                .checked_add(1)
-                .ok_or_else(|| Error::new(ErrorKind::OutOfMemory, "USB gadgets exhausted"))?;
         };
 
-        log::debug!("registering gadget at {}", dir.display());
+        match self.max_packet_size0 {
+            MaxPacketSize0::Auto => Ok(*allowed.last().unwrap()),
+            MaxPacketSize0::Value(value) if allowed.contains(&value) => Ok(value),
+            MaxPacketSize0::Value(value) => {
+                Err(Error::new(ErrorKind::InvalidInput, InvalidMaxPacketSize0 { value, speed, allowed }))
+            }
+        }
+    }
 
-        fs::write(dir.join("bDeviceClass"), hex_u8(self.device_class.class))?;
-        fs::write(dir.join("bDeviceSubClass"), hex_u8(self.device_class.sub_class))?;
-        fs::write(dir.join("bDeviceProtocol"), hex_u8(self.device_class.protocol))?;
+    /// `bMaxPacketSize0` as seen by the host at the specified speed.
+    fn max_packet_size0_at(&self, speed: Speed) -> u8 {
+        match (speed, self.max_packet_size0) {
+            (Speed::SuperSpeed | Speed::SuperSpeedPlus, _) => 9,
+            (Speed::LowSpeed, _) => 8,
+            (_, MaxPacketSize0::Value(value)) if value != 9 => value,
+            _ => 64,
+        }
+    }
 
-        fs::write(dir.join("idVendor"), hex_u16(self.id.vendor))?;
-        fs::write(dir.join("idProduct"), hex_u16(self.id.product))?;
+    /// Sets the maximum packet size of endpoint 0.
+    #[must_use]
+    pub fn with_max_packet_size0(mut self, max_packet_size0: impl Into<MaxPacketSize0>) -> Self {
+        self.max_packet_size0 = max_packet_size0.into();
+        self
+    }
 
-        fs::write(dir.join("bMaxPacketSize0"), hex_u8(self.max_packet_size0))?;
-        fs::write(dir.join("bcdDevice"), hex_u16(self.device_release))?;
-        fs::write(dir.join("bcdUSB"), hex_u16(self.usb_version.into()))?;
+    /// Sets the seed for deriving MAC addresses of network functions.
+    #[must_use]
+    pub fn with_mac_seed(mut self, mac_seed: u64) -> Self {
+        self.mac_seed = Some(mac_seed);
+        self
+    }
 
-        if let Some(v) = self.max_speed {
-            fs::write(dir.join("max_speed"), v.to_string())?;
+    /// Derives the serial number and MAC address seed of the gadget from the specified key.
+    ///
+    /// The serial number of all languages is replaced by a value derived from the key
+    /// and the vendor and product id, so that it cannot be linked to other devices.
+    #[must_use]
+    pub fn with_identity(mut self, key: &IdentityKey) -> Self {
+        let context = format!("{:04x}:{:04x}", self.id.vendor, self.id.product);
+        let serial_number = key.serial_number(context.as_bytes());
+        for strings in self.strings.values_mut() {
+            strings.serial_number = serial_number.clone();
+        }
+        self.mac_seed = Some(key.mac_seed(context.as_bytes()));
+        self
+    }
+
+    /// USB descriptors of the gadget as seen by the USB host at the specified speed.
+    ///
+    /// This consists of the device descriptor followed by each configuration descriptor
+    /// together with the descriptors of its functions.
+    ///
+    /// Only functions implemented in user space, such as [custom functions](function::custom),
+    /// provide their descriptors; descriptors of kernel function drivers are not included.
+    /// Interface numbers, endpoint addresses and string indices within function descriptors
+    /// are relative to each function.
+    /// Device strings use indices 1 to 3, followed by the configuration descriptions.
+    pub fn host_descriptors(&self, speed: Speed) -> Result<HostDescriptors> {
+        let (mut data, strings, configs) = self.descriptor_parts(speed)?;
+        for (config, funcs) in configs {
+            data.extend(config);
+            for (_driver, descs) in funcs {
+                data.extend(descs.map(|descs| descs.data).unwrap_or_default());
+            }
         }
+        Ok(HostDescriptors::new(data, strings))
+    }
 
-        if let Some(webusb) = &self.web_usb {
-            let webusb_dir = dir.join("webusb");
-            if webusb_dir.is_dir() {
-                fs::write(webusb_dir.join("bVendorCode"), hex_u8(webusb.vendor_code))?;
-                fs::write(webusb_dir.join("bcdVersion"), hex_u16(webusb.version.into()))?;
-                fs::write(webusb_dir.join("landingPage"), &webusb.landing_page)?;
-                fs::write(webusb_dir.join("use"), "1")?;
-            } else {
-                log::warn!("WebUSB descriptor is unsupported by kernel");
+    /// Renders the USB descriptors of the gadget at the specified speed as text
+    /// in the style of `lsusb -v`.
+    ///
+    /// This allows reviewing the descriptors the USB host will see before registering
+    /// the gadget.
+    /// See [`host_descriptors`](Self::host_descriptors) for limitations.
+    pub fn dump(&self, speed: Speed) -> Result<String> {
+        let (device, strings, configs) = self.descriptor_parts(speed)?;
+
+        let mut dump = DescriptorDump::new(speed);
+        dump.push(&device, &strings);
+        for (config, funcs) in configs {
+            dump.push(&config, &strings);
+            for (driver, descs) in funcs {
+                match descs {
+                    Some(descs) => dump.push(&descs.data, &descs.strings),
+                    None => dump.note(
+                        2,
+                        &format!("Function {}: descriptors provided by kernel", driver.to_string_lossy()),
+                    ),
+                }
             }
         }
 
-        for (&lang, strs) in &self.strings {
-            let lang_dir = dir.join("strings").join(hex_u16(lang.into()));
-            fs::create_dir(&lang_dir)?;
+        Ok(dump.finish())
+    }
 
-            fs::write(lang_dir.join("manufacturer"), &strs.manufacturer)?;
-            fs::write(lang_dir.join("product"), &strs.product)?;
-            fs::write(lang_dir.join("serialnumber"), &strs.serial_number)?;
-        }
+    /// Identifying properties of the gadget for locating it from the USB host.
+    ///
+    /// Strings are taken from the default language.
+    /// See [`host_descriptors`](Self::host_descriptors) for limitations regarding interfaces.
+    pub fn host_match(&self) -> Result<HostMatch> {
+        let strings = self.strings.get(&Language::default()).cloned().unwrap_or_default();
 
-        let functions: HashSet<_> = self.configs.iter().flat_map(|c| &c.functions).collect();
-        let mut func_dirs = HashMap::new();
-        for (func_idx, &func) in functions.iter().enumerate() {
-            let func_dir = dir.join(
-                dir.join("functions")
-                    .join(format!("{}.usb-gadget{gadget_idx}-{func_idx}", func.get().driver().to_str().unwrap())),
-            );
-            log::debug!("creating function at {}", func_dir.display());
-            fs::create_dir(&func_dir)?;
+        let mut interfaces = Vec::new();
+        let mut interface_guids = Vec::new();
+        for (idx, config) in self.configs.iter().enumerate() {
+            let value = u8::try_from(idx + 1)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "too many configurations"))?;
+            for func in config.ordered_functions() {
+                if let Some(descs) = func.get().descriptors(Speed::HighSpeed)? {
+                    interfaces.extend(
+                        crate::dump::interface_classes(&descs.data)
+                            .into_iter()
+                            .map(|interface_class| InterfaceMatch { config: value, interface_class }),
+                    );
+                }
+                for guid in func.get().interface_guids() {
+                    if !interface_guids.contains(&guid) {
+                        interface_guids.push(guid);
+                    }
+                }
+            }
+        }
 
-            func.get().dir().set_dir(&func_dir);
-            func.get().register()?;
+        Ok(HostMatch {
+            id: self.id,
+            device_class: self.device_class,
+            manufacturer: strings.manufacturer,
+            product: strings.product,
+            serial_number: strings.serial_number,
+            interfaces,
+            interface_guids,
+        })
+    }
 
-            func_dirs.insert(func.clone(), func_dir);
+    /// Device descriptor, strings and configuration descriptors with function descriptors.
+    #[allow(clippy::type_complexity)]
+    fn descriptor_parts(
+        &self, speed: Speed,
+    ) -> Result<(Vec<u8>, HashMap<u8, String>, Vec<(Vec<u8>, Vec<(OsString, Option<HostDescriptors>)>)>)> {
+        let super_speed = matches!(speed, Speed::SuperSpeed | Speed::SuperSpeedPlus);
+        let mut strings = HashMap::new();
+        if let Some(strs) = self.strings.get(&Language::default()) {
+            strings.insert(1, strs.manufacturer.clone());
+            strings.insert(2, strs.product.clone());
+            strings.insert(3, strs.serial_number.clone());
         }
 
-        let mut config_dirs = Vec::new();
+        let mut device = vec![18, 0x01];
+        device.extend(u16::from(self.usb_version).to_le_bytes());
+        device.extend([self.device_class.class, self.device_class.sub_class, self.device_class.protocol]);
+        device.push(self.max_packet_size0_at(speed));
+        device.extend(self.id.vendor.to_le_bytes());
+        device.extend(self.id.product.to_le_bytes());
+        device.extend(self.device_release.to_le_bytes());
+        device.extend([1, 2, 3]);
+        device.push(
+            self.configs
+                .len()
+                .try_into()
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "too many configurations"))?,
+        );
+
+        let mut configs = Vec::new();
         for (idx, config) in self.configs.iter().enumerate() {
-            let dir = config.register(&dir, idx + 1, &func_dirs)?;
-            config_dirs.push(dir);
-        }
+            let string_idx = u8::try_from(idx + 4)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "too many configurations"))?;
+            if let Some(description) = config.description.get(&Language::default()) {
+                strings.insert(string_idx, description.clone());
+            }
 
-        if let Some(os_desc) = &self.os_descriptor {
-            let os_desc_dir = dir.join("os_desc");
-            if os_desc_dir.is_dir() {
-                fs::write(os_desc_dir.join("b_vendor_code"), hex_u8(os_desc.vendor_code))?;
-                fs::write(os_desc_dir.join("qw_sign"), &os_desc.qw_sign)?;
-                fs::write(os_desc_dir.join("use"), "1")?;
+            let mut funcs = Vec::new();
+            let mut total_len = 9;
+            let mut interfaces = 0;
+            for func in config.ordered_functions() {
+                let descs = func.get().descriptors(speed)?;
+                if let Some(descs) = &descs {
+                    total_len += descs.data.len();
+                    interfaces += crate::dump::count_interfaces(&descs.data);
+                }
+                funcs.push((func.get().driver(), descs));
+            }
 
-                let config_dir = config_dirs.get(os_desc.config).ok_or_else(|| {
-                    Error::new(ErrorKind::InvalidInput, "invalid configuration index in OS descriptor")
-                })?;
-                symlink(config_dir, os_desc_dir.join(config_dir.file_name().unwrap()))?;
-            } else {
-                log::warn!("USB OS descriptor is unsupported by kernel");
+            let mut attributes = 0x80;
+            if config.self_powered {
+                attributes |= 0x40;
+            }
+            if config.remote_wakeup {
+                attributes |= 0x20;
             }
+            let max_power =
+                if super_speed { config.max_power.div_ceil(8) } else { config.max_power.min(500).div_ceil(2) };
+
+            let mut desc = vec![9, 0x02];
+            desc.extend(
+                u16::try_from(total_len)
+                    .map_err(|_| Error::new(ErrorKind::InvalidInput, "descriptors too long"))?
+                    .to_le_bytes(),
+            );
+            desc.extend([
+                interfaces,
+                u8::try_from(idx + 1).unwrap_or(u8::MAX),
+                string_idx,
+                attributes,
+                max_power.min(255) as u8,
+            ]);
+            configs.push((desc, funcs));
         }
 
-        log::debug!("gadget at {} registered", dir.display());
-        Ok(RegGadget { dir, attached: true, func_dirs })
+        Ok((device, strings, configs))
     }
 
-    /// Register and bind USB gadget to a USB device controller (UDC).
+    /// Filesystem access and capabilities required to register, bind and remove the gadget.
     ///
-    /// At least one [configuration](Config) must be added before the gadget
-    /// can be bound.
-    pub fn bind(self, udc: &Udc) -> Result<RegGadget> {
-        let reg = self.register()?;
-        reg.bind(Some(udc))?;
-        Ok(reg)
+    /// This includes the requirements of all functions of the gadget and can be used to
+    /// set up a sandbox, such as a Landlock ruleset.
+    /// See [`RegGadget::required_access`] for the requirements after registration.
+    pub fn required_access(&self) -> Result<AccessRequirements> {
+        let usb_gadget_dir = configfs_dir()?.join("usb_gadget");
+
+        let mut access = AccessRequirements::new();
+        access.add_path("/proc/mounts", AccessKind::Read, "locating configfs");
+        access.add_path(&usb_gadget_dir, AccessKind::ReadWrite, "writing gadget attributes");
+        access.add_path(&usb_gadget_dir, AccessKind::Create, "creating gadget");
+        access.add_path(&usb_gadget_dir, AccessKind::Remove, "removing gadget");
+        add_udc_access(&mut access)?;
+        access.add_capability(Capability::SysModule);
+
+        for config in &self.configs {
+            for func in config.ordered_functions() {
+                access.extend(func.get().required_access());
+            }
+        }
+
+        Ok(access)
     }
-}
 
-/// USB gadget registered with the system.
-///
-/// If this was obtained by calling [`Gadget::bind`], the USB gadget will be
-/// unbound and removed when this is dropped.
-///
-/// Call [`registered`] to obtain all gadgets registered on the system.
-#[must_use = "The USB gadget is removed when RegGadget is dropped."]
-pub struct RegGadget {
-    dir: PathBuf,
-    attached: bool,
-    func_dirs: HashMap<Handle, PathBuf>,
-}
+    /// Derives MAC addresses if a seed is set and checks that all MAC addresses are unique.
+    fn assign_mac_addresses(&self) -> Result<()> {
+        let mut functions = Vec::new();
+        for func in self.configs.iter().flat_map(|c| c.ordered_functions()) {
+            if !functions.contains(&func) {
+                functions.push(func);
+            }
+        }
 
-impl fmt::Debug for RegGadget {
+        if let Some(seed) = self.mac_seed {
+            let mut generate = mac_generator(seed);
+            for func in &functions {
+                func.get().derive_mac_addresses(&mut generate);
+            }
+        }
+
+        let mut addrs = HashSet::new();
+        for func in &functions {
+            for addr in func.get().mac_addresses() {
+                if !addrs.insert(addr) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("MAC address {addr} is used more than once in USB gadget"),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Register the USB gadget.
+    ///
+    /// At least one [configuration](Config) must be added before the gadget
+    /// can be registered.
+    pub fn register(self) -> Result<RegGadget> {
+        if !self.allow_empty_configs {
+            if self.configs.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "USB gadget must have at least one configuration",
+                ));
+            }
+            if let Some(idx) = self.configs.iter().position(|config| config.functions.is_empty()) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("USB gadget configuration {idx} must have at least one function"),
+                ));
+            }
+        }
+        if let Some(os_desc) = &self.os_descriptor {
+            os_desc.validate()?;
+            os_desc.config_index(&self.configs)?;
+        }
+        self.assign_mac_addresses()?;
+        let max_packet_size0 = self.max_packet_size0_value()?;
+
+        let usb_gadget_dir = usb_gadget_dir()?;
+        if !dry_run::is_recording() {
+            check_path(&usb_gadget_dir, AccessKind::Create)?;
+        }
+
+        let dir = self.namespace.create_gadget_dir(&usb_gadget_dir)?;
+
+        log::debug!("registering gadget at {}", dir.display());
+
+        let mut written = WrittenAttrs::default();
+
+        written.write(dir.join("bDeviceClass"), hex_u8(self.device_class.class))?;
+        written.write(dir.join("bDeviceSubClass"), hex_u8(self.device_class.sub_class))?;
+        written.write(dir.join("bDeviceProtocol"), hex_u8(self.device_class.protocol))?;
+
+        written.write(dir.join("idVendor"), hex_u16(self.id.vendor))?;
+        written.write(dir.join("idProduct"), hex_u16(self.id.product))?;
+
+        written.write(dir.join("bMaxPacketSize0"), hex_u8(max_packet_size0))?;
+        written.write(dir.join("bcdDevice"), hex_u16(self.device_release))?;
+        written.write(dir.join("bcdUSB"), hex_u16(self.usb_version.into()))?;
+
+        if let Some(v) = self.max_speed {
+            written.write(dir.join("max_speed"), v.to_string())?;
+        }
+
+        let mut unsupported = Vec::new();
+
+        if let Some(webusb) = &self.web_usb {
+            let webusb_dir = dir.join("webusb");
+            if dry_run::is_dir(&webusb_dir) {
+                written.write(webusb_dir.join("bVendorCode"), hex_u8(webusb.vendor_code))?;
+                written.write(webusb_dir.join("bcdVersion"), hex_u16(webusb.version.into()))?;
+                written.write(webusb_dir.join("landingPage"), &webusb.landing_page)?;
+                written.write(webusb_dir.join("use"), "1")?;
+            } else {
+                self.unsupported(&mut unsupported, "webusb")?;
+            }
+        }
+
+        for (&lang, strs) in &self.strings {
+            let lang_dir = dir.join("strings").join(hex_u16(lang.into()));
+            dry_run::create_dir(&lang_dir)?;
+
+            written.write(lang_dir.join("manufacturer"), &strs.manufacturer)?;
+            written.write(lang_dir.join("product"), &strs.product)?;
+            written.write(lang_dir.join("serialnumber"), &strs.serial_number)?;
+        }
+
+        let functions: HashSet<_> = self.configs.iter().flat_map(|c| &c.functions).collect();
+        let mut func_dirs = HashMap::new();
+        for (func_idx, &func) in functions.iter().enumerate() {
+            let func_dir = dir.join(dir.join("functions").join(format!(
+                "{}.{}-{func_idx}",
+                func.get().driver().to_str().unwrap(),
+                dir_name(&dir)
+            )));
+            log::debug!("creating function at {}", func_dir.display());
+            dry_run::create_dir(&func_dir)?;
+
+            func.get().dir().set_dir(&func_dir);
+            func_dirs.insert(func.clone(), func_dir);
+        }
+        self.register_functions(&functions.into_iter().collect::<Vec<_>>())?;
+
+        let mut config_dirs = Vec::new();
+        for (idx, config) in self.configs.iter().enumerate() {
+            let dir = config.register(&dir, idx + 1, &func_dirs, &mut written)?;
+            config_dirs.push(dir);
+        }
+
+        if let Some(os_desc) = &self.os_descriptor {
+            let os_desc_dir = dir.join("os_desc");
+            if dry_run::is_dir(&os_desc_dir) {
+                written.write(os_desc_dir.join("b_vendor_code"), hex_u8(os_desc.vendor_code))?;
+                written.write(os_desc_dir.join("qw_sign"), &os_desc.qw_sign)?;
+                written.write(os_desc_dir.join("use"), "1")?;
+
+                let config_dir = &config_dirs[os_desc.config_index(&self.configs)?];
+                dry_run::symlink(config_dir, &os_desc_dir.join(config_dir.file_name().unwrap()))?;
+            } else {
+                self.unsupported(&mut unsupported, "os_desc")?;
+            }
+        }
+
+        for func in func_dirs.keys() {
+            func.get().post_registration()?;
+        }
+
+        log::debug!("gadget at {} registered", dir.display());
+        if !dry_run::is_recording() {
+            cleanup::track(&dir);
+        }
+        Ok(RegGadget {
+            dir,
+            attached: true,
+            func_dirs,
+            unsupported,
+            written: written.0,
+            udc_file: None,
+            removal_priorities: HashMap::new(),
+            pre_removal_hooks: Vec::new(),
+            post_removal_hooks: Vec::new(),
+            definition: Some(self),
+        })
+    }
+
+    /// Registers the functions, whose directories have been created, using up to
+    /// [`register_threads`](Self::register_threads) threads.
+    fn register_functions(&self, funcs: &[&Handle]) -> Result<()> {
+        // Recording a dry run is local to the current thread.
+        let threads =
+            if dry_run::is_recording() { 1 } else { self.register_threads.clamp(1, funcs.len().max(1)) };
+        if threads == 1 {
+            for func in funcs {
+                func.get().register()?;
+            }
+            return Ok(());
+        }
+
+        log::debug!("registering {} functions using {threads} threads", funcs.len());
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        while !failed.load(Ordering::Relaxed) {
+                            let Some(func) = funcs.get(next.fetch_add(1, Ordering::Relaxed)) else { break };
+                            if let Err(err) = func.get().register() {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(err);
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers.into_iter().try_for_each(|worker| worker.join().unwrap())
+        })
+    }
+
+    /// Records the configfs operations [`register`](Self::register) would perform
+    /// without changing the filesystem.
+    ///
+    /// Kernel support for optional attributes is assumed.
+    /// Custom functions record creating and mounting their FunctionFS directory, but
+    /// do not write their descriptors, since this requires a mounted FunctionFS.
+    /// If configfs is not mounted, its usual mount point `/sys/kernel/config` is assumed.
+    pub fn dry_run(&self) -> Result<DryRun> {
+        let (res, ops) = dry_run::record(|| self.clone().register());
+
+        for config in &self.configs {
+            for func in &config.functions {
+                func.get().dir().reset_dir();
+            }
+        }
+
+        let mut reg = res?;
+        reg.detach();
+        Ok(DryRun { dir: reg.dir.clone(), ops })
+    }
+
+    /// Handles an extension not supported by the running kernel.
+    fn unsupported(&self, unsupported: &mut Vec<PathBuf>, name: &str) -> Result<()> {
+        unsupported.push(name.into());
+
+        let hint = match name {
+            "os_desc" => " (OS descriptors cannot be served from user space)",
+            "webusb" => " (Linux 6.3 or later is required, WebUSB cannot be served from user space)",
+            _ => "",
+        };
+        match self.strictness {
+            Strictness::Ignore => Ok(()),
+            Strictness::Warn => {
+                log::warn!("gadget attribute {name} is unsupported by kernel{hint}");
+                Ok(())
+            }
+            Strictness::Error => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("gadget attribute {name} is unsupported by kernel{hint}"),
+            )),
+        }
+    }
+
+    /// Register and bind USB gadget to a USB device controller (UDC).
+    ///
+    /// At least one [configuration](Config) must be added before the gadget
+    /// can be bound.
+    pub fn bind(self, udc: &Udc) -> Result<RegGadget> {
+        let reg = self.register()?;
+        reg.bind(Some(udc))?;
+        Ok(reg)
+    }
+
+    /// Register and bind USB gadget to a USB device controller (UDC) and wait until
+    /// the USB host has selected a configuration.
+    ///
+    /// If the timeout is reached, an [`ErrorKind::TimedOut`] error containing
+    /// [`EnumerationTimeout`] is returned and the gadget is removed.
+    /// Use [`RegGadget::bind_and_wait`] to keep the gadget in this case.
+    pub fn bind_and_wait(self, udc: &Udc, timeout: Duration) -> Result<RegGadget> {
+        let reg = self.register()?;
+        reg.bind_and_wait(udc, timeout)?;
+        Ok(reg)
+    }
+
+    /// Register USB gadget and bind it once a USB device controller (UDC) meeting the
+    /// criteria of `deferred` appears.
+    ///
+    /// This is useful on systems where the UDC driver is probed after the gadget is set up.
+    /// If a matching UDC is present already, the gadget is bound to it immediately.
+    pub fn register_deferred(self, deferred: DeferredBind) -> Result<DeferredGadget> {
+        let reg = self.register()?;
+        Ok(DeferredGadget::new(reg, deferred))
+    }
+
+    /// Predicts whether the USB gadget can be bound to the specified USB device controller (UDC).
+    ///
+    /// Returns the causes expected to prevent binding; an empty list indicates that binding
+    /// is expected to succeed.
+    /// The endpoints required by each configuration are allocated from the endpoints
+    /// of the UDC per direction and transfer type, if both are known,
+    /// see [`UdcCapabilities::endpoint_shortage`].
+    pub fn check_against(&self, udc: &Udc) -> Result<Vec<BindErrorCause>> {
+        if !udc.dir().exists() {
+            return Ok(vec![BindErrorCause::UdcNotFound]);
+        }
+
+        let mut causes = Vec::new();
+
+        if let Some(driver) = udc.function()? {
+            causes.push(BindErrorCause::UdcBusy { driver: Some(driver) });
+        }
+
+        if let Some(shortage) = self.endpoint_shortage(&udc.capabilities()?) {
+            if !shortage.is_empty() {
+                log::debug!("UDC lacks endpoints required by gadget: {shortage:?}");
+                causes.push(BindErrorCause::InsufficientEndpoints);
+            }
+        }
+
+        if let Some(requested) = self.max_speed {
+            let supported = udc.max_speed()?;
+            if exceeds_speed(requested, supported) {
+                causes.push(BindErrorCause::SpeedUnsupported { requested, supported });
+            }
+        }
+
+        if self.configs.is_empty() {
+            causes.push(BindErrorCause::NoConfigurations);
+        }
+        if let Some(config) = self.configs.iter().position(|config| config.functions.is_empty()) {
+            causes.push(BindErrorCause::EmptyConfiguration { config });
+        }
+
+        Ok(causes)
+    }
+
+    /// Shortfall of UDC endpoints of the configuration lacking the most endpoints.
+    ///
+    /// Endpoints are allocated separately for each configuration.
+    /// `None` if the endpoints of the UDC or of a function are unknown.
+    fn endpoint_shortage(&self, caps: &UdcCapabilities) -> Option<Vec<EndpointShortage>> {
+        let missing = |shortage: &[EndpointShortage]| shortage.iter().map(|s| s.missing).sum::<usize>();
+
+        let mut worst = Vec::new();
+        for config in &self.configs {
+            let required: Vec<_> = config
+                .functions
+                .iter()
+                .map(|func| func.get().required_endpoints())
+                .collect::<Option<Vec<_>>>()?
+                .concat();
+            let shortage = caps.endpoint_shortage(&required)?;
+            if missing(&shortage) > missing(&worst) {
+                worst = shortage;
+            }
+        }
+
+        Some(worst)
+    }
+}
+
+/// Error indicating that the USB host did not select a configuration of a USB gadget in time.
+///
+/// This is returned as the inner error of an [`ErrorKind::TimedOut`] I/O error by
+/// [`Gadget::bind_and_wait`] and [`RegGadget::bind_and_wait`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EnumerationTimeout {
+    /// Name of the UDC.
+    pub udc: OsString,
+    /// Activation state reached when the timeout expired.
+    pub state: GadgetState,
+    /// Timeout.
+    pub timeout: Duration,
+}
+
+impl fmt::Display for EnumerationTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let udc = self.udc.to_string_lossy();
+        write!(f, "USB gadget on UDC {udc} was not configured by host within {:?}: ", self.timeout)?;
+        match self.state {
+            GadgetState::Registered | GadgetState::Bound => write!(f, "not enumerated, check the USB connection"),
+            GadgetState::Enumerated => write!(f, "enumerated, but no configuration selected"),
+            GadgetState::Suspended => write!(f, "suspended by host"),
+            state => write!(f, "state {state:?}"),
+        }
+    }
+}
+
+impl std::error::Error for EnumerationTimeout {}
+
+/// Likely cause of a failure to bind a USB gadget to a USB device controller (UDC).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BindErrorCause {
+    /// The UDC does not exist.
+    UdcNotFound,
+    /// The UDC is already in use.
+    UdcBusy {
+        /// Name of the gadget driver currently using the UDC, if known.
+        driver: Option<OsString>,
+    },
+    /// A function is not ready to be bound.
+    ///
+    /// For example, a custom function has not written its descriptors to FunctionFS yet.
+    FunctionNotReady,
+    /// The UDC does not provide enough endpoints for the functions of the gadget.
+    InsufficientEndpoints,
+    /// A function failed to bind.
+    ///
+    /// Commonly, the UDC does not provide enough endpoints or a function is not ready.
+    FunctionBindFailed,
+    /// The maximum speed of the gadget exceeds the maximum speed supported by the UDC.
+    SpeedUnsupported {
+        /// Maximum speed of the gadget.
+        requested: Speed,
+        /// Maximum speed supported by the UDC.
+        supported: Speed,
+    },
+    /// The gadget has no configurations.
+    ///
+    /// See [`Gadget::allow_empty_configs`].
+    NoConfigurations,
+    /// A configuration of the gadget contains no functions.
+    ///
+    /// See [`Gadget::allow_empty_configs`].
+    EmptyConfiguration {
+        /// Index of the configuration in [`Gadget::configs`].
+        config: usize,
+    },
+    /// Other cause.
+    Other,
+}
+
+/// Failure to bind a USB gadget to a USB device controller (UDC).
+///
+/// This is returned as the inner error of the I/O error returned by [`RegGadget::bind`]
+/// and [`Gadget::bind`].
+/// The kind of the I/O error is preserved.
+/// Missing permissions are reported using [`InsufficientPermissions`](crate::InsufficientPermissions) instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BindError {
+    /// Name of the UDC.
+    pub udc: OsString,
+    /// Likely cause.
+    pub cause: BindErrorCause,
+    /// Error number returned by the kernel.
+    pub errno: Option<i32>,
+    /// Number of endpoints, excluding endpoint 0, required by the functions of the gadget.
+    ///
+    /// `None` if unknown for at least one function.
+    pub required_endpoints: Option<usize>,
+    /// Shortfall of UDC endpoints per direction and transfer type.
+    ///
+    /// Empty if no shortfall was detected or the endpoints of the UDC are unknown,
+    /// see [`UdcCapabilities::endpoint_shortage`].
+    pub endpoint_shortage: Vec<EndpointShortage>,
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let udc = self.udc.to_string_lossy();
+        write!(f, "cannot bind USB gadget to UDC {udc}: ")?;
+        match &self.cause {
+            BindErrorCause::UdcNotFound => write!(f, "UDC does not exist")?,
+            BindErrorCause::UdcBusy { driver: Some(driver) } => {
+                write!(f, "UDC is already in use by {}, unbind it first", driver.to_string_lossy())?
+            }
+            BindErrorCause::UdcBusy { driver: None } => write!(f, "UDC or gadget is already in use")?,
+            BindErrorCause::FunctionNotReady => {
+                write!(f, "a function is not ready, custom functions must be initialized before binding")?
+            }
+            BindErrorCause::InsufficientEndpoints => write!(f, "UDC does not provide enough endpoints")?,
+            BindErrorCause::FunctionBindFailed => write!(
+                f,
+                "a function failed to bind, check that the UDC provides enough endpoints \
+                 and all functions are ready"
+            )?,
+            BindErrorCause::SpeedUnsupported { requested, supported } => {
+                write!(f, "maximum speed {requested} exceeds maximum speed {supported} of UDC")?
+            }
+            BindErrorCause::NoConfigurations => write!(f, "gadget has no configurations")?,
+            BindErrorCause::EmptyConfiguration { config } => {
+                write!(f, "configuration {config} contains no functions")?
+            }
+            BindErrorCause::Other => write!(f, "unexpected error")?,
+        }
+        if let Some(errno) = self.errno {
+            write!(f, " ({})", Errno::from_raw(errno).desc())?;
+        }
+        if let Some(required) = self.required_endpoints {
+            if matches!(self.cause, BindErrorCause::InsufficientEndpoints | BindErrorCause::FunctionBindFailed) {
+                write!(f, ", functions require {required} endpoints")?;
+            }
+        }
+        for (idx, shortage) in self.endpoint_shortage.iter().enumerate() {
+            write!(f, "{}{shortage}", if idx == 0 { ": " } else { ", " })?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BindError {}
+
+/// Error indicating that a property cannot be changed while the gadget is bound to a UDC.
+///
+/// This is returned as the inner error of an [`ErrorKind::Other`] I/O error.
+/// Unbind the gadget before changing the property.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct GadgetBound {
+    /// Path of the property relative to the gadget directory.
+    pub property: PathBuf,
+}
+
+impl fmt::Display for GadgetBound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "property {} cannot be changed while the gadget is bound", self.property.display())
+    }
+}
+
+impl std::error::Error for GadgetBound {}
+
+/// Whether the requested speed is faster than the supported speed, if both are known.
+fn exceeds_speed(requested: Speed, supported: Speed) -> bool {
+    requested != Speed::Unknown && supported != Speed::Unknown && requested < supported
+}
+
+/// Gadget attribute whose value differs from the value written during registration.
+///
+/// Obtained by calling [`RegGadget::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AttributeMismatch {
+    /// Path of the attribute relative to the gadget directory.
+    pub path: PathBuf,
+    /// Value written during registration.
+    pub written: String,
+    /// Value read back.
+    pub read: String,
+}
+
+/// Attributes written during registration.
+#[derive(Debug, Default)]
+struct WrittenAttrs(Vec<(PathBuf, String)>);
+
+impl WrittenAttrs {
+    /// Writes an attribute and records the written value.
+    fn write(&mut self, path: impl AsRef<Path>, value: impl AsRef<str>) -> Result<()> {
+        let path = path.as_ref();
+        let value = value.as_ref();
+        dry_run::write(path, value.as_bytes())?;
+        self.0.push((path.to_path_buf(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// Compares attribute values, treating numbers in different notations as equal.
+fn attr_value_eq(a: &str, b: &str) -> bool {
+    fn parse(value: &str) -> Option<u64> {
+        match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        }
+    }
+
+    let a = a.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    let b = b.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    match (parse(a), parse(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// USB gadget registered with the system.
+///
+/// If this was obtained by calling [`Gadget::bind`], the USB gadget will be
+/// unbound and removed when this is dropped.
+///
+/// Call [`registered`] to obtain all gadgets registered on the system.
+#[must_use = "The USB gadget is removed when RegGadget is dropped."]
+pub struct RegGadget {
+    dir: PathBuf,
+    attached: bool,
+    func_dirs: HashMap<Handle, PathBuf>,
+    unsupported: Vec<PathBuf>,
+    written: Vec<(PathBuf, String)>,
+    udc_file: Option<File>,
+    removal_priorities: HashMap<Handle, i32>,
+    pre_removal_hooks: Vec<RemovalHook>,
+    post_removal_hooks: Vec<RemovalHook>,
+    definition: Option<Gadget>,
+}
+
+/// Hook called with the gadget directory during removal of a USB gadget.
+type RemovalHook = Box<dyn FnMut(&Path) -> Result<()> + Send + Sync>;
+
+impl fmt::Debug for RegGadget {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("RegGadget").field("name", &self.name()).field("is_attached", &self.is_attached()).finish()
     }
@@ -498,6 +1711,37 @@ impl RegGadget {
         self.attached
     }
 
+    /// Requested gadget attributes that were not supported by the running kernel during registration.
+    ///
+    /// Unsupported function attributes are reported by the status of the respective function.
+    pub fn unsupported_attributes(&self) -> &[PathBuf] {
+        &self.unsupported
+    }
+
+    /// Reads back the gadget and configuration attributes written during registration.
+    ///
+    /// The kernel may clamp or reject some values silently.
+    /// Returns the attributes whose current value differs from the written value.
+    /// For gadgets obtained by calling [`registered`] no attributes are checked.
+    pub fn verify(&self) -> Result<Vec<AttributeMismatch>> {
+        let mut mismatches = Vec::new();
+
+        for (path, written) in &self.written {
+            let read = fs::read_to_string(path)?;
+            if !attr_value_eq(written, &read) {
+                let path = path.strip_prefix(&self.dir).unwrap_or(path).to_path_buf();
+                log::warn!("gadget attribute {} is {} instead of {written}", path.display(), read.trim());
+                mismatches.push(AttributeMismatch {
+                    path,
+                    written: written.clone(),
+                    read: read.trim().to_string(),
+                });
+            }
+        }
+
+        Ok(mismatches)
+    }
+
     /// The name of the USB device controller (UDC) this gadget is bound to.
     pub fn udc(&self) -> Result<Option<OsString>> {
         let data = OsString::from_vec(fs::read(self.dir.join("UDC"))?);
@@ -509,54 +1753,544 @@ impl RegGadget {
         }
     }
 
+    /// The USB device controller (UDC) the gadget is bound to.
+    fn bound_udc(&self) -> Result<Option<Udc>> {
+        let Some(name) = self.udc()? else { return Ok(None) };
+        match Udc::by_name(name) {
+            Ok(udc) => Ok(Some(udc)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Activation state of the gadget.
+    pub fn state(&self) -> Result<GadgetState> {
+        match self.bound_udc()? {
+            Some(udc) => Ok(GadgetState::from_udc_state(udc.state()?)),
+            None => Ok(GadgetState::Registered),
+        }
+    }
+
+    /// Watch for changes of the activation state of the gadget.
+    ///
+    /// The gadget must be bound to a UDC.
+    /// Use [`GadgetStateWatcher::wait_configured`] to wait until the host has selected
+    /// a configuration before starting data transfers.
+    pub fn watch_state(&self) -> Result<GadgetStateWatcher> {
+        let udc = self.bound_udc()?.ok_or_else(|| Error::new(ErrorKind::NotConnected, "USB gadget not bound"))?;
+        GadgetStateWatcher::new(&udc)
+    }
+
+    /// Watch for the USB host suspending and resuming the gadget and invoke the specified hooks.
+    ///
+    /// The gadget must be bound to a UDC.
+    /// The hooks are invoked from a watcher thread until the returned watcher is dropped.
+    pub fn watch_power(&self, hooks: PowerHooks) -> Result<PowerWatcher> {
+        if let Some(func) = hooks.functions().find(|func| !self.func_dirs.contains_key(*func)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("function {} is not part of the gadget", func.get().driver().to_string_lossy()),
+            ));
+        }
+        Ok(PowerWatcher::new(self.watch_state()?, hooks))
+    }
+
+    /// OS descriptor as accepted by the kernel.
+    ///
+    /// `None` if no OS descriptor is in use.
+    /// [`OsDescriptor::config`] is zero if the configuration cannot be determined
+    /// and [`OsDescriptor::config_description`] is not set.
+    #[allow(deprecated)]
+    pub fn os_descriptor(&self) -> Result<Option<OsDescriptor>> {
+        let os_desc_dir = self.dir.join("os_desc");
+        if !os_desc_dir.is_dir() || fs::read_to_string(os_desc_dir.join("use"))?.trim() != "1" {
+            return Ok(None);
+        }
+
+        let vendor_code = fs::read_to_string(os_desc_dir.join("b_vendor_code"))?;
+        let vendor_code = vendor_code.trim();
+        let vendor_code = match vendor_code.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => vendor_code.parse(),
+        }
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid OS descriptor vendor code"))?;
+
+        let qw_sign = fs::read_to_string(os_desc_dir.join("qw_sign"))?;
+        let qw_sign = qw_sign.trim_end_matches(['\n', '\0']).to_string();
+
+        let mut config = 0;
+        for entry in fs::read_dir(&os_desc_dir)? {
+            let Ok(entry) = entry else { continue };
+            let name = entry.file_name();
+            if let Some(idx) = name.to_str().and_then(|n| n.strip_prefix("c.")?.parse::<usize>().ok()) {
+                config = idx.saturating_sub(1);
+            }
+        }
+
+        Ok(Some(OsDescriptor { vendor_code, qw_sign, config, config_description: None }))
+    }
+
+    /// Maximum power in mA the host has granted to this gadget by selecting a configuration.
+    ///
+    /// `None` if the gadget is not bound to a UDC, the host has not selected a configuration
+    /// or the selected configuration cannot be determined because the gadget provides
+    /// more than one configuration.
+    pub fn configured_power(&self) -> Result<Option<u16>> {
+        let Some(udc) = self.bound_udc()? else { return Ok(None) };
+        if udc.state()? != UdcState::Configured {
+            return Ok(None);
+        }
+
+        let mut configs = Vec::new();
+        for entry in fs::read_dir(self.dir.join("configs"))? {
+            let Ok(entry) = entry else { continue };
+            if entry.metadata()?.is_dir() {
+                configs.push(entry.path());
+            }
+        }
+
+        let [config] = configs.as_slice() else { return Ok(None) };
+        let max_power = fs::read_to_string(config.join("MaxPower"))?;
+        let max_power =
+            max_power.trim().parse().map_err(|_| Error::new(ErrorKind::InvalidData, "invalid maximum power"))?;
+        Ok(Some(max_power))
+    }
+
+    /// Updates the attributes and description strings of a configuration.
+    ///
+    /// `index` is the position of the configuration in [`Gadget::configs`].
+    /// The functions of `config` are ignored, i.e. the functions linked into the
+    /// configuration remain unchanged.
+    /// Description strings for languages not present in `config` are removed.
+    ///
+    /// The gadget must not be bound to a UDC, otherwise an error containing
+    /// [`GadgetBound`] is returned.
+    pub fn update_config(&mut self, index: usize, config: &Config) -> Result<()> {
+        let dir = self.config_dir(index, None)?;
+        let mut written = WrittenAttrs(Vec::new());
+        config.write_attributes(&dir, &mut written)?;
+        self.update_config_description(&dir, config, written)?;
+
+        if let Some(def) = self.definition.as_mut().and_then(|def| def.configs.get_mut(index)) {
+            def.max_power = config.max_power;
+            def.self_powered = config.self_powered;
+            def.remote_wakeup = config.remote_wakeup;
+            def.description = config.description.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Updates the description strings of a configuration.
+    ///
+    /// `index` is the position of the configuration in [`Gadget::configs`].
+    /// Description strings for languages not present in `description` are removed.
+    ///
+    /// The gadget must not be bound to a UDC, otherwise an error containing
+    /// [`GadgetBound`] is returned.
+    pub fn set_config_description(&mut self, index: usize, description: HashMap<Language, String>) -> Result<()> {
+        let dir = self.config_dir(index, Some("strings"))?;
+        let config = Config { description, ..Config::new("") };
+        self.update_config_description(&dir, &config, WrittenAttrs(Vec::new()))?;
+
+        if let Some(def) = self.definition.as_mut().and_then(|def| def.configs.get_mut(index)) {
+            def.description = config.description;
+        }
+
+        Ok(())
+    }
+
+    /// Directory of the configuration with the specified index, ensuring that the gadget is unbound.
+    ///
+    /// `property` is the entry within the configuration directory that is about to be changed,
+    /// or `None` if the attributes of the configuration itself are changed.
+    fn config_dir(&self, index: usize, property: Option<&str>) -> Result<PathBuf> {
+        let name = format!("c.{}", index + 1);
+        let dir = self.dir.join("configs").join(&name);
+        if !dry_run::is_dir(&dir) {
+            return Err(Error::new(ErrorKind::NotFound, format!("configuration {name} not found")));
+        }
+
+        if self.udc()?.is_some() {
+            let dir = Path::new("configs").join(name);
+            let property = property.map_or_else(|| dir.clone(), |property| dir.join(property));
+            return Err(Error::new(ErrorKind::Other, GadgetBound { property }));
+        }
+
+        Ok(dir)
+    }
+
+    /// Replaces the description strings of the configuration in `dir` and records written attributes.
+    fn update_config_description(
+        &mut self, dir: &Path, config: &Config, mut written: WrittenAttrs,
+    ) -> Result<()> {
+        for lang in dry_run::sub_dirs(&dir.join("strings"))? {
+            let keep = lang
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| u16::from_str_radix(name.strip_prefix("0x")?, 16).ok())
+                .is_some_and(|code| config.description.keys().any(|&l| u16::from(l) == code));
+            if !keep {
+                log::debug!("removing configuration strings at {}", lang.display());
+                dry_run::remove_dir(&lang)?;
+            }
+        }
+
+        config.write_description(dir, &mut written)?;
+
+        let strings_dir = dir.join("strings");
+        self.written.retain(|(path, _)| {
+            !path.starts_with(&strings_dir) && !written.0.iter().any(|(written, _)| written == path)
+        });
+        self.written.extend(written.0);
+
+        Ok(())
+    }
+
     /// Binds the gadget to the specified USB device controller (UDC).
     ///
     /// If `udc` is `None`, the gadget is unbound from any UDC.
     pub fn bind(&self, udc: Option<&Udc>) -> Result<()> {
         log::debug!("binding gadget {:?} to {:?}", self, &udc);
 
+        if let Some(udc) = udc {
+            self.check_speed(udc)?;
+            self.check_endpoints(udc)?;
+        }
+
         let name = match udc {
             Some(udc) => udc.name().to_os_string(),
             None => "\n".into(),
         };
 
-        match fs::write(self.dir.join("UDC"), name.as_bytes()) {
+        let res = match &self.udc_file {
+            Some(file) => file.write_at(name.as_bytes(), 0).map(|_| ()),
+            None => fs::write(self.dir.join("UDC"), name.as_bytes()),
+        }
+        .map_err(|err| permission_error(self.dir.join("UDC"), AccessKind::ReadWrite, err));
+        match res {
             Ok(()) => (),
             Err(err) if udc.is_none() && err.raw_os_error() == Some(Errno::ENODEV as i32) => (),
-            Err(err) => return Err(err),
+            Err(err) => match udc {
+                // Keep the missing permissions as inner error.
+                Some(udc) if err.kind() != ErrorKind::PermissionDenied => return Err(self.bind_error(udc, err)),
+                _ => return Err(err),
+            },
+        }
+
+        for func in self.func_dirs.keys() {
+            func.get().dir().set_bound(udc);
+        }
+
+        Ok(())
+    }
+
+    /// Binds the gadget to the specified USB device controller (UDC) and waits until
+    /// the USB host has selected a configuration.
+    ///
+    /// If the timeout is reached, an [`ErrorKind::TimedOut`] error containing
+    /// [`EnumerationTimeout`] is returned and the gadget stays bound.
+    pub fn bind_and_wait(&self, udc: &Udc, timeout: Duration) -> Result<()> {
+        let mut watcher = GadgetStateWatcher::new(udc)?;
+        self.bind(Some(udc))?;
+
+        if !watcher.wait_configured(Some(timeout))? {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                EnumerationTimeout { udc: udc.name().to_os_string(), state: watcher.state(), timeout },
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Opens the files required for binding and unbinding the gadget in advance.
+    ///
+    /// Afterwards [`bind`](Self::bind) operates on the held file descriptor and thus
+    /// no longer requires access to the gadget's `UDC` attribute by path.
+    /// This allows restricting filesystem access, for example using Landlock,
+    /// after the gadget has been registered.
+    ///
+    /// Only binding and unbinding use held file descriptors. Other operations on the
+    /// registered gadget, such as [`update_config`](Self::update_config) or changing
+    /// the medium of a mass storage function, still access configfs by path; they are
+    /// listed by [`required_access`](Self::required_access).
+    pub fn preopen(&mut self) -> Result<()> {
+        if self.udc_file.is_none() {
+            self.udc_file = Some(File::options().write(true).open(self.dir.join("UDC"))?);
+        }
+        Ok(())
+    }
+
+    /// Filesystem access and capabilities required for the remaining lifecycle of the gadget.
+    ///
+    /// Call [`preopen`](Self::preopen) first to minimize the requirements.
+    /// Removing the gadget always requires access to its configfs directory, since
+    /// removal of directories and symbolic links cannot be performed through held
+    /// file descriptors.
+    pub fn required_access(&self) -> Result<AccessRequirements> {
+        let mut access = AccessRequirements::new();
+        if self.udc_file.is_none() {
+            access.add_path(self.dir.join("UDC"), AccessKind::ReadWrite, "binding to UDC");
+        }
+        if self.attached {
+            access.add_path(&self.dir, AccessKind::Read, "enumerating gadget for removal");
+            access.add_path(self.dir.parent().unwrap_or(&self.dir), AccessKind::Remove, "removing gadget");
+        }
+        add_udc_access(&mut access)?;
+
+        let configs = self.dir.join("configs");
+        access.add_path(&configs, AccessKind::ReadWrite, "updating configurations");
+        for entry in fs::read_dir(&configs).into_iter().flatten() {
+            let Ok(entry) = entry else { continue };
+            let strings = entry.path().join("strings");
+            access.add_path(&strings, AccessKind::Create, "updating configuration descriptions");
+            access.add_path(&strings, AccessKind::Remove, "updating configuration descriptions");
         }
 
         for func in self.func_dirs.keys() {
-            func.get().dir().set_bound(udc.is_some());
+            access.extend(func.get().required_access());
+        }
+
+        Ok(access)
+    }
+
+    /// Validates the maximum speed of the gadget against the maximum speed of the UDC.
+    ///
+    /// Exceeding the speed of the UDC is handled according to the [strictness](Gadget::strictness)
+    /// of the gadget definition.
+    /// Nothing is checked if the gadget definition is unknown or does not specify a maximum speed.
+    fn check_speed(&self, udc: &Udc) -> Result<()> {
+        let Some(gadget) = &self.definition else { return Ok(()) };
+        let Some(requested) = gadget.max_speed else { return Ok(()) };
+        let Ok(supported) = udc.max_speed() else { return Ok(()) };
+
+        if exceeds_speed(requested, supported) {
+            let bind_err = BindError {
+                udc: udc.name().to_os_string(),
+                cause: BindErrorCause::SpeedUnsupported { requested, supported },
+                errno: None,
+                required_endpoints: None,
+                endpoint_shortage: Vec::new(),
+            };
+            match gadget.strictness {
+                Strictness::Ignore => (),
+                Strictness::Warn => log::warn!("{bind_err}, gadget will operate at lower speed"),
+                Strictness::Error => return Err(Error::new(ErrorKind::Unsupported, bind_err)),
+            }
+        } else if exceeds_speed(supported, requested) {
+            log::info!("maximum speed {requested} of gadget is below maximum speed {supported} of UDC");
+        }
+
+        Ok(())
+    }
+
+    /// Validates the endpoints required by the functions against the endpoints of the UDC.
+    ///
+    /// A shortfall is handled according to the [strictness](Gadget::strictness) of the gadget definition.
+    /// Nothing is checked if the gadget definition or the endpoints of the UDC are unknown.
+    fn check_endpoints(&self, udc: &Udc) -> Result<()> {
+        let Some(gadget) = &self.definition else { return Ok(()) };
+        let Ok(caps) = udc.capabilities() else { return Ok(()) };
+        let Some(endpoint_shortage) = gadget.endpoint_shortage(&caps) else { return Ok(()) };
+        if endpoint_shortage.is_empty() {
+            return Ok(());
+        }
+
+        let bind_err = BindError {
+            udc: udc.name().to_os_string(),
+            cause: BindErrorCause::InsufficientEndpoints,
+            errno: None,
+            required_endpoints: self.required_endpoints(),
+            endpoint_shortage,
+        };
+        match gadget.strictness {
+            Strictness::Ignore => (),
+            Strictness::Warn => log::warn!("{bind_err}, binding anyway"),
+            Strictness::Error => return Err(Error::new(ErrorKind::Unsupported, bind_err)),
         }
 
         Ok(())
     }
 
+    /// Number of endpoints required by all functions, if known.
+    fn required_endpoints(&self) -> Option<usize> {
+        self.func_dirs.keys().map(|func| func.get().endpoint_count()).sum()
+    }
+
+    /// Speed negotiated with the USB host.
+    ///
+    /// This is the effective maximum speed, limited by the gadget, the UDC and the host.
+    /// `None` if the gadget is not bound to a UDC or has not been enumerated by the host yet.
+    pub fn negotiated_speed(&self) -> Result<Option<Speed>> {
+        let Some(udc) = self.bound_udc()? else { return Ok(None) };
+        match udc.current_speed()? {
+            Speed::Unknown => Ok(None),
+            speed => Ok(Some(speed)),
+        }
+    }
+
+    /// Diagnoses a failure to bind to the specified UDC.
+    fn bind_error(&self, udc: &Udc, err: Error) -> Error {
+        /// Kernel internal error code returned when endpoint allocation fails.
+        const ENOTSUPP: i32 = 524;
+
+        let errno = err.raw_os_error();
+        let cause = if !udc.dir().exists() {
+            BindErrorCause::UdcNotFound
+        } else {
+            match errno {
+                Some(ENOTSUPP) => BindErrorCause::InsufficientEndpoints,
+                Some(errno) => match Errno::from_raw(errno) {
+                    Errno::EBUSY => BindErrorCause::UdcBusy { driver: udc.function().ok().flatten() },
+                    Errno::EBADFD => BindErrorCause::FunctionNotReady,
+                    Errno::ENOSPC | Errno::ENOTSUP => BindErrorCause::InsufficientEndpoints,
+                    Errno::ENODEV => BindErrorCause::FunctionBindFailed,
+                    Errno::EINVAL => self.config_error().unwrap_or(BindErrorCause::Other),
+                    _ => BindErrorCause::Other,
+                },
+                None => BindErrorCause::Other,
+            }
+        };
+
+        let endpoint_shortage = match (&cause, &self.definition) {
+            (BindErrorCause::InsufficientEndpoints | BindErrorCause::FunctionBindFailed, Some(gadget)) => {
+                udc.capabilities().ok().and_then(|caps| gadget.endpoint_shortage(&caps)).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+
+        let bind_err = BindError {
+            udc: udc.name().to_os_string(),
+            cause,
+            errno,
+            required_endpoints: self.required_endpoints(),
+            endpoint_shortage,
+        };
+        log::warn!("{bind_err}");
+        Error::new(err.kind(), bind_err)
+    }
+
+    /// Finds a configuration problem causing the kernel to refuse binding.
+    fn config_error(&self) -> Option<BindErrorCause> {
+        let mut configs: Vec<_> = fs::read_dir(self.dir.join("configs"))
+            .ok()?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_prefix("c.")?.parse::<usize>().ok()
+            })
+            .collect();
+        if configs.is_empty() {
+            return Some(BindErrorCause::NoConfigurations);
+        }
+
+        configs.sort_unstable();
+        configs.into_iter().find_map(|idx| {
+            let entries = fs::read_dir(self.dir.join("configs").join(format!("c.{idx}"))).ok()?;
+            let has_function = entries.flatten().any(|entry| entry.file_type().is_ok_and(|ty| ty.is_symlink()));
+            (!has_function).then_some(BindErrorCause::EmptyConfiguration { config: idx.saturating_sub(1) })
+        })
+    }
+
+    /// Registers a second gadget identical to this one, optionally with a different
+    /// vendor and product id.
+    ///
+    /// The duplicate is registered under the next free name of the specified namespace,
+    /// which may be the namespace of this gadget, and must be bound to a different UDC
+    /// than this gadget.
+    /// See [`Gadget::clone_with`] for how functions and MAC addresses are duplicated.
+    ///
+    /// Only gadgets registered by this program using [`Gadget::register`] can be duplicated.
+    pub fn duplicate(&self, namespace: &Namespace, id: Option<Id>) -> Result<(RegGadget, Duplicates)> {
+        let definition = self
+            .definition
+            .as_ref()
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "definition of USB gadget is unknown"))?;
+        let (gadget, duplicates) = definition.clone_with(|gadget| {
+            gadget.namespace = namespace.clone();
+            if let Some(id) = id {
+                gadget.id = id;
+            }
+        })?;
+        Ok((gadget.register()?, duplicates))
+    }
+
     /// Detach the handle from the USB gadget while keeping the USB gadget active.
     pub fn detach(&mut self) {
+        if self.attached {
+            cleanup::untrack(&self.dir);
+        }
         self.attached = false;
     }
 
+    /// Overrides the [removal priority](function::util::Function::removal_priority) of a function
+    /// of this gadget.
+    pub fn set_removal_priority(&mut self, func: &Handle, priority: i32) {
+        self.removal_priorities.insert(func.clone(), priority);
+    }
+
+    /// Adds a hook called with the gadget directory when removal of the gadget starts.
+    ///
+    /// Hooks are called in the order they were added, before the gadget is unbound and
+    /// its functions are notified.
+    /// If a hook fails, removal is aborted.
+    pub fn add_pre_removal_hook(&mut self, hook: impl FnMut(&Path) -> Result<()> + Send + Sync + 'static) {
+        self.pre_removal_hooks.push(Box::new(hook));
+    }
+
+    /// Adds a hook called with the gadget directory after the gadget has been removed.
+    ///
+    /// Hooks are called in the order they were added, after all functions have been notified.
+    pub fn add_post_removal_hook(&mut self, hook: impl FnMut(&Path) -> Result<()> + Send + Sync + 'static) {
+        self.post_removal_hooks.push(Box::new(hook));
+    }
+
+    /// Functions in the order of removal.
+    fn removal_order(&self) -> Vec<(&Handle, &PathBuf)> {
+        let mut funcs: Vec<_> = self.func_dirs.iter().collect();
+        funcs.sort_by_key(|(func, dir)| {
+            let priority =
+                self.removal_priorities.get(*func).copied().unwrap_or_else(|| func.get().removal_priority());
+            (cmp::Reverse(priority), dir.as_path())
+        });
+        funcs
+    }
+
     fn do_remove(&mut self) -> Result<()> {
-        for func in self.func_dirs.keys() {
+        for hook in &mut self.pre_removal_hooks {
+            hook(&self.dir)?;
+        }
+
+        if self.udc_file.is_some() {
+            self.bind(None)?;
+        }
+
+        let order = self.removal_order();
+
+        for (func, _) in &order {
             func.get().pre_removal()?;
         }
 
-        for func in self.func_dirs.keys() {
-            func.get().dir().set_bound(false);
+        for (func, _) in &order {
+            func.get().dir().set_bound(None);
         }
 
-        remove_at(&self.dir)?;
+        let order_dirs: Vec<_> = order.iter().map(|(_, dir)| dir.as_path()).collect();
+        remove_at(&self.dir, &order_dirs)?;
+        cleanup::untrack(&self.dir);
 
-        for func in self.func_dirs.keys() {
+        for (func, _) in &order {
             func.get().dir().reset_dir();
         }
 
-        for (func, dir) in &self.func_dirs {
+        for (func, dir) in &order {
             func.get().post_removal(dir)?;
         }
 
+        for hook in &mut self.post_removal_hooks {
+            hook(&self.dir)?;
+        }
+
         self.detach();
         Ok(())
     }
@@ -577,8 +2311,45 @@ impl Drop for RegGadget {
     }
 }
 
+/// Name of the gadget directory.
+pub(crate) fn dir_name(dir: &Path) -> String {
+    dir.file_name().unwrap().to_string_lossy().to_string()
+}
+
+/// Generates locally administered unicast MAC addresses from a seed.
+fn mac_generator(seed: u64) -> impl FnMut() -> MacAddr6 {
+    let mut state = seed;
+    move || {
+        // SplitMix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let b = (z ^ (z >> 31)).to_le_bytes();
+        MacAddr6::new((b[0] & 0xfc) | 0x02, b[1], b[2], b[3], b[4], b[5])
+    }
+}
+
+/// Sorts directory entries by their position in `order`.
+///
+/// Entries not contained in `order` are placed last, keeping their relative order.
+fn sort_entries(
+    entries: impl Iterator<Item = std::io::Result<fs::DirEntry>>, order: &[&Path],
+) -> Vec<fs::DirEntry> {
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|entry| {
+        // Functions are linked into configurations under arbitrary names.
+        let path = fs::read_link(entry.path()).unwrap_or_else(|_| entry.path());
+        order.iter().position(|dir| dir.file_name() == path.file_name()).unwrap_or(usize::MAX)
+    });
+    entries
+}
+
 /// Remove USB gadget at specified configfs gadget directory.
-fn remove_at(dir: &Path) -> Result<()> {
+///
+/// Functions are unlinked and removed in the specified order of function directories,
+/// followed by all other functions.
+pub(crate) fn remove_at(dir: &Path, order: &[&Path]) -> Result<()> {
     log::debug!("removing gadget at {}", dir.display());
 
     init_remove_handlers();
@@ -601,8 +2372,7 @@ fn remove_at(dir: &Path) -> Result<()> {
             continue;
         }
 
-        for func in fs::read_dir(config_dir.path())? {
-            let Ok(func) = func else { continue };
+        for func in sort_entries(fs::read_dir(config_dir.path())?, order) {
             if func.metadata()?.is_symlink() {
                 fs::remove_file(func.path())?;
             }
@@ -618,8 +2388,7 @@ fn remove_at(dir: &Path) -> Result<()> {
         fs::remove_dir(config_dir.path())?;
     }
 
-    for func_dir in fs::read_dir(dir.join("functions"))? {
-        let Ok(func_dir) = func_dir else { continue };
+    for func_dir in sort_entries(fs::read_dir(dir.join("functions"))?, order) {
         if !func_dir.metadata()?.is_dir() {
             continue;
         }
@@ -632,6 +2401,14 @@ fn remove_at(dir: &Path) -> Result<()> {
     for lang in fs::read_dir(dir.join("strings"))? {
         let Ok(lang) = lang else { continue };
         if lang.metadata()?.is_dir() {
+            // remove custom string descriptors
+            for string in fs::read_dir(lang.path())? {
+                let Ok(string) = string else { continue };
+                if string.metadata()?.is_dir() {
+                    fs::remove_dir(string.path())?;
+                }
+            }
+
             fs::remove_dir(lang.path())?;
         }
     }
@@ -642,10 +2419,18 @@ fn remove_at(dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Usual mount point of configfs.
+pub(crate) const DEFAULT_CONFIGFS_DIR: &str = "/sys/kernel/config";
+
 /// The path to the USB gadget configuration directory within configfs.
-fn usb_gadget_dir() -> Result<PathBuf> {
+pub(crate) fn usb_gadget_dir() -> Result<PathBuf> {
     let _ = request_module("libcomposite");
 
+    if dry_run::is_recording() {
+        let configfs_dir = configfs_dir().unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIGFS_DIR));
+        return Ok(configfs_dir.join("usb_gadget"));
+    }
+
     let usb_gadget_dir = configfs_dir()?.join("usb_gadget");
     if usb_gadget_dir.is_dir() {
         Ok(usb_gadget_dir)
@@ -654,10 +2439,23 @@ fn usb_gadget_dir() -> Result<PathBuf> {
     }
 }
 
+/// Adds read access to the sysfs directories of all UDCs.
+///
+/// The entries of `/sys/class/udc` are symbolic links, thus their targets are added as well.
+fn add_udc_access(access: &mut AccessRequirements) -> Result<()> {
+    access.add_path("/sys/class/udc", AccessKind::Read, "enumerating UDCs");
+    for udc in udcs()? {
+        let dir = fs::canonicalize(udc.dir())?;
+        access.add_path(dir, AccessKind::Read, "reading UDC state");
+    }
+    Ok(())
+}
+
 /// Get all USB gadgets registered on the system.
 ///
 /// This returns all USB gadgets, including gadgets not created by the running program or
 /// registered by other means than using this library.
+/// Use [`Namespace::registered`] to restrict this to the gadgets of a namespace.
 pub fn registered() -> Result<Vec<RegGadget>> {
     let usb_gadget_dir = usb_gadget_dir()?;
 
@@ -665,7 +2463,18 @@ pub fn registered() -> Result<Vec<RegGadget>> {
     for gadget_dir in fs::read_dir(usb_gadget_dir)? {
         let Ok(gadget_dir) = gadget_dir else { continue };
         if gadget_dir.metadata()?.is_dir() {
-            gadgets.push(RegGadget { dir: gadget_dir.path(), attached: false, func_dirs: HashMap::new() });
+            gadgets.push(RegGadget {
+                dir: gadget_dir.path(),
+                attached: false,
+                func_dirs: HashMap::new(),
+                unsupported: Vec::new(),
+                written: Vec::new(),
+                udc_file: None,
+                removal_priorities: HashMap::new(),
+                pre_removal_hooks: Vec::new(),
+                post_removal_hooks: Vec::new(),
+                definition: None,
+            });
         }
     }
 
@@ -676,30 +2485,182 @@ pub fn registered() -> Result<Vec<RegGadget>> {
 ///
 /// This removes all USB gadgets, including gadgets not created by the running program or
 /// registered by other means than using this library.
+/// Use [`Namespace::remove_all`] to restrict this to the gadgets of a namespace.
+/// Use [`remove_each`] to obtain the result for each gadget.
 pub fn remove_all() -> Result<()> {
-    let mut res = Ok(());
-
-    for gadget in registered()? {
-        if let Err(err) = gadget.remove() {
-            res = Err(err);
-        }
-    }
+    last_error(remove_each()?)
+}
 
-    res
+/// Remove all USB gadgets defined on the system, returning the result for each gadget.
+///
+/// Removal continues after a gadget fails to be removed.
+/// Each entry consists of the gadget name and the result of removing it.
+pub fn remove_each() -> Result<Vec<(OsString, Result<()>)>> {
+    Ok(registered()?.into_iter().map(|gadget| (gadget.name().to_os_string(), gadget.remove())).collect())
 }
 
 /// Unbind all USB gadgets defined on the system.
 ///
 /// This unbinds all USB gadgets, including gadgets not created by the running program or
 /// registered by other means than using this library.
+/// Use [`Namespace::unbind_all`] to restrict this to the gadgets of a namespace.
+/// Use [`unbind_each`] to obtain the result for each gadget.
 pub fn unbind_all() -> Result<()> {
-    let mut res = Ok(());
+    last_error(unbind_each()?)
+}
+
+/// Unbind all USB gadgets defined on the system, returning the result for each gadget.
+///
+/// Each entry consists of the gadget name and the result of unbinding it.
+pub fn unbind_each() -> Result<Vec<(OsString, Result<()>)>> {
+    Ok(registered()?.into_iter().map(|gadget| (gadget.name().to_os_string(), gadget.bind(None))).collect())
+}
+
+/// Returns the last error of per-gadget results, if any.
+fn last_error(results: Vec<(OsString, Result<()>)>) -> Result<()> {
+    results.into_iter().filter_map(|(_, res)| res.err()).last().map_or(Ok(()), Err)
+}
+
+/// Namespace of USB gadgets.
+///
+/// Gadgets registered in a namespace are named by the namespace prefix followed by
+/// a number. This allows several independent applications to manage only
+/// the USB gadgets they own, leaving foreign gadgets untouched.
+///
+/// The default namespace uses the prefix `usb-gadget`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Namespace {
+    prefix: String,
+}
 
-    for gadget in registered()? {
-        if let Err(err) = gadget.bind(None) {
-            res = Err(err);
+impl Default for Namespace {
+    fn default() -> Self {
+        Self { prefix: "usb-gadget".to_string() }
+    }
+}
+
+impl Namespace {
+    /// Creates a namespace with the specified gadget name prefix.
+    ///
+    /// The prefix must not be empty, must not contain a slash and must not end with a digit.
+    pub fn new(prefix: impl AsRef<str>) -> Result<Self> {
+        let prefix = prefix.as_ref();
+        if prefix.is_empty() || prefix.contains('/') || prefix.ends_with(|c: char| c.is_ascii_digit()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid gadget namespace prefix"));
         }
+
+        Ok(Self { prefix: prefix.to_string() })
+    }
+
+    /// Gadget name prefix.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
     }
 
-    res
+    /// Creates the directory of a new gadget in this namespace using the next free name.
+    pub(crate) fn create_gadget_dir(&self, usb_gadget_dir: &Path) -> Result<PathBuf> {
+        let mut gadget_idx: u16 = 0;
+        loop {
+            let dir = usb_gadget_dir.join(format!("{}{gadget_idx}", self.prefix));
+            match dry_run::create_dir(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => (),
+                Err(err) => return Err(err),
+            }
+            gadget_idx = gadget_idx
+                .checked_add(1)
+                .ok_or_else(|| Error::new(ErrorKind::OutOfMemory, "USB gadgets exhausted"))?;
+        }
+    }
+
+    /// Whether the gadget with the specified name belongs to this namespace.
+    pub fn contains(&self, name: &OsStr) -> bool {
+        match name.as_bytes().strip_prefix(self.prefix.as_bytes()) {
+            Some(idx) => !idx.is_empty() && idx.iter().all(|c| c.is_ascii_digit()),
+            None => false,
+        }
+    }
+
+    /// Get all USB gadgets registered in this namespace.
+    pub fn registered(&self) -> Result<Vec<RegGadget>> {
+        Ok(registered()?.into_iter().filter(|gadget| self.contains(gadget.name())).collect())
+    }
+
+    /// Remove all USB gadgets registered in this namespace.
+    pub fn remove_all(&self) -> Result<()> {
+        last_error(self.remove_each()?)
+    }
+
+    /// Remove all USB gadgets registered in this namespace, returning the result for each gadget.
+    pub fn remove_each(&self) -> Result<Vec<(OsString, Result<()>)>> {
+        Ok(self.registered()?.into_iter().map(|gadget| (gadget.name().to_os_string(), gadget.remove())).collect())
+    }
+
+    /// Unbind all USB gadgets registered in this namespace.
+    pub fn unbind_all(&self) -> Result<()> {
+        last_error(self.unbind_each()?)
+    }
+
+    /// Unbind all USB gadgets registered in this namespace, returning the result for each gadget.
+    pub fn unbind_each(&self) -> Result<Vec<(OsString, Result<()>)>> {
+        Ok(self
+            .registered()?
+            .into_iter()
+            .map(|gadget| (gadget.name().to_os_string(), gadget.bind(None)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use super::{Config, OsDescriptor};
+    use crate::Language;
+
+    #[test]
+    fn os_descriptor_sign() {
+        let os_desc = OsDescriptor::microsoft();
+        os_desc.validate().unwrap();
+        assert!(os_desc.is_microsoft());
+
+        let utf16 = os_desc.qw_sign_utf16le();
+        assert_eq!(utf16.len(), 14);
+        assert_eq!(OsDescriptor::from_utf16le(0xf0, &utf16).unwrap(), os_desc);
+
+        OsDescriptor::new(0xf0, "MSFT1000".to_string()).validate().unwrap_err();
+        OsDescriptor::new(0xf0, String::new()).validate().unwrap_err();
+        OsDescriptor::from_utf16le(0xf0, &[0x4d]).unwrap_err();
+    }
+
+    #[test]
+    fn os_descriptor_config() {
+        let configs = [Config::new("first"), Config::new("second"), Config::new("second")];
+
+        let os_desc = OsDescriptor::microsoft();
+        assert_eq!(os_desc.config_index(&configs).unwrap(), 0);
+        assert_eq!(os_desc.clone().with_config(&configs[0]).config_index(&configs).unwrap(), 0);
+        assert_eq!(
+            os_desc.clone().with_config(&configs[0]).config_index(&configs[1..]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        os_desc.clone().with_config(&configs[1]).config_index(&configs).unwrap_err();
+        assert_eq!(os_desc.clone().with_config(&configs[1]).config_index(&configs[..2]).unwrap(), 1);
+
+        let german =
+            Config { description: [(Language::GermanStandard, "erste".to_string())].into(), ..Config::new("") };
+        let with_german = [configs[0].clone(), german.clone()];
+        assert_eq!(os_desc.clone().with_config(&german).config_index(&with_german).unwrap(), 1);
+        assert_eq!(
+            os_desc.clone().with_config(&german).config_index(&[german.clone(), german]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        let localized = [Config {
+            description: [(Language::GermanStandard, "second".to_string())].into(),
+            ..Config::new("")
+        }];
+        let os_desc = os_desc.with_config(&configs[1]);
+        assert_eq!(os_desc.config_index(&localized).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
 }
@@ -0,0 +1,223 @@
+//! Host-side verification of USB gadgets.
+//!
+//! This requires the `host-tests` feature and access to the USB host controller the
+//! USB gadget is connected to, for example when testing on a device with a USB OTG port
+//! looped back to a host port or using the `dummy_hcd` kernel module.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use usb_gadget::{
+//!     default_udc,
+//!     function::serial::{Serial, SerialClass},
+//!     host::HostDevice,
+//!     Class, Config, Gadget, Id, Strings,
+//! };
+//!
+//! let (_serial, func) = Serial::new(SerialClass::Acm);
+//! let gadget = Gadget::new(Class::vendor_specific(0, 0), Id::new(6, 0x11), Strings::new("mfg", "product", "serial"))
+//!     .with_config(Config::new("config").with_function(func));
+//! let _reg = gadget.clone().bind(&default_udc().unwrap()).unwrap();
+//!
+//! let dev = HostDevice::wait(gadget.id, Duration::from_secs(5)).unwrap();
+//! dev.verify(&gadget).unwrap();
+//! ```
+
+use rusb::{Context, DeviceHandle, UsbContext};
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Result},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{Gadget, Id, Language};
+
+/// Timeout for host-side USB transfers.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+fn map_err(err: rusb::Error) -> Error {
+    let kind = match err {
+        rusb::Error::Timeout => ErrorKind::TimedOut,
+        rusb::Error::NotFound | rusb::Error::NoDevice => ErrorKind::NotFound,
+        rusb::Error::Access => ErrorKind::PermissionDenied,
+        rusb::Error::InvalidParam => ErrorKind::InvalidInput,
+        rusb::Error::Pipe => ErrorKind::BrokenPipe,
+        rusb::Error::Interrupted => ErrorKind::Interrupted,
+        rusb::Error::NoMem => ErrorKind::OutOfMemory,
+        rusb::Error::NotSupported => ErrorKind::Unsupported,
+        _ => ErrorKind::Other,
+    };
+    Error::new(kind, err)
+}
+
+/// Encodes a version decoded by rusb back into its BCD form.
+///
+/// rusb decodes the two major version digits into a single decimal number.
+fn version_bcd(version: rusb::Version) -> u16 {
+    let major = u16::from(version.major());
+    ((major / 10) << 12)
+        | ((major % 10) << 8)
+        | (u16::from(version.minor()) << 4)
+        | u16::from(version.sub_minor())
+}
+
+fn mismatch(what: &str, expected: impl fmt::Debug, actual: impl fmt::Debug) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{what} mismatch: expected {expected:?}, host sees {actual:?}"))
+}
+
+/// USB gadget as seen by the USB host.
+pub struct HostDevice {
+    hnd: DeviceHandle<Context>,
+}
+
+impl fmt::Debug for HostDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HostDevice").field("device", &self.hnd.device()).finish()
+    }
+}
+
+impl HostDevice {
+    /// Opens the USB device with the specified id.
+    pub fn open(id: Id) -> Result<Self> {
+        let ctx = Context::new().map_err(map_err)?;
+        let hnd = ctx
+            .open_device_with_vid_pid(id.vendor, id.product)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "USB device not found by host"))?;
+        Ok(Self { hnd })
+    }
+
+    /// Waits for the USB device with the specified id to enumerate, then opens it.
+    pub fn wait(id: Id, timeout: Duration) -> Result<Self> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match Self::open(id) {
+                Err(err) if err.kind() == ErrorKind::NotFound && Instant::now() < deadline => {
+                    sleep(Duration::from_millis(100))
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Underlying rusb device handle.
+    pub fn handle(&self) -> &DeviceHandle<Context> {
+        &self.hnd
+    }
+
+    /// Compares the descriptors read by the host against the gadget definition.
+    ///
+    /// An invalid data error describing the first mismatch is returned.
+    /// Only properties that are fully determined by the gadget definition are compared.
+    pub fn verify(&self, gadget: &Gadget) -> Result<()> {
+        let dev = self.hnd.device();
+        let desc = dev.device_descriptor().map_err(map_err)?;
+
+        if desc.vendor_id() != gadget.id.vendor || desc.product_id() != gadget.id.product {
+            return Err(mismatch(
+                "device id",
+                (gadget.id.vendor, gadget.id.product),
+                (desc.vendor_id(), desc.product_id()),
+            ));
+        }
+
+        let class = (desc.class_code(), desc.sub_class_code(), desc.protocol_code());
+        let expected = (gadget.device_class.class, gadget.device_class.sub_class, gadget.device_class.protocol);
+        if class != expected {
+            return Err(mismatch("device class", expected, class));
+        }
+
+        let version = version_bcd(desc.device_version());
+        if version != gadget.device_release {
+            return Err(mismatch("device release", gadget.device_release, version));
+        }
+
+        if usize::from(desc.num_configurations()) != gadget.configs.len() {
+            return Err(mismatch("number of configurations", gadget.configs.len(), desc.num_configurations()));
+        }
+
+        if let Some(strings) = gadget.strings.get(&Language::default()) {
+            let expected = [
+                ("manufacturer", &strings.manufacturer, desc.manufacturer_string_index()),
+                ("product", &strings.product, desc.product_string_index()),
+                ("serial number", &strings.serial_number, desc.serial_number_string_index()),
+            ];
+            for (what, expected, idx) in expected {
+                let actual = match idx {
+                    Some(idx) => self.hnd.read_string_descriptor_ascii(idx).map_err(map_err)?,
+                    None => String::new(),
+                };
+                if &actual != expected {
+                    return Err(mismatch(what, expected, actual));
+                }
+            }
+        }
+
+        for (idx, config) in gadget.configs.iter().enumerate() {
+            let idx = u8::try_from(idx).map_err(|_| Error::new(ErrorKind::InvalidInput, "too many configs"))?;
+            let desc = dev.config_descriptor(idx).map_err(map_err)?;
+
+            if desc.self_powered() != config.self_powered {
+                return Err(mismatch("self powered", config.self_powered, desc.self_powered()));
+            }
+            if desc.remote_wakeup() != config.remote_wakeup {
+                return Err(mismatch("remote wakeup", config.remote_wakeup, desc.remote_wakeup()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends data to a bulk OUT endpoint and checks that it is echoed back on a bulk IN endpoint.
+    ///
+    /// The interface is claimed for the duration of the transfer.
+    /// The device must echo all data, for example by using a
+    /// [custom function](crate::function::custom) that sends back what it receives.
+    pub fn loopback(&self, interface: u8, ep_out: u8, ep_in: u8, data: &[u8]) -> Result<()> {
+        self.hnd.claim_interface(interface).map_err(map_err)?;
+        let res = self.do_loopback(ep_out, ep_in, data);
+        let _ = self.hnd.release_interface(interface);
+        res
+    }
+
+    fn do_loopback(&self, ep_out: u8, ep_in: u8, data: &[u8]) -> Result<()> {
+        let mut received = Vec::with_capacity(data.len());
+
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| self.hnd.write_bulk(ep_out, data, TIMEOUT));
+
+            let mut buf = vec![0; 4096];
+            while received.len() < data.len() {
+                let n = self.hnd.read_bulk(ep_in, &mut buf, TIMEOUT).map_err(map_err)?;
+                received.extend_from_slice(&buf[..n]);
+            }
+
+            let written = writer.join().unwrap().map_err(map_err)?;
+            if written != data.len() {
+                return Err(Error::new(ErrorKind::WriteZero, "not all data was written"));
+            }
+
+            Ok(())
+        })?;
+
+        if received != data {
+            return Err(Error::new(ErrorKind::InvalidData, "echoed data does not match sent data"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::version_bcd;
+
+    #[test]
+    fn version_bcd_roundtrip() {
+        for bcd in [0x0000, 0x0102, 0x0999, 0x1000, 0x1234, 0x9999] {
+            assert_eq!(version_bcd(rusb::Version::from_bcd(bcd)), bcd);
+        }
+    }
+}
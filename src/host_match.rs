@@ -0,0 +1,122 @@
+//! Properties for locating a USB gadget from the USB host.
+//!
+//! Companion applications running on the USB host can use this information to find
+//! the device without duplicating constants of the gadget definition.
+
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{Class, Id};
+
+/// Identifying properties of a USB gadget as seen by the USB host.
+///
+/// Use [`Gadget::host_match`](crate::Gadget::host_match) to obtain it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HostMatch {
+    /// Vendor and product id.
+    pub id: Id,
+    /// Device class.
+    pub device_class: Class,
+    /// Manufacturer name.
+    pub manufacturer: String,
+    /// Product name.
+    pub product: String,
+    /// Serial number.
+    pub serial_number: String,
+    /// Interfaces whose descriptors are known.
+    ///
+    /// Interfaces of kernel function drivers are not included.
+    pub interfaces: Vec<InterfaceMatch>,
+    /// Device interface GUIDs announced to Microsoft Windows.
+    pub interface_guids: Vec<Uuid>,
+}
+
+/// Identifying properties of an interface as seen by the USB host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InterfaceMatch {
+    /// Value of the configuration containing the interface.
+    pub config: u8,
+    /// Interface class.
+    pub interface_class: Class,
+}
+
+impl HostMatch {
+    /// Renders a udev rule matching the device.
+    ///
+    /// `actions` are appended to the match keys, for example `MODE="0660", TAG+="uaccess"`.
+    pub fn udev_rule(&self, actions: &str) -> String {
+        let mut rule = format!(
+            r#"SUBSYSTEM=="usb", ENV{{DEVTYPE}}=="usb_device", ATTR{{idVendor}}=="{:04x}", ATTR{{idProduct}}=="{:04x}""#,
+            self.id.vendor, self.id.product
+        );
+        if !self.serial_number.is_empty() {
+            write!(rule, r#", ATTR{{serial}}=="{}""#, escape_udev(&self.serial_number)).unwrap();
+        }
+        if !actions.is_empty() {
+            write!(rule, ", {actions}").unwrap();
+        }
+        rule.push('\n');
+        rule
+    }
+
+    /// Renders the device-specific sections of a Windows INF file installing WinUSB
+    /// for the device.
+    ///
+    /// The device interface GUIDs are registered, so that applications can locate
+    /// the device using them.
+    pub fn winusb_inf(&self) -> String {
+        let hardware_id = format!("USB\\VID_{:04X}&PID_{:04X}", self.id.vendor, self.id.product);
+        let guids: Vec<_> = self.interface_guids.iter().map(|guid| format!("{{{guid}}}")).collect();
+
+        let mut inf = String::new();
+        writeln!(inf, "[Devices.NTamd64]").unwrap();
+        writeln!(inf, "%DeviceName%=USB_Install, {hardware_id}").unwrap();
+        writeln!(inf).unwrap();
+        writeln!(inf, "[Dev_AddReg]").unwrap();
+        if !guids.is_empty() {
+            writeln!(inf, "HKR,,DeviceInterfaceGUIDs,0x10000,\"{}\"", guids.join("\",\"")).unwrap();
+        }
+        writeln!(inf).unwrap();
+        writeln!(inf, "[Strings]").unwrap();
+        writeln!(inf, "DeviceName=\"{}\"", self.product.replace('"', "\"\"")).unwrap();
+        inf
+    }
+}
+
+/// Escapes a value for use within a quoted udev match key.
+fn escape_udev(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::HostMatch;
+    use crate::{Class, Id};
+
+    #[test]
+    fn rules() {
+        let host_match = HostMatch {
+            id: Id::new(0x1209, 0x0001),
+            device_class: Class::interface_specific(),
+            manufacturer: "manufacturer".to_string(),
+            product: "product".to_string(),
+            serial_number: "123".to_string(),
+            interfaces: Vec::new(),
+            interface_guids: vec![Uuid::nil()],
+        };
+
+        assert_eq!(
+            host_match.udev_rule(r#"MODE="0660""#),
+            "SUBSYSTEM==\"usb\", ENV{DEVTYPE}==\"usb_device\", ATTR{idVendor}==\"1209\", \
+             ATTR{idProduct}==\"0001\", ATTR{serial}==\"123\", MODE=\"0660\"\n"
+        );
+
+        let inf = host_match.winusb_inf();
+        assert!(inf.contains("USB\\VID_1209&PID_0001"));
+        assert!(inf.contains("\"{00000000-0000-0000-0000-000000000000}\""));
+    }
+}
@@ -0,0 +1,246 @@
+//! Privacy-preserving device identifiers.
+//!
+//! Serial numbers and MAC addresses are derived from a secret key using a keyed hash,
+//! so that they are stable for as long as the key is kept, but cannot be linked
+//! between devices or, when the key is rotated, between boots.
+
+use macaddr::MacAddr6;
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    fs::File,
+    io::{Error, ErrorKind, Read, Result, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Secret key for deriving device identifiers, such as serial numbers and MAC addresses.
+///
+/// Use [`load_or_create`](Self::load_or_create) to keep identifiers stable across boots,
+/// [`for_boot`](Self::for_boot) to obtain identifiers changing with every boot and
+/// [`random`](Self::random) for identifiers changing with every instantiation.
+/// Apply it to a gadget using [`Gadget::with_identity`](crate::Gadget::with_identity).
+#[derive(Clone, PartialEq, Eq)]
+pub struct IdentityKey([u8; Self::SIZE]);
+
+impl fmt::Debug for IdentityKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IdentityKey(..)")
+    }
+}
+
+impl IdentityKey {
+    /// Size of the key in bytes.
+    pub const SIZE: usize = 16;
+
+    /// Uses the specified key.
+    pub const fn new(key: [u8; Self::SIZE]) -> Self {
+        Self(key)
+    }
+
+    /// Generates a random key.
+    pub fn random() -> Result<Self> {
+        let mut key = [0; Self::SIZE];
+        File::open("/dev/urandom")?.read_exact(&mut key)?;
+        Ok(Self(key))
+    }
+
+    /// Loads the key from the specified file or, if it does not exist, generates a random key
+    /// and stores it in a newly created file readable only by its owner.
+    ///
+    /// The key is written to a temporary file in the same directory, which is then linked
+    /// into place, so that the file is never observed partially written. If several callers
+    /// create the key concurrently, all of them obtain the key that was linked first.
+    pub fn load_or_create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match Self::load(path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            res => return res,
+        }
+
+        let key = Self::random()?;
+        let tmp = Self::write_temp(path, &key)?;
+        let res = fs::hard_link(&tmp, path);
+        let _ = fs::remove_file(&tmp);
+        match res {
+            Ok(()) => {
+                log::debug!("created identity key at {}", path.display());
+                if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    File::open(dir)?.sync_all()?;
+                }
+                Ok(key)
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => Self::load(path),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Loads the key from the specified file.
+    fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let key = data
+            .try_into()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "identity key file has invalid size"))?;
+        Ok(Self(key))
+    }
+
+    /// Writes the key to a new temporary file next to `path` and returns its path.
+    fn write_temp(path: &Path, key: &Self) -> Result<PathBuf> {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+        let name = path
+            .file_name()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "identity key path has no file name"))?;
+        loop {
+            let mut tmp_name = OsString::from(".");
+            tmp_name.push(name);
+            tmp_name.push(format!(".{}-{}.tmp", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+            let tmp = path.with_file_name(tmp_name);
+
+            match File::options().write(true).create_new(true).mode(0o600).open(&tmp) {
+                Ok(mut file) => {
+                    if let Err(err) = file.write_all(&key.0).and_then(|()| file.sync_all()) {
+                        let _ = fs::remove_file(&tmp);
+                        return Err(err);
+                    }
+                    return Ok(tmp);
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Derives a key that changes with every boot of the system.
+    pub fn for_boot(&self) -> Result<Self> {
+        let boot_id = fs::read_to_string("/proc/sys/kernel/random/boot_id")?;
+        let lo = self.derive("boot-lo", boot_id.trim().as_bytes());
+        let hi = self.derive("boot-hi", boot_id.trim().as_bytes());
+        let mut key = [0; Self::SIZE];
+        key[..8].copy_from_slice(&lo.to_le_bytes());
+        key[8..].copy_from_slice(&hi.to_le_bytes());
+        Ok(Self(key))
+    }
+
+    /// Derives a value for the specified purpose and context using a keyed hash.
+    ///
+    /// The context should consist of parameters visible to the USB host, such as
+    /// vendor and product id, so that different devices obtain unrelated values.
+    pub fn derive(&self, purpose: &str, context: &[u8]) -> u64 {
+        let mut data = Vec::with_capacity(purpose.len() + 1 + context.len());
+        data.extend_from_slice(purpose.as_bytes());
+        data.push(0);
+        data.extend_from_slice(context);
+        siphash24(&self.0, &data)
+    }
+
+    /// Derives a serial number consisting of 16 uppercase hexadecimal digits.
+    pub fn serial_number(&self, context: &[u8]) -> String {
+        format!("{:016X}", self.derive("serial", context))
+    }
+
+    /// Derives a locally administered unicast MAC address.
+    pub fn mac_address(&self, context: &[u8]) -> MacAddr6 {
+        let b = self.derive("mac", context).to_le_bytes();
+        MacAddr6::new((b[0] & 0xfc) | 0x02, b[1], b[2], b[3], b[4], b[5])
+    }
+
+    /// Derives a seed for [`Gadget::mac_seed`](crate::Gadget::mac_seed).
+    pub fn mac_seed(&self, context: &[u8]) -> u64 {
+        self.derive("mac-seed", context)
+    }
+}
+
+/// SipHash-2-4 of `data` using the specified key.
+fn siphash24(key: &[u8; 16], data: &[u8]) -> u64 {
+    let k0 = u64::from_le_bytes(key[..8].try_into().unwrap());
+    let k1 = u64::from_le_bytes(key[8..].try_into().unwrap());
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    compress(u64::from_le_bytes(last) | ((data.len() as u64) << 56));
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{siphash24, IdentityKey};
+
+    #[test]
+    fn siphash() {
+        let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+        assert_eq!(siphash24(&key, &[]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(siphash24(&key, &[0]), 0x74f8_39c5_93dc_67fd);
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(&key, &data), 0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    fn derived_identifiers() {
+        let key = IdentityKey::new([7; 16]);
+        let serial = key.serial_number(b"1209:0001");
+        assert_eq!(serial.len(), 16);
+        assert_eq!(serial, key.serial_number(b"1209:0001"));
+        assert_ne!(serial, key.serial_number(b"1209:0002"));
+        assert_ne!(serial, IdentityKey::new([8; 16]).serial_number(b"1209:0001"));
+
+        let mac = key.mac_address(b"usb0");
+        assert_eq!(mac.as_bytes()[0] & 0x03, 0x02);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity");
+        let created = IdentityKey::load_or_create(&path).unwrap();
+        assert_eq!(IdentityKey::load_or_create(&path).unwrap(), created);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn load_or_create_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity");
+        let keys: Vec<_> = std::thread::scope(|s| {
+            let threads: Vec<_> =
+                (0..8).map(|_| s.spawn(|| IdentityKey::load_or_create(&path).unwrap())).collect();
+            threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+        });
+        assert!(keys.iter().all(|key| *key == keys[0]));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,3 +1,12 @@
+//! USB languages.
+
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+};
+
+use crate::Gadget;
+
 /// USB language id.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[non_exhaustive]
@@ -458,3 +467,295 @@ impl From<Language> for u16 {
         }
     }
 }
+
+impl From<u16> for Language {
+    fn from(code: u16) -> Language {
+        match code {
+            0x0436 => Language::Afrikaans,
+            0x041c => Language::Albanian,
+            0x0401 => Language::ArabicSaudiArabia,
+            0x0801 => Language::ArabicIraq,
+            0x0c01 => Language::ArabicEgypt,
+            0x1001 => Language::ArabicLibya,
+            0x1401 => Language::ArabicAlgeria,
+            0x1801 => Language::ArabicMorocco,
+            0x1c01 => Language::ArabicTunisia,
+            0x2001 => Language::ArabicOman,
+            0x2401 => Language::ArabicYemen,
+            0x2801 => Language::ArabicSyria,
+            0x2c01 => Language::ArabicJordan,
+            0x3001 => Language::ArabicLebanon,
+            0x3401 => Language::ArabicKuwait,
+            0x3801 => Language::ArabicUAE,
+            0x3c01 => Language::ArabicBahrain,
+            0x4001 => Language::ArabicQatar,
+            0x042b => Language::Armenian,
+            0x044d => Language::Assamese,
+            0x042c => Language::AzeriLatin,
+            0x082c => Language::AzeriCyrillic,
+            0x042d => Language::Basque,
+            0x0423 => Language::Belarussian,
+            0x0445 => Language::Bengali,
+            0x0402 => Language::Bulgarian,
+            0x0455 => Language::Burmese,
+            0x0403 => Language::Catalan,
+            0x0404 => Language::ChineseTaiwan,
+            0x0804 => Language::ChinesePRC,
+            0x0c04 => Language::ChineseHongKongSARPRC,
+            0x1004 => Language::ChineseSingapore,
+            0x1404 => Language::ChineseMacauSAR,
+            0x041a => Language::Croatian,
+            0x0405 => Language::Czech,
+            0x0406 => Language::Danish,
+            0x0413 => Language::DutchNetherlands,
+            0x0813 => Language::DutchBelgium,
+            0x0409 => Language::EnglishUnitedStates,
+            0x0809 => Language::EnglishUnitedKingdom,
+            0x0c09 => Language::EnglishAustralian,
+            0x1009 => Language::EnglishCanadian,
+            0x1409 => Language::EnglishNewZealand,
+            0x1809 => Language::EnglishIreland,
+            0x1c09 => Language::EnglishSouthAfrica,
+            0x2009 => Language::EnglishJamaica,
+            0x2409 => Language::EnglishCaribbean,
+            0x2809 => Language::EnglishBelize,
+            0x2c09 => Language::EnglishTrinidad,
+            0x3009 => Language::EnglishZimbabwe,
+            0x3409 => Language::EnglishPhilippines,
+            0x0425 => Language::Estonian,
+            0x0438 => Language::Faeroese,
+            0x0429 => Language::Farsi,
+            0x040b => Language::Finnish,
+            0x040c => Language::FrenchStandard,
+            0x080c => Language::FrenchBelgian,
+            0x0c0c => Language::FrenchCanadian,
+            0x100c => Language::FrenchSwitzerland,
+            0x140c => Language::FrenchLuxembourg,
+            0x180c => Language::FrenchMonaco,
+            0x0437 => Language::Georgian,
+            0x0407 => Language::GermanStandard,
+            0x0807 => Language::GermanSwitzerland,
+            0x0c07 => Language::GermanAustria,
+            0x1007 => Language::GermanLuxembourg,
+            0x1407 => Language::GermanLiechtenstein,
+            0x0408 => Language::Greek,
+            0x0447 => Language::Gujarati,
+            0x040d => Language::Hebrew,
+            0x0439 => Language::Hindi,
+            0x040e => Language::Hungarian,
+            0x040f => Language::Icelandic,
+            0x0421 => Language::Indonesian,
+            0x0410 => Language::ItalianStandard,
+            0x0810 => Language::ItalianSwitzerland,
+            0x0411 => Language::Japanese,
+            0x044b => Language::Kannada,
+            0x0860 => Language::KashmiriIndia,
+            0x043f => Language::Kazakh,
+            0x0457 => Language::Konkani,
+            0x0412 => Language::Korean,
+            0x0812 => Language::KoreanJohab,
+            0x0426 => Language::Latvian,
+            0x0427 => Language::Lithuanian,
+            0x0827 => Language::LithuanianClassic,
+            0x042f => Language::Macedonian,
+            0x043e => Language::MalayMalaysian,
+            0x083e => Language::MalayBruneiDarussalam,
+            0x044c => Language::Malayalam,
+            0x0458 => Language::Manipuri,
+            0x044e => Language::Marathi,
+            0x0861 => Language::NepaliIndia,
+            0x0414 => Language::NorwegianBokmal,
+            0x0814 => Language::NorwegianNynorsk,
+            0x0448 => Language::Oriya,
+            0x0415 => Language::Polish,
+            0x0416 => Language::PortugueseBrazil,
+            0x0816 => Language::PortugueseStandard,
+            0x0446 => Language::Punjabi,
+            0x0418 => Language::Romanian,
+            0x0419 => Language::Russian,
+            0x044f => Language::Sanskrit,
+            0x0c1a => Language::SerbianCyrillic,
+            0x081a => Language::SerbianLatin,
+            0x0459 => Language::Sindhi,
+            0x041b => Language::Slovak,
+            0x0424 => Language::Slovenian,
+            0x040a => Language::SpanishTraditionalSort,
+            0x080a => Language::SpanishMexican,
+            0x0c0a => Language::SpanishModernSort,
+            0x100a => Language::SpanishGuatemala,
+            0x140a => Language::SpanishCostaRica,
+            0x180a => Language::SpanishPanama,
+            0x1c0a => Language::SpanishDominicanRepublic,
+            0x200a => Language::SpanishVenezuela,
+            0x240a => Language::SpanishColombia,
+            0x280a => Language::SpanishPeru,
+            0x2c0a => Language::SpanishArgentina,
+            0x300a => Language::SpanishEcuador,
+            0x340a => Language::SpanishChile,
+            0x380a => Language::SpanishUruguay,
+            0x3c0a => Language::SpanishParaguay,
+            0x400a => Language::SpanishBolivia,
+            0x440a => Language::SpanishElSalvador,
+            0x480a => Language::SpanishHonduras,
+            0x4c0a => Language::SpanishNicaragua,
+            0x500a => Language::SpanishPuertoRico,
+            0x0430 => Language::Sutu,
+            0x0441 => Language::SwahiliKenya,
+            0x041d => Language::Swedish,
+            0x081d => Language::SwedishFinland,
+            0x0449 => Language::Tamil,
+            0x0444 => Language::TatarTatarstan,
+            0x044a => Language::Telugu,
+            0x041e => Language::Thai,
+            0x041f => Language::Turkish,
+            0x0422 => Language::Ukrainian,
+            0x0420 => Language::UrduPakistan,
+            0x0820 => Language::UrduIndia,
+            0x0443 => Language::UzbekLatin,
+            0x0843 => Language::UzbekCyrillic,
+            0x042a => Language::Vietnamese,
+            0x04ff => Language::HidUsageDataDescriptor,
+            0xf0ff => Language::HidVendorDefined1,
+            0xf4ff => Language::HidVendorDefined2,
+            0xf8ff => Language::HidVendorDefined3,
+            0xfcff => Language::HidVendorDefined4,
+            other => Language::Other(other),
+        }
+    }
+}
+
+/// Handling of strings missing in a language of a [`LanguageSet`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LanguageFallback {
+    /// Use the string of the primary language or, if missing, of the first language
+    /// of the set that provides it.
+    #[default]
+    Primary,
+    /// Use an empty string.
+    Empty,
+    /// Fail with an invalid input error.
+    Error,
+}
+
+/// Set of languages that USB strings are provided in.
+///
+/// The USB host reads the list of supported languages once for the whole device and
+/// expects every string to be available in each of them.
+/// Strings of the gadget, its configurations and custom functions are thus normalized to
+/// the same set of languages using [`Gadget::normalize_languages`] and
+/// [`CustomBuilder::normalize_languages`](crate::function::custom::CustomBuilder::normalize_languages).
+/// Strings missing in a language are filled in according to the [fallback policy](LanguageFallback)
+/// and strings in languages not contained in the set are removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageSet {
+    languages: Vec<Language>,
+    fallback: LanguageFallback,
+}
+
+impl LanguageSet {
+    /// Creates a language set containing the primary language.
+    pub fn new(primary: Language) -> Self {
+        Self { languages: vec![primary], fallback: LanguageFallback::default() }
+    }
+
+    /// Creates a language set containing all languages used by the gadget and its configurations.
+    ///
+    /// The default language is primary, if it is used.
+    pub fn from_gadget(gadget: &Gadget) -> Self {
+        let mut languages: Vec<_> = gadget
+            .strings
+            .keys()
+            .chain(gadget.configs.iter().flat_map(|config| config.description.keys()))
+            .copied()
+            .collect();
+        languages.sort_by_key(|&lang| (lang != Language::default(), lang));
+        languages.dedup();
+
+        match languages.first() {
+            Some(&primary) => languages.into_iter().fold(Self::new(primary), Self::with_language),
+            None => Self::new(Language::default()),
+        }
+    }
+
+    /// Adds a language.
+    #[must_use]
+    pub fn with_language(mut self, language: Language) -> Self {
+        if !self.languages.contains(&language) {
+            self.languages.push(language);
+        }
+        self
+    }
+
+    /// Sets the handling of strings missing in a language.
+    #[must_use]
+    pub fn with_fallback(mut self, fallback: LanguageFallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Primary language.
+    pub fn primary(&self) -> Language {
+        self.languages[0]
+    }
+
+    /// Languages, starting with the primary language.
+    pub fn languages(&self) -> &[Language] {
+        &self.languages
+    }
+
+    /// Handling of strings missing in a language.
+    pub fn fallback(&self) -> LanguageFallback {
+        self.fallback
+    }
+
+    /// Normalizes strings to contain exactly the languages of this set.
+    pub fn normalize<T: Clone + Default>(&self, strings: &HashMap<Language, T>) -> Result<HashMap<Language, T>> {
+        let fallback = self.languages.iter().find_map(|lang| strings.get(lang));
+
+        let mut normalized = HashMap::new();
+        for &lang in &self.languages {
+            let value = match (strings.get(&lang), self.fallback) {
+                (Some(value), _) => value.clone(),
+                (None, LanguageFallback::Primary) => fallback.cloned().unwrap_or_default(),
+                (None, LanguageFallback::Empty) => T::default(),
+                (None, LanguageFallback::Error) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("string missing in language {lang:?}"),
+                    ))
+                }
+            };
+            normalized.insert(lang, value);
+        }
+
+        Ok(normalized)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{Language, LanguageFallback, LanguageSet};
+
+    #[test]
+    fn normalize() {
+        let set = LanguageSet::new(Language::EnglishUnitedStates).with_language(Language::GermanStandard);
+        let strings = HashMap::from([
+            (Language::GermanStandard, "Geraet".to_string()),
+            (Language::FrenchStandard, "Appareil".to_string()),
+        ]);
+
+        let normalized = set.normalize(&strings).unwrap();
+        assert_eq!(normalized.len(), 2);
+        assert_eq!(normalized[&Language::EnglishUnitedStates], "Geraet");
+        assert_eq!(normalized[&Language::GermanStandard], "Geraet");
+
+        let set = set.with_fallback(LanguageFallback::Empty);
+        assert_eq!(set.normalize(&strings).unwrap()[&Language::EnglishUnitedStates], "");
+
+        let set = set.with_fallback(LanguageFallback::Error);
+        assert!(set.normalize(&strings).is_err());
+    }
+}
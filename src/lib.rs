@@ -12,12 +12,19 @@
 //!
 //! root permissions are required to configure USB gadgets and
 //! the `configfs` filesystem needs to be mounted.
+//! If no init system mounts it, for example during early boot, use [`ConfigfsSetup`].
+//! [`Gadget::required_access`] and [`RegGadget::required_access`] list the
+//! paths and capabilities needed in detail, for example to set up a Landlock ruleset.
+//! Denied access is reported as [`InsufficientPermissions`] error, which names the path
+//! and suggests how to grant access. [`AccessRequirements::check`] performs this check up front.
 //!
 //! ### Usage
 //!
 //! Start defining an USB gadget by calling [`Gadget::new`].
 //! When the gadget is fully specified, call [`Gadget::bind`] to register it with
 //! a [USB device controller (UDC)](Udc).
+//! The gadget is removed when the returned [`RegGadget`] is dropped.
+//! Use [`RemovalGuard`] to also remove it when the process panics or exits abnormally.
 
 #![warn(missing_docs)]
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
@@ -25,16 +32,19 @@
 #[cfg(not(target_os = "linux"))]
 compile_error!("usb_gadget only supports Linux");
 
+extern crate alloc;
+
 use proc_mounts::MountIter;
 use std::{
     ffi::{CStr, OsStr},
+    fmt,
     io::{Error, ErrorKind, Result},
     os::unix::prelude::OsStrExt,
     path::PathBuf,
-    process::Command,
     sync::OnceLock,
 };
 
+pub mod classes;
 pub mod function;
 
 mod gadget;
@@ -46,6 +56,44 @@ pub use udc::*;
 mod lang;
 pub use lang::*;
 
+mod system;
+pub use system::*;
+
+mod dump;
+pub use dump::*;
+
+mod access;
+pub use access::*;
+
+mod host_match;
+pub use host_match::*;
+
+mod identity;
+pub use identity::*;
+
+mod dry_run;
+pub use dry_run::*;
+
+mod doctor;
+pub use doctor::*;
+
+mod deferred;
+pub use deferred::*;
+
+mod power;
+pub use power::*;
+
+mod cleanup;
+pub use cleanup::*;
+
+pub mod service;
+
+#[cfg(feature = "netlink")]
+mod netlink;
+
+#[cfg(feature = "host-tests")]
+pub mod host;
+
 /// USB speed.
 #[derive(
     Default, Debug, strum::Display, strum::EnumString, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
@@ -53,7 +101,14 @@ pub use lang::*;
 #[non_exhaustive]
 pub enum Speed {
     /// USB 3.1: 10 Gbit/s.
-    #[strum(serialize = "super-speed-plus")]
+    ///
+    /// Also parsed from the signaling rate and lane count variants, e.g. `super-speed-plus-gen2x1`.
+    #[strum(
+        to_string = "super-speed-plus",
+        serialize = "super-speed-plus-gen1x2",
+        serialize = "super-speed-plus-gen2x1",
+        serialize = "super-speed-plus-gen2x2"
+    )]
     SuperSpeedPlus,
     /// USB 3.0: 5 Gbit/s.
     #[strum(serialize = "super-speed")]
@@ -61,6 +116,9 @@ pub enum Speed {
     /// USB 2.0: 480 Mbit/s.
     #[strum(serialize = "high-speed")]
     HighSpeed,
+    /// Wireless USB 2.5: 480 Mbit/s.
+    #[strum(serialize = "wireless")]
+    Wireless,
     /// USB 1.0: 12 Mbit/s.
     #[strum(serialize = "full-speed")]
     FullSpeed,
@@ -73,6 +131,45 @@ pub enum Speed {
     Unknown,
 }
 
+impl Speed {
+    /// Parses a speed as reported by the kernel in sysfs, e.g. `high-speed`.
+    ///
+    /// Surrounding whitespace and NUL characters are ignored.
+    /// Returns the trimmed value if it is unknown.
+    pub fn from_sysfs(value: &str) -> std::result::Result<Self, String> {
+        let value = sysfs_str(value);
+        value.parse().map_err(|_| value.to_string())
+    }
+}
+
+/// Error indicating that a sysfs attribute has a value unknown to this library.
+///
+/// This is returned as the inner error of an [`ErrorKind::InvalidData`] I/O error.
+/// It usually means that the kernel reports a value introduced after this library was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct UnknownSysfsValue {
+    /// Path of the attribute.
+    pub attribute: PathBuf,
+    /// Trimmed value of the attribute.
+    pub value: String,
+}
+
+impl fmt::Display for UnknownSysfsValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown value {:?} of sysfs attribute {}", self.value, self.attribute.display())
+    }
+}
+
+impl std::error::Error for UnknownSysfsValue {}
+
+/// Trims whitespace and NUL characters surrounding a sysfs value.
+///
+/// Only ASCII characters are trimmed, independent of the locale.
+fn sysfs_str(value: &str) -> &str {
+    value.trim_matches(|c: char| c.is_ascii_whitespace() || c == '\0')
+}
+
 /// 8-bit value to hexadecimal notation.
 fn hex_u8(value: u8) -> String {
     format!("0x{:02x}", value)
@@ -112,20 +209,7 @@ fn trim_os_str(value: &OsStr) -> &OsStr {
 
 /// Request a kernel module to be loaded.
 fn request_module(name: impl AsRef<OsStr>) -> Result<()> {
-    let mut res = Command::new("modprobe").arg("-q").arg(name.as_ref()).output();
-
-    match res {
-        Err(err) if err.kind() == ErrorKind::NotFound => {
-            res = Command::new("/sbin/modprobe").arg("-q").arg(name.as_ref()).output();
-        }
-        _ => (),
-    }
-
-    match res {
-        Ok(out) if out.status.success() => Ok(()),
-        Ok(_) => Err(Error::new(ErrorKind::Other, "modprobe failed")),
-        Err(err) => Err(err),
-    }
+    dry_run::load_module(name.as_ref())
 }
 
 /// Gets the Linux kernel version.
@@ -171,6 +255,20 @@ fn linux_version() -> Option<(u16, u16)> {
 
 #[cfg(test)]
 mod test {
+    use crate::Speed;
+
+    #[test]
+    fn speed_from_sysfs() {
+        assert_eq!(Speed::from_sysfs("high-speed\n"), Ok(Speed::HighSpeed));
+        assert_eq!(Speed::from_sysfs(" super-speed-plus\0"), Ok(Speed::SuperSpeedPlus));
+        assert_eq!(Speed::from_sysfs("super-speed-plus-gen2x2"), Ok(Speed::SuperSpeedPlus));
+        assert_eq!(Speed::SuperSpeedPlus.to_string(), "super-speed-plus");
+        assert_eq!(Speed::from_sysfs("wireless"), Ok(Speed::Wireless));
+        assert_eq!(Speed::from_sysfs("UNKNOWN"), Ok(Speed::Unknown));
+        assert_eq!(Speed::from_sysfs("ultra-speed\n"), Err("ultra-speed".to_string()));
+        assert_eq!(Speed::from_sysfs("High-Speed"), Err("High-Speed".to_string()));
+    }
+
     #[test]
     fn linux_version() {
         let (major, minor) = super::linux_version().expect("failed to get Linux version");
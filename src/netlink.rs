@@ -0,0 +1,333 @@
+//! Minimal netlink client for querying and configuring network interfaces.
+//!
+//! Link attributes are accessed through rtnetlink and offload features through
+//! the ethtool generic netlink family.
+
+use nix::sys::socket::{
+    bind, recv, send, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+use std::{
+    ffi::OsStr,
+    io::{Error, ErrorKind, Result},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+};
+
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+const NLA_HDRLEN: usize = 4;
+const NLA_F_NESTED: u16 = 1 << 15;
+const NLA_TYPE_MASK: u16 = !(NLA_F_NESTED | 1 << 14);
+
+const RTM_GETLINK: u16 = 18;
+const RTM_SETLINK: u16 = 19;
+const IFINFOMSG_LEN: usize = 16;
+
+/// Interface name.
+const IFLA_IFNAME: u16 = 3;
+/// Length of the transmit queue in packets.
+pub(crate) const IFLA_TXQLEN: u16 = 13;
+/// Number of transmit queues.
+pub(crate) const IFLA_NUM_TX_QUEUES: u16 = 31;
+/// Number of receive queues.
+pub(crate) const IFLA_NUM_RX_QUEUES: u16 = 32;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const ETHTOOL_GENL_NAME: &str = "ethtool";
+const ETHTOOL_GENL_VERSION: u8 = 1;
+const ETHTOOL_MSG_FEATURES_GET: u8 = 11;
+const ETHTOOL_MSG_FEATURES_SET: u8 = 12;
+const ETHTOOL_A_HEADER_DEV_NAME: u16 = 2;
+const ETHTOOL_A_FEATURES_HEADER: u16 = 1;
+const ETHTOOL_A_FEATURES_WANTED: u16 = 3;
+const ETHTOOL_A_FEATURES_ACTIVE: u16 = 4;
+const ETHTOOL_A_BITSET_NOMASK: u16 = 1;
+const ETHTOOL_A_BITSET_BITS: u16 = 3;
+const ETHTOOL_A_BITSET_BITS_BIT: u16 = 1;
+const ETHTOOL_A_BITSET_BIT_NAME: u16 = 2;
+const ETHTOOL_A_BITSET_BIT_VALUE: u16 = 3;
+
+/// Netlink request under construction.
+struct Request {
+    data: Vec<u8>,
+    nests: Vec<usize>,
+}
+
+impl Request {
+    /// Starts a request of the specified type followed by a family-specific header.
+    fn new(msg_type: u16, header: &[u8]) -> Self {
+        let mut data = vec![0; NLMSG_HDRLEN];
+        data[4..6].copy_from_slice(&msg_type.to_ne_bytes());
+        data[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        data.extend_from_slice(header);
+        let mut this = Self { data, nests: Vec::new() };
+        this.align();
+        this
+    }
+
+    /// Starts a request to an rtnetlink link.
+    fn link(msg_type: u16, ifname: &OsStr) -> Self {
+        let mut req = Self::new(msg_type, &[0; IFINFOMSG_LEN]);
+        req.put_str(IFLA_IFNAME, ifname);
+        req
+    }
+
+    /// Starts a generic netlink request.
+    fn generic(family: u16, cmd: u8, version: u8) -> Self {
+        Self::new(family, &[cmd, version, 0, 0])
+    }
+
+    fn align(&mut self) {
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+    }
+
+    fn put(&mut self, attr_type: u16, value: &[u8]) {
+        let len = u16::try_from(NLA_HDRLEN + value.len()).expect("netlink attribute too long");
+        self.data.extend_from_slice(&len.to_ne_bytes());
+        self.data.extend_from_slice(&attr_type.to_ne_bytes());
+        self.data.extend_from_slice(value);
+        self.align();
+    }
+
+    fn put_str(&mut self, attr_type: u16, value: impl AsRef<OsStr>) {
+        let mut value = value.as_ref().as_bytes().to_vec();
+        value.push(0);
+        self.put(attr_type, &value);
+    }
+
+    fn put_u32(&mut self, attr_type: u16, value: u32) {
+        self.put(attr_type, &value.to_ne_bytes());
+    }
+
+    fn begin_nest(&mut self, attr_type: u16) {
+        self.nests.push(self.data.len());
+        self.put(attr_type | NLA_F_NESTED, &[]);
+    }
+
+    fn end_nest(&mut self) {
+        let start = self.nests.pop().expect("no open nest");
+        let len = u16::try_from(self.data.len() - start).expect("netlink attribute too long");
+        self.data[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+    }
+
+    /// Finishes the request and returns its data.
+    fn finish(mut self, seq: u32) -> Vec<u8> {
+        assert!(self.nests.is_empty(), "unclosed nest");
+        let len = u32::try_from(self.data.len()).expect("netlink message too long");
+        self.data[0..4].copy_from_slice(&len.to_ne_bytes());
+        self.data[8..12].copy_from_slice(&seq.to_ne_bytes());
+        self.data
+    }
+}
+
+/// Iterates over the netlink attributes within data, yielding their types and values.
+fn attrs(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = usize::from(u16::from_ne_bytes(data.get(0..2)?.try_into().unwrap()));
+        let attr_type = u16::from_ne_bytes(data.get(2..4)?.try_into().unwrap());
+        let value = data.get(NLA_HDRLEN..len)?;
+        data = data.get(len.next_multiple_of(4)..).unwrap_or_default();
+        Some((attr_type & NLA_TYPE_MASK, value))
+    })
+}
+
+/// Finds a netlink attribute within data.
+fn attr(data: &[u8], attr_type: u16) -> Option<&[u8]> {
+    attrs(data).find_map(|(t, value)| (t == attr_type).then_some(value))
+}
+
+fn attr_u32(data: &[u8], attr_type: u16) -> Result<u32> {
+    let value = attr(data, attr_type)
+        .ok_or_else(|| Error::new(ErrorKind::Unsupported, format!("netlink attribute {attr_type} missing")))?;
+    Ok(u32::from_ne_bytes(
+        value.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, "invalid netlink attribute"))?,
+    ))
+}
+
+fn attr_str(value: &[u8]) -> &[u8] {
+    value.split(|&b| b == 0).next().unwrap_or_default()
+}
+
+/// Netlink socket.
+struct Netlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Netlink {
+    fn new(protocol: SockProtocol) -> Result<Self> {
+        let fd = socket(AddressFamily::Netlink, SockType::Raw, SockFlag::SOCK_CLOEXEC, protocol)?;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, 0))?;
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Sends a request and returns the payloads of the replies.
+    ///
+    /// Fails if the kernel acknowledges the request with an error.
+    fn request(&mut self, req: Request) -> Result<Vec<Vec<u8>>> {
+        self.seq = self.seq.wrapping_add(1);
+        send(self.fd.as_raw_fd(), &req.finish(self.seq), MsgFlags::empty())?;
+
+        let mut replies = Vec::new();
+        let mut buf = vec![0; 65536];
+        loop {
+            let n = recv(self.fd.as_raw_fd(), &mut buf, MsgFlags::empty())?;
+            let mut data = &buf[..n];
+            while data.len() >= NLMSG_HDRLEN {
+                let len = u32::from_ne_bytes(data[0..4].try_into().unwrap()) as usize;
+                let msg_type = u16::from_ne_bytes(data[4..6].try_into().unwrap());
+                let seq = u32::from_ne_bytes(data[8..12].try_into().unwrap());
+                let payload = data
+                    .get(NLMSG_HDRLEN..len)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "truncated netlink message"))?;
+                data = data.get(len.next_multiple_of(4)..).unwrap_or_default();
+
+                if seq != self.seq {
+                    continue;
+                }
+                match msg_type {
+                    NLMSG_ERROR => {
+                        let errno = i32::from_ne_bytes(
+                            payload
+                                .get(0..4)
+                                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "truncated netlink error"))?
+                                .try_into()
+                                .unwrap(),
+                        );
+                        return match errno {
+                            0 => Ok(replies),
+                            _ => Err(Error::from_raw_os_error(-errno)),
+                        };
+                    }
+                    NLMSG_DONE => return Ok(replies),
+                    _ => replies.push(payload.to_vec()),
+                }
+            }
+        }
+    }
+
+    /// Sends a request that is answered by exactly one reply and returns its payload.
+    fn request_one(&mut self, req: Request) -> Result<Vec<u8>> {
+        self.request(req)?.pop().ok_or_else(|| Error::new(ErrorKind::InvalidData, "no netlink reply"))
+    }
+}
+
+/// Reads an integer attribute of a network interface using rtnetlink.
+pub(crate) fn link_u32(ifname: &OsStr, attr_type: u16) -> Result<u32> {
+    let mut nl = Netlink::new(SockProtocol::NetlinkRoute)?;
+    let reply = nl.request_one(Request::link(RTM_GETLINK, ifname))?;
+    let attrs = reply.get(IFINFOMSG_LEN..).unwrap_or_default();
+    attr_u32(attrs, attr_type)
+}
+
+/// Sets an integer attribute of a network interface using rtnetlink.
+pub(crate) fn set_link_u32(ifname: &OsStr, attr_type: u16, value: u32) -> Result<()> {
+    let mut nl = Netlink::new(SockProtocol::NetlinkRoute)?;
+    let mut req = Request::link(RTM_SETLINK, ifname);
+    req.put_u32(attr_type, value);
+    nl.request(req)?;
+    Ok(())
+}
+
+/// Resolves the id of the ethtool generic netlink family.
+fn ethtool_family(nl: &mut Netlink) -> Result<u16> {
+    let mut req = Request::generic(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, 1);
+    req.put_str(CTRL_ATTR_FAMILY_NAME, ETHTOOL_GENL_NAME);
+    let reply = nl.request_one(req).map_err(|err| match err.kind() {
+        ErrorKind::NotFound => {
+            Error::new(ErrorKind::Unsupported, "ethtool netlink interface is unsupported by kernel")
+        }
+        _ => err,
+    })?;
+    let id = attr(reply.get(4..).unwrap_or_default(), CTRL_ATTR_FAMILY_ID)
+        .and_then(|id| id.try_into().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "ethtool family id missing"))?;
+    Ok(u16::from_ne_bytes(id))
+}
+
+/// Whether the offload feature with the specified name, for example `rx-gro`,
+/// is active on a network interface.
+pub(crate) fn ethtool_feature(ifname: &OsStr, feature: &str) -> Result<bool> {
+    let mut nl = Netlink::new(SockProtocol::NetlinkGeneric)?;
+    let family = ethtool_family(&mut nl)?;
+
+    let mut req = Request::generic(family, ETHTOOL_MSG_FEATURES_GET, ETHTOOL_GENL_VERSION);
+    req.begin_nest(ETHTOOL_A_FEATURES_HEADER);
+    req.put_str(ETHTOOL_A_HEADER_DEV_NAME, ifname);
+    req.end_nest();
+    let reply = nl.request_one(req)?;
+
+    let active = attr(reply.get(4..).unwrap_or_default(), ETHTOOL_A_FEATURES_ACTIVE)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "active features missing"))?;
+    let no_mask = attr(active, ETHTOOL_A_BITSET_NOMASK).is_some();
+    let bits = attr(active, ETHTOOL_A_BITSET_BITS).unwrap_or_default();
+    for (_, bit) in attrs(bits).filter(|(t, _)| *t == ETHTOOL_A_BITSET_BITS_BIT) {
+        if attr(bit, ETHTOOL_A_BITSET_BIT_NAME).map(attr_str) == Some(feature.as_bytes()) {
+            return Ok(no_mask || attr(bit, ETHTOOL_A_BITSET_BIT_VALUE).is_some());
+        }
+    }
+    Ok(false)
+}
+
+/// Enables or disables the offload feature with the specified name on a network interface.
+///
+/// Fails if the kernel does not apply the change, for example because the feature is fixed.
+pub(crate) fn set_ethtool_feature(ifname: &OsStr, feature: &str, enabled: bool) -> Result<()> {
+    let mut nl = Netlink::new(SockProtocol::NetlinkGeneric)?;
+    let family = ethtool_family(&mut nl)?;
+
+    let mut req = Request::generic(family, ETHTOOL_MSG_FEATURES_SET, ETHTOOL_GENL_VERSION);
+    req.begin_nest(ETHTOOL_A_FEATURES_HEADER);
+    req.put_str(ETHTOOL_A_HEADER_DEV_NAME, ifname);
+    req.end_nest();
+    req.begin_nest(ETHTOOL_A_FEATURES_WANTED);
+    req.begin_nest(ETHTOOL_A_BITSET_BITS);
+    req.begin_nest(ETHTOOL_A_BITSET_BITS_BIT);
+    req.put_str(ETHTOOL_A_BITSET_BIT_NAME, feature);
+    if enabled {
+        req.put(ETHTOOL_A_BITSET_BIT_VALUE, &[]);
+    }
+    req.end_nest();
+    req.end_nest();
+    req.end_nest();
+    nl.request(req)?;
+
+    if ethtool_feature(ifname, feature)? != enabled {
+        return Err(Error::new(ErrorKind::Unsupported, format!("feature {feature} cannot be changed")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attr, attr_str, attrs, Request, NLA_F_NESTED, NLMSG_HDRLEN};
+
+    #[test]
+    fn nested_attributes() {
+        let mut req = Request::generic(0x20, 1, 1);
+        req.begin_nest(1);
+        req.put_str(2, "usb0");
+        req.end_nest();
+        req.put(3, &[]);
+        let data = req.finish(7);
+
+        assert_eq!(u32::from_ne_bytes(data[0..4].try_into().unwrap()) as usize, data.len());
+        let attrs_data = &data[NLMSG_HDRLEN + 4..];
+        let types: Vec<_> = attrs(attrs_data).map(|(t, _)| t).collect();
+        assert_eq!(types, [1, 3]);
+        assert_eq!(u16::from_ne_bytes(attrs_data[2..4].try_into().unwrap()), 1 | NLA_F_NESTED);
+
+        let nested = attr(attrs_data, 1).unwrap();
+        assert_eq!(attr(nested, 2).map(attr_str), Some(&b"usb0"[..]));
+        assert_eq!(attr(attrs_data, 3), Some(&[][..]));
+    }
+}
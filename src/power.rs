@@ -0,0 +1,217 @@
+//! Power management hooks of USB gadgets.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{function::Handle, GadgetState, GadgetStateWatcher};
+
+/// Change of the power state of a USB gadget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PowerEvent {
+    /// The USB host suspended the bus.
+    Suspend,
+    /// The USB host resumed the bus or the gadget left the suspended state otherwise,
+    /// for example by being disconnected.
+    Resume,
+}
+
+/// Callback invoked on a change of the power state.
+type PowerCallback = Box<dyn FnMut(PowerEvent) + Send>;
+
+/// Callbacks invoked when the USB host suspends or resumes a USB gadget.
+///
+/// Pass to [`RegGadget::watch_power`](crate::RegGadget::watch_power).
+/// This allows gadgets consisting of kernel functions, which do not receive suspend
+/// and resume events, to stop DMA transfers and streams while the bus is suspended.
+pub struct PowerHooks {
+    /// Interval for checking whether the watcher has been dropped.
+    ///
+    /// Defaults to 500 ms.
+    pub poll_interval: Duration,
+    function_hooks: Vec<(Handle, PowerCallback)>,
+    callbacks: Vec<PowerCallback>,
+}
+
+impl fmt::Debug for PowerHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PowerHooks")
+            .field("poll_interval", &self.poll_interval)
+            .field("function_hooks", &self.function_hooks.len())
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl Default for PowerHooks {
+    fn default() -> Self {
+        Self { poll_interval: Duration::from_millis(500), function_hooks: Vec::new(), callbacks: Vec::new() }
+    }
+}
+
+impl PowerHooks {
+    /// Creates power hooks without callbacks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the interval for checking whether the watcher has been dropped.
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Adds a callback invoked when the USB host suspends the gadget.
+    #[must_use]
+    pub fn with_on_suspend(mut self, mut on_suspend: impl FnMut() + Send + 'static) -> Self {
+        self.callbacks.push(Box::new(move |event| {
+            if event == PowerEvent::Suspend {
+                on_suspend()
+            }
+        }));
+        self
+    }
+
+    /// Adds a callback invoked when the gadget leaves the suspended state.
+    #[must_use]
+    pub fn with_on_resume(mut self, mut on_resume: impl FnMut() + Send + 'static) -> Self {
+        self.callbacks.push(Box::new(move |event| {
+            if event == PowerEvent::Resume {
+                on_resume()
+            }
+        }));
+        self
+    }
+
+    /// Adds a hook quiescing the specified function on suspend and restarting it on resume.
+    ///
+    /// Function hooks are invoked before the suspend callbacks and after the resume callbacks.
+    /// The function must be part of the gadget.
+    #[must_use]
+    pub fn with_function_hook(mut self, func: &Handle, hook: impl FnMut(PowerEvent) + Send + 'static) -> Self {
+        self.function_hooks.push((func.clone(), Box::new(hook)));
+        self
+    }
+
+    /// Functions with hooks.
+    pub(crate) fn functions(&self) -> impl Iterator<Item = &Handle> {
+        self.function_hooks.iter().map(|(func, _)| func)
+    }
+
+    /// Invokes the hooks in the order appropriate for the event.
+    fn dispatch(&mut self, event: PowerEvent) {
+        log::debug!("dispatching power event {event:?}");
+        match event {
+            PowerEvent::Suspend => {
+                self.function_hooks.iter_mut().for_each(|(_, hook)| hook(event));
+                self.callbacks.iter_mut().for_each(|cb| cb(event));
+            }
+            PowerEvent::Resume => {
+                self.callbacks.iter_mut().for_each(|cb| cb(event));
+                self.function_hooks.iter_mut().for_each(|(_, hook)| hook(event));
+            }
+        }
+    }
+}
+
+/// Watches the power state of a USB gadget and invokes [`PowerHooks`].
+///
+/// Obtained by calling [`RegGadget::watch_power`](crate::RegGadget::watch_power).
+/// A watcher thread observes the state of the USB device controller (UDC) the gadget is bound to.
+/// Dropping this stops the watcher.
+#[derive(Debug)]
+pub struct PowerWatcher {
+    suspended: Arc<AtomicBool>,
+    stop_tx: Option<mpsc::Sender<()>>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl PowerWatcher {
+    pub(crate) fn new(mut state: GadgetStateWatcher, mut hooks: PowerHooks) -> Self {
+        let suspended = Arc::new(AtomicBool::new(state.state() == GadgetState::Suspended));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+
+        let thread_suspended = suspended.clone();
+        let watcher = thread::spawn(move || loop {
+            match stop_rx.try_recv() {
+                Err(mpsc::TryRecvError::Empty) => (),
+                _ => return,
+            }
+
+            match state.wait(Some(hooks.poll_interval)) {
+                Ok(Some(new_state)) => {
+                    let now_suspended = new_state == GadgetState::Suspended;
+                    if thread_suspended.swap(now_suspended, Ordering::SeqCst) != now_suspended {
+                        hooks.dispatch(if now_suspended { PowerEvent::Suspend } else { PowerEvent::Resume });
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    log::warn!("watching power state of gadget failed: {err}");
+                    return;
+                }
+            }
+        });
+
+        Self { suspended, stop_tx: Some(stop_tx), watcher: Some(watcher) }
+    }
+
+    /// Whether the gadget is currently suspended.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst)
+    }
+
+    /// Whether the watcher thread is still running.
+    ///
+    /// It stops if the state of the UDC cannot be read anymore.
+    pub fn is_running(&self) -> bool {
+        self.watcher.as_ref().is_some_and(|watcher| !watcher.is_finished())
+    }
+}
+
+impl Drop for PowerWatcher {
+    fn drop(&mut self) {
+        self.stop_tx.take();
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::{PowerEvent, PowerHooks};
+    use crate::function::hid::HidBuilder;
+
+    #[test]
+    fn dispatch_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (_hid, func) = HidBuilder::keyboard().build();
+
+        let c = calls.clone();
+        let f = calls.clone();
+        let r = calls.clone();
+        let mut hooks = PowerHooks::new()
+            .with_on_suspend(move || c.lock().unwrap().push("suspend"))
+            .with_function_hook(&func, move |event| {
+                f.lock().unwrap().push(if event == PowerEvent::Suspend { "quiesce" } else { "restart" })
+            })
+            .with_on_resume(move || r.lock().unwrap().push("resume"));
+        assert_eq!(hooks.functions().count(), 1);
+
+        hooks.dispatch(PowerEvent::Suspend);
+        hooks.dispatch(PowerEvent::Resume);
+        assert_eq!(*calls.lock().unwrap(), ["quiesce", "suspend", "resume", "restart"]);
+    }
+}
@@ -0,0 +1,325 @@
+//! Lifecycle of a USB gadget provided by a system service.
+//!
+//! A service typically removes stale gadgets left behind by a previous instance,
+//! registers and binds its gadget, notifies the service manager that it is ready
+//! and finally unbinds and removes the gadget when asked to terminate.
+//! [`Service`] codifies this sequence.
+
+use std::{
+    env,
+    ffi::OsStr,
+    io::{Error, ErrorKind, Result},
+    os::{
+        fd::{AsFd, AsRawFd, OwnedFd},
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    unistd::pipe2,
+};
+
+use crate::{default_udc, Gadget, Namespace, RegGadget, Udc};
+
+/// Environment variable containing the socket of the service manager.
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+/// Write end of the pipe signalled when a termination signal has been received,
+/// or -1 if no service is running.
+static TERMINATE_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn terminate_handler(_signal: libc::c_int) {
+    let fd = TERMINATE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // SAFETY: write is async-signal-safe and the pipe is non-blocking.
+        unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+    }
+}
+
+/// Sends a state notification to the service manager, e.g. `READY=1`.
+///
+/// The socket is taken from the `NOTIFY_SOCKET` environment variable.
+/// Returns `false` if the service was not started with notification support.
+pub fn notify(state: &str) -> Result<bool> {
+    match env::var_os(NOTIFY_SOCKET) {
+        Some(socket) => notify_socket(&socket, state),
+        None => Ok(false),
+    }
+}
+
+/// Sends a state notification to the service manager listening on the specified socket.
+fn notify_socket(socket: &OsStr, state: &str) -> Result<bool> {
+    if socket.is_empty() {
+        return Ok(false);
+    }
+
+    let addr = match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(socket)?,
+    };
+
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/// Lifecycle of a USB gadget provided by a system service.
+///
+/// Gadgets are registered in the namespace of the service.
+/// When starting, all gadgets of the namespace are removed, since they are left over
+/// from a previous instance of the service that did not terminate cleanly.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Service {
+    /// Namespace the gadget is registered in.
+    pub namespace: Namespace,
+    /// USB device controller to bind to.
+    ///
+    /// If unset, the [default UDC](default_udc) is used.
+    pub udc: Option<Udc>,
+    /// Whether to notify the service manager of state changes.
+    ///
+    /// Enabled by default.
+    /// Notifications are only sent when the `NOTIFY_SOCKET` environment variable is set.
+    pub notify: bool,
+    /// Maximum time to wait for the gadget to be unbound and removed when stopping.
+    ///
+    /// Defaults to 10 seconds.
+    pub stop_timeout: Duration,
+}
+
+impl Service {
+    /// Creates a new service managing gadgets in the specified namespace.
+    pub fn new(namespace: Namespace) -> Self {
+        Self { namespace, udc: None, notify: true, stop_timeout: Duration::from_secs(10) }
+    }
+
+    /// Sets the USB device controller to bind to.
+    #[must_use]
+    pub fn with_udc(mut self, udc: Udc) -> Self {
+        self.udc = Some(udc);
+        self
+    }
+
+    /// Sets whether to notify the service manager of state changes.
+    #[must_use]
+    pub fn with_notify(mut self, notify: bool) -> Self {
+        self.notify = notify;
+        self
+    }
+
+    /// Sets the maximum time to wait for the gadget to be removed when stopping.
+    #[must_use]
+    pub fn with_stop_timeout(mut self, stop_timeout: Duration) -> Self {
+        self.stop_timeout = stop_timeout;
+        self
+    }
+
+    /// Removes stale gadgets, registers and binds the gadget and
+    /// notifies the service manager that the service is ready.
+    ///
+    /// Handlers for `SIGTERM` and `SIGINT` are installed, so that
+    /// [`RunningService::wait_for_termination`] can detect a termination request.
+    /// The previous handlers are restored when the service is stopped or dropped.
+    /// Only one service can be running at a time.
+    pub fn start(self, gadget: Gadget) -> Result<RunningService> {
+        let signals = TerminationSignals::install()?;
+
+        match self.namespace.remove_each() {
+            Ok(results) => {
+                for (name, res) in results {
+                    match res {
+                        Ok(()) => log::info!("removed stale gadget {}", name.to_string_lossy()),
+                        Err(err) => log::warn!("removing stale gadget {} failed: {err}", name.to_string_lossy()),
+                    }
+                }
+            }
+            Err(err) => log::warn!("enumerating gadgets of namespace {} failed: {err}", self.namespace.prefix()),
+        }
+
+        let udc = match &self.udc {
+            Some(udc) => udc.clone(),
+            None => default_udc()?,
+        };
+        let reg = gadget.with_namespace(&self.namespace).bind(&udc)?;
+        log::info!("gadget {} bound to {}", reg.name().to_string_lossy(), udc.name().to_string_lossy());
+
+        if self.notify {
+            notify("READY=1")?;
+        }
+
+        Ok(RunningService { reg: Some(reg), service: self, signals })
+    }
+
+    /// Starts the service, waits for a termination signal and stops the service.
+    pub fn run(self, gadget: Gadget) -> Result<()> {
+        let running = self.start(gadget)?;
+        running.wait_for_termination();
+        running.stop()
+    }
+}
+
+/// A started [`Service`] providing a bound USB gadget.
+///
+/// Dropping it removes the gadget without a timeout.
+#[derive(Debug)]
+pub struct RunningService {
+    reg: Option<RegGadget>,
+    service: Service,
+    signals: TerminationSignals,
+}
+
+impl RunningService {
+    /// The registered USB gadget.
+    pub fn reg(&self) -> &RegGadget {
+        self.reg.as_ref().unwrap()
+    }
+
+    /// The registered USB gadget.
+    pub fn reg_mut(&mut self) -> &mut RegGadget {
+        self.reg.as_mut().unwrap()
+    }
+
+    /// Whether a termination signal has been received.
+    pub fn is_terminating(&self) -> bool {
+        self.signals.wait(PollTimeout::ZERO)
+    }
+
+    /// Blocks until `SIGTERM` or `SIGINT` is received.
+    pub fn wait_for_termination(&self) {
+        while !self.signals.wait(PollTimeout::NONE) {}
+    }
+
+    /// Notifies the service manager that the service is stopping, then
+    /// unbinds and removes the gadget.
+    ///
+    /// If removal does not finish within the [stop timeout](Service::stop_timeout),
+    /// an [`ErrorKind::TimedOut`] error is returned and removal continues in the background.
+    pub fn stop(mut self) -> Result<()> {
+        if self.service.notify {
+            if let Err(err) = notify("STOPPING=1") {
+                log::warn!("notifying service manager failed: {err}");
+            }
+        }
+
+        let reg = self.reg.take().unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(reg.remove());
+        });
+
+        match rx.recv_timeout(self.service.stop_timeout) {
+            Ok(res) => res,
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "timeout removing USB gadget")),
+        }
+    }
+}
+
+/// Handlers for termination signals, notifying a pipe.
+///
+/// The previous handlers are restored when dropped.
+#[derive(Debug)]
+struct TerminationSignals {
+    rx: OwnedFd,
+    _tx: OwnedFd,
+    prev: Vec<(Signal, SigAction)>,
+}
+
+impl TerminationSignals {
+    /// Installs handlers for `SIGTERM` and `SIGINT`.
+    fn install() -> Result<Self> {
+        let (rx, tx) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+        if TERMINATE_FD.compare_exchange(-1, tx.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            return Err(Error::new(ErrorKind::AlreadyExists, "another service is running"));
+        }
+
+        let mut this = Self { rx, _tx: tx, prev: Vec::new() };
+        let action = SigAction::new(SigHandler::Handler(terminate_handler), SaFlags::SA_RESTART, SigSet::empty());
+        for signal in [Signal::SIGTERM, Signal::SIGINT] {
+            // SAFETY: the handler only performs an async-signal-safe write.
+            let prev = unsafe { sigaction(signal, &action) }?;
+            this.prev.push((signal, prev));
+        }
+        Ok(this)
+    }
+
+    /// Waits until a termination signal has been received or the timeout expires.
+    ///
+    /// Returns whether a termination signal has been received.
+    fn wait(&self, timeout: PollTimeout) -> bool {
+        let mut fds = [PollFd::new(self.rx.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(n) => n > 0,
+            Err(Errno::EINTR) => false,
+            Err(err) => panic!("polling termination pipe failed: {err}"),
+        }
+    }
+}
+
+impl Drop for TerminationSignals {
+    fn drop(&mut self) {
+        for (signal, prev) in self.prev.drain(..) {
+            // SAFETY: restores the handler that was installed before.
+            let _ = unsafe { sigaction(signal, &prev) };
+        }
+        TERMINATE_FD.store(-1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nix::{
+        poll::PollTimeout,
+        sys::signal::{raise, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    };
+    use std::{ffi::OsStr, os::unix::net::UnixDatagram};
+
+    use super::{notify_socket, TerminationSignals};
+
+    #[test]
+    fn notify_service_manager() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let sock = UnixDatagram::bind(&path).unwrap();
+
+        assert!(notify_socket(path.as_os_str(), "READY=1").unwrap());
+        assert!(!notify_socket(OsStr::new(""), "READY=1").unwrap());
+
+        let mut buf = [0; 64];
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+
+    #[test]
+    fn termination_signals() {
+        let signals = TerminationSignals::install().unwrap();
+        assert!(TerminationSignals::install().is_err());
+        assert!(!signals.wait(PollTimeout::ZERO));
+
+        raise(Signal::SIGTERM).unwrap();
+        assert!(signals.wait(PollTimeout::NONE));
+        assert!(signals.wait(PollTimeout::ZERO));
+        drop(signals);
+
+        let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+        let prev = unsafe { sigaction(Signal::SIGTERM, &action) }.unwrap();
+        assert_eq!(prev.handler(), SigHandler::SigDfl);
+
+        let signals = TerminationSignals::install().unwrap();
+        assert!(!signals.wait(PollTimeout::ZERO));
+    }
+}
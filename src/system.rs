@@ -0,0 +1,161 @@
+//! Privileged system operations.
+
+use std::{
+    ffi::OsStr,
+    fmt,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, RwLock},
+};
+
+use crate::{configfs_dir, dry_run, gadget::DEFAULT_CONFIGFS_DIR, request_module};
+
+/// Privileged system operations performed while configuring USB gadgets.
+///
+/// The default implementation of each method performs the operation directly.
+/// Override methods to delegate them, for example to a privileged helper process,
+/// and install the implementation using [`set_system_ops`].
+pub trait SystemOps: fmt::Debug + Send + Sync {
+    /// Requests the kernel module with the specified name to be loaded.
+    fn modprobe(&self, module: &OsStr) -> Result<()> {
+        let mut res = Command::new("modprobe").arg("-q").arg(module).output();
+
+        match res {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                res = Command::new("/sbin/modprobe").arg("-q").arg(module).output();
+            }
+            _ => (),
+        }
+
+        match res {
+            Ok(out) if out.status.success() => Ok(()),
+            Ok(_) => Err(Error::new(ErrorKind::Other, "modprobe failed")),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Mounts the file system `source` of type `fs_type` at `target` using the specified mount data.
+    fn mount(&self, source: &OsStr, target: &Path, fs_type: &str, data: &str) -> Result<()> {
+        nix::mount::mount(Some(source), target, Some(fs_type), nix::mount::MsFlags::empty(), Some(data))?;
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at `target`.
+    ///
+    /// If `lazy` is true, the file system is detached immediately and cleaned up
+    /// once it is no longer busy.
+    fn umount(&self, target: &Path, lazy: bool) -> Result<()> {
+        let flags = if lazy { nix::mount::MntFlags::MNT_DETACH } else { nix::mount::MntFlags::empty() };
+        nix::mount::umount2(target, flags)?;
+        Ok(())
+    }
+
+    /// Creates a symbolic link at `link` pointing to `target`.
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+}
+
+/// Performs system operations directly.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultSystemOps;
+
+impl SystemOps for DefaultSystemOps {}
+
+static SYSTEM_OPS: RwLock<Option<Arc<dyn SystemOps>>> = RwLock::new(None);
+
+/// Sets the system operations used by this library.
+///
+/// This affects all subsequent operations.
+pub fn set_system_ops(ops: Arc<dyn SystemOps>) {
+    *SYSTEM_OPS.write().unwrap() = Some(ops);
+}
+
+/// Gets the system operations used by this library.
+pub fn system_ops() -> Arc<dyn SystemOps> {
+    match &*SYSTEM_OPS.read().unwrap() {
+        Some(ops) => ops.clone(),
+        None => Arc::new(DefaultSystemOps),
+    }
+}
+
+/// Preparation of configfs for USB gadgets when no init system has done so,
+/// for example during early boot from an initramfs.
+///
+/// This requires root privileges or the capabilities `CAP_SYS_ADMIN` and `CAP_SYS_MODULE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConfigfsSetup {
+    /// Where configfs is mounted if it is not mounted yet.
+    ///
+    /// Defaults to `/sys/kernel/config`.
+    pub mount_point: PathBuf,
+    /// Whether to load the `libcomposite` kernel module, which provides the
+    /// `usb_gadget` directory within configfs.
+    ///
+    /// Defaults to true.
+    pub load_libcomposite: bool,
+}
+
+impl Default for ConfigfsSetup {
+    fn default() -> Self {
+        Self { mount_point: PathBuf::from(DEFAULT_CONFIGFS_DIR), load_libcomposite: true }
+    }
+}
+
+impl ConfigfsSetup {
+    /// Creates the default configfs setup.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets where configfs is mounted if it is not mounted yet.
+    #[must_use]
+    pub fn with_mount_point(mut self, mount_point: impl Into<PathBuf>) -> Self {
+        self.mount_point = mount_point.into();
+        self
+    }
+
+    /// Sets whether to load the `libcomposite` kernel module.
+    #[must_use]
+    pub fn with_load_libcomposite(mut self, load_libcomposite: bool) -> Self {
+        self.load_libcomposite = load_libcomposite;
+        self
+    }
+
+    /// Mounts configfs unless it is mounted already and returns its mount point.
+    ///
+    /// The `configfs` kernel module is loaded if the mount point does not exist.
+    pub fn apply(&self) -> Result<PathBuf> {
+        let dir = match configfs_dir() {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if !self.mount_point.is_dir() {
+                    let _ = request_module("configfs");
+                }
+                if !self.mount_point.is_dir() {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("configfs mount point {} does not exist", self.mount_point.display()),
+                    ));
+                }
+
+                log::info!("mounting configfs at {}", self.mount_point.display());
+                dry_run::mount(OsStr::new("configfs"), &self.mount_point, "configfs", "")?;
+                self.mount_point.clone()
+            }
+            Err(err) => return Err(err),
+        };
+
+        if self.load_libcomposite {
+            let res = request_module("libcomposite");
+            if !dir.join("usb_gadget").is_dir() {
+                res?;
+                return Err(Error::new(ErrorKind::NotFound, "usb_gadget not found in configfs"));
+            }
+        }
+
+        Ok(dir)
+    }
+}
@@ -1,14 +1,20 @@
 //! USB device controller (UDC).
 
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
 use std::{
     ffi::{OsStr, OsString},
     fmt, fs,
-    io::{Error, ErrorKind, Result},
-    os::unix::prelude::OsStringExt,
+    fs::File,
+    io::{Error, ErrorKind, Read, Result, Seek, SeekFrom},
+    os::{
+        fd::AsFd,
+        unix::prelude::{OsStrExt, OsStringExt},
+    },
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use crate::{trim_os_str, Speed};
+use crate::{registered, sysfs_str, trim_os_str, RegGadget, Speed, UnknownSysfsValue};
 
 /// USB device controller (UDC).
 ///
@@ -25,11 +31,74 @@ impl fmt::Debug for Udc {
 }
 
 impl Udc {
+    /// Gets the USB device controller with the specified name.
+    ///
+    /// A not found error is returned if no such UDC is present.
+    pub fn by_name(name: impl AsRef<OsStr>) -> Result<Self> {
+        let name = name.as_ref();
+        let not_found = || {
+            Error::new(ErrorKind::NotFound, format!("USB device controller {} not found", name.to_string_lossy()))
+        };
+
+        if name.is_empty() || name.as_bytes().contains(&b'/') || name == "." || name == ".." {
+            return Err(not_found());
+        }
+
+        let dir = Path::new(UDC_CLASS_DIR).join(name);
+        if !dir.exists() {
+            return Err(not_found());
+        }
+        Ok(Self { dir })
+    }
+
+    /// Gets the USB device controller provided by the device at the specified sysfs path.
+    ///
+    /// See [`device_path`](Self::device_path) for details.
+    /// A not found error is returned if no such UDC is present.
+    pub fn by_device_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        udcs()?.into_iter().find(|udc| udc.device_path().is_ok_and(|p| p == path)).ok_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("no USB device controller at {}", path.display()))
+        })
+    }
+
+    /// Gets the USB device controller described by the specified device tree node.
+    ///
+    /// See [`of_node`](Self::of_node) for details.
+    /// A not found error is returned if no such UDC is present.
+    pub fn by_of_node(node: impl AsRef<str>) -> Result<Self> {
+        let node = node.as_ref();
+        udcs()?.into_iter().find(|udc| udc.of_node().ok().flatten().is_some_and(|n| n == node)).ok_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("no USB device controller for device tree node {node}"))
+        })
+    }
+
     /// The name of the USB device controller.
     pub fn name(&self) -> &OsStr {
         self.dir.file_name().unwrap()
     }
 
+    /// Path of the underlying device in sysfs, for example `/sys/devices/platform/soc/fe980000.usb`.
+    ///
+    /// Unlike the name, it identifies the controller independently of the order in which
+    /// drivers were probed and thus remains stable across reboots.
+    pub fn device_path(&self) -> Result<PathBuf> {
+        fs::canonicalize(self.dir.join("device"))
+    }
+
+    /// Path of the device tree node describing the controller, for example `/soc/usb@7e980000`.
+    ///
+    /// `None` if the controller is not described by a device tree.
+    pub fn of_node(&self) -> Result<Option<String>> {
+        let node = match fs::canonicalize(self.dir.join("device").join("of_node")) {
+            Ok(node) => node,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let Ok(node) = node.strip_prefix(DEVICE_TREE_DIR) else { return Ok(None) };
+        Ok(Some(format!("/{}", node.to_string_lossy())))
+    }
+
     /// Indicates if an OTG A-Host supports HNP at an alternate port.
     pub fn a_alt_hnp_support(&self) -> Result<bool> {
         Ok(fs::read_to_string(self.dir.join("a_alt_hnp_support"))?.trim() != "0")
@@ -47,14 +116,33 @@ impl Udc {
 
     /// Indicates the current negotiated speed at this port.
     ///
-    /// `None` if unknown.
+    /// [`Speed::Unknown`] if unknown.
+    /// A value not known to this library is logged and reported as [`Speed::Unknown`].
     pub fn current_speed(&self) -> Result<Speed> {
-        Ok(fs::read_to_string(self.dir.join("current_speed"))?.trim().parse().unwrap_or_default())
+        lenient(self.current_speed_strict())
+    }
+
+    /// Indicates the current negotiated speed at this port, failing on unknown values.
+    ///
+    /// A value not known to this library results in an [`ErrorKind::InvalidData`] error
+    /// containing [`UnknownSysfsValue`].
+    pub fn current_speed_strict(&self) -> Result<Speed> {
+        read_strict(&self.dir.join("current_speed"), Speed::from_sysfs)
     }
 
     /// Indicates the maximum USB speed supported by this port.
+    ///
+    /// A value not known to this library is logged and reported as [`Speed::Unknown`].
     pub fn max_speed(&self) -> Result<Speed> {
-        Ok(fs::read_to_string(self.dir.join("maximum_speed"))?.trim().parse().unwrap_or_default())
+        lenient(self.max_speed_strict())
+    }
+
+    /// Indicates the maximum USB speed supported by this port, failing on unknown values.
+    ///
+    /// A value not known to this library results in an [`ErrorKind::InvalidData`] error
+    /// containing [`UnknownSysfsValue`].
+    pub fn max_speed_strict(&self) -> Result<Speed> {
+        read_strict(&self.dir.join("maximum_speed"), Speed::from_sysfs)
     }
 
     /// Indicates that this port is the default Host on an OTG session but HNP was used to switch
@@ -71,8 +159,83 @@ impl Udc {
     /// Indicates current state of the USB Device Controller.
     ///
     /// However not all USB Device Controllers support reporting all states.
+    ///
+    /// A value not known to this library is logged and reported as [`UdcState::Unknown`].
     pub fn state(&self) -> Result<UdcState> {
-        Ok(fs::read_to_string(self.dir.join("state"))?.trim().parse().unwrap_or_default())
+        lenient(self.state_strict())
+    }
+
+    /// Indicates current state of the USB Device Controller, failing on unknown values.
+    ///
+    /// A value not known to this library results in an [`ErrorKind::InvalidData`] error
+    /// containing [`UnknownSysfsValue`].
+    pub fn state_strict(&self) -> Result<UdcState> {
+        read_strict(&self.dir.join("state"), UdcState::from_sysfs)
+    }
+
+    /// Watch for changes of the state of the USB Device Controller.
+    ///
+    /// The kernel notifies about state changes, for example when the host selects a
+    /// configuration and the state becomes [`UdcState::Configured`].
+    pub fn watch_state(&self) -> Result<UdcStateWatcher> {
+        let file = File::open(self.dir.join("state"))?;
+        let mut watcher = UdcStateWatcher { file, state: UdcState::Unknown };
+        watcher.state = watcher.read_state()?;
+        Ok(watcher)
+    }
+
+    /// Waits until the USB Device Controller reaches the specified state.
+    ///
+    /// Returns `false` if the timeout has been reached.
+    pub fn wait_state(&self, target: UdcState, timeout: Option<Duration>) -> Result<bool> {
+        self.watch_state()?.wait_for(target, timeout)
+    }
+
+    /// Stream of state changes of the USB Device Controller.
+    #[cfg(feature = "tokio")]
+    pub fn state_stream(&self) -> Result<UdcStateStream> {
+        let watcher = self.watch_state()?;
+        let state = watcher.state;
+        let fd = tokio::io::unix::AsyncFd::with_interest(watcher.file, tokio::io::Interest::READABLE)?;
+        Ok(UdcStateStream { fd, state })
+    }
+
+    /// Watch for changes of the current speed of the USB Device Controller.
+    ///
+    /// The speed is negotiated with the host during enumeration.
+    pub fn watch_speed(&self) -> Result<UdcSpeedWatcher> {
+        let state = self.watch_state()?;
+        let speed = self.current_speed()?;
+        Ok(UdcSpeedWatcher { udc: self.clone(), state, speed })
+    }
+
+    /// Current in mA that may be drawn from VBUS, as negotiated with the host.
+    ///
+    /// This is read from the power supply associated with the USB Device Controller.
+    /// `None` if the controller does not expose this information.
+    pub fn vbus_draw(&self) -> Result<Option<u32>> {
+        let entries = match fs::read_dir(self.dir.join("device").join("power_supply")) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        for entry in entries {
+            let Ok(entry) = entry else { continue };
+            match fs::read_to_string(entry.path().join("current_max")) {
+                Ok(ua) => {
+                    let ua: u32 = ua
+                        .trim()
+                        .parse()
+                        .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid maximum current"))?;
+                    return Ok(Some(ua / 1000));
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(None)
     }
 
     /// Manually start Session Request Protocol (SRP).
@@ -86,7 +249,49 @@ impl Udc {
         fs::write(self.dir.join("soft_connect"), if connect { "connect" } else { "disconnect" })
     }
 
+    /// Directory of the UDC in sysfs.
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Capabilities of the USB device controller.
+    ///
+    /// The endpoint inventory is read from debugfs, where controllers such as
+    /// DWC2 and DWC3 list their endpoints.
+    /// This usually requires root privileges and debugfs mounted at `/sys/kernel/debug`.
+    pub fn capabilities(&self) -> Result<UdcCapabilities> {
+        Ok(UdcCapabilities {
+            max_speed: self.max_speed()?,
+            is_otg: self.is_otg().unwrap_or_default(),
+            endpoints: self.endpoints()?,
+        })
+    }
+
+    /// Endpoints listed in debugfs, if available.
+    fn endpoints(&self) -> Result<Option<Vec<UdcEndpoint>>> {
+        let debugfs = Path::new(DEBUGFS);
+        for dir in [debugfs.join("usb").join(self.name()), debugfs.join(self.name())] {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied) => continue,
+                Err(err) => return Err(err),
+            };
+
+            let mut endpoints: Vec<_> =
+                entries.filter_map(|entry| UdcEndpoint::parse(entry.ok()?.file_name().to_str()?)).collect();
+            if !endpoints.is_empty() {
+                endpoints.sort_by(|a, b| a.name.cmp(&b.name));
+                return Ok(Some(endpoints));
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Name of currently running USB Gadget Driver.
+    ///
+    /// For gadgets registered through configfs this is the name of the gadget.
+    /// Use [`driver`](Self::driver) or [`gadget`](Self::gadget) to resolve it.
     pub fn function(&self) -> Result<Option<OsString>> {
         let data = OsString::from_vec(fs::read(self.dir.join("function"))?);
         let data = trim_os_str(&data);
@@ -96,6 +301,252 @@ impl Udc {
             Ok(Some(data.to_os_string()))
         }
     }
+
+    /// Currently running USB gadget driver, resolved to the registered USB gadget if possible.
+    pub fn driver(&self) -> Result<Option<UdcDriver>> {
+        let Some(function) = self.function()? else { return Ok(None) };
+        match self.find_gadget()? {
+            Some(gadget) => Ok(Some(UdcDriver::Gadget(Box::new(gadget)))),
+            None => Ok(Some(UdcDriver::Legacy(function))),
+        }
+    }
+
+    /// The registered USB gadget bound to this UDC.
+    ///
+    /// This allows determining which gadget is active on a port in systems with multiple UDCs.
+    /// `None` is returned if no gadget is bound or the UDC is used by a gadget driver not
+    /// managed through configfs.
+    pub fn gadget(&self) -> Result<Option<RegGadget>> {
+        if self.function()?.is_none() {
+            return Ok(None);
+        }
+        self.find_gadget()
+    }
+
+    /// Finds the registered gadget whose `UDC` attribute names this UDC.
+    fn find_gadget(&self) -> Result<Option<RegGadget>> {
+        let gadgets = match registered() {
+            Ok(gadgets) => gadgets,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        for gadget in gadgets {
+            if gadget.udc()?.as_deref() == Some(self.name()) {
+                return Ok(Some(gadget));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// USB gadget driver using a USB device controller (UDC).
+///
+/// Obtained by calling [`Udc::driver`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum UdcDriver {
+    /// USB gadget registered through configfs.
+    Gadget(Box<RegGadget>),
+    /// Gadget driver not managed through configfs, for example a legacy driver like `g_ether`.
+    ///
+    /// Contains the name of the driver.
+    Legacy(OsString),
+}
+
+/// Mount point of debugfs.
+const DEBUGFS: &str = "/sys/kernel/debug";
+
+/// Capabilities of a USB device controller (UDC).
+///
+/// Obtained from [`Udc::capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct UdcCapabilities {
+    /// Maximum USB speed supported by the controller.
+    pub max_speed: Speed,
+    /// Whether the controller supports OTG.
+    pub is_otg: bool,
+    /// Endpoints, excluding endpoint 0.
+    ///
+    /// `None` if the controller does not expose its endpoints.
+    pub endpoints: Option<Vec<UdcEndpoint>>,
+}
+
+impl UdcCapabilities {
+    /// Number of endpoints, excluding endpoint 0, if known.
+    pub fn endpoint_count(&self) -> Option<usize> {
+        self.endpoints.as_ref().map(|eps| eps.len())
+    }
+
+    /// Allocates endpoints of the controller to the required endpoints and reports
+    /// the shortfall per direction and transfer type.
+    ///
+    /// An empty list indicates that all required endpoints can be allocated.
+    /// `None` if the controller does not expose its endpoints.
+    pub fn endpoint_shortage(&self, required: &[EndpointRequirement]) -> Option<Vec<EndpointShortage>> {
+        let endpoints = self.endpoints.as_ref()?;
+        let mut used = vec![false; endpoints.len()];
+        let mut shortage: Vec<EndpointShortage> = Vec::new();
+
+        for req in required {
+            // Prefer the least versatile endpoint to keep others available.
+            let ep = endpoints.iter().enumerate().filter(|(idx, ep)| !used[*idx] && ep.supports(req)).min_by_key(
+                |(_, ep)| {
+                    usize::from(ep.device_to_host)
+                        + usize::from(ep.host_to_device)
+                        + usize::from(ep.transfer_type.is_none())
+                },
+            );
+            if let Some((idx, _)) = ep {
+                used[idx] = true;
+                continue;
+            }
+
+            match shortage
+                .iter_mut()
+                .find(|s| s.device_to_host == req.device_to_host && s.transfer_type == req.transfer_type)
+            {
+                Some(s) => s.missing += 1,
+                None => shortage.push(EndpointShortage {
+                    device_to_host: req.device_to_host,
+                    transfer_type: req.transfer_type,
+                    required: required.iter().filter(|r| *r == req).count(),
+                    usable: endpoints.iter().filter(|ep| ep.supports(req)).count(),
+                    missing: 1,
+                }),
+            }
+        }
+
+        Some(shortage)
+    }
+}
+
+/// Transfer type an endpoint of a UDC is restricted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum UdcEndpointType {
+    /// Bulk transfers.
+    Bulk,
+    /// Interrupt transfers.
+    Interrupt,
+    /// Isochronous transfers.
+    Isochronous,
+}
+
+impl fmt::Display for UdcEndpointType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Bulk => write!(f, "bulk"),
+            Self::Interrupt => write!(f, "interrupt"),
+            Self::Isochronous => write!(f, "isochronous"),
+        }
+    }
+}
+
+/// Endpoint, excluding endpoint 0, a function requires from the UDC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct EndpointRequirement {
+    /// Whether the endpoint is used for device-to-host (IN) transfers.
+    pub device_to_host: bool,
+    /// Transfer type.
+    pub transfer_type: UdcEndpointType,
+}
+
+impl EndpointRequirement {
+    /// Device-to-host (IN) endpoint of the specified transfer type.
+    pub const fn device_to_host(transfer_type: UdcEndpointType) -> Self {
+        Self { device_to_host: true, transfer_type }
+    }
+
+    /// Host-to-device (OUT) endpoint of the specified transfer type.
+    pub const fn host_to_device(transfer_type: UdcEndpointType) -> Self {
+        Self { device_to_host: false, transfer_type }
+    }
+}
+
+/// Shortfall of endpoints of a UDC for one direction and transfer type.
+///
+/// Obtained from [`UdcCapabilities::endpoint_shortage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct EndpointShortage {
+    /// Whether the endpoints are used for device-to-host (IN) transfers.
+    pub device_to_host: bool,
+    /// Transfer type.
+    pub transfer_type: UdcEndpointType,
+    /// Number of endpoints of this kind required by the functions.
+    pub required: usize,
+    /// Number of endpoints of the UDC usable for this kind.
+    ///
+    /// Some of them may have been allocated to endpoints of other kinds.
+    pub usable: usize,
+    /// Number of required endpoints that could not be allocated.
+    pub missing: usize,
+}
+
+impl fmt::Display for EndpointShortage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dir = if self.device_to_host { "IN" } else { "OUT" };
+        write!(
+            f,
+            "{} of {} {dir} {} endpoints missing ({} usable)",
+            self.missing, self.required, self.transfer_type, self.usable
+        )
+    }
+}
+
+/// Endpoint of a USB device controller (UDC).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct UdcEndpoint {
+    /// Name of the endpoint, for example `ep1in`.
+    pub name: String,
+    /// Endpoint number.
+    pub number: u8,
+    /// Whether the endpoint can be used for device-to-host transfers.
+    pub device_to_host: bool,
+    /// Whether the endpoint can be used for host-to-device transfers.
+    pub host_to_device: bool,
+    /// Transfer type the endpoint is restricted to, if any.
+    pub transfer_type: Option<UdcEndpointType>,
+}
+
+impl UdcEndpoint {
+    /// Parses an endpoint name following the kernel naming convention, such as
+    /// `ep1`, `ep2in` or `ep3out-bulk`.
+    ///
+    /// Endpoint 0 is not parsed.
+    fn parse(name: &str) -> Option<Self> {
+        let rest = name.strip_prefix("ep")?;
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let number: u8 = rest[..digits].parse().ok().filter(|&n| n != 0)?;
+
+        let (dir, transfer) = rest[digits..].split_once('-').unwrap_or((&rest[digits..], ""));
+        let (device_to_host, host_to_device) = match dir {
+            "" => (true, true),
+            "in" => (true, false),
+            "out" => (false, true),
+            _ => return None,
+        };
+        let transfer_type = match transfer {
+            "" => None,
+            "bulk" => Some(UdcEndpointType::Bulk),
+            "int" => Some(UdcEndpointType::Interrupt),
+            "iso" => Some(UdcEndpointType::Isochronous),
+            _ => return None,
+        };
+
+        Some(Self { name: name.to_string(), number, device_to_host, host_to_device, transfer_type })
+    }
+
+    /// Whether the endpoint can be allocated to the required endpoint.
+    pub fn supports(&self, required: &EndpointRequirement) -> bool {
+        let dir = if required.device_to_host { self.device_to_host } else { self.host_to_device };
+        dir && self.transfer_type.map_or(true, |ty| ty == required.transfer_type)
+    }
 }
 
 /// USB device controller (UDC) connection state.
@@ -137,6 +588,338 @@ pub enum UdcState {
     Unknown,
 }
 
+impl UdcState {
+    /// Parses a state as reported by the kernel in sysfs, e.g. `configured`.
+    ///
+    /// Surrounding whitespace and NUL characters are ignored.
+    /// Returns the trimmed value if it is unknown.
+    pub fn from_sysfs(value: &str) -> std::result::Result<Self, String> {
+        let value = sysfs_str(value);
+        value.parse().map_err(|_| value.to_string())
+    }
+}
+
+/// Reads a sysfs attribute and parses it using `parse`.
+///
+/// An unknown value results in an [`ErrorKind::InvalidData`] error containing [`UnknownSysfsValue`].
+fn read_strict<T>(path: &Path, parse: fn(&str) -> std::result::Result<T, String>) -> Result<T> {
+    parse_strict(path, &fs::read_to_string(path)?, parse)
+}
+
+/// Parses the value of a sysfs attribute using `parse`.
+fn parse_strict<T>(path: &Path, data: &str, parse: fn(&str) -> std::result::Result<T, String>) -> Result<T> {
+    parse(data).map_err(|value| {
+        Error::new(ErrorKind::InvalidData, UnknownSysfsValue { attribute: path.to_path_buf(), value })
+    })
+}
+
+/// Replaces an unknown sysfs value by the default value after logging it.
+fn lenient<T: Default>(res: Result<T>) -> Result<T> {
+    match res {
+        Err(err) if err.get_ref().is_some_and(|inner| inner.is::<UnknownSysfsValue>()) => {
+            log::warn!("{err}");
+            Ok(T::default())
+        }
+        res => res,
+    }
+}
+
+/// Watches the state of a USB device controller (UDC) for changes.
+///
+/// Obtained by calling [`Udc::watch_state`].
+#[derive(Debug)]
+pub struct UdcStateWatcher {
+    file: File,
+    state: UdcState,
+}
+
+impl UdcStateWatcher {
+    /// Last observed state.
+    pub fn state(&self) -> UdcState {
+        self.state
+    }
+
+    /// Waits for the state to change.
+    ///
+    /// Returns the new state or `None` if the timeout has been reached.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Option<UdcState>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => remaining.as_millis().try_into().unwrap_or(PollTimeout::MAX),
+                    None => return Ok(None),
+                },
+                None => PollTimeout::NONE,
+            };
+
+            let mut fds = [PollFd::new(self.file.as_fd(), PollFlags::POLLPRI | PollFlags::POLLERR)];
+            poll(&mut fds, remaining)?;
+            if fds[0].revents().map(|e| e.is_empty()).unwrap_or(true) {
+                continue;
+            }
+
+            let state = self.read_state()?;
+            if state != self.state {
+                self.state = state;
+                return Ok(Some(state));
+            }
+        }
+    }
+
+    /// Waits until the specified state is reached.
+    ///
+    /// Returns `false` if the timeout has been reached.
+    pub fn wait_for(&mut self, target: UdcState, timeout: Option<Duration>) -> Result<bool> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        while self.state != target {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => Some(remaining),
+                    None => return Ok(false),
+                },
+                None => None,
+            };
+
+            if self.wait(remaining)?.is_none() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn read_state(&mut self) -> Result<UdcState> {
+        read_state_file(&mut self.file)
+    }
+}
+
+/// Reads the state from the `state` file of a UDC.
+fn read_state_file(file: &mut File) -> Result<UdcState> {
+    let mut data = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut data)?;
+    lenient(parse_strict(Path::new("state"), &data, UdcState::from_sysfs))
+}
+
+/// Asynchronous stream of state changes of a USB device controller (UDC).
+///
+/// Obtained by calling [`Udc::state_stream`].
+/// Each change of the state is yielded.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct UdcStateStream {
+    fd: tokio::io::unix::AsyncFd<File>,
+    state: UdcState,
+}
+
+#[cfg(feature = "tokio")]
+impl UdcStateStream {
+    /// Last observed state.
+    pub fn state(&self) -> UdcState {
+        self.state
+    }
+
+    /// Waits for the state to change and returns the new state.
+    pub async fn changed(&mut self) -> Result<UdcState> {
+        std::future::poll_fn(|cx| self.poll_changed(cx)).await
+    }
+
+    /// Waits until the specified state is reached.
+    pub async fn wait_for(&mut self, target: UdcState) -> Result<()> {
+        while self.state != target {
+            self.changed().await?;
+        }
+        Ok(())
+    }
+
+    fn poll_changed(&mut self, cx: &mut std::task::Context) -> std::task::Poll<Result<UdcState>> {
+        use std::task::Poll;
+
+        loop {
+            let mut guard = match self.fd.poll_read_ready_mut(cx) {
+                Poll::Ready(res) => res?,
+                Poll::Pending => return Poll::Pending,
+            };
+            guard.clear_ready();
+
+            let state = read_state_file(guard.get_inner_mut())?;
+            if state != self.state {
+                self.state = state;
+                return Poll::Ready(Ok(state));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl futures_core::Stream for UdcStateStream {
+    type Item = Result<UdcState>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.get_mut().poll_changed(cx).map(Some)
+    }
+}
+
+/// Watches the current speed of a USB device controller (UDC) for changes.
+///
+/// Obtained by calling [`Udc::watch_speed`].
+/// The kernel does not notify about speed changes directly, thus the speed is re-read
+/// whenever the UDC state changes.
+#[derive(Debug)]
+pub struct UdcSpeedWatcher {
+    udc: Udc,
+    state: UdcStateWatcher,
+    speed: Speed,
+}
+
+impl UdcSpeedWatcher {
+    /// Last observed speed.
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Waits for the speed to change.
+    ///
+    /// Returns the new speed or `None` if the timeout has been reached.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Option<Speed>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => Some(remaining),
+                    None => return Ok(None),
+                },
+                None => None,
+            };
+
+            if self.state.wait(remaining)?.is_none() {
+                return Ok(None);
+            }
+
+            let speed = self.udc.current_speed()?;
+            if speed != self.speed {
+                self.speed = speed;
+                return Ok(Some(speed));
+            }
+        }
+    }
+}
+
+/// Activation state of a USB gadget.
+///
+/// States are ordered by progress of activation, with [`Suspended`](Self::Suspended) last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum GadgetState {
+    /// Registered but not bound to a UDC.
+    Registered,
+    /// Bound to a UDC but not enumerated by a USB host.
+    Bound,
+    /// Enumerated by the USB host, but no configuration has been selected yet.
+    Enumerated,
+    /// The USB host has selected a configuration.
+    ///
+    /// This is the state applications should wait for before starting data transfers.
+    Configured,
+    /// Suspended by the USB host.
+    Suspended,
+}
+
+impl GadgetState {
+    /// Activation state of a USB gadget bound to a UDC with the specified state.
+    pub fn from_udc_state(state: UdcState) -> Self {
+        match state {
+            UdcState::Default | UdcState::Addressed => Self::Enumerated,
+            UdcState::Configured => Self::Configured,
+            UdcState::Suspended => Self::Suspended,
+            _ => Self::Bound,
+        }
+    }
+}
+
+/// Watches the activation state of a USB gadget for changes.
+///
+/// Obtained by calling [`RegGadget::watch_state`](crate::RegGadget::watch_state) or
+/// [`Status::watch_gadget_state`](crate::function::util::Status::watch_gadget_state).
+#[derive(Debug)]
+pub struct GadgetStateWatcher {
+    udc: UdcStateWatcher,
+    state: GadgetState,
+}
+
+impl GadgetStateWatcher {
+    pub(crate) fn new(udc: &Udc) -> Result<Self> {
+        let udc = udc.watch_state()?;
+        let state = GadgetState::from_udc_state(udc.state());
+        Ok(Self { udc, state })
+    }
+
+    /// Last observed activation state.
+    pub fn state(&self) -> GadgetState {
+        self.state
+    }
+
+    /// Waits for the activation state to change.
+    ///
+    /// Returns the new state or `None` if the timeout has been reached.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Option<GadgetState>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => Some(remaining),
+                    None => return Ok(None),
+                },
+                None => None,
+            };
+
+            let Some(udc_state) = self.udc.wait(remaining)? else { return Ok(None) };
+
+            let state = GadgetState::from_udc_state(udc_state);
+            if state != self.state {
+                self.state = state;
+                return Ok(Some(state));
+            }
+        }
+    }
+
+    /// Waits until the USB host has selected a configuration.
+    ///
+    /// Returns `false` if the timeout has been reached.
+    pub fn wait_configured(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        while self.state != GadgetState::Configured {
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => Some(remaining),
+                    None => return Ok(false),
+                },
+                None => None,
+            };
+
+            if self.wait(remaining)?.is_none() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// sysfs directory containing the USB device controllers.
+const UDC_CLASS_DIR: &str = "/sys/class/udc";
+
+/// Directory of the device tree in sysfs.
+const DEVICE_TREE_DIR: &str = "/sys/firmware/devicetree/base";
+
 /// Gets the available USB device controllers (UDCs) in the system.
 pub fn udcs() -> Result<Vec<Udc>> {
     let class_dir = Path::new("/sys/class");
@@ -144,13 +927,13 @@ pub fn udcs() -> Result<Vec<Udc>> {
         return Err(Error::new(ErrorKind::NotFound, "sysfs is not available"));
     }
 
-    let udc_dir = class_dir.join("udc");
+    let udc_dir = Path::new(UDC_CLASS_DIR);
     if !udc_dir.is_dir() {
         return Ok(Vec::new());
     }
 
     let mut udcs = Vec::new();
-    for entry in fs::read_dir(&udc_dir)? {
+    for entry in fs::read_dir(udc_dir)? {
         let Ok(entry) = entry else { continue };
         udcs.push(Udc { dir: entry.path() });
     }
@@ -168,3 +951,68 @@ pub fn default_udc() -> Result<Udc> {
         .next()
         .ok_or_else(|| Error::new(ErrorKind::NotFound, "no USB device controller (UDC) available"))
 }
+
+#[cfg(test)]
+mod test {
+    use super::{EndpointRequirement, UdcCapabilities, UdcEndpoint, UdcEndpointType, UdcState};
+    use crate::Speed;
+
+    #[test]
+    fn state_from_sysfs() {
+        assert_eq!(UdcState::from_sysfs("configured\n"), Ok(UdcState::Configured));
+        assert_eq!(UdcState::from_sysfs("not attached"), Ok(UdcState::NotAttached));
+        assert_eq!(UdcState::from_sysfs("\0suspended \n"), Ok(UdcState::Suspended));
+        assert_eq!(UdcState::from_sysfs("UNKNOWN"), Ok(UdcState::Unknown));
+        assert_eq!(UdcState::from_sysfs("sleeping\n"), Err("sleeping".to_string()));
+        assert_eq!(UdcState::from_sysfs(""), Err(String::new()));
+
+        for state in ["attached", "powered", "reconnecting", "unauthenticated", "default", "addressed"] {
+            assert_eq!(UdcState::from_sysfs(state).unwrap().to_string(), state);
+        }
+    }
+
+    #[test]
+    fn parse_endpoint_names() {
+        let ep = UdcEndpoint::parse("ep3out-bulk").unwrap();
+        assert_eq!(ep.number, 3);
+        assert!(!ep.device_to_host && ep.host_to_device);
+        assert_eq!(ep.transfer_type, Some(UdcEndpointType::Bulk));
+
+        let ep = UdcEndpoint::parse("ep12in").unwrap();
+        assert_eq!(ep.number, 12);
+        assert!(ep.device_to_host && !ep.host_to_device);
+        assert_eq!(ep.transfer_type, None);
+
+        assert!(UdcEndpoint::parse("ep5").unwrap().host_to_device);
+        assert!(UdcEndpoint::parse("ep0").is_none());
+        assert!(UdcEndpoint::parse("ep0in").is_none());
+        assert!(UdcEndpoint::parse("regdump").is_none());
+        assert!(UdcEndpoint::parse("ep1in-ctrl").is_none());
+    }
+
+    #[test]
+    fn endpoint_shortage() {
+        let caps = UdcCapabilities {
+            max_speed: Speed::HighSpeed,
+            is_otg: false,
+            endpoints: Some(
+                ["ep1in-bulk", "ep2out-bulk", "ep3in-int", "ep4"]
+                    .into_iter()
+                    .map(|name| UdcEndpoint::parse(name).unwrap())
+                    .collect(),
+            ),
+        };
+        let int_in = EndpointRequirement::device_to_host(UdcEndpointType::Interrupt);
+        let bulk_in = EndpointRequirement::device_to_host(UdcEndpointType::Bulk);
+        let bulk_out = EndpointRequirement::host_to_device(UdcEndpointType::Bulk);
+
+        assert_eq!(caps.endpoint_shortage(&[bulk_out, int_in, bulk_in, bulk_out]), Some(Vec::new()));
+
+        let shortage = caps.endpoint_shortage(&[int_in, int_in, int_in, bulk_in, bulk_out]).unwrap();
+        assert_eq!(shortage.len(), 1);
+        assert_eq!(shortage[0].to_string(), "1 of 3 IN interrupt endpoints missing (2 usable)");
+
+        let caps = UdcCapabilities { endpoints: None, ..caps };
+        assert_eq!(caps.endpoint_shortage(&[int_in]), None);
+    }
+}
@@ -0,0 +1,32 @@
+mod common;
+use common::*;
+
+use std::time::Duration;
+
+use usb_gadget::function::custom::cdc_acm::{Acm, AcmEvent, LineCoding, SerialState};
+
+#[test]
+fn cdc_acm() {
+    init();
+    let _mutex = exclusive();
+
+    let (mut acm, handle) = Acm::builder().with_line_coding(LineCoding::default()).build();
+
+    let reg = reg(handle);
+    println!("CDC ACM function at {}", acm.custom().status().unwrap().path().unwrap().display());
+
+    for _ in 0..10 {
+        match acm.event(Duration::from_millis(100)).unwrap() {
+            Some(AcmEvent::Enabled) => {
+                acm.notify_serial_state(SerialState { dcd: true, dsr: true, ..Default::default() }).unwrap()
+            }
+            Some(event) => println!("event: {event:?}"),
+            None => (),
+        }
+    }
+    println!("line coding: {:?}", acm.line_coding());
+
+    if unreg(reg).unwrap() {
+        assert!(acm.custom().status().unwrap().path().is_none());
+    }
+}
@@ -44,6 +44,7 @@ pub fn reg(func: Handle) -> RegGadget {
 
     assert!(reg.is_attached());
     assert_eq!(reg.udc().unwrap().unwrap(), udc.name());
+    assert_eq!(reg.verify().unwrap(), Vec::new());
 
     println!(
         "bound USB gadget {} at {} to {}",
@@ -89,6 +90,9 @@ pub fn reg_with_os_desc(func: Handle) -> RegGadget {
     .expect("cannot bind to UDC");
 
     assert!(reg.is_attached());
+    if let Some(os_desc) = reg.os_descriptor().unwrap() {
+        assert_eq!(os_desc, OsDescriptor::microsoft());
+    }
     assert_eq!(reg.udc().unwrap().unwrap(), udc.name());
 
     println!("bound USB gadget {} at {}", reg.name().to_string_lossy(), reg.path().display());
@@ -1,9 +1,13 @@
-use std::{thread, time::Duration};
+use bytes::BytesMut;
+use std::{fs::File, os::fd::OwnedFd, thread, time::Duration};
 use uuid::uuid;
 
 use usb_gadget::{
     default_udc,
-    function::custom::{Custom, Endpoint, EndpointDirection, Interface, OsExtCompat, OsExtProp},
+    function::custom::{
+        ffs_mounts, CtrlFilter, CtrlRecipient, CtrlType, Custom, Endpoint, EndpointDirection, Event, Interface,
+        OsExtCompat, OsExtProp, SetupRouter,
+    },
     Class,
 };
 
@@ -18,7 +22,7 @@ fn custom() {
     let (mut ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
     let (mut ep2_tx, ep2_dir) = EndpointDirection::device_to_host();
 
-    let (custom, handle) = Custom::builder()
+    let (mut custom, handle) = Custom::builder()
         .with_interface(
             Interface::new(Class::vendor_specific(1, 1), "custom interface")
                 .with_endpoint(Endpoint::bulk(ep1_dir))
@@ -34,7 +38,19 @@ fn custom() {
     let _ep1_control = ep1_rx.control().unwrap();
 
     println!("Getting ep2_tx control");
-    let _ep2_control = ep2_tx.control().unwrap();
+    let ep2_control = ep2_tx.control().unwrap();
+    let ep2_fd = ep2_control.dup_fd().unwrap();
+    println!("Duplicated ep2_tx fd: {ep2_fd:?}");
+    drop(ep2_fd);
+    println!("Function speed: {:?}", custom.negotiated_speed());
+    println!("ep2_tx descriptor speed: {:?}", ep2_control.negotiated_speed());
+
+    let ffs_dir = custom.ffs_dir().unwrap();
+    let mounts = ffs_mounts().unwrap();
+    println!("FunctionFS mounts: {mounts:?}");
+    let mount = mounts.iter().find(|mount| mount.dir == ffs_dir).expect("FunctionFS mount not found");
+    assert!(mount.ep0_holders.contains(&std::process::id()));
+    assert!(!mount.is_orphaned());
 
     thread::sleep(Duration::from_secs(1));
 
@@ -44,6 +60,156 @@ fn custom() {
     }
 }
 
+#[test]
+fn custom_split_endpoints() {
+    init();
+    let _mutex = exclusive();
+
+    let (ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
+    let (ep2_tx, ep2_dir) = EndpointDirection::device_to_host();
+    let (_custom, handle) = Custom::builder()
+        .with_interface(
+            Interface::new(Class::vendor_specific(1, 1), "custom interface")
+                .with_endpoint(Endpoint::bulk(ep1_dir))
+                .with_endpoint(Endpoint::bulk(ep2_dir)),
+        )
+        .build();
+    let reg = reg(handle);
+
+    let (mut rx_submit, mut rx_complete) = ep1_rx.split().unwrap();
+    let (tx_submit, mut tx_complete) = ep2_tx.split().unwrap();
+    println!("ep1 control: {:?}", rx_submit.control().real_address());
+    println!("ep2 control: {:?}", tx_submit.control().real_address());
+
+    let submitter = thread::spawn(move || {
+        while rx_submit.is_ready() {
+            rx_submit.try_recv(BytesMut::with_capacity(512)).unwrap();
+        }
+        rx_submit.cancel();
+        rx_submit
+    });
+    let rx_submit = submitter.join().unwrap();
+
+    while rx_complete.fetch_timeout(Duration::from_secs(1)).is_ok_and(|data| data.is_some()) {}
+    assert!(tx_complete.try_completed().unwrap().is_none());
+    println!("receive queue empty: {}", rx_submit.is_empty());
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn custom_reinit() {
+    init();
+    let _mutex = exclusive();
+
+    let (_ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
+    let (custom, handle) = Custom::builder()
+        .with_interface(
+            Interface::new(Class::vendor_specific(1, 1), "custom interface")
+                .with_endpoint(Endpoint::bulk(ep1_dir)),
+        )
+        .build();
+    let reg = reg(handle);
+
+    let (mut ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
+    let (_ep2_tx, ep2_dir) = EndpointDirection::device_to_host();
+    let mut custom = custom
+        .reinit(
+            Custom::builder().with_interface(
+                Interface::new(Class::vendor_specific(1, 2), "reinitialized interface")
+                    .with_endpoint(Endpoint::bulk(ep1_dir))
+                    .with_endpoint(Endpoint::bulk(ep2_dir)),
+            ),
+        )
+        .unwrap();
+    assert!(reg.udc().unwrap().is_some());
+    println!("FunctionFS dir: {}", custom.ffs_dir().unwrap().display());
+    println!("ep1 control: {:?}", ep1_rx.control().unwrap().real_address());
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+#[ignore = "test requires a USB connection to a USB host"]
+fn custom_setup_router() {
+    init();
+    let _mutex = exclusive();
+
+    let (_ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
+    let (mut custom, handle) = Custom::builder()
+        .with_interface(
+            Interface::new(Class::vendor_specific(1, 1), "custom interface")
+                .with_endpoint(Endpoint::bulk(ep1_dir)),
+        )
+        .build();
+    let reg = reg(handle);
+
+    let mut router = SetupRouter::new()
+        .with_device_to_host(CtrlFilter::new(CtrlRecipient::Interface, CtrlType::Vendor, 1), |req| {
+            println!("vendor request 1: {req:?}");
+            Ok(b"hello".to_vec())
+        })
+        .with_host_to_device(CtrlFilter::new(CtrlRecipient::Interface, CtrlType::Vendor, 2), |req, data| {
+            println!("vendor request 2: {req:?} with {data:x?}");
+            Ok(())
+        });
+    println!("Router: {router:?}");
+
+    for _ in 0..100 {
+        let Some(event) = custom.event_timeout(Duration::from_millis(100)).unwrap() else { continue };
+        match router.handle(event).unwrap() {
+            Some(Event::Enable) => println!("enabled"),
+            Some(other) => println!("event: {other:?}"),
+            None => (),
+        }
+    }
+
+    unreg(reg).unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+#[ignore = "test requires a USB connection to a USB host"]
+fn custom_send_borrowed_runtime_dropped() {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use usb_gadget::function::custom::SharedAio;
+
+    init();
+    let _mutex = exclusive();
+
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let shared = rt.block_on(async { SharedAio::new_async(4) }).unwrap();
+
+    let (mut ep1_tx, ep1_dir) = EndpointDirection::device_to_host();
+    let mut builder = Custom::builder().with_interface(
+        Interface::new(Class::vendor_specific(1, 1), "custom interface").with_endpoint(Endpoint::bulk(ep1_dir)),
+    );
+    builder.aio = Some(shared);
+    let (mut custom, handle) = builder.build();
+    let reg = reg(handle);
+
+    while !matches!(custom.event_timeout(Duration::from_secs(10)).unwrap(), Some(Event::Enable)) {}
+
+    // The host does not read from the endpoint, thus the transfer stays in flight.
+    let data = vec![0x55; 512];
+    let completed = Arc::new(AtomicBool::new(false));
+    let completed_cb = completed.clone();
+    unsafe { ep1_tx.send_borrowed(&data, move || completed_cb.store(true, Ordering::SeqCst)) }.unwrap();
+
+    drop(rt);
+    thread::sleep(Duration::from_millis(100));
+    assert!(!completed.load(Ordering::SeqCst), "borrowed buffer released while transfer is in flight");
+
+    drop(ep1_tx);
+    drop(custom);
+    unreg(reg).unwrap();
+    assert!(completed.load(Ordering::SeqCst));
+    drop(data);
+}
+
 #[test]
 #[ignore = "test requires a USB connection to a USB host"]
 fn custom_with_os_desc() {
@@ -68,6 +234,7 @@ fn custom_with_os_desc() {
     let reg = reg_with_os_desc(handle);
     println!("Custom function at {}", custom.status().unwrap().path().unwrap().display());
     println!("real interface address 0: {}", custom.real_address(0).unwrap());
+    println!("interface numbers: {:?}", custom.interface_numbers().unwrap());
     println!();
 
     let ep1_control = ep1_rx.control().unwrap();
@@ -90,6 +257,39 @@ fn custom_with_os_desc() {
     }
 }
 
+#[test]
+fn custom_events() {
+    init();
+    let _mutex = exclusive();
+
+    let (_ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
+    let (mut custom, handle) = Custom::builder()
+        .with_interface(
+            Interface::new(Class::vendor_specific(1, 1), "custom interface")
+                .with_endpoint(Endpoint::bulk(ep1_dir)),
+        )
+        .build();
+
+    let reg = reg(handle);
+    let unbinder = thread::spawn(move || {
+        thread::sleep(Duration::from_secs(3));
+        println!("Unbinding");
+        reg.bind(None).unwrap();
+        reg
+    });
+
+    let mut events = custom.events().with_poll_interval(Duration::from_millis(100));
+    let mut unbound = false;
+    while let Some(event) = events.next_event() {
+        let event = event.unwrap();
+        println!("Event: {event:?}");
+        unbound = matches!(event, Event::Unbind);
+    }
+    assert!(unbound);
+
+    unreg(unbinder.join().unwrap()).unwrap();
+}
+
 #[test]
 fn custom_no_disconnect() {
     init();
@@ -223,6 +423,59 @@ fn custom_ext_init() {
         println!("Dropping custom interface");
     }
 
+    println!("Deactivating USB gadget");
+    reg.bind(None).unwrap();
+    thread::sleep(Duration::from_secs(3));
+
+    {
+        println!("Creating custom interface using directory file descriptor of existing FunctionFS mount");
+
+        let dir_fd: OwnedFd = File::open(&ffs_dir).unwrap().into();
+        let (_ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
+        let (_ep2_tx, ep2_dir) = EndpointDirection::device_to_host();
+        let mut custom = Custom::builder()
+            .with_interface(
+                Interface::new(Class::vendor_specific(1, 1), "custom interface")
+                    .with_endpoint(Endpoint::bulk(ep1_dir))
+                    .with_endpoint(Endpoint::bulk(ep2_dir)),
+            )
+            .existing_fd(dir_fd)
+            .unwrap();
+
+        println!("FunctionFS is at {}", custom.ffs_dir().unwrap().display());
+        assert!(custom.ffs_dir().unwrap().starts_with("/proc/self/fd"));
+
+        println!("Activating USB gadget");
+        reg.bind(Some(&default_udc().unwrap())).unwrap();
+
+        thread::sleep(Duration::from_secs(3));
+        println!("Dropping custom interface");
+    }
+
     println!("Unregistering");
     unreg(reg).unwrap();
 }
+
+#[test]
+fn custom_pinned_endpoint_addresses() {
+    init();
+    let _mutex = exclusive();
+
+    let (_ep1_rx, ep1_dir) = EndpointDirection::host_to_device();
+    let (_ep2_tx, ep2_dir) = EndpointDirection::device_to_host();
+
+    let builder = Custom::builder().with_interface(
+        Interface::new(Class::vendor_specific(1, 1), "custom interface")
+            .with_endpoint(Endpoint::bulk(ep1_dir).with_address(0x01))
+            .with_endpoint(Endpoint::bulk(ep2_dir).with_address(0x82)),
+    );
+    assert_eq!(builder.endpoint_addresses().unwrap(), [0x01, 0x82]);
+    let (mut custom, handle) = builder.build();
+
+    let reg = reg(handle);
+    println!("endpoint addresses: {:x?}", custom.endpoint_addresses().unwrap());
+
+    if unreg(reg).unwrap() {
+        assert!(custom.status().unwrap().path().is_none());
+    }
+}
@@ -0,0 +1,50 @@
+mod common;
+use common::*;
+
+use std::{io::Result, time::Duration};
+
+use usb_gadget::function::custom::dfu::{Dfu, DfuHandler, DfuState};
+
+#[derive(Default)]
+struct Firmware(Vec<u8>);
+
+impl DfuHandler for Firmware {
+    fn begin(&mut self) -> Result<()> {
+        self.0.clear();
+        Ok(())
+    }
+
+    fn write(&mut self, _offset: u64, data: &[u8]) -> Result<()> {
+        self.0.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.0.get(offset as usize..).unwrap_or_default();
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+}
+
+#[test]
+fn dfu() {
+    init();
+    let _mutex = exclusive();
+
+    let (mut dfu, handle) = Dfu::builder(Firmware::default()).with_upload(true).build().unwrap();
+
+    let reg = reg(handle);
+    println!("DFU function at {}", dfu.custom().status().unwrap().path().unwrap().display());
+
+    for _ in 0..10 {
+        for event in dfu.event(Duration::from_millis(100)).unwrap() {
+            println!("event: {event:?}");
+        }
+    }
+    assert_eq!(dfu.state(), DfuState::DfuIdle);
+
+    if unreg(reg).unwrap() {
+        assert!(dfu.custom().status().unwrap().path().is_none());
+    }
+}
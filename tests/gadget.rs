@@ -1,6 +1,34 @@
 mod common;
 use common::*;
 
+use std::{
+    io::ErrorKind,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use usb_gadget::{
+    default_udc,
+    function::{
+        custom::{Custom, Interface, OsExtProp},
+        serial::{Serial, SerialClass},
+        util::{State, Strictness},
+    },
+    registered, udcs, AccessKind, BindError, BindErrorCause, Class, Config, ConfigOp, ConfigfsSetup,
+    DeferredBind, EnumerationTimeout, Gadget, GadgetBound, GadgetState, Id, InvalidMaxPacketSize0, Language,
+    LanguageSet, MaxPacketSize0, Namespace, PowerHooks, RemovalGuard, Speed, Strings, UdcCriteria, UsbVersion,
+};
+
+#[test]
+fn configfs_setup() {
+    init();
+
+    let dir = ConfigfsSetup::new().apply().unwrap();
+    println!("configfs mounted at {}", dir.display());
+    assert!(dir.join("usb_gadget").is_dir());
+}
+
 #[test]
 fn registered_gadgets() {
     init();
@@ -29,3 +57,590 @@ fn unbind_all_gadgets() {
 
     usb_gadget::unbind_all().unwrap();
 }
+
+#[test]
+fn namespaced_gadgets() {
+    init();
+    let _mutex = exclusive();
+
+    let ns = Namespace::new("usb-gadget-test").unwrap();
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let mut reg =
+        Gadget::new(Class::new(1, 2, 3), Id::new(4, 5), Strings::new("manufacturer", "product", "serial_number"))
+            .with_config(Config::new("config").with_function(func))
+            .with_namespace(&ns)
+            .register()
+            .unwrap();
+    reg.detach();
+
+    let owned = ns.registered().unwrap();
+    assert_eq!(owned.len(), 1);
+    assert!(ns.contains(owned[0].name()));
+    assert!(!Namespace::default().contains(owned[0].name()));
+
+    ns.remove_all().unwrap();
+    assert!(ns.registered().unwrap().is_empty());
+}
+
+#[test]
+fn per_gadget_results() {
+    init();
+    let _mutex = exclusive();
+
+    let ns = Namespace::new("usb-gadget-each").unwrap();
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let mut reg =
+        Gadget::new(Class::new(1, 2, 3), Id::new(4, 5), Strings::new("manufacturer", "product", "serial_number"))
+            .with_config(Config::new("config").with_function(func))
+            .with_namespace(&ns)
+            .register()
+            .unwrap();
+    reg.detach();
+    let name = reg.name().to_os_string();
+
+    let unbound = ns.unbind_each().unwrap();
+    assert_eq!(unbound.len(), 1);
+    assert_eq!(unbound[0].0, name);
+    assert!(unbound[0].1.is_ok());
+
+    let removed = ns.remove_each().unwrap();
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].0, name);
+    assert!(removed[0].1.is_ok());
+    assert!(ns.registered().unwrap().is_empty());
+}
+
+#[test]
+fn function_link_names_and_order() {
+    init();
+    let _mutex = exclusive();
+
+    let (_acm, acm) = Serial::new(SerialClass::Acm);
+    let (_generic, generic) = Serial::new(SerialClass::Generic);
+
+    let mut config = Config::new("config").with_function(acm.clone()).with_function_as(generic.clone(), "gser");
+    config.set_function_order([generic.clone()]);
+    assert_eq!(config.function_order(), vec![generic.clone(), acm.clone()]);
+
+    let reg =
+        Gadget::new(Class::new(1, 2, 3), Id::new(4, 5), Strings::new("manufacturer", "product", "serial_number"))
+            .with_config(config)
+            .with_config(Config::new("other").with_function_as(acm, "acm").with_function(generic))
+            .register()
+            .unwrap();
+
+    assert!(reg.path().join("configs/c.1/gser").is_symlink());
+    assert!(reg.path().join("configs/c.2/acm").is_symlink());
+
+    reg.remove().unwrap();
+}
+
+#[test]
+fn bind_busy_udc() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let reg1 = reg(func);
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let err =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "busy", "serial"))
+            .with_config(Config::new("config").with_function(func))
+            .bind(&default_udc().unwrap())
+            .expect_err("binding to busy UDC must fail");
+    println!("bind error: {err}");
+
+    let bind_err = err.get_ref().and_then(|err| err.downcast_ref::<BindError>()).expect("not a bind error");
+    assert!(matches!(bind_err.cause, BindErrorCause::UdcBusy { .. }));
+    assert_eq!(bind_err.required_endpoints, Some(3));
+
+    unreg(reg1).unwrap();
+}
+
+#[test]
+fn parallel_function_registration() {
+    init();
+    let _mutex = exclusive();
+
+    let mut config = Config::new("config");
+    let mut serials = Vec::new();
+    for _ in 0..4 {
+        let (serial, func) = Serial::new(SerialClass::Acm);
+        config.add_function(func);
+        serials.push(serial);
+    }
+
+    let reg = Gadget::new(
+        Class::new(255, 255, 3),
+        Id::new(6, 0x11),
+        Strings::new("manufacturer", "parallel", "serial"),
+    )
+    .with_config(config)
+    .with_register_threads(4)
+    .register()
+    .unwrap();
+
+    for serial in &serials {
+        assert!(serial.status().path().is_some());
+    }
+    assert_eq!(reg.verify().unwrap(), Vec::new());
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn removal_order_and_hooks() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial1, func1) = Serial::new(SerialClass::Acm);
+    let (_serial2, func2) = Serial::new(SerialClass::Acm);
+    let mut reg =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "removal", "serial"))
+            .with_config(Config::new("config").with_function(func1.clone()).with_function(func2))
+            .register()
+            .unwrap();
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    reg.set_removal_priority(&func1, 10);
+    let pre_calls = calls.clone();
+    reg.add_pre_removal_hook(move |dir| {
+        assert!(dir.exists());
+        pre_calls.lock().unwrap().push("pre");
+        Ok(())
+    });
+    let post_calls = calls.clone();
+    reg.add_post_removal_hook(move |dir| {
+        assert!(!dir.exists());
+        post_calls.lock().unwrap().push("post");
+        Ok(())
+    });
+
+    reg.remove().unwrap();
+    assert_eq!(*calls.lock().unwrap(), vec!["pre", "post"]);
+}
+
+#[test]
+fn bind_and_wait() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let reg =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "wait", "serial"))
+            .with_config(Config::new("config").with_function(func))
+            .register()
+            .unwrap();
+
+    let udc = default_udc().unwrap();
+    match reg.bind_and_wait(&udc, Duration::from_secs(5)) {
+        Ok(()) => assert_eq!(reg.state().unwrap(), GadgetState::Configured),
+        Err(err) => {
+            println!("bind and wait error: {err}");
+            let timeout = err
+                .get_ref()
+                .and_then(|err| err.downcast_ref::<EnumerationTimeout>())
+                .expect("not an enumeration timeout");
+            assert_ne!(timeout.state, GadgetState::Configured);
+            assert_eq!(reg.udc().unwrap(), Some(udc.name().to_os_string()));
+        }
+    }
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn preopen_and_required_access() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let gadget =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "access", "serial"))
+            .with_config(Config::new("config").with_function(func));
+
+    let access = gadget.required_access().unwrap();
+    println!("required access before registration:\n{access}");
+    assert!(access.paths_with(AccessKind::Create).next().is_some());
+
+    let mut reg = gadget.register().unwrap();
+    reg.preopen().unwrap();
+
+    let access = reg.required_access().unwrap();
+    println!("required access after preopen:\n{access}");
+    assert!(access.paths_with(AccessKind::ReadWrite).all(|path| !path.ends_with("UDC")));
+    assert!(access.paths_with(AccessKind::ReadWrite).any(|path| path.ends_with("configs")));
+
+    let udc = default_udc().unwrap();
+    reg.bind(Some(&udc)).unwrap();
+    assert_eq!(reg.udc().unwrap(), Some(udc.name().to_os_string()));
+    reg.bind(None).unwrap();
+    assert_eq!(reg.udc().unwrap(), None);
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn dry_run() {
+    let (serial, func) = Serial::new(SerialClass::Acm);
+    let gadget =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "dry run", "serial"))
+            .with_config(Config::new("config").with_function(func));
+
+    let dry_run = gadget.dry_run().unwrap();
+    println!("{}", dry_run.to_shell_script());
+
+    assert!(!dry_run.dir.exists());
+    assert!(dry_run.ops.contains(&ConfigOp::CreateDir(dry_run.dir.clone())));
+    assert!(dry_run.ops.iter().any(|op| matches!(op, ConfigOp::Symlink { .. })));
+    assert_eq!(serial.status().state(), State::Unregistered);
+}
+
+#[test]
+fn check_against_udc() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let gadget =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "check", "serial"))
+            .with_config(Config::new("config").with_function(func));
+
+    for udc in udcs().unwrap() {
+        let causes = gadget.check_against(&udc).unwrap();
+        println!("{}: {causes:?}", udc.name().to_string_lossy());
+    }
+}
+
+#[test]
+fn normalize_languages() {
+    let mut gadget = Gadget::new(
+        Class::new(255, 255, 3),
+        Id::new(6, 0x11),
+        Strings::new("manufacturer", "languages", "serial"),
+    )
+    .with_config(Config::new("config"))
+    .with_allow_empty_configs(true);
+    gadget.strings.insert(Language::GermanStandard, Strings::new("Hersteller", "Sprachen", "Seriennummer"));
+
+    let languages = LanguageSet::from_gadget(&gadget);
+    assert_eq!(languages.primary(), Language::default());
+    assert_eq!(languages.languages().len(), 2);
+
+    gadget.normalize_languages(&languages).unwrap();
+    assert_eq!(gadget.configs[0].description[&Language::GermanStandard], "config");
+
+    let dry_run = gadget.dry_run().unwrap();
+    let german = format!("{:#06x}", u16::from(Language::GermanStandard));
+    assert!(dry_run.ops.iter().any(|op| matches!(op, ConfigOp::Write { path, .. }
+        if path.ends_with(format!("configs/c.1/strings/{german}/configuration")))));
+}
+
+#[test]
+fn max_packet_size0() {
+    let gadget = || {
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "ep0", "serial"))
+            .with_config(Config::new("config"))
+            .with_allow_empty_configs(true)
+    };
+
+    assert_eq!(gadget().max_packet_size0_value().unwrap(), 64);
+
+    let mut super_speed = gadget();
+    super_speed.usb_version = UsbVersion::V30;
+    assert_eq!(super_speed.max_packet_size0_value().unwrap(), 9);
+
+    let err = super_speed.with_max_packet_size0(64).dry_run().unwrap_err();
+    println!("{err}");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert_eq!(err.get_ref().unwrap().downcast_ref::<InvalidMaxPacketSize0>().unwrap().allowed, vec![9]);
+
+    let mut high_speed = gadget().with_max_packet_size0(8);
+    high_speed.max_speed = Some(Speed::HighSpeed);
+    assert!(high_speed.max_packet_size0_value().is_err());
+
+    let dry_run = gadget().with_max_packet_size0(MaxPacketSize0::Value(32)).dry_run().unwrap();
+    assert!(dry_run.ops.iter().any(|op| matches!(op, ConfigOp::Write { path, value }
+        if path.ends_with("bMaxPacketSize0") && value == b"0x20")));
+}
+
+#[test]
+fn clone_with() {
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let gadget =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "clone", "serial"))
+            .with_config(Config::new("config").with_function(func.clone()));
+
+    let (clone, mut duplicates) = gadget.clone_with(|g| g.id = Id::new(6, 0x12)).unwrap();
+    assert_eq!(clone.id, Id::new(6, 0x12));
+    assert_eq!(clone.configs[0].functions.len(), 1);
+    assert!(!clone.configs[0].functions.contains(&func));
+    assert!(clone.configs[0].functions.contains(duplicates.handle(&func).unwrap()));
+    assert!(duplicates.take::<Custom>(&func).is_none());
+    assert!(duplicates.take::<Serial>(&func).is_some());
+    assert!(duplicates.take::<Serial>(&func).is_none());
+
+    let dry_run = clone.dry_run().unwrap();
+    assert!(dry_run.ops.iter().any(|op| matches!(op, ConfigOp::Write { path, value }
+        if path.ends_with("idProduct") && value == b"0x0012")));
+
+    let (_custom, custom_func) = Custom::builder().build();
+    let (custom_gadget, _) = gadget.clone_with(|g| g.configs[0].add_function(custom_func)).unwrap();
+    let err = custom_gadget.clone_with(|_| ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn duplicate_gadget() {
+    init();
+    let _mutex = exclusive();
+
+    let udcs = udcs().unwrap();
+    if udcs.len() < 2 {
+        println!("test requires two UDCs");
+        return;
+    }
+
+    let (serial, func) = Serial::new(SerialClass::Acm);
+    let reg =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "dup", "serial"))
+            .with_config(Config::new("config").with_function(func.clone()))
+            .bind(&udcs[0])
+            .unwrap();
+
+    let namespace = Namespace::new("usb-gadget-dup").unwrap();
+    let (dup, mut duplicates) = reg.duplicate(&namespace, Some(Id::new(6, 0x12))).unwrap();
+    assert!(namespace.contains(dup.name()));
+    dup.bind(Some(&udcs[1])).unwrap();
+
+    let dup_serial = duplicates.take::<Serial>(&func).unwrap();
+    println!("duplicated serial TTY: {:?}", dup_serial.tty());
+    assert_ne!(dup_serial.tty().unwrap(), serial.tty().unwrap());
+
+    dup.remove().unwrap();
+    reg.remove().unwrap();
+}
+
+#[test]
+fn host_match() {
+    let guid = uuid::Uuid::from_u128(0x8fe6d4d7_49dd_41e7_9486_49afc6bfe475);
+    let (_custom, func) = Custom::builder()
+        .with_interface(
+            Interface::new(Class::vendor_specific(1, 2), "custom")
+                .with_os_ext_prop(OsExtProp::device_interface_guid(guid)),
+        )
+        .build();
+    let (_serial, serial) = Serial::new(SerialClass::Acm);
+    let gadget = Gadget::new(
+        Class::new(255, 255, 3),
+        Id::new(0x1209, 0x11),
+        Strings::new("manufacturer", "match", "1234"),
+    )
+    .with_config(Config::new("config").with_function(func).with_function(serial));
+
+    let host_match = gadget.host_match().unwrap();
+    assert_eq!(host_match.id, Id::new(0x1209, 0x11));
+    assert_eq!(host_match.serial_number, "1234");
+    assert_eq!(host_match.interfaces.len(), 1);
+    assert_eq!(host_match.interfaces[0].config, 1);
+    assert_eq!(host_match.interfaces[0].interface_class, Class::vendor_specific(1, 2));
+    assert_eq!(host_match.interface_guids, vec![guid]);
+
+    println!("{}", host_match.udev_rule(r#"MODE="0660", TAG+="uaccess""#));
+    println!("{}", host_match.winusb_inf());
+}
+
+#[test]
+fn max_speed_validation() {
+    init();
+    let _mutex = exclusive();
+
+    let udc = default_udc().unwrap();
+    let supported = udc.max_speed().unwrap();
+    println!("UDC maximum speed: {supported}");
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let mut gadget =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "speed", "serial"))
+            .with_config(Config::new("config").with_function(func));
+    gadget.max_speed = Some(Speed::SuperSpeedPlus);
+    gadget.strictness = Strictness::Error;
+
+    let causes = gadget.check_against(&udc).unwrap();
+    let exceeds = supported != Speed::Unknown && supported > Speed::SuperSpeedPlus;
+    assert_eq!(causes.iter().any(|cause| matches!(cause, BindErrorCause::SpeedUnsupported { .. })), exceeds);
+
+    match gadget.bind(&udc) {
+        Ok(reg) => {
+            assert!(!exceeds);
+            println!("negotiated speed: {:?}", reg.negotiated_speed().unwrap());
+            unreg(reg).unwrap();
+        }
+        Err(err) => {
+            println!("bind error: {err}");
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+            let bind_err = err.get_ref().and_then(|err| err.downcast_ref::<BindError>()).unwrap();
+            assert!(matches!(bind_err.cause, BindErrorCause::SpeedUnsupported { .. }));
+        }
+    }
+}
+
+#[test]
+fn update_config_strings() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let mut reg =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "config", "serial"))
+            .with_config(Config::new("config").with_function(func))
+            .register()
+            .unwrap();
+
+    let mut config = Config::new("Konfiguration");
+    config.description.insert(Language::GermanStandard, "Konfiguration".to_string());
+    config.max_power = 100;
+    reg.update_config(0, &config).unwrap();
+    reg.set_config_description(0, [(Language::default(), "configuration".to_string())].into()).unwrap();
+    assert!(reg.verify().unwrap().is_empty());
+
+    let udc = default_udc().unwrap();
+    reg.bind(Some(&udc)).unwrap();
+    let err = reg.set_config_description(0, [(Language::default(), "bound".to_string())].into()).unwrap_err();
+    let bound = err.get_ref().and_then(|err| err.downcast_ref::<GadgetBound>()).unwrap();
+    assert_eq!(bound.property, Path::new("configs/c.1/strings"));
+    let err = reg.update_config(0, &config).unwrap_err();
+    let bound = err.get_ref().and_then(|err| err.downcast_ref::<GadgetBound>()).unwrap();
+    assert_eq!(bound.property, Path::new("configs/c.1"));
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn power_hooks() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let other = Serial::new(SerialClass::Generic).1;
+    let reg = reg(func.clone());
+
+    let err = reg.watch_power(PowerHooks::new().with_function_hook(&other, |_| ())).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_hook = events.clone();
+    let watcher = reg
+        .watch_power(
+            PowerHooks::new()
+                .with_poll_interval(Duration::from_millis(100))
+                .with_on_suspend(|| println!("suspended"))
+                .with_on_resume(|| println!("resumed"))
+                .with_function_hook(&func, move |event| events_hook.lock().unwrap().push(event)),
+        )
+        .unwrap();
+    assert!(watcher.is_running());
+    println!("suspended: {}", watcher.is_suspended());
+
+    std::thread::sleep(Duration::from_secs(3));
+    drop(watcher);
+    println!("power events: {:?}", events.lock().unwrap());
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn deferred_bind() {
+    init();
+    let _mutex = exclusive();
+
+    let udc = default_udc().unwrap();
+    let bound = Arc::new(Mutex::new(None));
+    let bound_cb = bound.clone();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let deferred = Gadget::new(
+        Class::new(255, 255, 3),
+        Id::new(6, 0x11),
+        Strings::new("manufacturer", "deferred", "serial"),
+    )
+    .with_config(Config::new("config").with_function(func))
+    .register_deferred(
+        DeferredBind::new(UdcCriteria::new().with_name(udc.name()))
+            .with_poll_interval(Duration::from_millis(100))
+            .with_on_bound(move |_reg, udc| *bound_cb.lock().unwrap() = Some(udc.name().to_os_string())),
+    )
+    .unwrap();
+
+    let bound_udc = deferred.wait_bound(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(bound_udc.name(), udc.name());
+    assert_eq!(bound.lock().unwrap().as_deref(), Some(udc.name()));
+
+    let reg = deferred.into_reg();
+    assert_eq!(reg.udc().unwrap().as_deref(), Some(udc.name()));
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn empty_configs() {
+    let gadget =
+        Gadget::new(Class::new(255, 255, 3), Id::new(6, 0x11), Strings::new("manufacturer", "empty", "serial"))
+            .with_config(Config::new("config"));
+
+    let err = gadget.clone().dry_run().unwrap_err();
+    println!("{err}");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let gadget = gadget.with_allow_empty_configs(true);
+    gadget.dry_run().unwrap();
+
+    init();
+    let _mutex = exclusive();
+
+    let udc = default_udc().unwrap();
+    let causes = gadget.check_against(&udc).unwrap();
+    assert!(causes.contains(&BindErrorCause::EmptyConfiguration { config: 0 }));
+
+    let err = gadget.bind(&udc).unwrap_err();
+    println!("{err}");
+    let bind_err = err.get_ref().and_then(|err| err.downcast_ref::<BindError>()).unwrap();
+    assert_eq!(bind_err.cause, BindErrorCause::EmptyConfiguration { config: 0 });
+}
+
+#[test]
+fn removal_guard() {
+    init();
+    let _mutex = exclusive();
+
+    let guard = RemovalGuard::install();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let mut reg = reg(func);
+    assert!(RemovalGuard::owned().contains(&reg.path().to_path_buf()));
+
+    reg.detach();
+    assert!(!RemovalGuard::owned().contains(&reg.path().to_path_buf()));
+    let reg = registered().unwrap().into_iter().find(|r| r.name() == reg.name()).unwrap();
+    reg.remove().unwrap();
+
+    drop(guard);
+}
+
+#[test]
+fn removal_guard_unregister() {
+    init();
+    let _mutex = exclusive();
+
+    let guard = RemovalGuard::install();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let reg = reg(func);
+    let path = reg.path().to_path_buf();
+    assert!(RemovalGuard::owned().contains(&path));
+
+    reg.remove().unwrap();
+    assert!(!RemovalGuard::owned().contains(&path));
+
+    drop(guard);
+}
@@ -1,7 +1,14 @@
 mod common;
 use common::*;
 
-use usb_gadget::function::hid::Hid;
+use std::io::ErrorKind;
+
+use usb_gadget::{
+    function::hid::{
+        Hid, HidBuilder, KeyboardReport, ReportDescTruncated, KEYBOARD_REPORT_DESC, MAX_REPORT_DESC_LEN,
+    },
+    Class, Config, Gadget, Id, Strings,
+};
 
 #[test]
 fn hid() {
@@ -26,3 +33,52 @@ fn hid() {
 
     unreg(reg).unwrap();
 }
+
+#[test]
+fn hid_keyboard_preset() {
+    init();
+
+    let builder = HidBuilder::keyboard();
+    assert_eq!((builder.sub_class, builder.protocol, builder.report_len), (1, 1, KeyboardReport::LEN as u8));
+    assert_eq!(builder.report_desc, KEYBOARD_REPORT_DESC);
+    let (hid, func) = builder.build();
+
+    let reg = reg(func);
+
+    assert!(hid.status().path().is_some());
+    hid.device().unwrap();
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn hid_no_out_endpoint_wakeup() {
+    init();
+
+    let (hid, func) = HidBuilder::keyboard().with_no_out_endpoint(true).with_wakeup_on_write(true).build();
+
+    let reg = reg(func);
+
+    println!("HID device {:?} at {}", hid.device().unwrap(), hid.status().path().unwrap().display());
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn hid_large_report_desc() {
+    init();
+
+    let mut report_desc = KEYBOARD_REPORT_DESC.to_vec();
+    report_desc.resize(MAX_REPORT_DESC_LEN + 100, 0);
+    let (_hid, func) = HidBuilder::keyboard().with_report_desc(report_desc).build();
+
+    let err = Gadget::new(Class::new(1, 2, 3), Id::new(4, 5), Strings::new("manufacturer", "product", "serial"))
+        .with_config(Config::new("config").with_function(func))
+        .register()
+        .expect_err("kernel accepted report descriptor exceeding maximum length");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let truncated = err.get_ref().unwrap().downcast_ref::<ReportDescTruncated>().unwrap();
+    assert_eq!(truncated.len, MAX_REPORT_DESC_LEN + 100);
+    assert_eq!(truncated.accepted, MAX_REPORT_DESC_LEN);
+}
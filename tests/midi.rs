@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 mod common;
 use common::*;
 
@@ -17,5 +19,8 @@ fn midi() {
 
     println!("midi device at {}", midi.status().path().unwrap().display());
 
+    let device = midi.card_device(Some(Duration::from_secs(3))).unwrap();
+    println!("rawmidi device {} at {}", device.hw_name(), device.path().display());
+
     unreg(reg).unwrap();
 }
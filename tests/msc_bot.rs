@@ -0,0 +1,50 @@
+mod common;
+use common::*;
+
+use std::{io::Result, time::Duration};
+
+use usb_gadget::function::custom::msc_bot::{BlockDevice, MscBot};
+
+struct RamDisk(Vec<u8>);
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> u32 {
+        512
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.0.len() / 512) as u64
+    }
+
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        let start = lba as usize * 512;
+        buf.copy_from_slice(&self.0[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, lba: u64, data: &[u8]) -> Result<()> {
+        let start = lba as usize * 512;
+        self.0[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[test]
+fn msc_bot() {
+    init();
+    let _mutex = exclusive();
+
+    let (mut msc, handle) = MscBot::builder().with_lun(RamDisk(vec![0; 1024 * 1024])).build().unwrap();
+
+    let reg = reg(handle);
+    println!("mass storage function at {}", msc.custom().status().unwrap().path().unwrap().display());
+
+    for _ in 0..10 {
+        msc.process(Duration::from_millis(100)).unwrap();
+    }
+    assert_eq!(msc.lun(0).unwrap().block_count(), 2048);
+
+    if unreg(reg).unwrap() {
+        assert!(msc.custom().status().unwrap().path().is_none());
+    }
+}
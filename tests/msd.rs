@@ -4,7 +4,10 @@ use common::*;
 use std::{io::Write, thread::sleep, time::Duration};
 use tempfile::NamedTempFile;
 
-use usb_gadget::function::msd::{Lun, Msd};
+use usb_gadget::{
+    function::msd::{InquiryStringError, Lun, LunError, Msd, SwapPolicy},
+    Class, Config, Gadget, Id, Strings,
+};
 
 #[test]
 fn msd() {
@@ -29,7 +32,8 @@ fn msd() {
 
     println!("MSD device at {}", msd.status().path().unwrap().display());
 
-    sleep(Duration::from_secs(1));
+    let mut monitor = msd.monitor().unwrap();
+    println!("activity: {:?}", monitor.activity(Some(Duration::from_secs(1))).unwrap());
 
     msd.force_eject(0).unwrap();
     sleep(Duration::from_secs(1));
@@ -40,8 +44,69 @@ fn msd() {
     msd.set_file(1, Some(&path1)).unwrap();
     sleep(Duration::from_secs(1));
 
+    msd.swap_media(0, &path2, &SwapPolicy::new().with_force(true)).unwrap();
+    sleep(Duration::from_secs(1));
+
     if unreg(reg).unwrap() {
         path1.close().expect("cannot delete temp file");
         path2.close().expect("cannot delete temp file");
     }
 }
+
+#[test]
+fn inquiry_string() {
+    let mut lun = Lun::empty();
+    lun.set_inquiry("Vendor", "Product", "1.0").unwrap();
+    assert_eq!(lun.inquiry_string, "Vendor  Product         1.0 ");
+    assert_eq!(lun.inquiry_string.len(), Lun::INQUIRY_STRING_LEN);
+
+    let err = lun.set_inquiry("VendorName", "Product", "1.0").unwrap_err();
+    assert!(matches!(
+        err.get_ref().and_then(|err| err.downcast_ref::<InquiryStringError>()),
+        Some(InquiryStringError::FieldTooLong { field: "vendor", .. })
+    ));
+
+    lun.inquiry_string = "Vendör".to_string();
+    let (_msd, func) = Msd::builder().with_lun(lun).build();
+    let err = Gadget::new(Class::new(1, 2, 3), Id::new(4, 5), Strings::new("manufacturer", "product", "serial"))
+        .with_config(Config::new("config").with_function(func))
+        .dry_run()
+        .unwrap_err();
+    println!("{err}");
+    assert!(matches!(
+        err.get_ref().and_then(|err| err.downcast_ref::<InquiryStringError>()),
+        Some(InquiryStringError::InvalidChar { ch: 'ö', pos: 4 })
+    ));
+}
+
+#[test]
+fn lun_validation() {
+    let file = NamedTempFile::new().unwrap();
+
+    let mut builder = Msd::builder();
+    for _ in 0..=Msd::MAX_LUNS {
+        builder.add_lun(Lun::new(file.path()).unwrap());
+    }
+    let err = builder.validate().unwrap_err();
+    println!("{err}");
+    assert_eq!(
+        err.get_ref().unwrap().downcast_ref::<LunError>(),
+        Some(&LunError::TooMany { count: Msd::MAX_LUNS + 1, max: Msd::MAX_LUNS })
+    );
+
+    builder.luns.truncate(Msd::MAX_LUNS);
+    builder.validate().unwrap();
+
+    let builder = Msd::builder().with_lun(Lun::new("/nonexistent/usb-gadget.img").unwrap());
+    let err = builder.validate().unwrap_err();
+    println!("{err}");
+    assert!(matches!(
+        err.get_ref().unwrap().downcast_ref::<LunError>(),
+        Some(LunError::FileNotFound { lun: 0, .. })
+    ));
+
+    let mut lun = Lun::empty();
+    lun.removable = false;
+    let err = Msd::builder().with_lun(lun).validate().unwrap_err();
+    assert!(matches!(err.get_ref().unwrap().downcast_ref::<LunError>(), Some(LunError::MissingFile { lun: 0 })));
+}
@@ -2,7 +2,15 @@ mod common;
 use common::*;
 
 use macaddr::MacAddr6;
-use usb_gadget::function::net::{Net, NetClass};
+use std::io::ErrorKind;
+use usb_gadget::{
+    function::{
+        custom::OsExtCompat,
+        net::{InterfaceStringUnsupported, Net, NetClass},
+        util::Strictness,
+    },
+    Class, Config, ConfigOp, Gadget, Id, IdentityKey, OsDescriptor, Strings,
+};
 
 fn net(net_class: NetClass) {
     init();
@@ -58,3 +66,185 @@ fn ncm() {
 fn rndis() {
     net(NetClass::Rndis)
 }
+
+#[test]
+fn ncm_tuning() {
+    init();
+    let _mutex = exclusive();
+
+    let mut builder = Net::builder(NetClass::Ncm);
+    builder.max_segment_size = Some(1514);
+    let (net, func) = builder.build();
+
+    let reg = reg(func);
+
+    println!("NCM function at {}", net.status().path().unwrap().display());
+    println!("NTB input size: {:?}", net.ntb_input_size());
+    println!("NTB output size: {:?}", net.ntb_output_size());
+    println!("Unsupported attributes: {:?}", net.status().unsupported_attributes());
+
+    if let Ok(max_segment_size) = net.max_segment_size() {
+        assert_eq!(max_segment_size, 1514);
+    }
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn ncm_vendor_ntb_sizes() {
+    init();
+    let _mutex = exclusive();
+
+    let (net, func) = Net::builder(NetClass::Ncm)
+        .with_ntb_input_size(16384)
+        .with_ntb_output_size(16384)
+        .with_strictness(Strictness::Warn)
+        .build();
+
+    let reg = reg(func);
+
+    match net.ntb_input_size().unwrap() {
+        Some(size) => assert_eq!(size, 16384),
+        None => assert!(net.status().unsupported_attributes().contains(&"ntb_input_size".into())),
+    }
+
+    unreg(reg).unwrap();
+}
+
+fn multi_nic_gadget(host_addr: Option<MacAddr6>) -> Gadget {
+    let mut ecm = Net::builder(NetClass::Ecm);
+    ecm.host_addr = host_addr;
+    let mut ncm = Net::builder(NetClass::Ncm);
+    ncm.host_addr = host_addr;
+
+    Gadget::new(Class::interface_specific(), Id::new(6, 0x11), Strings::new("manufacturer", "nics", "serial"))
+        .with_config(Config::new("config").with_function(ecm.build().1).with_function(ncm.build().1))
+}
+
+fn written_mac_addresses(gadget: Gadget) -> Vec<String> {
+    let mut addrs: Vec<_> = gadget
+        .dry_run()
+        .unwrap()
+        .ops
+        .into_iter()
+        .filter_map(|op| match op {
+            ConfigOp::Write { path, value } if path.ends_with("dev_addr") || path.ends_with("host_addr") => {
+                Some(String::from_utf8(value).unwrap())
+            }
+            _ => None,
+        })
+        .collect();
+    addrs.sort();
+    addrs
+}
+
+#[test]
+fn net_ifname() {
+    init();
+    let _mutex = exclusive();
+
+    let mut builder = Net::builder(NetClass::Ncm);
+    builder.ifname = Some("usbtest%d".to_string());
+    let (net, func) = builder.build();
+
+    let reg = reg(func);
+
+    let ifname = net.ifname().unwrap();
+    println!("Net device {}", ifname.to_string_lossy());
+    assert!(ifname.to_string_lossy().starts_with("usbtest"));
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn interface_string_unsupported() {
+    let mut builder = Net::builder(NetClass::Ecm);
+    builder.interface_string = Some("My network".to_string());
+    let (_net, func) = builder.build();
+
+    let gadget =
+        Gadget::new(Class::interface_specific(), Id::new(6, 0x11), Strings::new("manufacturer", "net", "serial"))
+            .with_config(Config::new("config").with_function(func));
+    let err = gadget.dry_run().unwrap_err();
+    println!("{err}");
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert!(err.get_ref().unwrap().is::<InterfaceStringUnsupported>());
+}
+
+#[test]
+fn duplicate_mac_address() {
+    let gadget = multi_nic_gadget(Some(MacAddr6::new(0x02, 0, 0, 0, 0, 1)));
+    let err = gadget.dry_run().unwrap_err();
+    println!("{err}");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn derived_mac_addresses() {
+    let addrs = written_mac_addresses(multi_nic_gadget(None).with_mac_seed(1));
+    println!("derived MAC addresses: {addrs:?}");
+
+    assert_eq!(addrs.len(), 4);
+    assert!(addrs.windows(2).all(|w| w[0] != w[1]));
+    assert_eq!(addrs, written_mac_addresses(multi_nic_gadget(None).with_mac_seed(1)));
+    assert_ne!(addrs, written_mac_addresses(multi_nic_gadget(None).with_mac_seed(2)));
+    assert!(written_mac_addresses(multi_nic_gadget(None)).is_empty());
+
+    let gadget = multi_nic_gadget(None).with_mac_seed(1);
+    let (clone, _) = gadget.clone_with(|_| ()).unwrap();
+    let clone_addrs = written_mac_addresses(clone);
+    assert!(clone_addrs.iter().all(|addr| !addrs.contains(addr)));
+    let (clone, _) = gadget.clone_with(|g| g.id = Id::new(6, 0x12)).unwrap();
+    assert_ne!(clone_addrs, written_mac_addresses(clone));
+}
+
+#[test]
+fn identity_derived_addresses() {
+    let key = IdentityKey::new([1; 16]);
+    let addrs = written_mac_addresses(multi_nic_gadget(None).with_identity(&key));
+    assert_eq!(addrs.len(), 4);
+    assert_eq!(addrs, written_mac_addresses(multi_nic_gadget(None).with_identity(&key)));
+    assert_ne!(addrs, written_mac_addresses(multi_nic_gadget(None).with_identity(&IdentityKey::new([2; 16]))));
+
+    let gadget = multi_nic_gadget(None).with_identity(&key);
+    let serial = &gadget.strings.values().next().unwrap().serial_number;
+    assert_eq!(serial.len(), 16);
+    assert_eq!(
+        serial,
+        &gadget.clone_with(|_| ()).unwrap().0.with_identity(&key).strings.values().next().unwrap().serial_number
+    );
+
+    let (_net, func) = Net::builder(NetClass::Ecm).with_identity(&key, "usb0").build();
+    let gadget =
+        Gadget::new(Class::interface_specific(), Id::new(6, 0x11), Strings::new("manufacturer", "net", ""))
+            .with_config(Config::new("config").with_function(func));
+    let dev_addr = key.mac_address(b"dev:usb0").to_string();
+    let dry_run = gadget.dry_run().unwrap();
+    assert!(dry_run.ops.iter().any(|op| matches!(op, ConfigOp::Write { path, value }
+        if path.ends_with("dev_addr") && value == dev_addr.as_bytes())));
+}
+
+#[test]
+fn rndis_os_ext_compat() {
+    let mut builder = Net::builder(NetClass::Rndis);
+    builder.os_ext_compat = Some(OsExtCompat::rndis());
+    let (_net, func) = builder.build();
+
+    let gadget = Gadget::new(
+        Class::interface_specific(),
+        Id::new(6, 0x11),
+        Strings::new("manufacturer", "rndis", "serial"),
+    )
+    .with_config(Config::new("config").with_function(func))
+    .with_os_descriptor(OsDescriptor::microsoft());
+    let ops = gadget.dry_run().unwrap().ops;
+
+    let written = |name: &str| {
+        ops.iter().find_map(|op| match op {
+            ConfigOp::Write { path, value } if path.ends_with(name) => Some(value.clone()),
+            _ => None,
+        })
+    };
+    assert_eq!(written("os_desc/interface.rndis/compatible_id").unwrap(), b"RNDIS\0\0\0");
+    assert_eq!(written("os_desc/interface.rndis/sub_compatible_id").unwrap(), b"5162001\0");
+}
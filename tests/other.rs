@@ -1,7 +1,7 @@
 mod common;
 use common::*;
 
-use usb_gadget::function::other::Other;
+use usb_gadget::{function::other::Other, Namespace};
 
 #[test]
 fn other_ecm() {
@@ -24,3 +24,37 @@ fn other_ecm() {
 
     unreg(reg).unwrap();
 }
+
+#[test]
+fn other_msd_plan() {
+    init();
+    let _mutex = exclusive();
+
+    let mut builder = Other::builder("mass_storage").unwrap();
+    builder.create_dir("lun.1").unwrap();
+    builder.set("lun.1/removable", "1").unwrap();
+    builder.set_post_registration("lun.1/inquiry_string", "usb-gadget").unwrap();
+    let (other, func) = builder.build();
+
+    let reg = reg(func);
+
+    println!("Other device at {}", other.status().path().unwrap().display());
+
+    let inquiry = other.get("lun.1/inquiry_string").unwrap();
+    assert_eq!(String::from_utf8_lossy(&inquiry).trim(), "usb-gadget");
+
+    unreg(reg).unwrap();
+}
+
+#[test]
+fn other_discover_attributes() {
+    init();
+
+    let namespace = Namespace::new("usb-gadget-discover").unwrap();
+    let attrs = Other::discover_attributes("ecm", &namespace).unwrap();
+    for attr in &attrs {
+        println!("{}: {:?} (writable: {})", attr.name.display(), attr.value_str(), attr.writable);
+    }
+    assert!(attrs.iter().any(|attr| attr.name.as_os_str() == "dev_addr" && attr.writable));
+    assert!(namespace.registered().unwrap().is_empty());
+}
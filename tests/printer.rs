@@ -1,6 +1,8 @@
 mod common;
 use common::*;
 
+use std::time::Duration;
+
 use usb_gadget::function::printer::Printer;
 
 #[test]
@@ -15,7 +17,18 @@ fn printer() {
 
     let reg = reg(func);
 
-    println!("printer device at {}", printer.status().path().unwrap().display());
+    println!("printer function at {}", printer.status().path().unwrap().display());
+
+    let dev = printer.device_timeout(Duration::from_secs(5)).unwrap();
+    println!("printer device at {}", dev.display());
+    assert!(dev.exists());
 
     unreg(reg).unwrap();
 }
+
+#[test]
+fn printer_builder_setters() {
+    let builder = Printer::builder().with_pnp_string("Rust Printer").with_qlen(20);
+    assert_eq!(builder.pnp_string.as_deref(), Some("Rust Printer"));
+    assert_eq!(builder.qlen, Some(20));
+}
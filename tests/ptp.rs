@@ -0,0 +1,36 @@
+mod common;
+use common::*;
+
+use std::time::Duration;
+
+use usb_gadget::function::custom::ptp::{Ptp, PtpEvent, RESPONSE_OK};
+
+#[test]
+fn ptp() {
+    init();
+    let _mutex = exclusive();
+
+    let (mut ptp, handle) = Ptp::builder().build();
+
+    let reg = reg(handle);
+    println!("PTP function at {}", ptp.custom().status().unwrap().path().unwrap().display());
+
+    for _ in 0..10 {
+        match ptp.event(Duration::from_millis(100)).unwrap() {
+            Some(PtpEvent::Enabled) => println!("enabled"),
+            Some(event) => println!("event: {event:?}"),
+            None => (),
+        }
+
+        if ptp.is_enabled() {
+            if let Some(command) = ptp.recv_container(Duration::from_millis(100)).unwrap() {
+                println!("command: {command:?}");
+                ptp.send_response(RESPONSE_OK, command.transaction_id, &[]).unwrap();
+            }
+        }
+    }
+
+    if unreg(reg).unwrap() {
+        assert!(ptp.custom().status().unwrap().path().is_none());
+    }
+}
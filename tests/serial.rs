@@ -64,6 +64,13 @@ async fn serial_status() {
 
     let status = serial.status();
     assert_eq!(status.state(), State::Bound);
+    assert!(status.udc().is_some());
+    println!("speed: {:?}", status.speed().unwrap());
+    println!("gadget state: {:?}", status.gadget_state().unwrap());
+    assert_eq!(reg.state().unwrap(), status.gadget_state().unwrap());
+
+    let mut watcher = reg.watch_state().unwrap();
+    println!("configured: {}", watcher.wait_configured(Some(Duration::from_secs(1))).unwrap());
 
     let status = serial.status();
     let task = tokio::spawn(async move { status.unbound().await });
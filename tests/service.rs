@@ -0,0 +1,36 @@
+mod common;
+use common::*;
+
+use std::{thread::sleep, time::Duration};
+use usb_gadget::{
+    function::net::{Net, NetClass},
+    service::Service,
+    Class, Config, Gadget, Id, Namespace, Strings,
+};
+
+#[test]
+fn service_lifecycle() {
+    init();
+    let _mutex = exclusive();
+
+    let namespace = Namespace::new("usb-gadget-service-test").unwrap();
+    let gadget = || {
+        let (_net, func) = Net::new(NetClass::Ecm);
+        Gadget::new(Class::new(1, 2, 3), Id::new(4, 5), Strings::new("manufacturer", "product", "serial_number"))
+            .with_config(Config::new("config").with_function(func))
+    };
+
+    let mut stale = gadget().with_namespace(&namespace).register().unwrap();
+    stale.detach();
+
+    let service = Service::new(namespace.clone()).with_notify(false).with_stop_timeout(Duration::from_secs(5));
+    let running = service.start(gadget()).unwrap();
+    assert_eq!(namespace.registered().unwrap().len(), 1);
+    assert!(running.reg().udc().unwrap().is_some());
+    assert!(!running.is_terminating());
+
+    sleep(Duration::from_secs(1));
+
+    running.stop().unwrap();
+    assert!(namespace.registered().unwrap().is_empty());
+}
@@ -1,6 +1,14 @@
 mod common;
 use common::*;
 
+use std::time::Duration;
+
+use usb_gadget::{
+    default_udc,
+    function::serial::{Serial, SerialClass},
+    Udc, UdcCriteria, UdcDriver,
+};
+
 #[test]
 fn query_udcs() {
     init();
@@ -16,6 +24,97 @@ fn query_udcs() {
         println!("Max speed: {:?}", udc.max_speed().unwrap());
         println!("State: {:?}", udc.state().unwrap());
         println!("Function: {:?}", udc.function().unwrap());
+        println!("VBUS draw: {:?}", udc.vbus_draw().unwrap());
+        println!("Capabilities: {:#?}", udc.capabilities().unwrap());
+        println!("Device path: {}", udc.device_path().unwrap().display());
+        println!("Device tree node: {:?}", udc.of_node().unwrap());
         println!();
     }
 }
+
+#[test]
+fn udc_stable_ids() {
+    init();
+
+    let udc = default_udc().unwrap();
+    assert_eq!(Udc::by_name(udc.name()).unwrap().name(), udc.name());
+    assert_eq!(Udc::by_name("nonexistent").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    let device_path = udc.device_path().unwrap();
+    assert_eq!(Udc::by_device_path(&device_path).unwrap().name(), udc.name());
+    if let Some(node) = udc.of_node().unwrap() {
+        assert_eq!(Udc::by_of_node(&node).unwrap().name(), udc.name());
+    }
+
+    let criteria = UdcCriteria::new().with_device_path(device_path);
+    if udc.function().unwrap().is_none() {
+        assert!(criteria.matches(&udc));
+    }
+}
+
+#[test]
+fn watch_udc_state() {
+    init();
+
+    for udc in usb_gadget::udcs().unwrap() {
+        let mut watcher = udc.watch_state().unwrap();
+        println!("{}: {:?}", udc.name().to_string_lossy(), watcher.state());
+        println!("Changed: {:?}", watcher.wait(Some(Duration::from_millis(100))).unwrap());
+    }
+}
+
+#[test]
+fn watch_udc_speed() {
+    init();
+
+    for udc in usb_gadget::udcs().unwrap() {
+        let mut watcher = udc.watch_speed().unwrap();
+        println!("{}: {:?}", udc.name().to_string_lossy(), watcher.speed());
+        println!("Changed: {:?}", watcher.wait(Some(Duration::from_millis(100))).unwrap());
+    }
+}
+
+#[test]
+fn wait_udc_state() {
+    init();
+
+    for udc in usb_gadget::udcs().unwrap() {
+        let state = udc.state().unwrap();
+        assert!(udc.wait_state(state, Some(Duration::from_millis(100))).unwrap());
+        println!(
+            "{} configured: {}",
+            udc.name().to_string_lossy(),
+            udc.wait_state(usb_gadget::UdcState::Configured, Some(Duration::from_millis(100))).unwrap()
+        );
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn udc_state_stream() {
+    init();
+
+    for udc in usb_gadget::udcs().unwrap() {
+        let mut stream = udc.state_stream().unwrap();
+        println!("{}: {:?}", udc.name().to_string_lossy(), stream.state());
+        let changed = tokio::time::timeout(Duration::from_millis(100), stream.changed()).await;
+        println!("Changed: {changed:?}");
+    }
+}
+
+#[test]
+fn udc_gadget() {
+    init();
+    let _mutex = exclusive();
+
+    let (_serial, func) = Serial::new(SerialClass::Acm);
+    let reg = reg(func);
+
+    let udc = default_udc().unwrap();
+    let gadget = udc.gadget().unwrap().expect("gadget not found");
+    assert_eq!(gadget.path(), reg.path());
+    assert!(matches!(udc.driver().unwrap(), Some(UdcDriver::Gadget(gadget)) if gadget.path() == reg.path()));
+
+    unreg(reg).unwrap();
+    assert!(udc.gadget().unwrap().is_none());
+}
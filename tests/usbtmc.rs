@@ -0,0 +1,29 @@
+use std::{thread, time::Duration};
+
+use usb_gadget::function::{custom::Custom, usbtmc::UsbTmc};
+
+mod common;
+use common::*;
+
+#[test]
+fn usbtmc() {
+    init();
+    let _mutex = exclusive();
+
+    let (mut tmc, intf) = UsbTmc::usb488("USBTMC interface");
+    let (custom, handle) = Custom::builder().with_interface(intf).build();
+
+    let reg = reg(handle);
+    println!("USBTMC function at {}", custom.status().unwrap().path().unwrap().display());
+
+    println!("Bulk-OUT real address: {}", tmc.bulk_out.control().unwrap().real_address().unwrap());
+    println!("Bulk-IN real address: {}", tmc.bulk_in.control().unwrap().real_address().unwrap());
+    let interrupt_in = tmc.interrupt_in.as_mut().unwrap();
+    println!("Interrupt-IN real address: {}", interrupt_in.control().unwrap().real_address().unwrap());
+
+    thread::sleep(Duration::from_secs(1));
+
+    if unreg(reg).unwrap() {
+        assert!(custom.status().unwrap().path().is_none());
+    }
+}
@@ -1,7 +1,15 @@
 mod common;
 use common::*;
 
-use usb_gadget::function::video::{ColorMatching, Format, Frame, Uvc};
+use std::{io::ErrorKind, os::unix::fs::FileTypeExt, time::Duration};
+
+use usb_gadget::{
+    function::{
+        util::Strictness,
+        video::{CameraControls, ColorMatching, Format, Frame, ProcessingControls, Uvc},
+    },
+    Class, Config, ConfigOp, Gadget, Id, Strings,
+};
 
 #[test]
 fn video() {
@@ -14,12 +22,84 @@ fn video() {
         Frame::new(1920, 1080, vec![30], Format::Mjpeg),
     ]);
     builder.frames[0].color_matching = Some(ColorMatching::new(0x4, 0x1, 0x2));
-    builder.processing_controls = Some(0x05);
-    builder.camera_controls = Some(0x60);
+    builder.processing_controls = Some(ProcessingControls::BRIGHTNESS | ProcessingControls::HUE);
+    builder.camera_controls = Some(CameraControls::FOCUS_ABSOLUTE | CameraControls::FOCUS_RELATIVE);
+    builder.function_name = Some("Rust Camera".to_string());
+    builder.streaming_name = Some("Rust Camera Stream".to_string());
+    builder.strictness = Strictness::Warn;
     let (video, func) = builder.build();
     let reg = reg(func);
 
     println!("UVC video device at {}", video.status().path().unwrap().display());
 
+    let formats = video.v4l2_formats();
+    assert_eq!(formats.len(), 4);
+    assert_eq!(formats[0].pixel_format, u32::from_le_bytes(*b"YUYV"));
+    assert_eq!(formats[0].bytes_per_line, 640 * 2);
+
+    let negotiated = video.negotiate_format(Format::Mjpeg.fourcc(), 1280, 700).unwrap();
+    assert_eq!((negotiated.width, negotiated.height), (1280, 720));
+    assert!(video.negotiate_format(u32::from_le_bytes(*b"NV12"), 640, 360).is_none());
+
+    let device = video.video_device(Some(Duration::from_secs(1))).unwrap();
+    println!("V4L2 device node at {}", device.display());
+    assert!(device.file_name().unwrap().to_string_lossy().starts_with("video"));
+    assert!(device.metadata().unwrap().file_type().is_char_device());
+
+    reg.bind(None).unwrap();
+    let err = video.video_device(None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotConnected);
+
     unreg(reg).unwrap();
 }
+
+#[test]
+fn video_controls() {
+    let mut builder = Uvc::builder()
+        .with_frames(vec![Frame::new(640, 360, vec![30], Format::Mjpeg)])
+        .with_streaming_name("video stream");
+    builder.processing_controls = Some(ProcessingControls::BRIGHTNESS | ProcessingControls::GAIN);
+    builder.camera_controls = Some(CameraControls::ZOOM_ABSOLUTE | CameraControls::PRIVACY);
+    let (_video, func) = builder.build();
+
+    let gadget = Gadget::new(
+        Class::new(0xef, 0x02, 0x01),
+        Id::new(6, 0x11),
+        Strings::new("manufacturer", "uvc", "serial"),
+    )
+    .with_config(Config::new("config").with_function(func));
+    let dry_run = gadget.dry_run().unwrap();
+
+    let written = |name: &str| {
+        dry_run.ops.iter().find_map(|op| match op {
+            ConfigOp::Write { path, value } if path.ends_with(name) => Some(value.clone()),
+            _ => None,
+        })
+    };
+    assert_eq!(written("control/processing/default/bmControls").unwrap(), b"1\n2\n");
+    assert_eq!(written("control/terminal/camera/default/bmControls").unwrap(), b"0\n2\n4\n");
+    assert!(dry_run.ops.iter().any(|op| matches!(op, ConfigOp::Write { value, .. } if value == b"video stream")));
+    assert!(dry_run
+        .ops
+        .iter()
+        .any(|op| matches!(op, ConfigOp::Symlink { link, .. } if link.ends_with("vs1_desc"))));
+}
+
+#[test]
+fn video_misc_iad_class() {
+    let (_video, func) = Uvc::builder().with_frames(vec![Frame::new(640, 360, vec![30], Format::Mjpeg)]).build();
+
+    let gadget = Gadget::new(Class::MISC_IAD, Id::new(6, 0x11), Strings::new("manufacturer", "uvc", "serial"))
+        .with_config(Config::new("config").with_function(func));
+    let dry_run = gadget.dry_run().unwrap();
+
+    let written = |name: &str| {
+        dry_run.ops.iter().find_map(|op| match op {
+            ConfigOp::Write { path, value } if path.ends_with(name) => Some(value.clone()),
+            _ => None,
+        })
+    };
+    assert_eq!(written("bDeviceClass").unwrap(), b"0xef");
+    assert_eq!(written("bDeviceSubClass").unwrap(), b"0x02");
+    assert_eq!(written("bDeviceProtocol").unwrap(), b"0x01");
+}